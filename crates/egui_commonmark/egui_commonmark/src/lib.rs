@@ -67,6 +67,16 @@
 //! # });
 //! ```
 //!
+//! or every markdown file in a directory, shown by name
+//!
+//! ```rust,ignore
+//! use egui_commonmark::{CommonMarkCache, commonmark_dir};
+//! # egui::__run_test_ui(|ui| {
+//! let mut cache = CommonMarkCache::default();
+//! commonmark_dir!(ui, &mut cache, "docs", "guide/install");
+//! # });
+//! ```
+//!
 //! For more information check out the documentation for
 //! [egui_commonmark_macros_extended](https://docs.rs/crate/egui_commonmark_macros_extended/latest)
 #![cfg_attr(feature = "document-features", doc = "# Features")]
@@ -76,11 +86,30 @@ use egui::{self, Id};
 
 mod parsers;
 
+pub use egui_commonmark_backend_extended::CodeBlockFooterFn;
+pub use egui_commonmark_backend_extended::CodeLangMatcher;
+pub use egui_commonmark_backend_extended::RenderCodeBlockFn;
 pub use egui_commonmark_backend_extended::RenderHtmlFn;
+pub use egui_commonmark_backend_extended::{CheckboxClickedFn, RenderImageErrorFn, RewriteUrlFn};
 pub use egui_commonmark_backend_extended::RenderMathFn;
-pub use egui_commonmark_backend_extended::alerts::{Alert, AlertBundle};
-pub use egui_commonmark_backend_extended::misc::{CommonMarkCache, STRONG_FONT_FAMILY};
+pub use egui_commonmark_backend_extended::alerts::{Alert, AlertBundle, AlertIcon};
+pub use egui_commonmark_backend_extended::anchor::{anchor_target, heading_slug, AnchorSlugger};
+pub use egui_commonmark_backend_extended::config::CommonMarkConfig;
+pub use egui_commonmark_backend_extended::document::{BlockKind, DocumentBlock};
+pub use egui_commonmark_backend_extended::hooks::CommonMarkRenderHooks;
+pub use egui_commonmark_backend_extended::page_break::is_page_break;
+pub use egui_commonmark_backend_extended::misc::{
+    BlockquoteStyle, CodeCopyFormat, CommonMarkCache, DarkModeImages, HeaderKey, ImageCaptions, LinkClick,
+    LinkInfo, LinkStyle, LinkUnderline, ListMarkers, ListNumbering, MarkdownExtensions,
+    RenderError, RenderedHeading, RenderedTable, Sandbox, SourceBlock, SourceRange, STRONG_FONT_FAMILY, TableCells,
+    TableMode, TextDirection,
+};
 pub use egui_commonmark_backend_extended::typography::{Measurement, TypographyConfig};
+#[cfg(feature = "charts")]
+pub use egui_commonmark_backend_extended::charts;
+#[cfg(feature = "remote_images")]
+pub use egui_commonmark_backend_extended::http_loader;
+pub use egui_commonmark_backend_extended::texture_loader;
 #[cfg(feature = "math")]
 pub use egui_commonmark_backend_extended::render_math;
 #[cfg(feature = "math")]
@@ -119,6 +148,15 @@ impl<'f> CommonMarkViewer<'f> {
         Self::default()
     }
 
+    /// A viewer with the settings in `config`, e.g. loaded from the
+    /// application's config file with the `serde` feature. The builder
+    /// methods still apply on top of it.
+    pub fn from_config(config: &CommonMarkConfig) -> Self {
+        let mut viewer = Self::new();
+        config.apply(&mut viewer.options);
+        viewer
+    }
+
     /// The amount of spaces a bullet point is indented. By default this is 4
     /// spaces.
     pub fn indentation_spaces(mut self, spaces: usize) -> Self {
@@ -127,7 +165,8 @@ impl<'f> CommonMarkViewer<'f> {
     }
 
     /// The maximum size images are allowed to be. They will be scaled down if
-    /// they are larger
+    /// they are larger, and their textures are uploaded no wider than this
+    /// at the current `pixels_per_point`
     pub fn max_image_width(mut self, width: Option<usize>) -> Self {
         self.options.max_image_width = width;
         self
@@ -140,12 +179,292 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
+    /// Cap the width of the content column, so paragraphs, tables and images
+    /// keep a readable line length in wide viewers. By default the content
+    /// is as wide as the viewer, or [`default_width`](Self::default_width).
+    pub fn max_content_width(mut self, width: Option<f32>) -> Self {
+        self.options.max_content_width = width;
+        self
+    }
+
+    /// Center the content column when it is narrower than the viewer, as
+    /// with [`max_content_width`](Self::max_content_width). By default it is
+    /// aligned to the left.
+    pub fn center_content(mut self, center: bool) -> Self {
+        self.options.center_content = center;
+        self
+    }
+
+    /// Which way paragraphs, list items and blockquotes run. `Auto` lays each
+    /// of them out right to left, with list bullets and quote bars on the
+    /// right, when it starts in a right-to-left script like Hebrew or Arabic.
+    /// Code blocks stay left to right. By default everything is left to right.
+    pub fn text_direction(mut self, direction: TextDirection) -> Self {
+        self.options.text_direction = direction;
+        self
+    }
+
+    /// Let a selection dragged across paragraphs, lists and headings take in
+    /// the links on its way, so copying it gives their text in full. Links
+    /// still open when clicked. By default links are left out of selections.
+    pub fn continuous_selection(mut self, enable: bool) -> Self {
+        self.options.continuous_selection = enable;
+        self
+    }
+
     /// Show alt text when hovering over images. By default this is enabled.
     pub fn show_alt_text_on_hover(mut self, show: bool) -> Self {
         self.options.show_alt_text_on_hover = show;
         self
     }
 
+    /// Make images and mermaid diagrams clickable. A click is recorded for
+    /// [`CommonMarkCache::take_clicked_image`] (and `take_clicked_mermaid`
+    /// with the `mermaid` feature) so the host can open a lightbox. By default this is disabled.
+    pub fn clickable_images(mut self, clickable: bool) -> Self {
+        self.options.clickable_images = clickable;
+        self
+    }
+
+    /// Show a `done/total` count next to every list that contains task list
+    /// items, e.g. for TODO documents. By default this is disabled.
+    pub fn task_list_progress(mut self, show: bool) -> Self {
+        self.options.task_list_progress = show;
+        self
+    }
+
+    /// Keep single line breaks inside paragraphs as written, like many wikis
+    /// and note apps, instead of joining the lines with a space as CommonMark
+    /// does. By default this is disabled.
+    pub fn soft_breaks_as_newlines(mut self, enabled: bool) -> Self {
+        self.options.soft_breaks_as_newlines = enabled;
+        self
+    }
+
+    /// Accent bar colors for nested blockquotes, indexed by depth and repeating
+    /// once exhausted. By default deeper quotes get a progressively stronger
+    /// shade of the theme's weak text color.
+    pub fn blockquote_colors(mut self, colors: impl Into<Vec<egui::Color32>>) -> Self {
+        self.options.blockquote_colors = colors.into();
+        self
+    }
+
+    /// Width of the blockquote accent bar, a background tint and the corner
+    /// rounding of both, also used for alerts. By default quotes have a 3 point
+    /// bar, no background and square corners.
+    pub fn blockquote_style(mut self, style: BlockquoteStyle) -> Self {
+        self.options.blockquote_style = style;
+        self
+    }
+
+    /// Collapse blockquotes nested more than `depth` levels deep, like the older
+    /// messages of an exported email thread, behind a "show quoted text"
+    /// toggle. `Some(0)` collapses every quote. By default this is disabled.
+    pub fn collapse_quotes_deeper_than(mut self, depth: Option<usize>) -> Self {
+        self.options.collapse_quotes_deeper_than = depth;
+        self
+    }
+
+    /// The color of links, when they are underlined and the color of the ones
+    /// that were clicked before. By default links have the theme's hyperlink
+    /// color and are always underlined.
+    pub fn link_style(mut self, style: LinkStyle) -> Self {
+        self.options.link_style = style;
+        self
+    }
+
+    /// Bullets and numbering styles of lists by nesting depth, and the color
+    /// of their markers. By default bullets are a dot at the top level and
+    /// circles below it, numbers are decimal and both use the strong text
+    /// color.
+    pub fn list_markers(mut self, markers: ListMarkers) -> Self {
+        self.options.list_markers = markers;
+        self
+    }
+
+    /// Cap the width of every table column. Cell contents past the cap are
+    /// clipped unless [`table_wrap_cells`](Self::table_wrap_cells) is enabled.
+    pub fn table_max_column_width(mut self, width: f32) -> Self {
+        self.options.table_max_column_width = Some(width);
+        self
+    }
+
+    /// Wrap long table cell text onto several lines instead of letting the
+    /// table extend horizontally. By default this is disabled.
+    pub fn table_wrap_cells(mut self, enabled: bool) -> Self {
+        self.options.table_wrap_cells = enabled;
+        self
+    }
+
+    /// Alternate the background color of table rows. By default this is enabled.
+    pub fn table_striped(mut self, enabled: bool) -> Self {
+        self.options.table_striped = enabled;
+        self
+    }
+
+    /// Highlight the table row under the cursor. By default this is disabled.
+    pub fn table_row_hover(mut self, enabled: bool) -> Self {
+        self.options.table_row_hover = enabled;
+        self
+    }
+
+    /// Whether tables wider than the viewer scroll horizontally or are fitted
+    /// to its width with wrapping cells. Either way the vertical scroll wheel
+    /// keeps scrolling the page over a table. Default is [`TableMode::Scroll`].
+    pub fn table_mode(mut self, mode: TableMode) -> Self {
+        self.options.table_mode = mode;
+        self
+    }
+
+    /// Show line numbers beside code blocks. The numbers can't be selected and
+    /// are left out of the copied text. By default this is disabled.
+    pub fn code_line_numbers(mut self, enabled: bool) -> Self {
+        self.options.code_line_numbers = enabled;
+        self
+    }
+
+    /// What the copy button of code blocks copies: the code, the code in a
+    /// fence or the code with line numbers. A dropdown beside the button
+    /// switches the format for all code blocks. By default this is
+    /// [`CodeCopyFormat::Code`].
+    pub fn code_copy_format(mut self, format: CodeCopyFormat) -> Self {
+        self.options.code_copy_format = format;
+        self
+    }
+
+    /// Show a header strip above code blocks with the fence language and the
+    /// copy button, instead of overlaying the copy button on the code. By
+    /// default this is disabled.
+    pub fn code_block_header(mut self, enabled: bool) -> Self {
+        self.options.code_block_header = enabled;
+        self
+    }
+
+    /// The copy button of `console`, `bash` and other shell blocks whose lines
+    /// start with a `$ ` prompt copies only the commands, without the prompts
+    /// or the output shown after them, so they can be pasted into a terminal.
+    /// By default this is disabled.
+    pub fn copy_without_prompts(mut self, enabled: bool) -> Self {
+        self.options.copy_without_prompts = enabled;
+        self
+    }
+
+    /// Render ```` ```csv ```` and ```` ```tsv ```` code blocks as tables, with
+    /// a button to switch to the raw text. By default this is disabled.
+    pub fn csv_tables(mut self, enabled: bool) -> Self {
+        self.options.csv_tables = enabled;
+        self
+    }
+
+    /// Show images that point at video or audio files or YouTube videos as a
+    /// card with their thumbnail, when they have one, and a button to open them
+    /// externally, instead of a broken image. Links to them get an icon in
+    /// front. By default this is disabled.
+    pub fn media_embeds(mut self, enabled: bool) -> Self {
+        self.options.media_embeds = enabled;
+        self
+    }
+
+    /// Show the title of images, `![alt](uri "title")`, as a caption centered
+    /// under them in small muted text. [`ImageCaptions::TitleOrEmphasis`] also
+    /// takes a paragraph of only emphasized text right after a paragraph of only
+    /// an image as its caption. Default is [`ImageCaptions::Off`].
+    pub fn image_captions(mut self, captions: ImageCaptions) -> Self {
+        self.options.image_captions = captions;
+        self
+    }
+
+    /// Only load images within a screen of the visible part of the document,
+    /// reserving the space of the rest, and release the textures of images
+    /// scrolled far away. Keeps memory bounded in documents with hundreds of
+    /// images. By default this is disabled.
+    pub fn lazy_images(mut self, enabled: bool) -> Self {
+        self.options.lazy_images = enabled;
+        self
+    }
+
+    /// Dim images, or invert their lightness, while the dark theme is used,
+    /// so white screenshots don't glare. With [`Self::image_context_menu`],
+    /// an image's menu can switch it back to its original colors. By default
+    /// images are shown as they are.
+    pub fn dark_mode_images(mut self, mode: DarkModeImages) -> Self {
+        self.options.dark_mode_images = mode;
+        self
+    }
+
+    /// While scrolling, start loading the images of the next screenful in the
+    /// direction scrolled, or of up to three when scrolling fast, so they are
+    /// decoded by the time they come into view. By default this is disabled.
+    pub fn image_prefetch(mut self, enabled: bool) -> Self {
+        self.options.image_prefetch = enabled;
+        self
+    }
+
+    /// Right-clicking an image or diagram opens a menu to copy it, copy its
+    /// url or save it. Saving only records the image, see
+    /// [`CommonMarkCache::take_image_to_save`]. By default this is disabled.
+    pub fn image_context_menu(mut self, enabled: bool) -> Self {
+        self.options.image_context_menu = enabled;
+        self
+    }
+
+    /// Show a small square of the color beside inline code that is a hex
+    /// (`#ff8800`) or `rgb()` color. By default this is disabled.
+    pub fn color_swatches(mut self, enabled: bool) -> Self {
+        self.options.color_swatches = enabled;
+        self
+    }
+
+    /// Right-clicking a table opens a menu to copy it as CSV, TSV or
+    /// Markdown. By default this is disabled.
+    pub fn table_context_menu(mut self, enabled: bool) -> Self {
+        self.options.table_context_menu = enabled;
+        self
+    }
+
+    /// Hovering a heading shows a link icon beside it that copies a link to
+    /// the heading: `document#slug`, e.g. `notes.md#usage`, or just `#slug`
+    /// when `document` is empty. `None` shows no icon, which is the default.
+    pub fn heading_link_button(mut self, document: Option<&str>) -> Self {
+        self.options.heading_link_button = document.map(str::to_owned);
+        self
+    }
+
+    /// Hovering a paragraph or list item shows a copy icon in its top right
+    /// corner that copies the markdown source of the block, without the list
+    /// marker. Default is false.
+    pub fn block_copy_buttons(mut self, enabled: bool) -> Self {
+        self.options.block_copy_buttons = enabled;
+        self
+    }
+
+    /// Restrict what an untrusted document can do, e.g. [`Sandbox::all`]
+    /// to load no remote or `data:` images, show raw HTML as text and ask
+    /// before opening a link in another app. By default nothing is
+    /// restricted.
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.options.sandbox = sandbox;
+        self
+    }
+
+    /// Open long documents without freezing: [`Self::show_scrollable`] parses
+    /// them on a worker thread and lays them out a part per frame, showing a
+    /// progress bar until the whole document has been measured. Later layouts
+    /// for a new width or zoom are spread out the same way. By default this is
+    /// disabled.
+    pub fn progressive_loading(mut self, enabled: bool) -> Self {
+        self.options.progressive_loading = enabled;
+        self
+    }
+
+    /// Which markdown extensions are interpreted, e.g.
+    /// [`MarkdownExtensions::none`] for strict CommonMark. Disabled syntax is
+    /// shown as written. By default all of them are enabled.
+    pub fn extensions(mut self, extensions: MarkdownExtensions) -> Self {
+        self.options.extensions = extensions;
+        self
+    }
+
     /// Allows changing the default implicit `file://` uri scheme.
     /// This does nothing if [`explicit_image_uri_scheme`](`Self::explicit_image_uri_scheme`) is enabled
     ///
@@ -240,6 +559,133 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
+    /// Render images that fail to load with `func` instead of the built-in
+    /// placeholder (a warning sign, the alt text and the uri). Failures are
+    /// recorded either way, see [`CommonMarkCache::failed_images`].
+    pub fn render_image_error_fn(mut self, func: Option<&'f RenderImageErrorFn>) -> Self {
+        self.options.image_error_fn = func;
+        self
+    }
+
+    /// Call `func` with the source span of a task list checkbox and whether it
+    /// is checked now when it is clicked. This makes the checkboxes clickable
+    /// in [`show`](Self::show) and the scrollable viewers too, leaving it to
+    /// the host to change the text or not. By default checkboxes can only be
+    /// clicked with [`show_mut`](Self::show_mut).
+    pub fn checkbox_clicked_fn(mut self, func: Option<&'f CheckboxClickedFn>) -> Self {
+        self.options.checkbox_clicked_fn = func;
+        self
+    }
+
+    /// Paint a background behind the text rendered from each of `ranges`,
+    /// byte ranges of the markdown source, e.g. for annotations or the
+    /// matches of a search. Where they overlap the later one is painted, and
+    /// the search matches set with [`CommonMarkCache::set_search_ranges`] are
+    /// painted over them all. Text in code blocks is not highlighted. By
+    /// default nothing is highlighted.
+    pub fn highlight_ranges(mut self, ranges: &'f [SourceRange]) -> Self {
+        self.options.highlight_ranges = ranges;
+        self
+    }
+
+    /// Put a number such as `1.2` in front of each heading in `numbers`,
+    /// which pairs the byte offset a heading starts at in the markdown source
+    /// with its number, sorted by offset. The number isn't part of the
+    /// heading's text or anchor. By default headings aren't numbered.
+    pub fn heading_numbers(mut self, numbers: &'f [(usize, String)]) -> Self {
+        self.options.heading_numbers = numbers;
+        self
+    }
+
+    /// Rewrite the destination of every link and image with `func` before it
+    /// is used, e.g. to resolve relative paths against the document or map
+    /// custom schemes. It also gets whether the destination is an image and
+    /// returns `None` to keep it. Images without a scheme get the
+    /// [implicit one](Self::default_implicit_uri_scheme) after being
+    /// rewritten, and link hooks match the rewritten destination. By default
+    /// destinations are used as written.
+    pub fn rewrite_url_fn(mut self, func: Option<&'f RewriteUrlFn>) -> Self {
+        self.options.rewrite_url_fn = func;
+        self
+    }
+
+    /// Render fenced code blocks whose language `lang_matcher` accepts with
+    /// `render_fn`, e.g. for `plantuml` or `vega` diagrams. Can be called
+    /// several times; matching functions are tried in the order they were
+    /// added, before the [`CommonMarkRenderHooks`], until one returns `true`.
+    /// Blocks nobody renders fall through to the default code block.
+    ///
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// let is_music = |lang: &str| lang == "music" || lang == "abc";
+    /// let render_music = |ui: &mut egui::Ui, _lang: &str, notes: &str| {
+    ///     ui.label(format!("\u{1F3B5} {}", notes.trim()));
+    ///     true
+    /// };
+    /// CommonMarkViewer::new().code_block_fn(&is_music, &render_music);
+    /// ```
+    pub fn code_block_fn(
+        mut self,
+        lang_matcher: &'f CodeLangMatcher,
+        render_fn: &'f RenderCodeBlockFn,
+    ) -> Self {
+        self.options.code_block_fns.push((lang_matcher, render_fn));
+        self
+    }
+
+    /// Add widgets below the code blocks the viewer draws itself, e.g. a
+    /// button running a shell snippet and its output. `func` gets the block's
+    /// language, content and an id unique to it, to keep state under. Blocks
+    /// rendered by a [`code_block_fn`](Self::code_block_fn) or the render
+    /// hooks get no footer. By default there is none.
+    pub fn code_block_footer_fn(mut self, func: Option<&'f CodeBlockFooterFn>) -> Self {
+        self.options.code_block_footer_fn = func;
+        self
+    }
+
+    /// Let `hooks` render headings, paragraphs, images, links, code blocks and
+    /// tables in place of the defaults, see [`CommonMarkRenderHooks`].
+    pub fn render_hooks(mut self, hooks: Option<&'f dyn CommonMarkRenderHooks>) -> Self {
+        self.options.render_hooks = hooks;
+        self
+    }
+
+    /// Translate GitHub `:shortcode:` emoji (e.g. `:smile:` → 😄) using the
+    /// bundled gemoji table. Unknown shortcodes, code and image alt text stay
+    /// literal. Default is true.
+    pub fn emoji_shortcodes(mut self, enabled: bool) -> Self {
+        self.options.emoji_shortcodes = enabled;
+        self
+    }
+
+    /// Render `^superscript^` and `~subscript~` (plus inline `<sup>` and
+    /// `<sub>` HTML) as raised/lowered smaller text. Since single tildes then
+    /// mean subscript, `~text~` no longer renders as strikethrough; `~~text~~`
+    /// still does. Default is false.
+    pub fn superscript_subscript(mut self, enabled: bool) -> Self {
+        self.options.superscript_subscript = enabled;
+        self
+    }
+
+    /// Let a document define LaTeX macros for all of its formulas, under a
+    /// `macros:` key of its YAML front matter (`R: "\\mathbb{R}"`) or in a
+    /// first display math block of only `\newcommand`s and `\def`s, which is
+    /// then hidden. Front matter is no longer shown as text. By default this
+    /// is disabled.
+    pub fn math_macros(mut self, enabled: bool) -> Self {
+        self.options.math_macros = enabled;
+        self
+    }
+
+    /// Number display equations, right of them, and replace `\ref{label}` and
+    /// `\eqref{label}` in text and formulas with the number of the equation
+    /// holding `\label{label}`. `\tag{…}` sets a number and `\nonumber`
+    /// leaves one out. By default this is disabled.
+    pub fn equation_numbers(mut self, enabled: bool) -> Self {
+        self.options.equation_numbers = enabled;
+        self
+    }
+
     // ========== Typography Methods ==========
 
     /// Opt into the named font family used for markdown strong text.
@@ -383,6 +829,92 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
+    /// Set extra spacing between letters as a multiplier of font size.
+    ///
+    /// Recommended value: at most 0.12 (per WCAG 2.1 SC 1.4.12)
+    ///
+    /// # Example
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// CommonMarkViewer::new().letter_spacing(0.12); // 12% of font size
+    /// ```
+    pub fn letter_spacing(mut self, multiplier: f32) -> Self {
+        self.options.typography.letter_spacing = Some(Measurement::Multiplier(multiplier));
+        self
+    }
+
+    /// Set extra spacing between letters as absolute pixels.
+    ///
+    /// # Example
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// CommonMarkViewer::new().letter_spacing_px(1.0); // 1 pixel
+    /// ```
+    pub fn letter_spacing_px(mut self, pixels: f32) -> Self {
+        self.options.typography.letter_spacing = Some(Measurement::Pixels(pixels));
+        self
+    }
+
+    /// Set extra spacing between words as a multiplier of font size.
+    ///
+    /// Recommended value: at most 0.16 (per WCAG 2.1 SC 1.4.12)
+    ///
+    /// # Example
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// CommonMarkViewer::new().word_spacing(0.16); // 16% of font size
+    /// ```
+    pub fn word_spacing(mut self, multiplier: f32) -> Self {
+        self.options.typography.word_spacing = Some(Measurement::Multiplier(multiplier));
+        self
+    }
+
+    /// Set extra spacing between words as absolute pixels.
+    ///
+    /// # Example
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// CommonMarkViewer::new().word_spacing_px(3.0); // 3 pixels
+    /// ```
+    pub fn word_spacing_px(mut self, pixels: f32) -> Self {
+        self.options.typography.word_spacing = Some(Measurement::Pixels(pixels));
+        self
+    }
+
+    /// Indent the first line of paragraphs outside of lists, as a multiplier of
+    /// font size.
+    ///
+    /// # Example
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// CommonMarkViewer::new().first_line_indent(2.0); // 200% of font size
+    /// ```
+    pub fn first_line_indent(mut self, multiplier: f32) -> Self {
+        self.options.typography.first_line_indent = Some(Measurement::Multiplier(multiplier));
+        self
+    }
+
+    /// Indent the first line of paragraphs outside of lists by absolute pixels.
+    ///
+    /// # Example
+    /// ```
+    /// # use egui_commonmark::CommonMarkViewer;
+    /// CommonMarkViewer::new().first_line_indent_px(32.0); // 32 pixels
+    /// ```
+    pub fn first_line_indent_px(mut self, pixels: f32) -> Self {
+        self.options.typography.first_line_indent = Some(Measurement::Pixels(pixels));
+        self
+    }
+
+    /// Justify the text of paragraphs, stretching wrapped rows to the full
+    /// width. Runs of text in different styles, like a bold word, are
+    /// justified separately and the last row of each run is left as is.
+    /// By default text is aligned to the left.
+    pub fn justify(mut self, justify: bool) -> Self {
+        self.options.typography.justify = justify;
+        self
+    }
+
     /// Apply evidence-based typography defaults for optimal readability.
     ///
     /// Based on peer-reviewed HCI research, WCAG 2.1, and vision science:
@@ -430,24 +962,19 @@ impl<'f> CommonMarkViewer<'f> {
         self
     }
 
-    /// Shows rendered markdown
+    /// Shows rendered markdown, returning the links, images and headings the
+    /// user can interact with.
     pub fn show(
         self,
         ui: &mut egui::Ui,
         cache: &mut CommonMarkCache,
         text: &str,
-    ) -> egui::InnerResponse<()> {
+    ) -> CommonMarkResponse {
         egui_commonmark_backend_extended::prepare_show(cache, ui.ctx());
 
-        let (response, _) = parsers::pulldown::CommonMarkViewerInternal::new().show(
-            ui,
-            cache,
-            &self.options,
-            text,
-            None,
-        );
-
-        response
+        let mut viewer = parsers::pulldown::CommonMarkViewerInternal::new();
+        let (response, _) = viewer.show(ui, cache, &self.options, text, None);
+        viewer.into_response(response.response, cache)
     }
 
     /// Shows rendered markdown, and allows the rendered ui to mutate the source text.
@@ -458,18 +985,14 @@ impl<'f> CommonMarkViewer<'f> {
         ui: &mut egui::Ui,
         cache: &mut CommonMarkCache,
         text: &mut String,
-    ) -> egui::InnerResponse<()> {
+    ) -> CommonMarkResponse {
         self.options.mutable = true;
         egui_commonmark_backend_extended::prepare_show(cache, ui.ctx());
 
-        let (mut inner_response, checkmark_events) =
-            parsers::pulldown::CommonMarkViewerInternal::new().show(
-                ui,
-                cache,
-                &self.options,
-                text,
-                None,
-            );
+        let mut viewer = parsers::pulldown::CommonMarkViewerInternal::new();
+        let (inner_response, checkmark_events) =
+            viewer.show(ui, cache, &self.options, text, None);
+        let mut response = viewer.into_response(inner_response.response, cache);
 
         // Update source text for checkmarks that were clicked
         for ev in checkmark_events {
@@ -479,18 +1002,21 @@ impl<'f> CommonMarkViewer<'f> {
                 text.replace_range(ev.span, "[ ]")
             }
 
-            inner_response.response.mark_changed();
+            response.response.mark_changed();
         }
 
-        inner_response
+        response
     }
 
-    /// Shows markdown inside a [`ScrollArea`] with viewport-clipped rendering.
+    /// Shows markdown inside a [`ScrollArea`], laying out only the blocks
+    /// that are visible, so huge documents stay fast to scroll.
     ///
-    /// Renders only the events whose layout y-positions intersect the visible
-    /// viewport, using waypoints (split_points) populated on the first paint
-    /// and a layout signature (width / font size / theme) that invalidates
-    /// them when the rendering context changes.
+    /// The first frame lays out the whole document and measures each
+    /// top-level block. Later frames paint the blocks within the viewport and
+    /// reserve the measured heights for the rest. The document is measured
+    /// again when its width, font size or theme changes, when a painted block
+    /// changes height (e.g. an image finished loading), and for a
+    /// [`pending_scroll_offset`], so heading and search positions are exact.
     ///
     /// # Content change contract
     ///
@@ -501,6 +1027,7 @@ impl<'f> CommonMarkViewer<'f> {
     ///
     /// [`ScrollArea`]: egui::ScrollArea
     /// [`content_version`]: CommonMarkViewer::content_version
+    /// [`pending_scroll_offset`]: CommonMarkViewer::pending_scroll_offset
     #[cfg(feature = "pulldown_cmark")]
     pub fn show_scrollable(
         self,
@@ -521,6 +1048,45 @@ impl<'f> CommonMarkViewer<'f> {
             self.scroll_source,
         )
     }
+
+    /// Parse `text` into its headings, paragraphs, code blocks and tables with
+    /// their source spans, without rendering. Uses the same parser options as
+    /// the `show` methods, so outlines, search or exports built from it agree
+    /// with what is displayed.
+    pub fn parse_document(&self, text: &str) -> Vec<DocumentBlock> {
+        egui_commonmark_backend_extended::document::parse_document(
+            text,
+            parsers::pulldown::parser_options_for(&self.options),
+        )
+    }
+}
+
+/// What the user did with a document shown by [`CommonMarkViewer::show`] or
+/// [`CommonMarkViewer::show_mut`] this frame.
+pub struct CommonMarkResponse {
+    /// The response of the area the document was laid out in
+    pub response: egui::Response,
+    /// The link that was clicked. Links that aren't link hooks were opened
+    /// too.
+    pub clicked_link: Option<LinkInfo>,
+    /// The link under the pointer, or the one with keyboard focus
+    pub hovered_link: Option<LinkInfo>,
+    /// Texture id and size of the image that was clicked, when
+    /// [`clickable_images`](CommonMarkViewer::clickable_images) is enabled.
+    /// It is taken from the cache, so
+    /// [`CommonMarkCache::take_clicked_image`] returns `None` after `show`.
+    pub clicked_image: Option<(egui::TextureId, egui::Vec2)>,
+    /// The diagram that was clicked, like `clicked_image`
+    #[cfg(feature = "mermaid")]
+    pub clicked_mermaid: Option<(egui::TextureHandle, egui::Vec2)>,
+    /// Where each heading was drawn, in document order
+    pub heading_rects: Vec<(HeaderKey, egui::Rect)>,
+    /// Number of tables in the document
+    pub table_count: usize,
+    /// Parts of the document that failed to render and show an error
+    /// placeholder instead. They are also kept in the cache, see
+    /// [`CommonMarkCache::render_errors`].
+    pub render_errors: Vec<RenderError>,
 }
 
 pub(crate) struct ListLevel {
@@ -550,18 +1116,37 @@ impl List {
         !self.items.is_empty()
     }
 
+    /// Number of nested lists the renderer is in.
+    pub fn depth(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Leave the lists nested deeper than `depth`.
+    pub fn truncate(&mut self, depth: usize) {
+        self.items.truncate(depth);
+    }
+
     pub fn is_last_level(&self) -> bool {
         self.items.len() == 1
     }
 
     pub fn start_item(&mut self, ui: &mut egui::Ui, options: &CommonMarkOptions) {
+        self.start_item_row(ui);
+        self.item_marker(ui, options);
+    }
+
+    /// Move to the row of the next item.
+    pub fn start_item_row(&mut self, ui: &mut egui::Ui) {
         // To ensure that newlines are only inserted within the list and not before it
         if self.has_list_begun {
             newline(ui);
         } else {
             self.has_list_begun = true;
         }
+    }
 
+    /// The indentation and the bullet or number of the next item.
+    pub fn item_marker(&mut self, ui: &mut egui::Ui, options: &CommonMarkOptions) {
         let len = self.items.len();
         if let Some(item) = self.items.last_mut() {
             ui.label(" ".repeat((len - 1) * options.indentation_spaces));
@@ -574,13 +1159,20 @@ impl List {
                 .resolve_line_height(body_h)
                 .unwrap_or(body_h);
 
+            let markers = &options.list_markers;
+            let color = markers
+                .color
+                .unwrap_or_else(|| ui.visuals().strong_text_color());
             if let Some(number) = &mut item.current_number {
-                number_point(ui, &number.to_string(), row_height);
+                let label = markers.numbering(len - 1).label(*number);
+                number_point(ui, label, row_height, color);
                 *number += 1;
+            } else if let Some(glyph) = markers.bullet(len - 1) {
+                bullet_glyph(ui, glyph, row_height, color);
             } else if len > 1 {
-                bullet_point_hollow(ui, row_height);
+                bullet_point_hollow(ui, row_height, color);
             } else {
-                bullet_point(ui, row_height);
+                bullet_point(ui, row_height, color);
             }
         } else {
             unreachable!();
@@ -3,40 +3,65 @@ use std::ops::Range;
 
 use crate::{CommonMarkCache, CommonMarkOptions};
 
-use egui::{self, Id, Pos2, TextStyle, Ui};
+use egui::{self, Id, TextStyle, Ui};
 
 use crate::List;
+use egui_commonmark_backend_extended::anchor::{
+    footnote_anchor, footnote_ref_anchor, heading_slug, AnchorSlugger,
+};
+use egui_commonmark_backend_extended::alerts::alert_ui;
+use egui_commonmark_backend_extended::hooks::plain_text;
+use egui_commonmark_backend_extended::math_document::MathDocument;
+use egui_commonmark_backend_extended::media::MediaKind;
+use egui_commonmark_backend_extended::html_details::{HtmlDetails, parse_html_details};
+use egui_commonmark_backend_extended::html_table::{HtmlCell, HtmlTable};
 use egui_commonmark_backend_extended::elements::{
-    blockquote, footnote, footnote_start, heading_end_spacing, heading_start_spacing, newline,
-    paragraph_end_spacing, rule, soft_break, ImmutableCheckbox,
+    color_swatch, document_loading_placeholder, equation_number, footnote, render_error_placeholder, footnote_backlink, footnote_start, heading_end_spacing, heading_link_button, block_copy_button, heading_start_spacing, table_copy_menu, image_caption, newline,
+    justified_label, paragraph_end_spacing, paragraph_start_indent, rule, soft_break,
+    quoted_text_toggle, search_match_color, styled_blockquote, task_list_progress_label, ImmutableCheckbox,
 };
 use egui_commonmark_backend_extended::misc::*;
+use egui_commonmark_backend_extended::profile_scope;
 use egui_commonmark_backend_extended::pulldown::*;
 use pulldown_cmark::{CowStr, HeadingLevel};
 
+/// The text of `events`, to tell which way they run.
+fn texts<'a>(events: &'a [(pulldown_cmark::Event, Range<usize>)]) -> impl Iterator<Item = &'a str> {
+    events.iter().filter_map(|(event, _)| match event {
+        pulldown_cmark::Event::Text(text) => Some(text.as_ref()),
+        _ => None,
+    })
+}
+
+/// Lay `add_contents` out right to left, starting from the right edge, when
+/// `rtl` is set.
+fn directed<R>(ui: &mut Ui, rtl: bool, add_contents: impl FnOnce(&mut Ui) -> R) -> R {
+    if rtl {
+        let layout = egui::Layout::right_to_left(egui::Align::BOTTOM).with_main_wrap(true);
+        ui.allocate_ui_with_layout(egui::vec2(ui.available_width(), 0.0), layout, add_contents)
+            .inner
+    } else {
+        add_contents(ui)
+    }
+}
+
 /// Search-match highlight kind for a single rendered text segment.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum HighlightKind {
     None,
     Match,
     Active,
+    /// One of the viewer's `highlight_ranges`, in its color if it has one
+    Range(Option<egui::Color32>),
 }
 
 impl HighlightKind {
     fn background_color(self, ui: &Ui) -> Option<egui::Color32> {
-        let dark = ui.style().visuals.dark_mode;
         match self {
             HighlightKind::None => None,
-            HighlightKind::Match => Some(if dark {
-                egui::Color32::from_rgb(102, 92, 46)
-            } else {
-                egui::Color32::from_rgb(255, 229, 127)
-            }),
-            HighlightKind::Active => Some(if dark {
-                egui::Color32::from_rgb(156, 107, 26)
-            } else {
-                egui::Color32::from_rgb(255, 167, 38)
-            }),
+            HighlightKind::Range(Some(color)) => Some(color),
+            HighlightKind::Match | HighlightKind::Range(None) => Some(search_match_color(ui, false)),
+            HighlightKind::Active => Some(search_match_color(ui, true)),
         }
     }
 }
@@ -126,7 +151,61 @@ fn visit_emoji_text_segments<'a>(
     }
 }
 
+/// The markdown of the list item `source`, starting at `column` of its
+/// line, without its marker and its other lines dedented to match.
+fn item_markdown(source: &str, column: usize) -> String {
+    let mut lines = source.trim_end().lines();
+    let Some(first) = lines.next() else {
+        return String::new();
+    };
+    let marker = first.trim_start();
+    let content = marker
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .strip_prefix(['-', '*', '+', '.', ')'])
+        .unwrap_or(marker)
+        .trim_start();
+    let indent = column + first.len() - content.len();
+    let mut markdown = content.to_owned();
+    for line in lines {
+        markdown.push('\n');
+        let blank = line.len() - line.trim_start_matches(' ').len();
+        markdown.push_str(&line[blank.min(indent)..]);
+    }
+    markdown
+}
+
 /// Only ordinary visible text may expand; image alt text and code blocks stay literal.
+/// Whether a paragraph body, ending in its `End(Paragraph)`, is one image.
+fn is_image_only(body: &[(pulldown_cmark::Event, Range<usize>)]) -> bool {
+    matches!(
+        body.first(),
+        Some((pulldown_cmark::Event::Start(pulldown_cmark::Tag::Image { .. }), _))
+    ) && body
+        .iter()
+        .position(|(e, _)| matches!(e, pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Image)))
+        == Some(body.len().saturating_sub(2))
+}
+
+/// Whether a paragraph body, ending in its `End(Paragraph)`, is a single
+/// emphasized span like `*Figure 1: Results*`.
+fn is_emphasis_only(body: &[(pulldown_cmark::Event, Range<usize>)]) -> bool {
+    let mut depth = 0;
+    for (i, (event, _)) in body.iter().enumerate() {
+        match event {
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Emphasis) => depth += 1,
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Emphasis) => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 2 == body.len();
+                }
+            }
+            _ if depth == 0 => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
 fn emoji_expansion_is_eligible(in_image: bool, in_code_block: bool) -> bool {
     !in_image && !in_code_block
 }
@@ -194,6 +273,47 @@ fn visit_highlight_segments<'a>(
     }
 }
 
+/// Visit the pieces of `text`, the source bytes `span`, split where the
+/// `ranges` begin and end, with the last range covering each piece. Unlike
+/// search ranges these may overlap and come in any order.
+fn visit_source_range_segments<'a>(
+    text: &'a str,
+    span: &Range<usize>,
+    ranges: &[SourceRange],
+    mut visit: impl FnMut(&'a str, Option<&SourceRange>),
+) {
+    let mut edges = vec![0, text.len()];
+    for source in ranges {
+        for edge in [source.range.start, source.range.end] {
+            let edge = edge.clamp(span.start, span.end) - span.start;
+            if text.is_char_boundary(edge) {
+                edges.push(edge);
+            }
+        }
+    }
+    edges.sort_unstable();
+    edges.dedup();
+    // Neighbouring pieces under the same range are visited as one
+    let mut pending: Option<(usize, Option<usize>)> = None;
+    for piece in edges.windows(2) {
+        let (start, end) = (span.start + piece[0], span.start + piece[1]);
+        let covering = ranges
+            .iter()
+            .rposition(|source| source.range.start <= start && end <= source.range.end);
+        match pending {
+            Some((_, last)) if last == covering => {}
+            Some((from, last)) => {
+                visit(&text[from..piece[0]], last.map(|index| &ranges[index]));
+                pending = Some((piece[0], covering));
+            }
+            None => pending = Some((piece[0], covering)),
+        }
+    }
+    if let Some((from, last)) = pending {
+        visit(&text[from..], last.map(|index| &ranges[index]));
+    }
+}
+
 /// Split a long inline-code token into fixed-size chunks so the row-wrap layout
 /// can put each chunk on its own row instead of overflowing the content width.
 /// Short tokens (<= MAX) pass through unchanged.
@@ -256,50 +376,145 @@ fn html_cell_visual_lines(cell: &str) -> usize {
     explicit_lines.saturating_add(wrap_est).max(1)
 }
 
-/// Redirect Shift+vertical-wheel over a hovered wide-table into its inner
-/// horizontal scroll offset. Plain vertical wheel is left untouched so the
-/// outer document scroller keeps scrolling the page (this is the behavior
-/// users expect; the unconditional redirect from #4 caused #22).
+/// Lay out a table cell's contents according to its column's alignment.
+///
+/// The cell's widgets flow left to right, so center / right alignment is done
+/// by indenting with the space left over from the contents' width, which is
+/// remembered from the previous frame under `id`.
+fn aligned_cell(
+    ui: &mut Ui,
+    alignment: pulldown_cmark::Alignment,
+    id: Id,
+    add_contents: impl FnOnce(&mut Ui),
+) {
+    let factor = match alignment {
+        pulldown_cmark::Alignment::None | pulldown_cmark::Alignment::Left => {
+            add_contents(ui);
+            return;
+        }
+        pulldown_cmark::Alignment::Center => 0.5,
+        pulldown_cmark::Alignment::Right => 1.0,
+    };
+    let left = ui.max_rect().left();
+    let available = ui.available_width();
+    let last_width: Option<f32> = ui.data(|d| d.get_temp(id));
+    let indent = last_width.map_or(0.0, |w| ((available - w) * factor).max(0.0));
+    ui.add_space(indent);
+    add_contents(ui);
+    let width = ui.min_rect().right() - left - indent;
+    if last_width.map_or(true, |w| (w - width).abs() > 0.5) {
+        ui.data_mut(|d| d.insert_temp(id, width));
+        ui.ctx().request_repaint();
+    }
+}
+
+/// egui_extras only paints the hover highlight for rows with an interactive
+/// sense. A click sense is registered beneath the cell contents, so links and
+/// checkboxes inside cells keep receiving their clicks.
+fn table_row_sense(options: &CommonMarkOptions) -> egui::Sense {
+    if options.table_row_hover {
+        egui::Sense::click()
+    } else {
+        egui::Sense::hover()
+    }
+}
+
+/// Horizontal scroll area around a table, bounded at `max_width`. In
+/// [`TableMode::Fit`] scrolling is disabled and the columns are sized to fit
+/// instead.
 ///
-/// The Shift modifier acts as an explicit opt-in for sideways table scrolling
-/// without dragging the bottom scrollbar. Native horizontal trackpad input is
-/// already consumed by `ScrollArea::horizontal()` inside its `.show()` call,
-/// so this helper only ever touches the Y delta.
+/// The area takes no wheel input itself: plain vertical wheel stays with the
+/// outer document scroller (the unconditional redirect from #4 caused #22),
+/// and sideways wheel input is applied by
+/// [`forward_wheel_to_horizontal_scroll`].
+fn table_scroll_area<R>(
+    ui: &mut Ui,
+    id: Id,
+    max_width: f32,
+    mode: TableMode,
+    add_contents: impl FnOnce(&mut Ui) -> R,
+) -> egui::containers::scroll_area::ScrollAreaOutput<R> {
+    egui::ScrollArea::new([mode == TableMode::Scroll, false])
+        .id_salt(id.with("_scroll"))
+        .max_width(max_width)
+        .auto_shrink([false, true])
+        .scroll_source(egui::scroll_area::ScrollSource::SCROLL_BAR)
+        .show(ui, add_contents)
+}
+
+/// Scroll a hovered wide table sideways for wheel input that is meant to be
+/// horizontal: Shift+wheel, or a trackpad gesture that is mostly horizontal.
+/// Mostly vertical gestures are left alone so scrolling the page over a table
+/// doesn't nudge it sideways.
 ///
 /// Edge pass-through: when the table is at either side and the wheel direction
 /// would push past the edge, the delta is left for the outer scroller.
-fn forward_shift_wheel_to_horizontal_scroll<R>(
+fn forward_wheel_to_horizontal_scroll<R>(
     ui: &Ui,
     out: &mut egui::containers::scroll_area::ScrollAreaOutput<R>,
 ) {
     if !ui.rect_contains_pointer(out.inner_rect) {
         return;
     }
-    if !ui.ctx().input(|i| i.modifiers.shift) {
-        return;
-    }
-    let dy = ui.ctx().input(|i| i.smooth_scroll_delta.y);
-    if dy.abs() < 0.1 {
+    // egui already turns Shift+wheel into a horizontal delta on most
+    // platforms; the Y delta is checked for the rest.
+    let (delta, shift) = ui
+        .ctx()
+        .input(|i| (i.smooth_scroll_delta, i.modifiers.shift));
+    let (dx, axis) = if shift && delta.y.abs() >= 0.1 {
+        (delta.y, 1)
+    } else if delta.x.abs() >= 0.1 && delta.x.abs() > delta.y.abs() {
+        (delta.x, 0)
+    } else {
         return;
-    }
+    };
     let max_x = (out.content_size.x - out.inner_rect.width()).max(0.0);
     if max_x <= 0.0 {
         return;
     }
-    let at_left = out.state.offset.x <= 0.0 && dy > 0.0;
-    let at_right = out.state.offset.x >= max_x && dy < 0.0;
+    let at_left = out.state.offset.x <= 0.0 && dx > 0.0;
+    let at_right = out.state.offset.x >= max_x && dx < 0.0;
     if at_left || at_right {
         return;
     }
-    let new_x = (out.state.offset.x - dy).clamp(0.0, max_x);
+    let new_x = (out.state.offset.x - dx).clamp(0.0, max_x);
     if (new_x - out.state.offset.x).abs() > f32::EPSILON {
         out.state.offset.x = new_x;
         out.state.store(ui.ctx(), out.id);
-        ui.ctx().input_mut(|i| i.smooth_scroll_delta.y = 0.0);
+        ui.ctx().input_mut(|i| i.smooth_scroll_delta[axis] = 0.0);
         ui.ctx().request_repaint();
     }
 }
 
+/// Widest a column may be for `num_cols` columns to fit `max_width`, leaving
+/// room for the table's frame and the spacing between columns.
+/// Start loading the images ahead of `viewport` in the direction the
+/// document is scrolled, see `ScrollableCache::prefetch_range`.
+fn prefetch_images(
+    ctx: &egui::Context,
+    viewport: egui::Rect,
+    cache: &mut CommonMarkCache,
+    options: &CommonMarkOptions,
+    source_id: Id,
+) {
+    let sc = scroll_cache(cache, &source_id);
+    let range = sc.prefetch_range(viewport, ctx.input(|i| i.time));
+    let uris: Vec<String> = sc
+        .image_urls_in(range)
+        .into_iter()
+        .map(|url| Image::new(url, options).uri)
+        .collect();
+    for uri in uris {
+        prefetch_image(ctx, cache, options, &uri);
+    }
+}
+
+fn fit_column_width(ui: &Ui, max_width: f32, num_cols: usize) -> f32 {
+    let frame = egui::Frame::group(ui.style()).total_margin().sum().x;
+    let spacing = ui.spacing().item_spacing.x * num_cols.saturating_sub(1) as f32;
+    ((max_width - frame - spacing) / num_cols.max(1) as f32).max(40.0)
+}
+
 /// Newline logic is constructed by the following:
 /// All elements try to insert a newline before them (if they are allowed)
 /// and end their own line.
@@ -356,8 +571,12 @@ struct DefinitionList {
     is_def_list_def: bool,
 }
 
+/// A footnote definition's label and the events of its body.
+type FootnoteDefinition = (String, Vec<(pulldown_cmark::Event<'static>, Range<usize>)>);
+
 pub struct CommonMarkViewerInternal {
     curr_table: usize,
+    curr_details: usize,
     curr_code_block: usize,
     text_style: Style,
     list: List,
@@ -384,6 +603,69 @@ pub struct CommonMarkViewerInternal {
     /// disambiguate duplicate-titled headers (e.g. multiple `## Installation`).
     /// Reset at the start of each `show*` call so the count restarts at 0.
     heading_occurrence_counts: std::collections::HashMap<String, usize>,
+    /// Top-level block (source range, line) waiting for its first row, so the
+    /// recorded y skips the blank separator line inserted before it.
+    pending_source_block: Option<(Range<usize>, usize, usize)>,
+    /// Opening `<details>` HTML block whose body is rendered next.
+    details: Option<HtmlDetails>,
+    /// GitHub-style anchor slugs for the headings seen so far this pass.
+    anchor_slugger: AnchorSlugger,
+    /// Screen y of the document's top this pass, for content-relative
+    /// positions recorded from nested uis.
+    content_top: f32,
+    /// Set by `show_scrollable`, so footnote links can scroll the document.
+    source_id: Option<Id>,
+    /// Whether heading positions are recorded. Off when only part of the
+    /// document is painted, as duplicate headings are told apart by counting
+    /// the ones above; the last full layout recorded them.
+    record_headings: bool,
+    /// Footnote labels in order of first reference with the number of
+    /// references seen; a footnote's number is its index + 1.
+    footnote_refs: Vec<(String, usize)>,
+    /// Label of a footnote definition whose body is collected next.
+    footnote_definition: Option<String>,
+    /// Footnote definitions, rendered together at the end of the document.
+    footnote_definitions: Vec<FootnoteDefinition>,
+    /// Column alignments of the table being rendered.
+    table_alignments: Vec<pulldown_cmark::Alignment>,
+    /// Checked / total task items per list start offset, when
+    /// `task_list_progress` is enabled.
+    task_progress: std::collections::HashMap<usize, (usize, usize)>,
+    /// Macros and equation numbers of the document, when `math_macros` or
+    /// `equation_numbers` is enabled.
+    math: MathDocument,
+    /// Where the last image was drawn.
+    image_rect: Option<egui::Rect>,
+    /// Where the source of the last text shown ends, and where the image or
+    /// link just ended was drawn, to mark search matches in the parts of
+    /// them that aren't shown as text.
+    shown_text_end: usize,
+    element_rect: Option<egui::Rect>,
+    /// A list item whose marker waits until the direction of its text is
+    /// known.
+    pending_item: bool,
+    /// The link clicked and the one hovered this pass.
+    clicked_link: Option<LinkInfo>,
+    hovered_link: Option<LinkInfo>,
+    /// Where the headings recorded this pass were drawn.
+    heading_rects: Vec<(HeaderKey, egui::Rect)>,
+    /// Set right after a paragraph of only an image, to where it was drawn,
+    /// for a caption in the next paragraph.
+    figure_rect: Option<egui::Rect>,
+    /// The cache's text layouts, borrowed for the duration of `show`.
+    text_layouts: TextLayouts,
+    /// Whether text is in a paragraph, which justifies it when the
+    /// typography says so.
+    in_paragraph: bool,
+    /// Parts of the document shown as an error this pass.
+    render_errors: Vec<RenderError>,
+    /// Source start and top of the paragraphs and list items being drawn,
+    /// when `block_copy_buttons` is enabled.
+    open_copy_blocks: Vec<(usize, f32)>,
+    /// Source, rect and whether it is a list item of the paragraphs and list
+    /// items drawn this pass,
+    /// which get their copy buttons once the pass is done.
+    copy_blocks: Vec<(Range<usize>, egui::Rect, bool)>,
 }
 
 pub(crate) struct CheckboxClickEvent {
@@ -395,6 +677,7 @@ impl CommonMarkViewerInternal {
     pub fn new() -> Self {
         Self {
             curr_table: 0,
+            curr_details: 0,
             curr_code_block: 0,
             text_style: Style::default(),
             list: List::default(),
@@ -412,25 +695,107 @@ impl CommonMarkViewerInternal {
             current_heading_text: String::new(),
             current_heading_rich_texts: Vec::new(),
             heading_occurrence_counts: std::collections::HashMap::new(),
+            pending_source_block: None,
+            details: None,
+            anchor_slugger: AnchorSlugger::default(),
+            content_top: 0.0,
+            source_id: None,
+            record_headings: true,
+            footnote_refs: Vec::new(),
+            footnote_definition: None,
+            footnote_definitions: Vec::new(),
+            task_progress: std::collections::HashMap::new(),
+            math: MathDocument::default(),
+            table_alignments: Vec::new(),
+            image_rect: None,
+            shown_text_end: 0,
+            element_rect: None,
+            pending_item: false,
+            clicked_link: None,
+            hovered_link: None,
+            heading_rects: Vec::new(),
+            figure_rect: None,
+            text_layouts: TextLayouts::default(),
+            in_paragraph: false,
+            render_errors: Vec::new(),
+            open_copy_blocks: Vec::new(),
+            copy_blocks: Vec::new(),
+        }
+    }
+
+    /// What happened while showing a document, with `response` covering it.
+    pub(crate) fn into_response(
+        self,
+        response: egui::Response,
+        cache: &mut CommonMarkCache,
+    ) -> crate::CommonMarkResponse {
+        crate::CommonMarkResponse {
+            response,
+            clicked_link: self.clicked_link,
+            hovered_link: self.hovered_link,
+            clicked_image: cache.take_clicked_image(),
+            #[cfg(feature = "mermaid")]
+            clicked_mermaid: cache.take_clicked_mermaid(),
+            heading_rects: self.heading_rects,
+            table_count: self.curr_table,
+            render_errors: self.render_errors,
+        }
+    }
+
+    /// Copy buttons for the blocks of `text` drawn this pass.
+    fn block_copy_buttons(&mut self, ui: &mut Ui, text: &str) {
+        self.open_copy_blocks.clear();
+        for (span, rect, is_item) in std::mem::take(&mut self.copy_blocks) {
+            if let Some(source) = text.get(span.clone()) {
+                let source = if is_item {
+                    let line_start = text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+                    item_markdown(source, span.start - line_start)
+                } else {
+                    source.trim_end().to_owned()
+                };
+                block_copy_button(ui, rect, &source);
+            }
+        }
+    }
+
+    fn record_pending_source_block(&mut self, ui: &Ui, cache: &mut CommonMarkCache) {
+        if let Some((byte_range, line, end_line)) = self.pending_source_block.take() {
+            let y = ui.cursor().top() - self.content_top;
+            let source_id = self.source_id.unwrap_or(Id::NULL);
+            cache.record_source_block(source_id, SourceBlock {
+                byte_range,
+                line,
+                end_line,
+                y_range: egui::Rangef::point(y),
+            });
         }
     }
 }
 
-fn parser_options_math(is_math_enabled: bool) -> pulldown_cmark::Options {
-    if is_math_enabled {
-        parser_options() | pulldown_cmark::Options::ENABLE_MATH
-    } else {
-        parser_options()
+/// Parser options for a viewer configuration. `show` and `show_scrollable`
+/// must both parse through this, or their event streams diverge.
+pub(crate) fn parser_options_for(options: &CommonMarkOptions) -> pulldown_cmark::Options {
+    let mut parser_options = parser_options_with(&options.extensions);
+    if options.extensions.math && (options.math_fn.is_some() || cfg!(feature = "math")) {
+        parser_options |= pulldown_cmark::Options::ENABLE_MATH;
+    }
+    if options.math_macros {
+        parser_options |= pulldown_cmark::Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
     }
+    if options.superscript_subscript {
+        // Note: this turns `~x~` into subscript instead of strikethrough.
+        parser_options |=
+            pulldown_cmark::Options::ENABLE_SUPERSCRIPT | pulldown_cmark::Options::ENABLE_SUBSCRIPT;
+    }
+    parser_options
 }
 
 /// Hash the layout-affecting render context.
 ///
-/// `split_points` cache y-positions, which become invalid when anything that
-/// affects layout changes. The previous code (parsers/pulldown.rs invalidation
-/// block) only watched `available_size`, so zooming (Ctrl++/-) or toggling
-/// dark mode would leave stale split_points in place and the viewport-skip
-/// math would render the wrong content range.
+/// The blocks measured for `show_scrollable` have heights that become invalid
+/// when anything that affects layout changes. Watching only the available
+/// width would miss zooming (Ctrl++/-) or toggling dark mode, leaving stale
+/// heights in place so the viewport math paints the wrong content range.
 fn compute_layout_signature(ui: &egui::Ui, options: &CommonMarkOptions) -> u64 {
     use std::hash::{Hash, Hasher};
     let mut h = std::collections::hash_map::DefaultHasher::new();
@@ -446,51 +811,147 @@ fn compute_layout_signature(ui: &egui::Ui, options: &CommonMarkOptions) -> u64 {
     ((ui.text_style_height(&egui::TextStyle::Body) * 10.0).round() as i32).hash(&mut h);
     ((ui.text_style_height(&egui::TextStyle::Monospace) * 10.0).round() as i32).hash(&mut h);
     // Theme doesn't change widget heights, but it does change the resolved
-    // syntect theme — invalidating here keeps split_points and the syntect
+    // syntect theme — invalidating here keeps the blocks and the syntect
     // cache (added later) coherent.
     ui.style().visuals.dark_mode.hash(&mut h);
     // Caller-configured constraints that affect block widths.
     options.default_width.hash(&mut h);
+    options.max_content_width.map(f32::to_bits).hash(&mut h);
     options.indentation_spaces.hash(&mut h);
     h.finish()
 }
 
-/// Threshold for content-height drift from the last bootstrap that triggers a
-/// re-bootstrap to refresh split_points. Larger than the known ~44px egui
-/// oscillation between show()/show_viewport() content-size reporting, but
-/// small enough to catch real image-load growth.
-const CONTENT_H_DRIFT_THRESHOLD: f32 = 1024.0;
+/// Indices of the events that start a top-level block: those outside of any
+/// other block. The renderer's container state (lists, tables, emphasis) is
+/// neutral there, so painting can start at any of them.
+fn top_level_starts(events: &[(pulldown_cmark::Event, Range<usize>)]) -> Vec<usize> {
+    let mut depth = 0usize;
+    let mut starts = Vec::new();
+    for (index, (event, _)) in events.iter().enumerate() {
+        if depth == 0 {
+            starts.push(index);
+        }
+        match event {
+            pulldown_cmark::Event::Start(_) => depth += 1,
+            pulldown_cmark::Event::End(_) => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    starts
+}
 
-/// Whether a TagEnd marks a safe block-level boundary for viewport-skip.
+/// Which of the measured `blocks` [`lay_out_near_viewport`] paints: those
+/// within a screen of the viewport, those it records positions in and those
+/// overlapping the `repaint` byte ranges.
 ///
-/// At a block end the renderer's transient inline state (heading rich-text
-/// accumulator, list nesting, emphasis flags) is neutral, so a future frame
-/// can start rendering from the next event without losing context.
-///
-/// Inline ends (Emphasis, Strong, Link, Image, Superscript, Subscript) are
-/// rejected — splitting mid-paragraph would orphan inline formatting state.
-/// Table-internal ends (TableHead, TableRow, TableCell) are rejected because
-/// tables are pre-parsed and rendered as a single atomic unit.
-fn is_block_end_tag(tag: &pulldown_cmark::TagEnd) -> bool {
-    use pulldown_cmark::TagEnd;
+/// [`lay_out_near_viewport`]: CommonMarkViewerInternal::lay_out_near_viewport
+fn blocks_to_paint(
+    events: &[(pulldown_cmark::Event, Range<usize>)],
+    blocks: &[ScrollBlock],
+    viewport: egui::Rect,
+    repaint: &[Range<usize>],
+) -> Vec<bool> {
+    use pulldown_cmark::{Event, Tag};
+
+    let near = viewport.expand2(egui::vec2(0.0, viewport.height()));
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| {
+            let Some(next) = blocks.get(i + 1) else {
+                return true;
+            };
+            if block.top <= near.max.y && next.top >= near.min.y {
+                return true;
+            }
+            let block_events = &events[block.first_event..next.first_event];
+            block_events.iter().any(|(event, span)| {
+                matches!(
+                    event,
+                    Event::Start(Tag::Heading { .. } | Tag::FootnoteDefinition(_))
+                ) || repaint
+                    .iter()
+                    .any(|range| span.start < range.end && range.start < span.end)
+            })
+        })
+        .collect()
+}
+
+/// Whether blocks measured for the `old` events still fit the `new` ones,
+/// which an edit only changed the text of.
+fn edit_kept_blocks(
+    old: &[(pulldown_cmark::Event, Range<usize>)],
+    new: &[(pulldown_cmark::Event, Range<usize>)],
+    blocks: &[ScrollBlock],
+) -> bool {
+    use pulldown_cmark::Event;
+    use std::mem::discriminant;
+
+    let same_kind = |old: &Event, new: &Event| match (old, new) {
+        (Event::Start(old), Event::Start(new)) => discriminant(old) == discriminant(new),
+        _ => discriminant(old) == discriminant(new),
+    };
+    old.len() == new.len()
+        && blocks
+            .iter()
+            .all(|block| same_kind(&old[block.first_event].0, &new[block.first_event].0))
+}
+
+/// Whether an event opens a block that gets its own [`SourceBlock`] entry.
+fn is_block_start(event: &pulldown_cmark::Event) -> bool {
+    use pulldown_cmark::{Event, Tag};
     matches!(
-        tag,
-        TagEnd::Paragraph
-            | TagEnd::Heading(_)
-            | TagEnd::BlockQuote(_)
-            | TagEnd::CodeBlock
-            | TagEnd::List(_)
-            | TagEnd::Item
-            | TagEnd::FootnoteDefinition
-            | TagEnd::Table
-            | TagEnd::HtmlBlock
-            | TagEnd::MetadataBlock(_)
-            | TagEnd::DefinitionList
-            | TagEnd::DefinitionListTitle
-            | TagEnd::DefinitionListDefinition
+        event,
+        Event::Rule
+            | Event::Start(
+                Tag::Paragraph
+                    | Tag::Heading { .. }
+                    | Tag::BlockQuote(_)
+                    | Tag::CodeBlock(_)
+                    | Tag::List(_)
+                    | Tag::Table(_)
+                    | Tag::HtmlBlock
+                    | Tag::DefinitionList
+            )
     )
 }
 
+/// Incremental byte-offset → 1-based line lookup. Blocks arrive in source
+/// order, so each paint only scans the text once.
+struct LineCursor {
+    offset: usize,
+    line: usize,
+}
+
+impl LineCursor {
+    fn new() -> Self {
+        Self { offset: 0, line: 1 }
+    }
+
+    fn line_at(&mut self, text: &str, offset: usize) -> usize {
+        if offset < self.offset {
+            *self = Self::new();
+        }
+        let end = offset.min(text.len());
+        self.line += text.as_bytes()[self.offset..end]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count();
+        self.offset = end;
+        self.line
+    }
+
+    /// First and last line of the source `span`.
+    fn lines_of(&mut self, text: &str, span: &Range<usize>) -> (usize, usize) {
+        let line = self.line_at(text, span.start);
+        let end_line = line
+            + text
+                .get(span.clone())
+                .map_or(0, |s| s.trim_end_matches('\n').matches('\n').count());
+        (line, end_line)
+    }
+}
+
 /// Detect if text parsed as inline math (`$...$`) is actually NOT a real LaTeX
 /// formula. Returns true for currency amounts and other false positives like:
 /// - `$17.57` → parsed as InlineMath("17.57")
@@ -571,123 +1032,122 @@ fn is_likely_currency(tex: &str) -> bool {
     true
 }
 
-impl CommonMarkViewerInternal {
-    /// Compute a hash of the text content for event cache lookup.
-    fn hash_content(text: &str) -> u64 {
-        use std::hash::{Hash, Hasher};
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        text.hash(&mut hasher);
-        hasher.finish()
-    }
+/// With `progressive_loading`, documents at least this long are parsed on a
+/// worker thread and laid out over several frames.
+const PROGRESSIVE_LOADING_MIN_BYTES: usize = 512 * 1024;
+
+/// How long a frame spends laying out a progressively loaded document.
+const PROGRESSIVE_LAYOUT_BUDGET: std::time::Duration = std::time::Duration::from_millis(12);
 
+impl CommonMarkViewerInternal {
     /// Be aware that this acquires egui::Context internally.
-    /// If split Id is provided then split points will be populated
+    /// If a scroll id is provided then the document's top-level blocks are
+    /// measured into its `ScrollableCache`.
     pub(crate) fn show(
         &mut self,
         ui: &mut egui::Ui,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
         text: &str,
-        split_points_id: Option<Id>,
+        scroll_id: Option<Id>,
     ) -> (egui::InnerResponse<()>, Vec<CheckboxClickEvent>) {
+        profile_scope!("show");
         let max_width = options.max_width(ui);
         let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
 
-        // Compute content hash and ensure events are cached
-        let content_hash = Self::hash_content(text);
-        if cache.get_cached_events(content_hash).is_none() {
-            let math_enabled = options.math_fn.is_some() || cfg!(feature = "math");
-            let owned_events: Vec<(pulldown_cmark::Event<'static>, Range<usize>)> =
-                pulldown_cmark::Parser::new_ext(text, parser_options_math(math_enabled))
-                    .into_offset_iter()
-                    .map(|(event, range)| (event.into_static(), range))
-                    .collect();
-            cache.set_cached_events(content_hash, owned_events);
-        }
+        // A scrollable document was parsed by `show_scrollable`, and its
+        // blocks have to index into those very events.
+        let events_data = match scroll_id {
+            Some(scroll_id) => scroll_cache(cache, &scroll_id).events.clone(),
+            None => cache.parsed_events(ui.ctx(), text, parser_options_for(options)),
+        };
 
-        let re = ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
+        let margin = options.content_margin(ui);
+        let add_contents = |ui: &mut Ui| {
             ui.spacing_mut().item_spacing.x = 0.0;
             let height = ui.text_style_height(&TextStyle::Body);
             ui.set_row_height(height);
-
-            // Use cached events — clone the Vec reference data for iteration
-            // (events are 'static so this is cheap pointer copies, not re-parsing)
-            let events_data = cache.get_cached_events(content_hash)
-                .expect("events just cached")
-                .to_vec();
+            std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+            self.text_layouts.begin(ui, options);
+            cache.clear_source_blocks(scroll_id.unwrap_or(Id::NULL));
+            cache.invalidate_header_positions(scroll_id.unwrap_or(Id::NULL), text);
+            self.content_top = ui.min_rect().top();
+            self.source_id = scroll_id;
+            let mut line_cursor = LineCursor::new();
+
+            if options.task_list_progress {
+                self.task_progress = task_list_progress(&events_data);
+            }
+            if options.math_macros || options.equation_numbers {
+                self.math = MathDocument::new(&events_data, options.equation_numbers);
+            }
+            let mut block_starts = if scroll_id.is_some() {
+                top_level_starts(&events_data)
+            } else {
+                Vec::new()
+            }
+            .into_iter()
+            .peekable();
+            let mut blocks = Vec::new();
             let mut events = events_data
-                .into_iter()
+                .iter()
+                .map(|(event, span)| (borrowed_event(event), span.clone()))
                 .enumerate()
                 .peekable();
 
             while let Some((index, (e, src_span))) = events.next() {
-                let start_position = ui.next_widget_position();
-                // Add a viewport-skip waypoint at every block-level end (not
-                // just list-internal ends as the original code did). Without
-                // this, docs whose content is mostly headings + paragraphs
-                // produce empty split_points, the viewport-skip math falls
-                // back to Pos2::ZERO, and rendered content overlaps. This is
-                // the root cause of the "buggy in scenarios more complex
-                // than the example application" warning on show_scrollable.
-                let is_block_end = matches!(
-                    &e,
-                    pulldown_cmark::Event::End(end) if is_block_end_tag(end)
-                );
+                // Blocks swallowed by a container rendered before them (a
+                // `<details>` body) are part of that container's block.
+                while block_starts.next_if(|start| *start < index).is_some() {}
+                if block_starts.next_if_eq(&index).is_some() {
+                    blocks.push(self.scroll_block(ui, index));
+                }
 
                 if events.peek().is_none() {
                     self.line.should_end_newline_forced = false;
                 }
 
-                self.process_event(ui, &mut events, e, src_span, cache, options, max_width);
-
-                // Defense in depth: only add a split point when we're at a
-                // block end AND outside any stateful container (list, table,
-                // blockquote). The viewport-skip path in `show_scrollable`
-                // recreates the renderer with `CommonMarkViewerInternal::new`
-                // each frame, so the transient state of `self.list`,
-                // `self.is_table`, and `self.is_blockquote` is *not* replayed
-                // when iteration jumps in via `skip(first_event_index)`. A
-                // split point inside one of those containers would land
-                // iteration mid-state — for lists this fires
-                // `List::start_item` on an empty stack and panics
-                // (`lib.rs:566 unreachable!()`); for tables / blockquotes it
-                // would visually corrupt rendering. The container-state
-                // check below must run after `process_event` (above) since
-                // that's where the start/end of these containers updates
-                // `self.list` / `self.is_table` / `self.is_blockquote`.
-                let safe_for_split = is_block_end
-                    && !self.list.is_inside_a_list()
-                    && !self.is_table
-                    && !self.is_blockquote;
-
-                if let Some(source_id) = split_points_id {
-                    if safe_for_split {
-                        let scroll_cache = scroll_cache(cache, &source_id);
-                        let end_position = ui.next_widget_position();
-
-                        let split_point_exists = scroll_cache
-                            .split_points
-                            .iter()
-                            .any(|(i, _, _)| *i == index);
-
-                        if !split_point_exists {
-                            scroll_cache
-                                .split_points
-                                .push((index, start_position, end_position));
-                        }
-                    }
+                // Nested blocks are consumed inside their container, so only
+                // top-level starts reach this loop outside a list.
+                if is_block_start(&e) && !self.list.is_inside_a_list() {
+                    let (line, end_line) = line_cursor.lines_of(text, &src_span);
+                    self.pending_source_block = Some((src_span.clone(), line, end_line));
                 }
 
+                self.process_event(ui, &mut events, e, src_span, cache, options, max_width);
+                self.record_pending_source_block(ui, cache);
+
                 if index == 0 {
                     self.line.should_not_start_newline_forced = false;
                 }
             }
 
-            if let Some(source_id) = split_points_id {
-                scroll_cache(cache, &source_id).page_size =
-                    Some(ui.next_widget_position().to_vec2());
+            let blocks_end = ui.cursor().top() - self.content_top;
+            self.footnote_section(ui, cache, options, max_width);
+            self.block_copy_buttons(ui, text);
+            cache.finish_source_blocks(scroll_id.unwrap_or(Id::NULL), ui.min_rect().height());
+            std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+
+            if let Some(scroll_id) = scroll_id {
+                let scroll_cache = scroll_cache(cache, &scroll_id);
+                scroll_cache.remeasured = !scroll_cache.blocks.is_empty();
+                scroll_cache.blocks = blocks;
+                scroll_cache.edited.clear();
+                scroll_cache.blocks_end = blocks_end;
+                scroll_cache.content_height = ui.min_rect().height();
+                scroll_cache.needs_layout = false;
             }
-        });
+        };
+        let re = if margin > 0.0 {
+            // Allocated like `allocate_ui_with_layout` does, moved right
+            let rect = egui::Rect::from_min_size(
+                ui.cursor().min + egui::vec2(margin, 0.0),
+                egui::vec2(max_width, 0.0),
+            );
+            ui.scope_builder(egui::UiBuilder::new().max_rect(rect).layout(layout), add_contents)
+        } else {
+            ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, add_contents)
+        };
 
         (re, std::mem::take(&mut self.checkbox_events))
     }
@@ -704,108 +1164,78 @@ impl CommonMarkViewerInternal {
         pending_scroll_offset: Option<f32>,
         scroll_source: Option<egui::scroll_area::ScrollSource>,
     ) -> egui::scroll_area::ScrollAreaOutput<()> {
-        let available_size = ui.available_size();
+        profile_scope!("show_scrollable");
         let scroll_id = source_id.with("_scroll_area");
         let layout_sig = compute_layout_signature(ui, options);
 
-        // Ensure parsed events are cached on the ScrollableCache, keyed by a
-        // content version. The caller can provide a monotonic version (bumped
-        // on every reload) — when omitted we fall back to hashing the content,
-        // which still beats reparsing but is O(N) per frame for the hash.
-        // The big win either way is avoiding pulldown_cmark::Parser::new_ext +
-        // collect on every frame (~52 ms at 100k lines).
-        let version = content_version.unwrap_or_else(|| Self::hash_content(text));
-        let mut content_changed = false;
+        // The events are kept on the ScrollableCache, keyed by a content
+        // version. The caller can provide a monotonic version (bumped on every
+        // reload) so a static document isn't even hashed every frame; when
+        // omitted the version is the events themselves, which the
+        // `CommonMarkCache` only replaces when the text changes. Either way
+        // parsing (~52 ms at 100k lines) happens once per content.
+        let parser_options = parser_options_for(options);
+        let progressive =
+            options.progressive_loading && text.len() >= PROGRESSIVE_LOADING_MIN_BYTES;
+        // Shown instead of the document while it's parsed or laid out. Has an
+        // id of its own so the document keeps its scroll offset meanwhile.
+        let loading_area = || {
+            egui::ScrollArea::vertical()
+                .id_salt(scroll_id.with("_loading"))
+                .auto_shrink([false, true])
+        };
+        let sc = scroll_cache(cache, &source_id);
+        let content_changed = sc.events.is_empty()
+            || content_version != Some(sc.content_version)
+            || sc.parser_options != parser_options.bits();
+        if content_changed {
+            let events = if progressive {
+                cache.parsed_events_in_background(ui.ctx(), text, parser_options)
+            } else {
+                Some(cache.parsed_events(ui.ctx(), text, parser_options))
+            };
+            let Some(events) = events else {
+                return loading_area().show(ui, |ui| document_loading_placeholder(ui, None));
+            };
+            let sc = scroll_cache(cache, &source_id);
+            // Parsed events are shared, so an unchanged text hands back the
+            // same ones and the measured blocks stay valid. After an edit the
+            // host reported, those outside it can stay too.
+            if !std::sync::Arc::ptr_eq(&sc.events, &events) {
+                if sc.edited.is_empty() || !edit_kept_blocks(&sc.events, &events, &sc.blocks) {
+                    sc.blocks.clear();
+                    sc.edited.clear();
+                }
+                sc.events = events;
+                sc.progressive_layout = None;
+            }
+            sc.content_version = content_version.unwrap_or_default();
+            sc.parser_options = parser_options.bits();
+        }
         {
             let sc = scroll_cache(cache, &source_id);
-            if sc.events.is_empty() || sc.content_version != version {
-                content_changed = true;
-                // Must mirror `show()`'s `math_enabled` derivation
-                // (parsers/pulldown.rs in this file: `options.math_fn.is_some()
-                // || cfg!(feature = "math")`). The bootstrap branch below
-                // calls `self.show()` which parses again with `cfg!(feature =
-                // "math")` included; if our parse here omits it, the two
-                // event streams diverge for any document containing `$…$`
-                // (currency, regex, env vars). split_points are then indexed
-                // off `cache.cached_events` (with-math) but consumed against
-                // `sc.events` (without-math), so the viewport-skip path lands
-                // iteration at an unrelated event — often `Tag::Item` with no
-                // matching `Tag::List` start → `List::start_item` panics
-                // (`lib.rs:566 unreachable!()`). See docs/devlog/027.
-                let math_enabled =
-                    options.math_fn.is_some() || cfg!(feature = "math");
-                sc.events = pulldown_cmark::Parser::new_ext(
-                    text,
-                    parser_options_math(math_enabled),
-                )
-                .into_offset_iter()
-                .map(|(e, r)| (e.into_static(), r))
-                .collect();
-                sc.content_version = version;
-                // Content changed — cached split_points y-coords are no
-                // longer valid for this content. Drop them so the first
-                // post-change frame falls into the bootstrap branch below.
-                sc.page_size = None;
-                sc.split_points.clear();
-            }
-            // Width/zoom/theme change: y-coordinates are invalid for the
-            // new layout, even though parsed events are still good.
+            // Width/zoom/theme change: the measured heights are invalid for
+            // the new layout, even though parsed events are still good.
             if sc.layout_signature != layout_sig {
                 sc.layout_signature = layout_sig;
-                sc.page_size = None;
-                sc.split_points.clear();
-                sc.available_size = available_size;
-            }
-            // When the caller wants to jump to a specific scroll position
-            // (outline click, search-jump), we must paint *every* event
-            // this frame — not just the viewport-clipped subset. Otherwise
-            // a far target's block doesn't paint, the cache.active_search_y
-            // / header_position never gets recorded, and the two-stage
-            // corrective scroll (src/main.rs:scroll_to_active_match) can't
-            // snap to the precise y. Forcing the bootstrap branch costs one
-            // full-paint frame (~100 ms at 100k lines) per jump, which is
-            // acceptable for a one-off action.
-            //
-            // Critically, we DO NOT clear split_points here even though
-            // `page_size = None` forces a bootstrap. Reason: split_points
-            // store screen-y coordinates which are only meaningful at the
-            // scroll position they were captured at. The original scroll=0
-            // bootstrap stored values where screen-y ≈ content-y + panel
-            // chrome (~44 px). Clearing here lets the forced bootstrap at
-            // non-zero scroll re-populate them with screen-y values that
-            // diverge from content-y by the scroll amount, breaking every
-            // subsequent skip-paint's partition_point / allocate_space math
-            // by hundreds of pixels (visible as outline-click landing at
-            // the wrong heading and blank space at viewport top after
-            // scrolling). The push-site dedup-by-event-index keeps the
-            // original (good) values intact even though bootstrap re-runs.
-            if pending_scroll_offset.is_some() {
-                sc.page_size = None;
-            }
-            // Content-height drift check: if the previous frame's content
-            // height has drifted from when split_points were captured by
-            // more than CONTENT_H_DRIFT_THRESHOLD, the y-positions are stale
-            // (typically because async image/font loading shifted the doc
-            // after the initial bootstrap). Invalidate to trigger ONE
-            // re-bootstrap with refreshed positions. Uses absolute-drift
-            // hysteresis instead of bucketing because egui's `show()` vs
-            // `show_viewport()` content_size.y reporting differs by ~44 px
-            // (panel chrome) for the same content — any bucket-boundary
-            // approach would oscillate; only |drift| > threshold breaks
-            // out of that cycle.
-            if sc.bootstrap_content_h > 0.0
-                && (sc.last_content_h - sc.bootstrap_content_h).abs() > CONTENT_H_DRIFT_THRESHOLD
-            {
-                sc.page_size = None;
-                sc.split_points.clear();
+                sc.blocks.clear();
+                sc.progressive_layout = None;
             }
         }
-        // Header positions are content-keyed; new content means the cached
-        // y values point at the wrong headings. Done outside the `sc` borrow
-        // scope above so `cache` is reborrowable.
-        if content_changed {
-            cache.clear_header_positions();
+        // A progressively loaded document is only shown once measured. Later
+        // layouts are measured the same way while the old blocks are shown.
+        let measure_id = ui.id().with("_measure");
+        if progressive && scroll_cache(cache, &source_id).blocks.is_empty() {
+            let progress = CommonMarkViewerInternal::new()
+                .lay_out_progressively(ui, measure_id, cache, options, text, source_id);
+            return loading_area().show(ui, |ui| {
+                document_loading_placeholder(ui, Some(progress.unwrap_or(1.0)));
+            });
         }
+        // A `scroll_to_heading` request whose heading has been laid out turns
+        // into an ordinary pending offset for this frame.
+        let pending_scroll_offset =
+            pending_scroll_offset.or_else(|| cache.take_heading_scroll(source_id));
 
         // Helper: build the renderer-owned ScrollArea with caller config.
         let make_scroll_area = || {
@@ -821,216 +1251,798 @@ impl CommonMarkViewerInternal {
             sa
         };
 
-        // FORCE BOOTSTRAP EVERY FRAME: disable viewport-virtualization until
-        // the skip-paint slicing bugs are fully resolved (see
-        // docs/devlog/030-skip-paint-investigation.md for the design plan).
-        // The slice path renders events without their preceding container
-        // context (Start tags before the slice are missing), producing
-        // layout differences vs bootstrap — visible as flicker, wrong
-        // spacing, and shifted indents during scroll. Bootstrap renders
-        // the full document each frame; measured on T470 (i5-7200U, 2c):
-        // 1.2 ms / 348 events, 5.7 ms / 2514 events, 39 ms / 20k events,
-        // 229 ms / 100k events. Acceptable up to ~10k events; degraded
-        // above. The skip-paint code below is kept as `unreachable!`
-        // so future restoration can drop the early return.
-        {
-            let out = make_scroll_area().show(ui, |ui| {
+        // A full layout paints every block and measures them for the frames
+        // after it, which only paint the blocks near the viewport. It runs
+        // for new content or layout. After a painted block changed height,
+        // and for jumps (outline click, search), which need the positions it
+        // records for headings and matches anywhere in the document, the
+        // document is laid out again: in full while only the first layout
+        // measured it, around the viewport after that.
+        // Progressively loaded documents have measured them all already.
+        let sc = scroll_cache(cache, &source_id);
+        let relayout = sc.needs_layout || pending_scroll_offset.is_some();
+        if !progressive && (sc.blocks.is_empty() || relayout && !sc.remeasured) {
+            return make_scroll_area().show(ui, |ui| {
                 cache.set_scroll_offset(pending_scroll_offset.unwrap_or(0.0));
                 self.show(ui, cache, options, text, Some(source_id));
             });
-            let sc = scroll_cache(cache, &source_id);
-            sc.available_size = available_size;
-            sc.last_content_h = out.content_size.y;
-            sc.bootstrap_content_h = out.content_size.y;
-            return out;
-        }
-        // Kept for future restoration once skip-paint is bug-free.
-        #[allow(unreachable_code)]
-        let page_size_opt = scroll_cache(cache, &source_id).page_size;
-        #[allow(unreachable_code)]
-        let Some(page_size) = page_size_opt else {
-            unreachable!()
-        };
+        }
+        if !progressive && relayout {
+            return make_scroll_area().show_viewport(ui, |ui, viewport| {
+                cache.set_scroll_offset(viewport.min.y);
+                self.lay_out_near_viewport(ui, viewport, cache, options, text, source_id);
+                if options.image_prefetch {
+                    prefetch_images(ui.ctx(), viewport, cache, options, source_id);
+                }
+            });
+        }
 
-        let num_rows = scroll_cache(cache, &source_id).events.len();
+        let output = make_scroll_area().show_viewport(ui, |ui, viewport| {
+            cache.set_scroll_offset(viewport.min.y);
+            self.show_visible_blocks(ui, viewport, cache, options, source_id);
+            if options.image_prefetch {
+                prefetch_images(ui.ctx(), viewport, cache, options, source_id);
+            }
+        });
+        let sc = scroll_cache(cache, &source_id);
+        if progressive && (sc.needs_layout || sc.progressive_layout.is_some()) {
+            CommonMarkViewerInternal::new()
+                .lay_out_progressively(ui, measure_id, cache, options, text, source_id);
+        }
+        output
+    }
 
-        let out = make_scroll_area()
-            .show_viewport(ui, |ui, viewport| {
-                ui.set_height(page_size.y);
-                // ui.cursor().top() inside show_viewport is viewport-relative;
-                // record_header_position and record_active_search_y_viewport
-                // add this offset to recover content-relative y.
-                cache.set_scroll_offset(viewport.min.y);
-                let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+    /// Paint the blocks of a measured document that intersect `viewport`,
+    /// keeping the room the others took up in the last full layout. Flags a
+    /// full layout for the next frame if a painted block changed height.
+    fn show_visible_blocks(
+        &mut self,
+        ui: &mut Ui,
+        viewport: egui::Rect,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        source_id: Id,
+    ) {
+        profile_scope!("visible blocks");
+        let sc = scroll_cache(cache, &source_id);
+        let visible = sc.visible_blocks(viewport);
+        let end_event = sc.end_event(&visible);
+        let end_top = sc.end_top(&visible);
+        let to_the_end = visible.end == sc.blocks.len();
+        ui.set_min_height(sc.content_height);
+        // Taken out for the pass so rendering can borrow the cache mutably.
+        let events = sc.events.clone();
+        let blocks = std::mem::take(&mut sc.blocks);
+
+        self.content_top = ui.min_rect().top();
+        self.source_id = Some(source_id);
+        self.record_headings = false;
+        let mut needs_layout = false;
+        let first = &blocks[visible.start];
+        self.curr_table = first.tables;
+        self.curr_details = first.details;
+        self.curr_code_block = first.code_blocks;
+        self.footnote_refs = first.footnote_refs.clone();
+        if first.first_event > 0 {
+            self.line.should_not_start_newline_forced = false;
+        }
+        if options.task_list_progress {
+            self.task_progress = task_list_progress(&events);
+        }
+        if options.math_macros || options.equation_numbers {
+            self.math = MathDocument::new(&events, options.equation_numbers);
+        }
+        if to_the_end {
+            self.collect_skipped_footnote_definitions(&events, &blocks[..visible.start]);
+        }
 
-                let max_width = options.max_width(ui);
-                ui.allocate_ui_with_layout(egui::vec2(max_width, 0.0), layout, |ui| {
-                    ui.spacing_mut().item_spacing.x = 0.0;
-                    let scroll_cache = scroll_cache(cache, &source_id);
-
-                    // split_points are populated in event order, which matches
-                    // top-to-bottom layout order, so y-coords are monotonic
-                    // non-decreasing. Binary-search instead of linear filter:
-                    // O(log N) vs the old O(N) at 15k+ split points (100k-line doc).
-
-                    // First waypoint: the second-to-last split point whose
-                    // end.y is still above the viewport. Picking "second-to-last"
-                    // gives us a safety frame above the viewport top to avoid
-                    // clipping inline-flow content that started just above.
-                    let above = scroll_cache
-                        .split_points
-                        .partition_point(|(_, _, end)| end.y < viewport.min.y);
-                    let (first_event_index, _, first_end_position) = if above >= 2 {
-                        scroll_cache.split_points[above - 2]
-                    } else {
-                        (0, Pos2::ZERO, Pos2::ZERO)
-                    };
-
-                    // Last waypoint: the second split point whose start.y is
-                    // strictly below the viewport bottom. Same safety idea on
-                    // the bottom edge.
-                    let below = scroll_cache
-                        .split_points
-                        .partition_point(|(_, start, _)| start.y <= viewport.max.y);
-                    let last_event_index = scroll_cache
-                        .split_points
-                        .get(below + 1)
-                        .map(|(index, _, _)| *index)
-                        .unwrap_or(num_rows);
-
-                    // Clone only the events we'll actually iterate this frame
-                    // — the visible viewport plus safety margins above/below.
-                    // The previous implementation cloned the full Vec (~1.5 ms
-                    // at 30k events on Recent-Changes.md), then `skip`ed all
-                    // but ~150 events. This trims the clone to the actual
-                    // range used, dropping per-frame allocation churn from
-                    // ~1.5 ms to ~10 µs on the same doc. The slice clone is
-                    // released before `process_event` mutably re-borrows the
-                    // cache for syntect/header state — NLL covers this.
-                    let range_end = last_event_index.min(scroll_cache.events.len());
-                    let events_range: Vec<(pulldown_cmark::Event<'static>, Range<usize>)> =
-                        if first_event_index < range_end {
-                            scroll_cache.events[first_event_index..range_end].to_vec()
-                        } else {
-                            Vec::new()
-                        };
+        let max_width = options.max_width(ui);
+        let left = ui.min_rect().left() + options.content_margin(ui);
+        let rect = egui::Rect::from_min_size(
+            egui::pos2(left, self.content_top + first.top),
+            egui::vec2(max_width, first.filled_height),
+        );
+        let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+        ui.scope_builder(egui::UiBuilder::new().max_rect(rect).layout(layout), |ui| {
+            ui.spacing_mut().item_spacing.x = 0.0;
+            ui.set_row_height(first.row_height);
+            std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+            self.text_layouts.begin(ui, options);
 
-                    let last_sp_y_used = scroll_cache
-                        .split_points
-                        .get(below + 1)
-                        .map(|p| p.2.y)
-                        .unwrap_or(0.0);
-                    eprintln!(
-                        "[SKIP] vp=[{:.0},{:.0}] evt=[{},{}]/{} sp_y=[{:.0},{:.0}] a={} b={}",
-                        viewport.min.y, viewport.max.y,
-                        first_event_index, last_event_index, num_rows,
-                        first_end_position.y, last_sp_y_used,
-                        above, below
-                    );
-                    // Advance cursor VERTICALLY by first_end_position.y to
-                    // position events at the right viewport y. `to_vec2()`
-                    // would also pass first_end_position.x as allocation
-                    // width — that's the X-cursor where the previous block
-                    // ended (often a non-zero left margin or a list-indent
-                    // depth). In `left_to_right(BOTTOM).with_main_wrap`,
-                    // allocate_space consumes that as width-advance,
-                    // shifting subsequent events right and breaking
-                    // indentation of code blocks, tables, and text.
-                    ui.allocate_space(egui::vec2(0.0, first_end_position.y));
-
-                    // Re-attach original indices via map so peekable iteration
-                    // and downstream consumers still see the absolute event
-                    // index (used by `if i == 0 { ... }` below for the
-                    // bootstrap-newline gate).
-                    let mut events = events_range
-                        .into_iter()
-                        .enumerate()
-                        .map(|(offset, ev)| (offset + first_event_index, ev))
-                        .peekable();
-
-                    while let Some((i, (e, src_span))) = events.next() {
-                        if events.peek().is_none() {
-                            self.line.should_end_newline_forced = false;
-                        }
+            let mut next_block = visible.start;
+            let mut events = events[first.first_event..]
+                .iter()
+                .map(|(event, span)| (borrowed_event(event), span.clone()))
+                .enumerate()
+                .map(|(offset, event)| (offset + first.first_event, event))
+                .peekable();
+            while let Some((index, (e, src_span))) = events.next() {
+                if index >= end_event {
+                    break;
+                }
+                while blocks.get(next_block).is_some_and(|block| block.first_event < index) {
+                    next_block += 1;
+                }
+                if let Some(block) = blocks.get(next_block).filter(|b| b.first_event == index) {
+                    let top = ui.cursor().top() - self.content_top;
+                    needs_layout |= (top - block.top).abs() > 1.0;
+                    next_block += 1;
+                }
+
+                if events.peek().is_none() {
+                    self.line.should_end_newline_forced = false;
+                }
+                self.process_event(ui, &mut events, e, src_span, cache, options, max_width);
+                if index == 0 {
+                    self.line.should_not_start_newline_forced = false;
+                }
+            }
+            let top = ui.cursor().top() - self.content_top;
+            needs_layout |= (top - end_top).abs() > 1.0;
 
-                        self.process_event(ui, &mut events, e, src_span, cache, options, max_width);
+            if to_the_end {
+                self.footnote_section(ui, cache, options, max_width);
+            }
+            std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+        });
 
-                        if i == 0 {
-                            self.line.should_not_start_newline_forced = false;
-                        }
-                    }
-                });
-            });
-        // NOTE: deliberately NOT updating last_content_h from skip-paint's
-        // `out.content_size.y`. That value is unreliable: skip-paint does
-        // `set_height(page_size.y)` (min height) then `allocate_space(Vec2(0,
-        // first_end_position.y))` which can advance the cursor by tens of
-        // thousands of px when scrolled deep — content_size.y inflates to
-        // 2× the real document height. Feeding that into the drift check
-        // triggers an invalidation, re-bootstrap fires at the current
-        // (non-zero) scroll, split_points get repopulated with screen-y
-        // coords that are catastrophically off, the next skip-paint picks
-        // wrong events, content_h spikes the other way, drift fires again
-        // — death spiral. Empirically: 619 bootstraps in 30 s of scroll on
-        // T470, panel flickered blank with garbled styling. Restricting
-        // drift signal to bootstrap-only content_h prevents the false
-        // positive. Async-image-load growth is still caught — that fires
-        // during the SECOND bootstrap (which is allowed to happen for
-        // other reasons, e.g. font/scrollbar layout settling at startup),
-        // where the new content_h IS written to last_content_h.
-
-        // Scroll-overshoot clamp.
-        let real_max_scroll = (page_size.y - out.inner_rect.height()).max(0.0);
-        let clamped = out.state.offset.y > real_max_scroll;
-        if clamped {
-            let mut state = out.state;
-            state.offset.y = real_max_scroll;
-            state.store(ui.ctx(), out.id);
+        let sc = scroll_cache(cache, &source_id);
+        sc.blocks = blocks;
+        sc.needs_layout |= needs_layout;
+        if needs_layout {
             ui.ctx().request_repaint();
         }
-        let _ = clamped;
-        out
-        // No trailing invalidation needed — layout_signature is checked at
-        // the top of show_scrollable, so a width/zoom/theme change in the
-        // same frame falls into the bootstrap branch above immediately
-        // instead of one frame later.
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn process_event<'e>(
+    /// Lay a measured document out again, painting only the blocks near
+    /// `viewport`. The others keep their measured height and move by as much
+    /// as the painted blocks above them grew or shrank, which skips their
+    /// highlighting, tables and images. Blocks whose positions are recorded
+    /// for jumps (headings, footnote definitions, the active search match)
+    /// are painted wherever they are, and so are edited blocks and the last
+    /// one, which the footnotes follow.
+    fn lay_out_near_viewport(
         &mut self,
         ui: &mut Ui,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
-        event: pulldown_cmark::Event,
-        src_span: Range<usize>,
+        viewport: egui::Rect,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
-        max_width: f32,
+        text: &str,
+        source_id: Id,
     ) {
-        self.event(ui, event, src_span, cache, options, max_width);
+        profile_scope!("layout near viewport");
+        let sc = scroll_cache(cache, &source_id);
+        let events = sc.events.clone();
+        let mut blocks_end = sc.blocks_end;
+        let old_blocks = std::mem::take(&mut sc.blocks);
+        let mut repaint = std::mem::take(&mut sc.edited);
+        repaint.extend(cache.active_search_range().cloned());
+        let paint = blocks_to_paint(&events, &old_blocks, viewport, &repaint);
+        if paint.iter().all(|&paint| paint) {
+            self.show(ui, cache, options, text, Some(source_id));
+            return;
+        }
 
-        self.def_list_def_wrapping(events, max_width, cache, options, ui);
-        self.item_list_wrapping(events, max_width, cache, options, ui);
-        self.table(events, cache, options, ui, max_width);
-        self.blockquote(events, max_width, cache, options, ui);
+        let max_width = options.max_width(ui);
+        let left = ui.min_rect().left() + options.content_margin(ui);
+        let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+        std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+        self.text_layouts.begin(ui, options);
+        cache.clear_source_blocks(source_id);
+        self.content_top = ui.min_rect().top();
+        self.source_id = Some(source_id);
+        if options.task_list_progress {
+            self.task_progress = task_list_progress(&events);
+        }
+        if options.math_macros || options.equation_numbers {
+            self.math = MathDocument::new(&events, options.equation_numbers);
+        }
+        let mut line_cursor = LineCursor::new();
+        let mut blocks = Vec::with_capacity(old_blocks.len());
+        // How far the blocks after the ones painted so far moved.
+        let mut shift = 0.0;
+        let mut start = 0;
+        while start < old_blocks.len() {
+            let end = (start..old_blocks.len())
+                .find(|&i| paint[i] != paint[start])
+                .unwrap_or(old_blocks.len());
+            if !paint[start] {
+                for block in &old_blocks[start..end] {
+                    let top = block.top + shift;
+                    let (event, span) = &events[block.first_event];
+                    if is_block_start(event) {
+                        let (line, end_line) = line_cursor.lines_of(text, span);
+                        cache.record_source_block(source_id, SourceBlock {
+                            byte_range: span.clone(),
+                            line,
+                            end_line,
+                            y_range: egui::Rangef::point(top),
+                        });
+                    }
+                    blocks.push(ScrollBlock {
+                        top,
+                        ..block.clone()
+                    });
+                }
+                // The last block is always painted, so there is a next one.
+                let next = &old_blocks[end];
+                self.curr_table = next.tables;
+                self.curr_details = next.details;
+                self.curr_code_block = next.code_blocks;
+                self.footnote_refs = next.footnote_refs.clone();
+                start = end;
+                continue;
+            }
+
+            let first = &old_blocks[start];
+            let end_event = old_blocks
+                .get(end)
+                .map_or(events.len(), |block| block.first_event);
+            if first.first_event > 0 {
+                self.line.should_not_start_newline_forced = false;
+            }
+            let rect = egui::Rect::from_min_size(
+                egui::pos2(left, self.content_top + first.top + shift),
+                egui::vec2(max_width, first.filled_height),
+            );
+            ui.scope_builder(egui::UiBuilder::new().max_rect(rect).layout(layout), |ui| {
+                ui.spacing_mut().item_spacing.x = 0.0;
+                ui.set_row_height(first.row_height);
+                let mut next_block = start;
+                let mut events = events[first.first_event..end_event]
+                    .iter()
+                    .map(|(event, span)| (borrowed_event(event), span.clone()))
+                    .enumerate()
+                    .map(|(offset, event)| (offset + first.first_event, event))
+                    .peekable();
+                while let Some((index, (e, src_span))) = events.next() {
+                    while old_blocks.get(next_block).is_some_and(|b| b.first_event < index) {
+                        next_block += 1;
+                    }
+                    if old_blocks.get(next_block).is_some_and(|b| b.first_event == index) {
+                        blocks.push(self.scroll_block(ui, index));
+                        next_block += 1;
+                    }
+                    if events.peek().is_none() && end == old_blocks.len() {
+                        self.line.should_end_newline_forced = false;
+                    }
+                    if is_block_start(&e) && !self.list.is_inside_a_list() {
+                        let (line, end_line) = line_cursor.lines_of(text, &src_span);
+                        self.pending_source_block = Some((src_span.clone(), line, end_line));
+                    }
+                    self.process_event(ui, &mut events, e, src_span, cache, options, max_width);
+                    self.record_pending_source_block(ui, cache);
+                    if index == 0 {
+                        self.line.should_not_start_newline_forced = false;
+                    }
+                }
+                let bottom = ui.cursor().top() - self.content_top;
+                match old_blocks.get(end) {
+                    Some(next) => shift = bottom - next.top,
+                    None => {
+                        blocks_end = bottom;
+                        self.footnote_section(ui, cache, options, max_width);
+                    }
+                }
+            });
+            start = end;
+        }
+        self.block_copy_buttons(ui, text);
+        cache.finish_source_blocks(source_id, ui.min_rect().height());
+        std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+
+        let sc = scroll_cache(cache, &source_id);
+        sc.blocks = blocks;
+        sc.blocks_end = blocks_end;
+        sc.content_height = ui.min_rect().height();
+        sc.needs_layout = false;
     }
 
-    fn def_list_def_wrapping<'e>(
+    /// Lay out the next part of a document too long to lay out in one frame,
+    /// measuring its blocks in an invisible ui of its own. Hands the blocks to
+    /// the document once they're all measured; until then returns how much of
+    /// it is done.
+    fn lay_out_progressively(
         &mut self,
-        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
-        max_width: f32,
+        ui: &Ui,
+        measure_id: Id,
         cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
-        ui: &mut Ui,
-    ) {
-        if self.def_list.is_def_list_def {
-            self.def_list.is_def_list_def = false;
-
-            let item_events = delayed_events(events, |tag| {
-                matches!(tag, pulldown_cmark::TagEnd::DefinitionListDefinition)
-            });
-
-            let mut events_iter = item_events.into_iter().enumerate().peekable();
+        text: &str,
+        source_id: Id,
+    ) -> Option<f32> {
+        profile_scope!("progressive layout");
+        let sc = scroll_cache(cache, &source_id);
+        let events = sc.events.clone();
+        let mut progress = sc.progressive_layout.take().unwrap_or_default();
+        // The block this part starts at is measured again below.
+        let first = progress.blocks.pop().unwrap_or_default();
+
+        let max_width = options.max_width(ui);
+        let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+        // Widgets keep their state between layouts, as the id doesn't change,
+        // and sit on a layer of their own so they can't take the pointer.
+        let mut ui = Ui::new(
+            ui.ctx().clone(),
+            measure_id,
+            egui::UiBuilder::new()
+                .layer_id(egui::LayerId::new(egui::Order::Background, measure_id))
+                .max_rect(egui::Rect::from_min_size(
+                    egui::pos2(0.0, first.top),
+                    egui::vec2(max_width, first.filled_height),
+                ))
+                .layout(layout)
+                .invisible(),
+        );
+        let ui = &mut ui;
+        ui.spacing_mut().item_spacing.x = 0.0;
+        if first.first_event == 0 {
+            ui.set_row_height(ui.text_style_height(&TextStyle::Body));
+        } else {
+            ui.set_row_height(first.row_height);
+        }
+        std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+        self.text_layouts.begin(ui, options);
+
+        self.content_top = 0.0;
+        self.source_id = Some(source_id);
+        self.curr_table = first.tables;
+        self.curr_details = first.details;
+        self.curr_code_block = first.code_blocks;
+        self.footnote_refs = first.footnote_refs.clone();
+        self.heading_occurrence_counts = std::mem::take(&mut progress.heading_counts);
+        self.anchor_slugger = std::mem::take(&mut progress.anchor_slugger);
+        if first.first_event == 0 {
+            cache.clear_source_blocks(source_id);
+            cache.invalidate_header_positions(source_id, text);
+        } else {
+            self.line.should_not_start_newline_forced = false;
+        }
+        if options.task_list_progress {
+            self.task_progress = task_list_progress(&events);
+        }
+        if options.math_macros || options.equation_numbers {
+            self.math = MathDocument::new(&events, options.equation_numbers);
+        }
+
+        let deadline = std::time::Instant::now() + PROGRESSIVE_LAYOUT_BUDGET;
+        let mut block_starts = top_level_starts(&events[first.first_event..])
+            .into_iter()
+            .map(|start| start + first.first_event)
+            .peekable();
+        let mut line_cursor = LineCursor::new();
+        let mut finished = true;
+        let mut events_iter = events[first.first_event..]
+            .iter()
+            .map(|(event, span)| (borrowed_event(event), span.clone()))
+            .enumerate()
+            .map(|(offset, event)| (offset + first.first_event, event))
+            .peekable();
+        while let Some((index, (e, src_span))) = events_iter.next() {
+            while block_starts.next_if(|start| *start < index).is_some() {}
+            if block_starts.next_if_eq(&index).is_some() {
+                progress.blocks.push(self.scroll_block(ui, index));
+                // A caption belongs to the image above it, so the two are
+                // laid out in the same frame.
+                if index > first.first_event
+                    && self.figure_rect.is_none()
+                    && std::time::Instant::now() >= deadline
+                {
+                    finished = false;
+                    break;
+                }
+            }
+
+            if events_iter.peek().is_none() {
+                self.line.should_end_newline_forced = false;
+            }
+            if is_block_start(&e) && !self.list.is_inside_a_list() {
+                let line = line_cursor.line_at(text, src_span.start);
+                let end_line = line
+                    + text
+                        .get(src_span.clone())
+                        .map_or(0, |s| s.trim_end_matches('\n').matches('\n').count());
+                self.pending_source_block = Some((src_span.clone(), line, end_line));
+            }
+            self.process_event(ui, &mut events_iter, e, src_span, cache, options, max_width);
+            self.record_pending_source_block(ui, cache);
+            if index == 0 {
+                self.line.should_not_start_newline_forced = false;
+            }
+        }
+
+        let blocks_end = ui.cursor().top();
+        if finished {
+            let start = progress
+                .blocks
+                .partition_point(|block| block.first_event < first.first_event);
+            self.collect_skipped_footnote_definitions(&events, &progress.blocks[..start]);
+            self.footnote_section(ui, cache, options, max_width);
+            cache.finish_source_blocks(source_id, ui.min_rect().bottom());
+        }
+        std::mem::swap(&mut self.text_layouts, cache.text_layouts_mut());
+
+        let content_height = ui.min_rect().bottom();
+        let done = progress
+            .blocks
+            .last()
+            .map_or(0.0, |block| block.first_event as f32 / events.len().max(1) as f32);
+        let sc = scroll_cache(cache, &source_id);
+        ui.ctx().request_repaint();
+        if finished {
+            sc.blocks = progress.blocks;
+            sc.edited.clear();
+            sc.blocks_end = blocks_end;
+            sc.content_height = content_height;
+            sc.needs_layout = false;
+            None
+        } else {
+            progress.heading_counts = std::mem::take(&mut self.heading_occurrence_counts);
+            progress.anchor_slugger = std::mem::take(&mut self.anchor_slugger);
+            sc.progressive_layout = Some(progress);
+            Some(done)
+        }
+    }
+
+    /// Collect the footnote definitions starting `blocks`, which aren't
+    /// rendered this pass, for the footnote section that lists them all.
+    fn collect_skipped_footnote_definitions(
+        &mut self,
+        events: &[(pulldown_cmark::Event<'static>, Range<usize>)],
+        blocks: &[ScrollBlock],
+    ) {
+        for block in blocks {
+            let (event, _) = &events[block.first_event];
+            if let pulldown_cmark::Event::Start(pulldown_cmark::Tag::FootnoteDefinition(label)) =
+                event
+            {
+                self.footnote_definition = Some(label.to_string());
+                let body = events[block.first_event + 1..]
+                    .iter()
+                    .map(|(event, span)| (borrowed_event(event), span.clone()))
+                    .enumerate();
+                self.collect_footnote_definition(&mut body.peekable());
+            }
+        }
+    }
+
+    /// Where the block starting at event `index` begins and the renderer
+    /// state painting has to resume with there.
+    fn scroll_block(&self, ui: &Ui, index: usize) -> ScrollBlock {
+        ScrollBlock {
+            first_event: index,
+            top: ui.cursor().top() - self.content_top,
+            row_height: ui.cursor().height(),
+            filled_height: (ui.min_rect().bottom() - ui.cursor().top()).max(0.0),
+            tables: self.curr_table,
+            details: self.curr_details,
+            code_blocks: self.curr_code_block,
+            footnote_refs: self.footnote_refs.clone(),
+        }
+    }
+
+    /// Render `event` like [`Self::render_event`], catching a panic of the
+    /// renderers so one broken part doesn't take the rest of the document
+    /// down. The part is shown as an error placeholder instead and recorded
+    /// in the cache, which skips it from then on.
+    #[allow(clippy::too_many_arguments)]
+    fn process_event<'e>(
+        &mut self,
+        ui: &mut Ui,
+        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
+        event: pulldown_cmark::Event,
+        src_span: Range<usize>,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        let source_id = self.source_id.unwrap_or(Id::NULL);
+        if let Some(error) = cache.render_error(source_id, &src_span) {
+            // Don't panic again every frame
+            let error = error.clone();
+            while events.next_if(|(_, (_, span))| span.start < src_span.end).is_some() {}
+            render_error_placeholder(ui, &error.message);
+            self.render_errors.push(error);
+            return;
+        }
+        let text_style = self.text_style.clone();
+        let list_depth = self.list.depth();
+        let rendered = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.render_event(ui, events, event, src_span.clone(), cache, options, max_width);
+        }));
+        if let Err(panic) = rendered {
+            // Drop the rest of the part and the state it left behind
+            while events.next_if(|(_, (_, span))| span.start < src_span.end).is_some() {}
+            self.text_style = text_style;
+            self.list.truncate(list_depth);
+            self.link = None;
+            self.image = None;
+            self.code_block = None;
+            self.is_table = false;
+            self.is_blockquote = false;
+            let error = RenderError::from_panic(src_span, panic.as_ref());
+            render_error_placeholder(ui, &error.message);
+            cache.record_render_error(source_id, error.clone());
+            self.render_errors.push(error);
+        }
+    }
+
+    /// Render `event` and, for the start of a block, the events of the block
+    /// that are rendered together with it.
+    #[allow(clippy::too_many_arguments)]
+    fn render_event<'e>(
+        &mut self,
+        ui: &mut Ui,
+        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
+        event: pulldown_cmark::Event,
+        src_span: Range<usize>,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        if let pulldown_cmark::Event::Start(pulldown_cmark::Tag::MetadataBlock(_)) = event {
+            // Front matter is only read for its math macros
+            for (_, (event, _)) in events.by_ref() {
+                if let pulldown_cmark::Event::End(pulldown_cmark::TagEnd::MetadataBlock(_)) = event {
+                    break;
+                }
+            }
+            return;
+        }
+        let starts_paragraph = matches!(
+            event,
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Paragraph)
+        );
+        // Only the paragraph right after a figure can be its caption.
+        let figure_rect = self.figure_rect.take();
+        self.event(ui, event, src_span, cache, options, max_width);
+        if starts_paragraph {
+            self.paragraph(events, cache, options, ui, max_width, figure_rect);
+        }
+
+        self.def_list_def_wrapping(events, max_width, cache, options, ui);
+        self.item_list_wrapping(events, max_width, cache, options, ui);
+        self.table(events, cache, options, ui, max_width);
+        self.blockquote(events, max_width, cache, options, ui);
+        self.details(events, cache, options, ui, max_width);
+        self.collect_footnote_definition(events);
+    }
+
+    /// Render the paragraph that was just started as the caption of the
+    /// figure drawn at `figure_rect`, or offer it to the render hooks, falling
+    /// back to the default rendering when they decline.
+    fn paragraph<'e>(
+        &mut self,
+        events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        ui: &mut Ui,
+        max_width: f32,
+        figure_rect: Option<egui::Rect>,
+    ) {
+        let captions = options.image_captions == ImageCaptions::TitleOrEmphasis;
+        let directed_text = options.text_direction != TextDirection::LeftToRight;
+        if options.render_hooks.is_none() && !captions && !directed_text {
+            return;
+        }
+        let mut body = delayed_events(events, |tag| {
+            matches!(tag, pulldown_cmark::TagEnd::Paragraph)
+        });
+        let caption = figure_rect.filter(|_| captions && is_emphasis_only(&body));
+        let rendered = if let Some(figure_rect) = caption {
+            let layout = egui::Layout::top_down(egui::Align::Center);
+            ui.allocate_ui_with_layout(egui::vec2(figure_rect.width(), 0.0), layout, |ui| {
+                image_caption(ui, egui::RichText::new(plain_text(&body)).italics());
+            });
+            true
+        } else {
+            options
+                .render_hooks
+                .is_some_and(|hooks| hooks.paragraph(ui, &plain_text(&body)))
+        };
+        if rendered {
+            // Only close the paragraph, for its spacing.
+            body.retain(|(e, _)| {
+                matches!(e, pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Paragraph))
+            });
+        }
+        let is_figure = captions && is_image_only(&body);
+        self.image_rect = None;
+        let end = body.pop();
+        directed(ui, options.is_rtl(texts(&body)), |ui| {
+            for (e, src_span) in body {
+                self.event(ui, e, src_span, cache, options, max_width);
+            }
+        });
+        if let Some((e, src_span)) = end {
+            self.event(ui, e, src_span, cache, options, max_width);
+        }
+        if is_figure {
+            self.figure_rect = self.image_rect;
+        }
+    }
+
+    /// Number of footnote `label` and which reference to it this is (0-based),
+    /// counting the reference.
+    fn footnote_reference(&mut self, label: &str) -> (usize, usize) {
+        if let Some(index) = self.footnote_refs.iter().position(|(l, _)| l == label) {
+            let count = &mut self.footnote_refs[index].1;
+            *count += 1;
+            (index + 1, *count - 1)
+        } else {
+            self.footnote_refs.push((label.to_owned(), 1));
+            (self.footnote_refs.len(), 0)
+        }
+    }
+
+    /// Set aside the body of a footnote definition for [`Self::footnote_section`].
+    fn collect_footnote_definition<'e>(
+        &mut self,
+        events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
+    ) {
+        let Some(label) = self.footnote_definition.take() else {
+            return;
+        };
+        let mut body: Vec<_> = delayed_events(events, |tag| {
+            matches!(tag, pulldown_cmark::TagEnd::FootnoteDefinition)
+        })
+        .into_iter()
+        .map(|(e, range)| (e.into_static(), range))
+        .collect();
+        body.pop();
+        self.footnote_definitions.push((label, body));
+    }
+
+    /// Render the collected footnote definitions below a rule, numbered in
+    /// order of first reference, each followed by back-links to its references.
+    fn footnote_section(
+        &mut self,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        if self.footnote_definitions.is_empty() {
+            return;
+        }
+        let mut definitions = std::mem::take(&mut self.footnote_definitions);
+        // Unreferenced definitions keep their source order after the rest.
+        definitions.sort_by_key(|(label, _)| {
+            self.footnote_refs
+                .iter()
+                .position(|(l, _)| l == label)
+                .unwrap_or(usize::MAX)
+        });
+
+        self.line.should_end_newline_forced = true;
+        self.line.try_insert_start(ui);
+        rule(ui, true);
+        for (label, mut body) in definitions {
+            let (number, references) = match self.footnote_refs.iter().position(|(l, _)| *l == label)
+            {
+                Some(index) => (index + 1, self.footnote_refs[index].1),
+                None => {
+                    self.footnote_refs.push((label.clone(), 0));
+                    (self.footnote_refs.len(), 0)
+                }
+            };
+
+            self.line.try_insert_start(ui);
+            let y = ui.cursor().top() - self.content_top;
+            let source_id = self.source_id.unwrap_or(Id::NULL);
+            cache.record_anchor_position(source_id, footnote_anchor(&label), y);
+            self.line.should_start_newline = false;
+            self.line.should_end_newline = false;
+            footnote(ui, &number.to_string());
+
+            // The back-links go at the end of the last paragraph, not below it.
+            let closing_paragraph = matches!(
+                body.last(),
+                Some((pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Paragraph), _))
+            )
+            .then(|| body.pop())
+            .flatten();
+            let mut events_iter = body.into_iter().enumerate().peekable();
+            while let Some((_, (e, src_span))) = events_iter.next() {
+                self.process_event(ui, &mut events_iter, e, src_span, cache, options, max_width);
+            }
+            for nth in 0..references {
+                soft_break(ui);
+                let response = footnote_backlink(ui, nth);
+                if response.hovered() {
+                    ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                }
+                if response.clicked() {
+                    if let Some(source_id) = self.source_id {
+                        cache.scroll_to_heading(source_id, &footnote_ref_anchor(&label, nth));
+                    }
+                }
+            }
+            if let Some((e, src_span)) = closing_paragraph {
+                self.event(ui, e, src_span, cache, options, max_width);
+            }
+
+            self.line.should_start_newline = true;
+            self.line.should_end_newline = true;
+            self.line.try_insert_end(ui);
+        }
+    }
+
+    /// Render a `<details>` HTML block as a collapsing header titled with its
+    /// `<summary>`; the markdown up to `</details>` is the collapsible body.
+    fn details<'e>(
+        &mut self,
+        events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        ui: &mut Ui,
+        max_width: f32,
+    ) {
+        let Some(details) = self.details.take() else {
+            return;
+        };
+        let body_events = if details.inline_body.is_some() {
+            Vec::new()
+        } else {
+            delayed_events_details(events)
+        };
+
+        let id = ui.id().with("_details").with(self.curr_details);
+        self.curr_details += 1;
+        egui::CollapsingHeader::new(details.summary)
+            .id_salt(id)
+            .default_open(details.open)
+            .show(ui, |ui| {
+                let layout = egui::Layout::left_to_right(egui::Align::BOTTOM).with_main_wrap(true);
+                ui.with_layout(layout, |ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    if let Some(body) = details.inline_body {
+                        self.event_text(body.into(), ui, options);
+                    }
+
+                    // Lay the body out like a document of its own: no blank line
+                    // before its first block or after its last.
+                    self.line.should_not_start_newline_forced = true;
+                    let mut events_iter = body_events.into_iter().enumerate().peekable();
+                    while let Some((index, (e, src_span))) = events_iter.next() {
+                        if events_iter.peek().is_none() {
+                            self.line.should_end_newline_forced = false;
+                        }
+                        self.process_event(
+                            ui,
+                            &mut events_iter,
+                            e,
+                            src_span,
+                            cache,
+                            options,
+                            max_width,
+                        );
+                        if index == 0 {
+                            self.line.should_not_start_newline_forced = false;
+                        }
+                    }
+                    self.line.should_not_start_newline_forced = false;
+                    self.line.should_end_newline_forced = true;
+                });
+            });
+    }
+
+    fn def_list_def_wrapping<'e>(
+        &mut self,
+        events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
+        max_width: f32,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        ui: &mut Ui,
+    ) {
+        if self.def_list.is_def_list_def {
+            self.def_list.is_def_list_def = false;
+
+            let item_events = delayed_events(events, |tag| {
+                matches!(tag, pulldown_cmark::TagEnd::DefinitionListDefinition)
+            });
+
+            let mut events_iter = item_events.into_iter().enumerate().peekable();
 
             self.line.try_insert_start(ui);
 
@@ -1088,22 +2100,32 @@ impl CommonMarkViewerInternal {
             self.is_list_item = false;
 
             let item_events = delayed_events_list_item(events);
+            let pending = std::mem::take(&mut self.pending_item);
+            let rtl = pending && options.is_rtl(texts(&item_events));
+            if pending {
+                self.list.start_item_row(ui);
+            }
             let mut events_iter = item_events.into_iter().enumerate().peekable();
 
-            // Required to ensure that the content of the list item is aligned with
-            // the * or - when wrapping
-            ui.horizontal_wrapped(|ui| {
-                while let Some((_, (e, src_span))) = events_iter.next() {
-                    self.process_event(
-                        ui,
-                        &mut events_iter,
-                        e,
-                        src_span,
-                        cache,
-                        options,
-                        max_width,
-                    );
+            directed(ui, rtl, |ui| {
+                if pending {
+                    self.list.item_marker(ui, options);
                 }
+                // Required to ensure that the content of the list item is aligned with
+                // the * or - when wrapping. It runs the way of its parent.
+                ui.horizontal_wrapped(|ui| {
+                    while let Some((_, (e, src_span))) = events_iter.next() {
+                        self.process_event(
+                            ui,
+                            &mut events_iter,
+                            e,
+                            src_span,
+                            cache,
+                            options,
+                            max_width,
+                        );
+                    }
+                });
             });
         }
     }
@@ -1117,30 +2139,47 @@ impl CommonMarkViewerInternal {
         ui: &mut Ui,
     ) {
         if self.is_blockquote {
-            let mut collected_events = delayed_events(events, |tag| {
-                matches!(tag, pulldown_cmark::TagEnd::BlockQuote(_))
-            });
+            let mut collected_events = delayed_events_blockquote(events);
             self.line.try_insert_start(ui);
+            self.record_pending_source_block(ui, cache);
 
             // Currently the blockquotes are made in such a way that they need a newline at the end
             // and the start so when this is the first element in the markdown the newline must be
             // manually enabled
             self.line.should_not_start_newline_forced = false;
-            if let Some(alert) = parse_alerts(&options.alerts, &mut collected_events) {
-                egui_commonmark_backend_extended::alert_ui(alert, ui, |ui| {
-                    for (event, src_span) in collected_events {
-                        self.event(ui, event, src_span, cache, options, max_width);
-                    }
-                })
+            // Where the quote's text starts identifies it across frames
+            let alert_id = self
+                .source_id
+                .unwrap_or(Id::NULL)
+                .with("_alert")
+                .with(collected_events.first().map(|(_, span)| span.start));
+            let alert = if options.extensions.alerts {
+                parse_alerts(&options.alerts, &mut collected_events)
             } else {
-                blockquote(ui, ui.visuals().weak_text_color(), |ui| {
-                    self.text_style.quote = true;
-                    for (event, src_span) in collected_events {
-                        self.event(ui, event, src_span, cache, options, max_width);
+                None
+            };
+            let rtl = options.is_rtl(texts(&collected_events));
+            directed(ui, rtl, |ui| {
+                if let Some(alert) = alert {
+                    let open = alert
+                        .collapsible
+                        .map(|default| cache.alert_open(alert_id, default));
+                    let style = &options.blockquote_style;
+                    let toggled = alert_ui(alert, ui, style, open, |ui| {
+                        for (event, src_span) in collected_events {
+                            self.event(ui, event, src_span, cache, options, max_width);
+                        }
+                    });
+                    if let Some(open) = toggled {
+                        cache.set_alert_open(alert_id, open);
                     }
+                } else {
+                    let start = collected_events.first().map(|(_, span)| span.start);
+                    self.text_style.quote = true;
+                    self.quote(ui, collected_events, 0, start, cache, options, max_width);
                     self.text_style.quote = false;
-                });
-            }
+                }
+            });
 
             if events.peek().is_none() {
                 self.line.should_end_newline_forced = false;
@@ -1151,6 +2190,68 @@ impl CommonMarkViewerInternal {
         }
     }
 
+    /// Render a blockquote `depth` levels deep with the bar color of its level,
+    /// collapsed behind a toggle when it is nested deeper than the options
+    /// allow. `start` is where its text starts, identifying it across frames.
+    #[allow(clippy::too_many_arguments)]
+    fn quote(
+        &mut self,
+        ui: &mut Ui,
+        events: Vec<(pulldown_cmark::Event, Range<usize>)>,
+        depth: usize,
+        start: Option<usize>,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        let color = options.blockquote_color(ui, depth);
+        let collapsible = options
+            .collapse_quotes_deeper_than
+            .is_some_and(|max| depth >= max);
+        styled_blockquote(ui, color, &options.blockquote_style, |ui| {
+            if collapsible {
+                let id = self
+                    .source_id
+                    .unwrap_or(Id::NULL)
+                    .with("_quote")
+                    .with(start);
+                let open = cache.alert_open(id, false);
+                if quoted_text_toggle(ui, color, open) {
+                    cache.set_alert_open(id, !open);
+                }
+                if !open {
+                    return;
+                }
+            }
+            self.blockquote_body(ui, events, depth, cache, options, max_width);
+        });
+    }
+
+    /// Render the events of a blockquote `depth` levels deep, giving each
+    /// nested blockquote its own indented frame and accent bar.
+    fn blockquote_body(
+        &mut self,
+        ui: &mut Ui,
+        events: Vec<(pulldown_cmark::Event, Range<usize>)>,
+        depth: usize,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        let mut events = events.into_iter().enumerate();
+        while let Some((_, (event, src_span))) = events.next() {
+            if let pulldown_cmark::Event::Start(pulldown_cmark::Tag::BlockQuote(_)) = event {
+                let nested = delayed_events_blockquote(&mut events);
+                self.line.try_insert_start(ui);
+                let start = Some(src_span.start);
+                self.quote(ui, nested, depth + 1, start, cache, options, max_width);
+                self.line.try_insert_end(ui);
+            } else {
+                self.event(ui, event, src_span, cache, options, max_width);
+            }
+        }
+    }
+
     fn table<'e>(
         &mut self,
         events: &mut Peekable<impl Iterator<Item = EventIteratorItem<'e>>>,
@@ -1159,8 +2260,10 @@ impl CommonMarkViewerInternal {
         ui: &mut Ui,
         max_width: f32,
     ) {
+        profile_scope!("table");
         if self.is_table {
             self.line.try_insert_start(ui);
+            self.record_pending_source_block(ui, cache);
 
             let id = ui.id().with("_table").with(self.curr_table);
             self.curr_table += 1;
@@ -1170,8 +2273,25 @@ impl CommonMarkViewerInternal {
             // `header` is a Vec<Cell> for a single header row, so `header.len()` is
             // the column count. Each row in `rows` is itself a Vec<Cell>.
             let Table { header, rows } = parse_table(events);
+            let alignments = std::mem::take(&mut self.table_alignments);
             // Drop trailing empty rows that pulldown_cmark sometimes appends.
             let rows: Vec<_> = rows.into_iter().filter(|r| !r.is_empty()).collect();
+            let cells = TableCells {
+                header: header.iter().map(|cell| plain_text(cell)).collect(),
+                rows: rows
+                    .iter()
+                    .map(|row| row.iter().map(|cell| plain_text(cell)).collect())
+                    .collect(),
+                alignments: alignments.clone(),
+            };
+            cache.record_table(RenderedTable {
+                source_id: self.source_id.unwrap_or(Id::NULL),
+                index: self.curr_table - 1,
+                cells: cells.clone(),
+            });
+            let hooked = options
+                .render_hooks
+                .is_some_and(|hooks| hooks.table(ui, &cells.header, &cells.rows));
             let num_cols = if !header.is_empty() {
                 header.len()
             } else {
@@ -1179,7 +2299,7 @@ impl CommonMarkViewerInternal {
             };
             let line_h = ui.text_style_height(&egui::TextStyle::Body);
 
-            if num_cols == 0 {
+            if num_cols == 0 || hooked {
                 self.is_table = false;
                 if events.peek().is_none() {
                     self.line.should_end_newline_forced = false;
@@ -1196,38 +2316,67 @@ impl CommonMarkViewerInternal {
                 .map(|c| cell_visual_lines(c))
                 .max()
                 .unwrap_or(1);
-            let header_h = cell_h * header_lines as f32;
+            // Wrapped cells can't be measured before they are laid out, so their
+            // rows use the height measured on the previous frame (header is row 0).
+            let fit = options.table_mode == TableMode::Fit;
+            let wrap = options.table_wrap_cells || fit;
+            let row_height = |ui: &Ui, row: usize, estimate: f32| -> f32 {
+                if wrap {
+                    let measured: Option<f32> = ui.data(|d| d.get_temp(id.with(("_row_h", row))));
+                    estimate.max(measured.unwrap_or(0.0))
+                } else {
+                    estimate
+                }
+            };
+            let header_h = row_height(ui, 0, cell_h * header_lines as f32);
             // Pre-compute per-body-row height so multi-chunk cells aren't clipped.
             let body_heights: Vec<f32> = rows
                 .iter()
-                .map(|row| {
+                .enumerate()
+                .map(|(row_idx, row)| {
                     let max_lines = row
                         .iter()
                         .map(|c| cell_visual_lines(c))
                         .max()
                         .unwrap_or(1);
-                    cell_h * max_lines as f32
+                    row_height(ui, row_idx + 1, cell_h * max_lines as f32)
                 })
                 .collect();
-            // Outer ScrollArea::horizontal handles the case where columns
-            // (auto-sized to content) total wider than the parent ui; without it,
-            // narrow windows clip the rightmost columns. Plain vertical wheel
-            // remains with the outer document scroller (#22); Shift+wheel opts in
-            // to horizontal table scrolling via `forward_shift_wheel_to_horizontal_scroll`.
+            let mut used_heights = vec![0.0f32; rows.len() + 1];
+            let mut column = egui_extras::Column::auto().resizable(true).at_least(40.0);
+            let max_column_width = if fit {
+                let fit_width = fit_column_width(ui, max_width, num_cols);
+                Some(
+                    options
+                        .table_max_column_width
+                        .map_or(fit_width, |w| w.min(fit_width)),
+                )
+            } else {
+                options.table_max_column_width.or(wrap.then_some(max_width))
+            };
+            if let Some(max_column_width) = max_column_width {
+                column = column.at_most(max_column_width.max(40.0)).clip(true);
+            }
+            let cell_layout = if wrap {
+                egui::Layout::left_to_right(egui::Align::Min).with_main_wrap(true)
+            } else {
+                egui::Layout::left_to_right(egui::Align::Center)
+            };
+            // The outer scroll area handles the case where columns (auto-sized to
+            // content) total wider than the parent ui; without it, narrow windows
+            // clip the rightmost columns. See `table_scroll_area` for the wheel.
             // ui.vertical(...) is essential: TableBuilder's body() positions itself
             // relative to the parent's cursor, but the parent here is a horizontal-
             // flow Ui from the markdown renderer. Without the vertical scope the
             // body's first row overlaps the header row.
-            let mut scroll_out = egui::ScrollArea::horizontal()
-                .id_salt(id.with("_scroll"))
-                .max_width(max_width)
-                .auto_shrink([false, true])
-                .show(ui, |ui| {
+            let mut scroll_out =
+                table_scroll_area(ui, id, max_width, options.table_mode, |ui| {
                     ui.vertical(|ui| {
                         egui::Frame::group(ui.style()).show(ui, |ui| {
                             let table = egui_extras::TableBuilder::new(ui)
                                 .id_salt(id)
-                                .striped(true)
+                                .striped(options.table_striped)
+                                .sense(table_row_sense(options))
                                 .resizable(true)
                                 .vscroll(false)
                                 // Shrink horizontally to the columns' content so a
@@ -1238,43 +2387,18 @@ impl CommonMarkViewerInternal {
                                 // max_width and provides horizontal scroll.
                                 .auto_shrink([true, true])
                                 .min_scrolled_height(0.0)
-                                .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-                                .columns(
-                                    egui_extras::Column::auto().resizable(true).at_least(40.0),
-                                    num_cols,
-                                )
+                                .cell_layout(cell_layout)
+                                .columns(column, num_cols)
                                 .header(header_h, |mut row| {
-                                    for col in header {
-                                        row.col(|ui| {
+                                    for (col_idx, col) in header.into_iter().enumerate() {
+                                        let (used, _) = row.col(|ui| {
                                             let col_w = ui.available_width();
-                                            for (e, src_span) in col {
-                                                let tmp_start = std::mem::replace(
-                                                    &mut self.line.should_start_newline,
-                                                    false,
-                                                );
-                                                let tmp_end = std::mem::replace(
-                                                    &mut self.line.should_end_newline,
-                                                    false,
-                                                );
-                                                self.event(
-                                                    ui, e, src_span, cache, options, col_w,
-                                                );
-                                                self.line.should_start_newline = tmp_start;
-                                                self.line.should_end_newline = tmp_end;
-                                            }
-                                        });
-                                    }
-                                });
-                            table.body(|mut body| {
-                                for (row_idx, row) in rows.into_iter().enumerate() {
-                                    let h = body_heights
-                                        .get(row_idx)
-                                        .copied()
-                                        .unwrap_or(cell_h);
-                                    body.row(h, |mut row_ui| {
-                                        for col in row {
-                                            row_ui.col(|ui| {
-                                                let col_w = ui.available_width();
+                                            let alignment = alignments
+                                                .get(col_idx)
+                                                .copied()
+                                                .unwrap_or(pulldown_cmark::Alignment::None);
+                                            let cell_id = id.with(("_cell", 0, col_idx));
+                                            aligned_cell(ui, alignment, cell_id, |ui| {
                                                 for (e, src_span) in col {
                                                     let tmp_start = std::mem::replace(
                                                         &mut self.line.should_start_newline,
@@ -1291,14 +2415,67 @@ impl CommonMarkViewerInternal {
                                                     self.line.should_end_newline = tmp_end;
                                                 }
                                             });
+                                        });
+                                        used_heights[0] = used_heights[0].max(used.height());
+                                    }
+                                });
+                            table.body(|mut body| {
+                                for (row_idx, row) in rows.into_iter().enumerate() {
+                                    let h = body_heights
+                                        .get(row_idx)
+                                        .copied()
+                                        .unwrap_or(cell_h);
+                                    body.row(h, |mut row_ui| {
+                                        for (col_idx, col) in row.into_iter().enumerate() {
+                                            let (used, _) = row_ui.col(|ui| {
+                                                let col_w = ui.available_width();
+                                                let alignment = alignments
+                                                    .get(col_idx)
+                                                    .copied()
+                                                    .unwrap_or(pulldown_cmark::Alignment::None);
+                                                let cell_id =
+                                                    id.with(("_cell", row_idx + 1, col_idx));
+                                                aligned_cell(ui, alignment, cell_id, |ui| {
+                                                    for (e, src_span) in col {
+                                                        let tmp_start = std::mem::replace(
+                                                            &mut self.line.should_start_newline,
+                                                            false,
+                                                        );
+                                                        let tmp_end = std::mem::replace(
+                                                            &mut self.line.should_end_newline,
+                                                            false,
+                                                        );
+                                                        self.event(
+                                                            ui, e, src_span, cache, options, col_w,
+                                                        );
+                                                        self.line.should_start_newline = tmp_start;
+                                                        self.line.should_end_newline = tmp_end;
+                                                    }
+                                                });
+                                            });
+                                            used_heights[row_idx + 1] =
+                                                used_heights[row_idx + 1].max(used.height());
                                         }
                                     });
                                 }
                             });
                         });
+                        if wrap {
+                            for (row, used) in used_heights.iter().enumerate() {
+                                let key = id.with(("_row_h", row));
+                                let last: Option<f32> = ui.data(|d| d.get_temp(key));
+                                if last.map_or(true, |h| (h - used).abs() > 0.5) {
+                                    ui.data_mut(|d| d.insert_temp(key, *used));
+                                    ui.ctx().request_repaint();
+                                }
+                            }
+                        }
                     });
                 });
-            forward_shift_wheel_to_horizontal_scroll(ui, &mut scroll_out);
+            forward_wheel_to_horizontal_scroll(ui, &mut scroll_out);
+            if options.table_context_menu {
+                table_copy_menu(ui, scroll_out.inner_rect, id, &cells);
+            }
             self.is_table = false;
             if events.peek().is_none() {
                 self.line.should_end_newline_forced = false;
@@ -1317,12 +2494,73 @@ impl CommonMarkViewerInternal {
         options: &CommonMarkOptions,
         max_width: f32,
     ) {
+        // Items that are not laid out by `item_list_wrapping` keep the marker
+        // on the left
+        if std::mem::take(&mut self.pending_item) {
+            self.list.start_item(ui, options);
+        }
+        // Whether this starts a paragraph or list item with a copy button,
+        // or ends one, and whether it is an item
+        let copy_block = match &event {
+            _ if !options.block_copy_buttons => None,
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Paragraph)
+                if !self.list.is_inside_a_list() =>
+            {
+                Some((true, false))
+            }
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Paragraph)
+                if !self.list.is_inside_a_list() =>
+            {
+                Some((false, false))
+            }
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Item) => Some((true, true)),
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Item) => Some((false, true)),
+            _ => None,
+        };
+        let span = src_span.clone();
+        // Search matches in alt text, link titles and code block info
+        // strings aren't shown as text; the whole element is marked instead
+        let hidden = match &event {
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Image) => Some(span.clone()),
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Link) => {
+                Some(self.shown_text_end.max(span.start)..span.end)
+            }
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::CodeBlock) => {
+                self.code_block.as_ref().map(|block| {
+                    let content = block.source.first().map_or(span.end, |(_, source)| source.start);
+                    span.start..content
+                })
+            }
+            pulldown_cmark::Event::Text(_) | pulldown_cmark::Event::Code(_) => {
+                self.shown_text_end = span.end;
+                None
+            }
+            _ => None,
+        }
+        .filter(|hidden| {
+            cache
+                .search_ranges()
+                .iter()
+                .any(|range| hidden.start <= range.start && range.end <= hidden.end)
+        });
+        let top = ui.cursor().top();
+        self.element_rect = None;
         match event {
-            pulldown_cmark::Event::Start(tag) => self.start_tag(ui, tag, options),
+            pulldown_cmark::Event::Start(tag) => self.start_tag(ui, tag, src_span.start, options),
             pulldown_cmark::Event::End(tag) => self.end_tag(ui, tag, cache, options, max_width),
-            pulldown_cmark::Event::Text(text) => {
-                self.event_text_with_highlights(text, &src_span, cache, ui, options);
+            pulldown_cmark::Event::Text(text) if self.code_block.is_some() => {
+                if let Some(block) = &mut self.code_block {
+                    block.push_text(&text, src_span);
+                }
             }
+            pulldown_cmark::Event::Text(text) => match self.math.resolve_references(&text) {
+                // Search matches are mapped onto the source, which no longer
+                // lines up with text whose references were resolved
+                std::borrow::Cow::Owned(resolved) => self.event_text(resolved.into(), ui, options),
+                std::borrow::Cow::Borrowed(_) => {
+                    self.event_text_with_highlights(text, &src_span, cache, ui, options);
+                }
+            },
             pulldown_cmark::Event::Code(text) => {
                 self.text_style.code = true;
                 let segments = inline_code_wrap_segments(&text);
@@ -1359,32 +2597,60 @@ impl CommonMarkViewerInternal {
                     }
                 }
                 self.text_style.code = false;
+                if options.color_swatches {
+                    if let Some(color) = parse_color_code(&text) {
+                        color_swatch(ui, color);
+                    }
+                }
             }
             pulldown_cmark::Event::InlineHtml(text) => {
-                self.event_text(text, ui, options);
+                let script = options.superscript_subscript && !options.sandbox.block_raw_html;
+                if !(script && self.inline_html_script_tag(&text)) {
+                    self.event_text(text, ui, options);
+                }
             }
 
             pulldown_cmark::Event::Html(text) => {
                 // Always accumulate HTML blocks for table detection
                 self.html_block.push_str(&text);
             }
-            pulldown_cmark::Event::FootnoteReference(footnote) => {
-                footnote_start(ui, &footnote);
+            pulldown_cmark::Event::FootnoteReference(label) => {
+                let (number, nth) = self.footnote_reference(&label);
+                let response = footnote_start(ui, &number.to_string());
+                let y = response.rect.top() - self.content_top;
+                let source_id = self.source_id.unwrap_or(Id::NULL);
+                cache.record_anchor_position(source_id, footnote_ref_anchor(&label, nth), y);
+                if response.hovered() {
+                    ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                }
+                if response.clicked() {
+                    if let Some(source_id) = self.source_id {
+                        cache.scroll_to_heading(source_id, &footnote_anchor(&label));
+                    }
+                }
             }
             pulldown_cmark::Event::SoftBreak => {
-                soft_break(ui);
+                if options.soft_breaks_as_newlines {
+                    newline(ui);
+                } else {
+                    soft_break(ui);
+                }
             }
             pulldown_cmark::Event::HardBreak => newline(ui),
             pulldown_cmark::Event::Rule => {
                 self.line.try_insert_start(ui);
+                self.record_pending_source_block(ui, cache);
                 rule(ui, self.line.can_insert_end());
             }
             pulldown_cmark::Event::TaskListMarker(mut checkbox) => {
-                if options.mutable {
+                if options.mutable || options.checkbox_clicked_fn.is_some() {
                     if ui
                         .add(egui::Checkbox::without_text(&mut checkbox))
                         .clicked()
                     {
+                        if let Some(checkbox_clicked_fn) = options.checkbox_clicked_fn {
+                            checkbox_clicked_fn(src_span.clone(), checkbox);
+                        }
                         self.checkbox_events.push(CheckboxClickEvent {
                             checked: checkbox,
                             span: src_span,
@@ -1400,6 +2666,7 @@ impl CommonMarkViewerInternal {
                     let text: CowStr = format!("${tex}").into();
                     self.event_text(text, ui, options);
                 } else {
+                    let tex = cache.math_formula(ui.ctx(), &self.math, &tex);
                     #[cfg(feature = "math")]
                     {
                         crate::render_math(ui, cache, &tex, true);
@@ -1410,6 +2677,8 @@ impl CommonMarkViewerInternal {
                     }
                 }
             }
+            // The block defining the document's macros isn't shown
+            pulldown_cmark::Event::DisplayMath(_) if self.math.is_definitions(src_span.start) => {}
             pulldown_cmark::Event::DisplayMath(tex) => {
                 // Display math (`$$…$$`) is a block: force it onto its own line
                 // even when the source keeps it in the same paragraph as the
@@ -1417,17 +2686,56 @@ impl CommonMarkViewerInternal {
                 // the right of that text and, being taller than a line, gets
                 // pushed down by the row's bottom-alignment.
                 newline(ui);
-                #[cfg(feature = "math")]
-                {
-                    crate::render_math(ui, cache, &tex, false);
-                }
-                #[cfg(not(feature = "math"))]
-                if let Some(math_fn) = options.math_fn {
-                    math_fn(ui, &tex, false);
+                let number = self.math.number(src_span.start).map(str::to_owned);
+                let tex = cache.math_formula(ui.ctx(), &self.math, &tex);
+                let equation = ui.scope(|ui| {
+                    #[cfg(feature = "math")]
+                    {
+                        crate::render_math(ui, cache, &tex, false);
+                    }
+                    #[cfg(not(feature = "math"))]
+                    if let Some(math_fn) = options.math_fn {
+                        math_fn(ui, &tex, false);
+                    }
+                });
+                if let Some(number) = number {
+                    equation_number(ui, equation.response.rect, &number);
                 }
                 newline(ui);
             }
         }
+        if let Some(hidden) = hidden {
+            let rect = self.element_rect.unwrap_or_else(|| {
+                egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), top..=ui.min_rect().bottom())
+            });
+            let active = cache
+                .active_search_range()
+                .is_some_and(|range| hidden.start <= range.start && range.end <= hidden.end);
+            ui.painter().rect_stroke(
+                rect.expand(2.0),
+                3.0,
+                egui::Stroke::new(2.0, search_match_color(ui, active)),
+                egui::StrokeKind::Outside,
+            );
+            if active {
+                cache.record_active_search_y_viewport(rect.top());
+            }
+        }
+        match copy_block {
+            Some((true, _)) => self.open_copy_blocks.push((span.start, ui.cursor().top())),
+            Some((false, is_item)) => {
+                let Some(index) = self.open_copy_blocks.iter().rposition(|(start, _)| *start == span.start)
+                else {
+                    return;
+                };
+                let top = self.open_copy_blocks[index].1;
+                self.open_copy_blocks.truncate(index);
+                let bottom = ui.cursor().top().max(ui.min_rect().bottom());
+                let rect = egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), top..=bottom);
+                self.copy_blocks.push((span, rect, is_item));
+            }
+            None => {}
+        }
     }
 
     fn event_text(&mut self, text: CowStr, ui: &mut Ui, options: &CommonMarkOptions) {
@@ -1444,51 +2752,85 @@ impl CommonMarkViewerInternal {
         options: &CommonMarkOptions,
     ) {
         let bg = hl.background_color(ui);
-        let mut rich_text = if bg.is_some() && self.text_style.code {
-            // egui's RichText renderer overrides `background_color` with the theme's
-            // `code_bg_color` whenever `.code()` is set (widget_text.rs:421). To make
-            // our search highlight visible inside inline code, build the RichText
-            // manually with a monospace font instead of calling `.code()` — that gives
-            // the visual effect of code (monospace + slightly larger weight) while
-            // letting our background_color survive.
-            let mut t = egui::RichText::new(text.as_ref())
-                .text_style(egui::TextStyle::Monospace);
-            if self.text_style.strong {
-                t = t.strong();
-            }
-            if self.text_style.emphasis {
-                t = t.italics();
-            }
-            if self.text_style.strikethrough {
-                t = t.strikethrough();
-            }
-            if self.text_style.quote {
-                t = t.weak();
-            }
-            t
-        } else {
-            self.text_style.to_richtext_with_options(ui, &text, options)
+        let text_style = &self.text_style;
+        let rich_text = |ui: &Ui| {
+            let mut rich_text = if bg.is_some() && text_style.code {
+                // egui's RichText renderer overrides `background_color` with the theme's
+                // `code_bg_color` whenever `.code()` is set (widget_text.rs:421). To make
+                // our search highlight visible inside inline code, build the RichText
+                // manually with a monospace font instead of calling `.code()` — that gives
+                // the visual effect of code (monospace + slightly larger weight) while
+                // letting our background_color survive.
+                let mut t = egui::RichText::new(text.as_ref())
+                    .text_style(egui::TextStyle::Monospace);
+                if text_style.strong {
+                    t = t.strong();
+                }
+                if text_style.emphasis {
+                    t = t.italics();
+                }
+                if text_style.strikethrough {
+                    t = t.strikethrough();
+                }
+                if text_style.quote {
+                    t = t.weak();
+                }
+                t
+            } else {
+                text_style.to_richtext_with_options(ui, &text, options)
+            };
+            if let Some(bg) = bg {
+                rich_text = rich_text.background_color(bg);
+            }
+            rich_text
         };
-        if let Some(bg) = bg {
-            rich_text = rich_text.background_color(bg);
-        }
         if let Some(image) = &mut self.image {
-            image.alt_text.push(rich_text);
+            image.alt_text.push(rich_text(ui));
         } else if let Some(block) = &mut self.code_block {
-            // Code blocks render via syntect after end_tag; highlight inside code
-            // blocks is a v2 feature (would need syntect integration). Just collect text.
+            // Code blocks render via syntect after end_tag, painting the search
+            // matches of the text they got with `push_text` themselves
             block.content.push_str(&text);
         } else if let Some(link) = &mut self.link {
-            link.text.push(rich_text);
+            link.text.push(rich_text(ui));
         } else if self.text_style.heading.is_some() {
             // Accumulate heading text for position tracking
             self.current_heading_text
                 .push_str(raw_heading_text.unwrap_or(&text));
             // Accumulate RichText - will render all at once in end_tag(Heading)
-            self.current_heading_rich_texts.push(rich_text);
+            self.current_heading_rich_texts.push(rich_text(ui));
         } else {
-            ui.label(rich_text);
+            let job = self
+                .text_layouts
+                .get_or_insert(ui, &text, &self.text_style, bg, || rich_text(ui));
+            // Justifying follows the rows of left-to-right wrapping
+            let justify = options.typography.justify && !ui.layout().prefer_right_to_left();
+            if self.in_paragraph && justify {
+                justified_label(ui, job);
+            } else {
+                ui.label(egui::WidgetText::LayoutJob(job));
+            }
+        }
+    }
+
+    /// Emit `text`, the source bytes from `start`, split by the viewer's
+    /// `highlight_ranges` where it is not a search match.
+    fn emit_text_in_ranges(
+        &mut self,
+        text: &str,
+        start: usize,
+        hl: HighlightKind,
+        ui: &mut Ui,
+        options: &CommonMarkOptions,
+    ) {
+        if hl != HighlightKind::None || options.highlight_ranges.is_empty() {
+            self.emit_text(text.into(), None, hl, ui, options);
+            return;
         }
+        let span = start..start + text.len();
+        visit_source_range_segments(text, &span, options.highlight_ranges, |piece, range| {
+            let hl = range.map_or(HighlightKind::None, |range| HighlightKind::Range(range.color));
+            self.emit_text(piece.into(), None, hl, ui, options);
+        });
     }
 
     /// Render source-literal text while preserving fine-grained search highlighting.
@@ -1502,6 +2844,7 @@ impl CommonMarkViewerInternal {
     ) {
         // Emit borrowed slices directly; record captured active Y after cache borrows end.
         let mut active_y = None;
+        let mut start = span.start;
         visit_highlight_segments(
             &text,
             span,
@@ -1511,7 +2854,8 @@ impl CommonMarkViewerInternal {
                 if hl == HighlightKind::Active {
                     active_y = Some(ui.cursor().top());
                 }
-                self.emit_text(segment_text.into(), None, hl, ui, options);
+                self.emit_text_in_ranges(segment_text, start, hl, ui, options);
+                start += segment_text.len();
             },
         );
         if let Some(y) = active_y {
@@ -1535,14 +2879,23 @@ impl CommonMarkViewerInternal {
 
         // Emit borrowed source slices and static emoji strings directly.
         let mut active_y = None;
-        visit_emoji_text_segments(&text, span, |segment| {
+        let mut visit = |segment: EmojiTextSegment| {
             if segment.replaced {
                 // Replacement glyphs are indivisible, but overlap uses raw source range.
-                let hl = highlight_for_source_span(
+                let mut hl = highlight_for_source_span(
                     &segment.source_range,
                     cache.search_ranges(),
                     cache.active_search_range(),
                 );
+                if hl == HighlightKind::None {
+                    let source = &segment.source_range;
+                    let overlapping = options.highlight_ranges.iter().rev().find(|highlight| {
+                        highlight.range.start < source.end && source.start < highlight.range.end
+                    });
+                    if let Some(highlight) = overlapping {
+                        hl = HighlightKind::Range(highlight.color);
+                    }
+                }
                 if hl == HighlightKind::Active {
                     active_y = Some(ui.cursor().top());
                 }
@@ -1551,6 +2904,7 @@ impl CommonMarkViewerInternal {
             }
 
             // Plain source-preserving segments retain exact highlight splitting.
+            let mut start = segment.source_range.start;
             visit_highlight_segments(
                 segment.rendered,
                 &segment.source_range,
@@ -1560,19 +2914,40 @@ impl CommonMarkViewerInternal {
                     if hl == HighlightKind::Active {
                         active_y = Some(ui.cursor().top());
                     }
-                    self.emit_text(segment_text.into(), None, hl, ui, options);
+                    self.emit_text_in_ranges(segment_text, start, hl, ui, options);
+                    start += segment_text.len();
                 },
             );
-        });
+        };
+        if options.emoji_shortcodes {
+            visit_emoji_text_segments(&text, span, &mut visit);
+        } else {
+            visit(EmojiTextSegment {
+                rendered: &text,
+                source_range: span.clone(),
+                raw: &text,
+                replaced: false,
+            });
+        }
         if let Some(y) = active_y {
             cache.record_active_search_y_viewport(y);
         }
     }
 
-    fn start_tag(&mut self, ui: &mut Ui, tag: pulldown_cmark::Tag, options: &CommonMarkOptions) {
+    fn start_tag(
+        &mut self,
+        ui: &mut Ui,
+        tag: pulldown_cmark::Tag,
+        src_start: usize,
+        options: &CommonMarkOptions,
+    ) {
         match tag {
             pulldown_cmark::Tag::Paragraph => {
                 self.line.try_insert_start(ui);
+                self.in_paragraph = true;
+                if !self.list.is_inside_a_list() {
+                    paragraph_start_indent(ui, &options.typography);
+                }
             }
             pulldown_cmark::Tag::Heading { level, .. } => {
                 // End current row to ensure heading starts at left edge
@@ -1590,6 +2965,12 @@ impl CommonMarkViewerInternal {
                     HeadingLevel::H5 => 4,
                     HeadingLevel::H6 => 5,
                 });
+                let numbers = options.heading_numbers;
+                if let Ok(i) = numbers.binary_search_by_key(&src_start, |(start, _)| *start) {
+                    let number = format!("{} ", numbers[i].1);
+                    let number = self.text_style.to_richtext_with_options(ui, &number, options);
+                    self.current_heading_rich_texts.push(number);
+                }
             }
 
             // deliberately not using the built in alerts from pulldown-cmark as
@@ -1603,27 +2984,29 @@ impl CommonMarkViewerInternal {
                     ui.end_row();
                 }
 
-                match c {
-                    pulldown_cmark::CodeBlockKind::Fenced(lang) => {
-                        self.code_block = Some(crate::CodeBlock {
-                            lang: Some(lang.to_string()),
-                            content: "".to_string(),
-                        });
-                    }
-                    pulldown_cmark::CodeBlockKind::Indented => {
-                        self.code_block = Some(crate::CodeBlock {
-                            lang: None,
-                            content: "".to_string(),
-                        });
-                    }
-                }
+                self.code_block = Some(crate::CodeBlock::new(match &c {
+                    pulldown_cmark::CodeBlockKind::Fenced(info) => Some(info),
+                    pulldown_cmark::CodeBlockKind::Indented => None,
+                }));
                 self.line.try_insert_start(ui);
             }
 
             pulldown_cmark::Tag::List(point) => {
-                if !self.list.is_inside_a_list() && self.line.can_insert_start() {
+                let is_nested = self.list.is_inside_a_list();
+                if !is_nested && self.line.can_insert_start() {
                     newline(ui);
                 }
+                if let Some(&(done, total)) = self.task_progress.get(&src_start) {
+                    // A nested list's count sits at the end of its parent
+                    // item's line; a top-level list gets a line of its own.
+                    if is_nested {
+                        soft_break(ui);
+                    }
+                    task_list_progress_label(ui, done, total);
+                    if !is_nested {
+                        newline(ui);
+                    }
+                }
 
                 if let Some(number) = point {
                     self.list.start_level_with_number(number);
@@ -1636,18 +3019,21 @@ impl CommonMarkViewerInternal {
 
             pulldown_cmark::Tag::Item => {
                 self.is_list_item = true;
-                self.list.start_item(ui, options);
+                if options.text_direction == TextDirection::LeftToRight {
+                    self.list.start_item(ui, options);
+                } else {
+                    self.pending_item = true;
+                }
             }
 
-            pulldown_cmark::Tag::FootnoteDefinition(note) => {
-                self.line.try_insert_start(ui);
-
-                self.line.should_start_newline = false;
-                self.line.should_end_newline = false;
-                footnote(ui, &note);
+            pulldown_cmark::Tag::FootnoteDefinition(label) => {
+                // Collected by `collect_footnote_definition` and rendered in
+                // the footnote section at the end of the document.
+                self.footnote_definition = Some(label.to_string());
             }
-            pulldown_cmark::Tag::Table(_) => {
+            pulldown_cmark::Tag::Table(alignments) => {
                 self.is_table = true;
+                self.table_alignments = alignments;
             }
             pulldown_cmark::Tag::TableHead => {}
             pulldown_cmark::Tag::TableRow => {}
@@ -1661,14 +3047,21 @@ impl CommonMarkViewerInternal {
             pulldown_cmark::Tag::Strikethrough => {
                 self.text_style.strikethrough = true;
             }
-            pulldown_cmark::Tag::Link { dest_url, .. } => {
+            pulldown_cmark::Tag::Link {
+                dest_url, title, ..
+            } => {
                 self.link = Some(crate::Link {
-                    destination: dest_url.to_string(),
+                    destination: options.rewrite_url(&dest_url, false),
+                    title: title.to_string(),
                     text: Vec::new(),
                 });
             }
-            pulldown_cmark::Tag::Image { dest_url, .. } => {
-                self.image = Some(crate::Image::new(&dest_url, options));
+            pulldown_cmark::Tag::Image {
+                dest_url, title, ..
+            } => {
+                let mut image = crate::Image::new(&dest_url, options);
+                image.title = title.to_string();
+                self.image = Some(image);
             }
             pulldown_cmark::Tag::HtmlBlock => {
                 self.line.try_insert_start(ui);
@@ -1691,8 +3084,12 @@ impl CommonMarkViewerInternal {
             pulldown_cmark::Tag::DefinitionListDefinition => {
                 self.def_list.is_def_list_def = true;
             }
-            // Not yet supported
-            pulldown_cmark::Tag::Superscript | pulldown_cmark::Tag::Subscript => {}
+            pulldown_cmark::Tag::Superscript => {
+                self.text_style.superscript = true;
+            }
+            pulldown_cmark::Tag::Subscript => {
+                self.text_style.subscript = true;
+            }
         }
     }
 
@@ -1706,12 +3103,23 @@ impl CommonMarkViewerInternal {
     ) {
         match tag {
             pulldown_cmark::TagEnd::Paragraph => {
+                self.in_paragraph = false;
                 self.line.try_insert_end(ui);
                 // Add extra paragraph spacing if configured
                 paragraph_end_spacing(ui, &options.typography);
             }
             pulldown_cmark::TagEnd::Heading { .. } => {
+                let level = self.text_style.heading.unwrap_or(0) + 1;
+                if options
+                    .render_hooks
+                    .is_some_and(|hooks| hooks.heading(ui, level, &self.current_heading_text))
+                {
+                    self.current_heading_rich_texts.clear();
+                }
+                let anchor = (!self.current_heading_text.is_empty())
+                    .then(|| self.anchor_slugger.slug(&self.current_heading_text));
                 // Render all accumulated heading fragments at once, positioned at left edge
+                let mut drawn_at = None;
                 if !self.current_heading_rich_texts.is_empty() {
                     let available = ui.available_rect_before_wrap();
                     let left_edge = ui.min_rect().left();
@@ -1720,28 +3128,29 @@ impl CommonMarkViewerInternal {
                         egui::vec2(available.width() + (available.left() - left_edge), available.height()),
                     );
                     let rich_texts = std::mem::take(&mut self.current_heading_rich_texts);
-                    ui.allocate_ui_at_rect(heading_rect, |ui| {
+                    let response = ui.allocate_ui_at_rect(heading_rect, |ui| {
                         for rt in rich_texts {
                             ui.label(rt);
                         }
                     });
+                    drawn_at = Some(response.response.rect);
+                }
+                if let (Some(rect), Some(anchor), Some(document)) =
+                    (drawn_at, &anchor, &options.heading_link_button)
+                {
+                    heading_link_button(ui, rect, &format!("{document}#{anchor}"));
                 }
-                // Record header position for scroll navigation. Composite key
-                // is `normalized_title` for the 0th occurrence and
-                // `normalized_title#N` for the Nth duplicate (matches the key
-                // built by the app's `header_position_key` helper), so multiple
-                // headings with the same title get distinct cache entries.
+                // Record header position for scroll navigation, keyed by the
+                // viewer, the heading's slug and its occurrence among headings
+                // with that slug, so duplicates get distinct cache entries.
                 if let Some(y) = self.current_heading_y.take() {
-                    if !self.current_heading_text.is_empty() {
-                        let normalized = self.current_heading_text.trim().to_lowercase();
-                        let nth = self
-                            .heading_occurrence_counts
-                            .entry(normalized.clone())
-                            .or_insert(0);
-                        let key = if *nth == 0 {
-                            normalized.clone()
-                        } else {
-                            format!("{normalized}#{nth}")
+                    if self.record_headings && !self.current_heading_text.is_empty() {
+                        let slug = heading_slug(&self.current_heading_text);
+                        let nth = self.heading_occurrence_counts.entry(slug.clone()).or_insert(0);
+                        let key = HeaderKey {
+                            source_id: self.source_id.unwrap_or(Id::NULL),
+                            occurrence: *nth,
+                            slug,
                         };
                         *nth += 1;
                         // `y` (== `ui.cursor().top()` at heading start) is a
@@ -1749,8 +3158,8 @@ impl CommonMarkViewerInternal {
                         // cached value with `ScrollArea::vertical_scroll_offset(N)`,
                         // which interprets N as a CONTENT-y (where 0 is the
                         // top of the ScrollArea's content layout). Subtract
-                        // `ui.min_rect().top()` — that's the screen y of the
-                        // closure's ui top-left, which tracks the current
+                        // `self.content_top` — that's the screen y of the
+                        // document's top-left, which tracks the current
                         // scroll offset (it shifts up as the user scrolls).
                         // The subtraction cancels out both the panel chrome
                         // AND any active scroll offset, leaving a pure
@@ -1766,7 +3175,7 @@ impl CommonMarkViewerInternal {
                         // Previously stored `cur_offset + cursor.y` which gave
                         // 323 (off by 44 = panel chrome height), so scrolling
                         // to (323-50)=273 landed 44 px past the heading.
-                        let content_y = y - ui.min_rect().top();
+                        let content_y = y - self.content_top;
                         // Always refresh with current layout, not first-paint
                         // value. First-paint pinning produced increasing
                         // overshoot for deeper headers — the first frame
@@ -1779,7 +3188,21 @@ impl CommonMarkViewerInternal {
                         // stale (under-shot) position. Updating each paint
                         // keeps the click target in sync with the current
                         // rendered layout.
-                        cache.record_header_content_y(&key, content_y);
+                        if let Some(rect) = drawn_at {
+                            self.heading_rects.push((key.clone(), rect));
+                        }
+                        let bottom = ui.min_rect().bottom().max(y) - self.content_top;
+                        cache.record_heading(RenderedHeading {
+                            key: key.clone(),
+                            level,
+                            text: self.current_heading_text.clone(),
+                            y_range: egui::Rangef::new(content_y, bottom),
+                        });
+                        cache.record_header_content_y(key, content_y);
+                        if let Some(anchor) = anchor {
+                            let source_id = self.source_id.unwrap_or(Id::NULL);
+                            cache.record_anchor_position(source_id, anchor, content_y);
+                        }
                     }
                 }
                 self.current_heading_text.clear();
@@ -1790,7 +3213,15 @@ impl CommonMarkViewerInternal {
             }
             pulldown_cmark::TagEnd::BlockQuote(_) => {}
             pulldown_cmark::TagEnd::CodeBlock => {
-                self.end_code_block(ui, cache, options, max_width);
+                if ui.layout().prefer_right_to_left() {
+                    // Code reads left to right in any document
+                    let layout = egui::Layout::left_to_right(egui::Align::BOTTOM);
+                    ui.with_layout(layout.with_main_wrap(true), |ui| {
+                        self.end_code_block(ui, cache, options, max_width);
+                    });
+                } else {
+                    self.end_code_block(ui, cache, options, max_width);
+                }
 
                 // Keep any following list-item text below the completed block widget.
                 if self.list.is_inside_a_list() {
@@ -1812,11 +3243,7 @@ impl CommonMarkViewerInternal {
                 }
             }
             pulldown_cmark::TagEnd::Item => {}
-            pulldown_cmark::TagEnd::FootnoteDefinition => {
-                self.line.should_start_newline = true;
-                self.line.should_end_newline = true;
-                self.line.try_insert_end(ui);
-            }
+            pulldown_cmark::TagEnd::FootnoteDefinition => {}
             pulldown_cmark::TagEnd::Table => {}
             pulldown_cmark::TagEnd::TableHead => {}
             pulldown_cmark::TagEnd::TableRow => {}
@@ -1834,19 +3261,62 @@ impl CommonMarkViewerInternal {
                 self.text_style.strikethrough = false;
             }
             pulldown_cmark::TagEnd::Link => {
-                if let Some(link) = self.link.take() {
-                    link.end(ui, cache);
+                if let Some(mut link) = self.link.take() {
+                    let hooked = options.render_hooks.is_some_and(|hooks| {
+                        let text: String = link.text.iter().map(|t| t.text()).collect();
+                        hooks.link(ui, &link.destination, &link.title, &text)
+                    });
+                    if !hooked {
+                        let media = options
+                            .media_embeds
+                            .then(|| MediaKind::from_uri(&link.destination))
+                            .flatten();
+                        if let Some(kind) = media {
+                            let icon = format!("{} ", kind.icon());
+                            link.text.insert(0, egui::RichText::new(icon));
+                        }
+                        let info = LinkInfo {
+                            destination: link.destination.clone(),
+                            title: link.title.clone(),
+                            is_hook: cache.get_link_hook(&link.destination).is_some(),
+                        };
+                        let response = link.end(ui, cache, options);
+                        self.element_rect = Some(response.rect);
+                        if response.clicked() || response.middle_clicked() {
+                            self.clicked_link = Some(info.clone());
+                        }
+                        if response.hovered() || response.has_focus() {
+                            self.hovered_link = Some(info);
+                        }
+                    }
                 }
             }
             pulldown_cmark::TagEnd::Image => {
                 if let Some(image) = self.image.take() {
-                    image.end(ui, cache, options);
+                    let hooked = options.render_hooks.is_some_and(|hooks| {
+                        let alt_text: String = image.alt_text.iter().map(|t| t.text()).collect();
+                        hooks.image(ui, &image.uri, &alt_text)
+                    });
+                    if !hooked {
+                        self.image_rect = image.end(ui, cache, options);
+                        self.element_rect = self.image_rect;
+                    }
                 }
             }
             pulldown_cmark::TagEnd::HtmlBlock => {
                 if !self.html_block.is_empty() {
-                    if let Some(table) = egui_commonmark_backend_extended::html_table::parse_html_table(&self.html_block) {
-                        self.render_html_table(ui, &table, options, max_width);
+                    if options.sandbox.block_raw_html {
+                        let text: pulldown_cmark::CowStr = std::mem::take(&mut self.html_block).into();
+                        self.event_text(text, ui, options);
+                    } else if egui_commonmark_backend_extended::page_break::is_page_break(&self.html_block) {
+                        egui_commonmark_backend_extended::elements::page_break(ui);
+                        self.line.try_insert_end(ui);
+                    } else if let Some(details) = parse_html_details(&self.html_block) {
+                        // Rendered by `details` once the body events are collected
+                        self.details = Some(details);
+                    } else if let Some(table) = egui_commonmark_backend_extended::html_table::parse_html_table(&self.html_block) {
+                        self.render_html_table(ui, &table, cache, options, max_width);
+                        self.line.try_insert_end(ui);
                     } else if let Some(html_fn) = options.html_fn {
                         html_fn(ui, &self.html_block);
                     } else {
@@ -1863,8 +3333,26 @@ impl CommonMarkViewerInternal {
             pulldown_cmark::TagEnd::DefinitionList => self.line.try_insert_end(ui),
             pulldown_cmark::TagEnd::DefinitionListTitle
             | pulldown_cmark::TagEnd::DefinitionListDefinition => {}
-            pulldown_cmark::TagEnd::Superscript | pulldown_cmark::TagEnd::Subscript => {}
+            pulldown_cmark::TagEnd::Superscript => {
+                self.text_style.superscript = false;
+            }
+            pulldown_cmark::TagEnd::Subscript => {
+                self.text_style.subscript = false;
+            }
+        }
+    }
+
+    /// Apply inline `<sup>`/`<sub>` open and close tags to the text style.
+    /// Returns false for any other inline HTML.
+    fn inline_html_script_tag(&mut self, html: &str) -> bool {
+        match html.trim().to_ascii_lowercase().as_str() {
+            "<sup>" => self.text_style.superscript = true,
+            "</sup>" => self.text_style.superscript = false,
+            "<sub>" => self.text_style.subscript = true,
+            "</sub>" => self.text_style.subscript = false,
+            _ => return false,
         }
+        true
     }
 
     fn end_code_block(
@@ -1877,43 +3365,101 @@ impl CommonMarkViewerInternal {
         if let Some(block) = self.code_block.take() {
             let id = ui.id().with("_code_block").with(self.curr_code_block);
             self.curr_code_block += 1;
-            block.end(ui, cache, options, max_width, id);
+            let lang = block.lang.as_deref().unwrap_or("");
+            let hooked = options
+                .code_block_fns
+                .iter()
+                .any(|(matches, render)| matches(lang) && render(ui, lang, &block.content))
+                || options
+                    .render_hooks
+                    .is_some_and(|hooks| hooks.code_block(ui, lang, &block.content));
+            if !hooked {
+                self.code_block_or_table(ui, cache, options, max_width, id, &block);
+                if let Some(footer) = options.code_block_footer_fn {
+                    footer(ui, lang, &block.content, id);
+                }
+            }
             self.line.try_insert_end(ui);
         }
     }
 
+    /// Render `csv` and `tsv` fences as tables, when enabled, with a button
+    /// switching to the raw text. Everything else is a plain code block.
+    fn code_block_or_table(
+        &mut self,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+        id: Id,
+        block: &crate::CodeBlock,
+    ) {
+        use egui_commonmark_backend_extended::html_table::parse_delimited_table;
+        let table = match block.lang.as_deref() {
+            Some("csv") if options.csv_tables => parse_delimited_table(&block.content, ','),
+            Some("tsv") if options.csv_tables => parse_delimited_table(&block.content, '\t'),
+            _ => None,
+        };
+        let Some(table) = table else {
+            block.end(ui, cache, options, max_width, id);
+            return;
+        };
+
+        let raw_id = id.with("_raw");
+        let mut raw = ui.data(|d| d.get_temp::<bool>(raw_id).unwrap_or(false));
+        if ui
+            .small_button(if raw { "Table" } else { "Raw" })
+            .on_hover_text(if raw { "Show as a table" } else { "Show the raw text" })
+            .clicked()
+        {
+            raw = !raw;
+            ui.data_mut(|d| d.insert_temp(raw_id, raw));
+        }
+        if raw {
+            block.end(ui, cache, options, max_width, id);
+        } else {
+            self.render_html_table(ui, &table, cache, options, max_width);
+        }
+    }
+
     fn render_html_table(
         &mut self,
         ui: &mut Ui,
-        table: &egui_commonmark_backend_extended::html_table::HtmlTable,
+        table: &HtmlTable,
+        cache: &mut CommonMarkCache,
         options: &CommonMarkOptions,
         max_width: f32,
     ) {
         let id = ui.id().with("_html_table").with(self.curr_table);
         self.curr_table += 1;
 
+        if table.has_spans() {
+            self.render_spanning_html_table(ui, table, id, cache, options, max_width);
+            return;
+        }
+
         let num_cols = table
             .header
-            .first()
-            .or(table.rows.first())
+            .iter()
+            .chain(&table.rows)
             .map(|r| r.len())
+            .max()
             .unwrap_or(0);
 
         let line_h = ui.text_style_height(&egui::TextStyle::Body);
         let cell_h = line_h * 1.5;
 
         if num_cols == 0 {
-            self.line.try_insert_end(ui);
             return;
         }
 
         // Heuristic per-row heights: count explicit newlines + crude wrap est at
         // ~60 chars/visual-line. Over-estimates slightly (extra row height is
         // preferable to clipping). Header rows use the same heuristic.
-        let row_height_for = |cells: &[String]| -> f32 {
+        let row_height_for = |cells: &[HtmlCell]| -> f32 {
             let max_lines = cells
                 .iter()
-                .map(|c| html_cell_visual_lines(c))
+                .map(|c| html_cell_visual_lines(&c.text))
                 .max()
                 .unwrap_or(1);
             cell_h * max_lines as f32
@@ -1930,46 +3476,52 @@ impl CommonMarkViewerInternal {
             .map(|row| row_height_for(row))
             .collect();
         let body_heights: Vec<f32> = table.rows.iter().map(|row| row_height_for(row)).collect();
+        let empty = HtmlCell::plain(String::new());
+        // Short rows get empty cells so the column borders line up
+        let padded = |row: &[HtmlCell]| -> Vec<HtmlCell> {
+            let mut row = row.to_vec();
+            row.resize(num_cols, empty.clone());
+            row
+        };
 
-        // Outer ScrollArea::horizontal handles wide tables that exceed parent width;
-        // ui.vertical() prevents the header/body Y-overlap quirk. Plain vertical wheel
-        // stays with the outer document scroller (#22); Shift+wheel opts in to
-        // horizontal table scrolling via `forward_shift_wheel_to_horizontal_scroll`.
-        let mut scroll_out = egui::ScrollArea::horizontal()
-            .id_salt(id.with("_scroll"))
-            .max_width(max_width)
-            .auto_shrink([false, true])
-            .show(ui, |ui| {
+        // The outer scroll area handles wide tables that exceed parent width;
+        // ui.vertical() prevents the header/body Y-overlap quirk. See
+        // `table_scroll_area` for the wheel.
+        let fit = options.table_mode == TableMode::Fit;
+        let mut column = egui_extras::Column::auto().resizable(true).at_least(40.0);
+        if fit {
+            column = column
+                .at_most(fit_column_width(ui, max_width, num_cols))
+                .clip(true);
+        }
+        let mut scroll_out =
+            table_scroll_area(ui, id, max_width, options.table_mode, |ui| {
                 ui.vertical(|ui| {
                     egui::Frame::group(ui.style()).show(ui, |ui| {
                         let builder = egui_extras::TableBuilder::new(ui)
                             .id_salt(id)
-                            .striped(true)
+                            .striped(options.table_striped)
+                            .sense(table_row_sense(options))
                             .resizable(true)
                             .vscroll(false)
                             // Hug columns when narrower than the panel (#47); the
                             // outer ScrollArea still handles wide-table overflow.
                             .auto_shrink([true, true])
                             .min_scrolled_height(0.0)
-                            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-                            .columns(
-                                egui_extras::Column::auto().resizable(true).at_least(40.0),
-                                num_cols,
-                            );
-
-                        let render_cell_strong = |ui: &mut Ui, cell: &str| {
-                            egui::Frame::NONE
-                                .inner_margin(egui::Margin::symmetric(8, 4))
-                                .show(ui, |ui| {
-                                    ui.strong(cell);
-                                });
-                        };
+                            // Labels wrap in a top-down layout, which lets fitted
+                            // columns break long cells onto several lines.
+                            .cell_layout(if fit {
+                                egui::Layout::top_down(egui::Align::Min)
+                            } else {
+                                egui::Layout::left_to_right(egui::Align::Center)
+                            })
+                            .columns(column, num_cols);
 
                         if let Some(first_header) = table.header.first() {
                             builder
                                 .header(header_h, |mut row| {
-                                    for cell in first_header {
-                                        row.col(|ui| render_cell_strong(ui, cell));
+                                    for cell in padded(first_header) {
+                                        row.col(|ui| self.html_cell(ui, &cell, true, cache, options));
                                     }
                                 })
                                 .body(|mut body| {
@@ -1981,8 +3533,8 @@ impl CommonMarkViewerInternal {
                                             .copied()
                                             .unwrap_or(cell_h);
                                         body.row(h, |mut row_ui| {
-                                            for cell in extra {
-                                                row_ui.col(|ui| render_cell_strong(ui, cell));
+                                            for cell in padded(extra) {
+                                                row_ui.col(|ui| self.html_cell(ui, &cell, true, cache, options));
                                             }
                                         });
                                     }
@@ -1992,23 +3544,8 @@ impl CommonMarkViewerInternal {
                                             .copied()
                                             .unwrap_or(cell_h);
                                         body.row(h, |mut row_ui| {
-                                            for cell in row {
-                                                row_ui.col(|ui| {
-                                                    egui::Frame::NONE
-                                                        .inner_margin(egui::Margin::symmetric(
-                                                            8, 4,
-                                                        ))
-                                                        .show(ui, |ui| {
-                                                            let rich_text = self
-                                                                .text_style
-                                                                .to_richtext_with_options(
-                                                                    ui,
-                                                                    cell,
-                                                                    options,
-                                                                );
-                                                            ui.label(rich_text);
-                                                        });
-                                                });
+                                            for cell in padded(row) {
+                                                row_ui.col(|ui| self.html_cell(ui, &cell, false, cache, options));
                                             }
                                         });
                                     }
@@ -2021,21 +3558,8 @@ impl CommonMarkViewerInternal {
                                         .copied()
                                         .unwrap_or(cell_h);
                                     body.row(h, |mut row_ui| {
-                                        for cell in row {
-                                            row_ui.col(|ui| {
-                                                egui::Frame::NONE
-                                                    .inner_margin(egui::Margin::symmetric(8, 4))
-                                                    .show(ui, |ui| {
-                                                        let rich_text = self
-                                                            .text_style
-                                                            .to_richtext_with_options(
-                                                                ui,
-                                                                cell,
-                                                                options,
-                                                            );
-                                                        ui.label(rich_text);
-                                                    });
-                                            });
+                                        for cell in padded(row) {
+                                            row_ui.col(|ui| self.html_cell(ui, &cell, false, cache, options));
                                         }
                                     });
                                 }
@@ -2044,8 +3568,174 @@ impl CommonMarkViewerInternal {
                     });
                 });
             });
-        forward_shift_wheel_to_horizontal_scroll(ui, &mut scroll_out);
-        self.line.try_insert_end(ui);
+        forward_wheel_to_horizontal_scroll(ui, &mut scroll_out);
+    }
+
+    /// An HTML table with cells spanning several columns or rows, which
+    /// `TableBuilder` can't lay out. Columns are as wide as their widest cell
+    /// (up to the fitted width), and rows as tall as they were measured on the
+    /// previous frame, like the rows of markdown tables.
+    fn render_spanning_html_table(
+        &mut self,
+        ui: &mut Ui,
+        table: &HtmlTable,
+        id: Id,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        let (cells, num_cols) = table.place_cells();
+        let num_rows = table.header.len() + table.rows.len();
+        if num_cols == 0 || num_rows == 0 {
+            return;
+        }
+
+        let min_w = 40.0;
+        let min_h = ui.text_style_height(&egui::TextStyle::Body) * 1.5;
+        let padding = 2.0 * 8.0 + 2.0;
+        let max_col_w = match options.table_mode {
+            TableMode::Fit => fit_column_width(ui, max_width, num_cols),
+            TableMode::Scroll => max_width.max(min_w),
+        };
+
+        // Widths from the unwrapped text: single cells size their column,
+        // then spanning cells widen the columns they cover evenly.
+        let font = egui::TextStyle::Body.resolve(ui.style());
+        let text_w = |cell: &HtmlCell| {
+            ui.fonts_mut(|f| f.layout_no_wrap(cell.text.clone(), font.clone(), egui::Color32::WHITE))
+                .size()
+                .x
+                + padding
+        };
+        let mut widths = vec![min_w; num_cols];
+        for (_, col, cell) in cells.iter().filter(|(_, _, c)| c.colspan == 1) {
+            widths[*col] = widths[*col].max(text_w(cell).min(max_col_w));
+        }
+        for (_, col, cell) in cells.iter().filter(|(_, _, c)| c.colspan > 1) {
+            let span = *col..(*col + cell.colspan).min(num_cols);
+            let wanted = text_w(cell).min(max_col_w * span.len() as f32);
+            let missing = wanted - widths[span.clone()].iter().sum::<f32>();
+            if missing > 0.0 {
+                let share = missing / span.len() as f32;
+                widths[span].iter_mut().for_each(|w| *w += share);
+            }
+        }
+
+        let heights_id = id.with("_row_heights");
+        let heights: Vec<f32> = ui
+            .data(|d| d.get_temp::<Vec<f32>>(heights_id))
+            .filter(|h| h.len() == num_rows)
+            .unwrap_or_else(|| vec![min_h; num_rows]);
+
+        let offsets = |sizes: &[f32]| -> Vec<f32> {
+            std::iter::once(0.0)
+                .chain(sizes.iter().scan(0.0, |acc, s| {
+                    *acc += s;
+                    Some(*acc)
+                }))
+                .collect()
+        };
+        let xs = offsets(&widths);
+        let ys = offsets(&heights);
+
+        let mut used = vec![min_h; num_rows];
+        let mut spanning_used = Vec::new();
+        let mut scroll_out =
+            table_scroll_area(ui, id, max_width, options.table_mode, |ui| {
+                egui::Frame::group(ui.style()).show(ui, |ui| {
+                    let size = egui::vec2(xs[num_cols], ys[num_rows]);
+                    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+                    let visuals = ui.visuals().clone();
+                    if options.table_striped {
+                        for row in (table.header.len()..num_rows).skip(1).step_by(2) {
+                            let stripe = egui::Rect::from_x_y_ranges(
+                                rect.x_range(),
+                                rect.top() + ys[row]..=rect.top() + ys[row + 1],
+                            );
+                            ui.painter().rect_filled(stripe, 0.0, visuals.faint_bg_color);
+                        }
+                    }
+                    let stroke = visuals.widgets.noninteractive.bg_stroke;
+                    for (row, col, cell) in &cells {
+                        let (last_row, last_col) = (
+                            (row + cell.rowspan).min(num_rows),
+                            (col + cell.colspan).min(num_cols),
+                        );
+                        let cell_rect = egui::Rect::from_min_max(
+                            rect.min + egui::vec2(xs[*col], ys[*row]),
+                            rect.min + egui::vec2(xs[last_col], ys[last_row]),
+                        );
+                        ui.painter().rect_stroke(cell_rect, 0.0, stroke, egui::StrokeKind::Inside);
+                        let mut cell_ui = ui.new_child(
+                            egui::UiBuilder::new()
+                                .id_salt(("_cell", row, col))
+                                .max_rect(cell_rect)
+                                .layout(egui::Layout::top_down(egui::Align::Min)),
+                        );
+                        let header = *row < table.header.len();
+                        self.html_cell(&mut cell_ui, cell, header, cache, options);
+                        let height = cell_ui.min_rect().height();
+                        if last_row - row == 1 {
+                            used[*row] = used[*row].max(height);
+                        } else {
+                            spanning_used.push((*row..last_row, height));
+                        }
+                    }
+                });
+            });
+        forward_wheel_to_horizontal_scroll(ui, &mut scroll_out);
+
+        // Rows spanned by a cell taller than them grow at the bottom
+        for (span, height) in spanning_used {
+            let missing = height - used[span.clone()].iter().sum::<f32>();
+            if missing > 0.0 {
+                used[span.end - 1] += missing;
+            }
+        }
+        if used.iter().zip(&heights).any(|(u, h)| (u - h).abs() > 0.5) {
+            ui.data_mut(|d| d.insert_temp(heights_id, used));
+            ui.ctx().request_repaint();
+        }
+    }
+
+    /// The content of an HTML table cell, its inline formatting rendered like
+    /// the rest of the document.
+    fn html_cell(
+        &mut self,
+        ui: &mut Ui,
+        cell: &HtmlCell,
+        strong: bool,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) {
+        egui::Frame::NONE
+            .inner_margin(egui::Margin::symmetric(8, 4))
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    let width = ui.available_width();
+                    let was_strong = std::mem::replace(&mut self.text_style.strong, strong);
+                    let events = pulldown_cmark::Parser::new_ext(
+                        &cell.markdown,
+                        pulldown_cmark::Options::ENABLE_STRIKETHROUGH,
+                    );
+                    for event in events {
+                        if matches!(
+                            event,
+                            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Paragraph)
+                                | pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Paragraph)
+                        ) {
+                            continue;
+                        }
+                        let tmp_start =
+                            std::mem::replace(&mut self.line.should_start_newline, false);
+                        let tmp_end = std::mem::replace(&mut self.line.should_end_newline, false);
+                        self.event(ui, event, 0..0, cache, options, width);
+                        self.line.should_start_newline = tmp_start;
+                        self.line.should_end_newline = tmp_end;
+                    }
+                    self.text_style.strong = was_strong;
+                });
+            });
     }
 }
 
@@ -2054,6 +3744,15 @@ mod tests {
     use super::*;
     use pulldown_cmark::{Event, Options, Parser, Tag};
 
+    /// Key of a heading of a document shown without a source id.
+    fn header(slug: &str, occurrence: usize) -> HeaderKey {
+        HeaderKey {
+            source_id: Id::NULL,
+            occurrence,
+            slug: slug.to_owned(),
+        }
+    }
+
     // Snapshot scanner output so ranges and raw/rendered identities stay explicit.
     fn segment_snapshot(text: &str, start: usize) -> Vec<(String, Range<usize>, String, bool)> {
         let mut snapshots = Vec::new();
@@ -2108,6 +3807,13 @@ mod tests {
         visible
     }
 
+    #[test]
+    fn list_item_markdown_drops_the_marker() {
+        assert_eq!(item_markdown("- Write a haiku\n  about *rust*\n", 0), "Write a haiku\nabout *rust*");
+        assert_eq!(item_markdown("12. Step\n\n    ```\n    code\n    ```", 0), "Step\n\n```\ncode\n```");
+        assert_eq!(item_markdown("* nested\n    more", 2), "nested\nmore");
+    }
+
     #[test]
     fn no_colon_fast_path_borrows_original_text() {
         let text = "plain text only";
@@ -2289,9 +3995,89 @@ mod tests {
     }
 
     #[test]
-    fn emoji_expansion_eligibility_excludes_images_and_code_blocks() {
-        assert!(emoji_expansion_is_eligible(false, false));
-        assert!(!emoji_expansion_is_eligible(true, false));
+    fn source_ranges_split_text_and_later_ranges_win() {
+        let red = egui::Color32::RED;
+        let ranges = [
+            SourceRange::new(12..40),
+            SourceRange::new(0..14).color(red),
+        ];
+        let mut pieces = Vec::new();
+        visit_source_range_segments("hello world", &(10..21), &ranges, |piece, range| {
+            pieces.push((piece, range.and_then(|range| range.color)));
+        });
+        assert_eq!(pieces, [("hell", Some(red)), ("o world", None)]);
+
+        let mut pieces = Vec::new();
+        visit_source_range_segments("hello", &(0..5), &[SourceRange::new(9..12)], |piece, range| {
+            pieces.push((piece, range.is_some()));
+        });
+        assert_eq!(pieces, [("hello", false)]);
+    }
+
+    #[test]
+    fn highlight_ranges_give_way_to_search_matches() {
+        egui::__run_test_ui(|ui| {
+            let mut renderer = CommonMarkViewerInternal::new();
+            let mut cache = CommonMarkCache::default();
+            cache.set_search_ranges(std::iter::once(4..7).collect());
+            renderer.text_style.heading = Some(1);
+            let ranges = [SourceRange::new(0..10)];
+            let options = CommonMarkOptions {
+                highlight_ranges: &ranges,
+                ..Default::default()
+            };
+
+            let text = Event::Text("one two three".into());
+            renderer.event(ui, text, 0..13, &mut cache, &options, 540.0);
+
+            let texts: Vec<_> = renderer
+                .current_heading_rich_texts
+                .iter()
+                .map(|text| text.text().to_owned())
+                .collect();
+            assert_eq!(texts, ["one ", "two", " th", "ree"].map(str::to_owned));
+        });
+    }
+
+    #[test]
+    fn heading_numbers_go_in_front_of_their_headings_only() {
+        egui::__run_test_ui(|ui| {
+            let numbers = [(5, "1.2".to_owned())];
+            let options = CommonMarkOptions {
+                heading_numbers: &numbers,
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            let heading = || {
+                Event::Start(Tag::Heading {
+                    level: HeadingLevel::H2,
+                    id: None,
+                    classes: Vec::new(),
+                    attrs: Vec::new(),
+                })
+            };
+            for start in [0, 5] {
+                let mut renderer = CommonMarkViewerInternal::new();
+                renderer.event(ui, heading(), start..start + 4, &mut cache, &options, 540.0);
+                let text = Event::Text("B".into());
+                renderer.event(ui, text, start + 3..start + 4, &mut cache, &options, 540.0);
+                let texts: Vec<_> = renderer
+                    .current_heading_rich_texts
+                    .iter()
+                    .map(|text| text.text().to_owned())
+                    .collect();
+                let expected: &[&str] = if start == 5 { &["1.2 ", "B"] } else { &["B"] };
+                assert_eq!(texts, expected);
+                // The anchor is that of the heading's own text
+                assert_eq!(renderer.current_heading_text, "B");
+            }
+        });
+    }
+
+    #[test]
+    fn emoji_expansion_eligibility_excludes_images_and_code_blocks() {
+        assert!(emoji_expansion_is_eligible(false, false));
+        assert!(!emoji_expansion_is_eligible(true, false));
         assert!(!emoji_expansion_is_eligible(false, true));
         assert!(!emoji_expansion_is_eligible(true, true));
     }
@@ -2376,6 +4162,7 @@ mod tests {
                         classes: Vec::new(),
                         attrs: Vec::new(),
                     },
+                    0,
                     &options,
                 );
                 renderer.event(
@@ -2395,10 +4182,10 @@ mod tests {
                 );
             }
 
-            assert!(cache.get_header_position("pin :pushpin:").is_some());
-            assert!(cache.get_header_position("pin :pushpin:#1").is_some());
-            assert!(cache.get_header_position("pin 📌").is_none());
-            assert!(cache.get_header_position("pin 📌#1").is_none());
+            assert!(cache.get_header_position(&header("pin-pushpin", 0)).is_some());
+            assert!(cache.get_header_position(&header("pin-pushpin", 1)).is_some());
+            assert!(cache.get_header_position(&header("pin-", 0)).is_none());
+            assert!(cache.get_header_position(&header("pin-", 1)).is_none());
         });
     }
 
@@ -2411,9 +4198,813 @@ mod tests {
         );
     }
 
+    #[test]
+    fn line_cursor_counts_lines_incrementally() {
+        let text = "a\nb\n\nc\n";
+        let mut cursor = LineCursor::new();
+        assert_eq!(cursor.line_at(text, 0), 1);
+        assert_eq!(cursor.line_at(text, 2), 2);
+        assert_eq!(cursor.line_at(text, 5), 4);
+        // Going backwards restarts from the top instead of under-counting.
+        assert_eq!(cursor.line_at(text, 2), 2);
+    }
+
+    #[test]
+    fn show_records_top_level_source_blocks_in_order() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "# Title\n\nParagraph\n\n- one\n- two\n\n> quote\n> more\n\n---\n";
+            let mut cache = CommonMarkCache::default();
+            // A second document sharing the cache keeps its own blocks
+            for (source, text) in [("doc", markdown), ("other", "# Other\n")] {
+                CommonMarkViewerInternal::new().show_scrollable(
+                    Id::new(source),
+                    ui,
+                    &mut cache,
+                    &CommonMarkOptions::default(),
+                    text,
+                    None,
+                    None,
+                    None,
+                );
+            }
+            assert_eq!(cache.source_blocks("other").len(), 1);
+
+            let blocks = cache.source_blocks("doc");
+            let lines: Vec<usize> = blocks.iter().map(|b| b.line).collect();
+            assert_eq!(lines, vec![1, 3, 5, 8, 11]);
+            assert_eq!(&markdown[blocks[1].byte_range.clone()], "Paragraph\n");
+            let end_lines: Vec<usize> = blocks.iter().map(|b| b.end_line).collect();
+            assert_eq!(end_lines, vec![1, 3, 6, 9, 11]);
+            assert!(blocks.iter().all(|b| b.y_range.min >= 0.0));
+            assert!(blocks.windows(2).all(|w| w[0].y_range.max == w[1].y_range.min));
+        });
+    }
+
+    #[test]
+    fn links_take_keyboard_focus_and_open_on_enter() {
+        let ctx = egui::Context::default();
+        let mut cache = CommonMarkCache::default();
+        cache.add_link_hook("#a");
+        cache.add_link_hook("#b");
+        let mut frame = |key: Option<egui::Key>, shift: bool| {
+            let mut input = egui::RawInput::default();
+            if let Some(key) = key {
+                input.events.push(egui::Event::Key {
+                    key,
+                    physical_key: None,
+                    pressed: true,
+                    repeat: false,
+                    modifiers: egui::Modifiers {
+                        shift,
+                        ..Default::default()
+                    },
+                });
+            }
+            let mut links = (None, None);
+            let _ = ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    let response =
+                        crate::CommonMarkViewer::new().show(ui, &mut cache, "[one](#a) [two](#b)\n");
+                    let destination = |link: Option<LinkInfo>| link.map(|link| link.destination);
+                    links = (
+                        destination(response.hovered_link),
+                        destination(response.clicked_link),
+                    );
+                });
+            });
+            links
+        };
+        let focused = |link: &str| (Some(link.to_owned()), None);
+
+        assert_eq!(frame(None, false), (None, None));
+        assert_eq!(frame(Some(egui::Key::Tab), false), focused("#a"));
+        assert_eq!(frame(Some(egui::Key::Tab), false), focused("#b"));
+        assert_eq!(frame(None, false), focused("#b"));
+        // Going back lands on a link painted earlier, so it shows a frame later
+        assert_eq!(frame(Some(egui::Key::Tab), true), focused("#b"));
+        assert_eq!(
+            frame(Some(egui::Key::Enter), false),
+            (Some("#a".to_owned()), Some("#a".to_owned()))
+        );
+    }
+
+    #[test]
+    fn hovered_headings_copy_a_link_to_themselves() {
+        let ctx = egui::Context::default();
+        let mut cache = CommonMarkCache::default();
+        let mut frame = |events: Vec<egui::Event>| {
+            let input = egui::RawInput {
+                events,
+                ..Default::default()
+            };
+            let mut rects = Vec::new();
+            let output = ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    let response = crate::CommonMarkViewer::new()
+                        .heading_link_button(Some("notes.md"))
+                        .show(ui, &mut cache, "## Intro\n\n## Intro\n");
+                    rects = response.heading_rects;
+                });
+            });
+            let copied = output.platform_output.commands.iter().find_map(|command| match command {
+                egui::OutputCommand::CopyText(text) => Some(text.clone()),
+                _ => None,
+            });
+            (rects, copied)
+        };
+        let pointer = |pos: egui::Pos2, pressed: Option<bool>| {
+            let mut events = vec![egui::Event::PointerMoved(pos)];
+            if let Some(pressed) = pressed {
+                events.push(egui::Event::PointerButton {
+                    pos,
+                    button: egui::PointerButton::Primary,
+                    pressed,
+                    modifiers: Default::default(),
+                });
+            }
+            events
+        };
+
+        let (rects, _) = frame(Vec::new());
+        let rect = rects[1].1;
+        let button = egui::pos2(rect.right() + 12.0, rect.top() + 6.0);
+        frame(pointer(rect.center(), None));
+        frame(pointer(button, None));
+        frame(pointer(button, Some(true)));
+        let (_, copied) = frame(pointer(button, Some(false)));
+        assert_eq!(copied.as_deref(), Some("notes.md#intro-1"));
+    }
+
+    #[test]
+    fn scroll_to_heading_resolves_github_anchor() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "# Intro\n\ntext\n\n## Intro\n\nmore\n";
+            let source_id = Id::new("doc");
+            let mut cache = CommonMarkCache::default();
+            cache.scroll_to_heading("doc", "#intro-1");
+            for _ in 0..2 {
+                CommonMarkViewerInternal::new().show_scrollable(
+                    source_id,
+                    ui,
+                    &mut cache,
+                    &CommonMarkOptions::default(),
+                    markdown,
+                    None,
+                    None,
+                    None,
+                );
+            }
+
+            assert!(cache.anchor_position("doc", "intro").is_some());
+            assert!(cache.anchor_position("doc", "#intro-1").is_some());
+            // Consumed once the heading was laid out.
+            assert!(cache.take_heading_scroll(source_id).is_none());
+
+            cache.scroll_to_heading("doc", "missing");
+            assert!(cache.take_heading_scroll(source_id).is_none());
+            assert!(cache.take_heading_scroll(source_id).is_none());
+            assert!(scroll_cache(&mut cache, &source_id).pending_anchor.is_none());
+        });
+    }
+
+    #[test]
+    fn header_positions_are_kept_per_viewer_and_text() {
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            let show = |ui: &mut Ui, cache: &mut CommonMarkCache, source: &str, markdown: &str| {
+                CommonMarkViewerInternal::new().show_scrollable(
+                    Id::new(source),
+                    ui,
+                    cache,
+                    &CommonMarkOptions::default(),
+                    markdown,
+                    None,
+                    None,
+                    None,
+                );
+            };
+            show(ui, &mut cache, "a", "# Setup\n\n## Setup\n");
+            show(ui, &mut cache, "b", "text\n\n# Setup!\n");
+
+            let a = cache.get_header_position(&HeaderKey::new("a", 1, "setup"));
+            let b = cache.get_header_position(&HeaderKey::new("b", 0, "setup"));
+            assert!(a.is_some() && b.is_some());
+            assert!(cache.get_header_position(&HeaderKey::new("b", 1, "setup")).is_none());
+            assert_ne!(a, b);
+
+            // Editing one document forgets its stale positions only.
+            show(ui, &mut cache, "b", "# Other\n");
+            assert!(cache.get_header_position(&HeaderKey::new("b", 0, "setup")).is_none());
+            assert!(cache.get_header_position(&HeaderKey::new("b", 0, "other")).is_some());
+            assert_eq!(cache.get_header_position(&HeaderKey::new("a", 1, "setup")), a);
+        });
+    }
+
+    #[test]
+    fn rendered_headings_skip_comments_in_code_blocks() {
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            let markdown = "# Install *it*\n\n```sh\n# not a heading\n```\n\n### Run\n";
+            CommonMarkViewerInternal::new().show_scrollable(
+                Id::new("doc"),
+                ui,
+                &mut cache,
+                &CommonMarkOptions::default(),
+                markdown,
+                None,
+                None,
+                None,
+            );
+
+            let headings = cache.headings("doc");
+            let summary: Vec<_> = headings
+                .iter()
+                .map(|heading| (heading.level, heading.text.as_str(), heading.key.slug.as_str()))
+                .collect();
+            assert_eq!(summary, [(1, "Install it", "install-it"), (3, "Run", "run")]);
+            assert!(headings[0].y_range.max <= headings[1].y_range.min);
+            assert_eq!(
+                cache.get_header_position(&headings[1].key),
+                Some(headings[1].y_range.min)
+            );
+            assert!(cache.headings("other").is_empty());
+        });
+    }
+
+    #[test]
+    fn rendered_tables_keep_the_text_of_their_cells() {
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            let markdown = "| *Name* | Size |\n|:--|--:|\n| `a.md` | 2 |\n\n| One |\n|---|\n| x |\n";
+            CommonMarkViewerInternal::new().show_scrollable(
+                Id::new("doc"),
+                ui,
+                &mut cache,
+                &CommonMarkOptions::default(),
+                markdown,
+                None,
+                None,
+                None,
+            );
+
+            let tables = cache.tables("doc");
+            assert_eq!(tables.len(), 2);
+            assert_eq!(tables[0].cells.to_csv(), "Name,Size\na.md,2\n");
+            assert_eq!(
+                tables[0].cells.to_markdown(),
+                "| Name | Size |\n| :--- | ---: |\n| a.md | 2 |\n"
+            );
+            assert_eq!(tables[1].index, 1);
+            assert_eq!(tables[1].cells.rows, [["x"]]);
+            assert!(cache.tables("other").is_empty());
+        });
+    }
+
+    #[test]
+    fn html_tables_with_spans_render() {
+        let markdown = "<table>\n<tr><th colspan=\"2\">Both <em>columns</em></th></tr>\n\
+                        <tr><td rowspan=\"5\">Tall</td><td><a href=\"x.md\">link</a><br>next</td></tr>\n\
+                        <tr><td><code>b</code></td></tr>\n</table>\n";
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            // Twice, the second frame using the row heights measured by the first
+            for _ in 0..2 {
+                CommonMarkViewerInternal::new().show_scrollable(
+                    Id::new("doc"),
+                    ui,
+                    &mut cache,
+                    &CommonMarkOptions::default(),
+                    markdown,
+                    None,
+                    None,
+                    None,
+                );
+            }
+            // A panicking cell would have left an error placeholder instead
+            assert_eq!(cache.render_errors().count(), 0);
+            let blocks = cache.source_blocks("doc");
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].byte_range, 0..markdown.len());
+            assert_eq!((blocks[0].line, blocks[0].end_line), (1, 5));
+            // The header, the cell broken by <br> and the row below it
+            let row_height = ui.text_style_height(&egui::TextStyle::Body);
+            assert!(blocks[0].y_range.span() >= 4.0 * row_height);
+        });
+    }
+
+    #[test]
+    fn superscript_subscript_parsing_is_opt_in() {
+        let tags = |options: &CommonMarkOptions| {
+            Parser::new_ext("note ^sup^ and ~sub~", parser_options_for(options))
+                .filter_map(|e| match e {
+                    Event::Start(tag) => Some(tag.to_end()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut options = CommonMarkOptions::default();
+        assert!(!tags(&options).contains(&pulldown_cmark::TagEnd::Superscript));
+
+        options.superscript_subscript = true;
+        let enabled = tags(&options);
+        assert!(enabled.contains(&pulldown_cmark::TagEnd::Superscript));
+        assert!(enabled.contains(&pulldown_cmark::TagEnd::Subscript));
+    }
+
+    #[test]
+    fn disabled_extensions_are_shown_as_written() {
+        let source = "| a |\n|---|\n| b |\n\n- [x] ~~done~~[^1]\n\n[^1]: note\n";
+        let tags = |options: &CommonMarkOptions| {
+            Parser::new_ext(source, parser_options_for(options))
+                .filter_map(|e| match e {
+                    Event::Start(tag) => Some(tag.to_end()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut options = CommonMarkOptions::default();
+        let enabled = tags(&options);
+        for tag in [
+            pulldown_cmark::TagEnd::Table,
+            pulldown_cmark::TagEnd::Strikethrough,
+            pulldown_cmark::TagEnd::FootnoteDefinition,
+        ] {
+            assert!(enabled.contains(&tag), "{tag:?}");
+        }
+
+        options.extensions = MarkdownExtensions::none();
+        let disabled = tags(&options);
+        for tag in [
+            pulldown_cmark::TagEnd::Table,
+            pulldown_cmark::TagEnd::Strikethrough,
+            pulldown_cmark::TagEnd::FootnoteDefinition,
+        ] {
+            assert!(!disabled.contains(&tag), "{tag:?}");
+        }
+        let markers = |options: &CommonMarkOptions| {
+            Parser::new_ext(source, parser_options_for(options))
+                .filter(|e| matches!(e, Event::TaskListMarker(_)))
+                .count()
+        };
+        assert_eq!(markers(&options), 0);
+        assert_eq!(markers(&CommonMarkOptions::default()), 1);
+    }
+
+    #[test]
+    fn inline_sup_and_sub_tags_toggle_text_style() {
+        let mut renderer = CommonMarkViewerInternal::new();
+        assert!(renderer.inline_html_script_tag("<SUP>"));
+        assert!(renderer.text_style.superscript);
+        assert!(renderer.inline_html_script_tag("</sup>"));
+        assert!(!renderer.text_style.superscript);
+        assert!(renderer.inline_html_script_tag("<sub>"));
+        assert!(renderer.text_style.subscript);
+        assert!(!renderer.inline_html_script_tag("<kbd>"));
+    }
+
+    #[test]
+    fn details_block_renders_as_collapsing_section() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "<details>\n<summary>More</summary>\n\n- a\n- b\n\n</details>\n\n# After\n";
+            let mut renderer = CommonMarkViewerInternal::new();
+            let mut cache = CommonMarkCache::default();
+            renderer.show(ui, &mut cache, &CommonMarkOptions::default(), markdown, None);
+
+            assert_eq!(renderer.curr_details, 1);
+            assert!(renderer.details.is_none());
+            // The closing block is consumed with the body, so the heading after
+            // it still renders at top level.
+            assert!(cache.get_header_position(&header("after", 0)).is_some());
+        });
+    }
+
+    #[test]
+    fn collapsed_alerts_hide_their_body_until_opened() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "> [!NOTE]\n> # Inside\n";
+            let inside = header("inside", 0);
+            let mut options = CommonMarkOptions::default();
+            options.alerts.insert(
+                crate::Alert::new("NOTE", "Note", egui::Color32::BLUE).collapsible(false),
+            );
+            let mut cache = CommonMarkCache::default();
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+            assert!(cache.get_header_position(&inside).is_none());
+
+            // The quote's text starts after "> "
+            let id = Id::NULL.with("_alert").with(Some(2));
+            assert!(!cache.alert_open(id, false));
+            cache.set_alert_open(id, true);
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+            assert!(cache.get_header_position(&inside).is_some());
+        });
+    }
+
+    #[test]
+    fn quotes_nested_too_deep_collapse_until_shown() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "> Reply\n>\n> > # Earlier\n";
+            let earlier = header("earlier", 0);
+            let options = CommonMarkOptions {
+                collapse_quotes_deeper_than: Some(1),
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+            assert!(cache.get_header_position(&earlier).is_none());
+
+            // The nested quote starts at its own "> " on the third line
+            let id = Id::NULL.with("_quote").with(Some(12));
+            cache.set_alert_open(id, true);
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+            assert!(cache.get_header_position(&earlier).is_some());
+        });
+    }
+
+    #[test]
+    fn search_matches_in_alt_text_titles_and_info_strings_are_recorded() {
+        let markdown = "![architecture diagram](a.png)\n\n[docs](d.html \"User guide\")\n\n```rust\nfn main() {}\n```\n";
+        for query in ["diagram", "guide", "rust"] {
+            egui::__run_test_ui(|ui| {
+                let at = markdown.find(query).unwrap();
+                let mut cache = CommonMarkCache::default();
+                cache.set_search_ranges(vec![at..at + query.len()]);
+                cache.set_active_search_range(Some(at..at + query.len()));
+                CommonMarkViewerInternal::new().show(
+                    ui,
+                    &mut cache,
+                    &CommonMarkOptions::default(),
+                    markdown,
+                    None,
+                );
+                assert!(cache.active_search_y().is_some(), "{query}");
+            });
+        }
+    }
+
+    #[test]
+    fn search_matches_inside_code_blocks_are_recorded() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "- item\n\n  ```\n  let x = 1;\n  find(me)\n  ```\n";
+            let at = markdown.find("me)").unwrap();
+            let mut cache = CommonMarkCache::default();
+            cache.set_search_ranges(vec![at..at + 2]);
+            cache.set_active_search_range(Some(at..at + 2));
+            CommonMarkViewerInternal::new().show(
+                ui,
+                &mut cache,
+                &CommonMarkOptions::default(),
+                markdown,
+                None,
+            );
+            assert!(cache.active_search_y().is_some());
+        });
+    }
+
+    #[test]
+    fn math_macros_and_equation_references_are_resolved() {
+        let math_fn = |_: &mut Ui, _: &str, _: bool| {};
+        egui::__run_test_ui(|ui| {
+            let markdown = "---\nmacros:\n  R: \\mathbb{R}\n---\n\n\
+                            # See \\eqref{eq:r}\n\n$$\\R^n \\label{eq:r}$$\n";
+            let options = CommonMarkOptions {
+                math_fn: Some(&math_fn),
+                math_macros: true,
+                equation_numbers: true,
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            let mut renderer = CommonMarkViewerInternal::new();
+            renderer.show(ui, &mut cache, &options, markdown, None);
+            // Checked on the document itself, the `math` feature renders
+            // formulas without calling `math_fn`
+            assert_eq!(renderer.math.formula("\\R^n \\label{eq:r}"), "\\mathbb{R}^n ");
+            assert_eq!(renderer.math.number(markdown.find("$$").unwrap()), Some("1"));
+            assert!(cache.get_header_position(&header("see-1", 0)).is_some());
+        });
+    }
+
+    #[test]
+    fn a_panicking_renderer_only_takes_its_part_down() {
+        static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let is_diagram = |lang: &str| lang == "plantuml";
+        let render = |_: &mut Ui, _: &str, _: &str| -> bool {
+            CALLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            panic!("no diagrams today");
+        };
+        egui::__run_test_ui(|ui| {
+            let block = "```plantuml\nA -> B\n```\n";
+            let markdown = format!("# Before\n\n{block}\n# After\n");
+            let options = CommonMarkOptions {
+                code_block_fns: vec![(&is_diagram, &render)],
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            let mut renderer = CommonMarkViewerInternal::new();
+            renderer.show(ui, &mut cache, &options, &markdown, None);
+            assert!(cache.get_header_position(&header("after", 0)).is_some());
+            let start = markdown.find(block).unwrap();
+            let error = RenderError {
+                byte_range: start..start + block.trim_end().len(),
+                message: "no diagrams today".to_owned(),
+            };
+            assert_eq!(renderer.render_errors, [error.clone()]);
+            assert_eq!(cache.render_errors().collect::<Vec<_>>(), [&error]);
+
+            // Later frames show the error without trying again
+            let mut renderer = CommonMarkViewerInternal::new();
+            renderer.show(ui, &mut cache, &options, &markdown, None);
+            assert_eq!(renderer.render_errors, [error]);
+            assert_eq!(CALLS.load(std::sync::atomic::Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn footnotes_are_numbered_by_reference_and_rendered_last() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "[^b]: Bee.\n\nSee[^a] and[^b], again[^a].\n\n[^a]: Ay.\n\n# After\n";
+            let mut renderer = CommonMarkViewerInternal::new();
+            let mut cache = CommonMarkCache::default();
+            renderer.show(ui, &mut cache, &CommonMarkOptions::default(), markdown, None);
+
+            assert_eq!(
+                renderer.footnote_refs,
+                vec![("a".to_owned(), 2), ("b".to_owned(), 1)]
+            );
+            assert!(renderer.footnote_definitions.is_empty());
+
+            // Anchors are kept by the id the document is shown with
+            for _ in 0..2 {
+                CommonMarkViewerInternal::new().show_scrollable(
+                    Id::new("doc"),
+                    ui,
+                    &mut cache,
+                    &CommonMarkOptions::default(),
+                    markdown,
+                    None,
+                    None,
+                    None,
+                );
+            }
+            for anchor in ["fnref-a", "fnref-a-2", "fnref-b", "fn-a", "fn-b"] {
+                assert!(cache.anchor_position("doc", anchor).is_some(), "{anchor}");
+            }
+            // Definitions come after everything else, including the heading.
+            let after = cache.get_header_position(&HeaderKey::new("doc", 0, "after")).unwrap();
+            let anchor = |anchor: &str| cache.anchor_position("doc", anchor);
+            assert!(anchor("fn-a").unwrap() > after);
+            assert!(anchor("fn-b") >= anchor("fn-a"));
+        });
+    }
+
+    #[test]
+    fn task_list_progress_is_opt_in() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "- [x] a\n- [ ] b\n  - [x] c\n";
+            let mut cache = CommonMarkCache::default();
+            let mut options = CommonMarkOptions::default();
+            let mut renderer = CommonMarkViewerInternal::new();
+            renderer.show(ui, &mut cache, &options, markdown, None);
+            assert!(renderer.task_progress.is_empty());
+
+            options.task_list_progress = true;
+            let mut renderer = CommonMarkViewerInternal::new();
+            renderer.show(ui, &mut cache, &options, markdown, None);
+            assert_eq!(renderer.task_progress.get(&0), Some(&(1, 2)));
+            assert_eq!(renderer.task_progress.len(), 2);
+        });
+    }
+
+    #[test]
+    fn nested_blockquotes_stay_inside_their_parent() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "> outer\n> > inner\n> > > innermost\n>\n> outer again\n\n# After\n";
+            let mut renderer = CommonMarkViewerInternal::new();
+            let mut cache = CommonMarkCache::default();
+            let options = CommonMarkOptions {
+                blockquote_colors: vec![egui::Color32::RED, egui::Color32::BLUE],
+                ..Default::default()
+            };
+            renderer.show(ui, &mut cache, &options, markdown, None);
+
+            assert!(!renderer.is_blockquote);
+            assert!(!renderer.text_style.quote);
+            assert!(cache.get_header_position(&header("after", 0)).is_some());
+            assert_eq!(options.blockquote_color(ui, 2), egui::Color32::RED);
+        });
+    }
+
+    #[test]
+    fn render_hooks_replace_default_rendering() {
+        use egui_commonmark_backend_extended::hooks::CommonMarkRenderHooks;
+        use std::cell::RefCell;
+
+        #[derive(Default)]
+        struct Recorder(RefCell<Vec<String>>);
+
+        impl CommonMarkRenderHooks for Recorder {
+            fn heading(&self, _ui: &mut Ui, level: u8, text: &str) -> bool {
+                self.0.borrow_mut().push(format!("h{level} {text}"));
+                true
+            }
+            fn paragraph(&self, _ui: &mut Ui, text: &str) -> bool {
+                self.0.borrow_mut().push(format!("p {text}"));
+                // Declined, so the link inside is still offered.
+                false
+            }
+            fn link(&self, _ui: &mut Ui, destination: &str, title: &str, text: &str) -> bool {
+                self.0.borrow_mut().push(format!("a {destination} {title} {text}"));
+                true
+            }
+            fn code_block(&self, _ui: &mut Ui, lang: &str, code: &str) -> bool {
+                self.0.borrow_mut().push(format!("code {lang} {}", code.trim()));
+                true
+            }
+            fn table(&self, _ui: &mut Ui, header: &[String], rows: &[Vec<String>]) -> bool {
+                self.0.borrow_mut().push(format!("table {header:?} {rows:?}"));
+                true
+            }
+        }
+
+        egui::__run_test_ui(|ui| {
+            let markdown = "## Title\n\nSee [docs](d.md \"Docs\").\n\n```rs\nfn f() {}\n```\n\n| a |\n|---|\n| 1 |\n";
+            let recorder = Recorder::default();
+            let options = CommonMarkOptions {
+                render_hooks: Some(&recorder),
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+
+            assert_eq!(
+                recorder.0.into_inner(),
+                vec![
+                    "h2 Title",
+                    "p See docs.",
+                    "a d.md Docs docs",
+                    "code rs fn f() {}",
+                    "table [\"a\"] [[\"1\"]]",
+                ]
+            );
+            // Hooked headings still record their scroll position.
+            assert!(cache.get_header_position(&header("title", 0)).is_some());
+        });
+    }
+
+    #[test]
+    fn code_block_fns_render_matching_fences() {
+        egui::__run_test_ui(|ui| {
+            let markdown =
+                "```plantuml title=\"seq\"\nA -> B\n```\n\n```vega\n{}\n```\n\n```rs\nfn f() {}\n```\n";
+            let rendered = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+            let sink = rendered.clone();
+            let is_diagram = |lang: &str| lang == "plantuml" || lang == "vega";
+            // Declines vega, which falls through to the default code block.
+            let render = move |_: &mut Ui, lang: &str, code: &str| {
+                sink.borrow_mut().push(format!("{lang} {}", code.trim()));
+                lang == "plantuml"
+            };
+            let options = CommonMarkOptions {
+                code_block_fns: vec![(&is_diagram, &render)],
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+
+            assert_eq!(*rendered.borrow(), vec!["plantuml A -> B", "vega {}"]);
+        });
+    }
+
+    #[test]
+    fn code_block_footers_follow_default_blocks_only() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "```sh\necho hi\n```\n\n```plantuml\nA -> B\n```\n\n    indented\n";
+            let footers = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+            let sink = footers.clone();
+            let footer = move |_: &mut Ui, lang: &str, code: &str, id: Id| {
+                sink.borrow_mut().push((format!("{lang}:{}", code.trim()), id));
+            };
+            let is_diagram = |lang: &str| lang == "plantuml";
+            let render = |_: &mut Ui, _: &str, _: &str| true;
+            let options = CommonMarkOptions {
+                code_block_fns: vec![(&is_diagram, &render)],
+                code_block_footer_fn: Some(&footer),
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+
+            let footers = footers.borrow();
+            let langs: Vec<_> = footers.iter().map(|(lang, _)| lang.as_str()).collect();
+            assert_eq!(langs, vec!["sh:echo hi", ":indented"]);
+            assert_ne!(footers[0].1, footers[1].1);
+        });
+    }
+
+    #[test]
+    fn csv_fences_render_as_tables_when_enabled() {
+        egui::__run_test_ui(|ui| {
+            let markdown = "```csv\na,b\n1,2\n```\n\n```tsv\na\tb\n```\n";
+            for csv_tables in [false, true] {
+                let options = CommonMarkOptions {
+                    csv_tables,
+                    ..Default::default()
+                };
+                let mut cache = CommonMarkCache::default();
+                let mut renderer = CommonMarkViewerInternal::new();
+                renderer.show(ui, &mut cache, &options, markdown, None);
+                assert_eq!(renderer.curr_table, if csv_tables { 2 } else { 0 });
+            }
+        });
+    }
+
+    #[test]
+    fn failed_images_are_recorded_and_rendered_with_the_error_fn() {
+        egui::__run_test_ui(|ui| {
+            // No image loaders are installed in the test context, so every
+            // uri fails with "no matching loader".
+            let markdown = "![missing *pic*](pic.png)\n";
+            let rendered = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+            let sink = rendered.clone();
+            let error_fn = move |_: &mut Ui, uri: &str, alt: &str, _: &str| {
+                sink.borrow_mut().push((uri.to_owned(), alt.to_owned()));
+            };
+            let options = CommonMarkOptions {
+                image_error_fn: Some(&error_fn),
+                ..Default::default()
+            };
+            let mut cache = CommonMarkCache::default();
+            CommonMarkViewerInternal::new().show(ui, &mut cache, &options, markdown, None);
+
+            assert_eq!(
+                *rendered.borrow(),
+                vec![("file://pic.png".to_owned(), "missing pic".to_owned())]
+            );
+            let failed: Vec<_> = cache.failed_images().map(|(uri, _)| uri).collect();
+            assert_eq!(failed, vec!["file://pic.png"]);
+            cache.clear_failed_images();
+            assert_eq!(cache.failed_images().count(), 0);
+        });
+    }
+
+    #[test]
+    fn aligned_cells_indent_by_the_remembered_width() {
+        egui::__run_test_ui(|ui| {
+            let id = Id::new("cell");
+            let mut lefts = Vec::new();
+            for _ in 0..2 {
+                ui.scope(|ui| {
+                    ui.horizontal(|ui| {
+                        aligned_cell(ui, pulldown_cmark::Alignment::Right, id, |ui| {
+                            let (rect, _) = ui.allocate_exact_size(
+                                egui::vec2(10.0, 10.0),
+                                egui::Sense::hover(),
+                            );
+                            lefts.push(rect.left() - ui.max_rect().left());
+                        });
+                    });
+                });
+            }
+            // The first pass only measures; the second pushes the contents
+            // to the right edge.
+            assert_eq!(lefts[0], 0.0);
+            assert!(lefts[1] > lefts[0]);
+            assert_eq!(ui.data(|d| d.get_temp::<f32>(id)), Some(10.0));
+        });
+    }
+
+    #[test]
+    fn fitted_columns_share_the_viewer_width() {
+        egui::__run_test_ui(|ui| {
+            let width = fit_column_width(ui, 400.0, 4);
+            let spacing = ui.spacing().item_spacing.x * 3.0;
+            assert!(width * 4.0 + spacing < 400.0);
+            // Columns never collapse below the table's minimum width.
+            assert_eq!(fit_column_width(ui, 10.0, 4), 40.0);
+        });
+    }
+
     #[test]
     fn image_alt_text_and_url_remain_literal() {
         let markdown = "![:pushpin:](https://e/:rocket:.png)";
         assert_eq!(expanded_visible_text(markdown), ":pushpin:");
     }
+
+    #[test]
+    fn figures_and_emphasized_captions_are_recognized() {
+        fn body(markdown: &str) -> Vec<(pulldown_cmark::Event<'_>, Range<usize>)> {
+            pulldown_cmark::Parser::new(markdown)
+                .into_offset_iter()
+                .skip(1)
+                .collect()
+        }
+        assert!(is_image_only(&body("![a](x.png)")));
+        assert!(!is_image_only(&body("![a](x.png) and text")));
+        assert!(!is_image_only(&body("text ![a](x.png)")));
+
+        assert!(is_emphasis_only(&body("*Figure 1: `x` in **bold***")));
+        assert!(!is_emphasis_only(&body("*one* and *two*")));
+        assert!(!is_emphasis_only(&body("*one* trailing")));
+        assert!(!is_emphasis_only(&body("plain")));
+    }
 }
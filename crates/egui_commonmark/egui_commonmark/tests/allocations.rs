@@ -0,0 +1,61 @@
+//! Allocator pressure of repainting an unchanged document. Counted with a
+//! global allocator, so these tests live in their own binary.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use egui::Context;
+use egui_commonmark_extended::{CommonMarkCache, CommonMarkViewer};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Allocations made while showing `markdown`, once the caches have settled.
+fn allocations_per_frame(markdown: &str) -> usize {
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut allocations = 0;
+    for _ in 0..4 {
+        ctx.begin_pass(Default::default());
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            CommonMarkViewer::new().show(ui, &mut cache, markdown);
+            allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        });
+        let _ = ctx.end_pass();
+    }
+    allocations
+}
+
+#[test]
+fn repainting_does_not_copy_the_document_text() {
+    // Short runs fit in pulldown-cmark's inline strings, long ones don't; a
+    // frame that copied the parsed text would allocate once more per run.
+    let document = |run: &str| {
+        format!("Some {run} text, a [{run} link](https://example.com/{run}) and **{run}**.\n\n")
+            .repeat(100)
+    };
+    let short = allocations_per_frame(&document("short"));
+    let long = allocations_per_frame(&document(&"long".repeat(8)));
+    assert_eq!(short, long);
+}
@@ -4,7 +4,9 @@
 //! `inline_code_wrap_segments`.
 
 use egui::{Context, Rect, Shape, TextStyle};
-use egui_commonmark_extended::{CommonMarkCache, CommonMarkViewer};
+use egui_commonmark_extended::{
+    CommonMarkCache, CommonMarkRenderHooks, CommonMarkViewer, LinkStyle, TextDirection,
+};
 
 #[derive(Debug)]
 struct PaintedText {
@@ -298,3 +300,589 @@ fn top_level_code_block_layout_remains_separate() {
     // This control is already green before the fix and protects the list-only gate.
     assert_vertical_order(&painted, &["TOP_BEFORE", "TOP_CODE", "TOP_AFTER"]);
 }
+
+fn long_document() -> String {
+    let mut markdown = String::new();
+    for i in 0..40 {
+        markdown.push_str(&format!("## Section {i}\n\npara-{i} has a footnote[^n{i}].\n\n"));
+        match i % 4 {
+            0 => markdown.push_str(&format!("- item-{i}\n  - nested-{i}\n\n")),
+            1 => markdown.push_str(&format!("```rust\nlet code_{i} = {i};\n```\n\n")),
+            2 => markdown.push_str(&format!("| a | b |\n|---|---|\n| cell-{i} | x |\n\n")),
+            _ => markdown.push_str(&format!("> quote-{i}\n\n")),
+        }
+    }
+    for i in 0..40 {
+        markdown.push_str(&format!("[^n{i}]: note-{i}\n"));
+    }
+    markdown
+}
+
+/// Counts the headings and code blocks laid out, leaving their rendering to
+/// the viewer.
+#[derive(Default)]
+struct LayoutCounter {
+    headings: std::cell::Cell<usize>,
+    code_blocks: std::cell::Cell<usize>,
+}
+
+impl CommonMarkRenderHooks for LayoutCounter {
+    fn heading(&self, _ui: &mut egui::Ui, _level: u8, _text: &str) -> bool {
+        self.headings.set(self.headings.get() + 1);
+        false
+    }
+
+    fn code_block(&self, _ui: &mut egui::Ui, _lang: &str, _code: &str) -> bool {
+        self.code_blocks.set(self.code_blocks.get() + 1);
+        false
+    }
+}
+
+struct Frame {
+    painted: Vec<PaintedText>,
+    headings: usize,
+    code_blocks: usize,
+}
+
+/// Paint `markdown` with `show_scrollable` in a 400 px high window, one frame
+/// per entry of `offsets` (`None` keeps the current scroll).
+fn scrollable_frames(markdown: &str, offsets: &[Option<f32>]) -> Vec<Frame> {
+    let steps: Vec<_> = offsets.iter().map(|offset| (markdown, *offset)).collect();
+    edited_frames(&steps, |_, _| {})
+}
+
+/// Like [`scrollable_frames`], with the text given per frame and `before`
+/// called with each frame's index ahead of it.
+fn edited_frames(
+    steps: &[(&str, Option<f32>)],
+    before: impl Fn(usize, &mut CommonMarkCache),
+) -> Vec<Frame> {
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let input = egui::RawInput {
+        screen_rect: Some(Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(600.0, 400.0))),
+        ..Default::default()
+    };
+    let mut frames = Vec::new();
+    for (index, (markdown, offset)) in steps.iter().enumerate() {
+        before(index, &mut cache);
+        let counter = LayoutCounter::default();
+        ctx.begin_pass(input.clone());
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            CommonMarkViewer::new()
+                .render_hooks(Some(&counter))
+                .pending_scroll_offset(*offset)
+                .show_scrollable("virtualized_test", ui, &mut cache, markdown);
+        });
+        let mut painted = Vec::new();
+        for clipped in ctx.end_pass().shapes {
+            collect_painted_text(&clipped.shape, &mut painted);
+        }
+        frames.push(Frame {
+            painted,
+            headings: counter.headings.get(),
+            code_blocks: counter.code_blocks.get(),
+        });
+    }
+    frames
+}
+
+#[test]
+fn scrolled_frames_lay_out_only_visible_blocks_where_the_full_layout_put_them() {
+    let markdown = long_document();
+    let frames = scrollable_frames(&markdown, &[None, None, Some(2500.0), None, None]);
+    let (full, visible) = (&frames[2], &frames[4]);
+
+    // The jump lays out every heading, later frames only those on screen.
+    assert_eq!(full.headings, 40);
+    assert!(visible.headings < 10, "{} headings laid out", visible.headings);
+
+    assert_same_on_screen(&full.painted, &visible.painted);
+}
+
+#[test]
+fn jumps_skip_the_blocks_far_from_the_viewport() {
+    let markdown = long_document();
+    let frames = scrollable_frames(
+        &markdown,
+        &[None, None, Some(2500.0), None, Some(0.0), Some(2500.0), None],
+    );
+
+    // The first two layouts measure all 10 code blocks, later jumps only
+    // those near where they land.
+    assert_eq!(frames[0].code_blocks, 10);
+    assert_eq!(frames[2].code_blocks, 10);
+    for jump in [&frames[4], &frames[5]] {
+        assert!(jump.code_blocks < 5, "{} code blocks laid out", jump.code_blocks);
+    }
+
+    // They land where the full layouts put everything.
+    assert_same_on_screen(&frames[1].painted, &frames[4].painted);
+    assert_same_on_screen(&frames[2].painted, &frames[5].painted);
+    assert_same_on_screen(&frames[5].painted, &frames[6].painted);
+}
+
+/// Asserts that the texts on the 600×400 screen are painted at the same spots.
+fn assert_same_on_screen(expected: &[PaintedText], painted: &[PaintedText]) {
+    let on_screen = |painted: &[PaintedText]| -> Vec<(String, Rect)> {
+        let screen = Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(600.0, 400.0));
+        painted
+            .iter()
+            .filter(|text| text.rect.intersects(screen))
+            .map(|text| (text.text.clone(), text.rect))
+            .collect()
+    };
+    let (expected, painted) = (on_screen(expected), on_screen(painted));
+    assert!(painted.len() > 20, "{painted:?}");
+    assert_eq!(expected.len(), painted.len());
+    for ((expected, rect), (text, painted)) in expected.iter().zip(&painted) {
+        assert_eq!(expected, text);
+        assert!(
+            (rect.min - painted.min).length() < 0.5,
+            "{text:?} moved from {rect:?} to {painted:?}"
+        );
+    }
+}
+
+#[test]
+fn scrolled_to_the_end_all_footnotes_are_listed() {
+    let markdown = long_document();
+    let frames = scrollable_frames(&markdown, &[None, Some(1.0e6), None, None]);
+    let last = &frames.last().unwrap().painted;
+    // Numbered by the references in the blocks that weren't painted.
+    for text in ["note-39", "1.", "40."] {
+        assert!(last.iter().any(|t| t.text == text), "{text} missing");
+    }
+}
+
+#[test]
+fn progressively_loaded_documents_are_laid_out_like_the_rest() {
+    // Link reference definitions make the document long enough to load
+    // progressively without adding anything to lay out.
+    let mut markdown = String::new();
+    for i in 0..20_000 {
+        markdown.push_str(&format!("[padding-{i}]: https://example.com/{i}\n"));
+    }
+    markdown.push('\n');
+    markdown.push_str(&long_document());
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let input = egui::RawInput {
+        screen_rect: Some(Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(600.0, 400.0))),
+        ..Default::default()
+    };
+    let mut frame = |offset: Option<f32>| {
+        ctx.begin_pass(input.clone());
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            CommonMarkViewer::new()
+                .progressive_loading(true)
+                .pending_scroll_offset(offset)
+                .show_scrollable("progressive_test", ui, &mut cache, &markdown);
+        });
+        let mut painted = Vec::new();
+        for clipped in ctx.end_pass().shapes {
+            collect_painted_text(&clipped.shape, &mut painted);
+        }
+        painted
+    };
+
+    let first = frame(None);
+    assert!(first.iter().any(|t| t.text == "Parsing document…"), "{first:?}");
+    let mut loaded = None;
+    for _ in 0..1000 {
+        let painted = frame(None);
+        if painted.iter().any(|t| t.text == "Section 0") {
+            loaded = Some(painted);
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    assert!(loaded.is_some(), "the document never finished loading");
+    // Once the tables have settled on their sizes.
+    let settled = (0..4).map(|_| frame(None)).last().unwrap();
+    let direct = scrollable_frames(&markdown, &[None; 4]);
+    assert_same_on_screen(&direct[3].painted, &settled);
+
+    // Footnotes and positions were recorded along the way.
+    frame(Some(1.0e6));
+    let last = frame(None);
+    for text in ["note-39", "1.", "40."] {
+        assert!(last.iter().any(|t| t.text == text), "{text} missing");
+    }
+}
+
+#[test]
+fn edits_lay_out_only_the_invalidated_blocks_again() {
+    let markdown = format!("- [ ] task\n\n{}", long_document());
+    let edited = markdown.replacen("[ ]", "[x]", 1);
+    let steps = [
+        (markdown.as_str(), None),
+        (&markdown, None),
+        (&markdown, Some(2500.0)),
+        (&markdown, None),
+        (&edited, None),
+    ];
+
+    // Told what changed, the viewer keeps the other blocks' measurements.
+    let frames = edited_frames(&steps, |index, cache| {
+        if index == 4 {
+            cache.invalidate_block("virtualized_test", 2..5);
+        }
+    });
+    assert!(frames[4].code_blocks < 5, "{} code blocks laid out", frames[4].code_blocks);
+    assert_same_on_screen(&frames[3].painted, &frames[4].painted);
+
+    // Otherwise a changed text is laid out in full.
+    let frames = edited_frames(&steps, |_, _| {});
+    assert_eq!(frames[4].code_blocks, 10);
+    assert_same_on_screen(&frames[3].painted, &frames[4].painted);
+
+    let frames = edited_frames(&steps, |index, cache| {
+        if index == 4 {
+            cache.invalidate_all("virtualized_test");
+        }
+    });
+    assert_eq!(frames[4].code_blocks, 10);
+}
+
+fn collect_rects(shape: &Shape, rects: &mut Vec<Rect>) {
+    match shape {
+        Shape::Rect(rect) => rects.push(rect.rect),
+        Shape::Vec(shapes) => {
+            for shape in shapes {
+                collect_rects(shape, rects);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[test]
+fn checkbox_clicks_are_reported_by_show() {
+    let markdown = "- [ ] first\n- [ ] second\n";
+    let clicks = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let sink = clicks.clone();
+    let on_click = move |span: std::ops::Range<usize>, checked: bool| {
+        sink.borrow_mut().push((span, checked));
+    };
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut frame = |events: Vec<egui::Event>| {
+        ctx.begin_pass(egui::RawInput {
+            events,
+            ..Default::default()
+        });
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            CommonMarkViewer::new()
+                .checkbox_clicked_fn(Some(&on_click))
+                .show(ui, &mut cache, markdown);
+        });
+        ctx.end_pass().shapes
+    };
+
+    // The box of the second checkbox is the small rect painted in its row.
+    let shapes = frame(Vec::new());
+    let (mut painted, mut rects) = (Vec::new(), Vec::new());
+    for clipped in &shapes {
+        collect_painted_text(&clipped.shape, &mut painted);
+        collect_rects(&clipped.shape, &mut rects);
+    }
+    let label = text_rect(&painted, "second");
+    let checkbox = rects
+        .iter()
+        .rfind(|rect| rect.width() < label.width() && rect.y_range().contains(label.center().y))
+        .expect("checkbox painted")
+        .center();
+
+    let button = |pressed| egui::Event::PointerButton {
+        pos: checkbox,
+        button: egui::PointerButton::Primary,
+        pressed,
+        modifiers: Default::default(),
+    };
+    frame(vec![egui::Event::PointerMoved(checkbox), button(true)]);
+    frame(vec![button(false)]);
+    assert_eq!(*clicks.borrow(), vec![(14..17, true)]);
+}
+
+#[test]
+fn show_reports_the_clicked_link_headings_and_tables() {
+    let markdown = "# Title\n\nSee [the docs](https://example.com \"Docs\").\n\n| a |\n|---|\n| b |\n";
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut frame = |events: Vec<egui::Event>| {
+        ctx.begin_pass(egui::RawInput {
+            events,
+            ..Default::default()
+        });
+        let mut response = None;
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            response = Some(CommonMarkViewer::new().show(ui, &mut cache, markdown));
+        });
+        let mut painted = Vec::new();
+        for clipped in ctx.end_pass().shapes {
+            collect_painted_text(&clipped.shape, &mut painted);
+        }
+        (response.unwrap(), painted)
+    };
+
+    let (response, painted) = frame(Vec::new());
+    assert!(response.clicked_link.is_none());
+    assert_eq!(response.table_count, 1);
+    let [(title, rect)] = &response.heading_rects[..] else {
+        panic!("{:?}", response.heading_rects);
+    };
+    assert_eq!(title.slug, "title");
+    assert!(rect.contains(text_rect(&painted, "Title").center()));
+
+    let link = text_rect(&painted, "the docs").center();
+    let button = |pressed| egui::Event::PointerButton {
+        pos: link,
+        button: egui::PointerButton::Primary,
+        pressed,
+        modifiers: Default::default(),
+    };
+    let (response, _) = frame(vec![egui::Event::PointerMoved(link), button(true)]);
+    let hovered = response.hovered_link.expect("link hovered");
+    assert_eq!(hovered.destination, "https://example.com");
+    assert_eq!(hovered.title, "Docs");
+    assert!(!hovered.is_hook);
+    let (response, _) = frame(vec![button(false)]);
+    assert_eq!(response.clicked_link, Some(hovered));
+}
+
+fn text_color(shape: &Shape, marker: &str) -> Option<egui::Color32> {
+    match shape {
+        Shape::Text(text) if text.galley.job.text.contains(marker) => {
+            Some(text.galley.job.sections[0].format.color)
+        }
+        Shape::Vec(shapes) => shapes.iter().find_map(|shape| text_color(shape, marker)),
+        _ => None,
+    }
+}
+
+#[test]
+fn clicked_links_are_drawn_with_the_visited_color() {
+    let markdown = "See [the docs](https://example.com).\n";
+    let visited = egui::Color32::from_rgb(128, 0, 128);
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut frame = |events: Vec<egui::Event>| {
+        ctx.begin_pass(egui::RawInput {
+            events,
+            ..Default::default()
+        });
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            CommonMarkViewer::new()
+                .link_style(LinkStyle {
+                    visited_color: Some(visited),
+                    ..Default::default()
+                })
+                .show(ui, &mut cache, markdown);
+        });
+        let mut painted = Vec::new();
+        let mut color = None;
+        for clipped in ctx.end_pass().shapes {
+            collect_painted_text(&clipped.shape, &mut painted);
+            color = color.or_else(|| text_color(&clipped.shape, "the docs"));
+        }
+        (painted, color.expect("link painted"))
+    };
+
+    let (painted, color) = frame(Vec::new());
+    assert_eq!(color, ctx.style().visuals.hyperlink_color);
+    let link = text_rect(&painted, "the docs").center();
+    let button = |pressed| egui::Event::PointerButton {
+        pos: link,
+        button: egui::PointerButton::Primary,
+        pressed,
+        modifiers: Default::default(),
+    };
+    frame(vec![egui::Event::PointerMoved(link), button(true)]);
+    frame(vec![button(false)]);
+    let (_, color) = frame(Vec::new());
+    assert_eq!(color, visited);
+    assert!(cache.is_link_visited("https://example.com"));
+
+    cache.clear_visited_links();
+    assert!(!cache.is_link_visited("https://example.com"));
+}
+
+#[test]
+fn capped_content_is_centered_in_the_viewer() {
+    let markdown = "A paragraph that is long enough to wrap within the narrow column width.\n";
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut panel = Rect::NOTHING;
+    let mut body = Rect::NOTHING;
+    let mut painted = Vec::new();
+    for _ in 0..2 {
+        ctx.begin_pass(Default::default());
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            panel = ui.max_rect();
+            body = CommonMarkViewer::new()
+                .max_content_width(Some(200.0))
+                .center_content(true)
+                .show(ui, &mut cache, markdown)
+                .response
+                .rect;
+        });
+        painted.clear();
+        for clipped in ctx.end_pass().shapes {
+            collect_painted_text(&clipped.shape, &mut painted);
+        }
+    }
+
+    assert!(panel.width() > 400.0, "{panel:?}");
+    assert!(body.width() <= 200.0, "{body:?}");
+    assert!((body.left() - panel.left() - (panel.width() - 200.0) / 2.0).abs() < 1.0);
+    let text = text_rect(&painted, "A paragraph");
+    assert!(body.expand(0.5).contains_rect(text), "{text:?} outside {body:?}");
+    let body_id = TextStyle::Body.resolve(&ctx.style());
+    let row_height = ctx.fonts_mut(|fonts| fonts.row_height(&body_id));
+    assert!(text.height() > row_height * 1.5, "the column wraps: {text:?}");
+}
+
+fn text_galley(shape: &Shape, marker: &str) -> Option<(egui::Pos2, std::sync::Arc<egui::Galley>)> {
+    match shape {
+        Shape::Text(text) if text.galley.job.text.contains(marker) => {
+            Some((text.pos, text.galley.clone()))
+        }
+        Shape::Vec(shapes) => shapes.iter().find_map(|shape| text_galley(shape, marker)),
+        _ => None,
+    }
+}
+
+#[test]
+fn paragraphs_can_be_indented_and_justified() {
+    let markdown = "Some text that is long enough to wrap over a few rows of the narrow panel.\n\n\
+                    - an item\n";
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut body = Rect::NOTHING;
+    let mut shapes = Vec::new();
+    for _ in 0..2 {
+        ctx.begin_pass(Default::default());
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            body = CommonMarkViewer::new()
+                .max_content_width(Some(240.0))
+                .first_line_indent_px(30.0)
+                .justify(true)
+                .show(ui, &mut cache, markdown)
+                .response
+                .rect;
+        });
+        shapes = ctx.end_pass().shapes;
+    }
+
+    let (pos, galley) = shapes
+        .iter()
+        .find_map(|clipped| text_galley(&clipped.shape, "Some text"))
+        .expect("paragraph painted");
+    assert!(galley.rows.len() > 2, "{}", galley.rows.len());
+    let left = |row: &egui::epaint::text::PlacedRow| pos.x + row.pos.x + row.glyphs[0].pos.x;
+    let right = |row: &egui::epaint::text::PlacedRow| {
+        let last_letter = row.glyphs.iter().rfind(|glyph| !glyph.chr.is_whitespace());
+        pos.x + row.pos.x + last_letter.unwrap().max_x()
+    };
+    let first = left(&galley.rows[0]);
+    assert!((first - body.left() - 30.0).abs() < 1.0, "{first} in {body:?}");
+    assert!((left(&galley.rows[1]) - body.left()).abs() < 1.0);
+    // Wrapped rows reach the right edge, the last one doesn't.
+    let column_right = body.left() + 240.0;
+    for row in &galley.rows[..galley.rows.len() - 1] {
+        assert!((right(row) - column_right).abs() < 1.0, "{} vs {column_right}", right(row));
+    }
+    assert!(right(galley.rows.last().unwrap()) < column_right - 20.0);
+
+    let mut painted = Vec::new();
+    for clipped in &shapes {
+        collect_painted_text(&clipped.shape, &mut painted);
+    }
+    assert!(text_rect(&painted, "an item").left() < first);
+}
+
+#[test]
+fn right_to_left_blocks_start_from_the_right_edge() {
+    let markdown =
+        "שלום עולם\n\n1. פריט ראשון\n\n> ציטוט קצר\n\nLeft to right\n\n```\ncode line\n```\n";
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut body = Rect::NOTHING;
+    let mut painted = Vec::new();
+    for _ in 0..2 {
+        ctx.begin_pass(Default::default());
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            body = CommonMarkViewer::new()
+                .max_content_width(Some(300.0))
+                .text_direction(TextDirection::Auto)
+                .show(ui, &mut cache, markdown)
+                .response
+                .rect;
+        });
+        painted.clear();
+        for clipped in ctx.end_pass().shapes {
+            collect_painted_text(&clipped.shape, &mut painted);
+        }
+    }
+
+    let right = |marker| body.right() - text_rect(&painted, marker).right();
+    let left = |marker| text_rect(&painted, marker).left() - body.left();
+    assert!(right("שלום") < 1.0, "{body:?} {painted:#?}");
+    assert!(right("ציטוט") < 15.0, "the quote bar is on the right: {painted:#?}");
+    let item = text_rect(&painted, "פריט");
+    let number = text_rect(&painted, "1.");
+    assert!(number.left() >= item.right(), "{number:?} right of {item:?}");
+    assert!(right("1.") < 1.0, "{number:?}");
+    assert!(left("Left to right") < 1.0);
+    assert!(left("code line") < 20.0, "code stays left to right: {painted:#?}");
+}
+
+#[test]
+fn selections_dragged_across_blocks_copy_the_links_too() {
+    let markdown =
+        "# Heading one\n\nA [linked](https://example.com) word.\n\n- item one\n\nLast paragraph\n";
+    let ctx = Context::default();
+    let mut cache = CommonMarkCache::default();
+    let mut pass = |events: Vec<egui::Event>| {
+        let input = egui::RawInput {
+            screen_rect: Some(Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(600.0, 400.0))),
+            events,
+            ..Default::default()
+        };
+        ctx.begin_pass(input);
+        egui::CentralPanel::default().show(&ctx, |ui| {
+            CommonMarkViewer::new()
+                .continuous_selection(true)
+                .show(ui, &mut cache, markdown);
+        });
+        ctx.end_pass()
+    };
+    pass(Vec::new());
+    let mut painted = Vec::new();
+    for clipped in pass(Vec::new()).shapes {
+        collect_painted_text(&clipped.shape, &mut painted);
+    }
+
+    let from = text_rect(&painted, "Heading").left_center() + egui::vec2(1.0, 0.0);
+    let to = text_rect(&painted, "Last paragraph").right_center() + egui::vec2(2.0, 0.0);
+    let button = |pos, pressed| egui::Event::PointerButton {
+        pos,
+        button: egui::PointerButton::Primary,
+        pressed,
+        modifiers: egui::Modifiers::NONE,
+    };
+    pass(vec![egui::Event::PointerMoved(from)]);
+    pass(vec![button(from, true)]);
+    pass(vec![egui::Event::PointerMoved(from + egui::vec2(20.0, 10.0))]);
+    pass(vec![egui::Event::PointerMoved(to)]);
+    pass(vec![button(to, false)]);
+    let copied = pass(vec![egui::Event::Copy]).platform_output.commands;
+
+    assert_eq!(
+        copied,
+        [egui::OutputCommand::CopyText(
+            "Heading one\n\nA linked word.\n\nitem one\n\nLast paragraph".to_owned()
+        )]
+    );
+}
@@ -1,32 +1,167 @@
-use crate::elements::{blockquote, newline};
+use crate::elements::{newline, styled_blockquote};
+use crate::misc::BlockquoteStyle;
 use egui::Ui;
 use std::collections::HashMap;
 
+/// What is shown in front of the identifier of an alert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlertIcon {
+    /// Text such as an emoji
+    Glyph(String),
+    /// Uri of an image, loaded by the image loaders of egui. It is scaled to
+    /// the height of a line.
+    Image(String),
+}
+
+impl From<char> for AlertIcon {
+    fn from(glyph: char) -> Self {
+        Self::Glyph(glyph.to_string())
+    }
+}
+
+impl From<&str> for AlertIcon {
+    fn from(glyph: &str) -> Self {
+        Self::Glyph(glyph.to_owned())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Alert {
     /// The color that will be used to put emphasis to the alert
     pub accent_color: egui::Color32,
-    /// The icon that will be displayed
-    pub icon: char,
+    /// Fill behind the alert. `None` leaves it transparent like a blockquote
+    pub background_color: Option<egui::Color32>,
+    /// The icon that will be displayed, if any
+    pub icon: Option<AlertIcon>,
     /// The identifier that will be used to look for the blockquote such as NOTE and TIP
     pub identifier: String,
     /// The identifier that will be shown when rendering. E.g: Note and Tip
     pub identifier_rendered: String,
+    /// Whether the line with the icon and the rendered identifier is shown.
+    /// Collapsible alerts always show it.
+    pub show_identifier: bool,
+    /// `Some` makes the alert collapsible by clicking its identifier line,
+    /// starting out open when `true`. Whether it is open is stored in the
+    /// cache.
+    pub collapsible: Option<bool>,
+}
+
+impl Alert {
+    /// An alert for `> [!identifier]` with an identifier line but no icon or
+    /// background.
+    pub fn new(
+        identifier: impl Into<String>,
+        identifier_rendered: impl Into<String>,
+        accent_color: egui::Color32,
+    ) -> Self {
+        Self {
+            accent_color,
+            background_color: None,
+            icon: None,
+            identifier: identifier.into(),
+            identifier_rendered: identifier_rendered.into(),
+            show_identifier: true,
+            collapsible: None,
+        }
+    }
+
+    pub fn icon(mut self, icon: impl Into<AlertIcon>) -> Self {
+        self.icon = Some(icon.into());
+        self
+    }
+
+    pub fn background_color(mut self, color: egui::Color32) -> Self {
+        self.background_color = Some(color);
+        self
+    }
+
+    pub fn show_identifier(mut self, show: bool) -> Self {
+        self.show_identifier = show;
+        self
+    }
+
+    pub fn collapsible(mut self, open: bool) -> Self {
+        self.collapsible = Some(open);
+        self
+    }
 }
 
 // Seperate function to not leak into the public API
-pub fn alert_ui(alert: &Alert, ui: &mut Ui, add_contents: impl FnOnce(&mut Ui)) {
-    blockquote(ui, alert.accent_color, |ui| {
-        newline(ui);
-        ui.colored_label(alert.accent_color, alert.icon.to_string());
-        ui.add_space(3.0);
-        ui.colored_label(alert.accent_color, &alert.identifier_rendered);
-        // end line
-        newline(ui);
-        add_contents(ui);
-    })
+/// `open` is whether a collapsible alert is open. Returns whether it is open
+/// now if the user toggled it.
+pub fn alert_ui(
+    alert: &Alert,
+    ui: &mut Ui,
+    style: &BlockquoteStyle,
+    open: Option<bool>,
+    add_contents: impl FnOnce(&mut Ui),
+) -> Option<bool> {
+    let mut toggled = None;
+    let style = BlockquoteStyle {
+        background: alert.background_color.or(style.background),
+        ..*style
+    };
+    styled_blockquote(ui, alert.accent_color, &style, |ui| {
+        if alert.show_identifier || open.is_some() {
+            newline(ui);
+            let mut title = ui.allocate_response(egui::Vec2::ZERO, egui::Sense::hover());
+            if let Some(open) = open {
+                title |= ui.colored_label(alert.accent_color, if open { "▼" } else { "▶" });
+                ui.add_space(3.0);
+            }
+            match &alert.icon {
+                Some(AlertIcon::Glyph(glyph)) => {
+                    title |= ui.colored_label(alert.accent_color, glyph);
+                    ui.add_space(3.0);
+                }
+                Some(AlertIcon::Image(uri)) => {
+                    let size = ui.text_style_height(&egui::TextStyle::Body);
+                    title |= ui.add(
+                        egui::Image::new(uri.as_str()).fit_to_exact_size(egui::vec2(size, size)),
+                    );
+                    ui.add_space(3.0);
+                }
+                None => {}
+            }
+            title |= ui.colored_label(alert.accent_color, &alert.identifier_rendered);
+            if let Some(open) = open {
+                let title = ui.interact(title.rect, title.id.with("toggle"), egui::Sense::click());
+                if title
+                    .on_hover_cursor(egui::CursorIcon::PointingHand)
+                    .clicked()
+                {
+                    toggled = Some(!open);
+                    ui.ctx().request_repaint();
+                }
+            }
+            // end line
+            newline(ui);
+        }
+        if open != Some(false) {
+            add_contents(ui);
+        }
+    });
+    toggled
 }
 
+const GFM_IDENTIFIERS: [&str; 5] = ["NOTE", "TIP", "IMPORTANT", "WARNING", "CAUTION"];
+
+/// Shown names of the [`GFM_IDENTIFIERS`], by language.
+const GFM_TRANSLATIONS: &[(&str, [&str; 5])] = &[
+    ("de", ["Hinweis", "Tipp", "Wichtig", "Warnung", "Vorsicht"]),
+    ("es", ["Nota", "Consejo", "Importante", "Advertencia", "Precaución"]),
+    ("fr", ["Remarque", "Astuce", "Important", "Avertissement", "Attention"]),
+    ("it", ["Nota", "Suggerimento", "Importante", "Avviso", "Attenzione"]),
+    ("ja", ["注記", "ヒント", "重要", "警告", "注意"]),
+    ("ko", ["참고", "팁", "중요", "경고", "주의"]),
+    ("nl", ["Opmerking", "Tip", "Belangrijk", "Waarschuwing", "Let op"]),
+    ("pl", ["Uwaga", "Wskazówka", "Ważne", "Ostrzeżenie", "Przestroga"]),
+    ("pt", ["Nota", "Dica", "Importante", "Aviso", "Cuidado"]),
+    ("ru", ["Примечание", "Совет", "Важно", "Предупреждение", "Осторожно"]),
+    ("tr", ["Not", "İpucu", "Önemli", "Uyarı", "Dikkat"]),
+    ("zh", ["注意", "提示", "重要", "警告", "小心"]),
+];
+
 #[derive(Debug, Clone)]
 pub struct AlertBundle {
     /// the key is `[!identifier]`
@@ -63,39 +198,65 @@ impl AlertBundle {
     /// This is used by default
     pub fn gfm() -> Self {
         Self::from_alerts(vec![
-            Alert {
-                accent_color: egui::Color32::from_rgb(10, 80, 210),
-                icon: '❕',
-                identifier: "NOTE".to_owned(),
-                identifier_rendered: "Note".to_owned(),
-            },
-            Alert {
-                accent_color: egui::Color32::from_rgb(0, 130, 20),
-                icon: '💡',
-                identifier: "TIP".to_owned(),
-                identifier_rendered: "Tip".to_owned(),
-            },
-            Alert {
-                accent_color: egui::Color32::from_rgb(150, 30, 140),
-                icon: '💬',
-                identifier: "IMPORTANT".to_owned(),
-                identifier_rendered: "Important".to_owned(),
-            },
-            Alert {
-                accent_color: egui::Color32::from_rgb(200, 120, 0),
-                icon: '⚠',
-                identifier: "WARNING".to_owned(),
-                identifier_rendered: "Warning".to_owned(),
-            },
-            Alert {
-                accent_color: egui::Color32::from_rgb(220, 0, 0),
-                icon: '🔴',
-                identifier: "CAUTION".to_owned(),
-                identifier_rendered: "Caution".to_owned(),
-            },
+            Alert::new("NOTE", "Note", egui::Color32::from_rgb(10, 80, 210)).icon('❕'),
+            Alert::new("TIP", "Tip", egui::Color32::from_rgb(0, 130, 20)).icon('💡'),
+            Alert::new(
+                "IMPORTANT",
+                "Important",
+                egui::Color32::from_rgb(150, 30, 140),
+            )
+            .icon('💬'),
+            Alert::new("WARNING", "Warning", egui::Color32::from_rgb(200, 120, 0)).icon('⚠'),
+            Alert::new("CAUTION", "Caution", egui::Color32::from_rgb(220, 0, 0)).icon('🔴'),
         ])
     }
 
+    /// [`gfm`](Self::gfm) alerts with the shown names in `language`, an
+    /// ISO 639-1 code optionally followed by a region or encoding as in
+    /// locales (`de`, `pt_BR.UTF-8`). The identifiers in the markdown stay
+    /// `[!NOTE]`, `[!TIP]`, ... Unknown languages give the English names.
+    pub fn gfm_localized(language: &str) -> Self {
+        let language = language
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let Some((_, names)) = GFM_TRANSLATIONS.iter().find(|(code, _)| *code == language)
+        else {
+            return Self::gfm();
+        };
+        let mut bundle = Self::gfm();
+        for (identifier, name) in GFM_IDENTIFIERS.iter().zip(names) {
+            if let Some(alert) = bundle.alerts.get_mut(&format!("[!{identifier}]")) {
+                alert.identifier_rendered = (*name).to_owned();
+            }
+        }
+        bundle
+    }
+
+    /// Languages [`gfm_localized`](Self::gfm_localized) has names for,
+    /// besides English.
+    pub fn gfm_languages() -> impl Iterator<Item = &'static str> {
+        GFM_TRANSLATIONS.iter().map(|(code, _)| *code)
+    }
+
+    /// Add an alert, replacing the one with the same identifier. Returns the
+    /// replaced alert.
+    ///
+    /// ```
+    /// # use egui_commonmark_backend_extended::{Alert, AlertBundle};
+    /// let mut alerts = AlertBundle::gfm();
+    /// alerts.insert(
+    ///     Alert::new("DANGER", "Danger", egui::Color32::RED)
+    ///         .icon('☠')
+    ///         .background_color(egui::Color32::from_rgba_unmultiplied(255, 0, 0, 20)),
+    /// );
+    /// ```
+    pub fn insert(&mut self, alert: Alert) -> Option<Alert> {
+        self.alerts
+            .insert(format!("[!{}]", alert.identifier), alert)
+    }
+
     /// See if the bundle contains no alerts
     pub fn is_empty(&self) -> bool {
         self.alerts.is_empty()
@@ -105,3 +266,43 @@ impl AlertBundle {
 pub fn try_get_alert<'a>(bundle: &'a AlertBundle, text: &str) -> Option<&'a Alert> {
     bundle.alerts.get(&text.to_uppercase())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserted_alerts_replace_the_ones_with_their_identifier() {
+        let mut alerts = AlertBundle::gfm();
+        let danger = Alert::new("DANGER", "Danger", egui::Color32::RED).show_identifier(false);
+        assert!(alerts.insert(danger).is_none());
+        assert!(!try_get_alert(&alerts, "[!danger]").unwrap().show_identifier);
+
+        let note = Alert::new("NOTE", "Notiz", egui::Color32::BLUE).icon("📝");
+        let replaced = alerts.insert(note).unwrap();
+        assert_eq!(replaced.icon, Some(AlertIcon::from('❕')));
+        let note = try_get_alert(&alerts, "[!NOTE]").unwrap();
+        assert_eq!(note.identifier_rendered, "Notiz");
+        assert_eq!(note.icon, Some(AlertIcon::Glyph("📝".to_owned())));
+        assert_eq!(alerts.into_alerts().len(), 6);
+    }
+
+    #[test]
+    fn localized_alerts_keep_their_identifiers() {
+        let rendered = |bundle: &AlertBundle, text: &str| {
+            try_get_alert(bundle, text).map(|alert| alert.identifier_rendered.clone())
+        };
+        let german = AlertBundle::gfm_localized("de_DE.UTF-8");
+        assert_eq!(rendered(&german, "[!note]").as_deref(), Some("Hinweis"));
+        assert_eq!(rendered(&german, "[!CAUTION]").as_deref(), Some("Vorsicht"));
+        assert_eq!(
+            try_get_alert(&german, "[!TIP]").unwrap().icon,
+            Some(AlertIcon::from('💡'))
+        );
+        let portuguese = AlertBundle::gfm_localized("pt-BR");
+        assert_eq!(rendered(&portuguese, "[!TIP]").as_deref(), Some("Dica"));
+        let unknown = AlertBundle::gfm_localized("C.UTF-8");
+        assert_eq!(rendered(&unknown, "[!WARNING]").as_deref(), Some("Warning"));
+        assert!(AlertBundle::gfm_languages().any(|code| code == "ja"));
+    }
+}
@@ -0,0 +1,138 @@
+//! GitHub-compatible heading anchors.
+//!
+//! `## Getting Started` is reachable as `#getting-started`; a second heading
+//! with the same text becomes `#getting-started-1`, and so on. Footnotes get
+//! `#fn-<label>` for the definition and `#fnref-<label>` for the reference.
+
+use std::collections::HashMap;
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Slug for a single heading, following GitHub's rules: lowercase, drop
+/// everything except letters, combining marks, digits, `-`, `_` and spaces,
+/// then turn spaces into `-`. The text is NFC-normalized first, so composed
+/// and decomposed accents give the same slug. Does not deduplicate, see
+/// [`AnchorSlugger`] for that.
+pub fn heading_slug(text: &str) -> String {
+    text.trim()
+        .nfc()
+        .filter_map(|c| {
+            if c == ' ' {
+                Some('-')
+            } else if c.is_alphanumeric() || is_combining_mark(c) || c == '-' || c == '_' {
+                Some(c)
+            } else {
+                None
+            }
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The slug an anchor link points at: without its leading `#`, with
+/// percent-escapes decoded (`#%C3%BCber` is `#über`) and NFC-normalized.
+pub fn anchor_target(anchor: &str) -> String {
+    let anchor = anchor.strip_prefix('#').unwrap_or(anchor);
+    let bytes = anchor.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| anchor.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    match String::from_utf8(decoded) {
+        Ok(decoded) => decoded.nfc().collect(),
+        Err(_) => anchor.nfc().collect(),
+    }
+}
+
+/// Anchor of the definition of footnote `label`.
+pub fn footnote_anchor(label: &str) -> String {
+    format!("fn-{}", heading_slug(label))
+}
+
+/// Anchor of the `nth` (0-based) reference to footnote `label`. Like GitHub,
+/// the first reference has no suffix and later ones count from 2.
+pub fn footnote_ref_anchor(label: &str, nth: usize) -> String {
+    let slug = heading_slug(label);
+    if nth == 0 {
+        format!("fnref-{slug}")
+    } else {
+        format!("fnref-{slug}-{}", nth + 1)
+    }
+}
+
+/// Hands out unique slugs for the headings of one document, in order.
+#[derive(Debug, Default)]
+pub struct AnchorSlugger {
+    seen: HashMap<String, usize>,
+}
+
+impl AnchorSlugger {
+    pub fn slug(&mut self, text: &str) -> String {
+        let base = heading_slug(text);
+        let mut candidate = base.clone();
+        // Same as github-slugger: the counter lives on the base slug, and a
+        // candidate that collides with an earlier literal heading is skipped.
+        while self.seen.contains_key(&candidate) {
+            let n = self.seen.entry(base.clone()).or_insert(0);
+            *n += 1;
+            candidate = format!("{base}-{n}");
+        }
+        self.seen.insert(candidate.clone(), 0);
+        candidate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_match_github() {
+        assert_eq!(heading_slug("Getting Started"), "getting-started");
+        assert_eq!(heading_slug("What's new in v0.2?"), "whats-new-in-v02");
+        assert_eq!(heading_slug("  snake_case & kebab-case "), "snake_case--kebab-case");
+        assert_eq!(heading_slug("Ünïcode Überschrift"), "ünïcode-überschrift");
+        // Decomposed "é" (e + combining acute) is composed first
+        assert_eq!(heading_slug("Cafe\u{301} 😀 Menu"), "café--menu");
+        assert_eq!(heading_slug("देवनागरी"), "देवनागरी");
+    }
+
+    #[test]
+    fn anchor_targets_are_decoded() {
+        assert_eq!(anchor_target("#%C3%BCber-uns"), "über-uns");
+        assert_eq!(anchor_target("cafe\u{301}"), "café");
+        assert_eq!(anchor_target("#100%-done"), "100%-done");
+        assert_eq!(anchor_target("#%FF"), "%FF");
+    }
+
+    #[test]
+    fn duplicate_headings_get_numbered_suffixes() {
+        let mut slugger = AnchorSlugger::default();
+        assert_eq!(slugger.slug("Install"), "install");
+        assert_eq!(slugger.slug("Install"), "install-1");
+        assert_eq!(slugger.slug("Install-1"), "install-1-1");
+        assert_eq!(slugger.slug("Install"), "install-2");
+    }
+
+    #[test]
+    fn footnote_anchors_match_github() {
+        assert_eq!(footnote_anchor("Note"), "fn-note");
+        assert_eq!(footnote_ref_anchor("1", 0), "fnref-1");
+        assert_eq!(footnote_ref_anchor("1", 1), "fnref-1-2");
+    }
+}
@@ -0,0 +1,271 @@
+//! Bar, line and pie charts from small JSON specs in `chart` code blocks.
+//!
+//! A spec names the chart type in `type` (or vega-lite's `mark`) and holds
+//! the data inline, as an array of numbers or of records:
+//!
+//! ```json
+//! {
+//!   "type": "bar",
+//!   "title": "Downloads",
+//!   "data": [{ "month": "Jan", "count": 120 }, { "month": "Feb", "count": 180 }],
+//!   "encoding": { "x": { "field": "month" }, "y": { "field": "count" } }
+//! }
+//! ```
+//!
+//! Without an `encoding` the first text field of the records labels the
+//! points and the first number field is plotted. Pie charts also accept
+//! vega-lite's `theta` and `color` channels. The functions here plug into
+//! `CommonMarkViewer::code_block_fn`, and are registered by default.
+
+use egui::Ui;
+use egui_plot::{Bar, BarChart, Legend, Line, Plot, PlotPoints, Polygon};
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChartKind {
+    Bar,
+    Line,
+    Pie,
+}
+
+/// A parsed chart spec: one labelled series.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChartSpec {
+    pub kind: ChartKind,
+    pub title: Option<String>,
+    pub labels: Vec<String>,
+    pub values: Vec<f64>,
+}
+
+impl ChartSpec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec: Value = serde_json::from_str(spec).map_err(|e| e.to_string())?;
+        let mark = spec
+            .get("type")
+            .or_else(|| spec.get("mark"))
+            .map(|mark| mark.get("type").unwrap_or(mark))
+            .and_then(Value::as_str)
+            .ok_or("missing chart \"type\"")?;
+        let kind = match mark {
+            "bar" => ChartKind::Bar,
+            "line" => ChartKind::Line,
+            "pie" | "arc" => ChartKind::Pie,
+            other => return Err(format!("unsupported chart type \"{other}\"")),
+        };
+
+        let data = spec.get("data").ok_or("missing \"data\"")?;
+        let records = data
+            .get("values")
+            .unwrap_or(data)
+            .as_array()
+            .ok_or("\"data\" must be an array")?;
+        let field = |channels: &[&str]| {
+            channels.iter().find_map(|channel| {
+                spec.pointer(&format!("/encoding/{channel}/field"))
+                    .and_then(Value::as_str)
+            })
+        };
+        let label_field = field(&["x", "color"]);
+        let value_field = field(&["y", "theta"]);
+
+        let mut labels = Vec::new();
+        let mut values = Vec::new();
+        for (i, record) in records.iter().enumerate() {
+            let (label, value) = match record {
+                Value::Number(n) => ((i + 1).to_string(), n.as_f64()),
+                Value::Object(fields) => {
+                    let label = match label_field {
+                        Some(name) => fields.get(name),
+                        None => fields.values().find(|v| v.is_string()),
+                    };
+                    let value = match value_field {
+                        Some(name) => fields.get(name),
+                        None => fields.values().find(|v| v.is_number()),
+                    };
+                    let label = match label {
+                        Some(Value::String(s)) => s.clone(),
+                        Some(other) => other.to_string(),
+                        None => (i + 1).to_string(),
+                    };
+                    (label, value.and_then(Value::as_f64))
+                }
+                _ => return Err("\"data\" must hold numbers or records".to_owned()),
+            };
+            labels.push(label);
+            values.push(value.ok_or_else(|| format!("no number in data point {}", i + 1))?);
+        }
+        if values.is_empty() {
+            return Err("\"data\" is empty".to_owned());
+        }
+
+        Ok(Self {
+            kind,
+            title: spec.get("title").and_then(Value::as_str).map(str::to_owned),
+            labels,
+            values,
+        })
+    }
+}
+
+/// Matches the fences [`render_chart`] draws: `chart` and `vega-lite`.
+pub fn is_chart_fence(lang: &str) -> bool {
+    matches!(lang, "chart" | "vega-lite")
+}
+
+/// Draw the chart described by `spec`. An invalid spec shows the error and
+/// returns `false`, so the spec itself is still shown as a code block.
+pub fn render_chart(ui: &mut Ui, _lang: &str, spec: &str) -> bool {
+    let chart = match ChartSpec::parse(spec) {
+        Ok(chart) => chart,
+        Err(err) => {
+            ui.colored_label(ui.visuals().error_fg_color, format!("Chart error: {err}"));
+            return false;
+        }
+    };
+
+    ui.vertical(|ui| {
+        if let Some(title) = &chart.title {
+            ui.strong(title);
+        }
+        // A chart in a document is for reading, so it stays put instead of
+        // taking the scroll wheel and drags from the page.
+        let plot = Plot::new(ui.id().with(("_chart", spec)))
+            .height(240.0)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false)
+            .allow_double_click_reset(false);
+
+        let labels = chart.labels.clone();
+        let label_at = move |x: f64| {
+            let i = x.round();
+            ((x - i).abs() < 1e-6 && i >= 0.0)
+                .then(|| labels.get(i as usize).cloned())
+                .flatten()
+                .unwrap_or_default()
+        };
+        match chart.kind {
+            ChartKind::Bar => {
+                let bars = chart
+                    .labels
+                    .iter()
+                    .zip(&chart.values)
+                    .enumerate()
+                    .map(|(i, (label, value))| Bar::new(i as f64, *value).name(label))
+                    .collect();
+                plot.x_axis_formatter(move |mark, _| label_at(mark.value))
+                    .include_y(0.0)
+                    .show(ui, |plot_ui| {
+                        plot_ui.bar_chart(BarChart::new("", bars).color(slice_color(0)));
+                    });
+            }
+            ChartKind::Line => {
+                plot.x_axis_formatter(move |mark, _| label_at(mark.value))
+                    .show(ui, |plot_ui| {
+                        let points: Vec<_> = (0..)
+                            .map(f64::from)
+                            .zip(&chart.values)
+                            .map(|(x, y)| [x, *y])
+                            .collect();
+                        plot_ui.line(Line::new("", PlotPoints::from(points)).color(slice_color(0)));
+                    });
+            }
+            ChartKind::Pie => {
+                plot.data_aspect(1.0)
+                    .show_axes(false)
+                    .show_grid(false)
+                    .show_x(false)
+                    .show_y(false)
+                    .legend(Legend::default())
+                    .show(ui, |plot_ui| {
+                        for (i, slice) in pie_slices(&chart.values).into_iter().enumerate() {
+                            let name = &chart.labels[i];
+                            plot_ui.polygon(
+                                Polygon::new(name, PlotPoints::from(slice))
+                                    .fill_color(slice_color(i))
+                                    .stroke(egui::Stroke::NONE),
+                            );
+                        }
+                    });
+            }
+        }
+    });
+    true
+}
+
+/// Outline of each slice of a unit pie, starting at twelve o'clock and
+/// going clockwise. Negative values count as zero.
+fn pie_slices(values: &[f64]) -> Vec<Vec<[f64; 2]>> {
+    let total: f64 = values.iter().map(|v| v.max(0.0)).sum();
+    let mut start = 0.0;
+    values
+        .iter()
+        .map(|v| {
+            let sweep = if total > 0.0 { v.max(0.0) / total } else { 0.0 };
+            let steps = ((sweep * 64.0).ceil() as usize).max(1);
+            let mut outline = vec![[0.0, 0.0]];
+            for step in 0..=steps {
+                let turn = start + sweep * step as f64 / steps as f64;
+                let angle = std::f64::consts::FRAC_PI_2 - turn * std::f64::consts::TAU;
+                outline.push([angle.cos(), angle.sin()]);
+            }
+            start += sweep;
+            outline
+        })
+        .collect()
+}
+
+/// Distinct colors, spread around the hue circle like egui_plot's own.
+fn slice_color(i: usize) -> egui::Color32 {
+    let golden_ratio = (5.0_f32.sqrt() - 1.0) / 2.0;
+    egui::epaint::Hsva::new(i as f32 * golden_ratio, 0.85, 0.5, 1.0).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specs_read_records_by_encoding_or_field_type() {
+        let chart = ChartSpec::parse(
+            r#"{"mark": {"type": "bar"}, "title": "T",
+                "data": {"values": [{"a": "x", "b": 2, "c": 9}, {"a": "y", "b": 3, "c": 8}]},
+                "encoding": {"x": {"field": "a"}, "y": {"field": "c"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(chart.kind, ChartKind::Bar);
+        assert_eq!(chart.title.as_deref(), Some("T"));
+        assert_eq!(chart.labels, ["x", "y"]);
+        assert_eq!(chart.values, [9.0, 8.0]);
+
+        let chart = ChartSpec::parse(
+            r#"{"type": "pie", "data": [{"k": "a", "v": 1}, {"k": "b", "v": 3}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            (chart.kind, chart.labels, chart.values),
+            (
+                ChartKind::Pie,
+                vec!["a".to_owned(), "b".to_owned()],
+                vec![1.0, 3.0]
+            )
+        );
+
+        let chart = ChartSpec::parse(r#"{"type": "line", "data": [4, 5.5]}"#).unwrap();
+        assert_eq!(chart.labels, ["1", "2"]);
+
+        assert!(ChartSpec::parse(r#"{"type": "radar", "data": [1]}"#).is_err());
+        assert!(ChartSpec::parse(r#"{"type": "bar", "data": []}"#).is_err());
+        assert!(ChartSpec::parse("not json").is_err());
+    }
+
+    #[test]
+    fn pie_slices_split_the_circle_by_share() {
+        let slices = pie_slices(&[1.0, 3.0]);
+        // The first quarter ends at three o'clock, where the second begins.
+        let end = *slices[0].last().unwrap();
+        assert!((end[0] - 1.0).abs() < 1e-9 && end[1].abs() < 1e-9);
+        assert_eq!(slices[1][1], end);
+    }
+}
@@ -0,0 +1,239 @@
+//! Viewer settings as plain data, for applications that keep them in their
+//! own config files.
+
+use crate::misc::{
+    CodeCopyFormat, CommonMarkOptions, DarkModeImages, ImageCaptions, MarkdownExtensions, Sandbox,
+    TableMode,
+};
+use crate::typography::TypographyConfig;
+
+/// The settings of [`CommonMarkOptions`] that are plain data. With the
+/// `serde` feature it can be deserialized, and missing fields keep their
+/// defaults.
+///
+/// Callbacks, render hooks, alerts and colors aren't part of it and are set
+/// on the viewer as usual.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CommonMarkConfig {
+    pub indentation_spaces: usize,
+    pub max_image_width: Option<usize>,
+    pub show_alt_text_on_hover: bool,
+    pub default_width: Option<usize>,
+    /// Syntax highlighting themes by name. `None` keeps the default, and
+    /// both are ignored without the `better_syntax_highlighting` feature.
+    pub theme_light: Option<String>,
+    pub theme_dark: Option<String>,
+    pub use_explicit_uri_scheme: bool,
+    pub default_implicit_uri_scheme: String,
+    pub typography: TypographyConfig,
+    pub use_strong_font_family: bool,
+    pub emoji_shortcodes: bool,
+    pub superscript_subscript: bool,
+    pub math_macros: bool,
+    pub equation_numbers: bool,
+    pub clickable_images: bool,
+    pub task_list_progress: bool,
+    pub soft_breaks_as_newlines: bool,
+    pub collapse_quotes_deeper_than: Option<usize>,
+    pub table_max_column_width: Option<f32>,
+    pub table_wrap_cells: bool,
+    pub table_striped: bool,
+    pub table_row_hover: bool,
+    pub table_mode: TableMode,
+    pub code_line_numbers: bool,
+    pub code_block_header: bool,
+    pub copy_without_prompts: bool,
+    pub code_copy_format: CodeCopyFormat,
+    pub csv_tables: bool,
+    pub media_embeds: bool,
+    pub image_captions: ImageCaptions,
+    pub lazy_images: bool,
+    pub dark_mode_images: DarkModeImages,
+    pub image_prefetch: bool,
+    pub image_context_menu: bool,
+    pub table_context_menu: bool,
+    pub color_swatches: bool,
+    pub heading_link_button: Option<String>,
+    pub block_copy_buttons: bool,
+    pub sandbox: Sandbox,
+    pub progressive_loading: bool,
+    pub extensions: MarkdownExtensions,
+}
+
+impl Default for CommonMarkConfig {
+    fn default() -> Self {
+        Self::from_options(&CommonMarkOptions::default())
+    }
+}
+
+impl CommonMarkConfig {
+    /// The plain data settings of `options`, e.g. to save them.
+    pub fn from_options(options: &CommonMarkOptions) -> Self {
+        #[cfg(feature = "better_syntax_highlighting")]
+        let (theme_light, theme_dark) = (
+            Some(options.theme_light.clone()),
+            Some(options.theme_dark.clone()),
+        );
+        #[cfg(not(feature = "better_syntax_highlighting"))]
+        let (theme_light, theme_dark) = (None, None);
+
+        Self {
+            indentation_spaces: options.indentation_spaces,
+            max_image_width: options.max_image_width,
+            show_alt_text_on_hover: options.show_alt_text_on_hover,
+            default_width: options.default_width,
+            theme_light,
+            theme_dark,
+            use_explicit_uri_scheme: options.use_explicit_uri_scheme,
+            default_implicit_uri_scheme: options.default_implicit_uri_scheme.clone(),
+            typography: options.typography.clone(),
+            use_strong_font_family: options.use_strong_font_family,
+            emoji_shortcodes: options.emoji_shortcodes,
+            superscript_subscript: options.superscript_subscript,
+            math_macros: options.math_macros,
+            equation_numbers: options.equation_numbers,
+            clickable_images: options.clickable_images,
+            task_list_progress: options.task_list_progress,
+            soft_breaks_as_newlines: options.soft_breaks_as_newlines,
+            collapse_quotes_deeper_than: options.collapse_quotes_deeper_than,
+            table_max_column_width: options.table_max_column_width,
+            table_wrap_cells: options.table_wrap_cells,
+            table_striped: options.table_striped,
+            table_row_hover: options.table_row_hover,
+            table_mode: options.table_mode,
+            code_line_numbers: options.code_line_numbers,
+            code_block_header: options.code_block_header,
+            copy_without_prompts: options.copy_without_prompts,
+            code_copy_format: options.code_copy_format,
+            csv_tables: options.csv_tables,
+            media_embeds: options.media_embeds,
+            image_captions: options.image_captions,
+            lazy_images: options.lazy_images,
+            dark_mode_images: options.dark_mode_images,
+            image_prefetch: options.image_prefetch,
+            image_context_menu: options.image_context_menu,
+            table_context_menu: options.table_context_menu,
+            color_swatches: options.color_swatches,
+            heading_link_button: options.heading_link_button.clone(),
+            block_copy_buttons: options.block_copy_buttons,
+            sandbox: options.sandbox,
+            progressive_loading: options.progressive_loading,
+            extensions: options.extensions,
+        }
+    }
+
+    /// Set these settings on `options`, leaving the rest of them alone.
+    pub fn apply(&self, options: &mut CommonMarkOptions) {
+        options.indentation_spaces = self.indentation_spaces;
+        options.max_image_width = self.max_image_width;
+        options.show_alt_text_on_hover = self.show_alt_text_on_hover;
+        options.default_width = self.default_width;
+        #[cfg(feature = "better_syntax_highlighting")]
+        {
+            if let Some(theme) = &self.theme_light {
+                options.theme_light.clone_from(theme);
+            }
+            if let Some(theme) = &self.theme_dark {
+                options.theme_dark.clone_from(theme);
+            }
+        }
+        options.use_explicit_uri_scheme = self.use_explicit_uri_scheme;
+        options
+            .default_implicit_uri_scheme
+            .clone_from(&self.default_implicit_uri_scheme);
+        options.typography = self.typography.clone();
+        options.use_strong_font_family = self.use_strong_font_family;
+        options.emoji_shortcodes = self.emoji_shortcodes;
+        options.superscript_subscript = self.superscript_subscript;
+        options.math_macros = self.math_macros;
+        options.equation_numbers = self.equation_numbers;
+        options.clickable_images = self.clickable_images;
+        options.task_list_progress = self.task_list_progress;
+        options.soft_breaks_as_newlines = self.soft_breaks_as_newlines;
+        options.collapse_quotes_deeper_than = self.collapse_quotes_deeper_than;
+        options.table_max_column_width = self.table_max_column_width;
+        options.table_wrap_cells = self.table_wrap_cells;
+        options.table_striped = self.table_striped;
+        options.table_row_hover = self.table_row_hover;
+        options.table_mode = self.table_mode;
+        options.code_line_numbers = self.code_line_numbers;
+        options.code_block_header = self.code_block_header;
+        options.copy_without_prompts = self.copy_without_prompts;
+        options.code_copy_format = self.code_copy_format;
+        options.csv_tables = self.csv_tables;
+        options.media_embeds = self.media_embeds;
+        options.image_captions = self.image_captions;
+        options.lazy_images = self.lazy_images;
+        options.dark_mode_images = self.dark_mode_images;
+        options.image_prefetch = self.image_prefetch;
+        options.image_context_menu = self.image_context_menu;
+        options.table_context_menu = self.table_context_menu;
+        options.color_swatches = self.color_swatches;
+        options.heading_link_button = self.heading_link_button.clone();
+        options.block_copy_buttons = self.block_copy_buttons;
+        options.sandbox = self.sandbox;
+        options.progressive_loading = self.progressive_loading;
+        options.extensions = self.extensions;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typography::Measurement;
+
+    #[test]
+    fn applied_config_reproduces_the_options() {
+        let options = CommonMarkOptions {
+            indentation_spaces: 2,
+            typography: TypographyConfig::recommended(),
+            emoji_shortcodes: false,
+            table_mode: TableMode::Fit,
+            image_captions: ImageCaptions::Title,
+            lazy_images: true,
+            ..Default::default()
+        };
+        let config = CommonMarkConfig::from_options(&options);
+
+        let mut applied = CommonMarkOptions::default();
+        config.apply(&mut applied);
+        assert_eq!(CommonMarkConfig::from_options(&applied), config);
+        assert_eq!(applied.indentation_spaces, 2);
+        assert!(!applied.emoji_shortcodes);
+        assert_eq!(
+            applied.typography.line_height,
+            Some(Measurement::Multiplier(1.5))
+        );
+
+        let mut untouched = CommonMarkOptions::default();
+        CommonMarkConfig::default().apply(&mut untouched);
+        assert_eq!(
+            CommonMarkConfig::from_options(&untouched),
+            CommonMarkConfig::default()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn missing_config_fields_keep_their_defaults() {
+        let config: CommonMarkConfig = serde_json::from_str(
+            r#"{
+                "table_striped": false,
+                "table_mode": "Fit",
+                "typography": { "line_height": { "Multiplier": 1.6 } }
+            }"#,
+        )
+        .unwrap();
+        assert!(!config.table_striped);
+        assert_eq!(config.table_mode, TableMode::Fit);
+        assert_eq!(
+            config.typography.line_height,
+            Some(Measurement::Multiplier(1.6))
+        );
+        assert_eq!(config.typography.paragraph_spacing, None);
+        assert_eq!(config.indentation_spaces, 4);
+        assert!(config.emoji_shortcodes);
+    }
+}
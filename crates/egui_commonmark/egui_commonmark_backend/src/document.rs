@@ -0,0 +1,253 @@
+//! The block structure of a document, separate from rendering.
+//!
+//! Lets hosts build outlines, search indexes and exports from the same parse
+//! the viewer renders instead of scraping the markdown themselves.
+
+use std::ops::Range;
+
+use pulldown_cmark::{CodeBlockKind, Event, Tag, TagEnd};
+
+use crate::anchor::AnchorSlugger;
+use crate::hooks::plain_text;
+
+/// A heading, paragraph, code block or table, with where it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocumentBlock {
+    pub kind: BlockKind,
+    /// Byte range of the block in the source text.
+    pub byte_range: Range<usize>,
+    /// 1-based source line the block starts on.
+    pub line: usize,
+}
+
+/// Text is plain text with the formatting stripped, as in
+/// [`CommonMarkRenderHooks`](crate::hooks::CommonMarkRenderHooks).
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockKind {
+    Heading {
+        /// 1 for `#` through 6 for `######`.
+        level: u8,
+        text: String,
+        /// GitHub-style anchor, deduplicated across the document.
+        anchor: String,
+    },
+    Paragraph {
+        text: String,
+    },
+    CodeBlock {
+        /// Info string of a fenced block, `None` for indented blocks.
+        lang: Option<String>,
+        code: String,
+    },
+    Table {
+        header: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+}
+
+/// Parse `text` into its blocks in document order. Blocks nested in lists,
+/// blockquotes and footnotes are included; the containers themselves are not.
+/// The text of a tight list item, which has no paragraph of its own, is a
+/// paragraph.
+pub fn parse_document(text: &str, options: pulldown_cmark::Options) -> Vec<DocumentBlock> {
+    let mut blocks = Vec::new();
+    let mut slugger = AnchorSlugger::default();
+    let mut line = 1;
+    let mut line_offset = 0;
+    // Start tag and span of the block being collected, with its events.
+    let mut open: Option<(Tag, Range<usize>)> = None;
+    let mut events: Vec<(Event, Range<usize>)> = Vec::new();
+    // List items the parser is in, and whether the open block is the bare
+    // text of one, which ends with the item or the next block in it.
+    let mut items = 0;
+    let mut bare_text = false;
+
+    let mut push_block = |tag: Tag, byte_range: Range<usize>, events: &[(Event, Range<usize>)]| {
+        line += text[line_offset..byte_range.start].matches('\n').count();
+        line_offset = byte_range.start;
+        let kind = block_kind(tag, events, &mut slugger);
+        blocks.push(DocumentBlock {
+            kind,
+            byte_range,
+            line,
+        });
+    };
+
+    for (event, range) in pulldown_cmark::Parser::new_ext(text, options).into_offset_iter() {
+        let ends_bare_text = match &event {
+            Event::End(TagEnd::Item) => true,
+            Event::Start(_) => !is_inline(&event),
+            _ => false,
+        };
+        if bare_text && ends_bare_text {
+            if let Some((tag, first)) = open.take() {
+                let end = events.iter().map(|(_, range)| range.end).max();
+                push_block(tag, first.start..end.unwrap_or(first.end), &events);
+            }
+            bare_text = false;
+        }
+        match event {
+            Event::Start(Tag::Item) => items += 1,
+            Event::End(TagEnd::Item) => items -= 1,
+            Event::Start(
+                tag @ (Tag::Heading { .. } | Tag::Paragraph | Tag::CodeBlock(_) | Tag::Table(_)),
+            ) if open.is_none() => {
+                open = Some((tag, range));
+                events.clear();
+            }
+            Event::End(end) if open.as_ref().is_some_and(|(tag, _)| tag.to_end() == end) => {
+                let Some((tag, byte_range)) = open.take() else {
+                    continue;
+                };
+                push_block(tag, byte_range, &events);
+            }
+            event if open.is_some() => events.push((event, range)),
+            event if items > 0 && is_inline(&event) => {
+                open = Some((Tag::Paragraph, range.clone()));
+                bare_text = true;
+                events.clear();
+                events.push((event, range));
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Whether `event` is part of a paragraph's text rather than a block.
+fn is_inline(event: &Event) -> bool {
+    match event {
+        Event::Text(_)
+        | Event::Code(_)
+        | Event::InlineMath(_)
+        | Event::InlineHtml(_)
+        | Event::FootnoteReference(_)
+        | Event::SoftBreak
+        | Event::HardBreak => true,
+        Event::Start(tag) => matches!(
+            tag,
+            Tag::Emphasis
+                | Tag::Strong
+                | Tag::Strikethrough
+                | Tag::Superscript
+                | Tag::Subscript
+                | Tag::Link { .. }
+                | Tag::Image { .. }
+        ),
+        _ => false,
+    }
+}
+
+fn block_kind(tag: Tag, events: &[(Event, Range<usize>)], slugger: &mut AnchorSlugger) -> BlockKind {
+    match tag {
+        Tag::Heading { level, .. } => {
+            let text = plain_text(events).trim().to_owned();
+            BlockKind::Heading {
+                level: level as u8,
+                anchor: slugger.slug(&text),
+                text,
+            }
+        }
+        Tag::CodeBlock(kind) => BlockKind::CodeBlock {
+            lang: match kind {
+                CodeBlockKind::Fenced(lang) => Some(lang.to_string()),
+                CodeBlockKind::Indented => None,
+            },
+            code: plain_text(events),
+        },
+        Tag::Table(_) => {
+            let mut header = Vec::new();
+            let mut rows = Vec::new();
+            let mut row = Vec::new();
+            let mut cell_start = 0;
+            for (i, (event, _)) in events.iter().enumerate() {
+                match event {
+                    Event::Start(Tag::TableCell) => cell_start = i + 1,
+                    Event::End(TagEnd::TableCell) => {
+                        row.push(plain_text(&events[cell_start..i]));
+                    }
+                    Event::End(TagEnd::TableHead) => header = std::mem::take(&mut row),
+                    Event::End(TagEnd::TableRow) => rows.push(std::mem::take(&mut row)),
+                    _ => {}
+                }
+            }
+            BlockKind::Table { header, rows }
+        }
+        _ => BlockKind::Paragraph {
+            text: plain_text(events),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_come_in_source_order_with_lines() {
+        let md = "# Title\n\nSome *text*.\n\n```rust\nfn f() {}\n```\n\n> ## Title\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n- One *item*\n  - Nested\n- [ ] Task\n";
+        let blocks = parse_document(md, crate::pulldown::parser_options());
+        let kinds: Vec<_> = blocks.iter().map(|b| (b.line, b.kind.clone())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    1,
+                    BlockKind::Heading {
+                        level: 1,
+                        text: "Title".to_owned(),
+                        anchor: "title".to_owned(),
+                    }
+                ),
+                (
+                    3,
+                    BlockKind::Paragraph {
+                        text: "Some text.".to_owned(),
+                    }
+                ),
+                (
+                    5,
+                    BlockKind::CodeBlock {
+                        lang: Some("rust".to_owned()),
+                        code: "fn f() {}\n".to_owned(),
+                    }
+                ),
+                (
+                    9,
+                    BlockKind::Heading {
+                        level: 2,
+                        text: "Title".to_owned(),
+                        anchor: "title-1".to_owned(),
+                    }
+                ),
+                (
+                    11,
+                    BlockKind::Table {
+                        header: vec!["a".to_owned(), "b".to_owned()],
+                        rows: vec![vec!["1".to_owned(), "2".to_owned()]],
+                    }
+                ),
+                (
+                    15,
+                    BlockKind::Paragraph {
+                        text: "One item".to_owned(),
+                    }
+                ),
+                (
+                    16,
+                    BlockKind::Paragraph {
+                        text: "Nested".to_owned(),
+                    }
+                ),
+                (
+                    17,
+                    BlockKind::Paragraph {
+                        text: "Task".to_owned(),
+                    }
+                ),
+            ]
+        );
+        assert_eq!(&md[blocks[1].byte_range.clone()], "Some *text*.\n");
+        assert_eq!(&md[blocks[5].byte_range.clone()], "One *item*");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::misc::{BlockquoteStyle, CodeCopyFormat};
 use crate::typography::TypographyConfig;
 use egui::{self, NumExt, RichText, Sense, TextStyle, Ui, Vec2, epaint};
 
@@ -18,6 +19,143 @@ pub fn paragraph_end_spacing(ui: &mut Ui, typography: &TypographyConfig) {
     typography_spacing(ui, spacing);
 }
 
+/// Indent the first line of a paragraph based on typography configuration.
+#[inline]
+pub fn paragraph_start_indent(ui: &mut Ui, typography: &TypographyConfig) {
+    let font_size = ui.text_style_height(&TextStyle::Body);
+    let indent = typography.resolve_first_line_indent(font_size);
+    if indent > 0.0 {
+        ui.add_space(indent);
+    }
+}
+
+/// A label of `job` whose wrapped rows, but the last, are stretched to the
+/// full width. egui's own labels don't justify text in wrapping layouts, and
+/// its justification ignores the room taken by the widgets before the text,
+/// so the spaces between words are widened here instead.
+pub fn justified_label(ui: &mut Ui, job: std::sync::Arc<egui::text::LayoutJob>) -> egui::Response {
+    let available_width = ui.available_width();
+    if !ui.layout().main_wrap() || !available_width.is_finite() {
+        return ui.label(egui::WidgetText::LayoutJob(job));
+    }
+    let selectable = ui.style().interaction.selectable_labels;
+    let mut sense = Sense::hover();
+    if selectable {
+        sense |= Sense::click_and_drag() - Sense::FOCUSABLE;
+    }
+
+    // Laid out like `Label` does in a wrapping layout: the first row starts
+    // after the previous widget and the others at the left edge
+    let mut job = std::sync::Arc::unwrap_or_clone(job);
+    job.wrap.max_width = available_width;
+    job.first_row_min_height = ui.cursor().height();
+    job.halign = egui::Align::Min;
+    job.justify = false;
+    if let Some(first_section) = job.sections.first_mut() {
+        first_section.leading_space = available_width - ui.available_size_before_wrap().x;
+    }
+    let galley = ui.fonts_mut(|fonts| fonts.layout_job(job.clone()));
+    let (spaces, trailing_space) = justifying_spaces(&job.text, &galley, available_width);
+    let galley = if spaces.is_empty() {
+        galley
+    } else {
+        add_leading_space(&mut job, &spaces);
+        // The spaces the rows end with now stick out, but the next words
+        // still don't fit
+        job.wrap.max_width += trailing_space + 0.5;
+        ui.fonts_mut(|fonts| fonts.layout_job(job))
+    };
+    let pos = egui::pos2(ui.max_rect().left(), ui.cursor().top());
+    let rect = galley.rows[0].rect_without_leading_space();
+    let mut response = ui.allocate_rect(rect.translate(pos.to_vec2()), sense);
+    for row in galley.rows.iter().skip(1) {
+        response |= ui.allocate_rect(row.rect().translate(pos.to_vec2()), sense);
+    }
+
+    if ui.is_rect_visible(response.rect) {
+        let color = ui.visuals().text_color();
+        if selectable {
+            egui::text_selection::LabelSelectionState::label_text_selection(
+                ui,
+                &response,
+                pos,
+                galley,
+                color,
+                egui::Stroke::NONE,
+            );
+        } else {
+            ui.painter().galley(pos, galley, color);
+        }
+    }
+    response
+}
+
+/// Byte offsets in `text` of the runs of spaces between the words of the rows
+/// of `galley` but the last, with the room each gets to fill `width`, and the
+/// widest spaces a row ends with.
+fn justifying_spaces(text: &str, galley: &egui::Galley, width: f32) -> (Vec<(usize, f32)>, f32) {
+    let mut offsets = text.char_indices().map(|(offset, _)| offset);
+    let mut spaces = Vec::new();
+    let mut trailing_space = 0.0_f32;
+    let last = galley.rows.len().saturating_sub(1);
+    for (index, placed) in galley.rows.iter().enumerate() {
+        let glyphs = &placed.row.glyphs;
+        let row_offsets: Vec<usize> = offsets.by_ref().take(glyphs.len()).collect();
+        if placed.ends_with_newline {
+            offsets.next();
+            continue;
+        }
+        let words = glyphs.iter().position(|glyph| !glyph.chr.is_whitespace());
+        let words_end = glyphs.iter().rposition(|glyph| !glyph.chr.is_whitespace());
+        let (Some(begin), Some(end)) = (words, words_end) else {
+            continue;
+        };
+        if index == last || row_offsets.len() != glyphs.len() {
+            continue;
+        }
+        let runs: Vec<usize> = (begin + 1..end)
+            .filter(|&i| glyphs[i].chr.is_whitespace() && !glyphs[i - 1].chr.is_whitespace())
+            .collect();
+        let right = placed.pos.x + glyphs[end].max_x();
+        trailing_space = trailing_space.max(glyphs[glyphs.len() - 1].max_x() - glyphs[end].max_x());
+        if runs.is_empty() || right >= width {
+            continue;
+        }
+        let room = (width - right) / runs.len() as f32;
+        spaces.extend(runs.into_iter().map(|i| (row_offsets[i], room)));
+    }
+    (spaces, trailing_space)
+}
+
+/// Split the sections of `job` at the byte offsets of `spaces`, sorted, and
+/// add their room to the leading space of the sections starting there.
+fn add_leading_space(job: &mut egui::text::LayoutJob, spaces: &[(usize, f32)]) {
+    let mut spaces = spaces.iter().peekable();
+    let mut sections = Vec::with_capacity(job.sections.len() + spaces.len());
+    for section in std::mem::take(&mut job.sections) {
+        let mut start = section.byte_range.start;
+        let mut leading_space = section.leading_space;
+        while let Some(&(at, room)) = spaces.next_if(|(at, _)| *at < section.byte_range.end) {
+            if at > start {
+                sections.push(egui::text::LayoutSection {
+                    leading_space,
+                    byte_range: start..at,
+                    format: section.format.clone(),
+                });
+                start = at;
+                leading_space = 0.0;
+            }
+            leading_space += room;
+        }
+        sections.push(egui::text::LayoutSection {
+            leading_space,
+            byte_range: start..section.byte_range.end,
+            format: section.format,
+        });
+    }
+    job.sections = sections;
+}
+
 /// Add heading spacing above based on typography configuration.
 #[inline]
 pub fn heading_start_spacing(ui: &mut Ui, typography: &TypographyConfig) {
@@ -53,7 +191,7 @@ pub fn newline(ui: &mut Ui) {
     ui.label("\n");
 }
 
-pub fn bullet_point(ui: &mut Ui, row_height: f32) {
+pub fn bullet_point(ui: &mut Ui, row_height: f32, color: egui::Color32) {
     // The list row is `Align::BOTTOM` and as tall as the item text, which carries
     // the 1.5× accessibility line-height. Size the marker box to that same
     // row_height so it bottom-aligns identically to the text; keep the dot radius
@@ -65,14 +203,11 @@ pub fn bullet_point(ui: &mut Ui, row_height: f32) {
         egui::vec2(width_body_space(ui) * 4.0, row_height.max(raw)),
         Sense::hover(),
     );
-    ui.painter().circle_filled(
-        marker_center(rect, raw),
-        raw / 6.0,
-        ui.visuals().strong_text_color(),
-    );
+    ui.painter()
+        .circle_filled(marker_center(rect, raw), raw / 6.0, color);
 }
 
-pub fn bullet_point_hollow(ui: &mut Ui, row_height: f32) {
+pub fn bullet_point_hollow(ui: &mut Ui, row_height: f32, color: egui::Color32) {
     let raw = height_body(ui);
     let (rect, _) = ui.allocate_exact_size(
         egui::vec2(width_body_space(ui) * 4.0, row_height.max(raw)),
@@ -82,11 +217,32 @@ pub fn bullet_point_hollow(ui: &mut Ui, row_height: f32) {
         marker_center(rect, raw),
         raw / 6.0,
         egui::Color32::TRANSPARENT,
-        egui::Stroke::new(0.6, ui.visuals().strong_text_color()),
+        egui::Stroke::new(0.6, color),
     );
 }
 
-pub fn number_point(ui: &mut Ui, number: &str, row_height: f32) {
+/// A bullet drawn with `glyph` instead of a circle.
+pub fn bullet_glyph(ui: &mut Ui, glyph: char, row_height: f32, color: egui::Color32) {
+    let raw = height_body(ui);
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(width_body_space(ui) * 4.0, row_height.max(raw)),
+        Sense::hover(),
+    );
+    ui.painter().text(
+        marker_center(rect, raw),
+        egui::Align2::CENTER_CENTER,
+        glyph,
+        TextStyle::Body.resolve(ui.style()),
+        color,
+    );
+}
+
+pub fn number_point(
+    ui: &mut Ui,
+    number: impl std::fmt::Display,
+    row_height: f32,
+    color: egui::Color32,
+) {
     let raw = height_body(ui);
     let (rect, _) = ui.allocate_exact_size(
         egui::vec2(width_body_space(ui) * 4.0, row_height.max(raw)),
@@ -97,7 +253,7 @@ pub fn number_point(ui: &mut Ui, number: &str, row_height: f32) {
         egui::Align2::RIGHT_CENTER,
         format!("{number}."),
         TextStyle::Body.resolve(ui.style()),
-        ui.visuals().strong_text_color(),
+        color,
     );
 }
 
@@ -110,8 +266,238 @@ fn marker_center(rect: egui::Rect, raw: f32) -> egui::Pos2 {
 }
 
 #[inline]
-pub fn footnote_start(ui: &mut Ui, note: &str) {
-    ui.label(RichText::new(note).raised().strong().small());
+pub fn footnote_start(ui: &mut Ui, note: &str) -> egui::Response {
+    ui.add(egui::Label::new(RichText::new(note).raised().strong().small()).sense(Sense::click()))
+}
+
+/// A square filled with `color`, as tall as the text, drawn after a color code.
+pub fn color_swatch(ui: &mut Ui, color: egui::Color32) {
+    let size = ui.text_style_height(&TextStyle::Body) * 0.8;
+    let (rect, _) = ui.allocate_exact_size(Vec2::splat(size), Sense::hover());
+    if ui.is_rect_visible(rect) {
+        let painter = ui.painter();
+        painter.rect_filled(rect, 2.0, color);
+        painter.rect_stroke(
+            rect,
+            2.0,
+            ui.visuals().widgets.noninteractive.fg_stroke,
+            egui::StrokeKind::Inside,
+        );
+    }
+}
+
+/// Stand-in for an image that failed to load: a warning sign with the alt
+/// text and uri, and the error on hover.
+pub fn image_error_placeholder(ui: &mut Ui, uri: &str, alt_text: &str, error: &str) {
+    egui::Frame::group(ui.style())
+        .show(ui, |ui| {
+            ui.label(RichText::new("⚠").color(ui.visuals().warn_fg_color));
+            ui.add_space(4.0);
+            if !alt_text.is_empty() {
+                ui.label(alt_text);
+                ui.add_space(4.0);
+            }
+            ui.label(RichText::new(uri).small().weak());
+        })
+        .response
+        .on_hover_text(error);
+}
+
+/// Stand-in for an image a sandboxed document may not load: a lock with the
+/// alt text and `reason`, and the uri on hover.
+pub fn blocked_image_placeholder(ui: &mut Ui, uri: &str, alt_text: &str, reason: &str) {
+    // `data:` uris hold the whole image
+    let uri: String = uri.chars().take(200).collect();
+    egui::Frame::group(ui.style())
+        .show(ui, |ui| {
+            ui.label("🔒");
+            ui.add_space(4.0);
+            if !alt_text.is_empty() {
+                ui.label(alt_text);
+                ui.add_space(4.0);
+            }
+            ui.label(RichText::new(reason).small().weak());
+        })
+        .response
+        .on_hover_text(uri);
+}
+
+/// Stand-in for an image that is still downloading: a spinner with the
+/// bytes received so far, and a progress bar when the size is known.
+pub fn image_download_placeholder(
+    ui: &mut Ui,
+    max_width: f32,
+    (received, total): (usize, Option<usize>),
+) {
+    ui.allocate_ui(egui::vec2(max_width, 0.0), |ui| {
+        egui::Frame::group(ui.style()).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                let kib = |bytes: usize| bytes.div_ceil(1024);
+                match total {
+                    Some(total) if total > 0 => {
+                        ui.add(
+                            egui::ProgressBar::new(received as f32 / total as f32)
+                                .text(format!("{} / {} KiB", kib(received), kib(total))),
+                        );
+                    }
+                    _ => {
+                        ui.weak(format!("{} KiB", kib(received)));
+                    }
+                }
+            });
+        });
+    });
+}
+
+/// Stand-in for a document that is still being parsed or laid out: a
+/// spinner, with a progress bar once the layout has started.
+pub fn document_loading_placeholder(ui: &mut Ui, progress: Option<f32>) {
+    egui::Frame::group(ui.style()).show(ui, |ui| {
+        ui.horizontal(|ui| {
+            ui.spinner();
+            match progress {
+                Some(progress) => {
+                    ui.add(
+                        egui::ProgressBar::new(progress)
+                            .desired_width(240.0)
+                            .text("Laying out document…"),
+                    );
+                }
+                None => {
+                    ui.weak("Parsing document…");
+                }
+            }
+        });
+    });
+}
+
+/// Stand-in for media egui can't play: a thumbnail when there is one, or
+/// an icon, with the alt text and a button to open `uri` in another app.
+pub fn media_card(
+    ui: &mut Ui,
+    cache: &mut crate::misc::CommonMarkCache,
+    options: &crate::misc::CommonMarkOptions,
+    uri: &str,
+    kind: &crate::media::MediaKind,
+    alt_text: &str,
+    max_width: f32,
+) {
+    egui::Frame::group(ui.style()).show(ui, |ui| {
+        ui.set_max_width(max_width);
+        ui.vertical(|ui| {
+            let thumbnail = kind
+                .thumbnail_uri()
+                .filter(|thumbnail| options.sandbox.blocked_image(thumbnail).is_none());
+            if let Some(thumbnail) = &thumbnail {
+                let response = ui.add(
+                    egui::Image::from_uri(thumbnail)
+                        .max_width(max_width.min(480.0))
+                        .corner_radius(4.0)
+                        .sense(Sense::click()),
+                );
+                ui.painter().text(
+                    response.rect.center(),
+                    egui::Align2::CENTER_CENTER,
+                    "▶",
+                    egui::FontId::proportional(40.0),
+                    egui::Color32::from_white_alpha(220),
+                );
+                if response.clicked() {
+                    cache.open_external(ui.ctx(), options, uri);
+                }
+                response.on_hover_cursor(egui::CursorIcon::PointingHand);
+            }
+            ui.horizontal(|ui| {
+                if thumbnail.is_none() {
+                    ui.label(RichText::new(kind.icon()).size(24.0));
+                }
+                ui.vertical(|ui| {
+                    if !alt_text.is_empty() {
+                        ui.label(alt_text);
+                    }
+                    ui.label(RichText::new(uri).small().weak());
+                });
+            });
+            if ui.button("Open externally").clicked() {
+                cache.open_external(ui.ctx(), options, uri);
+            }
+        });
+    });
+}
+
+/// Small muted text centered under an image.
+pub fn image_caption(ui: &mut Ui, text: RichText) {
+    ui.add(egui::Label::new(text.small().weak()).wrap().halign(egui::Align::Center));
+}
+
+/// `done/total` next to a list with checkboxes.
+pub fn task_list_progress_label(ui: &mut Ui, done: usize, total: usize) {
+    let text = RichText::new(format!("{done}/{total}")).small();
+    if done == total {
+        ui.label(text.color(ui.visuals().strong_text_color()));
+    } else {
+        ui.label(text.weak());
+    }
+}
+
+/// Shown in place of a part of the document that failed to render.
+pub fn render_error_placeholder(ui: &mut Ui, message: &str) {
+    let color = ui.visuals().error_fg_color;
+    egui::Frame::new()
+        .stroke(egui::Stroke::new(1.0, color.gamma_multiply(0.5)))
+        .corner_radius(4.0)
+        .inner_margin(egui::Margin::symmetric(6, 2))
+        .show(ui, |ui| {
+            ui.label(RichText::new("⚠ Couldn't render this part").color(color))
+                .on_hover_text(message);
+        });
+}
+
+/// A dashed line across the available width with "page break" in the middle,
+/// where a printed document starts a new page.
+pub fn page_break(ui: &mut Ui) {
+    let color = ui.visuals().weak_text_color();
+    let font = TextStyle::Small.resolve(ui.style());
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width(), ui.text_style_height(&TextStyle::Body)),
+        egui::Sense::hover(),
+    );
+    let label = ui.painter().layout_no_wrap("page break".to_owned(), font, color);
+    let gap = label.size().x / 2.0 + 6.0;
+    let stroke = egui::Stroke::new(1.0, color);
+    let y = rect.center().y;
+    for (from, to) in [(rect.left(), rect.center().x - gap), (rect.center().x + gap, rect.right())] {
+        ui.painter()
+            .extend(egui::Shape::dashed_line(&[egui::pos2(from, y), egui::pos2(to, y)], stroke, 4.0, 4.0));
+    }
+    ui.painter().galley(rect.center() - label.size() / 2.0, label, color);
+}
+
+/// The number of a display equation, in parentheses at the right of `rect`
+/// like LaTeX sets it.
+pub fn equation_number(ui: &Ui, rect: egui::Rect, number: &str) {
+    ui.painter().text(
+        rect.right_center(),
+        egui::Align2::RIGHT_CENTER,
+        format!("({number})"),
+        TextStyle::Body.resolve(ui.style()),
+        ui.visuals().text_color(),
+    );
+}
+
+/// The `↩` after a footnote definition that leads back to its `nth` (0-based)
+/// reference.
+pub fn footnote_backlink(ui: &mut Ui, nth: usize) -> egui::Response {
+    let text = if nth == 0 {
+        "↩".to_owned()
+    } else {
+        format!("↩{}", nth + 1)
+    };
+    ui.add(
+        egui::Label::new(RichText::new(text).color(ui.visuals().hyperlink_color))
+            .sense(Sense::click()),
+    )
 }
 
 pub fn footnote(ui: &mut Ui, text: &str) {
@@ -137,10 +523,55 @@ fn width_body_space(ui: &Ui) -> f32 {
     ui.fonts_mut(|f| f.glyph_width(&id, ' '))
 }
 
+/// Background of search matches in the document, or of the active one.
+pub fn search_match_color(ui: &Ui, active: bool) -> egui::Color32 {
+    match (active, ui.visuals().dark_mode) {
+        (false, true) => egui::Color32::from_rgb(102, 92, 46),
+        (false, false) => egui::Color32::from_rgb(255, 229, 127),
+        (true, true) => egui::Color32::from_rgb(156, 107, 26),
+        (true, false) => egui::Color32::from_rgb(255, 167, 38),
+    }
+}
+
+/// Search matches in a code block, as byte ranges of its text.
+#[derive(Clone, Debug, Default)]
+pub struct CodeMatches {
+    pub ranges: Vec<std::ops::Range<usize>>,
+    /// Index of the active match in `ranges`, scrolled into view sideways
+    /// when it becomes active
+    pub active: Option<usize>,
+}
+
 /// Enhanced/specialized version of egui's code blocks. This one features copy button and borders.
 /// Uses selectable Label instead of TextEdit to allow text selection across code block boundaries.
-pub fn code_block(ui: &mut Ui, text: &str, layout_job: egui::text::LayoutJob, max_width: f32, id: egui::Id) {
+///
+/// With a `header`, the fence language or title (empty for none) and the
+/// copy button are shown in a strip above the code instead of overlaying the
+/// copy button on it. Lines in `highlight_lines` (1-based) get a tinted
+/// background. A dropdown beside the copy button picks what it copies, see
+/// [`CodeCopy`].
+///
+/// Returns the screen y of the top of the active match of `matches`, if
+/// the block has one.
+#[allow(clippy::too_many_arguments)]
+pub fn code_block(
+    ui: &mut Ui,
+    text: &str,
+    copy: &CodeCopy,
+    layout_job: egui::text::LayoutJob,
+    max_width: f32,
+    id: egui::Id,
+    line_numbers: bool,
+    header: Option<&str>,
+    highlight_lines: &[std::ops::RangeInclusive<usize>],
+    matches: &CodeMatches,
+) -> Option<f32> {
     let text = text.strip_suffix('\n').unwrap_or(text);
+    let mut active_y = None;
+    let gutter = line_numbers.then(|| line_number_gutter(ui, text, &layout_job));
+    let copy_state_id = id.with("_copied");
+    let ctx = ui.ctx().clone();
+    let copied = || copy.text(text, code_copy_format(&ctx, copy.default_format));
 
     // Reserve space for background drawing
     let where_to_put_background = ui.painter().add(egui::Shape::Noop);
@@ -151,18 +582,91 @@ pub fn code_block(ui: &mut Ui, text: &str, layout_job: egui::text::LayoutJob, ma
     let frame_response = egui::Frame::new()
         .inner_margin(egui::Margin::same(8))
         .show(ui, |ui| {
-            // Force all code blocks to fill the available width
-            ui.set_min_width(ui.available_width());
-            egui::ScrollArea::horizontal()
-                .id_salt(id)
-                .show(ui, |ui| {
-                    ui.add(
-                        egui::Label::new(layout_job)
-                            .selectable(true)
-                            .wrap_mode(egui::TextWrapMode::Extend),
-                    )
+            ui.vertical(|ui| {
+                // Force all code blocks to fill the available width
+                ui.set_min_width(ui.available_width());
+                if let Some(lang) = header {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new(lang).small().weak());
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            copy_button(ui, copied, "🗐", copy_state_id, |ui, button| ui.add(button));
+                            code_copy_format_menu(ui, copy.default_format, |ui, button| ui.add(button));
+                        });
+                    });
+                    ui.separator();
+                }
+                ui.horizontal_top(|ui| {
+                    // The gutter stays outside the scroll area so the numbers remain
+                    // visible while scrolling sideways.
+                    if let Some(gutter) = gutter {
+                        ui.add(egui::Label::new(gutter).selectable(false));
+                        ui.separator();
+                    }
+                    egui::ScrollArea::horizontal()
+                        .id_salt(id)
+                        .show(ui, |ui| {
+                            let galley = ui.fonts_mut(|f| f.layout_job(layout_job));
+                            let where_to_put_highlight = ui.painter().add(egui::Shape::Noop);
+                            let response = ui.add(
+                                egui::Label::new(galley.clone())
+                                    .selectable(true)
+                                    .wrap_mode(egui::TextWrapMode::Extend),
+                            );
+                            // Highlighted lines span the visible width, not just their text
+                            let right = response.rect.right().max(ui.clip_rect().right());
+                            let fill = ui.visuals().selection.bg_fill.gamma_multiply(0.35);
+                            let highlights = highlighted_rows(&galley, highlight_lines)
+                                .into_iter()
+                                .map(|row| {
+                                    let row = row.translate(response.rect.min.to_vec2());
+                                    let row = egui::Rect::from_x_y_ranges(
+                                        response.rect.left()..=right,
+                                        row.y_range(),
+                                    );
+                                    egui::Shape::rect_filled(row, 0.0, fill)
+                                })
+                                .collect::<Vec<_>>();
+                            let char_at = |byte: usize| {
+                                egui::text::CCursor::new(text[..byte.min(text.len())].chars().count())
+                            };
+                            let mut highlights = highlights;
+                            for (i, range) in matches.ranges.iter().enumerate() {
+                                let start = galley.pos_from_cursor(char_at(range.start));
+                                let end = galley.pos_from_cursor(char_at(range.end));
+                                let rect = egui::Rect::from_min_max(start.min, end.max)
+                                    .translate(response.rect.min.to_vec2());
+                                let active = matches.active == Some(i);
+                                highlights.push(egui::Shape::rect_filled(
+                                    rect,
+                                    0.0,
+                                    search_match_color(ui, active),
+                                ));
+                                if !active {
+                                    continue;
+                                }
+                                active_y = Some(rect.top());
+                                // Sideways only once per match, so the block can
+                                // still be scrolled away from it
+                                let revealed_id = id.with("_revealed_match");
+                                let revealed = ui.data(|d| d.get_temp::<(usize, usize)>(revealed_id));
+                                if revealed != Some((range.start, range.end)) {
+                                    ui.data_mut(|d| d.insert_temp(revealed_id, (range.start, range.end)));
+                                    // Only reaches this scroll area: it takes the
+                                    // vertical target along with the horizontal one
+                                    ui.scroll_to_rect(rect, None);
+                                }
+                            }
+                            if matches.active.is_none() {
+                                ui.data_mut(|d| d.remove::<(usize, usize)>(id.with("_revealed_match")));
+                            }
+                            ui.painter().set(where_to_put_highlight, highlights);
+                            response
+                        })
+                        .inner
                 })
                 .inner
+            })
+            .inner
         });
 
     let frame_rect = frame_response.response.rect;
@@ -179,48 +683,253 @@ pub fn code_block(ui: &mut Ui, text: &str, layout_job: egui::text::LayoutJob, ma
         ),
     );
 
+    if header.is_some() {
+        return active_y;
+    }
+
     // Copy icon
     let spacing = &ui.style().spacing;
     let position = egui::pos2(
         frame_rect.right_top().x - spacing.icon_width * 0.5 - spacing.button_padding.x,
         frame_rect.right_top().y + spacing.button_padding.y * 2.0,
     );
-
-    // Check if we should show ✔ instead of 🗐 if the text was copied and the mouse is hovered
-    let persistent_id = ui.make_persistent_id(frame_response.response.id);
-    let copied_icon = ui.memory_mut(|m| *m.data.get_temp_mut_or_default::<bool>(persistent_id));
-
-    let copy_button = ui
-        .put(
+    copy_button(ui, copied, "🗐", copy_state_id, |ui, button| {
+        ui.put(
             egui::Rect {
                 min: position,
                 max: position,
             },
-            egui::Button::new(if copied_icon { "✔" } else { "🗐" })
-                .small()
-                .frame(false)
-                .fill(egui::Color32::TRANSPARENT),
+            button,
         )
-        .on_hover_cursor(
-            ui.visuals()
-                .interact_cursor
-                .unwrap_or(egui::CursorIcon::Default),
-        );
+    });
+    let spacing = &ui.style().spacing;
+    let position = position - egui::vec2(spacing.icon_width + spacing.item_spacing.x, 0.0);
+    code_copy_format_menu(ui, copy.default_format, |ui, button| {
+        ui.put(
+            egui::Rect {
+                min: position,
+                max: position,
+            },
+            button,
+        )
+    });
+    active_y
+}
+
+/// What the copy button of a code block copies.
+#[derive(Clone, Debug, Default)]
+pub struct CodeCopy<'a> {
+    /// The commands of a shell session, copied instead of the whole session
+    /// in [`CodeCopyFormat::Code`]
+    pub commands: Option<&'a str>,
+    /// Language of the fence, `None` for an indented block
+    pub lang: Option<&'a str>,
+    /// Format copied until another one is picked from the dropdown
+    pub default_format: CodeCopyFormat,
+}
+
+impl CodeCopy<'_> {
+    /// The text copied from the code block `text` in `format`.
+    fn text(&self, text: &str, format: CodeCopyFormat) -> String {
+        match format {
+            CodeCopyFormat::Code => self.commands.unwrap_or(text).to_owned(),
+            CodeCopyFormat::Fenced => {
+                // A fence longer than any backtick run in the code
+                let longest = text
+                    .split(|c| c != '`')
+                    .map(str::len)
+                    .max()
+                    .unwrap_or(0);
+                let fence = "`".repeat(longest.max(2) + 1);
+                let lang = self.lang.unwrap_or("");
+                format!("{fence}{lang}\n{text}\n{fence}\n")
+            }
+            CodeCopyFormat::LineNumbers => {
+                let digits = text.split('\n').count().to_string().len();
+                text.split('\n')
+                    .enumerate()
+                    .map(|(i, line)| format!("{:>digits$}  {line}\n", i + 1))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Where the format picked from a code block's copy dropdown is kept; the
+/// choice applies to every code block.
+fn code_copy_format_id() -> egui::Id {
+    egui::Id::new("code_copy_format")
+}
+
+fn code_copy_format(ctx: &egui::Context, default: CodeCopyFormat) -> CodeCopyFormat {
+    ctx.data(|d| d.get_temp(code_copy_format_id())).unwrap_or(default)
+}
+
+/// Small dropdown button picking what the copy buttons of code blocks copy.
+/// `add` places the button.
+fn code_copy_format_menu(
+    ui: &mut Ui,
+    default: CodeCopyFormat,
+    add: impl FnOnce(&mut Ui, egui::Button<'static>) -> egui::Response,
+) {
+    let button = egui::Button::new(RichText::new("⏷").weak())
+        .small()
+        .frame(false)
+        .fill(egui::Color32::TRANSPARENT);
+    let response = add(ui, button).on_hover_text("What the copy button copies");
+    egui::Popup::menu(&response).show(|ui| {
+        let mut format = code_copy_format(ui.ctx(), default);
+        let before = format;
+        ui.radio_value(&mut format, CodeCopyFormat::Code, "Code");
+        ui.radio_value(&mut format, CodeCopyFormat::Fenced, "Code in a fence");
+        ui.radio_value(&mut format, CodeCopyFormat::LineNumbers, "Code with line numbers");
+        if format != before {
+            ui.data_mut(|d| d.insert_temp(code_copy_format_id(), format));
+            ui.close();
+        }
+    });
+}
+
+/// Rects, relative to the galley, of the rows of the 1-based `lines`.
+fn highlighted_rows(
+    galley: &egui::Galley,
+    lines: &[std::ops::RangeInclusive<usize>],
+) -> Vec<egui::Rect> {
+    let mut rects = Vec::new();
+    let mut line = 1;
+    for row in &galley.rows {
+        if lines.iter().any(|r| r.contains(&line)) {
+            rects.push(row.rect());
+        }
+        if row.ends_with_newline {
+            line += 1;
+        }
+    }
+    rects
+}
+
+/// Link icon right of a heading drawn at `heading_rect`, shown while it is
+/// hovered, that copies `link` to the clipboard.
+pub fn heading_link_button(ui: &mut Ui, heading_rect: egui::Rect, link: &str) {
+    let size = ui.spacing().interact_size.y;
+    let button_rect = egui::Rect::from_min_size(
+        egui::pos2(heading_rect.right() + ui.spacing().item_spacing.x, heading_rect.top()),
+        egui::vec2(size, size.min(heading_rect.height())),
+    );
+    if !ui.rect_contains_pointer(heading_rect.union(button_rect)) {
+        return;
+    }
+    let state_id = ui.id().with(("heading_link", link));
+    copy_button(ui, || link.to_owned(), "🔗", state_id, |ui, button| {
+        ui.put(button_rect, button)
+            .on_hover_text(format!("Copy link: {link}"))
+    });
+}
+
+/// Copy icon in the top right corner of the block drawn in `block_rect`,
+/// shown while the block is hovered, copying `source`.
+pub fn block_copy_button(ui: &mut Ui, block_rect: egui::Rect, source: &str) {
+    if !ui.rect_contains_pointer(block_rect) {
+        return;
+    }
+    let size = ui.spacing().interact_size.y;
+    let button_rect = egui::Rect::from_min_size(
+        egui::pos2(block_rect.right() - size, block_rect.top()),
+        egui::vec2(size, size.min(block_rect.height())),
+    );
+    let state_id = ui.id().with(("block_copy", block_rect.top().to_bits()));
+    copy_button(ui, || source.to_owned(), "🗐", state_id, |ui, button| {
+        ui.put(button_rect, button).on_hover_text("Copy markdown")
+    });
+}
+
+/// A table copy menu entry: its label and the text it copies.
+type TableCopyFormat = (&'static str, fn(&crate::misc::TableCells) -> String);
+
+/// "Copy as CSV", "Copy as TSV" and "Copy as Markdown" when the table drawn
+/// in `table_rect` is right-clicked, even on text inside it.
+pub fn table_copy_menu(ui: &Ui, table_rect: egui::Rect, id: egui::Id, cells: &crate::misc::TableCells) {
+    let response = ui.interact(table_rect, id.with("_copy_menu"), Sense::hover());
+    let opened = response.contains_pointer() && ui.input(|i| i.pointer.secondary_clicked());
+    egui::Popup::menu(&response)
+        .open_memory(opened.then_some(egui::SetOpenCommand::Bool(true)))
+        .at_pointer_fixed()
+        .show(|ui| {
+            let formats: [TableCopyFormat; 3] = [
+                ("Copy as CSV", crate::misc::TableCells::to_csv),
+                ("Copy as TSV", crate::misc::TableCells::to_tsv),
+                ("Copy as Markdown", crate::misc::TableCells::to_markdown),
+            ];
+            for (label, format) in formats {
+                if ui.button(label).clicked() {
+                    ui.ctx().copy_text(format(cells));
+                    ui.close();
+                }
+            }
+        });
+}
+
+/// Button copying `text` to the clipboard. Shows ✔ instead of `icon` after a
+/// click until the pointer leaves it, remembered under `state_id`. `add`
+/// places the button.
+fn copy_button(
+    ui: &mut Ui,
+    text: impl FnOnce() -> String,
+    icon: &'static str,
+    state_id: egui::Id,
+    add: impl FnOnce(&mut Ui, egui::Button<'static>) -> egui::Response,
+) {
+    let copied_icon = ui.memory_mut(|m| *m.data.get_temp_mut_or_default::<bool>(state_id));
+
+    let button = egui::Button::new(if copied_icon { "✔" } else { icon })
+        .small()
+        .frame(false)
+        .fill(egui::Color32::TRANSPARENT);
+    let copy_button = add(ui, button).on_hover_cursor(
+        ui.visuals()
+            .interact_cursor
+            .unwrap_or(egui::CursorIcon::Default),
+    );
 
     // Update icon state in persistent memory
     if copied_icon && !copy_button.hovered() {
-        ui.memory_mut(|m| *m.data.get_temp_mut_or_default(persistent_id) = false);
+        ui.memory_mut(|m| *m.data.get_temp_mut_or_default(state_id) = false);
     }
     if !copied_icon && copy_button.clicked() {
-        ui.memory_mut(|m| *m.data.get_temp_mut_or_default(persistent_id) = true);
+        ui.memory_mut(|m| *m.data.get_temp_mut_or_default(state_id) = true);
     }
 
     // Copy full code block text when button clicked
     if copy_button.clicked() {
-        ui.ctx().copy_text(text.to_owned());
+        ui.ctx().copy_text(text());
     }
 }
 
+/// Right-aligned line numbers for `text`, in the code's font and line height
+/// so each number lines up with its line of `layout_job`.
+fn line_number_gutter(
+    ui: &Ui,
+    text: &str,
+    layout_job: &egui::text::LayoutJob,
+) -> egui::text::LayoutJob {
+    let lines = text.split('\n').count();
+    let digits = lines.to_string().len();
+    let numbers = (1..=lines)
+        .map(|n| format!("{n:>digits$}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut format = egui::TextFormat::simple(
+        TextStyle::Monospace.resolve(ui.style()),
+        ui.visuals().weak_text_color(),
+    );
+    format.line_height = layout_job
+        .sections
+        .first()
+        .and_then(|section| section.format.line_height);
+    egui::text::LayoutJob::single_section(numbers, format)
+}
+
 // Stripped down version of egui's Checkbox. The only difference is that this
 // creates a noninteractive checkbox. ui.add_enabled could have been used instead,
 // but it makes the checkbox too grey.
@@ -275,30 +984,201 @@ impl egui::Widget for ImmutableCheckbox<'_> {
 }
 
 pub fn blockquote(ui: &mut Ui, accent: egui::Color32, add_contents: impl FnOnce(&mut Ui)) {
+    styled_blockquote(ui, accent, &BlockquoteStyle::default(), add_contents);
+}
+
+/// The line of a collapsed quote toggling whether its text is shown, in the
+/// color of its bar. Returns whether it was clicked.
+pub fn quoted_text_toggle(ui: &mut Ui, accent: egui::Color32, open: bool) -> bool {
+    newline(ui);
+    let text = if open {
+        "▼ hide quoted text"
+    } else {
+        "▶ show quoted text"
+    };
+    let clicked = ui
+        .add(egui::Label::new(RichText::new(text).color(accent).small()).sense(Sense::click()))
+        .on_hover_cursor(egui::CursorIcon::PointingHand)
+        .clicked();
+    newline(ui);
+    clicked
+}
+
+/// A [`blockquote`] with its bar and background drawn as `style` says.
+pub fn styled_blockquote(
+    ui: &mut Ui,
+    accent: egui::Color32,
+    style: &BlockquoteStyle,
+    add_contents: impl FnOnce(&mut Ui),
+) {
     let start = ui.painter().add(egui::Shape::Noop);
+    // The bar goes on the side the text starts from
+    let rtl = ui.layout().prefer_right_to_left();
+    let margin = if rtl {
+        egui::Margin {
+            right: 10,
+            ..Default::default()
+        }
+    } else {
+        egui::Margin {
+            left: 10,
+            ..Default::default()
+        }
+    };
     let response = egui::Frame::new()
         // offset the frame so that we can use the space for the horizontal line and other stuff
         // By not using a separator we have better control
-        .outer_margin(egui::Margin {
-            left: 10,
-            ..Default::default()
-        })
+        .outer_margin(margin)
         .show(ui, add_contents)
         .response;
 
-    // FIXME: Add some rounding
-
-    ui.painter().set(
-        start,
-        egui::epaint::Shape::line_segment(
-            [
-                egui::pos2(response.rect.left_top().x, response.rect.left_top().y + 5.0),
-                egui::pos2(
-                    response.rect.left_bottom().x,
-                    response.rect.left_bottom().y - 5.0,
-                ),
-            ],
-            egui::Stroke::new(3.0, accent),
-        ),
+    let rect = response.rect;
+    let half_width = style.bar_width / 2.0;
+    let bar_x = if rtl { rect.right() } else { rect.left() };
+    let bar = egui::Rect::from_min_max(
+        egui::pos2(bar_x - half_width, rect.top() + 5.0),
+        egui::pos2(bar_x + half_width, rect.bottom() - 5.0),
+    );
+    let bar = egui::Shape::rect_filled(
+        bar,
+        egui::CornerRadius::same(style.rounding.min(half_width as u8)),
+        accent,
     );
+    let shape = match style.background {
+        Some(fill) => {
+            let background = if rtl {
+                rect.with_max_x(rect.right() + half_width)
+            } else {
+                rect.with_min_x(rect.left() - half_width)
+            };
+            egui::Shape::Vec(vec![
+                egui::Shape::rect_filled(
+                    background,
+                    egui::CornerRadius::same(style.rounding),
+                    fill,
+                ),
+                bar,
+            ])
+        }
+        None => bar,
+    };
+    ui.painter().set(start, shape);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_copies_plain_fenced_or_numbered() {
+        let copy = CodeCopy {
+            commands: Some("ls"),
+            lang: Some("console"),
+            default_format: CodeCopyFormat::Code,
+        };
+        let text = "$ ls\nREADME.md ```x```";
+        assert_eq!(copy.text(text, CodeCopyFormat::Code), "ls");
+        assert_eq!(
+            copy.text(text, CodeCopyFormat::Fenced),
+            "````console\n$ ls\nREADME.md ```x```\n````\n"
+        );
+        let numbered = CodeCopy::default().text(&format!("{}y", "x\n".repeat(9)), CodeCopyFormat::LineNumbers);
+        assert!(numbered.starts_with(" 1  x\n"));
+        assert!(numbered.ends_with("10  y\n"));
+    }
+
+    #[test]
+    fn line_numbers_follow_the_code_lines() {
+        egui::__run_test_ui(|ui| {
+            let format = egui::TextFormat {
+                line_height: Some(21.0),
+                ..Default::default()
+            };
+            let code = (1..=10)
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let job = egui::text::LayoutJob::single_section(code.clone(), format);
+
+            let gutter = line_number_gutter(ui, &code, &job);
+            assert_eq!(gutter.text.lines().next(), Some(" 1"));
+            assert_eq!(gutter.text.lines().count(), 10);
+            assert_eq!(gutter.sections[0].format.line_height, Some(21.0));
+        });
+    }
+
+    #[test]
+    fn code_block_header_sits_above_the_code() {
+        egui::__run_test_ui(|ui| {
+            let heights: Vec<f32> = [None, Some("rust")]
+                .into_iter()
+                .enumerate()
+                .map(|(i, header)| {
+                    let job = egui::text::LayoutJob::simple_singleline(
+                        "fn main() {}".to_owned(),
+                        TextStyle::Monospace.resolve(ui.style()),
+                        egui::Color32::WHITE,
+                    );
+                    let id = egui::Id::new(i);
+                    ui.scope(|ui| {
+                        code_block(ui, "fn main() {}\n", &CodeCopy::default(), job, 400.0, id, false, header, &[], &CodeMatches::default())
+                    })
+                    .response
+                    .rect
+                    .height()
+                })
+                .collect();
+            assert!(heights[1] > heights[0]);
+        });
+    }
+
+    #[test]
+    fn highlighted_lines_map_to_their_rows() {
+        egui::__run_test_ui(|ui| {
+            let job = egui::text::LayoutJob::simple(
+                "a\nb\nc\nd\n".to_owned(),
+                TextStyle::Monospace.resolve(ui.style()),
+                egui::Color32::WHITE,
+                f32::INFINITY,
+            );
+            let galley = ui.fonts_mut(|f| f.layout_job(job));
+            let rows = highlighted_rows(&galley, &[2..=3]);
+            assert_eq!(rows, [galley.rows[1].rect(), galley.rows[2].rect()]);
+        });
+    }
+
+    #[test]
+    fn blockquote_style_shapes_the_bar_and_background() {
+        egui::__run_test_ui(|ui| {
+            let style = BlockquoteStyle {
+                bar_width: 6.0,
+                background: Some(egui::Color32::YELLOW),
+                rounding: 8,
+            };
+            let shape = ui.painter().add(egui::Shape::Noop);
+            styled_blockquote(ui, egui::Color32::RED, &style, |ui| {
+                ui.add_space(40.0);
+            });
+            let layer = ui.layer_id();
+            let shapes = ui.ctx().graphics(|graphics| {
+                let list = graphics.get(layer).unwrap();
+                list.all_entries()
+                    .nth(shape.0 + 1)
+                    .map(|clipped| clipped.shape.clone())
+            });
+            let Some(egui::Shape::Vec(shapes)) = shapes else {
+                panic!("{shapes:?}");
+            };
+            let [egui::Shape::Rect(background), egui::Shape::Rect(bar)] = &shapes[..] else {
+                panic!("{shapes:?}");
+            };
+            assert_eq!(background.fill, egui::Color32::YELLOW);
+            assert_eq!(background.corner_radius, egui::CornerRadius::same(8));
+            assert_eq!(bar.fill, egui::Color32::RED);
+            assert_eq!(bar.rect.width(), 6.0);
+            // The bar is only as round as half its width
+            assert_eq!(bar.corner_radius, egui::CornerRadius::same(3));
+            assert_eq!(bar.rect.left(), background.rect.left());
+        });
+    }
 }
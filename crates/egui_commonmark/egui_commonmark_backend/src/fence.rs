@@ -0,0 +1,129 @@
+//! Attributes in the info string of fenced code blocks.
+//!
+//! The first word names the language and the rest is split into attributes,
+//! covering the common conventions:
+//!
+//! ~~~text
+//! ```rust,ignore,no_run
+//! ```python title="example.py"
+//! ```js {1,3-5}
+//! ```toml hl_lines="2 4"
+//! ~~~
+
+use std::ops::RangeInclusive;
+
+/// An info string split into its parts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FenceInfo {
+    /// First word, used for syntax highlighting. Empty when there is none.
+    pub lang: String,
+    /// From `title="..."`
+    pub title: Option<String>,
+    /// 1-based lines to emphasize, from `{1,3-5}`, `highlight_lines="1 3-5"`
+    /// or `hl_lines="1 3-5"`.
+    pub highlight_lines: Vec<RangeInclusive<usize>>,
+    /// Everything else, e.g. `ignore` and `no_run`, or `key=value` with the
+    /// quotes removed.
+    pub attributes: Vec<String>,
+}
+
+impl FenceInfo {
+    pub fn parse(info: &str) -> Self {
+        let info = info.trim();
+        let lang_end = info
+            .find(|c: char| c.is_whitespace() || c == ',' || c == '{')
+            .unwrap_or(info.len());
+        let mut fence = Self {
+            lang: info[..lang_end].to_owned(),
+            ..Default::default()
+        };
+
+        for token in tokens(&info[lang_end..]) {
+            if let Some(lines) = token.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                fence.highlight_lines.extend(line_ranges(lines));
+            } else if let Some(title) = token.strip_prefix("title=") {
+                fence.title = Some(unquote(title).to_owned());
+            } else if let Some(lines) = token
+                .strip_prefix("highlight_lines=")
+                .or_else(|| token.strip_prefix("hl_lines="))
+            {
+                fence.highlight_lines.extend(line_ranges(unquote(lines)));
+            } else {
+                fence.attributes.push(token.replace('"', ""));
+            }
+        }
+        fence
+    }
+
+    /// Whether `line` (1-based) is one of the [`highlight_lines`](Self::highlight_lines).
+    pub fn is_highlighted(&self, line: usize) -> bool {
+        self.highlight_lines.iter().any(|r| r.contains(&line))
+    }
+}
+
+/// Split on whitespace and commas outside of quotes and braces.
+fn tokens(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_quotes = false;
+    let mut in_braces = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '{' if !in_quotes => in_braces = true,
+            '}' if !in_quotes => in_braces = false,
+            c if (c.is_whitespace() || c == ',') && !in_quotes && !in_braces => {
+                if let Some(start) = start.take() {
+                    tokens.push(&s[start..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(start) = start {
+        tokens.push(&s[start..]);
+    }
+    tokens
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// `1,3-5` or `1 3-5`. Malformed entries are skipped.
+fn line_ranges(s: &str) -> impl Iterator<Item = RangeInclusive<usize>> + '_ {
+    s.split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(|part| match part.split_once('-') {
+            Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+            None => part.parse().ok().map(|n| n..=n),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_strings_split_into_lang_and_attributes() {
+        let fence = FenceInfo::parse("rust,ignore,no_run");
+        assert_eq!(fence.lang, "rust");
+        assert_eq!(fence.attributes, ["ignore", "no_run"]);
+
+        let fence = FenceInfo::parse(r#"python title="my example.py" {1,3-4}"#);
+        assert_eq!(fence.lang, "python");
+        assert_eq!(fence.title.as_deref(), Some("my example.py"));
+        assert_eq!(fence.highlight_lines, [1..=1, 3..=4]);
+        assert!(fence.is_highlighted(4) && !fence.is_highlighted(2));
+
+        let fence = FenceInfo::parse(r#"js{2} hl_lines="5 7-8" linenums="1""#);
+        assert_eq!(fence.lang, "js");
+        assert_eq!(fence.highlight_lines, [2..=2, 5..=5, 7..=8]);
+        assert_eq!(fence.attributes, ["linenums=1"]);
+
+        assert_eq!(FenceInfo::parse(""), FenceInfo::default());
+    }
+}
@@ -0,0 +1,76 @@
+//! Per-element overrides for the default rendering.
+
+use std::ops::Range;
+
+use egui::Ui;
+
+/// Callbacks that `CommonMarkViewer` consults before rendering an element
+/// itself, so hosts can put their own widgets in place of the defaults.
+///
+/// Each method returns whether it rendered the element. The default
+/// implementations return `false`, leaving the element to the viewer. Text is
+/// passed as plain text with formatting stripped.
+///
+/// Paragraphs inside blockquotes always use the default rendering.
+pub trait CommonMarkRenderHooks {
+    /// `level` is 1 for `#` through 6 for `######`.
+    fn heading(&self, _ui: &mut Ui, _level: u8, _text: &str) -> bool {
+        false
+    }
+
+    fn paragraph(&self, _ui: &mut Ui, _text: &str) -> bool {
+        false
+    }
+
+    fn image(&self, _ui: &mut Ui, _uri: &str, _alt_text: &str) -> bool {
+        false
+    }
+
+    /// `title` is empty when the link has none. Link hooks registered on the
+    /// cache are not triggered for links rendered here.
+    fn link(&self, _ui: &mut Ui, _destination: &str, _title: &str, _text: &str) -> bool {
+        false
+    }
+
+    /// `lang` is the first word of a fenced block's info string, empty for
+    /// indented blocks.
+    fn code_block(&self, _ui: &mut Ui, _lang: &str, _code: &str) -> bool {
+        false
+    }
+
+    fn table(&self, _ui: &mut Ui, _header: &[String], _rows: &[Vec<String>]) -> bool {
+        false
+    }
+}
+
+/// The text of a run of inline events, without formatting.
+#[doc(hidden)]
+pub fn plain_text(events: &[(pulldown_cmark::Event<'_>, Range<usize>)]) -> String {
+    let mut text = String::new();
+    for (event, _) in events {
+        match event {
+            pulldown_cmark::Event::Text(t)
+            | pulldown_cmark::Event::Code(t)
+            | pulldown_cmark::Event::InlineMath(t)
+            | pulldown_cmark::Event::InlineHtml(t) => text.push_str(t),
+            pulldown_cmark::Event::SoftBreak => text.push(' '),
+            pulldown_cmark::Event::HardBreak => text.push('\n'),
+            _ => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_drops_formatting() {
+        let events: Vec<_> =
+            pulldown_cmark::Parser::new("Some *emphasis*, `code`\nand [a link](x).")
+                .into_offset_iter()
+                .collect();
+        assert_eq!(plain_text(&events), "Some emphasis, code and a link.");
+    }
+}
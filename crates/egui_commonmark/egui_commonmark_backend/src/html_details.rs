@@ -0,0 +1,98 @@
+/// Recognizes HTML `<details>` / `<summary>` blocks so they can be rendered as
+/// collapsing sections. The markdown between the opening and closing HTML
+/// blocks is rendered normally, so only the tags themselves are parsed here.
+use crate::html_table::{decode_entities, strip_html_tags};
+
+/// Opening `<details>` HTML block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlDetails {
+    /// Plain text of the `<summary>` element, or "Details" if there is none.
+    pub summary: String,
+    /// Whether the `open` attribute is set.
+    pub open: bool,
+    /// HTML after the summary when `</details>` closes in the same block, so
+    /// no markdown body follows.
+    pub inline_body: Option<String>,
+}
+
+/// Parse an HTML block that starts with `<details>`.
+/// Returns `None` for any other HTML.
+pub fn parse_html_details(html: &str) -> Option<HtmlDetails> {
+    let trimmed = html.trim_start();
+    let lower = trimmed.to_ascii_lowercase();
+    let after_name = lower.strip_prefix("<details")?;
+    if !after_name.starts_with(|c: char| c == '>' || c.is_ascii_whitespace()) {
+        return None;
+    }
+    let open_tag_end = lower.find('>')?;
+    let open = lower[..open_tag_end]
+        .split_whitespace()
+        .skip(1)
+        .any(|attr| attr == "open" || attr.starts_with("open="));
+
+    let mut body_start = open_tag_end + 1;
+    let mut summary = None;
+    if let Some(summary_start) = lower[body_start..].find("<summary").map(|i| i + body_start) {
+        let content_start = lower[summary_start..].find('>')? + summary_start + 1;
+        let content_end = lower[content_start..]
+            .find("</summary>")
+            .map_or(lower.len(), |i| i + content_start);
+        let text = decode_entities(strip_html_tags(&trimmed[content_start..content_end]).trim());
+        if !text.is_empty() {
+            summary = Some(text);
+        }
+        body_start = (content_end + "</summary>".len()).min(trimmed.len());
+    }
+
+    let inline_body = lower.rfind("</details>").map(|close| {
+        let body = trimmed.get(body_start..close).unwrap_or_default();
+        decode_entities(strip_html_tags(body).trim())
+    });
+
+    Some(HtmlDetails {
+        summary: summary.unwrap_or_else(|| "Details".to_owned()),
+        open,
+        inline_body,
+    })
+}
+
+/// Net change in `<details>` nesting depth caused by an HTML fragment.
+pub fn details_depth_change(html: &str) -> isize {
+    let lower = html.to_ascii_lowercase();
+    lower.matches("<details").count() as isize - lower.matches("</details>").count() as isize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_summary_and_open_attribute() {
+        let details = parse_html_details("<details open>\n<summary><b>Setup</b> &amp; run</summary>\n")
+            .unwrap();
+        assert_eq!(details.summary, "Setup & run");
+        assert!(details.open);
+        assert_eq!(details.inline_body, None);
+    }
+
+    #[test]
+    fn defaults_without_summary_and_keeps_inline_body() {
+        let details = parse_html_details("<DETAILS>hidden <i>text</i></DETAILS>").unwrap();
+        assert_eq!(details.summary, "Details");
+        assert!(!details.open);
+        assert_eq!(details.inline_body.as_deref(), Some("hidden text"));
+    }
+
+    #[test]
+    fn ignores_other_html() {
+        assert!(parse_html_details("<div>details</div>").is_none());
+        assert!(parse_html_details("<detailsx>").is_none());
+    }
+
+    #[test]
+    fn depth_change_counts_nested_tags() {
+        assert_eq!(details_depth_change("<details><summary>a</summary>"), 1);
+        assert_eq!(details_depth_change("</details>\n"), -1);
+        assert_eq!(details_depth_change("<details>x</details>"), 0);
+    }
+}
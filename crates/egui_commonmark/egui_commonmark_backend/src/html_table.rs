@@ -1,13 +1,93 @@
 /// Simple HTML table parser for rendering `<table>` blocks as egui grids.
-/// Handles `<thead>`, `<tbody>`, `<tr>`, `<th>`, `<td>` elements.
+/// Handles `<thead>`, `<tbody>`, `<tr>`, `<th>`, `<td>` elements, the
+/// `colspan` and `rowspan` of cells and bold, italic, struck out, code and
+/// link formatting inside them.
 /// No external dependencies — string-based parsing only.
 
 /// Parsed HTML table ready for rendering.
 pub struct HtmlTable {
     /// Header rows (from `<thead>` or rows containing `<th>` cells).
-    pub header: Vec<Vec<String>>,
+    pub header: Vec<Vec<HtmlCell>>,
     /// Body rows (from `<tbody>` or rows containing `<td>` cells).
-    pub rows: Vec<Vec<String>>,
+    pub rows: Vec<Vec<HtmlCell>>,
+}
+
+/// A `<td>` or `<th>` cell.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HtmlCell {
+    /// Text without markup, entities decoded
+    pub text: String,
+    /// The text with its inline formatting as markdown, for the inline
+    /// renderer. Tags other than `<b>`, `<strong>`, `<i>`, `<em>`, `<s>`,
+    /// `<del>`, `<code>`, `<a href>` and `<br>` are dropped.
+    pub markdown: String,
+    /// Columns the cell spans, at least 1
+    pub colspan: usize,
+    /// Rows the cell spans, at least 1
+    pub rowspan: usize,
+}
+
+impl HtmlCell {
+    /// A cell of `text` without formatting, spanning one column and row.
+    pub fn plain(text: impl Into<String>) -> Self {
+        let text = text.into();
+        Self {
+            markdown: escape_markdown(&text),
+            text,
+            colspan: 1,
+            rowspan: 1,
+        }
+    }
+}
+
+impl PartialEq<&str> for HtmlCell {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+impl HtmlTable {
+    /// Whether a cell spans more than one column or row.
+    pub fn has_spans(&self) -> bool {
+        self.header
+            .iter()
+            .chain(&self.rows)
+            .flatten()
+            .any(|cell| cell.colspan > 1 || cell.rowspan > 1)
+    }
+
+    /// Where the cells go in a grid of rows and columns, header rows first:
+    /// `(row, column, cell)` for each cell, skipping the slots taken by
+    /// cells spanning from the rows above. Spans are cut at the last row.
+    /// Also returns the number of columns.
+    pub fn place_cells(&self) -> (Vec<(usize, usize, HtmlCell)>, usize) {
+        let rows: Vec<&Vec<HtmlCell>> = self.header.iter().chain(&self.rows).collect();
+        // Rows still taken in each column by a rowspan from above
+        let mut taken: Vec<usize> = Vec::new();
+        let mut placed = Vec::new();
+        for (row, cells) in rows.iter().enumerate() {
+            let mut col = 0;
+            for cell in cells.iter() {
+                while taken.get(col).is_some_and(|&rows| rows > 0) {
+                    col += 1;
+                }
+                let rowspan = cell.rowspan.clamp(1, rows.len() - row);
+                let end = col + cell.colspan;
+                if taken.len() < end {
+                    taken.resize(end, 0);
+                }
+                for rows in &mut taken[col..end] {
+                    *rows = rowspan;
+                }
+                placed.push((row, col, HtmlCell { rowspan, ..cell.clone() }));
+                col = end;
+            }
+            for rows in &mut taken {
+                *rows = rows.saturating_sub(1);
+            }
+        }
+        (placed, taken.len())
+    }
 }
 
 /// Parse an HTML block string into a table structure.
@@ -103,8 +183,8 @@ pub fn parse_html_table(html: &str) -> Option<HtmlTable> {
     Some(HtmlTable { header, rows })
 }
 
-/// Parse `<tr>` rows from an HTML fragment, extracting cell text from `<td>` and `<th>`.
-fn parse_rows(html: &str) -> Vec<Vec<String>> {
+/// Parse `<tr>` rows from an HTML fragment, extracting the cells from `<td>` and `<th>`.
+fn parse_rows(html: &str) -> Vec<Vec<HtmlCell>> {
     let mut result = Vec::new();
     let lower = html.to_ascii_lowercase();
     let bytes = lower.as_bytes();
@@ -141,8 +221,8 @@ fn parse_rows(html: &str) -> Vec<Vec<String>> {
     result
 }
 
-/// Extract cell text from `<td>` and `<th>` elements within a `<tr>`.
-fn parse_cells(html: &str) -> Vec<String> {
+/// Extract the cells from `<td>` and `<th>` elements within a `<tr>`.
+fn parse_cells(html: &str) -> Vec<HtmlCell> {
     let mut cells = Vec::new();
     let lower = html.to_ascii_lowercase();
     let mut pos = 0;
@@ -168,6 +248,7 @@ fn parse_cells(html: &str) -> Vec<String> {
             break;
         };
         let content_start = cell_start + tag_end + 1;
+        let open_tag = &lower[cell_start..content_start];
 
         // Find closing tag
         let content_end = lower[content_start..]
@@ -178,7 +259,18 @@ fn parse_cells(html: &str) -> Vec<String> {
         let raw = &html[content_start..content_end];
         let text = strip_html_tags(raw);
         let text = decode_entities(&text);
-        cells.push(text.trim().to_string());
+        let span = |name: &str| {
+            attribute(open_tag, name)
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(1)
+                .clamp(1, 1000)
+        };
+        cells.push(HtmlCell {
+            text: text.trim().to_string(),
+            markdown: inline_markdown(raw),
+            colspan: span("colspan"),
+            rowspan: span("rowspan"),
+        });
 
         pos = content_end + close_tag.len();
     }
@@ -186,6 +278,101 @@ fn parse_cells(html: &str) -> Vec<String> {
     cells
 }
 
+/// Value of the attribute `name` in the opening tag `tag`, quoted or not.
+fn attribute<'t>(tag: &'t str, name: &str) -> Option<&'t str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    let start = loop {
+        let at = from + lower[from..].find(name)?;
+        from = at + name.len();
+        let before = lower[..at].chars().next_back();
+        if before.is_some_and(char::is_whitespace) && lower[from..].trim_start().starts_with('=') {
+            break from;
+        }
+    };
+    let value = tag[start..].trim_start().strip_prefix('=')?.trim_start();
+    Some(match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()?,
+    })
+}
+
+/// The inline formatting of the cell contents `html` as markdown: bold,
+/// italics, strikethrough, code, links and line breaks. Other tags are
+/// dropped and text is escaped, so it is never read as markdown itself.
+fn inline_markdown(html: &str) -> String {
+    let mut markdown = String::new();
+    let mut links: Vec<String> = Vec::new();
+    let mut in_code = false;
+    let mut rest = html;
+    while !rest.is_empty() {
+        let (text, after) = rest.split_once('<').unwrap_or((rest, ""));
+        let text = decode_entities(text);
+        // Runs of whitespace collapse to one space, as in HTML
+        let words = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.starts_with(char::is_whitespace) {
+            markdown.push(' ');
+        }
+        if in_code {
+            markdown.push_str(&words);
+        } else {
+            markdown.push_str(&escape_markdown(&words));
+        }
+        if !words.is_empty() && text.ends_with(char::is_whitespace) {
+            markdown.push(' ');
+        }
+        let Some((tag, after)) = after.split_once('>') else {
+            break;
+        };
+        rest = after;
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "b" | "strong" => markdown.push_str("**"),
+            "i" | "em" => markdown.push('*'),
+            "s" | "del" | "strike" => markdown.push_str("~~"),
+            "code" => {
+                in_code = !closing;
+                markdown.push('`');
+            }
+            "br" => markdown.push_str("\\\n"),
+            "a" if !closing => {
+                if let Some(href) = attribute(tag, "href") {
+                    markdown.push('[');
+                    links.push(decode_entities(href));
+                }
+            }
+            "a" => {
+                if let Some(href) = links.pop() {
+                    markdown.push_str(&format!("](<{}>)", href.replace(['<', '>'], "")));
+                }
+            }
+            _ => {}
+        }
+    }
+    markdown.trim().to_string()
+}
+
+/// `text` with its ASCII punctuation backslash-escaped, so markdown shows it
+/// as it is.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 /// Collect the raw HTML content of each `<tr>` block for inspection.
 fn find_tag_positions(html: &str, _tag: &str) -> Vec<String> {
     let mut result = Vec::new();
@@ -210,7 +397,7 @@ fn find_tag_positions(html: &str, _tag: &str) -> Vec<String> {
 }
 
 /// Remove HTML tags from a string, preserving text content.
-fn strip_html_tags(html: &str) -> String {
+pub(crate) fn strip_html_tags(html: &str) -> String {
     let mut result = String::with_capacity(html.len());
     let mut in_tag = false;
 
@@ -227,7 +414,7 @@ fn strip_html_tags(html: &str) -> String {
 }
 
 /// Decode common HTML entities.
-fn decode_entities(text: &str) -> String {
+pub(crate) fn decode_entities(text: &str) -> String {
     text.replace("&amp;", "&")
         .replace("&lt;", "<")
         .replace("&gt;", ">")
@@ -237,10 +424,85 @@ fn decode_entities(text: &str) -> String {
         .replace("&apos;", "'")
 }
 
+/// Parse CSV (`delimiter` `,`) or TSV (`'\t'`) text into a table, with the
+/// first record as the header. Fields may be quoted with `"`, doubling any
+/// quotes inside. Rows are padded or cut to the width of the header.
+/// Returns `None` if there are no records.
+pub fn parse_delimited_table(text: &str, delimiter: char) -> Option<HtmlTable> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c != '"' {
+                field.push(c);
+            } else if chars.peek() == Some(&'"') {
+                chars.next();
+                field.push('"');
+            } else {
+                in_quotes = false;
+            }
+        } else if c == '"' && field.trim().is_empty() {
+            in_quotes = true;
+        } else if c == delimiter {
+            record.push(field.trim().to_owned());
+            field.clear();
+        } else if c == '\n' {
+            record.push(field.trim().to_owned());
+            field.clear();
+            records.push(std::mem::take(&mut record));
+        } else if c != '\r' {
+            field.push(c);
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field.trim().to_owned());
+        records.push(record);
+    }
+
+    let mut records = records
+        .into_iter()
+        .filter(|record| record.iter().any(|field| !field.is_empty()));
+    let header = records.next()?;
+    let width = header.len();
+    let cells = |record: Vec<String>| record.into_iter().map(HtmlCell::plain).collect();
+    let rows = records
+        .map(|mut record| {
+            record.resize(width, String::new());
+            cells(record)
+        })
+        .collect();
+    Some(HtmlTable {
+        header: vec![cells(header)],
+        rows,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn csv_and_tsv_fences() {
+        let csv = "name, note\n\"Smith, J\",\"said \"\"hi\"\"\"\r\n\nDoe\nA,B,C\n";
+        let table = parse_delimited_table(csv, ',').unwrap();
+        assert_eq!(table.header, vec![vec!["name", "note"]]);
+        assert_eq!(
+            table.rows,
+            vec![
+                vec!["Smith, J", "said \"hi\""],
+                vec!["Doe", ""],
+                vec!["A", "B"],
+            ]
+        );
+
+        let table = parse_delimited_table("a\tb\n1\t2", '\t').unwrap();
+        assert_eq!(table.rows, vec![vec!["1", "2"]]);
+        assert!(parse_delimited_table("\n\n", ',').is_none());
+    }
+
     #[test]
     fn basic_table() {
         let html = r#"
@@ -305,6 +567,53 @@ mod tests {
         assert_eq!(table.header[0][1], "Link");
     }
 
+    #[test]
+    fn cells_keep_their_inline_formatting() {
+        let html = r#"
+        <table>
+            <tr><td><strong>Bold</strong> and <em>2*3</em></td>
+                <td>See <a href="https://x.org/?a=1&amp;b=2">the <code>a_b</code> docs</a><br>next</td></tr>
+        </table>"#;
+
+        let table = parse_html_table(html).unwrap();
+        assert_eq!(table.header[0][0].markdown, r"**Bold** and *2\*3*");
+        assert_eq!(
+            table.header[0][1].markdown,
+            "See [the `a_b` docs](<https://x.org/?a=1&b=2>)\\\nnext"
+        );
+        assert_eq!(table.header[0][1].text, "See the a_b docsnext");
+    }
+
+    #[test]
+    fn spans_take_up_the_slots_they_cover() {
+        let html = r#"
+        <table>
+            <tr><th colspan="2">Name</th><th rowspan=3>Total</th></tr>
+            <tr><td rowspan='2'>A</td><td>1</td></tr>
+            <tr><td>2</td></tr>
+        </table>"#;
+
+        let table = parse_html_table(html).unwrap();
+        assert!(table.has_spans());
+        let (placed, columns) = table.place_cells();
+        let slots: Vec<_> = placed
+            .iter()
+            .map(|(row, col, cell)| (*row, *col, cell.text.as_str(), cell.colspan, cell.rowspan))
+            .collect();
+        assert_eq!(columns, 3);
+        assert_eq!(
+            slots,
+            vec![
+                (0, 0, "Name", 2, 1),
+                (0, 2, "Total", 1, 3),
+                (1, 0, "A", 1, 2),
+                (1, 1, "1", 1, 1),
+                (2, 1, "2", 1, 1),
+            ]
+        );
+        assert!(!parse_delimited_table("a,b\n1,2", ',').unwrap().has_spans());
+    }
+
     #[test]
     fn decodes_entities() {
         let html = r#"
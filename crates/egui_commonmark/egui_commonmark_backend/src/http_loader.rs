@@ -0,0 +1,451 @@
+//! Downloads `http://` and `https://` images on a background thread.
+//!
+//! Every URL is fetched once and kept until the loader is told to forget it.
+//! While a download runs its progress can be read with [`download_progress`]
+//! to draw a placeholder in place of the image. With a [`DiskCache`] set,
+//! downloads are also kept on disk for later runs and for working offline.
+
+use egui::load::{Bytes, BytesLoadResult, BytesLoader, BytesPoll, LoadError};
+use egui::mutex::Mutex;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub fn install_loader(ctx: &egui::Context) {
+    if !ctx.is_loader_installed(HttpLoader::ID) {
+        let loader = Arc::new(HttpLoader::default());
+        ctx.data_mut(|d| d.insert_temp(egui::Id::new(HttpLoader::ID), loader.clone()));
+        ctx.add_bytes_loader(loader);
+    }
+}
+
+/// Keep downloads in `disk_cache` from now on, or only in memory with `None`.
+/// Installs the loader if it isn't yet.
+pub fn set_disk_cache(ctx: &egui::Context, disk_cache: Option<DiskCache>) {
+    install_loader(ctx);
+    if let Some(loader) = ctx.data(|d| d.get_temp::<Arc<HttpLoader>>(egui::Id::new(HttpLoader::ID)))
+    {
+        *loader.disk_cache.lock() = disk_cache.map(Arc::new);
+    }
+}
+
+/// Bytes received so far and the total from `Content-Length`, if the server
+/// sent one. `None` when `uri` is not being downloaded.
+pub fn download_progress(ctx: &egui::Context, uri: &str) -> Option<(usize, Option<usize>)> {
+    let loader: Arc<HttpLoader> = ctx.data(|d| d.get_temp(egui::Id::new(HttpLoader::ID)))?;
+    let cache = loader.cache.lock();
+    match cache.get(uri)? {
+        Entry::Loading { received, total } => Some((*received, *total)),
+        Entry::Ready(_) => None,
+    }
+}
+
+pub fn is_remote(uri: &str) -> bool {
+    uri.starts_with("https://") || uri.starts_with("http://")
+}
+
+#[derive(Clone)]
+struct Data {
+    bytes: Arc<[u8]>,
+    mime: Option<String>,
+}
+
+#[derive(Clone)]
+enum Entry {
+    Loading {
+        received: usize,
+        total: Option<usize>,
+    },
+    Ready(Result<Data, String>),
+}
+
+#[derive(Default)]
+pub struct HttpLoader {
+    cache: Arc<Mutex<HashMap<String, Entry>>>,
+    disk_cache: Mutex<Option<Arc<DiskCache>>>,
+}
+
+impl HttpLoader {
+    pub const ID: &'static str = egui::generate_loader_id!(HttpLoader);
+}
+
+impl BytesLoader for HttpLoader {
+    fn id(&self) -> &str {
+        Self::ID
+    }
+
+    fn load(&self, ctx: &egui::Context, uri: &str) -> BytesLoadResult {
+        if !is_remote(uri) {
+            return Err(LoadError::NotSupported);
+        }
+
+        let mut cache = self.cache.lock();
+        if let Some(entry) = cache.get(uri).cloned() {
+            match entry {
+                Entry::Ready(Ok(file)) => Ok(BytesPoll::Ready {
+                    size: None,
+                    bytes: Bytes::Shared(file.bytes),
+                    mime: file.mime,
+                }),
+                Entry::Ready(Err(err)) => Err(LoadError::Loading(err)),
+                Entry::Loading { .. } => Ok(BytesPoll::Pending { size: None }),
+            }
+        } else {
+            cache.insert(
+                uri.to_owned(),
+                Entry::Loading {
+                    received: 0,
+                    total: None,
+                },
+            );
+            drop(cache);
+
+            let cache = self.cache.clone();
+            let disk_cache = self.disk_cache.lock().clone();
+            let uri = uri.to_owned();
+            let ctx = ctx.clone();
+
+            std::thread::Builder::new()
+                .name("HttpLoader".to_owned())
+                .spawn(move || {
+                    let result = fetch(&uri, disk_cache.as_deref(), |received, total| {
+                        if let Some(entry @ Entry::Loading { .. }) = cache.lock().get_mut(&uri) {
+                            *entry = Entry::Loading { received, total };
+                        }
+                        ctx.request_repaint();
+                    });
+                    // Forgotten while downloading: don't bring the entry back.
+                    if let Some(entry) = cache.lock().get_mut(&uri) {
+                        *entry = Entry::Ready(result);
+                    }
+                    ctx.request_repaint();
+                })
+                .expect("could not spawn thread");
+
+            Ok(BytesPoll::Pending { size: None })
+        }
+    }
+
+    fn forget(&self, uri: &str) {
+        let _ = self.cache.lock().remove(uri);
+    }
+
+    fn forget_all(&self) {
+        self.cache.lock().clear();
+    }
+
+    fn byte_size(&self) -> usize {
+        self.cache
+            .lock()
+            .values()
+            .map(|entry| match entry {
+                Entry::Ready(Ok(file)) => {
+                    file.bytes.len() + file.mime.as_ref().map_or(0, |m| m.len())
+                }
+                Entry::Ready(Err(err)) => err.len(),
+                Entry::Loading { .. } => 0,
+            })
+            .sum()
+    }
+}
+
+/// Downloaded images kept in a folder, one `.bin` file with the body and one
+/// `.meta` file with the url, ETag, mime type and download time per url.
+#[derive(Clone, Debug)]
+pub struct DiskCache {
+    pub dir: PathBuf,
+    /// The least recently downloaded images are removed once the bodies in
+    /// `dir` add up to more than this.
+    pub max_bytes: u64,
+    /// How long an image is used without asking the server again. Older ones
+    /// are downloaded again, or only revalidated when they have an ETag, and
+    /// are still used when the server can't be reached.
+    pub max_age: Duration,
+}
+
+impl DiskCache {
+    /// Up to 256 MiB of images, refreshed after a week.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes: 256 * 1024 * 1024,
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+
+    fn path(&self, uri: &str, extension: &str) -> PathBuf {
+        // FNV-1a, as the names must stay the same across runs and builds.
+        let hash = uri.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        self.dir.join(format!("{hash:016x}.{extension}"))
+    }
+
+    fn read(&self, uri: &str) -> Option<CachedImage> {
+        let meta = std::fs::read_to_string(self.path(uri, "meta")).ok()?;
+        let mut fields = HashMap::new();
+        for line in meta.lines() {
+            if let Some((key, value)) = line.split_once(' ') {
+                fields.insert(key, value);
+            }
+        }
+        // Another url with the same hash.
+        if fields.get("url") != Some(&uri) {
+            return None;
+        }
+        let bytes = std::fs::read(self.path(uri, "bin")).ok()?;
+        Some(CachedImage {
+            data: Data {
+                bytes: bytes.into(),
+                mime: fields.get("mime").map(|mime| mime.to_string()),
+            },
+            etag: fields.get("etag").map(|etag| etag.to_string()),
+            fetched: UNIX_EPOCH + Duration::from_secs(fields.get("fetched")?.parse().ok()?),
+        })
+    }
+
+    /// Store `data`, or only mark the stored copy as fresh when it is `None`.
+    /// Failing to write just means downloading again next time.
+    fn write(&self, uri: &str, data: Option<&Data>, etag: Option<&str>) {
+        let _ = std::fs::create_dir_all(&self.dir);
+        if let Some(data) = data {
+            if std::fs::write(self.path(uri, "bin"), &data.bytes).is_err() {
+                return;
+            }
+        }
+        let mime = match data {
+            Some(data) => data.mime.clone(),
+            None => self.read(uri).and_then(|cached| cached.data.mime),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut meta = format!("url {uri}\nfetched {}\n", now.as_secs());
+        if let Some(etag) = etag {
+            meta += &format!("etag {etag}\n");
+        }
+        if let Some(mime) = mime {
+            meta += &format!("mime {mime}\n");
+        }
+        let _ = std::fs::write(self.path(uri, "meta"), meta);
+    }
+
+    /// Remove the least recently written images until the rest fit in
+    /// `max_bytes`.
+    fn prune(&self) {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut bodies: Vec<_> = dir
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "bin"))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+            })
+            .collect();
+        let mut total: u64 = bodies.iter().map(|(_, len, _)| len).sum();
+        bodies.sort();
+        for (_, len, path) in bodies {
+            if total <= self.max_bytes {
+                break;
+            }
+            let _ = std::fs::remove_file(path.with_extension("meta"));
+            let _ = std::fs::remove_file(&path);
+            total -= len;
+        }
+    }
+}
+
+struct CachedImage {
+    data: Data,
+    etag: Option<String>,
+    fetched: SystemTime,
+}
+
+/// Serve `uri` from `disk_cache` while it is fresh, and download it otherwise.
+fn fetch(
+    uri: &str,
+    disk_cache: Option<&DiskCache>,
+    progress: impl FnMut(usize, Option<usize>),
+) -> Result<Data, String> {
+    let Some(disk_cache) = disk_cache else {
+        return match download(uri, None, progress)? {
+            Download::Modified(data, _) => Ok(data),
+            Download::NotModified => Err("unexpected 304 Not Modified".to_owned()),
+        };
+    };
+
+    let cached = disk_cache.read(uri);
+    if let Some(cached) = &cached {
+        let age = cached.fetched.elapsed().unwrap_or_default();
+        if age < disk_cache.max_age {
+            return Ok(cached.data.clone());
+        }
+    }
+
+    let etag = cached.as_ref().and_then(|cached| cached.etag.as_deref());
+    match (download(uri, etag, progress), cached) {
+        (Ok(Download::Modified(data, etag)), _) => {
+            disk_cache.write(uri, Some(&data), etag.as_deref());
+            disk_cache.prune();
+            Ok(data)
+        }
+        (Ok(Download::NotModified), Some(cached)) => {
+            disk_cache.write(uri, None, cached.etag.as_deref());
+            Ok(cached.data)
+        }
+        (Ok(Download::NotModified), None) => Err("unexpected 304 Not Modified".to_owned()),
+        // Offline: an old copy beats none.
+        (Err(_), Some(cached)) => Ok(cached.data),
+        (Err(err), None) => Err(err),
+    }
+}
+
+enum Download {
+    /// The body and its ETag.
+    Modified(Data, Option<String>),
+    NotModified,
+}
+
+/// Fetch `uri`, reporting the bytes read so far after every chunk. With an
+/// `etag` the server may answer that the copy with that tag is still current.
+fn download(
+    uri: &str,
+    etag: Option<&str>,
+    mut progress: impl FnMut(usize, Option<usize>),
+) -> Result<Download, String> {
+    let mut request = ureq::get(uri);
+    if let Some(etag) = etag {
+        request = request.set("If-None-Match", etag);
+    }
+    let response = request.call().map_err(|e| e.to_string())?;
+    if response.status() == 304 {
+        return Ok(Download::NotModified);
+    }
+    let etag = response.header("ETag").map(str::to_owned);
+    let mime = response
+        .header("Content-Type")
+        .map(|mime| mime.split(';').next().unwrap_or(mime).trim().to_owned());
+    let total = response
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok());
+
+    let mut reader = response.into_reader();
+    let mut bytes = Vec::with_capacity(total.unwrap_or(0));
+    let mut chunk = [0; 16 * 1024];
+    loop {
+        let n = reader.read(&mut chunk).map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..n]);
+        progress(bytes.len(), total);
+    }
+
+    Ok(Download::Modified(
+        Data {
+            bytes: bytes.into(),
+            mime,
+        },
+        etag,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    #[test]
+    fn images_download_once_and_report_progress() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}/cat.png", listener.local_addr().unwrap());
+        let (respond, respond_rx) = std::sync::mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            respond_rx.recv().unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 4\r\nConnection: close\r\n\r\nabcd",
+                )
+                .unwrap();
+        });
+
+        let ctx = egui::Context::default();
+        install_loader(&ctx);
+        let loader: Arc<HttpLoader> = ctx
+            .data(|d| d.get_temp(egui::Id::new(HttpLoader::ID)))
+            .unwrap();
+
+        assert!(matches!(
+            loader.load(&ctx, "file:///cat.png"),
+            Err(LoadError::NotSupported)
+        ));
+        assert!(matches!(
+            loader.load(&ctx, &uri),
+            Ok(BytesPoll::Pending { .. })
+        ));
+        assert_eq!(download_progress(&ctx, &uri), Some((0, None)));
+
+        respond.send(()).unwrap();
+        server.join().unwrap();
+        let start = std::time::Instant::now();
+        let bytes = loop {
+            // The server only answers once, so this is served from the cache.
+            if let Ok(BytesPoll::Ready { bytes, mime, .. }) = loader.load(&ctx, &uri) {
+                assert_eq!(mime.as_deref(), Some("image/png"));
+                break bytes;
+            }
+            assert!(start.elapsed().as_secs() < 10, "download timed out");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert_eq!(&*bytes, b"abcd");
+        assert_eq!(download_progress(&ctx, &uri), None);
+    }
+
+    #[test]
+    fn disk_cache_serves_fresh_and_offline_copies_and_prunes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut disk_cache = DiskCache::new(dir.path());
+        // Nothing listens on the discard port, so every download fails.
+        let uri = "http://127.0.0.1:9/cat.png";
+        let data = |bytes: &[u8]| Data {
+            bytes: bytes.into(),
+            mime: Some("image/png".to_owned()),
+        };
+        let no_progress = |_, _| {};
+
+        assert!(fetch(uri, Some(&disk_cache), no_progress).is_err());
+        disk_cache.write(uri, Some(&data(b"cat")), Some("\"v1\""));
+        let cached = disk_cache.read(uri).unwrap();
+        assert_eq!(cached.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(cached.data.mime.as_deref(), Some("image/png"));
+        let fetched = fetch(uri, Some(&disk_cache), no_progress).unwrap();
+        assert_eq!(&*fetched.bytes, b"cat");
+
+        disk_cache.max_age = Duration::ZERO;
+        let fetched = fetch(uri, Some(&disk_cache), no_progress).unwrap();
+        assert_eq!(&*fetched.bytes, b"cat");
+
+        let other = "http://127.0.0.1:9/dog.png";
+        disk_cache.write(other, Some(&data(b"dog")), None);
+        let old = SystemTime::now() - Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(disk_cache.path(uri, "bin"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        disk_cache.max_bytes = 3;
+        disk_cache.prune();
+        assert!(disk_cache.read(uri).is_none());
+        assert!(disk_cache.read(other).is_some());
+    }
+}
@@ -4,23 +4,64 @@
 
 #[doc(hidden)]
 pub mod alerts;
+pub mod anchor;
+#[cfg(feature = "charts")]
+pub mod charts;
+pub mod config;
+pub mod document;
 #[doc(hidden)]
 pub mod elements;
+pub mod fence;
+pub mod hooks;
+pub mod math_document;
+pub mod media;
+#[doc(hidden)]
+pub mod html_details;
 #[doc(hidden)]
 pub mod html_table;
 #[doc(hidden)]
 pub mod misc;
 #[doc(hidden)]
+pub mod page_break;
+#[doc(hidden)]
 pub mod pulldown;
 pub mod typography;
 
+#[cfg(feature = "profiling")]
+#[doc(hidden)]
+pub use profiling;
+
+/// Times the rest of the enclosing block under a name, with the `profiling`
+/// feature. Expands to nothing without it.
+#[cfg(feature = "profiling")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        $crate::profiling::scope!($name);
+    };
+    ($name:expr, $data:expr) => {
+        $crate::profiling::scope!($name, $data);
+    };
+}
+
+#[cfg(not(feature = "profiling"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! profile_scope {
+    ($($arg:tt)*) => {};
+}
+
 #[cfg(feature = "embedded_image")]
 mod data_url_loader;
+#[cfg(feature = "remote_images")]
+pub mod http_loader;
+pub mod texture_loader;
 
 // For ease of use in proc macros
 #[doc(hidden)]
 pub use {
-    alerts::{Alert, AlertBundle, alert_ui},
+    alerts::{Alert, AlertBundle, AlertIcon, alert_ui},
     // Pretty much every single element in this module is used by the proc macros
     elements::*,
     misc::{CodeBlock, CommonMarkOptions, Image, Link, prepare_show},
@@ -40,3 +81,22 @@ pub use misc::warm_math_fonts;
 pub type RenderMathFn = dyn Fn(&mut egui::Ui, &str, bool);
 /// Takes [`egui::Ui`] and the html text to be rendered/used
 pub type RenderHtmlFn = dyn Fn(&mut egui::Ui, &str);
+/// Takes [`egui::Ui`], the uri of an image that failed to load, its alt text and the error
+pub type RenderImageErrorFn = dyn Fn(&mut egui::Ui, &str, &str, &str);
+/// Takes the source span of a clicked task list checkbox and whether it is
+/// checked now
+pub type CheckboxClickedFn = dyn Fn(std::ops::Range<usize>, bool);
+/// Takes the destination of a link or image and whether it is an image.
+/// Returns the destination to use instead, `None` keeps it
+pub type RewriteUrlFn = dyn Fn(&str, bool) -> Option<String>;
+/// Takes the language of a code block (the first word of its info string,
+/// empty for indented blocks) and returns whether the block goes to the
+/// paired [`RenderCodeBlockFn`]
+pub type CodeLangMatcher = dyn Fn(&str) -> bool;
+/// Takes [`egui::Ui`], the language and the content of a code block.
+/// Returns whether it rendered the block; `false` falls through to the next
+/// matching function and finally the default code block
+pub type RenderCodeBlockFn = dyn Fn(&mut egui::Ui, &str, &str) -> bool;
+/// Takes [`egui::Ui`], the language and the content of a code block and an
+/// id unique to the block, and adds widgets below it
+pub type CodeBlockFooterFn = dyn Fn(&mut egui::Ui, &str, &str, egui::Id);
@@ -0,0 +1,523 @@
+//! Document-wide math: macros defined once for every formula, and numbers for
+//! the display equations that `\ref{}` and `\eqref{}` point at.
+//!
+//! Macros come from a `macros:` key in the YAML front matter or from a first
+//! display math block holding nothing but `\newcommand` and `\def`
+//! definitions, which then isn't shown.
+
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// Expansions nesting deeper than this are left as they are, so a macro that
+/// uses itself can't hang the renderer.
+const MAX_EXPANSION_DEPTH: usize = 16;
+
+/// Formulas whose macros expand to more than this are left unexpanded, so a
+/// macro using itself several times can't either.
+const MAX_EXPANSION_LEN: usize = 64 * 1024;
+
+/// A macro usable in every formula of a document.
+#[derive(Clone, Debug, PartialEq)]
+struct Macro {
+    args: usize,
+    /// With `#1` to `#9` standing for the arguments
+    body: String,
+}
+
+/// The macros and equation numbers of a document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MathDocument {
+    /// By name, without the backslash
+    macros: HashMap<String, Macro>,
+    /// Numbers of the display equations by their source start offset
+    numbers: HashMap<usize, String>,
+    /// Numbers of the equations by the names of their `\label{}`s
+    labels: HashMap<String, String>,
+    /// Source start of the display math block the macros were defined in
+    definitions: Option<usize>,
+    numbering: bool,
+    /// Hash of the macros, labels and numbering, identifying what formulas
+    /// become
+    key: u64,
+}
+
+impl MathDocument {
+    /// Collect the macros of a document, and number its display equations
+    /// when `numbering`. Equations with `\nonumber` or `\notag` get no number
+    /// and ones with a `\tag{}` are numbered by it.
+    pub fn new(events: &[(Event<'_>, Range<usize>)], numbering: bool) -> Self {
+        let mut document = Self {
+            numbering,
+            ..Default::default()
+        };
+        let mut in_metadata = false;
+        let mut seen_display_math = false;
+        let mut counter = 0;
+        for (event, range) in events {
+            match event {
+                Event::Start(Tag::MetadataBlock(_)) => in_metadata = true,
+                Event::End(TagEnd::MetadataBlock(_)) => in_metadata = false,
+                Event::Text(yaml) if in_metadata => {
+                    document.macros.extend(front_matter_macros(yaml));
+                }
+                Event::DisplayMath(tex) => {
+                    let first = !seen_display_math;
+                    seen_display_math = true;
+                    if first {
+                        if let Some(macros) = definitions(tex) {
+                            document.macros.extend(macros);
+                            document.definitions = Some(range.start);
+                            continue;
+                        }
+                    }
+                    if !numbering || tex.contains("\\nonumber") || tex.contains("\\notag") {
+                        continue;
+                    }
+                    let number = match command_args(tex, "tag").into_iter().next() {
+                        Some(tag) => tag.trim().to_owned(),
+                        None => {
+                            counter += 1;
+                            counter.to_string()
+                        }
+                    };
+                    for label in command_args(tex, "label") {
+                        document
+                            .labels
+                            .insert(label.trim().to_owned(), number.clone());
+                    }
+                    document.numbers.insert(range.start, number);
+                }
+                _ => {}
+            }
+        }
+        document.key = document.hash_key();
+        document
+    }
+
+    fn hash_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.numbering.hash(&mut hasher);
+        let mut macros: Vec<_> = self.macros.iter().collect();
+        macros.sort_unstable_by_key(|(name, _)| *name);
+        for (name, found) in macros {
+            (name, found.args, &found.body).hash(&mut hasher);
+        }
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_unstable();
+        labels.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether [`formula`](Self::formula) gives every formula back as it is.
+    pub fn is_plain(&self) -> bool {
+        self.macros.is_empty() && !self.numbering
+    }
+
+    /// Key of `tex` as a formula of this document, the same for documents
+    /// that turn it into the same thing.
+    pub fn formula_key(&self, tex: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.key.hash(&mut hasher);
+        tex.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether the display math block starting at `start` only defines the
+    /// document's macros.
+    pub fn is_definitions(&self, start: usize) -> bool {
+        self.definitions == Some(start)
+    }
+
+    /// Number of the display equation starting at `start`.
+    pub fn number(&self, start: usize) -> Option<&str> {
+        self.numbers.get(&start).map(String::as_str)
+    }
+
+    /// `tex` as the math renderer gets it: with the references resolved, the
+    /// macros expanded and the numbering commands taken out. Formulas the
+    /// macros would blow up past `MAX_EXPANSION_LEN` keep them unexpanded.
+    pub fn formula<'a>(&self, tex: &'a str) -> Cow<'a, str> {
+        if self.is_plain() {
+            return Cow::Borrowed(tex);
+        }
+        let mut tex = Cow::Borrowed(tex);
+        if self.numbering {
+            let resolved = replace_commands(&tex, |name, arg| match (name, arg) {
+                ("label" | "tag", Some(_)) | ("nonumber" | "notag", None) => Some(String::new()),
+                ("ref" | "eqref", Some(label)) => Some(self.reference(name, label, true)),
+                _ => None,
+            });
+            tex = Cow::Owned(resolved);
+        }
+        if !self.macros.is_empty() {
+            if let Some(expanded) = self.expand(&tex, 0) {
+                tex = Cow::Owned(expanded);
+            }
+        }
+        tex
+    }
+
+    /// `text` with its `\ref{}`s and `\eqref{}`s replaced by the numbers of
+    /// the equations they name.
+    pub fn resolve_references<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.numbering || !text.contains("ref{") {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(replace_commands(text, |name, arg| match (name, arg) {
+            ("ref" | "eqref", Some(label)) => Some(self.reference(name, label, false)),
+            _ => None,
+        }))
+    }
+
+    /// What `\ref{label}` or `\eqref{label}` shows, `??` like LaTeX for
+    /// labels that don't exist.
+    fn reference(&self, command: &str, label: &str, in_math: bool) -> String {
+        let number = match self.labels.get(label.trim()) {
+            Some(number) if in_math => format!("\\text{{{number}}}"),
+            Some(number) => number.clone(),
+            None if in_math => "\\text{??}".to_owned(),
+            None => "??".to_owned(),
+        };
+        if command == "eqref" {
+            format!("({number})")
+        } else {
+            number
+        }
+    }
+
+    /// `tex` with its macros expanded, `None` once it grows past
+    /// `MAX_EXPANSION_LEN`.
+    fn expand(&self, tex: &str, depth: usize) -> Option<String> {
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Some(tex.to_owned());
+        }
+        let mut out = String::with_capacity(tex.len());
+        let mut expanded = false;
+        let mut rest = tex;
+        while let Some(at) = rest.find('\\') {
+            out.push_str(&rest[..at]);
+            let (name, after) = command_name(&rest[at + 1..]);
+            let Some(found) = self.macros.get(name) else {
+                // Skip an escaped character such as `\\` along with its backslash
+                let len = match name.len() {
+                    0 => after.chars().next().map_or(0, char::len_utf8),
+                    len => len,
+                };
+                out.push_str(&rest[at..at + 1 + len]);
+                rest = &rest[at + 1 + len..];
+                continue;
+            };
+            let mut after = after;
+            let mut args = Vec::with_capacity(found.args);
+            for _ in 0..found.args {
+                let Some((arg, next)) = argument(after) else {
+                    break;
+                };
+                args.push(arg);
+                after = next;
+            }
+            let mut body = found.body.clone();
+            for (n, arg) in args.iter().enumerate().rev() {
+                body = body.replace(&format!("#{}", n + 1), arg);
+            }
+            out.push_str(&body);
+            if out.len() > MAX_EXPANSION_LEN {
+                return None;
+            }
+            expanded = true;
+            rest = after;
+        }
+        out.push_str(rest);
+        if expanded {
+            self.expand(&out, depth + 1)
+        } else {
+            Some(out)
+        }
+    }
+}
+
+/// The name of the command after a backslash, letters only, and the text
+/// after it.
+fn command_name(s: &str) -> (&str, &str) {
+    let end = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// The contents of the `{…}` group `s` starts with and the text after it.
+fn braced(s: &str) -> Option<(&str, &str)> {
+    let inner = s.strip_prefix('{')?;
+    let mut depth = 0usize;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some((&inner[..i], &inner[i + 1..])),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A macro argument: a `{…}` group, a command or a single character.
+fn argument(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    if s.starts_with('{') {
+        return braced(s);
+    }
+    if let Some(after) = s.strip_prefix('\\') {
+        let (name, _) = command_name(after);
+        // An escaped character such as `\\` or `\é` when there's no name
+        let len = match name.len() {
+            0 => after.chars().next().map_or(0, char::len_utf8),
+            len => len,
+        };
+        return Some(s.split_at(1 + len));
+    }
+    let c = s.chars().next()?;
+    Some(s.split_at(c.len_utf8()))
+}
+
+/// The arguments of every `\name{…}` in `tex`.
+fn command_args<'a>(tex: &'a str, name: &str) -> Vec<&'a str> {
+    let needle = format!("\\{name}");
+    let mut args = Vec::new();
+    let mut rest = tex;
+    while let Some(at) = rest.find(&needle) {
+        rest = &rest[at + needle.len()..];
+        if let Some((arg, after)) = braced(rest.trim_start()) {
+            args.push(arg);
+            rest = after;
+        }
+    }
+    args
+}
+
+/// `text` with the commands that `replace` returns something for replaced
+/// by it. It is given the command's name and its `{…}` argument, if any.
+fn replace_commands(text: &str, replace: impl Fn(&str, Option<&str>) -> Option<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('\\') {
+        out.push_str(&rest[..at]);
+        let (name, after) = command_name(&rest[at + 1..]);
+        if name.is_empty() {
+            // An escaped character such as `\\` or `\{`
+            let len = after.chars().next().map_or(0, char::len_utf8);
+            out.push_str(&rest[at..at + 1 + len]);
+            rest = &rest[at + 1 + len..];
+            continue;
+        }
+        let group = braced(after);
+        match replace(name, group.map(|(arg, _)| arg)) {
+            Some(replacement) => {
+                out.push_str(&replacement);
+                rest = match group {
+                    Some((_, after)) => after,
+                    None => after,
+                };
+            }
+            None => {
+                out.push('\\');
+                out.push_str(name);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The macros a display math block defines, if it holds nothing else.
+fn definitions(tex: &str) -> Option<Vec<(String, Macro)>> {
+    let mut macros = Vec::new();
+    let mut rest = tex.trim_start();
+    while !rest.is_empty() {
+        let (command, after) = command_name(rest.strip_prefix('\\')?);
+        let (name, definition, after) = match command {
+            "newcommand" | "renewcommand" | "providecommand" => {
+                let after = after.strip_prefix('*').unwrap_or(after);
+                let (name, after) = defined_name(after)?;
+                let (args, after) = arg_count(after.trim_start())?;
+                let (body, after) = braced(after.trim_start())?;
+                let body = body.to_owned();
+                (name, Macro { args, body }, after)
+            }
+            "DeclareMathOperator" => {
+                let (name, after) = defined_name(after)?;
+                let (body, after) = braced(after.trim_start())?;
+                let body = format!("\\operatorname{{{body}}}");
+                (name, Macro { args: 0, body }, after)
+            }
+            "def" => {
+                let (name, after) = command_name(after.trim_start().strip_prefix('\\')?);
+                let params_end = after.find('{')?;
+                let args = after[..params_end].matches('#').count();
+                let (body, after) = braced(&after[params_end..])?;
+                let body = body.to_owned();
+                (name, Macro { args, body }, after)
+            }
+            _ => return None,
+        };
+        if name.is_empty() {
+            return None;
+        }
+        macros.push((name.to_owned(), definition));
+        rest = after.trim_start();
+    }
+    (!macros.is_empty()).then_some(macros)
+}
+
+/// The name a definition gives, written `{\name}` or `\name`.
+fn defined_name(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    match braced(s) {
+        Some((name, after)) => {
+            let (name, rest) = command_name(name.trim().strip_prefix('\\')?);
+            rest.is_empty().then_some((name, after))
+        }
+        None => Some(command_name(s.strip_prefix('\\')?)),
+    }
+}
+
+/// The `[n]` argument count of a `\newcommand`, 0 without one.
+fn arg_count(s: &str) -> Option<(usize, &str)> {
+    let Some(inner) = s.strip_prefix('[') else {
+        return Some((0, s));
+    };
+    let (count, after) = inner.split_once(']')?;
+    let count = count.trim().parse().ok().filter(|count| *count <= 9)?;
+    // An optional first argument with a default isn't supported
+    (!after.trim_start().starts_with('[')).then_some((count, after))
+}
+
+/// The macros under the `macros:` key of YAML front matter, one
+/// `name: expansion` pair per indented line.
+fn front_matter_macros(yaml: &str) -> Vec<(String, Macro)> {
+    let mut macros = Vec::new();
+    let mut lines = yaml.lines().skip_while(|line| line.trim_end() != "macros:");
+    lines.next();
+    for line in lines {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if !line.starts_with([' ', '\t']) {
+            break;
+        }
+        let Some((name, body)) = line.split_once(':') else {
+            continue;
+        };
+        let name = unquote(name.trim());
+        let name = name.strip_prefix('\\').unwrap_or(&name);
+        let body = unquote(body.trim());
+        let args = (1..=9)
+            .rev()
+            .find(|n| body.contains(&format!("#{n}")))
+            .unwrap_or(0);
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic()) {
+            macros.push((name.to_owned(), Macro { args, body }));
+        }
+    }
+    macros
+}
+
+/// A YAML scalar without its quotes. Double quoted ones have `\\` for a
+/// backslash.
+fn unquote(value: &str) -> String {
+    if let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        inner.replace("\\\\", "\\").replace("\\\"", "\"")
+    } else if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        inner.replace("''", "'")
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser};
+
+    fn document(markdown: &str, numbering: bool) -> MathDocument {
+        let options = Options::ENABLE_MATH | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+        let events: Vec<_> = Parser::new_ext(markdown, options)
+            .into_offset_iter()
+            .collect();
+        MathDocument::new(&events, numbering)
+    }
+
+    #[test]
+    fn macros_come_from_front_matter_or_a_leading_block() {
+        let markdown = "---\ntitle: x\nmacros:\n  \"\\\\R\": \"\\\\mathbb{R}\"\n  \
+                        norm: '\\lVert #1 \\rVert'\n---\n\n\
+                        $$\n\\newcommand{\\e}{\\varepsilon}\n\\def\\abs#1{|#1|}\n$$\n\n\
+                        $\\norm{x} \\in \\R^n, \\abs{\\e}$\n";
+        let math = document(markdown, false);
+        assert!(math.is_definitions(markdown.find("$$").unwrap()));
+        assert_eq!(
+            math.formula("\\norm{x} \\in \\R^n, \\abs{\\e} \\\\ y"),
+            "\\lVert x \\rVert \\in \\mathbb{R}^n, |\\varepsilon| \\\\ y"
+        );
+        // A macro using itself stops expanding instead of hanging
+        let mut math = MathDocument::default();
+        math.macros.insert(
+            "x".into(),
+            Macro {
+                args: 0,
+                body: "\\x".into(),
+            },
+        );
+        assert!(math.formula("\\x").starts_with("\\x"));
+        // Neither does one using itself several times
+        math.macros.insert(
+            "a".into(),
+            Macro {
+                args: 0,
+                body: "\\a\\a\\a\\a".into(),
+            },
+        );
+        assert_eq!(math.formula("x + \\a"), "x + \\a");
+        // An argument may be any escaped character
+        math.macros.insert(
+            "f".into(),
+            Macro {
+                args: 1,
+                body: "f(#1)".into(),
+            },
+        );
+        assert_eq!(math.formula("\\f\\é"), "f(\\é)");
+        // Math that does more than define is an ordinary equation
+        assert!(!document("$$\n\\def\\a{b} a\n$$\n", false).is_definitions(0));
+    }
+
+    #[test]
+    fn equations_are_numbered_and_referenced() {
+        let markdown = "See \\eqref{eq:b}.\n\n$$a \\label{eq:a}$$\n\n$$x \\nonumber$$\n\n\
+                        $$b \\label{eq:b}$$\n\n$$c \\tag{*} \\label{eq:c}$$\n";
+        let start = |equation: &str| markdown.find(equation).unwrap();
+        let math = document(markdown, true);
+        assert_eq!(math.number(start("$$a")), Some("1"));
+        assert_eq!(math.number(start("$$x")), None);
+        assert_eq!(math.number(start("$$b")), Some("2"));
+        assert_eq!(math.number(start("$$c")), Some("*"));
+        assert_eq!(
+            math.resolve_references("See \\eqref{eq:b} and \\ref{eq:c}, not \\ref{nope}."),
+            "See (2) and *, not ??."
+        );
+        assert_eq!(math.formula("a \\label{eq:a}"), "a ");
+        assert_eq!(math.formula("\\ref{eq:a}"), "\\text{1}");
+        // Without numbering the commands are left to the renderer
+        let math = document(markdown, false);
+        assert_eq!(math.number(start("$$a")), None);
+        assert_eq!(math.resolve_references("\\ref{eq:a}"), "\\ref{eq:a}");
+    }
+}
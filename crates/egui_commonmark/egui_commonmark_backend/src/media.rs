@@ -0,0 +1,106 @@
+//! Recognizing links to video and audio that egui can't play itself.
+
+/// What a url points at, when it is media.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Video,
+    Audio,
+    /// A YouTube video with this id.
+    YouTube(String),
+}
+
+impl MediaKind {
+    /// `None` for urls that aren't video or audio. Files are recognized by
+    /// their extension.
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        if let Some(id) = youtube_id(uri) {
+            return Some(Self::YouTube(id.to_owned()));
+        }
+        let path = uri.split(['?', '#']).next().unwrap_or(uri);
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "mp4" | "m4v" | "webm" | "mov" | "mkv" | "avi" | "ogv" => Some(Self::Video),
+            "mp3" | "wav" | "ogg" | "oga" | "flac" | "m4a" | "aac" | "opus" => Some(Self::Audio),
+            _ => None,
+        }
+    }
+
+    /// A preview image, for the kinds that have one online.
+    pub fn thumbnail_uri(&self) -> Option<String> {
+        match self {
+            Self::YouTube(id) => Some(format!("https://img.youtube.com/vi/{id}/hqdefault.jpg")),
+            Self::Video | Self::Audio => None,
+        }
+    }
+
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Self::Audio => "🔊",
+            Self::Video | Self::YouTube(_) => "🎬",
+        }
+    }
+}
+
+/// The video id of `youtube.com/watch?v=`, `youtu.be/`, `/shorts/` and
+/// `/embed/` urls.
+fn youtube_id(uri: &str) -> Option<&str> {
+    let rest = uri
+        .strip_prefix("https://")
+        .or_else(|| uri.strip_prefix("http://"))?;
+    let rest = rest
+        .strip_prefix("www.")
+        .or_else(|| rest.strip_prefix("m."))
+        .unwrap_or(rest);
+    let id = if let Some(path) = rest.strip_prefix("youtu.be/") {
+        path
+    } else {
+        let path = rest.strip_prefix("youtube.com/")?;
+        if let Some(query) = path.strip_prefix("watch?") {
+            query.split('&').find_map(|pair| pair.strip_prefix("v="))?
+        } else {
+            path.strip_prefix("shorts/")
+                .or_else(|| path.strip_prefix("embed/"))?
+        }
+    };
+    let id = id.split(['?', '&', '#', '/']).next().unwrap_or(id);
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_is_recognized_by_extension_or_youtube_url() {
+        let youtube = Some(MediaKind::YouTube("dQw4w9WgXcQ".to_owned()));
+        for uri in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?feature=share&v=dQw4w9WgXcQ#t=10",
+            "https://youtu.be/dQw4w9WgXcQ?t=42",
+            "http://m.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://www.youtube.com/embed/dQw4w9WgXcQ",
+        ] {
+            assert_eq!(MediaKind::from_uri(uri), youtube, "{uri}");
+        }
+        assert_eq!(
+            youtube.unwrap().thumbnail_uri().as_deref(),
+            Some("https://img.youtube.com/vi/dQw4w9WgXcQ/hqdefault.jpg")
+        );
+
+        assert_eq!(
+            MediaKind::from_uri("file:///clips/demo.MP4"),
+            Some(MediaKind::Video)
+        );
+        assert_eq!(
+            MediaKind::from_uri("https://example.com/talk.mp3?dl=1"),
+            Some(MediaKind::Audio)
+        );
+        assert_eq!(MediaKind::from_uri("https://example.com/cat.png"), None);
+        assert_eq!(MediaKind::from_uri("https://youtube.com/feed"), None);
+        assert_eq!(MediaKind::from_uri("https://example.com/"), None);
+    }
+}
@@ -1,15 +1,13 @@
 use crate::alerts::AlertBundle;
+use crate::fence::FenceInfo;
+use crate::math_document::MathDocument;
 use crate::typography::TypographyConfig;
 use egui::{RichText, TextStyle, Ui, text::LayoutJob};
-use std::collections::HashMap;
-#[cfg(feature = "math")]
-use std::collections::HashSet;
-#[cfg(any(feature = "better_syntax_highlighting", feature = "mermaid"))]
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-#[cfg(any(feature = "mermaid", feature = "math"))]
 use std::sync::mpsc;
 
 use crate::pulldown::ScrollableCache;
@@ -29,17 +27,436 @@ use std::sync::LazyLock;
 static GLOBAL_SYNTAX_SET: LazyLock<Arc<SyntaxSet>> =
     LazyLock::new(|| Arc::new(SyntaxSet::load_defaults_newlines()));
 
+/// The bundled themes, shared by every cache. Like the syntax set, they are
+/// only loaded once the first code block is highlighted.
+#[cfg(feature = "better_syntax_highlighting")]
+static GLOBAL_THEME_SET: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
 
 #[cfg(feature = "better_syntax_highlighting")]
 const DEFAULT_THEME_LIGHT: &str = "base16-ocean.light";
 #[cfg(feature = "better_syntax_highlighting")]
 const DEFAULT_THEME_DARK: &str = "base16-ocean.dark";
 
+/// What is shown as a caption under an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ImageCaptions {
+    #[default]
+    Off,
+    /// The title of the image, from `![alt](uri "title")`.
+    Title,
+    /// The title, or else a paragraph of only emphasized text directly
+    /// following a paragraph of only the image.
+    TitleOrEmphasis,
+}
+
+/// How tables wider than the viewer are laid out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum TableMode {
+    /// Columns size to their contents and the table scrolls horizontally.
+    #[default]
+    Scroll,
+    /// Columns share the viewer width and long cells wrap.
+    Fit,
+}
+
+/// What the copy button of a code block copies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum CodeCopyFormat {
+    /// Just the code
+    #[default]
+    Code,
+    /// The code in a fence with its language, ready to paste into markdown
+    Fenced,
+    /// The code with each line preceded by its number
+    LineNumbers,
+}
+
+/// How images are changed in dark mode, so documents full of white
+/// screenshots don't glare.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum DarkModeImages {
+    /// Shown as they are
+    #[default]
+    Off,
+    /// Darkened slightly
+    Dim,
+    /// Light and dark swapped, keeping hues, so white backgrounds turn
+    /// black. SVGs are dimmed instead.
+    Invert,
+}
+
+/// Gray images are tinted with when dimmed in dark mode.
+const DARK_MODE_IMAGE_DIM: u8 = 190;
+
+/// How the accent bar and the background of blockquotes and alerts are
+/// drawn. The bar colors are set with `blockquote_colors`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockquoteStyle {
+    /// Width of the accent bar
+    pub bar_width: f32,
+    /// Fill behind blockquotes, and behind alerts without a background color
+    pub background: Option<egui::Color32>,
+    /// Corner radius of the background, and of the bar as far as its width
+    /// allows
+    pub rounding: u8,
+}
+
+/// Which way paragraphs, list items and blockquotes run. Code blocks are
+/// always left to right.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum TextDirection {
+    /// Right to left when the first letter with a direction is Hebrew,
+    /// Arabic or another right-to-left script
+    Auto,
+    #[default]
+    LeftToRight,
+    RightToLeft,
+}
+
+/// Whether `text` runs right to left, judged by its first letter with a
+/// direction. `None` when it has no letters.
+pub fn is_right_to_left(text: &str) -> Option<bool> {
+    text.chars().find(|c| c.is_alphabetic()).map(|c| {
+        matches!(
+            c,
+            '\u{0590}'..='\u{08FF}'
+                | '\u{FB1D}'..='\u{FDFF}'
+                | '\u{FE70}'..='\u{FEFF}'
+                | '\u{10800}'..='\u{10FFF}'
+                | '\u{1E800}'..='\u{1EFFF}'
+        )
+    })
+}
+
+/// The color written as `text`: `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`,
+/// or `rgb(…)`/`rgba(…)` with 0-255 channels and an alpha from 0 to 1,
+/// separated by commas or spaces. `None` for anything else.
+pub fn parse_color_code(text: &str) -> Option<egui::Color32> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix('#') {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..=i], 16).ok().map(|d| d * 17);
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        let [r, g, b, a] = match hex.len() {
+            3 => [digit(0)?, digit(1)?, digit(2)?, 255],
+            4 => [digit(0)?, digit(1)?, digit(2)?, digit(3)?],
+            6 => [pair(0)?, pair(2)?, pair(4)?, 255],
+            8 => [pair(0)?, pair(2)?, pair(4)?, pair(6)?],
+            _ => return None,
+        };
+        return Some(egui::Color32::from_rgba_unmultiplied(r, g, b, a));
+    }
+    let args = text
+        .strip_prefix("rgba(")
+        .or_else(|| text.strip_prefix("rgb("))?
+        .strip_suffix(')')?;
+    let parts: Vec<&str> = args
+        .split([',', ' ', '/'])
+        .filter(|part| !part.is_empty())
+        .collect();
+    let channel = |part: &str| part.parse::<u8>().ok();
+    let alpha = match parts.get(3) {
+        None => 255,
+        Some(part) => match part.strip_suffix('%') {
+            Some(percent) => (percent.parse::<f32>().ok()? / 100.0 * 255.0).round() as u8,
+            None => (part.parse::<f32>().ok()?.clamp(0.0, 1.0) * 255.0).round() as u8,
+        },
+    };
+    match parts[..] {
+        [r, g, b] | [r, g, b, _] => Some(egui::Color32::from_rgba_unmultiplied(
+            channel(r)?,
+            channel(g)?,
+            channel(b)?,
+            alpha,
+        )),
+        _ => None,
+    }
+}
+
+/// Source bytes whose rendered text gets a background, e.g. to mark
+/// annotations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceRange {
+    pub range: std::ops::Range<usize>,
+    /// `None` uses the color of search matches
+    pub color: Option<egui::Color32>,
+}
+
+impl SourceRange {
+    pub fn new(range: std::ops::Range<usize>) -> Self {
+        Self { range, color: None }
+    }
+
+    pub fn color(mut self, color: egui::Color32) -> Self {
+        self.color = Some(color);
+        self
+    }
+}
+
+/// When links are underlined.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum LinkUnderline {
+    #[default]
+    Always,
+    /// Only while the pointer is over the link
+    OnHover,
+    Never,
+}
+
+/// How links are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkStyle {
+    /// `None` uses the theme's hyperlink color
+    pub color: Option<egui::Color32>,
+    pub underline: LinkUnderline,
+    /// Color of the links that were clicked before, as remembered by the
+    /// cache. `None` draws them like the others.
+    pub visited_color: Option<egui::Color32>,
+}
+
+/// How the items of ordered lists are numbered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ListNumbering {
+    /// 1. 2. 3.
+    #[default]
+    Decimal,
+    /// a. b. c. and aa. after z.
+    LowerAlpha,
+    /// A. B. C. and AA. after Z.
+    UpperAlpha,
+    /// i. ii. iii.
+    LowerRoman,
+    /// I. II. III.
+    UpperRoman,
+}
+
+impl ListNumbering {
+    /// The label of item `number`, without the trailing dot. Numbers that
+    /// have no letter or roman numeral, like 0, are written in decimal.
+    pub fn label(self, number: u64) -> String {
+        match self {
+            Self::Decimal => number.to_string(),
+            Self::LowerAlpha => alphabetic(number).unwrap_or_else(|| number.to_string()),
+            Self::UpperAlpha => alphabetic(number)
+                .map(|label| label.to_ascii_uppercase())
+                .unwrap_or_else(|| number.to_string()),
+            Self::LowerRoman => roman(number).unwrap_or_else(|| number.to_string()),
+            Self::UpperRoman => roman(number)
+                .map(|label| label.to_ascii_uppercase())
+                .unwrap_or_else(|| number.to_string()),
+        }
+    }
+}
+
+/// `a` to `z`, then `aa`, `ab` and so on.
+fn alphabetic(mut number: u64) -> Option<String> {
+    if number == 0 {
+        return None;
+    }
+    let mut letters = Vec::new();
+    while number > 0 {
+        number -= 1;
+        letters.push(b'a' + (number % 26) as u8);
+        number /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).ok()
+}
+
+/// Lowercase roman numerals up to 3999.
+fn roman(mut number: u64) -> Option<String> {
+    if !(1..4000).contains(&number) {
+        return None;
+    }
+    const NUMERALS: [(u64, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut label = String::new();
+    for (value, numeral) in NUMERALS {
+        while number >= value {
+            label.push_str(numeral);
+            number -= value;
+        }
+    }
+    Some(label)
+}
+
+/// The bullets and numbers in front of list items.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListMarkers {
+    /// Bullets of unordered lists by nesting depth, repeating once exhausted,
+    /// e.g. `•`, `◦` and `▪`. Empty draws a dot at the top level and circles
+    /// below it.
+    pub bullets: Vec<char>,
+    /// Numbering of ordered lists by nesting depth, repeating once exhausted.
+    /// Empty numbers them all in decimal.
+    pub numbering: Vec<ListNumbering>,
+    /// Color of the markers. `None` uses the theme's strong text color.
+    pub color: Option<egui::Color32>,
+}
+
+impl ListMarkers {
+    /// The bullet of a list nested `depth` levels deep, if one is set.
+    pub fn bullet(&self, depth: usize) -> Option<char> {
+        (!self.bullets.is_empty()).then(|| self.bullets[depth % self.bullets.len()])
+    }
+
+    /// The numbering of an ordered list nested `depth` levels deep.
+    pub fn numbering(&self, depth: usize) -> ListNumbering {
+        if self.numbering.is_empty() {
+            ListNumbering::Decimal
+        } else {
+            self.numbering[depth % self.numbering.len()]
+        }
+    }
+}
+
+impl Default for BlockquoteStyle {
+    fn default() -> Self {
+        Self {
+            bar_width: 3.0,
+            background: None,
+            rounding: 0,
+        }
+    }
+}
+
+/// The syntax beyond CommonMark that is interpreted. Disabled extensions are
+/// shown as the text they are written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MarkdownExtensions {
+    pub tables: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub task_lists: bool,
+    pub definition_lists: bool,
+    /// `$inline$` and `$$display$$` math. Needs the `math` feature or a
+    /// math renderer to be shown as math.
+    pub math: bool,
+    /// GitHub alerts, `> [!NOTE]` and the like.
+    pub alerts: bool,
+}
+
+impl Default for MarkdownExtensions {
+    fn default() -> Self {
+        Self {
+            tables: true,
+            footnotes: true,
+            strikethrough: true,
+            task_lists: true,
+            definition_lists: true,
+            math: true,
+            alerts: true,
+        }
+    }
+}
+
+impl MarkdownExtensions {
+    /// Plain CommonMark.
+    pub fn none() -> Self {
+        Self {
+            tables: false,
+            footnotes: false,
+            strikethrough: false,
+            task_lists: false,
+            definition_lists: false,
+            math: false,
+            alerts: false,
+        }
+    }
+}
+
+/// What an untrusted document is kept from doing. By default nothing is
+/// restricted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Sandbox {
+    /// Images from `http://` and `https://` aren't loaded, and a placeholder
+    /// with their alt text is shown instead.
+    pub block_remote_images: bool,
+    /// Clicking a link that opens in another app asks first.
+    pub confirm_external_links: bool,
+    /// `data:` images aren't shown and `data:` links aren't opened.
+    pub block_data_uris: bool,
+    /// Raw HTML is shown as the text it is written in, rather than as tables,
+    /// collapsible sections or superscripts.
+    pub block_raw_html: bool,
+}
+
+impl Sandbox {
+    /// Every restriction, for documents from untrusted sources.
+    pub fn all() -> Self {
+        Self {
+            block_remote_images: true,
+            confirm_external_links: true,
+            block_data_uris: true,
+            block_raw_html: true,
+        }
+    }
+
+    /// Why the image at `uri` isn't loaded, when it isn't.
+    pub fn blocked_image(&self, uri: &str) -> Option<&'static str> {
+        if self.block_data_uris && has_scheme(uri, "data:") {
+            Some("Embedded data: images are blocked in this document")
+        } else if self.block_remote_images
+            && (has_scheme(uri, "http://") || has_scheme(uri, "https://"))
+        {
+            Some("Remote images are blocked in this document")
+        } else {
+            None
+        }
+    }
+
+    /// Whether the link to `url` is never opened.
+    pub fn blocks_link(&self, url: &str) -> bool {
+        self.block_data_uris && has_scheme(url, "data:")
+    }
+}
+
+/// Whether `uri` starts with `scheme`, in any case.
+fn has_scheme(uri: &str, scheme: &str) -> bool {
+    uri.trim_start()
+        .get(..scheme.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(scheme))
+}
+
 pub struct CommonMarkOptions<'f> {
     pub indentation_spaces: usize,
     pub max_image_width: Option<usize>,
     pub show_alt_text_on_hover: bool,
     pub default_width: Option<usize>,
+    /// Widest the column of text, tables and images may be
+    pub max_content_width: Option<f32>,
+    /// Whether a column narrower than the viewer is centered in it
+    pub center_content: bool,
+    pub text_direction: TextDirection,
+    /// Whether links can be selected along with the text around them
+    pub continuous_selection: bool,
     #[cfg(feature = "better_syntax_highlighting")]
     pub theme_light: String,
     #[cfg(feature = "better_syntax_highlighting")]
@@ -51,11 +468,121 @@ pub struct CommonMarkOptions<'f> {
     pub mutable: bool,
     pub math_fn: Option<&'f crate::RenderMathFn>,
     pub html_fn: Option<&'f crate::RenderHtmlFn>,
+    /// Renders images that failed to load in place of the built-in placeholder
+    pub image_error_fn: Option<&'f crate::RenderImageErrorFn>,
+    /// Told about task list checkboxes being clicked, which makes them
+    /// clickable without `mutable`
+    pub checkbox_clicked_fn: Option<&'f crate::CheckboxClickedFn>,
+    /// Called with every link and image destination before it is used
+    pub rewrite_url_fn: Option<&'f crate::RewriteUrlFn>,
+    /// Renderers for fenced code blocks, tried in order on blocks whose
+    /// language they match. Starts out with the chart renderer when the
+    /// `charts` feature is enabled.
+    pub code_block_fns: Vec<(&'f crate::CodeLangMatcher, &'f crate::RenderCodeBlockFn)>,
+    /// Called below every code block drawn by the viewer itself
+    pub code_block_footer_fn: Option<&'f crate::CodeBlockFooterFn>,
+    /// Consulted before the default rendering of headings, paragraphs,
+    /// images, links, code blocks and tables
+    pub render_hooks: Option<&'f dyn crate::hooks::CommonMarkRenderHooks>,
     /// Typography configuration for line height and spacing
     pub typography: TypographyConfig,
     /// Opt into using the named strong font family. Callers must register
     /// `STRONG_FONT_FAMILY` in egui before enabling this to avoid lookup panics.
     pub use_strong_font_family: bool,
+    /// Render recognized gemoji shortcodes such as `:smile:` as Unicode emoji
+    pub emoji_shortcodes: bool,
+    /// Parse `^sup^` / `~sub~` and honor inline `<sup>` / `<sub>` tags.
+    /// Changes `~text~` from strikethrough to subscript.
+    pub superscript_subscript: bool,
+    /// Expand the macros a document defines in its front matter or a leading
+    /// block of `\newcommand`s in all of its formulas
+    pub math_macros: bool,
+    /// Number display equations and resolve `\ref{}` / `\eqref{}` to them
+    pub equation_numbers: bool,
+    /// Make images and mermaid diagrams clickable, recording the click for
+    /// `CommonMarkCache::take_clicked_image` / `take_clicked_mermaid`
+    pub clickable_images: bool,
+    /// Show `done/total` next to every list that contains task list items
+    pub task_list_progress: bool,
+    /// Render soft line breaks (a single newline inside a paragraph) as line
+    /// breaks instead of spaces
+    pub soft_breaks_as_newlines: bool,
+    /// Accent bar colors for blockquotes by nesting depth, repeating once
+    /// exhausted. Empty deepens the theme's weak text color with each level.
+    pub blockquote_colors: Vec<egui::Color32>,
+    pub blockquote_style: BlockquoteStyle,
+    /// Blockquotes nested more than this many levels deep, like the older
+    /// messages of an email thread, are collapsed behind a "show quoted
+    /// text" toggle. `None` shows all of them.
+    pub collapse_quotes_deeper_than: Option<usize>,
+    pub list_markers: ListMarkers,
+    pub link_style: LinkStyle,
+    /// Painted behind the text rendered from them. Search matches set in the
+    /// cache are painted over them.
+    pub highlight_ranges: &'f [SourceRange],
+    /// Numbers put in front of headings, by the source offset the heading
+    /// starts at, sorted by it
+    pub heading_numbers: &'f [(usize, String)],
+    /// Widest a table column may grow to before its cells are clipped, or
+    /// wrapped when `table_wrap_cells` is enabled
+    pub table_max_column_width: Option<f32>,
+    /// Wrap long table cell text onto several lines instead of widening the
+    /// column. Columns are capped at the viewer width when no
+    /// `table_max_column_width` is set.
+    pub table_wrap_cells: bool,
+    /// Alternate the background color of table rows
+    pub table_striped: bool,
+    /// Highlight the table row under the cursor
+    pub table_row_hover: bool,
+    pub table_mode: TableMode,
+    /// Show a line number gutter beside code blocks
+    pub code_line_numbers: bool,
+    /// Show the fence language and the copy button in a strip above code
+    /// blocks instead of overlaying the copy button on the code
+    pub code_block_header: bool,
+    /// Copy only the commands of shell sessions, the lines after a `$ `
+    /// prompt in `console`, `bash` and similar blocks, without the prompts
+    pub copy_without_prompts: bool,
+    /// What the copy button of code blocks copies until another format is
+    /// picked from the dropdown beside it
+    pub code_copy_format: CodeCopyFormat,
+    /// Render `csv` and `tsv` fences as tables, with a button to show the
+    /// raw text instead
+    pub csv_tables: bool,
+    /// Show images of video or audio files and YouTube videos as a card with
+    /// a button to open them externally, and mark links to them with an icon
+    pub media_embeds: bool,
+    /// Text shown centered under images
+    pub image_captions: ImageCaptions,
+    /// Only load images near the viewport and release the ones scrolled far
+    /// away
+    pub lazy_images: bool,
+    /// Dim images, or invert their lightness, in dark mode
+    pub dark_mode_images: DarkModeImages,
+    /// Start loading the images of the next screenful, or more when
+    /// scrolling fast, before they scroll into view
+    pub image_prefetch: bool,
+    /// Right-clicking an image or diagram offers to copy or save it
+    pub image_context_menu: bool,
+    /// Right-clicking a table offers to copy it as CSV, TSV or Markdown
+    pub table_context_menu: bool,
+    /// Show a swatch of the color beside inline code that is a hex or
+    /// `rgb()` color, like `#ff8800`
+    pub color_swatches: bool,
+    /// Show a button beside hovered headings that copies a link to them,
+    /// `#slug` after the given document name such as `notes.md`. `None`
+    /// shows no button.
+    pub heading_link_button: Option<String>,
+    /// Show a button on hovered paragraphs and list items that copies their
+    /// markdown source
+    pub block_copy_buttons: bool,
+    /// What the document is kept from doing, for untrusted documents
+    pub sandbox: Sandbox,
+    /// Long documents are parsed on a worker thread and laid out over several
+    /// frames behind a progress bar
+    pub progressive_loading: bool,
+    /// Markdown extensions that are interpreted
+    pub extensions: MarkdownExtensions,
 }
 
 impl std::fmt::Debug for CommonMarkOptions<'_> {
@@ -65,7 +592,11 @@ impl std::fmt::Debug for CommonMarkOptions<'_> {
         s.field("indentation_spaces", &self.indentation_spaces)
             .field("max_image_width", &self.max_image_width)
             .field("show_alt_text_on_hover", &self.show_alt_text_on_hover)
-            .field("default_width", &self.default_width);
+            .field("default_width", &self.default_width)
+            .field("max_content_width", &self.max_content_width)
+            .field("center_content", &self.center_content)
+            .field("text_direction", &self.text_direction)
+            .field("continuous_selection", &self.continuous_selection);
 
         #[cfg(feature = "better_syntax_highlighting")]
         s.field("theme_light", &self.theme_light)
@@ -80,6 +611,43 @@ impl std::fmt::Debug for CommonMarkOptions<'_> {
             .field("mutable", &self.mutable)
             .field("typography", &self.typography)
             .field("use_strong_font_family", &self.use_strong_font_family)
+            .field("emoji_shortcodes", &self.emoji_shortcodes)
+            .field("superscript_subscript", &self.superscript_subscript)
+            .field("math_macros", &self.math_macros)
+            .field("equation_numbers", &self.equation_numbers)
+            .field("clickable_images", &self.clickable_images)
+            .field("task_list_progress", &self.task_list_progress)
+            .field("soft_breaks_as_newlines", &self.soft_breaks_as_newlines)
+            .field("blockquote_colors", &self.blockquote_colors)
+            .field("blockquote_style", &self.blockquote_style)
+            .field("collapse_quotes_deeper_than", &self.collapse_quotes_deeper_than)
+            .field("list_markers", &self.list_markers)
+            .field("link_style", &self.link_style)
+            .field("highlight_ranges", &self.highlight_ranges)
+            .field("heading_numbers", &self.heading_numbers)
+            .field("table_max_column_width", &self.table_max_column_width)
+            .field("table_wrap_cells", &self.table_wrap_cells)
+            .field("table_striped", &self.table_striped)
+            .field("table_row_hover", &self.table_row_hover)
+            .field("table_mode", &self.table_mode)
+            .field("code_line_numbers", &self.code_line_numbers)
+            .field("code_block_header", &self.code_block_header)
+            .field("copy_without_prompts", &self.copy_without_prompts)
+            .field("code_copy_format", &self.code_copy_format)
+            .field("csv_tables", &self.csv_tables)
+            .field("media_embeds", &self.media_embeds)
+            .field("image_captions", &self.image_captions)
+            .field("lazy_images", &self.lazy_images)
+            .field("dark_mode_images", &self.dark_mode_images)
+            .field("image_prefetch", &self.image_prefetch)
+            .field("image_context_menu", &self.image_context_menu)
+            .field("table_context_menu", &self.table_context_menu)
+            .field("color_swatches", &self.color_swatches)
+            .field("heading_link_button", &self.heading_link_button)
+            .field("block_copy_buttons", &self.block_copy_buttons)
+            .field("sandbox", &self.sandbox)
+            .field("progressive_loading", &self.progressive_loading)
+            .field("extensions", &self.extensions)
             .finish()
     }
 }
@@ -91,6 +659,10 @@ impl Default for CommonMarkOptions<'_> {
             max_image_width: None,
             show_alt_text_on_hover: true,
             default_width: None,
+            max_content_width: None,
+            center_content: false,
+            text_direction: TextDirection::default(),
+            continuous_selection: false,
             #[cfg(feature = "better_syntax_highlighting")]
             theme_light: DEFAULT_THEME_LIGHT.to_owned(),
             #[cfg(feature = "better_syntax_highlighting")]
@@ -101,8 +673,54 @@ impl Default for CommonMarkOptions<'_> {
             mutable: false,
             math_fn: None,
             html_fn: None,
+            image_error_fn: None,
+            checkbox_clicked_fn: None,
+            rewrite_url_fn: None,
+            #[cfg(not(feature = "charts"))]
+            code_block_fns: Vec::new(),
+            #[cfg(feature = "charts")]
+            code_block_fns: vec![(&crate::charts::is_chart_fence, &crate::charts::render_chart)],
+            code_block_footer_fn: None,
+            render_hooks: None,
             typography: TypographyConfig::default(),
             use_strong_font_family: false,
+            emoji_shortcodes: true,
+            superscript_subscript: false,
+            math_macros: false,
+            equation_numbers: false,
+            clickable_images: false,
+            task_list_progress: false,
+            soft_breaks_as_newlines: false,
+            blockquote_colors: Vec::new(),
+            blockquote_style: BlockquoteStyle::default(),
+            collapse_quotes_deeper_than: None,
+            list_markers: ListMarkers::default(),
+            link_style: LinkStyle::default(),
+            highlight_ranges: &[],
+            heading_numbers: &[],
+            table_max_column_width: None,
+            table_wrap_cells: false,
+            table_striped: true,
+            table_row_hover: false,
+            table_mode: TableMode::Scroll,
+            code_line_numbers: false,
+            code_block_header: false,
+            copy_without_prompts: false,
+            code_copy_format: CodeCopyFormat::Code,
+            csv_tables: false,
+            media_embeds: false,
+            image_captions: ImageCaptions::Off,
+            lazy_images: false,
+            dark_mode_images: DarkModeImages::Off,
+            image_prefetch: false,
+            image_context_menu: false,
+            table_context_menu: false,
+            color_swatches: false,
+            heading_link_button: None,
+            block_copy_buttons: false,
+            sandbox: Sandbox::default(),
+            progressive_loading: false,
+            extensions: MarkdownExtensions::default(),
         }
     }
 }
@@ -122,18 +740,240 @@ impl CommonMarkOptions<'_> {
 
         // Use default_width as the preferred width, but never exceed available_width
         // This ensures text wraps properly when the window is narrower than default_width
-        if let Some(default_width) = self.default_width {
+        let width = if let Some(default_width) = self.default_width {
             (default_width as f32).min(available_width)
         } else {
             available_width
+        };
+        self.max_content_width.map_or(width, |max| width.min(max))
+    }
+
+    /// How far the content column is moved right to center it, 0 unless
+    /// `center_content` is set and there is room to spare.
+    pub fn content_margin(&self, ui: &Ui) -> f32 {
+        if self.center_content {
+            ((ui.available_width() - self.max_width(ui)) / 2.0).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether a block made of `texts` is laid out right to left.
+    pub fn is_rtl<'t>(&self, texts: impl IntoIterator<Item = &'t str>) -> bool {
+        match self.text_direction {
+            TextDirection::Auto => texts.into_iter().find_map(is_right_to_left).unwrap_or(false),
+            TextDirection::LeftToRight => false,
+            TextDirection::RightToLeft => true,
+        }
+    }
+
+    /// `url` as rewritten by `rewrite_url_fn`.
+    pub fn rewrite_url(&self, url: &str, is_image: bool) -> String {
+        self.rewrite_url_fn
+            .and_then(|rewrite_url_fn| rewrite_url_fn(url, is_image))
+            .unwrap_or_else(|| url.to_owned())
+    }
+
+    /// Accent bar color of a blockquote nested `depth` levels deep (0 for a
+    /// top-level one).
+    pub fn blockquote_color(&self, ui: &Ui, depth: usize) -> egui::Color32 {
+        if self.blockquote_colors.is_empty() {
+            let visuals = ui.visuals();
+            let t = (depth as f32 * 0.35).min(1.0);
+            visuals
+                .weak_text_color()
+                .lerp_to_gamma(visuals.strong_text_color(), t)
+        } else {
+            self.blockquote_colors[depth % self.blockquote_colors.len()]
+        }
+    }
+}
+
+/// A top-level rendered block and where it came from in the markdown source.
+///
+/// Recorded by the renderer on every paint so hosts can cross-reference the
+/// rendered document with the source text (line number gutters, scroll sync).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceBlock {
+    /// Byte range of the block in the source text.
+    pub byte_range: std::ops::Range<usize>,
+    /// 1-based line number of the block's first source line.
+    pub line: usize,
+    /// 1-based line number of the block's last source line.
+    pub end_line: usize,
+    /// Content-relative vertical extent (same space as
+    /// [`CommonMarkCache::get_header_position`]). Runs from the block's top
+    /// edge to the top of the next block, so consecutive ranges tile the
+    /// document without gaps.
+    pub y_range: egui::Rangef,
+}
+
+/// A part of the document whose rendering panicked. It is shown as an error
+/// placeholder instead, and skipped until the document changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderError {
+    /// Byte range of the part in the source text.
+    pub byte_range: std::ops::Range<usize>,
+    /// The panic message
+    pub message: String,
+}
+
+impl RenderError {
+    pub fn from_panic(
+        byte_range: std::ops::Range<usize>,
+        panic: &(dyn std::any::Any + Send),
+    ) -> Self {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_owned());
+        Self {
+            byte_range,
+            message,
         }
     }
 }
 
+/// Where [`CommonMarkCache`] records the position of a heading: the viewer
+/// it was shown in, its [`heading_slug`](crate::anchor::heading_slug) and how
+/// many earlier headings of the document share that slug. Keyed like this,
+/// duplicate headings and documents shown side by side don't overwrite each
+/// other's positions.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HeaderKey {
+    /// The id the document was shown with, `egui::Id::NULL` for documents
+    /// shown without one.
+    pub source_id: egui::Id,
+    /// 0 for the first heading with this slug, 1 for the second, and so on.
+    pub occurrence: usize,
+    pub slug: String,
+}
+
+impl HeaderKey {
+    /// Key of the `occurrence`th heading with `slug` in the document shown
+    /// with `source_id`.
+    pub fn new(source_id: impl std::hash::Hash, occurrence: usize, slug: impl Into<String>) -> Self {
+        Self {
+            source_id: egui::Id::new(source_id),
+            occurrence,
+            slug: slug.into(),
+        }
+    }
+}
+
+/// A heading as it was last rendered, see [`CommonMarkCache::headings`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderedHeading {
+    /// Holds the heading's slug
+    pub key: HeaderKey,
+    /// 1 for `#` to 6 for `######`
+    pub level: u8,
+    /// The text of the heading without its markup
+    pub text: String,
+    /// Content-relative vertical extent, from the top of the heading to the
+    /// bottom of its text
+    pub y_range: egui::Rangef,
+}
+
+/// The text of a table's cells, without their markup.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableCells {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// Alignment of each column, empty for tables that have none
+    pub alignments: Vec<pulldown_cmark::Alignment>,
+}
+
+impl TableCells {
+    fn columns(&self) -> usize {
+        self.rows
+            .iter()
+            .map(Vec::len)
+            .chain([self.header.len()])
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn all_rows(&self) -> impl Iterator<Item = &Vec<String>> {
+        std::iter::once(&self.header)
+            .filter(|header| !header.is_empty())
+            .chain(&self.rows)
+    }
+
+    /// Comma separated values, quoting cells with commas, quotes or line
+    /// breaks as RFC 4180 does.
+    pub fn to_csv(&self) -> String {
+        let quote = |cell: &String| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        };
+        self.all_rows()
+            .map(|row| row.iter().map(quote).collect::<Vec<_>>().join(",") + "\n")
+            .collect()
+    }
+
+    /// Tab separated values. Tabs and line breaks in cells become spaces.
+    pub fn to_tsv(&self) -> String {
+        let clean = |cell: &String| cell.replace(['\t', '\n', '\r'], " ");
+        self.all_rows()
+            .map(|row| row.iter().map(clean).collect::<Vec<_>>().join("\t") + "\n")
+            .collect()
+    }
+
+    /// A GitHub flavored Markdown table keeping the column alignments.
+    pub fn to_markdown(&self) -> String {
+        let columns = self.columns();
+        let row = |cells: &[String]| {
+            let cells = (0..columns).map(|i| {
+                cells
+                    .get(i)
+                    .map_or(String::new(), |cell| cell.replace('|', "\\|").replace('\n', " "))
+            });
+            format!("| {} |\n", cells.collect::<Vec<_>>().join(" | "))
+        };
+        let delimiter = (0..columns).map(|i| match self.alignments.get(i) {
+            Some(pulldown_cmark::Alignment::Left) => ":---",
+            Some(pulldown_cmark::Alignment::Center) => ":---:",
+            Some(pulldown_cmark::Alignment::Right) => "---:",
+            _ => "---",
+        });
+        let mut markdown = row(&self.header);
+        markdown += &format!("| {} |\n", delimiter.collect::<Vec<_>>().join(" | "));
+        for cells in &self.rows {
+            markdown += &row(cells);
+        }
+        markdown
+    }
+}
+
+/// A table as it was last rendered, see [`CommonMarkCache::tables`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderedTable {
+    /// The id the document was shown with, `egui::Id::NULL` for documents
+    /// shown without one.
+    pub source_id: egui::Id,
+    /// 0 for the first table of the document, 1 for the second, and so on
+    pub index: usize,
+    pub cells: TableCells,
+}
+
 /// Font family name used for Markdown strong text when the app registers a bold face.
 pub const STRONG_FONT_FAMILY: &str = "MarkdownStrong";
 
-#[derive(Default, Clone)]
+/// `FontFamily::Name(STRONG_FONT_FAMILY)`, interned so styling strong text
+/// doesn't allocate the name again for every run.
+fn strong_font() -> egui::FontFamily {
+    static STRONG_FONT: std::sync::OnceLock<egui::FontFamily> = std::sync::OnceLock::new();
+    STRONG_FONT
+        .get_or_init(|| egui::FontFamily::Name(STRONG_FONT_FAMILY.into()))
+        .clone()
+}
+
+#[derive(Default, Clone, Hash)]
 pub struct Style {
     pub heading: Option<u8>,
     pub strong: bool,
@@ -141,6 +981,8 @@ pub struct Style {
     pub strikethrough: bool,
     pub quote: bool,
     pub code: bool,
+    pub superscript: bool,
+    pub subscript: bool,
 }
 
 impl Style {
@@ -240,6 +1082,13 @@ impl Style {
             }
         }
 
+        if let Some(typo) = typography {
+            if typo.letter_spacing.is_some() {
+                let letter_spacing = typo.resolve_letter_spacing(selected_font_size);
+                rich_text = rich_text.extra_letter_spacing(letter_spacing);
+            }
+        }
+
         if self.quote {
             rich_text = rich_text.weak();
         }
@@ -252,7 +1101,7 @@ impl Style {
             if use_strong_font_family && !self.code {
                 rich_text = rich_text.font(egui::FontId::new(
                     selected_font_size,
-                    egui::FontFamily::Name(STRONG_FONT_FAMILY.into()),
+                    strong_font(),
                 ));
             }
         }
@@ -276,6 +1125,15 @@ impl Style {
             rich_text = rich_text.code().size(selected_font_size);
         }
 
+        if self.superscript || self.subscript {
+            // Rows align text to the bottom, so smaller text already sits
+            // lowered like a subscript; superscript is raised to the top.
+            rich_text = rich_text.size(selected_font_size * 0.75);
+            if self.superscript {
+                rich_text = rich_text.raised();
+            }
+        }
+
         rich_text
     }
 }
@@ -284,6 +1142,67 @@ impl Style {
 mod tests {
     use super::*;
 
+    #[test]
+    fn color_codes_are_hex_or_rgb() {
+        use egui::Color32;
+        assert_eq!(parse_color_code("#ff8800"), Some(Color32::from_rgb(255, 136, 0)));
+        assert_eq!(parse_color_code("#F80"), Some(Color32::from_rgb(255, 136, 0)));
+        assert_eq!(
+            parse_color_code("#ff880080"),
+            Some(Color32::from_rgba_unmultiplied(255, 136, 0, 128))
+        );
+        assert_eq!(parse_color_code("rgb(10, 20, 30)"), Some(Color32::from_rgb(10, 20, 30)));
+        assert_eq!(
+            parse_color_code("rgba(10,20,30,0.5)"),
+            Some(Color32::from_rgba_unmultiplied(10, 20, 30, 128))
+        );
+        assert_eq!(
+            parse_color_code("rgb(10 20 30 / 50%)"),
+            Some(Color32::from_rgba_unmultiplied(10, 20, 30, 128))
+        );
+        for not_a_color in ["#ff888", "#ggg", "#1234567", "rgb(300, 0, 0)", "rgb(1, 2)", "red"] {
+            assert_eq!(parse_color_code(not_a_color), None, "{not_a_color}");
+        }
+    }
+
+    #[test]
+    fn table_cells_copy_as_csv_tsv_and_markdown() {
+        let cells = TableCells {
+            header: vec!["Name".into(), "Note".into()],
+            rows: vec![
+                vec!["a, b".into(), "say \"hi\"".into()],
+                vec!["x|y".into(), "tab\there".into()],
+                vec!["short".into()],
+            ],
+            alignments: vec![pulldown_cmark::Alignment::Left, pulldown_cmark::Alignment::Right],
+        };
+        assert_eq!(
+            cells.to_csv(),
+            "Name,Note\n\"a, b\",\"say \"\"hi\"\"\"\nx|y,tab\there\nshort\n"
+        );
+        assert_eq!(
+            cells.to_tsv(),
+            "Name\tNote\na, b\tsay \"hi\"\nx|y\ttab here\nshort\n"
+        );
+        assert_eq!(
+            cells.to_markdown(),
+            "| Name | Note |\n| :--- | ---: |\n| a, b | say \"hi\" |\n\
+             | x\\|y | tab\there |\n| short |  |\n"
+        );
+    }
+
+    #[test]
+    fn shell_sessions_copy_only_their_commands() {
+        let session = "$ cargo build \\\n    --release\n   Compiling app\n  $ ls\nREADME.md\n";
+        assert_eq!(
+            shell_session_commands(session).as_deref(),
+            Some("cargo build \\\n    --release\nls")
+        );
+        assert_eq!(shell_session_commands("echo hi\n# $ not a prompt\n"), None);
+        assert!(is_shell_fence("console"));
+        assert!(!is_shell_fence("rust"));
+    }
+
     fn first_text_format(ui: &Ui, rich_text: RichText) -> egui::TextFormat {
         // Convert RichText into the same LayoutJob format path widgets use so
         // tests can inspect formatting decisions without depending on pixels.
@@ -386,23 +1305,623 @@ mod tests {
                 .to_richtext_with_options(ui, "code", &options),
             );
 
-            assert_eq!(
-                strong_code_format.font_id.family, code_format.font_id.family,
-                "strong inline code should preserve code font family; code={code_format:?}, strong_code={strong_code_format:?}"
-            );
+            assert_eq!(
+                strong_code_format.font_id.family, code_format.font_id.family,
+                "strong inline code should preserve code font family; code={code_format:?}, strong_code={strong_code_format:?}"
+            );
+        });
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn list_numbering_labels() {
+        let labels = |numbering: ListNumbering, numbers: &[u64]| {
+            numbers
+                .iter()
+                .map(|&number| numbering.label(number))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            labels(ListNumbering::LowerAlpha, &[1, 26, 27, 52, 703, 0]),
+            ["a", "z", "aa", "az", "aaa", "0"]
+        );
+        assert_eq!(labels(ListNumbering::UpperAlpha, &[2, 28]), ["B", "AB"]);
+        assert_eq!(
+            labels(ListNumbering::LowerRoman, &[1, 4, 9, 14, 1994, 4000]),
+            ["i", "iv", "ix", "xiv", "mcmxciv", "4000"]
+        );
+        assert_eq!(labels(ListNumbering::UpperRoman, &[40]), ["XL"]);
+        assert_eq!(labels(ListNumbering::Decimal, &[7]), ["7"]);
+
+        let markers = ListMarkers {
+            bullets: vec!['•', '◦'],
+            ..Default::default()
+        };
+        assert_eq!(markers.bullet(2), Some('•'));
+        assert_eq!(markers.numbering(3), ListNumbering::Decimal);
+        assert_eq!(ListMarkers::default().bullet(0), None);
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn syntax_theme_names_lists_bundled_defaults() {
+        let cache = CommonMarkCache::default();
+        let names: Vec<&str> = cache.syntax_theme_names().collect();
+        assert!(names.contains(&"base16-ocean.dark"), "{names:?}");
+        assert!(names.contains(&"base16-ocean.light"), "{names:?}");
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn added_themes_stay_with_their_cache() {
+        let mut cache = CommonMarkCache::default();
+        let theme = GLOBAL_THEME_SET.themes["Solarized (dark)"].clone();
+        Arc::make_mut(&mut cache.themes).insert("mine".to_owned(), theme);
+        assert!(cache.syntax_theme_names().any(|name| name == "mine"));
+        assert!(!CommonMarkCache::default()
+            .syntax_theme_names()
+            .any(|name| name == "mine"));
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn shared_resources_are_not_copied_or_changed_by_other_caches() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let mut cache = CommonMarkCache::default();
+        let syntax = "name: Mine\nfile_extensions: [mine]\nscope: source.mine\ncontexts:\n  main: []\n";
+        cache.add_syntax_from_str(syntax, None);
+        let theme = GLOBAL_THEME_SET.themes["Solarized (dark)"].clone();
+        Arc::make_mut(&mut cache.themes).insert("mine".to_owned(), theme.clone());
+        assert_send_sync(&cache);
+
+        let mut other = cache.share_resources();
+        assert!(Arc::ptr_eq(cache.syntax_set(), other.syntax_set()));
+        assert!(Arc::ptr_eq(&cache.themes, &other.themes));
+        assert!(other.syntax_set().find_syntax_by_extension("mine").is_some());
+
+        Arc::make_mut(&mut other.themes).insert("theirs".to_owned(), theme);
+        assert!(!cache.syntax_theme_names().any(|name| name == "theirs"));
+    }
+
+    #[test]
+    fn code_block_search_matches_map_onto_its_content() {
+        let mut block = CodeBlock::new(Some("rs"));
+        // An indented line comes without its indent
+        block.push_text("let a = 1;\n", 10..21);
+        block.push_text("a += a;\n", 25..33);
+        let mut cache = CommonMarkCache::default();
+        cache.set_search_ranges(vec![2..3, 14..15, 25..26, 30..31]);
+        cache.set_active_search_range(Some(30..31));
+        let matches = block.search_matches(&cache);
+        assert_eq!(matches.ranges, vec![4..5, 11..12, 16..17]);
+        assert_eq!(matches.active, Some(2));
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn switching_syntax_theme_rehighlights_code_block() {
+        // The LayoutJob cache must key on the theme name, not just dark/light,
+        // or a runtime theme switch keeps showing the old colors.
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            let block = CodeBlock {
+                content: "fn main() {}\n".to_owned(),
+                ..CodeBlock::new(Some("rs"))
+            };
+            let mut options = CommonMarkOptions::default();
+            for theme in ["base16-ocean.dark", "Solarized (dark)"] {
+                options.theme_dark = theme.to_owned();
+                options.theme_light = theme.to_owned();
+                block.end(ui, &mut cache, &options, 400.0, egui::Id::new(theme));
+            }
+            assert_eq!(cache.syntax_layouts.len(), 2);
+        });
+    }
+
+    #[cfg(feature = "mermaid")]
+    #[test]
+    fn mermaid_renders_are_bounded_by_the_thread_count() {
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            cache.set_mermaid_threads(2);
+            let options = CommonMarkOptions::default();
+            for node in ["A", "B", "C"] {
+                let block = CodeBlock {
+                    content: format!("graph TD\n  {node} --> Z\n"),
+                    ..CodeBlock::new(Some("mermaid"))
+                };
+                block.end(ui, &mut cache, &options, 400.0, egui::Id::new(node));
+            }
+            assert_eq!(cache.mermaid_states.len(), 3);
+            assert!(cache.mermaid_rendering.len() <= 2);
+        });
+    }
+
+    #[cfg(feature = "mermaid")]
+    #[test]
+    fn mermaid_follows_the_ui_theme() {
+        let dark = MermaidTheme::from_visuals(&egui::Visuals::dark()).site_config();
+        assert_eq!(dark.get_str("theme"), Some("dark"));
+        assert_eq!(dark.get_bool("themeVariables.darkMode"), Some(true));
+        let light = MermaidTheme::from_visuals(&egui::Visuals::light()).site_config();
+        assert_eq!(light.get_str("theme"), Some("default"));
+        let [r, g, b, _] = egui::Visuals::light().panel_fill.to_array();
+        let background = format!("#{r:02x}{g:02x}{b:02x}");
+        assert_eq!(
+            light.get_str("themeVariables.background"),
+            Some(background.as_str())
+        );
+    }
+
+    #[cfg(feature = "mermaid")]
+    #[test]
+    fn diagrams_rasterize_at_twice_the_display_density() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="30" height="20"/>"#;
+        for ppp in [1.0, 1.5] {
+            let (image, size) = rasterize_mermaid_svg(svg, ppp).unwrap();
+            assert_eq!(size, egui::vec2(30.0, 20.0));
+            assert_eq!(image.size, [(60.0 * ppp) as usize, (40.0 * ppp) as usize]);
+        }
+    }
+
+    #[cfg(feature = "graphviz")]
+    #[test]
+    fn dot_fences_lay_out_to_svg() {
+        let svg = graphviz_svg("digraph { a -> b [label=\"x\"]; a -> c; }").unwrap();
+        assert!(svg.contains("<svg"));
+        assert!(svg.contains("fill=\"white\""));
+        let svg = CodeBlock::sanitize_svg_font_family(&svg);
+        assert!(rasterize_mermaid_svg(svg.as_bytes(), 1.0).is_some());
+        assert!(graphviz_svg("digraph { a -> ; }").is_err());
+        assert!(graphviz_svg("digraph { }").is_err());
+    }
+
+    #[test]
+    fn diff_lines_are_colored_by_kind() {
+        egui::__run_test_ui(|ui| {
+            let diff = "--- a/f\n+++ b/f\n@@ -1 +1 @@\n-old\n+new\n same\n";
+            let job = diff_highlighting(ui, diff, None);
+            assert_eq!(job.text, diff);
+            let backgrounds: Vec<_> = job.sections.iter().map(|s| s.format.background).collect();
+            let none = egui::Color32::TRANSPARENT;
+            assert_eq!(backgrounds[..3], [none, none, none]);
+            assert_ne!(backgrounds[3], none);
+            assert_ne!(backgrounds[4], none);
+            assert_ne!(backgrounds[3], backgrounds[4]);
+            assert_eq!(backgrounds[5], none);
+            assert_eq!(job.sections[2].format.color, ui.visuals().hyperlink_color);
+        });
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn large_code_blocks_are_highlighted_in_the_background() {
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            let block = CodeBlock {
+                content: "fn main() {}\n".repeat(BACKGROUND_HIGHLIGHT_MIN_BYTES / 8),
+                ..CodeBlock::new(Some("rs"))
+            };
+            let options = CommonMarkOptions::default();
+            block.end(ui, &mut cache, &options, 400.0, egui::Id::new("code"));
+            // Shown as plain text for now; the worker is still running.
+            assert!(cache.syntax_layouts.is_empty());
+            assert_eq!(cache.highlighting.len(), 1);
+
+            let start = std::time::Instant::now();
+            while !cache.highlighting.is_empty() {
+                assert!(start.elapsed() < std::time::Duration::from_secs(30));
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                cache.receive_highlighted();
+            }
+            let job = cache.syntax_layouts.values().next().unwrap();
+            assert!(job.sections.len() > 1);
+        });
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    #[test]
+    fn background_highlighting_is_bounded_and_survives_failed_workers() {
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            let options = CommonMarkOptions::default();
+            for i in 0..BACKGROUND_HIGHLIGHT_THREADS + 3 {
+                let block = CodeBlock {
+                    content: format!("fn f{i}() {{}}\n").repeat(BACKGROUND_HIGHLIGHT_MIN_BYTES / 8),
+                    ..CodeBlock::new(Some("rs"))
+                };
+                block.end(ui, &mut cache, &options, 400.0, egui::Id::new(i));
+            }
+            assert_eq!(cache.highlighting.len(), BACKGROUND_HIGHLIGHT_THREADS);
+
+            // A worker that panicked still reports back, and its block stays plain
+            cache.highlighting.insert(7);
+            drop(HighlightDone {
+                key: 7,
+                job: None,
+                tx: cache.highlight_tx.clone(),
+            });
+            let start = std::time::Instant::now();
+            while cache.highlighting.contains(&7) {
+                assert!(start.elapsed() < std::time::Duration::from_secs(30));
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                cache.receive_highlighted();
+            }
+            assert!(cache.highlight_failed.contains(&7));
+        });
+    }
+
+    #[test]
+    fn images_only_sense_clicks_when_opted_in() {
+        let mut options = CommonMarkOptions::default();
+        assert!(!image_sense(&options).senses_click());
+        options.clickable_images = true;
+        assert!(image_sense(&options).senses_click());
+    }
+
+    #[test]
+    fn saved_images_are_named_after_their_uri() {
+        assert_eq!(image_file_name("https://e.com/img/cat.png?s=2#x"), "cat.png");
+        assert_eq!(image_file_name("file:///home/a/b.svg"), "b.svg");
+        assert_eq!(image_file_name("https://e.com/"), "image");
+        assert_eq!(image_file_name("data:image/png;base64,iVBO/Rw=="), "image");
+    }
+
+    #[test]
+    fn sandbox_blocks_remote_and_data_images() {
+        let sandbox = Sandbox::all();
+        assert!(sandbox.blocked_image("https://e.com/a.png").is_some());
+        assert!(sandbox.blocked_image("HTTP://e.com/a.png").is_some());
+        assert!(sandbox.blocked_image("data:image/png;base64,iVBO").is_some());
+        assert_eq!(sandbox.blocked_image("file:///home/a/b.png"), None);
+        assert!(sandbox.blocks_link(" data:text/html,<b>hi</b>"));
+        assert!(!sandbox.blocks_link("https://e.com"));
+
+        let open = Sandbox::default();
+        assert_eq!(open.blocked_image("https://e.com/a.png"), None);
+        assert!(!open.blocks_link("data:text/html,x"));
+    }
+
+    #[test]
+    fn typography_spaces_letters_and_words() {
+        egui::__run_test_ui(|ui| {
+            let mut options = CommonMarkOptions::default();
+            options.typography.letter_spacing = Some(crate::typography::Measurement::Pixels(1.5));
+            let rich_text = Style::default().to_richtext_with_options(ui, "a  b c", &options);
+            let mut job = egui::WidgetText::from(rich_text).into_layout_job(
+                ui.style(),
+                egui::FontSelection::Default,
+                egui::Align::Center,
+            );
+            crate::typography::space_words(Arc::make_mut(&mut job), 4.0);
+            let sections: Vec<_> = job
+                .sections
+                .iter()
+                .map(|section| (&job.text[section.byte_range.clone()], section.leading_space))
+                .collect();
+            assert_eq!(
+                sections,
+                [("a", 0.0), ("  ", 4.0), ("b", 0.0), (" ", 4.0), ("c", 0.0)]
+            );
+            assert!(job
+                .sections
+                .iter()
+                .all(|section| section.format.extra_letter_spacing == 1.5));
+        });
+    }
+
+    #[test]
+    fn the_first_letter_decides_the_direction() {
+        assert_eq!(is_right_to_left("1. שלום world"), Some(true));
+        assert_eq!(is_right_to_left("«مرحبا»"), Some(true));
+        assert_eq!(is_right_to_left("hello שלום"), Some(false));
+        assert_eq!(is_right_to_left("42 - 7"), None);
+
+        let options = CommonMarkOptions {
+            text_direction: TextDirection::Auto,
+            ..Default::default()
+        };
+        assert!(options.is_rtl(["", "123 ", "שלום"]));
+        assert!(!options.is_rtl(["123"]));
+        assert!(!CommonMarkOptions::default().is_rtl(["שלום"]));
+    }
+
+    #[test]
+    fn image_uris_are_rewritten_before_the_implicit_scheme() {
+        let rewrite: &crate::RewriteUrlFn = &|url, is_image| {
+            assert!(is_image);
+            url.strip_prefix("wiki:")
+                .map(|page| format!("https://wiki.example/{page}"))
+                .or_else(|| (url == "keep.png").then(|| "docs/keep.png".to_owned()))
+        };
+        let options = CommonMarkOptions {
+            rewrite_url_fn: Some(rewrite),
+            ..Default::default()
+        };
+        assert_eq!(
+            Image::new("wiki:Cat.png", &options).uri,
+            "https://wiki.example/Cat.png"
+        );
+        assert_eq!(Image::new("keep.png", &options).uri, "file://docs/keep.png");
+        assert_eq!(Image::new("other.png", &options).uri, "file://other.png");
+    }
+
+    #[test]
+    fn lazy_images_load_near_the_viewport_only() {
+        egui::__run_test_ui(|ui| {
+            let mut cache = CommonMarkCache::default();
+            let options = CommonMarkOptions {
+                lazy_images: true,
+                ..Default::default()
+            };
+            Image::new("file:///near.png", &options).end(ui, &mut cache, &options);
+            ui.add_space(ui.clip_rect().height() * 5.0);
+            let top = ui.cursor().top();
+            assert_eq!(
+                Image::new("file:///far.png", &options).end(ui, &mut cache, &options),
+                None
+            );
+            // The far image still takes up space.
+            assert_eq!(ui.cursor().top() - top, 200.0 + ui.spacing().item_spacing.y);
+            assert!(cache.loaded_images.contains_key("file:///near.png"));
+            assert!(!cache.loaded_images.contains_key("file:///far.png"));
+        });
+    }
+
+    #[test]
+    fn dark_mode_images_are_dimmed_or_inverted_unless_opted_out() {
+        let ctx = egui::Context::default();
+        ctx.set_visuals(egui::Visuals::dark());
+        let mut cache = CommonMarkCache::default();
+        let mut options = CommonMarkOptions {
+            dark_mode_images: DarkModeImages::Dim,
+            ..Default::default()
+        };
+        let dim = Some(egui::Color32::from_gray(DARK_MODE_IMAGE_DIM));
+        assert_eq!(dark_mode_tint(&ctx, &cache, &options, "a.png"), dim);
+
+        options.dark_mode_images = DarkModeImages::Invert;
+        assert_eq!(dark_mode_tint(&ctx, &cache, &options, "a.png"), None);
+        assert_eq!(dark_mode_tint(&ctx, &cache, &options, "b.svg"), dim);
+
+        cache.set_image_unadjusted("b.svg", true);
+        assert_eq!(dark_mode_tint(&ctx, &cache, &options, "b.svg"), None);
+        ctx.set_visuals(egui::Visuals::light());
+        options.dark_mode_images = DarkModeImages::Dim;
+        assert_eq!(dark_mode_tint(&ctx, &cache, &options, "a.png"), None);
+    }
+
+    #[test]
+    fn text_layouts_are_reused_until_the_style_changes() {
+        egui::__run_test_ui(|ui| {
+            let options = CommonMarkOptions::default();
+            let mut layouts = TextLayouts::default();
+            let strong = Style {
+                strong: true,
+                ..Default::default()
+            };
+            let mut builds = 0;
+            let mut layout = |ui: &Ui, layouts: &mut TextLayouts, style: &Style| {
+                layouts.get_or_insert(ui, "text", style, None, || {
+                    builds += 1;
+                    style.to_richtext_with_options(ui, "text", &options)
+                })
+            };
+
+            layouts.begin(ui, &options);
+            let first = layout(ui, &mut layouts, &Style::default());
+            layout(ui, &mut layouts, &strong);
+            layouts.begin(ui, &options);
+            assert!(Arc::ptr_eq(&first, &layout(ui, &mut layouts, &Style::default())));
+            assert_eq!(layouts.len(), 2);
+
+            ui.visuals_mut().dark_mode = !ui.visuals().dark_mode;
+            layouts.begin(ui, &options);
+            assert!(layouts.is_empty());
+            layout(ui, &mut layouts, &Style::default());
+            assert_eq!(builds, 3);
+        });
+    }
+
+    #[test]
+    fn parsed_events_are_shared_until_the_text_changes() {
+        let ctx = egui::Context::default();
+        let mut cache = CommonMarkCache::default();
+        let options = pulldown_cmark::Options::empty();
+
+        let first = cache.parsed_events(&ctx, "# Title", options);
+        assert!(Arc::ptr_eq(&first, &cache.parsed_events(&ctx, "# Title", options)));
+        let tables = cache.parsed_events(&ctx, "# Title", pulldown_cmark::Options::ENABLE_TABLES);
+        assert!(!Arc::ptr_eq(&first, &tables));
+        let edited = cache.parsed_events(&ctx, "# Title!", options);
+        assert!(!Arc::ptr_eq(&first, &edited));
+        assert_eq!(edited.len(), first.len());
+    }
+
+    fn cache_with_blocks(blocks: &[(usize, usize, f32)]) -> CommonMarkCache {
+        let mut cache = CommonMarkCache::default();
+        for &(line, end_line, y) in blocks {
+            cache.record_source_block(egui::Id::new("doc"), SourceBlock {
+                byte_range: 0..0,
+                line,
+                end_line,
+                y_range: egui::Rangef::point(y),
+            });
+        }
+        cache.finish_source_blocks(egui::Id::new("doc"), 200.0);
+        cache
+    }
+
+    #[test]
+    fn y_for_line_interpolates_within_blocks() {
+        // Heading on line 1, four-line code block on lines 3-6, paragraph on 8.
+        let cache = cache_with_blocks(&[(1, 1, 0.0), (3, 6, 40.0), (8, 8, 120.0)]);
+        let y_for_line = |line| cache.y_for_line("doc", line);
+        assert_eq!(y_for_line(1), Some(0.0));
+        assert_eq!(y_for_line(2), Some(40.0));
+        assert_eq!(y_for_line(3), Some(40.0));
+        assert_eq!(y_for_line(5), Some(80.0));
+        assert_eq!(y_for_line(8), Some(120.0));
+        assert_eq!(y_for_line(50), Some(200.0));
+        assert_eq!(CommonMarkCache::default().y_for_line("doc", 1), None);
+    }
+
+    #[test]
+    fn line_for_y_inverts_y_for_line() {
+        let cache = cache_with_blocks(&[(1, 1, 0.0), (3, 6, 40.0), (8, 8, 120.0)]);
+        let id = "doc";
+        for line in [1, 3, 4, 5, 6, 8] {
+            let y = cache.y_for_line(id, line).unwrap();
+            assert_eq!(cache.line_for_y(id, y), Some(line), "line {line}");
+        }
+        assert_eq!(cache.line_for_y(id, -10.0), Some(1));
+        assert_eq!(cache.line_for_y(id, 1_000.0), Some(8));
+        assert_eq!(CommonMarkCache::default().line_for_y(id, 0.0), None);
+    }
+
+    #[test]
+    fn source_blocks_are_kept_per_viewer() {
+        let mut cache = cache_with_blocks(&[(1, 1, 0.0), (3, 6, 40.0)]);
+        let other = egui::Id::new("other");
+        cache.clear_source_blocks(other);
+        cache.record_source_block(other, SourceBlock {
+            byte_range: 0..0,
+            line: 1,
+            end_line: 10,
+            y_range: egui::Rangef::point(0.0),
         });
+        cache.finish_source_blocks(other, 1_000.0);
+
+        assert_eq!(cache.y_for_line("doc", 3), Some(40.0));
+        assert_eq!(cache.y_for_line("other", 3), Some(200.0));
+        assert_eq!(cache.line_for_y("doc", 100.0), Some(4));
+        assert!(cache.source_blocks("unshown").is_empty());
+    }
+
+    #[test]
+    fn heading_scrolls_go_to_the_anchor_of_their_own_viewer() {
+        let mut cache = CommonMarkCache::default();
+        let (left, right) = (egui::Id::new("left"), egui::Id::new("right"));
+        cache.record_anchor_position(left, "intro".to_owned(), 40.0);
+        cache.record_anchor_position(right, "intro".to_owned(), 300.0);
+
+        cache.scroll_to_heading("left", "#intro");
+        cache.scroll_to_heading("right", "#intro");
+        assert_eq!(cache.take_heading_scroll(left), Some(40.0));
+        assert_eq!(cache.take_heading_scroll(right), Some(300.0));
+        assert_eq!(cache.anchor_position("left", "intro"), Some(40.0));
+        assert_eq!(cache.anchor_position("elsewhere", "intro"), None);
+    }
+
+    #[test]
+    fn invalidating_one_viewer_keeps_the_anchors_of_others() {
+        let mut cache = CommonMarkCache::default();
+        let (left, right) = (egui::Id::new("left"), egui::Id::new("right"));
+        for id in [left, right] {
+            cache.invalidate_header_positions(id, "# Intro");
+            cache.record_anchor_position(id, "intro".to_owned(), 40.0);
+        }
+
+        cache.invalidate_header_positions(left, "# Intro, edited");
+        assert_eq!(cache.anchor_position("left", "intro"), None);
+        assert_eq!(cache.anchor_position("right", "intro"), Some(40.0));
+
+        cache.record_anchor_position(left, "intro".to_owned(), 40.0);
+        cache.invalidate_all("right");
+        assert_eq!(cache.anchor_position("left", "intro"), Some(40.0));
+        assert_eq!(cache.anchor_position("right", "intro"), None);
+    }
+
+    #[test]
+    fn selection_maps_to_overlapping_block_ranges() {
+        let mut cache = CommonMarkCache::default();
+        for (range, y) in [(0..8, 0.0), (9..30, 40.0), (31..40, 120.0)] {
+            cache.record_source_block(egui::Id::new("doc"), SourceBlock {
+                byte_range: range,
+                line: 1,
+                end_line: 1,
+                y_range: egui::Rangef::point(y),
+            });
+        }
+        cache.finish_source_blocks(egui::Id::new("doc"), 200.0);
+
+        let ranges =
+            |min, max| cache.source_ranges_for_selection("doc", egui::Rangef::new(min, max));
+        assert_eq!(ranges(50.0, 60.0), vec![9..30]);
+        assert_eq!(ranges(10.0, 130.0), vec![0..8, 9..30, 31..40]);
+        // A selection ending exactly where a block starts still includes it;
+        // one starting exactly where a block ends does not.
+        assert_eq!(ranges(40.0, 40.0), vec![9..30]);
+        assert!(ranges(250.0, 300.0).is_empty());
+    }
+
+    #[test]
+    fn link_tooltip_prefers_title_and_hides_hooked_urls() {
+        assert_eq!(link_tooltip("a.md", "", false).as_deref(), Some("a.md"));
+        assert_eq!(link_tooltip("a.md", "", true), None);
+        assert_eq!(
+            link_tooltip("a.md", "Intro", true).as_deref(),
+            Some("Intro")
+        );
+        assert_eq!(
+            link_tooltip("a.md", "Intro", false).as_deref(),
+            Some("Intro\na.md")
+        );
     }
 }
 
 #[derive(Default)]
 pub struct Link {
     pub destination: String,
+    /// The optional title from `[text](url "title")`, empty if absent.
+    pub title: String,
     pub text: Vec<RichText>,
 }
 
+/// A link that was clicked or hovered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkInfo {
+    pub destination: String,
+    /// The optional title from `[text](url "title")`, empty if absent.
+    pub title: String,
+    /// Whether the destination is a link hook, which isn't opened when
+    /// clicked
+    pub is_hook: bool,
+}
+
+/// How a hooked link was clicked, see [`CommonMarkCache::get_link_hook_click`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkClick {
+    /// `Primary` for a normal click, `Middle` for a middle click
+    pub button: egui::PointerButton,
+    /// The modifier keys held during the click
+    pub modifiers: egui::Modifiers,
+}
+
+/// Hover text for a link: the title when there is one, followed by the url
+/// unless the link is hooked (hooked urls are usually not meant for display).
+fn link_tooltip(destination: &str, title: &str, is_hook: bool) -> Option<String> {
+    match (title.is_empty(), is_hook) {
+        (true, true) => None,
+        (true, false) => Some(destination.to_owned()),
+        (false, true) => Some(title.to_owned()),
+        (false, false) => Some(format!("{title}\n{destination}")),
+    }
+}
+
 impl Link {
-    pub fn end(self, ui: &mut Ui, cache: &mut CommonMarkCache) {
-        let Self { destination, text } = self;
+    pub fn end(
+        self,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) -> egui::Response {
+        let Self {
+            destination,
+            title,
+            text,
+        } = self;
 
         let mut layout_job = LayoutJob::default();
         for t in text {
@@ -415,49 +1934,297 @@ impl Link {
         }
 
         // Apply underline and hyperlink color to all sections for better visibility
-        let link_color = ui.visuals().hyperlink_color;
+        let style = &options.link_style;
+        let link_color = match style.visited_color {
+            Some(visited_color) if cache.visited_links.contains(&destination) => visited_color,
+            _ => style.color.unwrap_or(ui.visuals().hyperlink_color),
+        };
+        let underline = egui::Stroke::new(1.0, link_color);
         for section in &mut layout_job.sections {
-            section.format.underline = egui::Stroke::new(1.0, link_color);
+            if style.underline == LinkUnderline::Always {
+                section.format.underline = underline;
+            }
             section.format.color = link_color;
             // Remove extra line height to bring underline closer to text
             section.format.line_height = None;
         }
+        let body = ui.text_style_height(&TextStyle::Body);
+        let word_spacing = options.typography.resolve_word_spacing(body);
+        crate::typography::space_words(&mut layout_job, word_spacing);
 
         // Use clickable label to preserve our custom underline styling
-        let response = ui.add(
-            egui::Label::new(layout_job)
-                .selectable(false)
-                .sense(egui::Sense::click()),
-        );
+        let selectable = options.continuous_selection;
+        let label = egui::Label::new(layout_job)
+            .selectable(selectable)
+            .sense(egui::Sense::click());
+        let response = if style.underline == LinkUnderline::OnHover {
+            // Painted here, as whether it is hovered is only known once laid out
+            let (pos, galley, response) = label.layout_in_ui(ui);
+            if ui.is_rect_visible(response.rect) {
+                let underline = if response.hovered() || response.has_focus() {
+                    underline
+                } else {
+                    egui::Stroke::NONE
+                };
+                if selectable {
+                    egui::text_selection::LabelSelectionState::label_text_selection(
+                        ui, &response, pos, galley, link_color, underline,
+                    );
+                } else {
+                    let text = egui::epaint::TextShape::new(pos, galley, link_color);
+                    ui.painter().add(text.with_underline(underline));
+                }
+            }
+            response
+        } else {
+            ui.add(label)
+        };
+
+        // Tab / Shift+Tab move the focus between links; Enter opens the
+        // focused one like a click
+        if response.gained_focus() {
+            response.scroll_to_me(None);
+        }
+        if response.has_focus() && ui.is_rect_visible(response.rect) {
+            ui.painter().rect_stroke(
+                response.rect.expand(2.0),
+                2.0,
+                ui.visuals().selection.stroke,
+                egui::StrokeKind::Outside,
+            );
+        }
 
         let is_hook = cache.link_hooks().contains_key(&destination);
 
         if response.clicked() || response.middle_clicked() {
+            cache.visited_links.insert(destination.clone());
             if is_hook {
                 cache.link_hooks_mut().insert(destination.clone(), true);
+                let button = if response.middle_clicked() {
+                    egui::PointerButton::Middle
+                } else {
+                    egui::PointerButton::Primary
+                };
+                let modifiers = ui.input(|i| i.modifiers);
+                cache
+                    .link_hook_clicks
+                    .insert(destination.clone(), LinkClick { button, modifiers });
+                if !title.is_empty() {
+                    cache
+                        .link_hook_titles
+                        .insert(destination.clone(), title.clone());
+                }
             } else {
-                ui.ctx().open_url(egui::OpenUrl::new_tab(&destination));
+                cache.open_external(ui.ctx(), options, &destination);
             }
         }
 
-        // Show pointer cursor and URL on hover
+        // Show pointer cursor and the title and/or URL on hover
         if response.hovered() {
             ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
-            if !is_hook {
-                response.on_hover_text(&destination);
+            if let Some(tooltip) = link_tooltip(&destination, &title, is_hook) {
+                return response.on_hover_text(tooltip);
+            }
+        }
+        response
+    }
+}
+
+/// Images only take clicks when the viewer opted in, so a plain embed keeps
+/// them inert (no pointer cursor, no recorded click).
+fn image_sense(options: &CommonMarkOptions) -> egui::Sense {
+    if options.clickable_images || options.image_context_menu {
+        egui::Sense::click()
+    } else {
+        egui::Sense::hover()
+    }
+}
+
+/// What the context menu of an image acts on.
+#[derive(Clone, Copy)]
+enum ImageMenuSource<'a> {
+    /// An image from the loaders
+    Uri(&'a str),
+    /// A rendered diagram
+    #[cfg(feature = "mermaid")]
+    Svg(&'a Arc<[u8]>),
+}
+
+/// "Copy image", "Copy image URL" and "Save image as…" on right click. Saving
+/// is left to the host, see [`CommonMarkCache::take_image_to_save`].
+fn image_context_menu(
+    response: &egui::Response,
+    cache: &mut CommonMarkCache,
+    options: &CommonMarkOptions,
+    source: ImageMenuSource,
+) {
+    response.context_menu(|ui| {
+        if ui.button("Copy image").clicked() {
+            let image = match source {
+                ImageMenuSource::Uri(uri) => {
+                    match ui.ctx().try_load_image(uri, egui::load::SizeHint::default()) {
+                        Ok(egui::load::ImagePoll::Ready { image }) => Some((*image).clone()),
+                        _ => None,
+                    }
+                }
+                #[cfg(feature = "mermaid")]
+                ImageMenuSource::Svg(svg) => {
+                    rasterize_mermaid_svg(svg, 1.0).map(|(image, _)| image)
+                }
+            };
+            if let Some(image) = image {
+                ui.ctx().copy_image(image);
+            }
+            ui.close();
+        }
+        let uri = match source {
+            ImageMenuSource::Uri(uri) => Some(uri),
+            #[cfg(feature = "mermaid")]
+            ImageMenuSource::Svg(_) => None,
+        };
+        if let Some(uri) = uri {
+            if ui.button("Copy image URL").clicked() {
+                ui.ctx().copy_text(uri.to_owned());
+                ui.close();
+            }
+            if options.dark_mode_images != DarkModeImages::Off && ui.visuals().dark_mode {
+                let mut unadjusted = cache.unadjusted_images.contains(uri);
+                if ui.checkbox(&mut unadjusted, "Show original colors").clicked() {
+                    cache.set_image_unadjusted(uri, unadjusted);
+                    ui.close();
+                }
             }
         }
+        if ui.button("Save image as…").clicked() {
+            cache.image_to_save = match source {
+                ImageMenuSource::Uri(uri) => match ui.ctx().try_load_bytes(uri) {
+                    Ok(egui::load::BytesPoll::Ready { bytes, .. }) => {
+                        Some((image_file_name(uri), bytes.to_vec()))
+                    }
+                    _ => None,
+                },
+                #[cfg(feature = "mermaid")]
+                ImageMenuSource::Svg(svg) => Some(("diagram.svg".to_owned(), svg.to_vec())),
+            };
+            ui.close();
+        }
+    });
+}
+
+/// Last path segment of `uri`, as the suggested name to save it under.
+fn image_file_name(uri: &str) -> String {
+    let path = uri.split(['?', '#']).next().unwrap_or(uri);
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() && !uri.starts_with("data:") => name.to_owned(),
+        _ => "image".to_owned(),
+    }
+}
+
+/// Viewport heights around the visible part of the document in which lazy
+/// images are loaded, and beyond which they are released again.
+const LAZY_IMAGE_LOAD_MARGIN: f32 = 1.0;
+const LAZY_IMAGE_KEEP_MARGIN: f32 = 3.0;
+
+/// Whether a lazy image is close enough to the viewport to be shown. Images
+/// further away get the space they took when last shown, or a guess, and are
+/// marked for release by `prepare_show` once beyond the keep margin.
+fn lazy_image_is_near(
+    ui: &mut Ui,
+    cache: &mut CommonMarkCache,
+    options: &CommonMarkOptions,
+    uri: &str,
+) -> bool {
+    let size = cache
+        .image_sizes
+        .get(uri)
+        .copied()
+        .unwrap_or_else(|| egui::vec2(options.max_width(ui), 200.0));
+    let estimate = egui::Rect::from_min_size(ui.cursor().min, size);
+    let clip = ui.clip_rect();
+    let around = |margin: f32| clip.expand2(egui::vec2(0.0, clip.height() * margin));
+
+    if around(LAZY_IMAGE_KEEP_MARGIN).intersects(estimate) {
+        let pass = ui.ctx().cumulative_pass_nr();
+        if let Some(last_near) = cache.loaded_images.get_mut(uri) {
+            *last_near = pass;
+        } else if around(LAZY_IMAGE_LOAD_MARGIN).intersects(estimate) {
+            cache.loaded_images.insert(uri.to_owned(), pass);
+        }
+    }
+    if cache.loaded_images.contains_key(uri) {
+        return true;
+    }
+    ui.allocate_exact_size(size, egui::Sense::hover());
+    false
+}
+
+/// Start loading the image at `uri` before it is shown, so it is decoded by
+/// the time it scrolls into view. A lazy image counts as near the viewport
+/// meanwhile, so it isn't released again.
+pub fn prefetch_image(
+    ctx: &egui::Context,
+    cache: &mut CommonMarkCache,
+    options: &CommonMarkOptions,
+    uri: &str,
+) {
+    let media = options.media_embeds && crate::media::MediaKind::from_uri(uri).is_some();
+    let blocked = options.sandbox.blocked_image(uri).is_some();
+    if media || blocked || cache.failed_images.contains_key(uri) {
+        return;
+    }
+    if options.lazy_images {
+        cache
+            .loaded_images
+            .insert(uri.to_owned(), ctx.cumulative_pass_nr());
+    }
+    if let Some(max_image_width) = options.max_image_width {
+        let pixels = max_image_width as f32 * ctx.pixels_per_point();
+        crate::texture_loader::limit_width(ctx, uri, Some(pixels.ceil() as u32));
+    }
+    dark_mode_tint(ctx, cache, options, uri);
+    // The size hint of an image fit to its original size doesn't depend on
+    // the space available, so this is the load `Image::end` makes later.
+    let _ = egui::Image::from_uri(uri)
+        .fit_to_original_size(1.0)
+        .load_for_size(ctx, egui::Vec2::ZERO);
+}
+
+/// The tint to draw the image at `uri` with in dark mode, after marking it
+/// for lightness inversion when `options` ask for that. `None` draws it as it
+/// is.
+fn dark_mode_tint(
+    ctx: &egui::Context,
+    cache: &CommonMarkCache,
+    options: &CommonMarkOptions,
+    uri: &str,
+) -> Option<egui::Color32> {
+    let mode = if ctx.style().visuals.dark_mode && !cache.unadjusted_images.contains(uri) {
+        options.dark_mode_images
+    } else {
+        DarkModeImages::Off
+    };
+    // SVGs are rasterized by egui's loader rather than the one inverting
+    let raster = !uri.ends_with(".svg");
+    crate::texture_loader::invert_lightness(ctx, uri, mode == DarkModeImages::Invert && raster);
+    match mode {
+        DarkModeImages::Off => None,
+        DarkModeImages::Invert if raster => None,
+        _ => Some(egui::Color32::from_gray(DARK_MODE_IMAGE_DIM)),
     }
 }
 
 pub struct Image {
     pub uri: String,
     pub alt_text: Vec<RichText>,
+    /// The optional title from `![alt](uri "title")`, empty if absent.
+    pub title: String,
 }
 
 impl Image {
     // FIXME: string conversion
     pub fn new(uri: &str, options: &CommonMarkOptions) -> Self {
+        let uri = options.rewrite_url(uri, true);
+        let uri = uri.as_str();
         let has_scheme = uri.contains("://") || uri.starts_with("data:");
         let uri = if options.use_explicit_uri_scheme || has_scheme {
             uri.to_string()
@@ -472,26 +2239,115 @@ impl Image {
         Self {
             uri,
             alt_text: Vec::new(),
+            title: String::new(),
         }
     }
 
-    pub fn end(self, ui: &mut Ui, cache: &mut CommonMarkCache, options: &CommonMarkOptions) {
-        let response = ui.add(
-            egui::Image::from_uri(&self.uri)
-                .fit_to_original_size(1.0)
-                .max_width(options.max_width(ui))
-                .sense(egui::Sense::click()),
-        );
+    /// Returns where the image was drawn, `None` when something else was
+    /// shown in its place.
+    pub fn end(
+        self,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+    ) -> Option<egui::Rect> {
+        crate::profile_scope!("image");
+        if options.media_embeds {
+            if let Some(kind) = crate::media::MediaKind::from_uri(&self.uri) {
+                let alt_text: String = self.alt_text.iter().map(|t| t.text()).collect();
+                let max_width = options.max_width(ui);
+                crate::elements::media_card(ui, cache, options, &self.uri, &kind, &alt_text, max_width);
+                return None;
+            }
+        }
 
-        if response.hovered() {
+        if let Some(reason) = options.sandbox.blocked_image(&self.uri) {
+            let alt_text: String = self.alt_text.iter().map(|t| t.text()).collect();
+            crate::elements::blocked_image_placeholder(ui, &self.uri, &alt_text, reason);
+            return None;
+        }
+
+        if options.lazy_images && !lazy_image_is_near(ui, cache, options, &self.uri) {
+            return None;
+        }
+
+        let mut max_width = options.max_width(ui);
+        if let Some(max_image_width) = options.max_image_width {
+            max_width = max_width.min(max_image_width as f32);
+            let pixels = max_image_width as f32 * ui.ctx().pixels_per_point();
+            crate::texture_loader::limit_width(ui.ctx(), &self.uri, Some(pixels.ceil() as u32));
+        }
+        let mut image = egui::Image::from_uri(&self.uri)
+            .fit_to_original_size(1.0)
+            .max_width(max_width)
+            .sense(image_sense(options));
+        if let Some(tint) = dark_mode_tint(ui.ctx(), cache, options, &self.uri) {
+            image = image.tint(tint);
+        }
+
+        // Same load the widget does, so a failure is known before egui paints
+        // its own error indicator.
+        let load = image.load_for_size(ui.ctx(), ui.available_size());
+        #[cfg(feature = "remote_images")]
+        if let Ok(egui::load::TexturePoll::Pending { .. }) = load {
+            if let Some(progress) = crate::http_loader::download_progress(ui.ctx(), &self.uri) {
+                crate::elements::image_download_placeholder(ui, options.max_width(ui), progress);
+                return None;
+            }
+        }
+        if let Err(err) = load {
+            let error = err.to_string();
+            let alt_text: String = self.alt_text.iter().map(|t| t.text()).collect();
+            if let Some(image_error_fn) = options.image_error_fn {
+                image_error_fn(ui, &self.uri, &alt_text, &error);
+            } else {
+                crate::elements::image_error_placeholder(ui, &self.uri, &alt_text, &error);
+            }
+            cache.failed_images.insert(self.uri, error);
+            return None;
+        }
+        if !cache.failed_images.is_empty() {
+            cache.failed_images.remove(&self.uri);
+        }
+
+        let caption = (options.image_captions != ImageCaptions::Off && !self.title.is_empty())
+            .then_some(self.title.as_str());
+        let response = match caption {
+            Some(caption) => {
+                let width = image
+                    .load_and_calc_size(ui, ui.available_size())
+                    .map_or(ui.available_width(), |size| size.x);
+                let layout = egui::Layout::top_down(egui::Align::Center);
+                ui.allocate_ui_with_layout(egui::vec2(width, 0.0), layout, |ui| {
+                    let response = ui.add(image);
+                    crate::elements::image_caption(ui, egui::RichText::new(caption));
+                    response
+                })
+                .inner
+            }
+            None => ui.add(image),
+        };
+        let rect = response.rect;
+        if options.lazy_images {
+            cache.image_sizes.insert(self.uri.clone(), rect.size());
+        }
+
+        if options.clickable_images && response.hovered() {
             ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
         }
 
-        if response.clicked() {
+        if options.image_context_menu {
+            image_context_menu(&response, cache, options, ImageMenuSource::Uri(&self.uri));
+        }
+
+        if options.clickable_images && response.clicked() {
+            // Vector images are rasterized at twice the display density for
+            // a crisp lightbox zoom; the texture's size stays the one in points.
+            let scale = 2.0 * ui.ctx().pixels_per_point();
             let load = ui.ctx().try_load_texture(
                 &self.uri,
                 egui::TextureOptions::default(),
-                egui::load::SizeHint::default(),
+                egui::load::SizeHint::Scale(scale.into()),
             );
             if let Ok(egui::load::TexturePoll::Ready { texture }) = load {
                 cache.clicked_image = Some((texture.id, texture.size));
@@ -505,15 +2361,63 @@ impl Image {
                 }
             });
         }
+        Some(rect)
     }
 }
 
 pub struct CodeBlock {
+    /// First word of the info string of a fenced block, `None` for indented
+    /// blocks
     pub lang: Option<String>,
     pub content: String,
+    pub info: FenceInfo,
+    /// Where pieces of `content` are in the document source: their offset in
+    /// `content` and their source bytes, for the search matches in them
+    pub source: Vec<(usize, std::ops::Range<usize>)>,
 }
 
 impl CodeBlock {
+    /// An empty block for the content to be collected into. `info` is the
+    /// info string of a fenced block, `None` for an indented one.
+    pub fn new(info: Option<&str>) -> Self {
+        let info = info.map(FenceInfo::parse);
+        Self {
+            lang: info.as_ref().map(|info| info.lang.clone()),
+            content: String::new(),
+            info: info.unwrap_or_default(),
+            source: Vec::new(),
+        }
+    }
+
+    /// Add `text`, read from the `source` bytes of the document, to the
+    /// content.
+    pub fn push_text(&mut self, text: &str, source: std::ops::Range<usize>) {
+        // Text that isn't a plain copy of its source can't be mapped back
+        if text.len() == source.len() {
+            self.source.push((self.content.len(), source));
+        }
+        self.content.push_str(text);
+    }
+
+    /// The search matches of `cache` inside the block, as ranges of its
+    /// content.
+    fn search_matches(&self, cache: &CommonMarkCache) -> crate::elements::CodeMatches {
+        let all = cache.search_ranges();
+        let active = cache.active_search_range();
+        let mut matches = crate::elements::CodeMatches::default();
+        for (offset, source) in &self.source {
+            let first = all.partition_point(|range| range.start < source.start);
+            for range in all[first..].iter().take_while(|range| range.end <= source.end) {
+                if active == Some(range) {
+                    matches.active = Some(matches.ranges.len());
+                }
+                let start = offset + range.start - source.start;
+                matches.ranges.push(start..start + range.len());
+            }
+        }
+        matches
+    }
+
     pub fn end(
         &self,
         ui: &mut Ui,
@@ -528,6 +2432,12 @@ impl CodeBlock {
             return;
         }
 
+        #[cfg(feature = "graphviz")]
+        if self.lang.as_deref().is_some_and(is_graphviz_fence) {
+            self.render_graphviz(ui, cache, options, max_width);
+            return;
+        }
+
         ui.scope(|ui| {
             Self::pre_syntax_highlighting(cache, options, ui);
 
@@ -544,20 +2454,34 @@ impl CodeBlock {
                 self.content.hash(&mut h);
                 self.lang.hash(&mut h);
                 ui.style().visuals.dark_mode.hash(&mut h);
+                // The active theme name, so switching syntax themes at runtime
+                // re-highlights instead of hitting the previous theme's job.
+                #[cfg(feature = "better_syntax_highlighting")]
+                options.curr_theme(ui).hash(&mut h);
                 mono_font_size.to_bits().hash(&mut h);
                 code_line_height.map(f32::to_bits).hash(&mut h);
                 h.finish()
             };
 
+            #[cfg(feature = "better_syntax_highlighting")]
+            cache.receive_highlighted();
+
             // Build (or retrieve) the LayoutJob for syntax highlighting.
             // Cache hit: clone the stored LayoutJob (cheap — egui's LayoutJob
             // is just text + Vec<LayoutSection>). Cache miss: run syntect,
             // store, and return. With viewport virtualization (C8), only the
-            // visible code blocks ever pay the syntect cost.
+            // visible code blocks ever pay the syntect cost. Large blocks are
+            // highlighted on a worker thread and shown as plain text meanwhile.
             let mut job = if let Some(cached) = cache.syntax_layouts.get(&cache_key).cloned() {
                 cached
+            } else if let Some(placeholder) =
+                self.highlight_in_background(cache, options, ui, cache_key, code_line_height)
+            {
+                placeholder
             } else {
-                let job = if let Some(lang) = &self.lang {
+                let job = if self.lang.as_deref().is_some_and(is_diff_fence) {
+                    diff_highlighting(ui, &self.content, code_line_height)
+                } else if let Some(lang) = &self.lang {
                     self.syntax_highlighting(
                         cache,
                         options,
@@ -576,7 +2500,36 @@ impl CodeBlock {
             // Don't wrap code block text - use horizontal scroll instead
             job.wrap.max_width = f32::INFINITY;
 
-            crate::elements::code_block(ui, &self.content, job, max_width, id);
+            let commands = (options.copy_without_prompts
+                && self.lang.as_deref().is_some_and(is_shell_fence))
+            .then(|| shell_session_commands(&self.content))
+            .flatten();
+            let copy = crate::elements::CodeCopy {
+                commands: commands.as_deref(),
+                lang: self.lang.as_deref(),
+                default_format: options.code_copy_format,
+            };
+            let matches = self.search_matches(cache);
+            let active_y = crate::elements::code_block(
+                ui,
+                &self.content,
+                &copy,
+                job,
+                max_width,
+                id,
+                options.code_line_numbers,
+                // A title always gets a header to sit in
+                self.info.title.as_deref().or_else(|| {
+                    options
+                        .code_block_header
+                        .then(|| self.lang.as_deref().unwrap_or(""))
+                }),
+                &self.info.highlight_lines,
+                &matches,
+            );
+            if let Some(y) = active_y {
+                cache.record_active_search_y_viewport(y);
+            }
         });
     }
 }
@@ -585,15 +2538,50 @@ impl CodeBlock {
 enum MermaidState {
     /// Background thread is rendering this diagram
     Rendering,
-    /// Rendered and ready to display (texture is 2x for crisp lightbox zoom)
+    /// Rendered and ready to display (texture is 2x the display density for
+    /// crisp lightbox zoom)
     Ready {
         texture: egui::TextureHandle,
         size: egui::Vec2,
+        /// Kept to rasterize again when `pixels_per_point` changes
+        svg: Arc<[u8]>,
+        pixels_per_point: f32,
     },
     /// Rendering failed
     Error(String),
 }
 
+/// The parts of the egui visuals a mermaid diagram is themed after.
+#[cfg(feature = "mermaid")]
+#[derive(Clone, Copy, Hash)]
+struct MermaidTheme {
+    dark: bool,
+    background: egui::Color32,
+}
+
+#[cfg(feature = "mermaid")]
+impl MermaidTheme {
+    fn from_visuals(visuals: &egui::Visuals) -> Self {
+        Self {
+            dark: visuals.dark_mode,
+            background: visuals.panel_fill,
+        }
+    }
+
+    /// Site config selecting mermaid's matching built-in theme. An `init`
+    /// directive in the diagram still takes precedence.
+    fn site_config(self) -> merman::MermaidConfig {
+        let [r, g, b, _] = self.background.to_array();
+        merman::MermaidConfig::from_value(serde_json::json!({
+            "theme": if self.dark { "dark" } else { "default" },
+            "themeVariables": {
+                "darkMode": self.dark,
+                "background": format!("#{r:02x}{g:02x}{b:02x}"),
+            },
+        }))
+    }
+}
+
 #[cfg(feature = "mermaid")]
 struct MermaidRenderResult {
     hash: u64,
@@ -604,6 +2592,23 @@ struct MermaidRenderResult {
 struct MermaidRendered {
     image: egui::ColorImage,
     size: egui::Vec2,
+    svg: Arc<[u8]>,
+    pixels_per_point: f32,
+}
+
+#[cfg(feature = "mermaid")]
+impl MermaidRendered {
+    fn rasterize(svg: Arc<[u8]>, pixels_per_point: f32) -> Result<Self, String> {
+        match rasterize_mermaid_svg(&svg, pixels_per_point) {
+            Some((image, size)) => Ok(Self {
+                image,
+                size,
+                svg,
+                pixels_per_point,
+            }),
+            None => Err("Failed to rasterize SVG".to_string()),
+        }
+    }
 }
 
 #[cfg(feature = "mermaid")]
@@ -613,6 +2618,17 @@ static MERMAID_FONTDB: LazyLock<Arc<resvg::usvg::fontdb::Database>> = LazyLock::
     Arc::new(db)
 });
 
+/// Default number of diagrams to render concurrently. Layout and
+/// rasterization are CPU-bound, so a few threads are enough to keep a
+/// diagram-heavy document moving without starving the rest of the machine.
+#[cfg(feature = "mermaid")]
+fn default_mermaid_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get() / 2)
+        .unwrap_or(1)
+        .clamp(1, 4)
+}
+
 #[cfg(feature = "mermaid")]
 fn fix_double_escaped_xml_entities(svg: &str) -> String {
     // merman's escape_xml_text double-escapes text that mermaid has already
@@ -627,7 +2643,10 @@ fn fix_double_escaped_xml_entities(svg: &str) -> String {
 }
 
 #[cfg(feature = "mermaid")]
-fn rasterize_mermaid_svg(svg_bytes: &[u8]) -> Option<(egui::ColorImage, egui::Vec2)> {
+fn rasterize_mermaid_svg(
+    svg_bytes: &[u8],
+    pixels_per_point: f32,
+) -> Option<(egui::ColorImage, egui::Vec2)> {
     let opts = resvg::usvg::Options {
         fontdb: Arc::clone(&MERMAID_FONTDB),
         ..Default::default()
@@ -635,8 +2654,8 @@ fn rasterize_mermaid_svg(svg_bytes: &[u8]) -> Option<(egui::ColorImage, egui::Ve
     let tree = resvg::usvg::Tree::from_data(svg_bytes, &opts).ok()?;
     let svg_size = tree.size();
 
-    // Rasterize at 2x for crisp lightbox zoom (matches LightboxState::new)
-    let scale = 2.0_f32;
+    // Rasterize at 2x the display density for crisp lightbox zoom
+    let scale = 2.0 * pixels_per_point;
     let w = (svg_size.width() * scale) as u32;
     let h = (svg_size.height() * scale) as u32;
     if w == 0 || h == 0 {
@@ -681,16 +2700,41 @@ impl CodeBlock {
         options: &CommonMarkOptions,
         max_width: f32,
     ) {
+        // Diagrams are drawn in the theme of the ui, so a theme flip renders
+        // them again under a new hash.
+        let theme = MermaidTheme::from_visuals(ui.visuals());
         let mut hasher = DefaultHasher::new();
         self.content.hash(&mut hasher);
+        theme.hash(&mut hasher);
         let hash = hasher.finish();
 
+        self.render_diagram(ui, cache, options, max_width, hash, "Mermaid", |cache, ppp| {
+            Self::spawn_mermaid_render(hash, &self.content, theme, ppp, cache);
+        });
+    }
+
+    /// Show the diagram rendered under `hash`, calling `spawn` to start its
+    /// background render at the given `pixels_per_point` once a thread slot
+    /// is free. Shared by all diagram
+    /// languages, so they take turns on the same threads and open in the
+    /// same lightbox.
+    #[allow(clippy::too_many_arguments)]
+    fn render_diagram(
+        &self,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+        hash: u64,
+        kind: &str,
+        spawn: impl FnOnce(&mut CommonMarkCache, f32),
+    ) {
+        crate::profile_scope!("diagram", kind);
+        let pixels_per_point = ui.ctx().pixels_per_point();
+
         // Poll for completed background renders
         while let Ok(result) = cache.mermaid_rx.try_recv() {
-            // Clear the rendering slot if this result is from the active thread
-            if cache.mermaid_rendering == Some(result.hash) {
-                cache.mermaid_rendering = None;
-            }
+            cache.mermaid_rendering.remove(&result.hash);
             match result.result {
                 Ok(rendered) => {
                     let texture = ui.ctx().load_texture(
@@ -703,6 +2747,8 @@ impl CodeBlock {
                         MermaidState::Ready {
                             texture,
                             size: rendered.size,
+                            svg: rendered.svg,
+                            pixels_per_point: rendered.pixels_per_point,
                         },
                     );
                 }
@@ -712,26 +2758,46 @@ impl CodeBlock {
             }
         }
 
-        // First encounter: insert as Rendering placeholder, spawn only if slot is free
+        // First encounter: insert as Rendering placeholder
         if !cache.mermaid_states.contains_key(&hash) {
             cache.mermaid_states.insert(hash, MermaidState::Rendering);
-
-            if cache.mermaid_rendering.is_none() {
-                Self::spawn_mermaid_render(hash, &self.content, cache);
-            }
         }
 
-        // Promote: if this diagram is waiting and no thread is active, spawn now.
-        // Since egui processes blocks in document order, the first Rendering block
-        // encountered after the slot clears is always the topmost waiting one.
+        // Spawn if this diagram is waiting, isn't already being rendered, and a
+        // thread slot is free. Since egui processes blocks in document order,
+        // the topmost waiting diagrams take the free slots first.
         if matches!(cache.mermaid_states.get(&hash), Some(MermaidState::Rendering))
-            && cache.mermaid_rendering.is_none()
+            && !cache.mermaid_rendering.contains(&hash)
+            && cache.mermaid_rendering.len() < cache.mermaid_threads
         {
-            Self::spawn_mermaid_render(hash, &self.content, cache);
+            spawn(cache, pixels_per_point);
+        }
+
+        // The display density changed (ui scale, another monitor): rasterize
+        // the svg again, showing the old texture until the new one is ready.
+        if let Some(MermaidState::Ready {
+            svg,
+            pixels_per_point: rendered_ppp,
+            ..
+        }) = cache.mermaid_states.get(&hash)
+        {
+            if *rendered_ppp != pixels_per_point
+                && !cache.mermaid_rendering.contains(&hash)
+                && cache.mermaid_rendering.len() < cache.mermaid_threads
+            {
+                let svg = svg.clone();
+                cache.mermaid_rendering.insert(hash);
+                let tx = cache.mermaid_tx.clone();
+                std::thread::spawn(move || {
+                    let result = MermaidRendered::rasterize(svg, pixels_per_point);
+                    let _ = tx.send(MermaidRenderResult { hash, result });
+                });
+            }
         }
 
         // Display based on current state
         let mut clicked: Option<(egui::TextureHandle, egui::Vec2)> = None;
+        let mut menu = None;
 
         match cache.mermaid_states.get(&hash) {
             Some(MermaidState::Rendering) => {
@@ -753,25 +2819,30 @@ impl CodeBlock {
                 ui.ctx()
                     .request_repaint_after(std::time::Duration::from_millis(100));
             }
-            Some(MermaidState::Ready { texture, size }) => {
+            Some(MermaidState::Ready {
+                texture, size, svg, ..
+            }) => {
                 let sized_texture = egui::load::SizedTexture::new(texture.id(), *size);
                 let response = ui.add(
                     egui::Image::new(egui::ImageSource::Texture(sized_texture))
                         .fit_to_original_size(1.0)
                         .max_width(options.max_width(ui).min(max_width))
-                        .sense(egui::Sense::click()),
+                        .sense(image_sense(options)),
                 );
-                if response.hovered() {
+                if options.clickable_images && response.hovered() {
                     ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
                 }
-                if response.clicked() {
+                if options.clickable_images && response.clicked() {
                     clicked = Some((texture.clone(), *size));
                 }
+                if options.image_context_menu {
+                    menu = Some((response, svg.clone()));
+                }
             }
             Some(MermaidState::Error(err_msg)) => {
                 ui.colored_label(
                     ui.visuals().error_fg_color,
-                    format!("Mermaid render error: {err_msg}"),
+                    format!("{kind} render error: {err_msg}"),
                 );
             }
             None => unreachable!(),
@@ -780,28 +2851,36 @@ impl CodeBlock {
         if let Some(data) = clicked {
             cache.clicked_mermaid = Some(data);
         }
+        if let Some((response, svg)) = menu {
+            image_context_menu(&response, cache, options, ImageMenuSource::Svg(&svg));
+        }
     }
 
     /// Spawn a background thread to render a mermaid diagram and mark it as active.
-    fn spawn_mermaid_render(hash: u64, content: &str, cache: &mut CommonMarkCache) {
-        cache.mermaid_rendering = Some(hash);
+    fn spawn_mermaid_render(
+        hash: u64,
+        content: &str,
+        theme: MermaidTheme,
+        pixels_per_point: f32,
+        cache: &mut CommonMarkCache,
+    ) {
+        cache.mermaid_rendering.insert(hash);
         let content = content.to_owned();
         let tx = cache.mermaid_tx.clone();
-        let renderer = cache.mermaid_renderer.clone();
+        let renderer = cache
+            .mermaid_renderer
+            .clone()
+            .with_site_config(theme.site_config());
 
         std::thread::spawn(move || {
+            crate::profile_scope!("render mermaid diagram");
             let result = match renderer.render_svg_readable_sync(&content) {
                 Ok(Some(svg_string)) => {
                     let svg_string = fix_double_escaped_xml_entities(&svg_string);
                     let svg_string = CodeBlock::sanitize_svg_font_family(&svg_string);
                     let svg_string = CodeBlock::strip_stroke_text(&svg_string);
                     let svg_string = CodeBlock::wrap_fallback_text(&svg_string);
-                    let svg_bytes = svg_string.into_bytes();
-
-                    match rasterize_mermaid_svg(&svg_bytes) {
-                        Some((image, size)) => Ok(MermaidRendered { image, size }),
-                        None => Err("Failed to rasterize SVG".to_string()),
-                    }
+                    MermaidRendered::rasterize(svg_string.into_bytes().into(), pixels_per_point)
                 }
                 Ok(None) => Err("Unknown diagram type".to_string()),
                 Err(e) => Err(e.to_string()),
@@ -810,6 +2889,48 @@ impl CodeBlock {
         });
     }
 
+    /// Spawn a background thread to lay out and rasterize a graphviz diagram.
+    #[cfg(feature = "graphviz")]
+    fn spawn_graphviz_render(
+        hash: u64,
+        content: &str,
+        pixels_per_point: f32,
+        cache: &mut CommonMarkCache,
+    ) {
+        cache.mermaid_rendering.insert(hash);
+        let content = content.to_owned();
+        let tx = cache.mermaid_tx.clone();
+
+        std::thread::spawn(move || {
+            crate::profile_scope!("render graphviz diagram");
+            let result = graphviz_svg(&content).and_then(|svg| {
+                let svg = CodeBlock::sanitize_svg_font_family(&svg);
+                MermaidRendered::rasterize(svg.into_bytes().into(), pixels_per_point)
+            });
+            let _ = tx.send(MermaidRenderResult { hash, result });
+        });
+    }
+
+    #[cfg(feature = "graphviz")]
+    fn render_graphviz(
+        &self,
+        ui: &mut Ui,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        max_width: f32,
+    ) {
+        // Salted so a dot block never shares a hash with an identical
+        // mermaid block.
+        let mut hasher = DefaultHasher::new();
+        "graphviz".hash(&mut hasher);
+        self.content.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        self.render_diagram(ui, cache, options, max_width, hash, "Graphviz", |cache, ppp| {
+            Self::spawn_graphviz_render(hash, &self.content, ppp, cache);
+        });
+    }
+
     /// Sanitize SVG font-family values for resvg compatibility.
     /// Handles both XML attributes (`font-family="..."`) and CSS properties
     /// (`font-family: ...;`) since merman outputs fonts in CSS format.
@@ -1105,6 +3226,8 @@ enum MathState {
         /// Baseline position as a fraction of image height from the top
         /// (0 = top, 1 = bottom). Used to align inline math to the text baseline.
         baseline_ratio: f32,
+        /// Display density the texture was rendered for
+        pixels_per_point: f32,
     },
     /// Rendering failed - show styled fallback
     Error(String),
@@ -1122,6 +3245,7 @@ struct MathRendered {
     size: egui::Vec2,
     /// Baseline position as a fraction of image height from the top.
     baseline_ratio: f32,
+    pixels_per_point: f32,
 }
 
 /// Typst preamble defining mitex helper functions needed to compile mitex output.
@@ -1286,6 +3410,7 @@ fn render_math_formula(
     is_inline: bool,
     fg: egui::Color32,
     bg: egui::Color32,
+    pixels_per_point: f32,
 ) -> Result<MathRendered, String> {
     // 0. Decode common HTML entities that OCR/conversion tools may leave in math
     let latex = latex
@@ -1345,8 +3470,9 @@ fn render_math_formula(
 
     let baseline_ratio = math_baseline_ratio(page);
 
-    // 4. Render directly to pixels via typst-render (no SVG intermediary)
-    let pixel_per_pt = 3.0_f32;
+    // 4. Render directly to pixels via typst-render (no SVG intermediary), one
+    // texel per physical pixel so the formula is as crisp as the text around it
+    let pixel_per_pt = pixels_per_point;
     let pixmap = typst_render::render(page, pixel_per_pt);
 
     let w = pixmap.width() as usize;
@@ -1393,6 +3519,7 @@ fn render_math_formula(
         image,
         size,
         baseline_ratio,
+        pixels_per_point,
     })
 }
 
@@ -1434,6 +3561,7 @@ pub fn render_math(
                         texture,
                         size: rendered.size,
                         baseline_ratio: rendered.baseline_ratio,
+                        pixels_per_point: rendered.pixels_per_point,
                     },
                 );
             }
@@ -1454,13 +3582,24 @@ pub fn render_math(
         cache.math_states.insert(hash, MathState::Rendering);
     }
 
-    // Spawn if this formula is still waiting, isn't already being rendered, and
-    // a concurrency slot is free. Multiple formulas render in parallel.
-    if matches!(cache.math_states.get(&hash), Some(MathState::Rendering))
+    // Spawn if this formula is still waiting, or was rendered for another
+    // display density (the old texture stays up until the new one is ready),
+    // isn't already being rendered, and a concurrency slot is free. Multiple
+    // formulas render in parallel.
+    let pixels_per_point = ui.ctx().pixels_per_point();
+    let needs_render = match cache.math_states.get(&hash) {
+        Some(MathState::Rendering) => true,
+        Some(MathState::Ready {
+            pixels_per_point: rendered_ppp,
+            ..
+        }) => *rendered_ppp != pixels_per_point,
+        _ => false,
+    };
+    if needs_render
         && !cache.math_rendering.contains(&hash)
         && cache.math_rendering.len() < math_concurrency()
     {
-        spawn_math_render(hash, latex, is_inline, fg, bg, cache);
+        spawn_math_render(hash, latex, is_inline, fg, bg, pixels_per_point, cache);
     }
 
     // Display based on current state
@@ -1493,6 +3632,7 @@ pub fn render_math(
             texture,
             size,
             baseline_ratio,
+            ..
         }) => {
             let sized_texture = egui::load::SizedTexture::new(texture.id(), *size);
             if is_inline {
@@ -1577,6 +3717,7 @@ fn spawn_math_render(
     is_inline: bool,
     fg: egui::Color32,
     bg: egui::Color32,
+    pixels_per_point: f32,
     cache: &mut CommonMarkCache,
 ) {
     cache.math_rendering.insert(hash);
@@ -1584,7 +3725,8 @@ fn spawn_math_render(
     let tx = cache.math_tx.clone();
 
     std::thread::spawn(move || {
-        let result = render_math_formula(&latex, is_inline, fg, bg);
+        crate::profile_scope!("render math formula");
+        let result = render_math_formula(&latex, is_inline, fg, bg, pixels_per_point);
         let _ = tx.send(MathRenderResult { hash, result });
     });
 }
@@ -1599,6 +3741,17 @@ impl CodeBlock {
         ui.style_mut().visuals.extreme_bg_color = ui.visuals().extreme_bg_color;
     }
 
+    fn highlight_in_background(
+        &self,
+        _cache: &mut CommonMarkCache,
+        _options: &CommonMarkOptions,
+        _ui: &Ui,
+        _cache_key: u64,
+        _code_line_height: Option<f32>,
+    ) -> Option<egui::text::LayoutJob> {
+        None
+    }
+
     fn syntax_highlighting(
         &self,
         _cache: &mut CommonMarkCache,
@@ -1642,31 +3795,130 @@ impl CodeBlock {
         text: &str,
         code_line_height: Option<f32>,
     ) -> egui::text::LayoutJob {
-        if let Some(syntax) = cache.ps.find_syntax_by_extension(extension) {
-            let mut job = egui::text::LayoutJob::default();
-            let mut h = HighlightLines::new(syntax, cache.curr_theme(ui, options));
-
-            for line in LinesWithEndings::from(text) {
-                let ranges = h.highlight_line(line, &cache.ps).unwrap();
-                for v in ranges {
-                    let front = v.0.foreground;
-                    let mut format = egui::TextFormat::simple(
-                        TextStyle::Monospace.resolve(ui.style()),
-                        syntect_color_to_egui(front),
-                    );
-                    // Apply code line height if configured
-                    if let Some(line_height) = code_line_height {
-                        format.line_height = Some(line_height);
-                    }
-                    job.append(v.1, 0.0, format);
-                }
-            }
+        syntect_highlighting(
+            cache.syntax_set(),
+            cache.curr_theme(ui, options),
+            TextStyle::Monospace.resolve(ui.style()),
+            extension,
+            text,
+            code_line_height,
+        )
+        .unwrap_or_else(|| simple_highlighting(ui, text, extension, code_line_height))
+    }
+
+    /// For a block too large to highlight within a frame, start highlighting
+    /// it on a worker thread (unless one already is) and return it as plain
+    /// text to show until the result lands in `syntax_layouts`. At most
+    /// `BACKGROUND_HIGHLIGHT_THREADS` blocks are highlighted at once; the
+    /// others wait for a free thread. A block its worker failed on stays
+    /// plain.
+    fn highlight_in_background(
+        &self,
+        cache: &mut CommonMarkCache,
+        options: &CommonMarkOptions,
+        ui: &Ui,
+        cache_key: u64,
+        code_line_height: Option<f32>,
+    ) -> Option<egui::text::LayoutJob> {
+        let lang = self.lang.as_deref()?;
+        if self.content.len() < BACKGROUND_HIGHLIGHT_MIN_BYTES
+            || is_diff_fence(lang)
+            || cache.syntax_set().find_syntax_by_extension(lang).is_none()
+        {
+            return None;
+        }
+
+        let plain = plain_highlighting(ui, &self.content, code_line_height);
+        if cache.highlight_failed.contains(&cache_key) {
+            return Some(plain);
+        }
+        if !cache.highlighting.contains(&cache_key)
+            && cache.highlighting.len() < BACKGROUND_HIGHLIGHT_THREADS
+        {
+            cache.highlighting.insert(cache_key);
+            let ps = Arc::clone(cache.syntax_set());
+            let theme = cache.curr_theme(ui, options).clone();
+            let font_id = TextStyle::Monospace.resolve(ui.style());
+            let lang = lang.to_owned();
+            let content = self.content.clone();
+            let done = HighlightDone {
+                key: cache_key,
+                job: None,
+                tx: cache.highlight_tx.clone(),
+            };
+            std::thread::spawn(move || {
+                let mut done = done;
+                done.job = syntect_highlighting(
+                    &ps,
+                    &theme,
+                    font_id,
+                    &lang,
+                    &content,
+                    code_line_height,
+                );
+            });
+        }
+        ui.ctx()
+            .request_repaint_after(std::time::Duration::from_millis(100));
+        Some(plain)
+    }
+}
+
+/// Code blocks at least this long are highlighted off the UI thread.
+#[cfg(feature = "better_syntax_highlighting")]
+const BACKGROUND_HIGHLIGHT_MIN_BYTES: usize = 16 * 1024;
 
-            job
-        } else {
-            simple_highlighting(ui, text, extension, code_line_height)
+/// Code blocks highlighted on worker threads at once.
+#[cfg(feature = "better_syntax_highlighting")]
+const BACKGROUND_HIGHLIGHT_THREADS: usize = 2;
+
+/// Sends what a highlighting worker made of its block when the worker ends,
+/// `None` when it failed or panicked, so the block doesn't wait forever.
+#[cfg(feature = "better_syntax_highlighting")]
+struct HighlightDone {
+    key: u64,
+    job: Option<LayoutJob>,
+    tx: mpsc::Sender<(u64, Option<LayoutJob>)>,
+}
+
+#[cfg(feature = "better_syntax_highlighting")]
+impl Drop for HighlightDone {
+    fn drop(&mut self) {
+        let _ = self.tx.send((self.key, self.job.take()));
+    }
+}
+
+/// Highlight `text` with syntect, or `None` if it has no syntax for
+/// `extension`. Doesn't touch the `Ui` so it can run on a worker thread.
+#[cfg(feature = "better_syntax_highlighting")]
+fn syntect_highlighting(
+    ps: &SyntaxSet,
+    theme: &Theme,
+    font_id: egui::FontId,
+    extension: &str,
+    text: &str,
+    code_line_height: Option<f32>,
+) -> Option<egui::text::LayoutJob> {
+    crate::profile_scope!("highlight code block", extension);
+    let syntax = ps.find_syntax_by_extension(extension)?;
+    let mut job = egui::text::LayoutJob::default();
+    let mut h = HighlightLines::new(syntax, theme);
+
+    for line in LinesWithEndings::from(text) {
+        let ranges = h.highlight_line(line, ps).unwrap();
+        for v in ranges {
+            let front = v.0.foreground;
+            let mut format =
+                egui::TextFormat::simple(font_id.clone(), syntect_color_to_egui(front));
+            // Apply code line height if configured
+            if let Some(line_height) = code_line_height {
+                format.line_height = Some(line_height);
+            }
+            job.append(v.1, 0.0, format);
         }
     }
+
+    Some(job)
 }
 
 fn simple_highlighting(ui: &Ui, text: &str, extension: &str, code_line_height: Option<f32>) -> egui::text::LayoutJob {
@@ -1686,6 +3938,105 @@ fn simple_highlighting(ui: &Ui, text: &str, extension: &str, code_line_height: O
     job
 }
 
+fn is_diff_fence(lang: &str) -> bool {
+    matches!(lang, "diff" | "patch")
+}
+
+fn is_shell_fence(lang: &str) -> bool {
+    matches!(
+        lang,
+        "console" | "shell-session" | "sh-session" | "bash" | "sh" | "shell" | "zsh"
+    )
+}
+
+/// The commands typed in a shell session: the lines after a `$ ` prompt, and
+/// the lines a command ending in `\` continues on, leaving out its output.
+/// `None` when no line starts with a prompt.
+fn shell_session_commands(text: &str) -> Option<String> {
+    let mut commands = Vec::new();
+    let mut continued = false;
+    for line in text.lines() {
+        let command = if continued {
+            Some(line)
+        } else {
+            line.trim_start().strip_prefix("$ ")
+        };
+        continued = command.is_some_and(|command| command.trim_end().ends_with('\\'));
+        commands.extend(command);
+    }
+    (!commands.is_empty()).then(|| commands.join("\n"))
+}
+
+#[cfg(feature = "graphviz")]
+fn is_graphviz_fence(lang: &str) -> bool {
+    matches!(lang, "dot" | "graphviz")
+}
+
+/// Lay out a graphviz `dot` graph and draw it as SVG on a white background,
+/// like graphviz does by default.
+#[cfg(feature = "graphviz")]
+fn graphviz_svg(dot: &str) -> Result<String, String> {
+    let graph = layout::gv::DotParser::new(dot).process()?;
+    // The layout engine asserts on graphs it can't handle rather than
+    // returning an error.
+    std::panic::catch_unwind(|| {
+        let mut builder = layout::gv::GraphBuilder::new();
+        builder.visit_graph(&graph);
+        let mut visual = builder.get();
+        if visual.num_nodes() == 0 {
+            return Err("Empty graph".to_string());
+        }
+        let mut svg = layout::backends::svg::SVGWriter::new();
+        visual.do_it(false, false, false, &mut svg);
+        Ok(svg.finalize().replacen(
+            "<defs>",
+            "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n<defs>",
+            1,
+        ))
+    })
+    .unwrap_or_else(|_| Err("Graph layout failed".to_string()))
+}
+
+/// Color a unified diff line by line: added lines on a green background,
+/// removed lines on red and `@@` hunk headers in the hyperlink color. Uses
+/// fixed colors rather than the syntax theme so changes always stand out.
+fn diff_highlighting(ui: &Ui, text: &str, code_line_height: Option<f32>) -> egui::text::LayoutJob {
+    let dark = ui.visuals().dark_mode;
+    let (added, removed) = if dark {
+        (
+            egui::Color32::from_rgb(0x1f, 0x4a, 0x2c),
+            egui::Color32::from_rgb(0x5a, 0x22, 0x26),
+        )
+    } else {
+        (
+            egui::Color32::from_rgb(0xd7, 0xf5, 0xdd),
+            egui::Color32::from_rgb(0xff, 0xdc, 0xe0),
+        )
+    };
+
+    let mut job = egui::text::LayoutJob::default();
+    for line in text.split_inclusive('\n') {
+        let mut format = egui::TextFormat::simple(
+            TextStyle::Monospace.resolve(ui.style()),
+            ui.visuals().text_color(),
+        );
+        if let Some(line_height) = code_line_height {
+            format.line_height = Some(line_height);
+        }
+        if line.starts_with("@@") {
+            format.color = ui.visuals().hyperlink_color;
+        } else if line.starts_with("+++") || line.starts_with("---") {
+            format.color = ui.visuals().strong_text_color();
+        } else if line.starts_with('+') {
+            format.background = added;
+        } else if line.starts_with('-') {
+            format.background = removed;
+        }
+        job.append(line, 0.0, format);
+    }
+    job
+}
+
 fn plain_highlighting(ui: &Ui, text: &str, code_line_height: Option<f32>) -> egui::text::LayoutJob {
     let mut job = egui::text::LayoutJob::default();
     let mut format = egui::TextFormat::simple(
@@ -1714,23 +4065,193 @@ fn default_theme(ui: &Ui) -> &str {
     }
 }
 
+/// A document's parsed events, owned so they outlive its text and shared
+/// between the renderers showing it.
+pub type ParsedEvents = Arc<Vec<(pulldown_cmark::Event<'static>, std::ops::Range<usize>)>>;
+
+fn parse_events(text: &str, options: pulldown_cmark::Options) -> ParsedEvents {
+    crate::profile_scope!("parse markdown");
+    Arc::new(
+        pulldown_cmark::Parser::new_ext(text, options)
+            .into_offset_iter()
+            .map(|(event, range)| (event.into_static(), range))
+            .collect(),
+    )
+}
+
+/// Key of a document's entry in `CommonMarkCache::cached_events`.
+fn events_key(text: &str, options: pulldown_cmark::Options) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    options.bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// `LayoutJob`s of the text runs shown in the last pass, so a document that
+/// didn't change doesn't rebuild its rich text every frame.
+///
+/// Jobs are keyed by text, [`Style`] and background; everything else that
+/// shapes them (colors, font sizes, line height) is covered by a signature
+/// that drops the whole set when it changes. Wrapping to the available width
+/// is left to the label showing the job, whose galleys egui caches itself.
+#[derive(Default)]
+pub struct TextLayouts {
+    signature: u64,
+    /// Word spacing of the typography, in pixels
+    word_spacing: f32,
+    /// Key → (pass last used in, job).
+    jobs: HashMap<u64, (u64, Arc<LayoutJob>)>,
+}
+
+impl TextLayouts {
+    /// Call before a pass renders text. Forgets runs that weren't shown in
+    /// the previous pass, and everything when the style changed.
+    pub fn begin(&mut self, ui: &Ui, options: &CommonMarkOptions) {
+        let signature = {
+            let mut h = DefaultHasher::new();
+            let visuals = ui.visuals();
+            visuals.dark_mode.hash(&mut h);
+            visuals.text_color().hash(&mut h);
+            visuals.weak_text_color().hash(&mut h);
+            visuals.strong_text_color().hash(&mut h);
+            visuals.code_bg_color.hash(&mut h);
+            for style in [TextStyle::Body, TextStyle::Monospace, TextStyle::Heading] {
+                ui.text_style_height(&style).to_bits().hash(&mut h);
+            }
+            let body = ui.text_style_height(&TextStyle::Body);
+            options
+                .typography
+                .resolve_line_height(body)
+                .map(f32::to_bits)
+                .hash(&mut h);
+            for spacing in [
+                options.typography.resolve_letter_spacing(body),
+                options.typography.resolve_word_spacing(body),
+            ] {
+                spacing.to_bits().hash(&mut h);
+            }
+            options.use_strong_font_family.hash(&mut h);
+            h.finish()
+        };
+        self.word_spacing = options
+            .typography
+            .resolve_word_spacing(ui.text_style_height(&TextStyle::Body));
+        if signature != self.signature {
+            self.signature = signature;
+            self.jobs.clear();
+        }
+        let pass = ui.ctx().cumulative_pass_nr();
+        self.jobs.retain(|_, (last_used, _)| *last_used + 1 >= pass);
+    }
+
+    /// The job for `text` in `style`, built with `rich_text` when it isn't
+    /// cached yet.
+    pub fn get_or_insert(
+        &mut self,
+        ui: &Ui,
+        text: &str,
+        style: &Style,
+        background: Option<egui::Color32>,
+        rich_text: impl FnOnce() -> RichText,
+    ) -> Arc<LayoutJob> {
+        let valign = ui.text_valign();
+        let key = {
+            let mut h = DefaultHasher::new();
+            text.hash(&mut h);
+            style.hash(&mut h);
+            background.hash(&mut h);
+            valign.hash(&mut h);
+            h.finish()
+        };
+        let pass = ui.ctx().cumulative_pass_nr();
+        let word_spacing = self.word_spacing;
+        let (last_used, job) = self.jobs.entry(key).or_insert_with(|| {
+            let mut job = egui::WidgetText::from(rich_text()).into_layout_job(
+                ui.style(),
+                egui::FontSelection::Default,
+                valign,
+            );
+            if word_spacing != 0.0 {
+                crate::typography::space_words(Arc::make_mut(&mut job), word_spacing);
+            }
+            (pass, job)
+        });
+        *last_used = pass;
+        job.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+/// The receiving end of a channel from worker threads. Only read through
+/// `&mut`, so locking never waits, but it lets the cache be shared between
+/// threads.
+struct WorkerResults<T>(std::sync::Mutex<mpsc::Receiver<T>>);
+
+impl<T> WorkerResults<T> {
+    fn try_recv(&mut self) -> Result<T, mpsc::TryRecvError> {
+        self.0
+            .get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .try_recv()
+    }
+}
+
+fn worker_channel<T>() -> (mpsc::Sender<T>, WorkerResults<T>) {
+    let (tx, rx) = mpsc::channel();
+    (tx, WorkerResults(std::sync::Mutex::new(rx)))
+}
+
 /// A cache used for storing content such as images.
+///
+/// It is `Send + Sync`, so it can live in a static or behind a lock shared
+/// between windows. To show several documents, give each a cache of its own
+/// from [`share_resources`](Self::share_resources).
 pub struct CommonMarkCache {
     // Everything stored in `CommonMarkCache` must take into account that
     // the cache is for multiple `CommonMarkviewer`s with different source_ids.
+    /// Syntaxes, when any were added to this cache. `None` uses the shared
+    /// `GLOBAL_SYNTAX_SET`.
     #[cfg(feature = "better_syntax_highlighting")]
-    ps: Arc<SyntaxSet>,
+    ps: Option<Arc<SyntaxSet>>,
 
+    /// Themes added to this cache, shadowing bundled themes of the same name
     #[cfg(feature = "better_syntax_highlighting")]
-    ts: ThemeSet,
+    themes: Arc<BTreeMap<String, Theme>>,
 
     link_hooks: HashMap<String, bool>,
+    link_hook_titles: HashMap<String, String>,
+    link_hook_clicks: HashMap<String, LinkClick>,
+    /// Destinations of the links that were clicked
+    visited_links: HashSet<String>,
+    /// Link a sandboxed document asks to open, waiting for the user to
+    /// confirm it
+    confirm_link: Option<String>,
 
     scroll: HashMap<egui::Id, ScrollableCache>,
 
-    /// Cached parsed markdown events to avoid re-parsing every frame.
-    /// Keyed by a hash of the source text. Cleared when cache is reset.
-    cached_events: Option<(u64, Vec<(pulldown_cmark::Event<'static>, std::ops::Range<usize>)>)>,
+    /// Parsed events of the documents shown recently, keyed by a hash of
+    /// their text and parser options, with the pass they were last shown in.
+    cached_events: HashMap<u64, (u64, ParsedEvents)>,
+
+    /// Formulas as the math renderer gets them, keyed by
+    /// `MathDocument::formula_key`, with the pass they were last shown in.
+    math_formulas: HashMap<u64, (u64, String)>,
+
+    /// Keys of `cached_events` entries being parsed on a worker thread.
+    parsing: HashSet<u64>,
+
+    /// Channel sender for background parsing results
+    parse_tx: mpsc::Sender<(u64, ParsedEvents)>,
+
+    /// Channel receiver for background parsing results
+    parse_rx: WorkerResults<(u64, ParsedEvents)>,
 
     /// Cached syntect LayoutJobs for code blocks, keyed by
     /// (content_hash, lang, theme_is_dark, mono_font_size).
@@ -1738,13 +4259,45 @@ pub struct CommonMarkCache {
     /// Theme/zoom changes naturally key in new entries; old ones become dead
     /// weight until the cache is reset on file load.
     syntax_layouts: HashMap<u64, LayoutJob>,
+
+    /// Layouts of the text in paragraphs, headings and the like.
+    text_layouts: TextLayouts,
+
+    /// Keys of `syntax_layouts` entries being highlighted on a worker thread.
+    #[cfg(feature = "better_syntax_highlighting")]
+    highlighting: HashSet<u64>,
+
+    /// Keys of blocks whose highlighting worker failed, shown as plain text.
+    #[cfg(feature = "better_syntax_highlighting")]
+    highlight_failed: HashSet<u64>,
+
+    /// Channel sender for background highlighting results
+    #[cfg(feature = "better_syntax_highlighting")]
+    highlight_tx: mpsc::Sender<(u64, Option<LayoutJob>)>,
+
+    /// Channel receiver for background highlighting results
+    #[cfg(feature = "better_syntax_highlighting")]
+    highlight_rx: WorkerResults<(u64, Option<LayoutJob>)>,
     pub(self) has_installed_loaders: bool,
 
-    /// Stores the y-position of each header (by normalized title) for scroll navigation.
-    /// Populated during rendering, cleared on content change.
-    header_positions: HashMap<String, f32>,
+    /// Stores the y-position of each header for scroll navigation.
+    /// Populated during rendering, invalidated per source when its text changes.
+    header_positions: HashMap<HeaderKey, f32>,
+    /// The headings rendered, invalidated together with `header_positions`.
+    headings: HashMap<HeaderKey, RenderedHeading>,
+    /// The tables rendered by source and index, invalidated like `headings`.
+    tables: HashMap<(egui::Id, usize), RenderedTable>,
+    /// Hash of the text the header positions of each source were recorded for.
+    header_texts: HashMap<egui::Id, u64>,
     /// Current scroll offset, set before rendering to calculate content-relative positions.
     current_scroll_offset: f32,
+    /// Top-level blocks of each source in document order, with their source
+    /// ranges and y positions. Rebuilt on every paint of that source.
+    source_blocks: HashMap<egui::Id, Vec<SourceBlock>>,
+    /// Content-relative y of each heading keyed by the source it was shown
+    /// with and its GitHub-style slug (see [`crate::anchor`]). Cleared
+    /// together with `header_positions`.
+    anchor_positions: HashMap<(egui::Id, String), f32>,
 
     /// Byte ranges of search matches in the source content. Renderer paints a background
     /// color on overlapping text events. Sorted ascending by start.
@@ -1766,7 +4319,7 @@ pub struct CommonMarkCache {
 
     /// Channel receiver for background mermaid render results
     #[cfg(feature = "mermaid")]
-    mermaid_rx: mpsc::Receiver<MermaidRenderResult>,
+    mermaid_rx: WorkerResults<MermaidRenderResult>,
 
     /// Mermaid renderer instance (reused across renders)
     #[cfg(feature = "mermaid")]
@@ -1780,10 +4333,36 @@ pub struct CommonMarkCache {
     /// Texture lifetime is owned by egui's loader, so we only carry the id.
     clicked_image: Option<(egui::TextureId, egui::Vec2)>,
 
-    /// Hash of the diagram that currently has an active background thread.
-    /// Only one diagram renders at a time so they appear top-to-bottom.
+    /// Images that failed to load, by uri, with the error.
+    failed_images: BTreeMap<String, String>,
+
+    /// Parts of documents that failed to render, by document id and source
+    /// start. Cleared when a document is parsed again.
+    render_errors: BTreeMap<(u64, usize), RenderError>,
+
+    /// Size lazy images were last shown at, to reserve while unloaded.
+    image_sizes: HashMap<String, egui::Vec2>,
+    /// File name and bytes picked with "Save image as…".
+    image_to_save: Option<(String, Vec<u8>)>,
+    /// Images shown unchanged in dark mode, picked from their context menu.
+    unadjusted_images: HashSet<String>,
+
+    /// Lazy images handed to the loader, with the pass they were last near
+    /// the viewport in.
+    loaded_images: HashMap<String, u64>,
+
+    /// Collapsible alerts the user opened or closed. The others keep the
+    /// state they start in.
+    open_alerts: HashMap<egui::Id, bool>,
+
+    /// Hashes of diagrams that currently have an active background thread,
+    /// at most `mermaid_threads` of them.
+    #[cfg(feature = "mermaid")]
+    mermaid_rendering: HashSet<u64>,
+
+    /// Number of diagrams rendered concurrently
     #[cfg(feature = "mermaid")]
-    mermaid_rendering: Option<u64>,
+    mermaid_threads: usize,
 
     /// Math formula render states: content hash → rendering/ready/error
     #[cfg(feature = "math")]
@@ -1795,7 +4374,7 @@ pub struct CommonMarkCache {
 
     /// Channel receiver for background math render results
     #[cfg(feature = "math")]
-    math_rx: mpsc::Receiver<MathRenderResult>,
+    math_rx: WorkerResults<MathRenderResult>,
 
     /// Hashes of formulas that currently have an active background render
     /// thread (bounded by `math_concurrency()`).
@@ -1807,17 +4386,36 @@ impl std::fmt::Debug for CommonMarkCache {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut s = f.debug_struct("CommonMarkCache");
         s.field("link_hooks", &self.link_hooks)
+            .field("link_hook_titles", &self.link_hook_titles)
+            .field("link_hook_clicks", &self.link_hook_clicks)
+            .field("visited_links", &self.visited_links)
+            .field("confirm_link", &self.confirm_link)
             .field("scroll", &self.scroll)
             .field("has_installed_loaders", &self.has_installed_loaders)
             .field("header_positions", &self.header_positions)
+            .field("headings", &self.headings)
+            .field("tables", &self.tables)
+            .field("header_texts", &self.header_texts)
             .field("current_scroll_offset", &self.current_scroll_offset)
+            .field(
+                "source_blocks_count",
+                &self.source_blocks.values().map(Vec::len).sum::<usize>(),
+            )
+            .field("anchor_positions", &self.anchor_positions)
             .field("search_ranges_count", &self.search_ranges.len())
             .field("active_search_range", &self.active_search_range);
         #[cfg(feature = "mermaid")]
         s.field("mermaid_states_count", &self.mermaid_states.len());
         #[cfg(feature = "mermaid")]
+        s.field("mermaid_threads", &self.mermaid_threads);
+        #[cfg(feature = "mermaid")]
         s.field("clicked_mermaid", &self.clicked_mermaid.is_some());
         s.field("clicked_image", &self.clicked_image.is_some());
+        s.field("failed_images", &self.failed_images);
+        s.field("render_errors", &self.render_errors);
+        s.field("loaded_images", &self.loaded_images.len());
+        s.field("open_alerts", &self.open_alerts);
+        s.field("text_layouts", &self.text_layouts.len());
         #[cfg(feature = "math")]
         s.field("math_states_count", &self.math_states.len());
         s.finish()
@@ -1828,20 +4426,32 @@ impl std::fmt::Debug for CommonMarkCache {
 impl Default for CommonMarkCache {
     fn default() -> Self {
         #[cfg(feature = "mermaid")]
-        let (mermaid_tx, mermaid_rx) = mpsc::channel();
+        let (mermaid_tx, mermaid_rx) = worker_channel();
         #[cfg(feature = "math")]
-        let (math_tx, math_rx) = mpsc::channel();
+        let (math_tx, math_rx) = worker_channel();
+        #[cfg(feature = "better_syntax_highlighting")]
+        let (highlight_tx, highlight_rx) = worker_channel();
+        let (parse_tx, parse_rx) = worker_channel();
 
         Self {
             #[cfg(feature = "better_syntax_highlighting")]
-            ps: Arc::clone(&GLOBAL_SYNTAX_SET),
+            ps: None,
             #[cfg(feature = "better_syntax_highlighting")]
-            ts: ThemeSet::load_defaults(),
+            themes: Arc::default(),
             link_hooks: HashMap::new(),
+            link_hook_titles: HashMap::new(),
+            link_hook_clicks: HashMap::new(),
+            visited_links: HashSet::new(),
+            confirm_link: None,
             scroll: Default::default(),
             has_installed_loaders: false,
             header_positions: HashMap::new(),
+            headings: HashMap::new(),
+            tables: HashMap::new(),
+            header_texts: HashMap::new(),
             current_scroll_offset: 0.0,
+            source_blocks: HashMap::new(),
+            anchor_positions: HashMap::new(),
             search_ranges: Vec::new(),
             active_search_range: None,
             active_search_y: None,
@@ -1863,8 +4473,17 @@ impl Default for CommonMarkCache {
             #[cfg(feature = "mermaid")]
             clicked_mermaid: None,
             clicked_image: None,
+            failed_images: BTreeMap::new(),
+            render_errors: BTreeMap::new(),
+            image_sizes: HashMap::new(),
+            image_to_save: None,
+            unadjusted_images: HashSet::new(),
+            loaded_images: HashMap::new(),
+            open_alerts: HashMap::new(),
             #[cfg(feature = "mermaid")]
-            mermaid_rendering: None,
+            mermaid_rendering: HashSet::new(),
+            #[cfg(feature = "mermaid")]
+            mermaid_threads: default_mermaid_threads(),
             #[cfg(feature = "math")]
             math_states: HashMap::new(),
             #[cfg(feature = "math")]
@@ -1873,25 +4492,50 @@ impl Default for CommonMarkCache {
             math_rx,
             #[cfg(feature = "math")]
             math_rendering: HashSet::new(),
-            cached_events: None,
+            cached_events: HashMap::new(),
+            math_formulas: HashMap::new(),
+            parsing: HashSet::new(),
+            parse_tx,
+            parse_rx,
             syntax_layouts: HashMap::new(),
+            text_layouts: TextLayouts::default(),
+            #[cfg(feature = "better_syntax_highlighting")]
+            highlighting: HashSet::new(),
+            #[cfg(feature = "better_syntax_highlighting")]
+            highlight_failed: HashSet::new(),
+            #[cfg(feature = "better_syntax_highlighting")]
+            highlight_tx,
+            #[cfg(feature = "better_syntax_highlighting")]
+            highlight_rx,
         }
     }
 }
 
 impl CommonMarkCache {
+    /// An empty cache for another document that shares this one's syntaxes
+    /// and color themes, instead of loading them again.
+    pub fn share_resources(&self) -> Self {
+        Self {
+            #[cfg(feature = "better_syntax_highlighting")]
+            ps: self.ps.clone(),
+            #[cfg(feature = "better_syntax_highlighting")]
+            themes: Arc::clone(&self.themes),
+            ..Default::default()
+        }
+    }
+
     #[cfg(feature = "better_syntax_highlighting")]
     pub fn add_syntax_from_folder(&mut self, path: &str) {
-        let mut builder = (*self.ps).clone().into_builder();
+        let mut builder = (**self.syntax_set()).clone().into_builder();
         let _ = builder.add_from_folder(path, true);
-        self.ps = Arc::new(builder.build());
+        self.ps = Some(Arc::new(builder.build()));
     }
 
     #[cfg(feature = "better_syntax_highlighting")]
     pub fn add_syntax_from_str(&mut self, s: &str, fallback_name: Option<&str>) {
-        let mut builder = (*self.ps).clone().into_builder();
+        let mut builder = (**self.syntax_set()).clone().into_builder();
         let _ = SyntaxDefinition::load_from_str(s, true, fallback_name).map(|d| builder.add(d));
-        self.ps = Arc::new(builder.build());
+        self.ps = Some(Arc::new(builder.build()));
     }
 
     #[cfg(feature = "better_syntax_highlighting")]
@@ -1902,7 +4546,23 @@ impl CommonMarkCache {
         &mut self,
         path: impl AsRef<std::path::Path>,
     ) -> Result<(), syntect::LoadingError> {
-        self.ts.add_from_folder(path)
+        Arc::make_mut(&mut self.themes).extend(ThemeSet::load_from_folder(path)?.themes);
+        Ok(())
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    /// Names of all loaded color themes for code blocks, in sorted order. Includes
+    /// the syntect defaults plus anything added with
+    /// [`add_syntax_themes_from_folder`](Self::add_syntax_themes_from_folder) or
+    /// [`add_syntax_theme_from_bytes`](Self::add_syntax_theme_from_bytes).
+    pub fn syntax_theme_names(&self) -> impl Iterator<Item = &str> {
+        GLOBAL_THEME_SET
+            .themes
+            .keys()
+            .chain(self.themes.keys())
+            .map(String::as_str)
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
     }
 
     #[cfg(feature = "better_syntax_highlighting")]
@@ -1915,8 +4575,7 @@ impl CommonMarkCache {
         bytes: &[u8],
     ) -> Result<(), syntect::LoadingError> {
         let mut cursor = std::io::Cursor::new(bytes);
-        self.ts
-            .themes
+        Arc::make_mut(&mut self.themes)
             .insert(name.into(), ThemeSet::load_from_reader(&mut cursor)?);
         Ok(())
     }
@@ -1934,6 +4593,67 @@ impl CommonMarkCache {
         self.clicked_image.take()
     }
 
+    /// The suggested file name and the bytes of an image picked with "Save
+    /// image as…" in the image context menu, for the host to ask where to
+    /// write it. Diagrams are saved as svg. See
+    /// `CommonMarkViewer::image_context_menu`.
+    pub fn take_image_to_save(&mut self) -> Option<(String, Vec<u8>)> {
+        self.image_to_save.take()
+    }
+
+    /// Show the image at `uri` unchanged in dark mode, or adjusted like the
+    /// others again. See `CommonMarkViewer::dark_mode_images`.
+    pub fn set_image_unadjusted(&mut self, uri: &str, unadjusted: bool) {
+        if unadjusted {
+            self.unadjusted_images.insert(uri.to_owned());
+        } else {
+            self.unadjusted_images.remove(uri);
+        }
+    }
+
+    /// Images that failed to load, as `(uri, error)` sorted by uri, for a
+    /// "missing images" warning. An image is dropped from the list once it
+    /// loads, e.g. after the file was put back and the document repainted.
+    pub fn failed_images(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.failed_images
+            .iter()
+            .map(|(uri, error)| (uri.as_str(), error.as_str()))
+    }
+
+    /// Forget all recorded image load failures.
+    pub fn clear_failed_images(&mut self) {
+        self.failed_images.clear();
+    }
+
+    /// Parts of the documents shown that failed to render, in source order.
+    pub fn render_errors(&self) -> impl Iterator<Item = &RenderError> {
+        self.render_errors.values()
+    }
+
+    /// The error that `byte_range` of the document `source_id` failed to
+    /// render with.
+    pub fn render_error(
+        &self,
+        source_id: egui::Id,
+        byte_range: &std::ops::Range<usize>,
+    ) -> Option<&RenderError> {
+        self.render_errors
+            .get(&(source_id.value(), byte_range.start))
+            .filter(|error| error.byte_range == *byte_range)
+    }
+
+    pub fn record_render_error(&mut self, source_id: egui::Id, error: RenderError) {
+        self.render_errors
+            .insert((source_id.value(), error.byte_range.start), error);
+    }
+
+    /// Set how many mermaid diagrams may render at once, each on its own
+    /// thread. Defaults to half the available cores, between 1 and 4.
+    #[cfg(feature = "mermaid")]
+    pub fn set_mermaid_threads(&mut self, threads: usize) {
+        self.mermaid_threads = threads.max(1);
+    }
+
     /// Clear the cache for all scrollable elements
     pub fn clear_scrollable(&mut self) {
         self.scroll.clear();
@@ -1945,6 +4665,53 @@ impl CommonMarkCache {
         self.scroll.remove(&egui::Id::new(source_id)).is_some()
     }
 
+    /// Forget the layout of the part of `source_id`'s text in `range` after
+    /// editing it, e.g. toggling a task list checkbox. `range` is where the
+    /// edited text is now. Only the blocks overlapping it are laid out again;
+    /// the others keep their measured heights, unless the edit added or
+    /// removed blocks. A content version passed to the viewer has to be
+    /// bumped as usual.
+    pub fn invalidate_block(
+        &mut self,
+        source_id: impl std::hash::Hash,
+        range: std::ops::Range<usize>,
+    ) {
+        if let Some(scroll) = self.scroll.get_mut(&egui::Id::new(source_id)) {
+            scroll.edited.push(range);
+            scroll.needs_layout = true;
+        }
+    }
+
+    /// Forget everything cached for `source_id`, its layout and header
+    /// positions, so it is laid out from scratch. Other viewers sharing the
+    /// cache keep theirs.
+    pub fn invalidate_all(&mut self, source_id: impl std::hash::Hash) {
+        let source_id = egui::Id::new(source_id);
+        self.scroll.remove(&source_id);
+        self.header_positions
+            .retain(|key, _| key.source_id != source_id);
+        self.headings.retain(|key, _| key.source_id != source_id);
+        self.tables.retain(|(id, _), _| *id != source_id);
+        self.header_texts.remove(&source_id);
+        self.anchor_positions.retain(|(id, _), _| *id != source_id);
+        self.source_blocks.remove(&source_id);
+    }
+
+    /// Whether the collapsible alert `id` is open, `default` unless it was
+    /// toggled.
+    pub fn alert_open(&self, id: egui::Id, default: bool) -> bool {
+        self.open_alerts.get(&id).copied().unwrap_or(default)
+    }
+
+    /// Open or close the collapsible alert `id`. Scrollable viewers lay their
+    /// document out again, since its height changed.
+    pub fn set_alert_open(&mut self, id: egui::Id, open: bool) {
+        self.open_alerts.insert(id, open);
+        for scroll in self.scroll.values_mut() {
+            scroll.needs_layout = true;
+        }
+    }
+
     /// If the user clicks on a link in the markdown render that has `name` as a link. The hook
     /// specified with this method will be set to true. It's status can be acquired
     /// with [`get_link_hook`](Self::get_link_hook). Be aware that all hook state is reset once
@@ -1975,6 +4742,8 @@ impl CommonMarkCache {
     /// Returns None if the link hook could not be found. Returns the last known status of the
     /// hook otherwise.
     pub fn remove_link_hook(&mut self, name: &str) -> Option<bool> {
+        self.link_hook_titles.remove(name);
+        self.link_hook_clicks.remove(name);
         self.link_hooks.remove(name)
     }
 
@@ -1983,9 +4752,34 @@ impl CommonMarkCache {
         self.link_hooks.get(name).copied()
     }
 
+    /// Title of the hooked link `name`, if it was clicked this frame and the
+    /// markdown gave it one (`[text](name "title")`).
+    pub fn get_link_hook_title(&self, name: &str) -> Option<&str> {
+        self.link_hook_titles.get(name).map(String::as_str)
+    }
+
+    /// The mouse button and modifiers of the click on the hooked link
+    /// `name`, if it was clicked this frame.
+    pub fn get_link_hook_click(&self, name: &str) -> Option<LinkClick> {
+        self.link_hook_clicks.get(name).copied()
+    }
+
+    /// Whether a link to `destination` was clicked, which draws it with the
+    /// visited color of `LinkStyle`
+    pub fn is_link_visited(&self, destination: &str) -> bool {
+        self.visited_links.contains(destination)
+    }
+
+    /// Forget which links were clicked
+    pub fn clear_visited_links(&mut self) {
+        self.visited_links.clear();
+    }
+
     /// Remove all link hooks
     pub fn link_hooks_clear(&mut self) {
         self.link_hooks.clear();
+        self.link_hook_titles.clear();
+        self.link_hook_clicks.clear();
     }
 
     /// All link hooks
@@ -1998,20 +4792,58 @@ impl CommonMarkCache {
         &mut self.link_hooks
     }
 
+    /// Open `url` in another app, unless the sandbox of `options` blocks it,
+    /// or asks the user first when it confirms external links.
+    pub fn open_external(&mut self, ctx: &egui::Context, options: &CommonMarkOptions, url: &str) {
+        if options.sandbox.blocks_link(url) {
+            return;
+        }
+        if options.sandbox.confirm_external_links {
+            self.confirm_link = Some(url.to_owned());
+            ctx.request_repaint();
+        } else {
+            ctx.open_url(egui::OpenUrl::new_tab(url));
+        }
+    }
+
     /// Set all link hooks to false
     fn deactivate_link_hooks(&mut self) {
         for v in self.link_hooks.values_mut() {
             *v = false;
         }
+        self.link_hook_titles.clear();
+        self.link_hook_clicks.clear();
+    }
+
+    /// Move finished background highlighting into `syntax_layouts`.
+    #[cfg(feature = "better_syntax_highlighting")]
+    fn receive_highlighted(&mut self) {
+        while let Ok((key, job)) = self.highlight_rx.try_recv() {
+            self.highlighting.remove(&key);
+            match job {
+                Some(job) => {
+                    self.syntax_layouts.insert(key, job);
+                }
+                None => {
+                    self.highlight_failed.insert(key);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "better_syntax_highlighting")]
+    fn syntax_set(&self) -> &Arc<SyntaxSet> {
+        self.ps.as_ref().unwrap_or(&GLOBAL_SYNTAX_SET)
     }
 
     #[cfg(feature = "better_syntax_highlighting")]
     fn curr_theme(&self, ui: &Ui, options: &CommonMarkOptions) -> &Theme {
-        self.ts
-            .themes
-            .get(options.curr_theme(ui))
-            // Since we have called load_defaults, the default theme *should* always be available..
-            .unwrap_or_else(|| &self.ts.themes[default_theme(ui)])
+        let name = options.curr_theme(ui);
+        self.themes
+            .get(name)
+            .or_else(|| GLOBAL_THEME_SET.themes.get(name))
+            // The bundled default themes are always available
+            .unwrap_or_else(|| &GLOBAL_THEME_SET.themes[default_theme(ui)])
     }
 
     /// Set the current scroll offset before rendering.
@@ -2023,10 +4855,9 @@ impl CommonMarkCache {
     /// Record the y-position of a header for scroll navigation.
     /// Converts viewport-relative position to content-relative using scroll offset.
     /// Always updates to reflect current layout (e.g. after zoom changes the content width).
-    /// The `normalized_key` should be a pre-computed lowercase key for the header title.
-    pub fn record_header_position(&mut self, normalized_key: &str, viewport_y: f32) {
+    pub fn record_header_position(&mut self, key: HeaderKey, viewport_y: f32) {
         let content_y = self.current_scroll_offset + viewport_y;
-        self.header_positions.insert(normalized_key.to_string(), content_y);
+        self.header_positions.insert(key, content_y);
     }
 
     /// Record a header's already-content-relative y directly (no offset addition).
@@ -2035,8 +4866,8 @@ impl CommonMarkCache {
     /// `state.offset.y`). The other entry point — `record_header_position` —
     /// is for `show_viewport`, whose cursor is already viewport-relative so
     /// the offset addition is needed.
-    pub fn record_header_content_y(&mut self, normalized_key: &str, content_y: f32) {
-        self.header_positions.insert(normalized_key.to_string(), content_y);
+    pub fn record_header_content_y(&mut self, key: HeaderKey, content_y: f32) {
+        self.header_positions.insert(key, content_y);
     }
 
     /// Same as `record_header_content_y` but only stores if the key isn't
@@ -2045,23 +4876,212 @@ impl CommonMarkCache {
     /// first sighting keeps the cache stable and matches what the user sees
     /// in the layout that was first painted. Reflow events invalidate via
     /// `clear_header_positions`.
-    pub fn record_header_content_y_if_absent(&mut self, normalized_key: &str, content_y: f32) {
-        self.header_positions
-            .entry(normalized_key.to_string())
-            .or_insert(content_y);
+    pub fn record_header_content_y_if_absent(&mut self, key: HeaderKey, content_y: f32) {
+        self.header_positions.entry(key).or_insert(content_y);
     }
 
-    /// Get the y-position of a header by its normalized title (content-relative).
+    /// Get the y-position of a header (content-relative).
     /// Returns None if the header hasn't been rendered yet.
-    /// The `normalized_key` should be a pre-computed lowercase key for the header title.
-    pub fn get_header_position(&self, normalized_key: &str) -> Option<f32> {
-        self.header_positions.get(normalized_key).copied()
+    pub fn get_header_position(&self, key: &HeaderKey) -> Option<f32> {
+        self.header_positions.get(key).copied()
+    }
+
+    /// Forget the header positions of `source_id` if they were recorded for
+    /// a different text. Called by the renderer before it records positions,
+    /// so a cache can be kept when the document is edited or replaced.
+    pub fn invalidate_header_positions(&mut self, source_id: egui::Id, text: &str) {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let text_hash = hasher.finish();
+        let previous = self.header_texts.insert(source_id, text_hash);
+        if previous.is_some_and(|previous| previous != text_hash) {
+            self.header_positions
+                .retain(|key, _| key.source_id != source_id);
+            self.headings.retain(|key, _| key.source_id != source_id);
+            self.tables.retain(|(id, _), _| *id != source_id);
+            self.anchor_positions.retain(|(id, _), _| *id != source_id);
+        }
     }
 
     /// Clear all recorded header positions.
-    /// Should be called when content changes.
     pub fn clear_header_positions(&mut self) {
         self.header_positions.clear();
+        self.headings.clear();
+        self.tables.clear();
+        self.header_texts.clear();
+        self.anchor_positions.clear();
+    }
+
+    /// Record a rendered heading, replacing what was recorded for its key.
+    pub fn record_heading(&mut self, heading: RenderedHeading) {
+        self.headings.insert(heading.key.clone(), heading);
+    }
+
+    /// The headings of the document shown with `source_id` (`egui::Id::NULL`
+    /// for documents shown without one) from top to bottom, as parsed and
+    /// laid out when they were last rendered. Headings in code blocks are
+    /// not among them. Empty until the document is shown.
+    pub fn headings(&self, source_id: impl std::hash::Hash) -> Vec<RenderedHeading> {
+        let source_id = egui::Id::new(source_id);
+        let mut headings: Vec<_> = self
+            .headings
+            .values()
+            .filter(|heading| heading.key.source_id == source_id)
+            .cloned()
+            .collect();
+        headings.sort_by(|a, b| a.y_range.min.total_cmp(&b.y_range.min));
+        headings
+    }
+
+    /// Record a rendered table, replacing what was recorded at its index.
+    pub fn record_table(&mut self, table: RenderedTable) {
+        self.tables.insert((table.source_id, table.index), table);
+    }
+
+    /// The tables of the document shown with `source_id` in document order,
+    /// with the text of their cells as they were last rendered. Tables that
+    /// have not been scrolled into view yet are not among them.
+    pub fn tables(&self, source_id: impl std::hash::Hash) -> Vec<RenderedTable> {
+        let source_id = egui::Id::new(source_id);
+        let mut tables: Vec<_> = self
+            .tables
+            .values()
+            .filter(|table| table.source_id == source_id)
+            .cloned()
+            .collect();
+        tables.sort_by_key(|table| table.index);
+        tables
+    }
+
+    /// Record the content-relative y of the heading with anchor `slug` in
+    /// the document shown with `source_id`.
+    pub fn record_anchor_position(&mut self, source_id: egui::Id, slug: String, content_y: f32) {
+        self.anchor_positions.insert((source_id, slug), content_y);
+    }
+
+    /// Content-relative y of the heading with the given anchor, as resolved
+    /// by [`anchor_target`](crate::anchor::anchor_target), in the document
+    /// shown with `source_id` (`egui::Id::NULL` for documents shown without
+    /// one). Returns None if no such heading has been rendered yet.
+    pub fn anchor_position(&self, source_id: impl std::hash::Hash, anchor: &str) -> Option<f32> {
+        let key = (egui::Id::new(source_id), crate::anchor::anchor_target(anchor));
+        self.anchor_positions.get(&key).copied()
+    }
+
+    /// Ask the scrollable viewer shown with `source_id` to scroll to the
+    /// heading with the given anchor, e.g. `"#getting-started"`, or to a
+    /// footnote (`"#fn-1"`) or footnote reference (`"#fnref-1"`). The jump
+    /// happens in a following `show_scrollable` call once the heading has been
+    /// laid out; requests for anchors the document doesn't have are dropped.
+    pub fn scroll_to_heading(&mut self, source_id: impl std::hash::Hash, anchor: &str) {
+        let slug = crate::anchor::anchor_target(anchor);
+        let sc = scroll_cache(self, &egui::Id::new(source_id));
+        sc.pending_anchor = Some(slug);
+        sc.pending_anchor_painted = false;
+    }
+
+    /// Resolve a pending [`scroll_to_heading`](Self::scroll_to_heading)
+    /// request for `source_id` into a scroll offset. Called by the renderer
+    /// before painting; gives the document one paint to record its anchors.
+    pub fn take_heading_scroll(&mut self, source_id: egui::Id) -> Option<f32> {
+        let sc = self.scroll.get_mut(&source_id)?;
+        let slug = sc.pending_anchor.clone()?;
+        if let Some(y) = self.anchor_positions.get(&(source_id, slug)) {
+            sc.pending_anchor = None;
+            Some(*y)
+        } else {
+            if sc.pending_anchor_painted {
+                sc.pending_anchor = None;
+            }
+            sc.pending_anchor_painted = true;
+            None
+        }
+    }
+
+    /// Top-level blocks of the document shown with `source_id`, recorded
+    /// during its last paint, in document order.
+    pub fn source_blocks(&self, source_id: impl std::hash::Hash) -> &[SourceBlock] {
+        self.source_blocks
+            .get(&egui::Id::new(source_id))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn clear_source_blocks(&mut self, source_id: egui::Id) {
+        self.source_blocks.entry(source_id).or_default().clear();
+    }
+
+    pub fn record_source_block(&mut self, source_id: egui::Id, block: SourceBlock) {
+        let blocks = self.source_blocks.entry(source_id).or_default();
+        if let Some(prev) = blocks.last_mut() {
+            prev.y_range.max = block.y_range.min;
+        }
+        blocks.push(block);
+    }
+
+    /// Extend the last block recorded for `source_id` down to `bottom`, the
+    /// end of the content.
+    pub fn finish_source_blocks(&mut self, source_id: egui::Id, bottom: f32) {
+        let last = self.source_blocks.get_mut(&source_id).and_then(|b| b.last_mut());
+        if let Some(last) = last {
+            last.y_range.max = bottom.max(last.y_range.min);
+        }
+    }
+
+    /// Content-relative y at which 1-based source `line` is rendered in the
+    /// document shown with `source_id`, for go-to-line and editor → viewer
+    /// scroll sync. Lines inside a block are interpolated across its height;
+    /// blank lines between blocks map to the top of the next block. Returns
+    /// None before the first paint.
+    pub fn y_for_line(&self, source_id: impl std::hash::Hash, line: usize) -> Option<f32> {
+        let blocks = self.source_blocks(source_id);
+        let idx = blocks.partition_point(|b| b.line <= line).checked_sub(1);
+        let Some(block) = idx.map(|i| &blocks[i]) else {
+            return blocks.first().map(|b| b.y_range.min);
+        };
+        if line > block.end_line {
+            return Some(block.y_range.max);
+        }
+        let span = (block.end_line - block.line + 1) as f32;
+        let fraction = (line - block.line) as f32 / span;
+        Some(block.y_range.min + fraction * block.y_range.span())
+    }
+
+    /// Source byte ranges of the blocks a rendered selection touches in the
+    /// document shown with `source_id`, in document order, so hosts can copy a selection as markdown or anchor an
+    /// annotation to the source. `selection` is the selection's content-relative
+    /// vertical extent (e.g. the drag rect minus the scroll area's content
+    /// origin). Granularity is one top-level block: egui does not expose which
+    /// characters of a label are selected.
+    pub fn source_ranges_for_selection(
+        &self,
+        source_id: impl std::hash::Hash,
+        selection: egui::Rangef,
+    ) -> Vec<std::ops::Range<usize>> {
+        self.source_blocks(source_id)
+            .iter()
+            .filter(|b| b.y_range.min <= selection.max && selection.min < b.y_range.max)
+            .map(|b| b.byte_range.clone())
+            .collect()
+    }
+
+    /// 1-based source line rendered at content-relative `y` in the document
+    /// shown with `source_id`, the inverse of [`y_for_line`](Self::y_for_line).
+    /// Returns None before the first paint.
+    pub fn line_for_y(&self, source_id: impl std::hash::Hash, y: f32) -> Option<usize> {
+        let blocks = self.source_blocks(source_id);
+        let idx = blocks
+            .partition_point(|b| b.y_range.min <= y)
+            .saturating_sub(1);
+        let block = blocks.get(idx)?;
+        let height = block.y_range.span();
+        let fraction = if height > 0.0 {
+            ((y - block.y_range.min) / height).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let span = block.end_line - block.line + 1;
+        let offset = ((fraction * span as f32) as usize).min(span - 1);
+        Some(block.line + offset)
     }
 
     /// Replace the set of search-match byte ranges. Renderer paints a background color
@@ -2111,20 +5131,82 @@ impl CommonMarkCache {
         self.active_search_range.as_ref()
     }
 
-    /// Get cached parsed events if the content hash matches.
-    pub fn get_cached_events(&self, content_hash: u64) -> Option<&[(pulldown_cmark::Event<'static>, std::ops::Range<usize>)]> {
-        self.cached_events.as_ref().and_then(|(hash, events)| {
-            if *hash == content_hash {
-                Some(events.as_slice())
-            } else {
-                None
-            }
-        })
+    /// The events of `text` parsed with `options`. A document shown in the
+    /// last pass is parsed again only once its text or options change.
+    pub fn parsed_events(
+        &mut self,
+        ctx: &egui::Context,
+        text: &str,
+        options: pulldown_cmark::Options,
+    ) -> ParsedEvents {
+        let pass = ctx.cumulative_pass_nr();
+        let (last_shown, events) = self
+            .cached_events
+            .entry(events_key(text, options))
+            .or_insert_with(|| {
+                // The spans of the parts that failed may now be other parts
+                self.render_errors.clear();
+                (pass, parse_events(text, options))
+            });
+        *last_shown = pass;
+        Arc::clone(events)
+    }
+
+    /// Like [`parsed_events`](Self::parsed_events), but a document that needs
+    /// parsing is parsed on a worker thread, returning `None` until it's done.
+    pub fn parsed_events_in_background(
+        &mut self,
+        ctx: &egui::Context,
+        text: &str,
+        options: pulldown_cmark::Options,
+    ) -> Option<ParsedEvents> {
+        let pass = ctx.cumulative_pass_nr();
+        while let Ok((key, events)) = self.parse_rx.try_recv() {
+            self.parsing.remove(&key);
+            self.cached_events.insert(key, (pass, events));
+            self.render_errors.clear();
+        }
+
+        let key = events_key(text, options);
+        if let Some((last_shown, events)) = self.cached_events.get_mut(&key) {
+            *last_shown = pass;
+            return Some(Arc::clone(events));
+        }
+        if self.parsing.insert(key) {
+            let text = text.to_owned();
+            let tx = self.parse_tx.clone();
+            std::thread::spawn(move || {
+                let _ = tx.send((key, parse_events(&text, options)));
+            });
+        }
+        ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        None
+    }
+
+    /// `tex` as [`MathDocument::formula`] gives it, expanded only once while
+    /// the document's math stays the same.
+    pub fn math_formula<'a>(
+        &mut self,
+        ctx: &egui::Context,
+        math: &MathDocument,
+        tex: &'a str,
+    ) -> std::borrow::Cow<'a, str> {
+        if math.is_plain() {
+            return std::borrow::Cow::Borrowed(tex);
+        }
+        let pass = ctx.cumulative_pass_nr();
+        let (last_shown, formula) = self
+            .math_formulas
+            .entry(math.formula_key(tex))
+            .or_insert_with(|| (pass, math.formula(tex).into_owned()));
+        *last_shown = pass;
+        std::borrow::Cow::Owned(formula.clone())
     }
 
-    /// Store parsed events in the cache.
-    pub fn set_cached_events(&mut self, content_hash: u64, events: Vec<(pulldown_cmark::Event<'static>, std::ops::Range<usize>)>) {
-        self.cached_events = Some((content_hash, events));
+    /// Text layouts kept between frames. The renderer swaps them out for the
+    /// duration of a pass.
+    pub fn text_layouts_mut(&mut self) -> &mut TextLayouts {
+        &mut self.text_layouts
     }
 }
 
@@ -2135,8 +5217,35 @@ pub fn scroll_cache<'a>(cache: &'a mut CommonMarkCache, id: &egui::Id) -> &'a mu
     cache.scroll.get_mut(id).unwrap()
 }
 
+/// Ask whether to open the link a sandboxed document wants to open.
+fn confirm_external_link(cache: &mut CommonMarkCache, ctx: &egui::Context) {
+    let Some(url) = &cache.confirm_link else {
+        return;
+    };
+    let mut answered = false;
+    let modal = egui::Modal::new(egui::Id::new("commonmark_confirm_link")).show(ctx, |ui| {
+        ui.set_max_width(420.0);
+        ui.label(RichText::new("Open external link?").strong());
+        ui.label("This document isn't trusted. The link goes to:");
+        ui.add(egui::Label::new(RichText::new(url).monospace()).wrap());
+        ui.add_space(4.0);
+        ui.horizontal(|ui| {
+            if ui.button("Open").clicked() {
+                ctx.open_url(egui::OpenUrl::new_tab(url));
+                answered = true;
+            }
+            answered |= ui.button("Cancel").clicked();
+        });
+    });
+    if answered || modal.should_close() {
+        cache.confirm_link = None;
+    }
+}
+
 /// Should be called before any rendering
 pub fn prepare_show(cache: &mut CommonMarkCache, ctx: &egui::Context) {
+    confirm_external_link(cache, ctx);
+
     if !cache.has_installed_loaders {
         // Even though the install function can be called multiple times, its not the cheapest
         // so we ensure that we only call it once.
@@ -2148,8 +5257,31 @@ pub fn prepare_show(cache: &mut CommonMarkCache, ctx: &egui::Context) {
         crate::data_url_loader::install_loader(ctx);
 
         egui_extras::install_image_loaders(ctx);
+        // Texture loaders are asked newest first, so images are shrunk before
+        // egui's default loader would upload them at full size.
+        crate::texture_loader::install_loader(ctx);
+        // Installed last so it is asked before egui_extras' own http loader.
+        #[cfg(feature = "remote_images")]
+        crate::http_loader::install_loader(ctx);
         cache.has_installed_loaders = true;
     }
 
+    // Lazy images that weren't near the viewport last pass have been scrolled
+    // far away, or their document isn't shown any more.
+    let pass = ctx.cumulative_pass_nr();
+    cache.loaded_images.retain(|uri, last_near| {
+        let near = *last_near + 1 >= pass;
+        if !near {
+            ctx.forget_image(uri);
+        }
+        near
+    });
+    cache
+        .cached_events
+        .retain(|_, (last_shown, _)| *last_shown + 1 >= pass);
+    cache
+        .math_formulas
+        .retain(|_, (last_shown, _)| *last_shown + 1 >= pass);
+
     cache.deactivate_link_hooks();
 }
@@ -0,0 +1,46 @@
+//! Recognizes HTML blocks that only force a page break when printing, such as
+//! `<div class="page-break"></div>` or `<div style="break-after: page"></div>`,
+//! so they can be rendered as a page break marker instead of as text.
+
+/// Whether `html` is an empty `<div>` whose class or style breaks the page.
+pub fn is_page_break(html: &str) -> bool {
+    let lower = html.trim().to_ascii_lowercase();
+    let Some(after_name) = lower.strip_prefix("<div") else {
+        return false;
+    };
+    if !after_name.starts_with(|c: char| c.is_ascii_whitespace()) {
+        return false;
+    }
+    let Some(open_tag_end) = after_name.find('>') else {
+        return false;
+    };
+    let (attributes, rest) = after_name.split_at(open_tag_end);
+    let empty = match rest[1..].trim().strip_prefix("</div") {
+        Some(close) => close.trim() == ">",
+        None => attributes.ends_with('/') && rest[1..].trim().is_empty(),
+    };
+    let attributes: String = attributes.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    empty
+        && (attributes.contains("class=\"page-break\"")
+            || attributes.contains("class='page-break'")
+            || attributes.contains("page-break-after:always")
+            || attributes.contains("page-break-before:always")
+            || attributes.contains("break-after:page")
+            || attributes.contains("break-before:page"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_empty_page_break_divs() {
+        assert!(is_page_break("<div class=\"page-break\"></div>\n"));
+        assert!(is_page_break("<DIV CLASS='page-break' />"));
+        assert!(is_page_break("<div style=\"page-break-after: always\">\n</div>"));
+        assert!(is_page_break("<div style=\"break-before: page;\"></div>"));
+        assert!(!is_page_break("<div class=\"page-break\">text</div>"));
+        assert!(!is_page_break("<div class=\"note\"></div>"));
+        assert!(!is_page_break("<divx class=\"page-break\"></divx>"));
+    }
+}
@@ -1,42 +1,218 @@
 use crate::alerts::*;
-use egui::{Pos2, Vec2};
 use pulldown_cmark::Options;
+use std::collections::HashMap;
 use std::ops::Range;
 
 #[derive(Default, Debug)]
 pub struct ScrollableCache {
-    pub available_size: Vec2,
-    pub page_size: Option<Vec2>,
-    pub split_points: Vec<(usize, Pos2, Pos2)>,
-    /// Parsed pulldown events, owned (Event<'static>) so they outlive the
-    /// borrow of the source text. Repopulated only when `content_version`
-    /// changes, replacing the per-frame `Parser::new_ext(text).collect()`
-    /// that previously ran at every paint.
-    pub events: Vec<(pulldown_cmark::Event<'static>, Range<usize>)>,
+    /// Top-level blocks in document order, as measured by the last full
+    /// layout. Empty until the document has been laid out once; painting
+    /// only the blocks near the viewport needs them.
+    pub blocks: Vec<ScrollBlock>,
+    /// Content-relative y where the last block ends and the footnotes begin.
+    pub blocks_end: f32,
+    /// Content height of the last full layout, footnotes included.
+    pub content_height: f32,
+    /// Set when a block painted at a different height than it was measured
+    /// at (an image loaded, a `<details>` opened), so the next frame lays out
+    /// the document again and the recorded positions stay in sync.
+    pub needs_layout: bool,
+    /// Whether `blocks` were measured by a layout after the first one. Tables
+    /// settle their size on their second frame, so until then the measured
+    /// heights can't stand in for blocks that aren't laid out.
+    pub remeasured: bool,
+    /// Byte ranges of the text edited since `blocks` were measured, see
+    /// `CommonMarkCache::invalidate_block`. The blocks overlapping them are
+    /// laid out again, the others keep their measurements.
+    pub edited: Vec<std::ops::Range<usize>>,
+    /// A layout spread over several frames, for documents too long to lay
+    /// out in one.
+    pub progressive_layout: Option<ProgressiveLayout>,
+    /// Parsed pulldown events, shared with `CommonMarkCache`'s parsed
+    /// documents. Looked up again only when `content_version` changes, so a
+    /// static document isn't even hashed every frame.
+    pub events: crate::misc::ParsedEvents,
     /// Last content version this cache was populated for. The caller
     /// (typically a `Tab`) bumps a u64 on every load/reload; when the
-    /// renderer sees a mismatch it re-parses and clears split_points.
+    /// renderer sees a mismatch it re-parses and drops the blocks.
     pub content_version: u64,
+    /// `pulldown_cmark::Options` bits the events were parsed with, so a
+    /// viewer option that changes parsing also triggers a re-parse.
+    pub parser_options: u32,
     /// Hash of the layout-affecting context (width, font size, line height,
-    /// theme is_dark). When this changes, split_points must be cleared —
-    /// their y-positions are no longer valid for the new layout.
+    /// theme is_dark). When this changes, the measured blocks must be
+    /// dropped — their heights are no longer valid for the new layout.
     pub layout_signature: u64,
-    /// Content height as reported by the previous frame's ScrollAreaOutput.
-    pub last_content_h: f32,
-    /// Content height captured at the most recent bootstrap. Subsequent
-    /// paints compare `last_content_h` against this value with a hysteresis
-    /// threshold: only when |last - bootstrap| exceeds the threshold do we
-    /// invalidate page_size and trigger a re-bootstrap. This avoids the
-    /// known egui artifact where `ScrollArea::show` (bootstrap path) and
-    /// `ScrollArea::show_viewport` (skip-paint path) report content_size.y
-    /// differing by ~44 px (the panel chrome offset) for the same content —
-    /// without hysteresis that oscillation crosses any bucket boundary and
-    /// keeps the renderer in a perpetual bootstrap loop.
-    pub bootstrap_content_h: f32,
+    /// Anchor slug requested through `CommonMarkCache::scroll_to_heading`.
+    pub pending_anchor: Option<String>,
+    /// Whether the document has been painted since `pending_anchor` was set,
+    /// so a still-unknown anchor can be given up on.
+    pub pending_anchor_painted: bool,
+    /// Time and viewport top of the last frame, the speed the document
+    /// scrolls at in points per second and the way it was last scrolled (-1
+    /// up, 1 down, 0 not yet), for prefetching the images ahead.
+    pub last_scroll: Option<(f64, f32)>,
+    pub scroll_speed: f32,
+    pub scroll_direction: f32,
+}
+
+/// Seconds of scrolling at the current speed whose images are prefetched,
+/// on top of the next screenful, and the most screens ahead prefetched.
+const PREFETCH_SECONDS: f32 = 0.5;
+const PREFETCH_MAX_SCREENS: f32 = 3.0;
+
+/// A top-level block of a scrollable document, with what the renderer needs
+/// to start painting at it without replaying the blocks above.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScrollBlock {
+    /// Index of the block's first event.
+    pub first_event: usize,
+    /// Content-relative y of the block's top.
+    pub top: f32,
+    /// Height of the row the block starts in, and how far into it the
+    /// content above reaches. A layout resuming at the block starts with
+    /// both, since they decide where the block's first widgets go.
+    pub row_height: f32,
+    pub filled_height: f32,
+    /// Tables, `<details>` sections and code blocks above this block. Their
+    /// ids are numbered in document order.
+    pub tables: usize,
+    pub details: usize,
+    pub code_blocks: usize,
+    /// Footnote labels referenced above this block with their reference
+    /// counts, which number the footnotes.
+    pub footnote_refs: Vec<(String, usize)>,
+}
+
+/// Where a layout spread over several frames got to.
+#[derive(Debug, Default)]
+pub struct ProgressiveLayout {
+    /// The blocks measured so far. The last one hasn't been laid out yet;
+    /// the next frame starts at it.
+    pub blocks: Vec<ScrollBlock>,
+    /// Headings seen so far per slug, which tell duplicates apart.
+    pub heading_counts: HashMap<String, usize>,
+    pub anchor_slugger: crate::anchor::AnchorSlugger,
+}
+
+impl ScrollableCache {
+    /// The blocks to paint for `viewport` (in content coordinates): from the
+    /// one containing its top to the last one starting above its bottom.
+    pub fn visible_blocks(&self, viewport: egui::Rect) -> Range<usize> {
+        let first = self
+            .blocks
+            .partition_point(|block| block.top <= viewport.min.y)
+            .saturating_sub(1);
+        let last = self
+            .blocks
+            .partition_point(|block| block.top < viewport.max.y);
+        first..last.max(first)
+    }
+
+    /// Follow the viewport from frame to frame, and return the content range
+    /// ahead of it to prefetch images in: the next screenful in the
+    /// direction last scrolled, extended by how far the current speed
+    /// carries in `PREFETCH_SECONDS`.
+    pub fn prefetch_range(&mut self, viewport: egui::Rect, time: f64) -> egui::Rangef {
+        if let Some((last_time, last_top)) = self.last_scroll {
+            let moved = viewport.min.y - last_top;
+            if moved != 0.0 {
+                self.scroll_direction = moved.signum();
+            }
+            let dt = (time - last_time) as f32;
+            if dt > 0.0 {
+                // Smoothed, so one uneven frame doesn't swing the range
+                self.scroll_speed = 0.5 * self.scroll_speed + 0.5 * moved.abs() / dt;
+            }
+        }
+        self.last_scroll = Some((time, viewport.min.y));
+        let height = viewport.height();
+        let ahead = (height + self.scroll_speed * PREFETCH_SECONDS).min(height * PREFETCH_MAX_SCREENS);
+        if self.scroll_direction < 0.0 {
+            egui::Rangef::new(viewport.min.y - ahead, viewport.min.y)
+        } else {
+            egui::Rangef::new(viewport.max.y, viewport.max.y + ahead)
+        }
+    }
+
+    /// Destinations of the images in the blocks overlapping `range`.
+    pub fn image_urls_in(&self, range: egui::Rangef) -> Vec<&str> {
+        let blocks = self.visible_blocks(egui::Rect::from_x_y_ranges(0.0..=0.0, range));
+        let Some(first) = self.blocks.get(blocks.start) else {
+            return Vec::new();
+        };
+        self.events[first.first_event..self.end_event(&blocks)]
+            .iter()
+            .filter_map(|(event, _)| match event {
+                pulldown_cmark::Event::Start(pulldown_cmark::Tag::Image { dest_url, .. }) => {
+                    Some(dest_url.as_ref())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Index of the first event after `blocks`.
+    pub fn end_event(&self, blocks: &Range<usize>) -> usize {
+        self.blocks
+            .get(blocks.end)
+            .map_or(self.events.len(), |block| block.first_event)
+    }
+
+    /// Content-relative y where `blocks` end.
+    pub fn end_top(&self, blocks: &Range<usize>) -> f32 {
+        self.blocks
+            .get(blocks.end)
+            .map_or(self.blocks_end, |block| block.top)
+    }
 }
 
 pub type EventIteratorItem<'e> = (usize, (pulldown_cmark::Event<'e>, Range<usize>));
 
+/// `event` with its text borrowed instead of copied. Cached events own their
+/// strings, so cloning them to render a frame would allocate every text run
+/// and link again.
+pub fn borrowed_event<'a>(event: &'a pulldown_cmark::Event<'_>) -> pulldown_cmark::Event<'a> {
+    use pulldown_cmark::{CowStr, Event, Tag};
+
+    fn borrow<'a>(text: &'a CowStr<'_>) -> CowStr<'a> {
+        CowStr::Borrowed(text)
+    }
+
+    match event {
+        Event::Text(text) => Event::Text(borrow(text)),
+        Event::Code(text) => Event::Code(borrow(text)),
+        Event::InlineMath(text) => Event::InlineMath(borrow(text)),
+        Event::DisplayMath(text) => Event::DisplayMath(borrow(text)),
+        Event::Html(text) => Event::Html(borrow(text)),
+        Event::InlineHtml(text) => Event::InlineHtml(borrow(text)),
+        Event::FootnoteReference(label) => Event::FootnoteReference(borrow(label)),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type: *link_type,
+            dest_url: borrow(dest_url),
+            title: borrow(title),
+            id: borrow(id),
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type: *link_type,
+            dest_url: borrow(dest_url),
+            title: borrow(title),
+            id: borrow(id),
+        }),
+        event => event.clone(),
+    }
+}
+
 /// Parse events until a desired end tag is reached or no more events are found.
 /// This is needed for multiple events that must be rendered inside a single widget
 pub fn delayed_events<'e>(
@@ -102,6 +278,65 @@ pub fn delayed_events_list_item<'e>(
     total_events
 }
 
+/// Collect the markdown body of an HTML `<details>` block whose opening HTML
+/// block has just been consumed. Stops after the HTML block containing the
+/// matching `</details>`, which is dropped; nested `<details>` stay in the body.
+/// Collect the events of a blockquote whose start tag was just consumed, up to
+/// and including its own end tag; nested blockquotes are kept whole.
+pub fn delayed_events_blockquote<'e>(
+    events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
+) -> Vec<(pulldown_cmark::Event<'e>, Range<usize>)> {
+    let mut depth = 1usize;
+    let mut total_events = Vec::new();
+    for (_, (event, range)) in events {
+        match &event {
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::BlockQuote(_)) => depth += 1,
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::BlockQuote(_)) => depth -= 1,
+            _ => {}
+        }
+        total_events.push((event, range));
+        if depth == 0 {
+            break;
+        }
+    }
+    total_events
+}
+
+pub fn delayed_events_details<'e>(
+    events: &mut impl Iterator<Item = EventIteratorItem<'e>>,
+) -> Vec<(pulldown_cmark::Event<'e>, Range<usize>)> {
+    let mut depth = 1isize;
+    let mut total_events = Vec::new();
+    // Start of the HTML block currently being collected, if any.
+    let mut html_block_start = None;
+    for (_, (event, range)) in events.by_ref() {
+        match &event {
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::HtmlBlock) => {
+                html_block_start = Some(total_events.len());
+            }
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::HtmlBlock) => {
+                html_block_start = None;
+            }
+            pulldown_cmark::Event::Html(html) => {
+                depth += crate::html_details::details_depth_change(html);
+                if depth <= 0 {
+                    total_events.truncate(html_block_start.unwrap_or(total_events.len()));
+                    // Drop the rest of the closing HTML block.
+                    for (_, (event, _)) in events.by_ref() {
+                        if matches!(event, pulldown_cmark::Event::End(pulldown_cmark::TagEnd::HtmlBlock)) {
+                            break;
+                        }
+                    }
+                    return total_events;
+                }
+            }
+            _ => {}
+        }
+        total_events.push((event, range));
+    }
+    total_events
+}
+
 type Column<'e> = Vec<(pulldown_cmark::Event<'e>, Range<usize>)>;
 type Row<'e> = Vec<Column<'e>>;
 
@@ -221,14 +456,54 @@ pub fn parse_alerts<'a>(
     }
 }
 
+/// Checked and total task list items of every list that has any, keyed by
+/// the list's source start offset. Items of a nested list count towards that
+/// list only.
+pub fn task_list_progress(
+    events: &[(pulldown_cmark::Event<'_>, Range<usize>)],
+) -> HashMap<usize, (usize, usize)> {
+    let mut progress = HashMap::new();
+    // (list start, checked, total) for each open list
+    let mut open_lists: Vec<(usize, usize, usize)> = Vec::new();
+    for (event, range) in events {
+        match event {
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::List(_)) => {
+                open_lists.push((range.start, 0, 0));
+            }
+            pulldown_cmark::Event::TaskListMarker(checked) => {
+                if let Some((_, done, total)) = open_lists.last_mut() {
+                    *done += usize::from(*checked);
+                    *total += 1;
+                }
+            }
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::List(_)) => {
+                if let Some((start, done, total)) = open_lists.pop() {
+                    if total > 0 {
+                        progress.insert(start, (done, total));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    progress
+}
+
 /// Supported pulldown_cmark options
 #[inline]
 pub fn parser_options() -> Options {
-    Options::ENABLE_TABLES
-        | Options::ENABLE_TASKLISTS
-        | Options::ENABLE_STRIKETHROUGH
-        | Options::ENABLE_FOOTNOTES
-        | Options::ENABLE_DEFINITION_LIST
+    parser_options_with(&crate::misc::MarkdownExtensions::default())
+}
+
+/// Parser options with only the parser-level `extensions` enabled.
+pub fn parser_options_with(extensions: &crate::misc::MarkdownExtensions) -> Options {
+    let mut options = Options::empty();
+    options.set(Options::ENABLE_TABLES, extensions.tables);
+    options.set(Options::ENABLE_TASKLISTS, extensions.task_lists);
+    options.set(Options::ENABLE_STRIKETHROUGH, extensions.strikethrough);
+    options.set(Options::ENABLE_FOOTNOTES, extensions.footnotes);
+    options.set(Options::ENABLE_DEFINITION_LIST, extensions.definition_lists);
+    options
 }
 
 #[cfg(test)]
@@ -258,6 +533,32 @@ mod tests {
         (events, pos + 1)
     }
 
+    #[test]
+    fn delayed_events_blockquote_keeps_nested_quotes() {
+        let md = "> outer\n> > inner\n>\n> outer again\n\nafter\n";
+        let events: Vec<_> = Parser::new_ext(md, parser_options())
+            .into_offset_iter()
+            .collect();
+        let mut iter = events.into_iter().enumerate().skip(1);
+        let collected = delayed_events_blockquote(&mut iter);
+        assert!(matches!(collected.last(), Some((Event::End(TagEnd::BlockQuote(_)), _))));
+        assert!(collected.iter().any(|(e, _)| matches!(e, Event::Text(t) if &**t == "outer again")));
+        assert!(matches!(iter.next(), Some((_, (Event::Start(Tag::Paragraph), _)))));
+    }
+
+    #[test]
+    fn task_list_progress_counts_each_list_separately() {
+        let md = "- [x] a\n- [ ] b\n  - [x] c\n  - [x] d\n- plain\n\n1. one\n";
+        let events: Vec<_> = Parser::new_ext(md, parser_options())
+            .into_offset_iter()
+            .collect();
+        let progress = task_list_progress(&events);
+        assert_eq!(progress.get(&0), Some(&(1, 2)));
+        assert_eq!(progress.get(&md.find("- [x] c").unwrap()), Some(&(2, 2)));
+        // The ordered list has no checkboxes.
+        assert_eq!(progress.len(), 2);
+    }
+
     #[test]
     fn delayed_events_list_item_simple_item() {
         let md = "- alpha\n- beta\n";
@@ -344,4 +645,77 @@ mod tests {
         let collected = delayed_events_list_item(&mut iter);
         assert_eq!(collected.len(), 2);
     }
+
+    #[test]
+    fn delayed_events_details_stops_at_matching_close() {
+        let md = "<details>\n<summary>Outer</summary>\n\nBody\n\n<details>\n\nInner\n\n</details>\n\n</details>\n\nAfter\n";
+        let events: Vec<_> = Parser::new_ext(md, parser_options())
+            .into_offset_iter()
+            .map(|(e, r)| (e.into_static(), r))
+            .collect();
+        let mut iter = events.into_iter().enumerate().peekable();
+        // Skip the opening HTML block, as the renderer does before collecting.
+        for (_, (event, _)) in iter.by_ref() {
+            if matches!(event, Event::End(TagEnd::HtmlBlock)) {
+                break;
+            }
+        }
+
+        let body = delayed_events_details(&mut iter);
+        let texts: Vec<_> = body
+            .iter()
+            .filter_map(|(e, _)| match e {
+                Event::Text(t) => Some(t.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, ["Body", "Inner"]);
+        assert!(!matches!(body.last(), Some((Event::Start(Tag::HtmlBlock), _))));
+        // The outer iterator resumes after the closing `</details>` block.
+        assert!(matches!(iter.next(), Some((_, (Event::Start(Tag::Paragraph), _)))));
+    }
+
+    #[test]
+    fn images_ahead_of_the_viewport_are_found_in_the_scroll_direction() {
+        let md = "![a](a.png)\n\n![b](b.png)\n\n![c](c.png)\n";
+        let events: Vec<_> = Parser::new_ext(md, parser_options())
+            .into_offset_iter()
+            .map(|(e, r)| (e.into_static(), r))
+            .collect();
+        let starts: Vec<usize> = events
+            .iter()
+            .enumerate()
+            .filter(|(_, (e, _))| matches!(e, Event::Start(Tag::Paragraph)))
+            .map(|(i, _)| i)
+            .collect();
+        let mut sc = ScrollableCache {
+            blocks: starts
+                .iter()
+                .enumerate()
+                .map(|(n, &first_event)| ScrollBlock {
+                    first_event,
+                    top: n as f32 * 1000.0,
+                    ..Default::default()
+                })
+                .collect(),
+            blocks_end: 3000.0,
+            events: std::sync::Arc::new(events),
+            ..Default::default()
+        };
+        let viewport = |top: f32| egui::Rect::from_min_size(egui::pos2(0.0, top), egui::vec2(800.0, 500.0));
+
+        // Not scrolled yet: the next screenful below
+        let range = sc.prefetch_range(viewport(0.0), 0.0);
+        assert_eq!(range, egui::Rangef::new(500.0, 1000.0));
+        assert_eq!(sc.image_urls_in(range), ["a.png"]);
+        // Scrolling down fast reaches further, up to three screens
+        let range = sc.prefetch_range(viewport(1000.0), 0.1);
+        assert_eq!(range, egui::Rangef::new(1500.0, 3000.0));
+        assert_eq!(sc.image_urls_in(range), ["b.png", "c.png"]);
+        // Then back up, slowing down
+        let range = sc.prefetch_range(viewport(900.0), 1.1);
+        assert_eq!(sc.scroll_direction, -1.0);
+        assert!(range.max == 900.0 && range.min < 400.0);
+        assert_eq!(sc.image_urls_in(range), ["a.png"]);
+    }
 }
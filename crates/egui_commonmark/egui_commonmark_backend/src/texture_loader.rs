@@ -0,0 +1,285 @@
+//! Uploads raster images no larger than they can ever be displayed.
+//!
+//! egui's own texture loader uploads every image at its full resolution, so a
+//! 20 megapixel screenshot takes 80 MB of GPU memory even when it is shown
+//! 800 points wide. This loader is asked first: with a width limit set through
+//! [`limit_width`] it shrinks the decoded image to that many pixels before
+//! uploading it, and drops the full size decoded copy. A texture is only
+//! decoded again when a wider limit is set, e.g. after zooming in. SVGs are
+//! left to egui's loader, which rasterizes them at the size they are shown.
+//!
+//! Images marked with [`invert_lightness`] are uploaded with their lightness
+//! inverted, for dark mode. They are cached apart from the unchanged ones.
+
+use egui::load::{
+    ImagePoll, LoadError, SizeHint, SizedTexture, TextureLoadResult, TextureLoader, TexturePoll,
+};
+use egui::mutex::Mutex;
+use egui::{Color32, ColorImage, TextureHandle, TextureOptions};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+
+pub fn install_loader(ctx: &egui::Context) {
+    if !ctx.is_loader_installed(ScaledTextureLoader::ID) {
+        let loader = Arc::new(ScaledTextureLoader::default());
+        ctx.data_mut(|d| d.insert_temp(egui::Id::new(ScaledTextureLoader::ID), loader.clone()));
+        ctx.add_texture_loader(loader);
+    }
+}
+
+fn loader(ctx: &egui::Context) -> Option<Arc<ScaledTextureLoader>> {
+    ctx.data(|d| d.get_temp(egui::Id::new(ScaledTextureLoader::ID)))
+}
+
+/// Upload `uri` at most `width` pixels wide, or at full size with `None`.
+pub fn limit_width(ctx: &egui::Context, uri: &str, width: Option<u32>) {
+    let Some(loader) = loader(ctx) else {
+        return;
+    };
+    let mut limits = loader.limits.lock();
+    match width {
+        Some(width) => {
+            if limits.get(uri) != Some(&width) {
+                limits.insert(uri.to_owned(), width);
+            }
+        }
+        None => {
+            limits.remove(uri);
+        }
+    }
+}
+
+/// Upload `uri` with its lightness inverted, or unchanged again.
+pub fn invert_lightness(ctx: &egui::Context, uri: &str, invert: bool) {
+    let Some(loader) = loader(ctx) else {
+        return;
+    };
+    let mut inverted = loader.inverted.lock();
+    if invert != inverted.contains(uri) {
+        if invert {
+            inverted.insert(uri.to_owned());
+        } else {
+            inverted.remove(uri);
+        }
+    }
+}
+
+struct Entry {
+    handle: TextureHandle,
+    /// Size of the image before it was shrunk
+    source_size: egui::Vec2,
+    /// Width limit the image was shrunk to, `None` when it is full size
+    width_limit: Option<u32>,
+}
+
+impl Entry {
+    /// Whether the texture is as sharp as an image limited to `width` needs.
+    fn covers(&self, width: Option<u32>) -> bool {
+        match (self.width_limit, width) {
+            (None, _) => true,
+            (Some(limit), Some(width)) => width <= limit,
+            (Some(_), None) => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ScaledTextureLoader {
+    limits: Mutex<HashMap<String, u32>>,
+    inverted: Mutex<HashSet<String>>,
+    /// Textures by uri, options and whether their lightness is inverted
+    cache: Mutex<HashMap<(String, TextureOptions, bool), Entry>>,
+    /// GPU memory the cached textures take, reported to egui through
+    /// [`TextureLoader::byte_size`]
+    bytes: AtomicUsize,
+}
+
+impl ScaledTextureLoader {
+    pub const ID: &'static str = egui::generate_loader_id!(ScaledTextureLoader);
+}
+
+impl TextureLoader for ScaledTextureLoader {
+    fn id(&self) -> &str {
+        Self::ID
+    }
+
+    fn load(
+        &self,
+        ctx: &egui::Context,
+        uri: &str,
+        texture_options: TextureOptions,
+        size_hint: SizeHint,
+    ) -> TextureLoadResult {
+        if uri.ends_with(".svg") {
+            return Err(LoadError::NotSupported);
+        }
+        let width = self.limits.lock().get(uri).copied();
+        let invert = self.inverted.lock().contains(uri);
+        let key = (uri.to_owned(), texture_options, invert);
+        if let Some(entry) = self.cache.lock().get(&key) {
+            if entry.covers(width) {
+                let texture = SizedTexture::new(entry.handle.id(), entry.source_size);
+                return Ok(TexturePoll::Ready { texture });
+            }
+        }
+
+        let image = match ctx.try_load_image(uri, size_hint)? {
+            ImagePoll::Pending { size } => return Ok(TexturePoll::Pending { size }),
+            ImagePoll::Ready { image } => image,
+        };
+        let source_size = image.source_size;
+        let (mut image, width_limit) = match width {
+            Some(width) if image.width() > width as usize => (Arc::new(downscale(&image, width)), Some(width)),
+            _ => (image, None),
+        };
+        if invert {
+            invert_image_lightness(Arc::make_mut(&mut image));
+        }
+        let handle = ctx.load_texture(uri, image, texture_options);
+        // The texture is all that is drawn, so the decoded pixels only take
+        // memory from here on.
+        for loader in ctx.loaders().image.lock().iter() {
+            loader.forget(uri);
+        }
+
+        let texture = SizedTexture::new(handle.id(), source_size);
+        self.bytes.fetch_add(handle.byte_size(), Relaxed);
+        let entry = Entry {
+            handle,
+            source_size,
+            width_limit,
+        };
+        if let Some(old) = self.cache.lock().insert(key, entry) {
+            self.bytes.fetch_sub(old.handle.byte_size(), Relaxed);
+        }
+        Ok(TexturePoll::Ready { texture })
+    }
+
+    fn forget(&self, uri: &str) {
+        self.cache.lock().retain(|(key, _, _), entry| {
+            let keep = key != uri;
+            if !keep {
+                self.bytes.fetch_sub(entry.handle.byte_size(), Relaxed);
+            }
+            keep
+        });
+    }
+
+    fn forget_all(&self) {
+        self.cache.lock().clear();
+        self.bytes.store(0, Relaxed);
+    }
+
+    fn byte_size(&self) -> usize {
+        self.bytes.load(Relaxed)
+    }
+}
+
+/// `image` shrunk to `width` pixels wide, keeping its aspect ratio. Each
+/// pixel is the average of the pixels it covers in `image`.
+fn downscale(image: &ColorImage, width: u32) -> ColorImage {
+    let [src_width, src_height] = image.size;
+    let width = (width as usize).clamp(1, src_width.max(1));
+    let height = ((src_height * width) as f64 / src_width as f64).round().max(1.0) as usize;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let top = y * src_height / height;
+        let bottom = ((y + 1) * src_height / height).max(top + 1);
+        for x in 0..width {
+            let left = x * src_width / width;
+            let right = ((x + 1) * src_width / width).max(left + 1);
+            let mut sum = [0u32; 4];
+            for row in top..bottom {
+                let start = row * src_width;
+                for pixel in &image.pixels[start + left..start + right] {
+                    for (sum, channel) in sum.iter_mut().zip(pixel.to_array()) {
+                        *sum += u32::from(channel);
+                    }
+                }
+            }
+            let count = ((bottom - top) * (right - left)) as u32;
+            let [r, g, b, a] = sum.map(|sum| ((sum + count / 2) / count) as u8);
+            pixels.push(Color32::from_rgba_premultiplied(r, g, b, a));
+        }
+    }
+    ColorImage {
+        size: [width, height],
+        source_size: image.source_size,
+        pixels,
+    }
+}
+
+/// Turn light pixels of `image` dark and dark ones light, keeping their hue:
+/// each channel moves by how far the pixel's luma is from its opposite.
+fn invert_image_lightness(image: &mut ColorImage) {
+    for pixel in &mut image.pixels {
+        let [r, g, b, a] = pixel.to_srgba_unmultiplied();
+        let luma = 0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b);
+        let shift = 255.0 - 2.0 * luma;
+        let [r, g, b] = [r, g, b].map(|c| (f32::from(c) + shift).round().clamp(0.0, 255.0) as u8);
+        *pixel = Color32::from_rgba_unmultiplied(r, g, b, a);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscaling_averages_and_keeps_the_aspect_ratio() {
+        let pixels = (0..8 * 4)
+            .map(|i| {
+                if (i % 8) < 4 {
+                    Color32::BLACK
+                } else {
+                    Color32::WHITE
+                }
+            })
+            .collect();
+        let image = ColorImage::new([8, 4], pixels);
+        let small = downscale(&image, 2);
+        assert_eq!(small.size, [2, 1]);
+        assert_eq!(small.source_size, egui::vec2(8.0, 4.0));
+        assert_eq!(small.pixels, vec![Color32::BLACK, Color32::WHITE]);
+
+        let gray = downscale(&image, 1);
+        assert_eq!(gray.size, [1, 1]);
+        assert_eq!(gray.pixels[0], Color32::from_gray(128));
+    }
+
+    #[test]
+    fn inverting_lightness_darkens_white_and_keeps_hue() {
+        let mut image = ColorImage::new(
+            [3, 1],
+            vec![Color32::WHITE, Color32::BLACK, Color32::from_rgb(255, 0, 0)],
+        );
+        invert_image_lightness(&mut image);
+        assert_eq!(image.pixels[0], Color32::BLACK);
+        assert_eq!(image.pixels[1], Color32::WHITE);
+        let [r, g, b, _] = image.pixels[2].to_array();
+        assert!(r == 255 && g == b && g > 90);
+    }
+
+    #[test]
+    fn textures_are_uploaded_again_only_for_wider_limits() {
+        let ctx = egui::Context::default();
+        let image = ColorImage::new([4, 4], vec![Color32::RED; 16]);
+        let entry = Entry {
+            handle: ctx.load_texture("a.png", image, TextureOptions::default()),
+            source_size: egui::vec2(4.0, 4.0),
+            width_limit: Some(200),
+        };
+        assert!(entry.covers(Some(100)));
+        assert!(entry.covers(Some(200)));
+        assert!(!entry.covers(Some(300)));
+        assert!(!entry.covers(None));
+        let full = Entry {
+            width_limit: None,
+            ..entry
+        };
+        assert!(full.covers(Some(300)));
+        assert!(full.covers(None));
+    }
+}
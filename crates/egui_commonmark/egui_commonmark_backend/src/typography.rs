@@ -4,6 +4,7 @@
 
 /// Specifies a measurement that can be either a multiplier of font size or absolute pixels.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum Measurement {
     /// Multiplier relative to font size (e.g., 1.5 means 150% of font size)
     Multiplier(f32),
@@ -31,7 +32,9 @@ impl Default for Measurement {
 ///
 /// Controls line height, paragraph spacing, and heading spacing for improved readability.
 /// Based on WCAG 2.1 SC 1.4.12 guidelines and peer-reviewed HCI research.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct TypographyConfig {
     /// Line height for body text. Applied via egui's TextFormat.line_height.
     /// Research: 1.5× recommended (WCAG 2.1 SC 1.4.12, Reading University)
@@ -57,6 +60,27 @@ pub struct TypographyConfig {
     /// Research: line-height × 0.5 ≈ space below heading
     /// Default: None
     pub heading_spacing_below: Option<Measurement>,
+
+    /// Extra spacing between letters, relative to the size of the text.
+    /// Research: 0.12× recommended at the most (WCAG 2.1 SC 1.4.12)
+    /// Default: None
+    pub letter_spacing: Option<Measurement>,
+
+    /// Extra spacing between words in paragraphs and links, relative to the
+    /// body font size. Applied as leading space of the whitespace between them.
+    /// Research: 0.16× recommended at the most (WCAG 2.1 SC 1.4.12)
+    /// Default: None
+    pub word_spacing: Option<Measurement>,
+
+    /// Indentation of the first line of paragraphs outside of lists, relative
+    /// to the body font size.
+    /// Default: None
+    pub first_line_indent: Option<Measurement>,
+
+    /// Stretch the wrapped rows of paragraphs to the full width. Each run of
+    /// text in one style is justified on its own, and its last row is not.
+    /// Default: false
+    pub justify: bool,
 }
 
 impl TypographyConfig {
@@ -75,6 +99,10 @@ impl TypographyConfig {
             paragraph_spacing: Some(Measurement::Multiplier(2.0)),
             heading_spacing_above: Some(Measurement::Multiplier(2.0)),
             heading_spacing_below: Some(Measurement::Multiplier(0.75)),
+            letter_spacing: None,
+            word_spacing: None,
+            first_line_indent: None,
+            justify: false,
         }
     }
 
@@ -85,6 +113,10 @@ impl TypographyConfig {
             || self.paragraph_spacing.is_some()
             || self.heading_spacing_above.is_some()
             || self.heading_spacing_below.is_some()
+            || self.letter_spacing.is_some()
+            || self.word_spacing.is_some()
+            || self.first_line_indent.is_some()
+            || self.justify
     }
 
     /// Resolve line height to pixels given a font size.
@@ -122,4 +154,70 @@ impl TypographyConfig {
             .map(|m| m.resolve(font_size))
             .unwrap_or(0.0)
     }
+
+    /// Resolve letter spacing to pixels given a font size.
+    /// Returns 0.0 if not configured.
+    pub fn resolve_letter_spacing(&self, font_size: f32) -> f32 {
+        self.letter_spacing
+            .map(|m| m.resolve(font_size))
+            .unwrap_or(0.0)
+    }
+
+    /// Resolve first line indentation to pixels given a font size.
+    /// Returns 0.0 if not configured.
+    pub fn resolve_first_line_indent(&self, font_size: f32) -> f32 {
+        self.first_line_indent
+            .map(|m| m.resolve(font_size))
+            .unwrap_or(0.0)
+    }
+
+    /// Resolve word spacing to pixels given a font size.
+    /// Returns 0.0 if not configured.
+    pub fn resolve_word_spacing(&self, font_size: f32) -> f32 {
+        self.word_spacing
+            .map(|m| m.resolve(font_size))
+            .unwrap_or(0.0)
+    }
+}
+
+/// Add `word_spacing` pixels in front of every run of spaces in `job`,
+/// splitting its sections where needed.
+///
+/// egui has no word spacing, and letter spacing is only added between the
+/// glyphs of a section, so the runs get sections of their own with leading
+/// space. When a row wraps at a run the space stays at the end of the row.
+pub fn space_words(job: &mut egui::text::LayoutJob, word_spacing: f32) {
+    if word_spacing == 0.0 {
+        return;
+    }
+    let mut sections = Vec::with_capacity(job.sections.len());
+    for section in std::mem::take(&mut job.sections) {
+        let range = section.byte_range.clone();
+        let mut start = range.start;
+        let mut leading_space = section.leading_space;
+        let mut in_spaces = None;
+        for (i, chr) in job.text[range.clone()].char_indices() {
+            let is_space = chr == ' ';
+            if in_spaces.is_some_and(|in_spaces| in_spaces != is_space) {
+                let at = range.start + i;
+                sections.push(egui::text::LayoutSection {
+                    leading_space,
+                    byte_range: start..at,
+                    format: section.format.clone(),
+                });
+                start = at;
+                leading_space = 0.0;
+            }
+            if is_space && in_spaces != Some(true) {
+                leading_space += word_spacing;
+            }
+            in_spaces = Some(is_space);
+        }
+        sections.push(egui::text::LayoutSection {
+            leading_space,
+            byte_range: start..range.end,
+            format: section.format,
+        });
+    }
+    job.sections = sections;
 }
@@ -1,7 +1,7 @@
 use std::iter::Peekable;
 
 use egui_commonmark_backend_extended::{
-    CodeBlock, CommonMarkOptions, Image, alerts::Alert, misc::Style, pulldown::*,
+    CodeBlock, CommonMarkOptions, Image, alerts::{Alert, AlertIcon}, misc::Style, pulldown::*,
 };
 
 use proc_macro2::TokenStream;
@@ -110,14 +110,24 @@ impl List {
 
             // The compile-time macro path has no typography config; pass the raw
             // body height so marker layout is unchanged from the runtime default.
+            let markers = &options.list_markers;
+            let color = match markers.color {
+                Some(color) => {
+                    let [r, g, b, a] = color.to_array();
+                    quote!(egui::Color32::from_rgba_premultiplied(#r, #g, #b, #a))
+                }
+                None => quote!(ui.visuals().strong_text_color()),
+            };
             if let Some(number) = &mut item.current_number {
-                let num = number.to_string();
-                stream.extend(quote!( egui_commonmark_backend_extended::number_point(ui, #num, ui.text_style_height(&egui::TextStyle::Body));));
+                let num = markers.numbering(len - 1).label(*number);
+                stream.extend(quote!( egui_commonmark_backend_extended::number_point(ui, #num, ui.text_style_height(&egui::TextStyle::Body), #color);));
                 *number += 1;
+            } else if let Some(glyph) = markers.bullet(len - 1) {
+                stream.extend(quote!( egui_commonmark_backend_extended::bullet_glyph(ui, #glyph, ui.text_style_height(&egui::TextStyle::Body), #color);));
             } else if len > 1 {
-                stream.extend(quote!( egui_commonmark_backend_extended::bullet_point_hollow(ui, ui.text_style_height(&egui::TextStyle::Body));));
+                stream.extend(quote!( egui_commonmark_backend_extended::bullet_point_hollow(ui, ui.text_style_height(&egui::TextStyle::Body), #color);));
             } else {
-                stream.extend(quote!( egui_commonmark_backend_extended::bullet_point(ui, ui.text_style_height(&egui::TextStyle::Body));));
+                stream.extend(quote!( egui_commonmark_backend_extended::bullet_point(ui, ui.text_style_height(&egui::TextStyle::Body), #color);));
             }
         } else {
             unreachable!();
@@ -157,6 +167,7 @@ impl StyledText {
 
 pub struct StyledLink {
     pub destination: String,
+    pub title: String,
     pub text: Vec<StyledText>,
 }
 
@@ -387,12 +398,16 @@ impl CommonMarkViewerInternal {
 
             // See non proc macro version for reaseon
             self.line.should_not_start_newline_forced = false;
+            let start = collected_events.first().map_or(0, |(_, span)| span.start);
             if let Some(alert) = parse_alerts(&options.alerts, &mut collected_events) {
                 let Alert {
                     accent_color,
+                    background_color,
                     icon,
                     identifier,
                     identifier_rendered,
+                    show_identifier,
+                    collapsible,
                 } = alert;
 
                 let mut inner = TokenStream::new();
@@ -400,19 +415,48 @@ impl CommonMarkViewerInternal {
                     inner.extend(self.event(event, cache, options));
                 }
 
-                let r = accent_color.r();
-                let g = accent_color.g();
-                let b = accent_color.b();
-                let a = accent_color.a();
-                stream.extend(quote!(
-                egui_commonmark_backend_extended::alert_ui(&egui_commonmark_backend_extended::Alert {
-                    accent_color: egui::Color32::from_rgba_premultiplied(#r, #g, #b, #a),
+                let color = |color: &egui::Color32| {
+                    let [r, g, b, a] = color.to_array();
+                    quote!(egui::Color32::from_rgba_premultiplied(#r, #g, #b, #a))
+                };
+                let accent_color = color(accent_color);
+                let background_color = match background_color {
+                    Some(background) => {
+                        let background = color(background);
+                        quote!(Some(#background))
+                    }
+                    None => quote!(None),
+                };
+                let icon = match icon {
+                    Some(AlertIcon::Glyph(glyph)) => {
+                        quote!(Some(egui_commonmark_backend_extended::AlertIcon::Glyph(#glyph.to_owned())))
+                    }
+                    Some(AlertIcon::Image(uri)) => {
+                        quote!(Some(egui_commonmark_backend_extended::AlertIcon::Image(#uri.to_owned())))
+                    }
+                    None => quote!(None),
+                };
+                let collapsible = match collapsible {
+                    Some(open) => quote!(Some(#open)),
+                    None => quote!(None),
+                };
+                stream.extend(quote!({
+                let alert_id = ui.id().with("_alert").with(#start);
+                let open = #collapsible.map(|default: bool| (#cache).alert_open(alert_id, default));
+                let toggled = egui_commonmark_backend_extended::alert_ui(&egui_commonmark_backend_extended::Alert {
+                    accent_color: #accent_color,
+                    background_color: #background_color,
                     icon: #icon,
                     identifier: #identifier.to_owned(),
-                    identifier_rendered: #identifier_rendered.to_owned()
-                }, ui, |ui| {
+                    identifier_rendered: #identifier_rendered.to_owned(),
+                    show_identifier: #show_identifier,
+                    collapsible: #collapsible,
+                }, ui, &Default::default(), open, |ui| {
                     #inner
-                });));
+                });
+                if let Some(open) = toggled {
+                    (#cache).set_alert_open(alert_id, open);
+                }}));
             } else {
                 let mut inner = TokenStream::new();
 
@@ -605,20 +649,10 @@ impl CommonMarkViewerInternal {
                 TokenStream::new()
             }
             pulldown_cmark::Tag::CodeBlock(c) => {
-                match c {
-                    pulldown_cmark::CodeBlockKind::Fenced(lang) => {
-                        self.code_block = Some(CodeBlock {
-                            lang: Some(lang.to_string()),
-                            content: "".to_string(),
-                        });
-                    }
-                    pulldown_cmark::CodeBlockKind::Indented => {
-                        self.code_block = Some(CodeBlock {
-                            lang: None,
-                            content: "".to_string(),
-                        });
-                    }
-                }
+                self.code_block = Some(CodeBlock::new(match &c {
+                    pulldown_cmark::CodeBlockKind::Fenced(info) => Some(info),
+                    pulldown_cmark::CodeBlockKind::Indented => None,
+                }));
 
                 self.line.try_insert_start()
             }
@@ -672,9 +706,12 @@ impl CommonMarkViewerInternal {
                 self.text_style.strikethrough = true;
                 TokenStream::new()
             }
-            pulldown_cmark::Tag::Link { dest_url, .. } => {
+            pulldown_cmark::Tag::Link {
+                dest_url, title, ..
+            } => {
                 self.link = Some(StyledLink {
                     destination: dest_url.to_string(),
+                    title: title.to_string(),
                     text: Vec::new(),
                 });
                 TokenStream::new()
@@ -768,7 +805,11 @@ impl CommonMarkViewerInternal {
             }
             pulldown_cmark::TagEnd::Link => {
                 if let Some(link) = self.link.take() {
-                    let StyledLink { destination, text } = link;
+                    let StyledLink {
+                        destination,
+                        title,
+                        text,
+                    } = link;
                     let mut text_stream = TokenStream::new();
                     for text_style in text {
                         text_stream
@@ -779,8 +820,9 @@ impl CommonMarkViewerInternal {
                     quote!(
                     egui_commonmark_backend_extended::Link {
                         destination: #destination.to_owned(),
+                        title: #title.to_owned(),
                         text: vec![#text_stream]
-                    }.end(ui, #cache);)
+                    }.end(ui, #cache, &options);)
                 } else {
                     TokenStream::new()
                 }
@@ -832,12 +874,27 @@ impl CommonMarkViewerInternal {
             let content = block.content;
 
             stream.extend(if let Some(lang) = block.lang {
+                let info = block.info;
+                let starts = info.highlight_lines.iter().map(|r| *r.start());
+                let ends = info.highlight_lines.iter().map(|r| *r.end());
+                let title = match info.title {
+                    Some(title) => quote!(Some(#title.to_owned())),
+                    None => quote!(None),
+                };
                 quote!(egui_commonmark_backend_extended::CodeBlock {
-                    lang: Some(#lang.to_owned()), content: #content.to_owned()}
+                    lang: Some(#lang.to_owned()), content: #content.to_owned(),
+                    info: egui_commonmark_backend_extended::fence::FenceInfo {
+                        lang: #lang.to_owned(),
+                        title: #title,
+                        highlight_lines: vec![#(#starts..=#ends),*],
+                        attributes: vec![],
+                    },
+                    source: vec![]}
                     .end(ui, #cache, &options, max_width);)
             } else {
                 quote!(egui_commonmark_backend_extended::CodeBlock {
-                    lang: None, content: #content.to_owned()}
+                    content: #content.to_owned(),
+                    ..egui_commonmark_backend_extended::CodeBlock::new(None)}
                     .end(ui, #cache, &options, max_width);)
             });
 
@@ -52,6 +52,29 @@
 //! work around this you can use a nightly compiler and enable the
 //! `nightly` feature when iterating on your markdown files.
 //!
+//! ### Embedding a directory of markdown files
+//!
+//! commonmark_dir!(ui, cache, dir_path, name);
+//!
+//! Embeds every `.md` and `.markdown` file below `dir_path` and shows the one
+//! called `name`, which is its path relative to `dir_path` without the
+//! extension (`"intro"`, `"guide/install"`). Returns `None` when there is no
+//! such file.
+//!
+//! #### Example
+//!
+//! ```rust,ignore
+//! # use egui_commonmark_backend_extended::CommonMarkCache;
+//! # use egui_commonmark_macros_extended::commonmark_dir;
+//! # egui::__run_test_ui(|ui| {
+//! let mut cache = CommonMarkCache::default();
+//! let page = "guide/install";
+//! if commonmark_dir!(ui, &mut cache, "docs", page).is_none() {
+//!     ui.label("No such page");
+//! }
+//! # });
+//! ```
+//!
 //! ## Limitations
 //!
 //! Compared to it's runtime counterpart egui_commonmark it currently does not
@@ -102,6 +125,32 @@ impl Parse for Parameters {
     }
 }
 
+struct DirParameters {
+    ui: Expr,
+    cache: Expr,
+    dir: LitStr,
+    name: Expr,
+}
+
+impl Parse for DirParameters {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let ui: Expr = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let cache: Expr = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let dir: LitStr = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let name: Expr = input.parse()?;
+
+        Ok(DirParameters {
+            ui,
+            cache,
+            dir,
+            name,
+        })
+    }
+}
+
 fn commonmark_impl(ui: Expr, cache: Expr, text: String) -> proc_macro2::TokenStream {
     let stream = CommonMarkViewerInternal::new().show(ui, cache, &text);
 
@@ -155,6 +204,79 @@ pub fn commonmark_str(input: proc_macro::TokenStream) -> proc_macro::TokenStream
     commonmark_impl(ui, cache, md).into()
 }
 
+#[proc_macro]
+pub fn commonmark_dir(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let DirParameters {
+        ui,
+        cache,
+        dir,
+        name,
+    } = parse_macro_input!(input as DirParameters);
+
+    let root = std::path::PathBuf::from(dir.value());
+    let mut files = Vec::new();
+    if markdown_files(&root, &mut files).is_err() {
+        return quote_spanned!(dir.span()=>
+            compile_error!("Could not read markdown directory")
+        )
+        .into();
+    }
+    // Sorted so the generated code doesn't depend on the directory order
+    files.sort();
+
+    let mut arms = proc_macro2::TokenStream::new();
+    for path in files {
+        #[cfg(feature = "nightly")]
+        {
+            proc_macro::tracked_path::path(path.to_string_lossy());
+        }
+
+        let Ok(md) = std::fs::read_to_string(&path) else {
+            return quote_spanned!(dir.span()=>
+                compile_error!("Could not read markdown file")
+            )
+            .into();
+        };
+        let name = path
+            .strip_prefix(&root)
+            .unwrap_or(&path)
+            .with_extension("")
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let stream = commonmark_impl(ui.clone(), cache.clone(), md);
+        arms.extend(quote::quote!(#name => Some(#stream),));
+    }
+
+    quote::quote!(
+        match #name {
+            #arms
+            _ => None,
+        }
+    )
+    .into()
+}
+
+/// Collects the markdown files below `dir`.
+fn markdown_files(
+    dir: &std::path::Path,
+    files: &mut Vec<std::path::PathBuf>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            markdown_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "md" || extension == "markdown")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
 fn resolve_backend_crate_import() -> proc_macro2::TokenStream {
     // The purpose of this is to ensure that when used through egui_commonmark
     // the generated code can always find egui_commonmark_backend_extended without the
@@ -0,0 +1,10 @@
+use egui::__run_test_ui;
+use egui_commonmark_macros_extended::commonmark_dir;
+
+// Check that it fails to compile when it is not able to find the directory
+fn main() {
+    let mut cache = egui_commonmark_backend_extended::CommonMarkCache::default();
+    __run_test_ui(|ui| {
+        commonmark_dir!(ui, &mut cache, "docs", "intro");
+    });
+}
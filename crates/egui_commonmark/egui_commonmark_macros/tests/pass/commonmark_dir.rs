@@ -0,0 +1,25 @@
+use egui::__run_test_ui;
+use egui_commonmark_macros_extended::commonmark_dir;
+
+// Check that files are found by name, also in subdirectories
+fn main() {
+    __run_test_ui(|ui| {
+        let mut cache = egui_commonmark_backend_extended::CommonMarkCache::default();
+        for name in ["intro", "guide/install"] {
+            let response: Option<egui::InnerResponse<()>> = commonmark_dir!(
+                ui,
+                &mut cache,
+                "../../../../egui_commonmark_macros/tests/docs",
+                name
+            );
+            assert!(response.is_some());
+        }
+        let missing: Option<egui::InnerResponse<()>> = commonmark_dir!(
+            ui,
+            &mut cache,
+            "../../../../egui_commonmark_macros/tests/docs",
+            "outro"
+        );
+        assert!(missing.is_none());
+    });
+}
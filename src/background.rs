@@ -0,0 +1,216 @@
+//! Long-running work on background threads, listed in the status bar with
+//! its progress and a button to cancel it.
+//!
+//! [`Jobs::spawn`] runs the work and hands back a [`Job`] to poll for the
+//! result. The work reports how far it got and checks whether it was
+//! cancelled through the [`Progress`] it is given.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+
+use eframe::egui;
+
+/// How far a job got, shared between its thread and the UI.
+pub struct Progress {
+    label: String,
+    done: AtomicUsize,
+    /// Steps in all, 0 while unknown
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    ctx: egui::Context,
+}
+
+impl Progress {
+    pub fn new(label: impl Into<String>, ctx: egui::Context) -> Self {
+        Self {
+            label: label.into(),
+            done: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            ctx,
+        }
+    }
+
+    /// Set the number of steps the job takes, once it is known.
+    pub fn set_total(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+        self.ctx.request_repaint();
+    }
+
+    /// Count one more step as done.
+    pub fn advance(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        self.ctx.request_repaint();
+    }
+
+    /// Whether the user cancelled the job; the work should stop early.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Share of the steps done, `None` while the total is unknown.
+    pub fn fraction(&self) -> Option<f32> {
+        let total = self.total.load(Ordering::Relaxed);
+        let done = self.done.load(Ordering::Relaxed).min(total);
+        (total > 0).then(|| done as f32 / total as f32)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+/// A job started by [`Jobs::spawn`].
+pub struct Job<T> {
+    result: Receiver<T>,
+    progress: Arc<Progress>,
+}
+
+impl<T> Job<T> {
+    /// The result, once the work is done.
+    pub fn try_take(&self) -> Option<T> {
+        self.result.try_recv().ok()
+    }
+
+    /// Whether the job was cancelled; it then never has a result.
+    pub fn is_cancelled(&self) -> bool {
+        self.progress.is_cancelled()
+    }
+}
+
+/// The jobs running in the background.
+#[derive(Default)]
+pub struct Jobs {
+    running: Vec<Arc<Progress>>,
+}
+
+impl Jobs {
+    /// Run `work` on a thread named after `label`, which the status bar shows
+    /// while it runs.
+    pub fn spawn<T: Send + 'static>(
+        &mut self,
+        ctx: &egui::Context,
+        label: &str,
+        work: impl FnOnce(&Progress) -> T + Send + 'static,
+    ) -> Job<T> {
+        let progress = Arc::new(Progress::new(label, ctx.clone()));
+        let (tx, rx) = mpsc::channel();
+        let shared = progress.clone();
+        std::thread::Builder::new()
+            .name(label.to_lowercase().replace(' ', "-"))
+            .spawn(move || {
+                let result = work(&shared);
+                if !shared.is_cancelled() {
+                    let _ = tx.send(result);
+                }
+                shared.finished.store(true, Ordering::Relaxed);
+                shared.ctx.request_repaint();
+            })
+            .expect("failed to spawn background job thread");
+        self.running.push(progress.clone());
+        Job {
+            result: rx,
+            progress,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    /// Forget the jobs that finished.
+    pub fn prune(&mut self) {
+        self.running.retain(|job| !job.is_finished());
+    }
+
+    /// Share of the work of all running jobs done, for the window title;
+    /// `None` when none reports its progress.
+    pub fn overall_fraction(&self) -> Option<f32> {
+        let fractions: Vec<f32> = self
+            .running
+            .iter()
+            .filter(|job| !job.is_cancelled())
+            .filter_map(|job| job.fraction())
+            .collect();
+        (!fractions.is_empty()).then(|| fractions.iter().sum::<f32>() / fractions.len() as f32)
+    }
+
+    /// Each running job with its progress and a button cancelling it.
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        for job in self.running.iter().filter(|job| !job.is_cancelled()) {
+            ui.horizontal(|ui| {
+                match job.fraction() {
+                    Some(fraction) => {
+                        ui.add(
+                            egui::ProgressBar::new(fraction)
+                                .desired_width(80.0)
+                                .desired_height(8.0),
+                        );
+                    }
+                    None => {
+                        ui.spinner();
+                    }
+                }
+                ui.label(egui::RichText::new(&job.label).small());
+                if ui
+                    .small_button("✕")
+                    .on_hover_text(format!("Cancel {}", job.label.to_lowercase()))
+                    .clicked()
+                {
+                    job.cancel();
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_until(done: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !done() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn jobs_report_progress_and_can_be_cancelled() {
+        let ctx = egui::Context::default();
+        let mut jobs = Jobs::default();
+        let job = jobs.spawn(&ctx, "Counting", |progress| {
+            progress.set_total(4);
+            (0..4).for_each(|_| progress.advance());
+            4
+        });
+        wait_until(|| jobs.running[0].is_finished());
+        assert_eq!(job.try_take(), Some(4));
+        assert_eq!(jobs.overall_fraction(), Some(1.0));
+        jobs.prune();
+        assert!(jobs.is_empty());
+
+        let (started, release) = mpsc::channel::<()>();
+        let (go, wait) = mpsc::channel::<()>();
+        let job = jobs.spawn(&ctx, "Waiting", move |progress| {
+            let _ = started.send(());
+            let _ = wait.recv();
+            progress.is_cancelled()
+        });
+        release.recv().unwrap();
+        assert_eq!(jobs.overall_fraction(), None);
+        jobs.running[0].cancel();
+        go.send(()).unwrap();
+        wait_until(|| jobs.running[0].is_finished());
+        assert!(job.is_cancelled());
+        assert_eq!(job.try_take(), None);
+    }
+}
@@ -0,0 +1,538 @@
+//! Pandoc-style citations resolved against a bibliography.
+//!
+//! `[@key]`, `[see @key, p. 3; @other]` and in-text `@key` become author-year
+//! citations linking to a References section appended to the document. The
+//! bibliography is the BibTeX or CSL JSON file named by a `bibliography:`
+//! line in the document's front matter, or else the nearest `references.bib`,
+//! `references.json`, `bibliography.bib` or `bibliography.json` in the
+//! document's folder or one above it, shared by the workspace below it.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files looked for next to the document and in each folder above it.
+const BIBLIOGRAPHY_FILES: [&str; 4] = [
+    "references.bib",
+    "references.json",
+    "bibliography.bib",
+    "bibliography.json",
+];
+
+/// One bibliography entry, as much of it as the References section shows.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Reference {
+    /// Family names, in order
+    authors: Vec<String>,
+    title: String,
+    year: String,
+    /// Journal, book or publisher
+    container: String,
+    /// DOI link or URL
+    link: String,
+}
+
+impl Reference {
+    /// `Smith`, `Smith & Doe` or `Smith et al.`
+    fn short_authors(&self, key: &str) -> String {
+        match self.authors.as_slice() {
+            [] => key.to_string(),
+            [one] => one.clone(),
+            [one, two] => format!("{one} & {two}"),
+            [one, ..] => format!("{one} et al."),
+        }
+    }
+
+    fn year(&self) -> &str {
+        if self.year.is_empty() {
+            "n.d."
+        } else {
+            &self.year
+        }
+    }
+}
+
+/// Resolve the citations of the document at `path`, appending its
+/// References section. Documents without citations or a bibliography are
+/// returned as they are.
+pub fn process_document(path: &Path, content: String) -> String {
+    if !content.contains('@') {
+        return content;
+    }
+    let Some(bibliography) = find_bibliography(path, &content) else {
+        return content;
+    };
+    let Ok(text) = fs::read_to_string(&bibliography) else {
+        log::warn!("Failed to read bibliography {}", bibliography.display());
+        return content;
+    };
+    let references = if bibliography.extension().is_some_and(|ext| ext == "json") {
+        parse_csl_json(&text)
+    } else {
+        parse_bibtex(&text)
+    };
+    cite(&content, &references)
+}
+
+/// The bibliography named in the front matter, relative to the document, or
+/// the nearest one in its folder or above.
+fn find_bibliography(path: &Path, content: &str) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    if let Some(name) = front_matter_bibliography(content) {
+        return Some(dir.join(name));
+    }
+    dir.ancestors()
+        .flat_map(|dir| BIBLIOGRAPHY_FILES.iter().map(move |name| dir.join(name)))
+        .find(|file| file.is_file())
+}
+
+/// The `bibliography:` value of a YAML front matter block.
+fn front_matter_bibliography(content: &str) -> Option<&str> {
+    let rest = content.strip_prefix("---\n")?;
+    let end = rest.find("\n---")?;
+    rest[..end].lines().find_map(|line| {
+        let value = line.strip_prefix("bibliography:")?.trim();
+        let value = value.trim_matches(|c| c == '"' || c == '\'');
+        (!value.is_empty()).then_some(value)
+    })
+}
+
+/// Replace the citations of `content` whose keys are in `references`, outside
+/// code, and append the References section.
+fn cite(content: &str, references: &HashMap<String, Reference>) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut cited: Vec<&str> = Vec::new();
+    let mut in_fence: Option<&str> = None;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let fence = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f));
+        match (in_fence, fence) {
+            (None, Some(fence)) => in_fence = Some(fence),
+            (Some(open), Some(fence)) if open == fence => in_fence = None,
+            (None, None) => {
+                cite_line(line, references, &mut cited, &mut out);
+                continue;
+            }
+            _ => {}
+        }
+        out.push_str(line);
+    }
+    if cited.is_empty() {
+        return content.to_string();
+    }
+
+    let mut entries: Vec<(&str, &Reference)> =
+        cited.iter().map(|key| (*key, &references[*key])).collect();
+    entries.sort_by(|(a_key, a), (b_key, b)| {
+        (a.short_authors(a_key), a.year()).cmp(&(b.short_authors(b_key), b.year()))
+    });
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str("\n## References\n\n");
+    for (key, reference) in entries {
+        out.push_str(&format_reference(key, reference));
+        out.push_str("\n\n");
+    }
+    out
+}
+
+/// Replace the citations in one line outside code spans.
+fn cite_line<'r>(
+    line: &str,
+    references: &'r HashMap<String, Reference>,
+    cited: &mut Vec<&'r str>,
+    out: &mut String,
+) {
+    let mut rest = line;
+    while !rest.is_empty() {
+        // Code spans are copied as they are
+        if rest.starts_with('`') {
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let fence = &rest[..ticks];
+            let end = rest[ticks..]
+                .find(fence)
+                .map_or(rest.len(), |i| ticks + i + ticks);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        if rest.starts_with('[') {
+            if let Some((citation, len)) = bracketed_citation(rest, references, cited) {
+                out.push_str(&citation);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+        let at_word_start = out.is_empty() || out.ends_with(|c: char| c.is_whitespace());
+        if rest.starts_with('@') && at_word_start {
+            let key_len = citation_key_len(&rest[1..]);
+            if let Some((key, reference)) = references.get_key_value(&rest[1..1 + key_len]) {
+                note_cited(cited, key);
+                let authors = reference.short_authors(key);
+                out.push_str(&format!("{authors} ([{}](#references))", reference.year()));
+                rest = &rest[1 + key_len..];
+                continue;
+            }
+        }
+        let next = rest.chars().next().map_or(1, char::len_utf8);
+        out.push_str(&rest[..next]);
+        rest = &rest[next..];
+    }
+}
+
+/// A `[...]` citation at the start of `text`, rendered, and its length.
+/// `None` when it isn't a citation or cites an unknown key.
+fn bracketed_citation<'r>(
+    text: &str,
+    references: &'r HashMap<String, Reference>,
+    cited: &mut Vec<&'r str>,
+) -> Option<(String, usize)> {
+    let end = text.find(']')?;
+    let inner = &text[1..end];
+    // `[text](url)` and `[text][ref]` are links
+    if !inner.contains('@') || text[end + 1..].starts_with(['(', '[']) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    let mut keys = Vec::new();
+    for part in inner.split(';') {
+        let at = part.find('@')?;
+        let key_len = citation_key_len(&part[at + 1..]);
+        let (key, reference) = references.get_key_value(&part[at + 1..at + 1 + key_len])?;
+        keys.push(key.as_str());
+        let prefix = part[..at].trim();
+        let locator = part[at + 1 + key_len..].trim_start_matches(',').trim();
+        let mut cite = format!(
+            "[{} {}](#references)",
+            reference.short_authors(key),
+            reference.year()
+        );
+        if !prefix.is_empty() {
+            cite = format!("{prefix} {cite}");
+        }
+        if !locator.is_empty() {
+            cite = format!("{cite}, {locator}");
+        }
+        parts.push(cite);
+    }
+    for key in keys {
+        note_cited(cited, key);
+    }
+    Some((format!("({})", parts.join("; ")), end + 1))
+}
+
+fn note_cited<'r>(cited: &mut Vec<&'r str>, key: &'r str) {
+    if !cited.contains(&key) {
+        cited.push(key);
+    }
+}
+
+/// Length of the citation key at the start of `text`: letters, digits and
+/// `_:.#$%&-+?<>~/`, not ending in punctuation.
+fn citation_key_len(text: &str) -> usize {
+    let len = text
+        .find(|c: char| !(c.is_alphanumeric() || "_:.#$%&-+?<>~/".contains(c)))
+        .unwrap_or(text.len());
+    text[..len]
+        .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_')
+        .len()
+}
+
+/// `Smith, J., & Doe, A. (2020). *Title*. Journal. <https://doi.org/…>`
+fn format_reference(key: &str, reference: &Reference) -> String {
+    let authors = match reference.authors.as_slice() {
+        [] => key.to_string(),
+        [one] => one.clone(),
+        [init @ .., last] => format!("{}, & {last}", init.join(", ")),
+    };
+    let mut text = format!("{authors} ({}).", reference.year());
+    if !reference.title.is_empty() {
+        text.push_str(&format!(" *{}*.", reference.title.trim_end_matches('.')));
+    }
+    if !reference.container.is_empty() {
+        text.push_str(&format!(" {}.", reference.container.trim_end_matches('.')));
+    }
+    if !reference.link.is_empty() {
+        text.push_str(&format!(" <{}>", reference.link));
+    }
+    text
+}
+
+/// The family name of a BibTeX name: `Last, First` or `First Last`.
+fn family_name(name: &str) -> String {
+    let name = name.trim();
+    match name.split_once(',') {
+        Some((last, _)) => last.trim().to_string(),
+        None => name.rsplit(' ').next().unwrap_or(name).to_string(),
+    }
+}
+
+fn doi_link(doi: &str) -> String {
+    if doi.starts_with("http") {
+        doi.to_string()
+    } else {
+        format!("https://doi.org/{doi}")
+    }
+}
+
+/// Entries of a BibTeX file by key. `@string`, `@comment` and `@preamble`
+/// are skipped and macros are not expanded.
+fn parse_bibtex(text: &str) -> HashMap<String, Reference> {
+    let mut references = HashMap::new();
+    let mut rest = text;
+    while let Some(at) = rest.find('@') {
+        rest = &rest[at + 1..];
+        let Some(open) = rest.find(['{', '(']) else {
+            break;
+        };
+        let kind = rest[..open].trim().to_ascii_lowercase();
+        let Some(body_len) = balanced_len(&rest[open..]) else {
+            break;
+        };
+        let body = &rest[open + 1..open + body_len - 1];
+        rest = &rest[open + body_len..];
+        if matches!(kind.as_str(), "string" | "comment" | "preamble") {
+            continue;
+        }
+        let Some((key, fields)) = body.split_once(',') else {
+            continue;
+        };
+        let fields = bibtex_fields(fields);
+        let field = |name: &str| fields.get(name).cloned().unwrap_or_default();
+        let link = match fields.get("doi") {
+            Some(doi) => doi_link(doi),
+            None => field("url"),
+        };
+        let container = ["journal", "booktitle", "publisher", "howpublished"]
+            .into_iter()
+            .find_map(|name| fields.get(name).cloned())
+            .unwrap_or_default();
+        let authors = fields
+            .get("author")
+            .or_else(|| fields.get("editor"))
+            .map(|names| {
+                names
+                    .split(" and ")
+                    .filter(|name| name.trim() != "others")
+                    .map(family_name)
+                    .collect()
+            })
+            .unwrap_or_default();
+        references.insert(
+            key.trim().to_string(),
+            Reference {
+                authors,
+                title: field("title"),
+                year: field("year"),
+                container,
+                link,
+            },
+        );
+    }
+    references
+}
+
+/// Length of the `{...}` or `(...)` group at the start of `text`, brackets
+/// included.
+fn balanced_len(text: &str) -> Option<usize> {
+    let (open, close) = if text.starts_with('(') {
+        ('(', ')')
+    } else {
+        ('{', '}')
+    };
+    let mut depth = 0usize;
+    for (i, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth = depth.checked_sub(1)?;
+            if depth == 0 {
+                return Some(i + 1);
+            }
+        }
+    }
+    None
+}
+
+/// `name = {value}`, `name = "value"` and `name = 2020` fields, by lowercase
+/// name, with braces and common LaTeX escapes removed from the values.
+fn bibtex_fields(text: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    let mut rest = text;
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq]
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let value_text = rest[eq + 1..].trim_start();
+        let (value, len) = if value_text.starts_with('{') {
+            let Some(len) = balanced_len(value_text) else {
+                break;
+            };
+            (&value_text[1..len - 1], len)
+        } else if let Some(quoted) = value_text.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            (&quoted[..end], (end + 2).min(value_text.len()))
+        } else {
+            let end = value_text.find(',').unwrap_or(value_text.len());
+            (value_text[..end].trim(), end)
+        };
+        let value = value
+            .replace(['{', '}'], "")
+            .replace("\\&", "&")
+            .replace("--", "–")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        fields.insert(name, value);
+        rest = &value_text[len..];
+    }
+    fields
+}
+
+/// Entries of a CSL JSON file by `id`.
+fn parse_csl_json(text: &str) -> HashMap<String, Reference> {
+    let Ok(serde_json::Value::Array(items)) = serde_json::from_str(text) else {
+        return HashMap::new();
+    };
+    let string = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    };
+    items
+        .iter()
+        .filter_map(|item| {
+            let id = string(item.get("id")?)?;
+            let text = |name: &str| item.get(name).and_then(string).unwrap_or_default();
+            let authors = item
+                .get("author")
+                .and_then(|authors| authors.as_array())
+                .map(|authors| {
+                    authors
+                        .iter()
+                        .filter_map(|a| a.get("family").or_else(|| a.get("literal")))
+                        .filter_map(string)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let year = item
+                .pointer("/issued/date-parts/0/0")
+                .and_then(string)
+                .unwrap_or_default();
+            let link = match item.get("DOI").and_then(string) {
+                Some(doi) => doi_link(&doi),
+                None => text("URL"),
+            };
+            let container = match text("container-title") {
+                container if container.is_empty() => text("publisher"),
+                container => container,
+            };
+            Some((
+                id,
+                Reference {
+                    authors,
+                    title: text("title"),
+                    year,
+                    container,
+                    link,
+                },
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIBTEX: &str = r#"
+@string{acm = "ACM"}
+@article{knuth84,
+  author = {Donald E. Knuth},
+  title = {Literate {Programming}},
+  journal = "The Computer Journal",
+  year = 1984,
+  doi = {10.1093/comjnl/27.2.97},
+}
+@book{sicp, author = {Abelson, Harold and Sussman, Gerald Jay and others},
+  title = {Structure and Interpretation of Computer Programs}, publisher = {MIT Press}, year = {1996}}
+"#;
+
+    #[test]
+    fn bibtex_and_csl_json_entries_are_read() {
+        let references = parse_bibtex(BIBTEX);
+        assert_eq!(references.len(), 2);
+        assert_eq!(
+            references["knuth84"],
+            Reference {
+                authors: vec!["Knuth".to_string()],
+                title: "Literate Programming".to_string(),
+                year: "1984".to_string(),
+                container: "The Computer Journal".to_string(),
+                link: "https://doi.org/10.1093/comjnl/27.2.97".to_string(),
+            }
+        );
+        assert_eq!(references["sicp"].authors, ["Abelson", "Sussman"]);
+        assert_eq!(references["sicp"].container, "MIT Press");
+
+        let csl = r#"[{"id": "doe2020", "title": "A Study",
+            "author": [{"family": "Doe", "given": "Jane"}, {"literal": "W3C"}],
+            "issued": {"date-parts": [[2020, 5]]}, "container-title": "Nature",
+            "URL": "https://example.com/study"}]"#;
+        let references = parse_csl_json(csl);
+        assert_eq!(references["doe2020"].authors, ["Doe", "W3C"]);
+        assert_eq!(references["doe2020"].year, "2020");
+        assert_eq!(references["doe2020"].link, "https://example.com/study");
+    }
+
+    #[test]
+    fn citations_link_to_a_generated_references_section() {
+        let references = parse_bibtex(BIBTEX);
+        let content = "---\ntitle: x\n---\n\
+            As @knuth84 put it [see @knuth84, p. 97; @sicp].\n\
+            Not [@unknown], a [link](@knuth84), `[@sicp]` or mail me@knuth84.\n\
+            ```\n[@sicp]\n```\n";
+        let cited = cite(content, &references);
+        assert_eq!(
+            cited,
+            "---\ntitle: x\n---\n\
+            As Knuth ([1984](#references)) put it \
+            (see [Knuth 1984](#references), p. 97; [Abelson & Sussman 1996](#references)).\n\
+            Not [@unknown], a [link](@knuth84), `[@sicp]` or mail me@knuth84.\n\
+            ```\n[@sicp]\n```\n\
+            \n## References\n\n\
+            Abelson, & Sussman (1996). *Structure and Interpretation of Computer Programs*. \
+            MIT Press.\n\n\
+            Knuth (1984). *Literate Programming*. The Computer Journal. \
+            <https://doi.org/10.1093/comjnl/27.2.97>\n\n"
+        );
+
+        // Nothing cited, nothing appended
+        assert_eq!(cite("Just @someone.\n", &references), "Just @someone.\n");
+    }
+
+    #[test]
+    fn the_bibliography_comes_from_front_matter_or_the_nearest_folder() {
+        assert_eq!(
+            front_matter_bibliography("---\nbibliography: \"refs/main.bib\"\n---\n# Doc\n"),
+            Some("refs/main.bib")
+        );
+        assert_eq!(
+            front_matter_bibliography("# Doc\nbibliography: x.bib\n"),
+            None
+        );
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let docs = root.join("docs");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(root.join("references.bib"), BIBTEX).unwrap();
+        let found = find_bibliography(&docs.join("a.md"), "# A\n");
+        let processed = process_document(&docs.join("a.md"), "See [@sicp].\n".to_string());
+        assert_eq!(found, Some(root.join("references.bib")));
+        assert!(processed.starts_with("See ([Abelson & Sussman 1996](#references)).\n"));
+    }
+}
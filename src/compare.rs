@@ -0,0 +1,236 @@
+//! Block-level differences between two markdown documents, for compare tabs.
+//!
+//! Both documents are split into their top-level blocks (headings,
+//! paragraphs, lists, tables, code blocks, …) and the blocks matched up by
+//! their longest common subsequence. Blocks differing only in trailing
+//! whitespace count as the same.
+//!
+//! The blocks the documents start and end with in common are set aside, and
+//! the subsequence of the rest is found in linear space (Hirschberg's
+//! algorithm), so long documents don't need a table of every pair of blocks.
+
+use std::collections::HashMap;
+
+use pulldown_cmark::{Event, Options, Parser};
+
+/// A top-level block of a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub source: String,
+    /// 1-based source line
+    pub line: usize,
+}
+
+/// A row of a comparison, in document order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Row {
+    /// A block both documents have, as in the old one and the new one
+    Same(Block, Block),
+    /// Blocks of the old document replaced by those of the new one; either
+    /// side is empty for blocks only removed or only added
+    Changed { old: Vec<Block>, new: Vec<Block> },
+}
+
+/// The top-level blocks of `content`.
+pub fn blocks(content: &str) -> Vec<Block> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_DEFINITION_LIST
+        | Options::ENABLE_MATH
+        | Options::ENABLE_GFM
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+    let mut blocks = Vec::new();
+    let mut depth = 0usize;
+    for (event, range) in Parser::new_ext(content, options).into_offset_iter() {
+        let starts_block = match event {
+            Event::Start(_) => {
+                depth += 1;
+                depth == 1
+            }
+            Event::End(_) => {
+                depth -= 1;
+                false
+            }
+            _ => depth == 0,
+        };
+        if starts_block {
+            blocks.push(Block {
+                source: content[range.clone()].trim_end().to_string(),
+                line: line_starts.partition_point(|&start| start <= range.start),
+            });
+        }
+    }
+    blocks
+}
+
+/// The rows comparing the blocks of `old` with those of `new`.
+pub fn compare(old: &str, new: &str) -> Vec<Row> {
+    let (old, new) = (blocks(old), blocks(new));
+    // Blocks are compared by an id per distinct text
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut id = |block: &Block| -> usize {
+        let lines: Vec<&str> = block.source.lines().map(str::trim_end).collect();
+        let next = ids.len();
+        *ids.entry(lines.join("\n")).or_insert(next)
+    };
+    let old_ids: Vec<usize> = old.iter().map(&mut id).collect();
+    let new_ids: Vec<usize> = new.iter().map(&mut id).collect();
+
+    let prefix = old_ids
+        .iter()
+        .zip(&new_ids)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_ids[prefix..]
+        .iter()
+        .rev()
+        .zip(new_ids[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    common(
+        &old_ids[prefix..old_end],
+        &new_ids[prefix..new_end],
+        (prefix, prefix),
+        &mut pairs,
+    );
+    pairs.extend((0..suffix).map(|k| (old_end + k, new_end + k)));
+
+    let mut rows = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    for (old_at, new_at) in pairs {
+        removed.extend_from_slice(&old[i..old_at]);
+        added.extend_from_slice(&new[j..new_at]);
+        flush(&mut rows, &mut removed, &mut added);
+        rows.push(Row::Same(old[old_at].clone(), new[new_at].clone()));
+        (i, j) = (old_at + 1, new_at + 1);
+    }
+    removed.extend_from_slice(&old[i..]);
+    added.extend_from_slice(&new[j..]);
+    flush(&mut rows, &mut removed, &mut added);
+    rows
+}
+
+/// Push the positions of a longest common subsequence of `a` and `b` onto
+/// `pairs`, in order and offset by `at`. Splits `a` in half and `b` where
+/// the subsequences of the halves add up to the longest, keeping only a row
+/// of lengths at a time.
+fn common(a: &[usize], b: &[usize], at: (usize, usize), pairs: &mut Vec<(usize, usize)>) {
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    if a.len() == 1 {
+        if let Some(j) = b.iter().position(|&id| id == a[0]) {
+            pairs.push((at.0, at.1 + j));
+        }
+        return;
+    }
+    let mid = a.len() / 2;
+    let front = lengths(a[..mid].iter(), b.iter());
+    let back = lengths(a[mid..].iter().rev(), b.iter().rev());
+    // front[k] + back[m - k]: in common with b[..k] before and b[k..] after
+    let m = b.len();
+    let split = (0..=m)
+        .max_by_key(|&k| (front[k] + back[m - k], std::cmp::Reverse(k)))
+        .unwrap_or(0);
+    common(&a[..mid], &b[..split], at, pairs);
+    common(&a[mid..], &b[split..], (at.0 + mid, at.1 + split), pairs);
+}
+
+/// The lengths of the longest common subsequences of all of `a` with each
+/// start `b[..k]` of `b`.
+fn lengths<'a>(
+    a: impl Iterator<Item = &'a usize>,
+    b: impl Iterator<Item = &'a usize> + Clone,
+) -> Vec<u32> {
+    let mut row = vec![0u32; b.clone().count() + 1];
+    for x in a {
+        let mut diagonal = 0;
+        for (k, y) in b.clone().enumerate() {
+            let above = row[k + 1];
+            row[k + 1] = if x == y {
+                diagonal + 1
+            } else {
+                above.max(row[k])
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+/// End the run of changed blocks in `removed` and `added` with its row.
+fn flush(rows: &mut Vec<Row>, removed: &mut Vec<Block>, added: &mut Vec<Block>) {
+    if !removed.is_empty() || !added.is_empty() {
+        rows.push(Row::Changed {
+            old: std::mem::take(removed),
+            new: std::mem::take(added),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_blocks_are_grouped_between_the_same_ones() {
+        let old = "# Guide\n\nIntro.\n\n- one\n- two\n\nOld paragraph.\n\n---\n\nEnd.\n";
+        let new = "# Guide\n\nIntro.   \n\n- one\n- two\n- three\n\nNew paragraph.\n\n\
+                   Another one.\n\n---\n\nEnd.\n\n## Added\n";
+        let rows = compare(old, new);
+        let summary: Vec<(Vec<usize>, Vec<usize>)> = rows
+            .iter()
+            .map(|row| match row {
+                Row::Same(old, new) => (vec![old.line], vec![new.line]),
+                Row::Changed { old, new } => (
+                    old.iter().map(|b| b.line).collect(),
+                    new.iter().map(|b| b.line).collect(),
+                ),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (vec![1], vec![1]),
+                (vec![3], vec![3]),
+                (vec![5, 8], vec![5, 9, 11]),
+                (vec![10], vec![13]),
+                (vec![12], vec![15]),
+                (vec![], vec![17]),
+            ]
+        );
+        let Row::Changed { old, new } = &rows[2] else {
+            panic!("{:?}", rows[2]);
+        };
+        assert_eq!(old[0].source, "- one\n- two");
+        assert_eq!(new[2].source, "Another one.");
+    }
+
+    #[test]
+    fn the_longest_run_in_common_is_kept_between_moved_blocks() {
+        let ids = |text: &str| -> Vec<usize> { text.bytes().map(usize::from).collect() };
+        let mut pairs = Vec::new();
+        common(&ids("abcbdab"), &ids("bdcaba"), (0, 0), &mut pairs);
+        assert_eq!(pairs.len(), 4);
+        assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+
+        let old: String = (0..300).map(|i| format!("Block {i}.\n\n")).collect();
+        let new = old.replace("Block 150.", "Moved.") + "Block 150.\n";
+        let rows = compare(&old, &new);
+        assert_eq!(rows.len(), 301);
+        let sizes = |row: &Row| match row {
+            Row::Same(..) => None,
+            Row::Changed { old, new } => Some((old.len(), new.len())),
+        };
+        assert_eq!(sizes(&rows[150]), Some((1, 1)));
+        assert_eq!(sizes(&rows[300]), Some((0, 1)));
+    }
+}
@@ -0,0 +1,128 @@
+//! Alert types besides GitHub's, defined in `alerts.json` in the config folder
+//! (`~/.config/md-viewer/alerts.json` by default):
+//!
+//! ```json
+//! [
+//!   { "identifier": "DANGER", "title": "Danger", "icon": "☠", "color": "#d1242f" },
+//!   { "identifier": "EXAMPLE", "color": "#8250df", "background": "#8250df20" }
+//! ]
+//! ```
+//!
+//! `> [!DANGER]` then renders like the built-in alerts. The title defaults to
+//! the identifier in title case, and an entry with a GitHub identifier such
+//! as `NOTE` replaces that alert.
+
+use std::path::PathBuf;
+
+use eframe::egui::Color32;
+use egui_commonmark_extended::{Alert, AlertBundle};
+use serde::Deserialize;
+
+/// An alert type as written in the config file.
+#[derive(Deserialize)]
+struct AlertConfig {
+    identifier: String,
+    title: Option<String>,
+    /// Text such as an emoji shown before the title
+    icon: Option<String>,
+    /// Hex color of the bar and the title
+    color: String,
+    /// Hex color filled behind the alert
+    background: Option<String>,
+}
+
+/// The built-in alerts with the ones from the config file added, for
+/// `language`.
+pub fn bundle(language: &str) -> AlertBundle {
+    let mut bundle = AlertBundle::gfm_localized(language);
+    for alert in load() {
+        bundle.insert(alert);
+    }
+    bundle
+}
+
+/// `$XDG_CONFIG_HOME/md-viewer/alerts.json`, or `~/.config/md-viewer/alerts.json`.
+fn config_file() -> Option<PathBuf> {
+    crate::config_dir().map(|dir| dir.join("alerts.json"))
+}
+
+/// The alerts defined in the config file. A missing file defines none, and a
+/// file that can't be read is logged and ignored.
+fn load() -> Vec<Alert> {
+    let Some(path) = config_file().filter(|path| path.exists()) else {
+        return Vec::new();
+    };
+    let parsed = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| parse(&json));
+    parsed.unwrap_or_else(|e| {
+        log::warn!("Ignoring custom alerts in {}: {e}", path.display());
+        Vec::new()
+    })
+}
+
+fn parse(json: &str) -> Result<Vec<Alert>, String> {
+    let configs: Vec<AlertConfig> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    configs.into_iter().map(AlertConfig::into_alert).collect()
+}
+
+impl AlertConfig {
+    fn into_alert(self) -> Result<Alert, String> {
+        let identifier = self.identifier.trim().to_uppercase();
+        if identifier.is_empty() || identifier.contains([' ', '[', ']', '!']) {
+            return Err(format!("invalid identifier {:?}", self.identifier));
+        }
+        let color = |hex: &str| {
+            Color32::from_hex(hex).map_err(|_| format!("invalid color {hex:?} for {identifier}"))
+        };
+        let title = self.title.unwrap_or_else(|| {
+            let mut chars = identifier.chars();
+            chars
+                .next()
+                .into_iter()
+                .chain(chars.flat_map(char::to_lowercase))
+                .collect()
+        });
+        let mut alert = Alert::new(identifier.clone(), title, color(&self.color)?);
+        if let Some(icon) = self.icon.filter(|icon| !icon.is_empty()) {
+            alert = alert.icon(icon.as_str());
+        }
+        if let Some(background) = self.background {
+            alert = alert.background_color(color(&background)?);
+        }
+        Ok(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui_commonmark_extended::AlertIcon;
+
+    #[test]
+    fn alerts_are_read_from_json() {
+        let alerts = parse(
+            r##"[
+                { "identifier": "danger", "icon": "☠", "color": "#d1242f" },
+                { "identifier": "EXAMPLE", "title": "For example", "color": "#8250df",
+                  "background": "#8250df20" }
+            ]"##,
+        )
+        .unwrap();
+        let danger = &alerts[0];
+        assert_eq!(danger.identifier, "DANGER");
+        assert_eq!(danger.identifier_rendered, "Danger");
+        assert_eq!(danger.icon, Some(AlertIcon::Glyph("☠".to_string())));
+        assert_eq!(danger.accent_color, Color32::from_rgb(0xd1, 0x24, 0x2f));
+        assert_eq!(danger.background_color, None);
+        let example = &alerts[1];
+        assert_eq!(example.identifier_rendered, "For example");
+        assert_eq!(
+            example.background_color,
+            Some(Color32::from_rgba_unmultiplied(0x82, 0x50, 0xdf, 0x20))
+        );
+
+        assert!(parse(r#"[{ "identifier": "X", "color": "red" }]"#).is_err());
+        assert!(parse(r##"[{ "identifier": "[!X]", "color": "#fff" }]"##).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Figures and tables of a markdown document, for the Figures and Tables
+//! panel.
+//!
+//! A figure is an image standing alone in its paragraph, captioned by its
+//! title or else its alt text, the way Pandoc makes figures. A table is
+//! captioned by a `Table: …` or Pandoc-style `: …` paragraph right before or
+//! after it, or else by its header row.
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Figure,
+    Table,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Figure => "Figure",
+            Kind::Table => "Table",
+        }
+    }
+}
+
+/// A captioned figure or a table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Figure {
+    pub kind: Kind,
+    /// 1-based among those of its kind, in document order
+    pub number: usize,
+    pub caption: String,
+    /// 1-based source line
+    pub line: usize,
+}
+
+/// A paragraph being read: its line, its text outside images and the
+/// captions of the images in it.
+struct Paragraph {
+    line: usize,
+    text: String,
+    images: Vec<String>,
+}
+
+/// The captioned figures and the tables of `content`, in document order.
+pub fn find_figures(content: &str) -> Vec<Figure> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset);
+
+    let mut figures: Vec<Figure> = Vec::new();
+    let mut paragraph: Option<Paragraph> = None;
+    // Title and alt text of the image being read
+    let mut image: Option<(String, String)> = None;
+    // Header cells of the table being read, while in its header row
+    let mut header: Option<Vec<String>> = None;
+    let mut table_line = 0;
+    // A caption paragraph just read, for a table right after it
+    let mut caption_before: Option<String> = None;
+    // A table captioned by its header row, for a caption paragraph right after it
+    let mut uncaptioned: Option<usize> = None;
+    let push = |figures: &mut Vec<Figure>, kind: Kind, caption: String, line: usize| {
+        let number = figures.iter().filter(|f| f.kind == kind).count() + 1;
+        figures.push(Figure {
+            kind,
+            number,
+            caption,
+            line,
+        });
+    };
+
+    for (event, range) in Parser::new_ext(content, Options::ENABLE_TABLES).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                paragraph = Some(Paragraph {
+                    line: line_of(range.start),
+                    text: String::new(),
+                    images: Vec::new(),
+                });
+            }
+            Event::End(TagEnd::Paragraph) => {
+                let Some(p) = paragraph.take() else {
+                    continue;
+                };
+                let caption = table_caption(&p.text);
+                if let (Some(caption), Some(i)) = (&caption, uncaptioned.take()) {
+                    figures[i].caption = caption.clone();
+                    continue;
+                }
+                match &p.images[..] {
+                    [caption] if p.text.trim().is_empty() && !caption.is_empty() => {
+                        push(&mut figures, Kind::Figure, caption.clone(), p.line);
+                    }
+                    _ => {}
+                }
+                caption_before = caption;
+            }
+            Event::Start(Tag::Image { title, .. }) => {
+                image = Some((title.to_string(), String::new()))
+            }
+            Event::End(TagEnd::Image) => {
+                let (Some((title, alt)), Some(p)) = (image.take(), &mut paragraph) else {
+                    continue;
+                };
+                let caption = if title.trim().is_empty() { alt } else { title };
+                p.images.push(caption.trim().to_string());
+            }
+            Event::Start(Tag::Table(_)) => table_line = line_of(range.start),
+            Event::Start(Tag::TableHead) => header = Some(Vec::new()),
+            Event::Start(Tag::TableCell) => {
+                if let Some(cells) = &mut header {
+                    cells.push(String::new());
+                }
+            }
+            Event::End(TagEnd::TableHead) => {
+                let cells = header.take().unwrap_or_default();
+                let caption = match caption_before.take() {
+                    Some(caption) => caption,
+                    None => {
+                        uncaptioned = Some(figures.len());
+                        let cells: Vec<&str> = cells.iter().map(|c| c.trim()).collect();
+                        cells.join(" | ")
+                    }
+                };
+                push(&mut figures, Kind::Table, caption, table_line);
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, alt)) = &mut image {
+                    alt.push_str(&text);
+                } else if let Some(cell) = header.as_mut().and_then(|cells| cells.last_mut()) {
+                    cell.push_str(&text);
+                } else if let Some(p) = &mut paragraph {
+                    p.text.push_str(&text);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                if let Some(p) = &mut paragraph {
+                    p.text.push(' ');
+                }
+            }
+            // Any other block comes between a caption and its table
+            Event::Start(
+                Tag::Heading { .. }
+                | Tag::BlockQuote(_)
+                | Tag::CodeBlock(_)
+                | Tag::HtmlBlock
+                | Tag::List(_)
+                | Tag::FootnoteDefinition(_),
+            )
+            | Event::Rule => {
+                caption_before = None;
+                uncaptioned = None;
+            }
+            _ => {}
+        }
+    }
+    figures
+}
+
+/// The caption in a `Table: …`, `Table 2. …` or `: …` paragraph.
+fn table_caption(text: &str) -> Option<String> {
+    let text = text.trim();
+    let rest = text.strip_prefix(':').or_else(|| {
+        let rest = text
+            .strip_prefix("Table")?
+            .trim_start_matches(|c: char| c.is_ascii_digit() || c == ' ');
+        rest.strip_prefix(':').or_else(|| rest.strip_prefix('.'))
+    })?;
+    Some(rest.trim().to_string()).filter(|caption| !caption.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captioned_images_and_tables_are_numbered() {
+        let content = "# Doc\n\n![A *cat*](cat.png)\n\nText with ![inline](x.png) image.\n\n\
+                       ![](no-caption.png)\n\n[![Linked](dog.png \"The dog\")](dog.html)\n\n\
+                       Table: Prices\n\n| Item | Cost |\n|---|---|\n| Tea | 2 |\n\n\
+                       | Name | Age |\n|---|---|\n| Ann | 3 |\n\n: People\n\n\
+                       | Plain | Header |\n|---|---|\n\n## Next\n\nTable 3 shows more.\n";
+        let figures = find_figures(content);
+        let found: Vec<(Kind, usize, &str, usize)> = figures
+            .iter()
+            .map(|f| (f.kind, f.number, f.caption.as_str(), f.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Kind::Figure, 1, "A cat", 3),
+                (Kind::Figure, 2, "The dog", 9),
+                (Kind::Table, 1, "Prices", 13),
+                (Kind::Table, 2, "People", 17),
+                (Kind::Table, 3, "Plain | Header", 23),
+            ]
+        );
+    }
+}
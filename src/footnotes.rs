@@ -0,0 +1,110 @@
+//! Footnotes of a markdown document, for the Footnotes panel.
+//!
+//! Each footnote is listed with its text, the line it is defined on and the
+//! lines referring to it, so the panel can jump to either end and tell which
+//! footnotes are referred to from the part of the document on screen.
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+/// A footnote definition and where it is referred to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Footnote {
+    pub label: String,
+    /// Plain text of the definition
+    pub text: String,
+    /// 1-based source line of the definition
+    pub line: usize,
+    /// 1-based source lines of the references, in document order
+    pub references: Vec<usize>,
+}
+
+impl Footnote {
+    /// Whether it is referred to between `first` and `last`, inclusive.
+    pub fn is_referenced_within(&self, first: usize, last: usize) -> bool {
+        self.references
+            .iter()
+            .any(|line| (first..=last).contains(line))
+    }
+}
+
+/// The footnotes defined in `content`, ordered by their first reference like
+/// the rendered notes, with unreferenced ones last.
+pub fn find_footnotes(content: &str) -> Vec<Footnote> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset);
+
+    let mut footnotes: Vec<Footnote> = Vec::new();
+    let mut references: Vec<(String, usize)> = Vec::new();
+    let mut open: Option<usize> = None;
+    let parser = Parser::new_ext(content, Options::ENABLE_FOOTNOTES).into_offset_iter();
+    for (event, range) in parser {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                open = Some(footnotes.len());
+                footnotes.push(Footnote {
+                    label: label.to_string(),
+                    text: String::new(),
+                    line: line_of(range.start),
+                    references: Vec::new(),
+                });
+            }
+            Event::End(TagEnd::FootnoteDefinition) => open = None,
+            Event::FootnoteReference(label) => {
+                references.push((label.to_string(), line_of(range.start)));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(i) = open {
+                    footnotes[i].text.push_str(&text);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak | Event::End(TagEnd::Paragraph) => {
+                if let Some(note) = open.map(|i| &mut footnotes[i]) {
+                    if !note.text.ends_with(' ') {
+                        note.text.push(' ');
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for footnote in &mut footnotes {
+        footnote.text.truncate(footnote.text.trim_end().len());
+    }
+    for (label, line) in references {
+        if let Some(footnote) = footnotes.iter_mut().find(|f| f.label == label) {
+            footnote.references.push(line);
+        }
+    }
+    footnotes.sort_by_key(|f| f.references.first().copied().unwrap_or(usize::MAX));
+    footnotes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn footnotes_know_their_definition_and_references() {
+        let content = "Intro.[^b]\n\nMore[^a] and[^b].\n\n\
+                       [^a]: First *note*\n    continued.\n\n\
+                       [^b]: Second with `code`.\n\n[^unused]: Never cited.\n";
+        let found = find_footnotes(content);
+        let summary: Vec<(&str, &str, usize, &[usize])> = found
+            .iter()
+            .map(|f| (f.label.as_str(), f.text.as_str(), f.line, &f.references[..]))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("b", "Second with code.", 8, &[1, 3][..]),
+                ("a", "First note continued.", 5, &[3][..]),
+                ("unused", "Never cited.", 10, &[][..]),
+            ]
+        );
+        assert!(found[0].is_referenced_within(2, 4));
+        assert!(!found[1].is_referenced_within(4, 9));
+    }
+}
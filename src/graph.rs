@@ -0,0 +1,221 @@
+//! The link graph of the documents under the explorer root.
+//!
+//! Every markdown file is a node and every relative link from one to another
+//! an edge. Nodes are laid out by a force-directed simulation: they push each
+//! other apart, edges pull linked ones together and a weak pull toward the
+//! middle keeps unlinked ones in view. The simulation cools down step by step
+//! until the nodes stop moving.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eframe::egui;
+
+use crate::background::Progress;
+
+/// Most documents put on the graph, as laying it out takes a pass over every
+/// pair of nodes.
+const GRAPH_FILE_LIMIT: usize = 500;
+
+/// How much of the temperature a step keeps.
+const COOLING: f32 = 0.95;
+
+/// A document on the graph.
+pub struct Node {
+    pub path: PathBuf,
+    pub pos: egui::Pos2,
+}
+
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    /// Links between nodes, from the linking one to the linked one; each
+    /// pair once
+    pub edges: Vec<(usize, usize)>,
+    /// How far a node can move in a step
+    temperature: f32,
+}
+
+impl Graph {
+    /// The graph of `files`, linked as `links` gives the files each one links
+    /// to. Links to files that aren't among them are left out.
+    pub fn new(files: Vec<PathBuf>, links: impl Fn(&Path) -> Vec<PathBuf>) -> Self {
+        let index: HashMap<&Path, usize> = files
+            .iter()
+            .enumerate()
+            .map(|(i, path)| (path.as_path(), i))
+            .collect();
+        let mut edges = HashSet::new();
+        for (from, path) in files.iter().enumerate() {
+            for target in links(path) {
+                if let Some(&to) = index.get(target.as_path()) {
+                    if to != from {
+                        edges.insert((from, to));
+                    }
+                }
+            }
+        }
+        let mut edges: Vec<(usize, usize)> = edges.into_iter().collect();
+        edges.sort_unstable();
+        // Start on a sunflower spiral so no two nodes sit on each other
+        let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        let nodes: Vec<Node> = files
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let radius = (i as f32 + 0.5).sqrt();
+                let angle = i as f32 * golden_angle;
+                Node {
+                    path,
+                    pos: egui::pos2(radius * angle.cos(), radius * angle.sin()),
+                }
+            })
+            .collect();
+        let temperature = (nodes.len() as f32).sqrt() / 2.0;
+        Self {
+            nodes,
+            edges,
+            temperature,
+        }
+    }
+
+    /// Replace the links of the document at `path`, after it changed.
+    /// Returns false when it isn't on the graph.
+    pub fn set_links(&mut self, path: &Path, links: &[PathBuf]) -> bool {
+        let Some(from) = self.index_of(path) else {
+            return false;
+        };
+        self.edges.retain(|&(source, _)| source != from);
+        for target in links {
+            if let Some(to) = self.index_of(target) {
+                if to != from && !self.edges.contains(&(from, to)) {
+                    self.edges.push((from, to));
+                }
+            }
+        }
+        self.edges.sort_unstable();
+        // Let the nodes settle into the new links
+        self.temperature = self.temperature.max(0.5);
+        true
+    }
+
+    pub fn index_of(&self, path: &Path) -> Option<usize> {
+        self.nodes.iter().position(|node| node.path == path)
+    }
+
+    /// The nodes `node` links to or is linked from.
+    pub fn neighbors(&self, node: usize) -> HashSet<usize> {
+        self.edges
+            .iter()
+            .filter_map(|&(from, to)| {
+                if from == node {
+                    Some(to)
+                } else if to == node {
+                    Some(from)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Move the nodes one step of the simulation. Returns false once they
+    /// have settled.
+    pub fn step(&mut self) -> bool {
+        if self.temperature < 0.01 || self.nodes.is_empty() {
+            return false;
+        }
+        // Linked nodes end up about a unit apart
+        let mut moves = vec![egui::Vec2::ZERO; self.nodes.len()];
+        for i in 0..self.nodes.len() {
+            for j in i + 1..self.nodes.len() {
+                let delta = self.nodes[i].pos - self.nodes[j].pos;
+                let distance = delta.length().max(0.01);
+                let push = delta / distance / distance;
+                moves[i] += push;
+                moves[j] -= push;
+            }
+        }
+        for &(from, to) in &self.edges {
+            let delta = self.nodes[from].pos - self.nodes[to].pos;
+            let pull = delta * delta.length();
+            moves[from] -= pull;
+            moves[to] += pull;
+        }
+        for (node, step) in self.nodes.iter_mut().zip(moves) {
+            let step = step - node.pos.to_vec2() * 0.1;
+            let length = step.length();
+            if length > 0.0 {
+                node.pos += step / length * length.min(self.temperature);
+            }
+        }
+        self.temperature *= COOLING;
+        true
+    }
+}
+
+/// The graph of the markdown files under `root`.
+pub fn scan(root: &Path, progress: &Progress) -> Graph {
+    let files: Vec<PathBuf> = crate::markdown_files_under(root, GRAPH_FILE_LIMIT, progress)
+        .into_iter()
+        .map(|path| path.canonicalize().unwrap_or(path))
+        .collect();
+    progress.set_total(files.len());
+    Graph::new(files, |path| {
+        if progress.is_cancelled() {
+            return Vec::new();
+        }
+        progress.advance();
+        fs::read_to_string(path)
+            .map(|content| links(&content, path))
+            .unwrap_or_default()
+    })
+}
+
+/// The documents the document at `path` with `content` links to, relative
+/// to its folder.
+pub fn links(content: &str, path: &Path) -> Vec<PathBuf> {
+    let folder = path.parent().unwrap_or(Path::new(""));
+    crate::parse_local_links(content)
+        .into_iter()
+        .filter(|link| !link.starts_with(['#', '/']))
+        .filter_map(|link| {
+            let file = link
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .replace("%20", " ");
+            folder.join(file).canonicalize().ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linked_documents_settle_closer_than_unlinked_ones() {
+        let files: Vec<PathBuf> = ["a.md", "b.md", "c.md", "d.md"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let mut graph = Graph::new(files, |path| match path.to_str() {
+            Some("a.md") => vec!["b.md".into(), "b.md".into(), "a.md".into()],
+            Some("b.md") => vec!["c.md".into(), "elsewhere.md".into()],
+            _ => Vec::new(),
+        });
+        assert_eq!(graph.edges, vec![(0, 1), (1, 2)]);
+        assert_eq!(graph.neighbors(1), HashSet::from([0, 2]));
+        assert!(graph.neighbors(3).is_empty());
+
+        while graph.step() {}
+        let distance = |a: usize, b: usize| graph.nodes[a].pos.distance(graph.nodes[b].pos);
+        assert!(distance(0, 1) < distance(0, 3));
+        assert!(distance(1, 2) < distance(2, 3));
+
+        assert!(graph.set_links(Path::new("d.md"), &["a.md".into()]));
+        assert_eq!(graph.neighbors(3), HashSet::from([0]));
+        assert!(!graph.set_links(Path::new("elsewhere.md"), &[]));
+    }
+}
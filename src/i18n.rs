@@ -0,0 +1,456 @@
+//! Translations of the menu bar, and the language alerts are shown in.
+//!
+//! Strings are looked up by their English text, so a string without a
+//! translation stays English. The language comes from the locale unless one
+//! is picked in View → Language.
+
+/// Languages that can be picked, besides English, with their own names.
+/// Alerts are translated to all of them, menus to those in [`MENU_LANGUAGES`].
+pub const LANGUAGES: &[(&str, &str)] = &[
+    ("de", "Deutsch"),
+    ("es", "Español"),
+    ("fr", "Français"),
+    ("it", "Italiano"),
+    ("ja", "日本語"),
+    ("ko", "한국어"),
+    ("nl", "Nederlands"),
+    ("pl", "Polski"),
+    ("pt", "Português"),
+    ("ru", "Русский"),
+    ("tr", "Türkçe"),
+    ("zh", "中文"),
+];
+
+/// Languages of the columns of [`MENU_STRINGS`].
+const MENU_LANGUAGES: [&str; 3] = ["de", "es", "fr"];
+
+const MENU_STRINGS: &[(&str, [&str; 3])] = &[
+    ("File", ["Datei", "Archivo", "Fichier"]),
+    (
+        "New Tab...",
+        ["Neuer Tab...", "Nueva pestaña...", "Nouvel onglet..."],
+    ),
+    (
+        "New Tab from Clipboard",
+        [
+            "Neuer Tab aus Zwischenablage",
+            "Nueva pestaña desde el portapapeles",
+            "Nouvel onglet depuis le presse-papiers",
+        ],
+    ),
+    (
+        "Compare Files...",
+        [
+            "Dateien vergleichen...",
+            "Comparar archivos...",
+            "Comparer des fichiers...",
+        ],
+    ),
+    (
+        "Save As...",
+        [
+            "Speichern unter...",
+            "Guardar como...",
+            "Enregistrer sous...",
+        ],
+    ),
+    (
+        "Open Folder...",
+        [
+            "Ordner öffnen...",
+            "Abrir carpeta...",
+            "Ouvrir un dossier...",
+        ],
+    ),
+    (
+        "Close Tab",
+        ["Tab schließen", "Cerrar pestaña", "Fermer l'onglet"],
+    ),
+    (
+        "Open in Editor",
+        [
+            "Im Editor öffnen",
+            "Abrir en el editor",
+            "Ouvrir dans l'éditeur",
+        ],
+    ),
+    (
+        "Editor Command",
+        [
+            "Editor-Befehl",
+            "Comando del editor",
+            "Commande de l'éditeur",
+        ],
+    ),
+    (
+        "Export Site...",
+        [
+            "Website exportieren...",
+            "Exportar sitio...",
+            "Exporter le site...",
+        ],
+    ),
+    (
+        "Export Contents",
+        [
+            "Inhaltsverzeichnis beim Export",
+            "Índice al exportar",
+            "Sommaire à l'export",
+        ],
+    ),
+    ("Find...", ["Suchen...", "Buscar...", "Rechercher..."]),
+    (
+        "Command Palette...",
+        [
+            "Befehlspalette...",
+            "Paleta de comandos...",
+            "Palette de commandes...",
+        ],
+    ),
+    (
+        "Allow Running Code Blocks",
+        [
+            "Ausführen von Codeblöcken erlauben",
+            "Permitir ejecutar bloques de código",
+            "Autoriser l'exécution des blocs de code",
+        ],
+    ),
+    (
+        "Restrict Untrusted Documents",
+        [
+            "Nicht vertrauenswürdige Dokumente einschränken",
+            "Restringir documentos no confiables",
+            "Restreindre les documents non fiables",
+        ],
+    ),
+    (
+        "Trusted Folders",
+        [
+            "Vertrauenswürdige Ordner",
+            "Carpetas de confianza",
+            "Dossiers de confiance",
+        ],
+    ),
+    (
+        "Rust Runner",
+        ["Rust-Ausführung", "Ejecutor de Rust", "Exécuteur Rust"],
+    ),
+    (
+        "Watch Files",
+        [
+            "Dateien überwachen",
+            "Vigilar archivos",
+            "Surveiller les fichiers",
+        ],
+    ),
+    (
+        "Watch Mode",
+        [
+            "Überwachungsmodus",
+            "Modo de vigilancia",
+            "Mode de surveillance",
+        ],
+    ),
+    ("Quit", ["Beenden", "Salir", "Quitter"]),
+    ("Navigate", ["Navigieren", "Navegar", "Navigation"]),
+    ("← Back", ["← Zurück", "← Atrás", "← Précédent"]),
+    ("→ Forward", ["→ Vorwärts", "→ Adelante", "→ Suivant"]),
+    ("View", ["Ansicht", "Ver", "Affichage"]),
+    (
+        "☀ Light Mode",
+        ["☀ Heller Modus", "☀ Modo claro", "☀ Mode clair"],
+    ),
+    (
+        "🌙 Dark Mode",
+        ["🌙 Dunkler Modus", "🌙 Modo oscuro", "🌙 Mode sombre"],
+    ),
+    (
+        "Follow System Theme",
+        [
+            "Systemdesign folgen",
+            "Seguir el tema del sistema",
+            "Suivre le thème du système",
+        ],
+    ),
+    (
+        "Show Explorer",
+        [
+            "Explorer anzeigen",
+            "Mostrar explorador",
+            "Afficher l'explorateur",
+        ],
+    ),
+    (
+        "Show Outline",
+        ["Gliederung anzeigen", "Mostrar esquema", "Afficher le plan"],
+    ),
+    (
+        "Show Tasks",
+        ["Aufgaben anzeigen", "Mostrar tareas", "Afficher les tâches"],
+    ),
+    (
+        "Show Problems",
+        [
+            "Probleme anzeigen",
+            "Mostrar problemas",
+            "Afficher les problèmes",
+        ],
+    ),
+    (
+        "Show Footnotes",
+        [
+            "Fußnoten anzeigen",
+            "Mostrar notas al pie",
+            "Afficher les notes",
+        ],
+    ),
+    (
+        "Show Figures and Tables",
+        [
+            "Abbildungen und Tabellen anzeigen",
+            "Mostrar figuras y tablas",
+            "Afficher les figures et tableaux",
+        ],
+    ),
+    (
+        "Show Link Graph",
+        [
+            "Linkgraph anzeigen",
+            "Mostrar grafo de enlaces",
+            "Afficher le graphe des liens",
+        ],
+    ),
+    (
+        "Full Width",
+        ["Volle Breite", "Ancho completo", "Pleine largeur"],
+    ),
+    (
+        "Content Width",
+        ["Inhaltsbreite", "Ancho del contenido", "Largeur du contenu"],
+    ),
+    (
+        "Center Content",
+        [
+            "Inhalt zentrieren",
+            "Centrar contenido",
+            "Centrer le contenu",
+        ],
+    ),
+    (
+        "Source Line Numbers",
+        [
+            "Quellzeilennummern",
+            "Números de línea del código",
+            "Numéros de ligne source",
+        ],
+    ),
+    (
+        "Color Swatches",
+        ["Farbmuster", "Muestras de color", "Échantillons de couleur"],
+    ),
+    (
+        "Copy Buttons on Paragraphs",
+        [
+            "Kopierknöpfe an Absätzen",
+            "Botones de copia en párrafos",
+            "Boutons de copie sur les paragraphes",
+        ],
+    ),
+    (
+        "Email Quote Style",
+        [
+            "E-Mail-Zitatstil",
+            "Estilo de citas de correo",
+            "Style de citation d'e-mail",
+        ],
+    ),
+    (
+        "Equation Numbers",
+        [
+            "Gleichungsnummern",
+            "Numeración de ecuaciones",
+            "Numéros d'équation",
+        ],
+    ),
+    (
+        "Number Headings",
+        [
+            "Überschriften nummerieren",
+            "Numerar encabezados",
+            "Numéroter les titres",
+        ],
+    ),
+    (
+        "Only Tasks",
+        ["Nur Aufgaben", "Solo tareas", "Tâches uniquement"],
+    ),
+    (
+        "View Source",
+        [
+            "Quelltext anzeigen",
+            "Ver código fuente",
+            "Afficher la source",
+        ],
+    ),
+    (
+        "Page Preview",
+        [
+            "Seitenvorschau",
+            "Vista previa de página",
+            "Aperçu des pages",
+        ],
+    ),
+    (
+        "Page Setup",
+        ["Seite einrichten", "Configurar página", "Mise en page"],
+    ),
+    (
+        "Narrow Margins",
+        ["Schmale Ränder", "Márgenes estrechos", "Marges étroites"],
+    ),
+    (
+        "Normal Margins",
+        ["Normale Ränder", "Márgenes normales", "Marges normales"],
+    ),
+    (
+        "Wide Margins",
+        ["Breite Ränder", "Márgenes anchos", "Marges larges"],
+    ),
+    (
+        "Images in Dark Mode",
+        [
+            "Bilder im dunklen Modus",
+            "Imágenes en modo oscuro",
+            "Images en mode sombre",
+        ],
+    ),
+    ("Original", ["Original", "Original", "Originales"]),
+    ("Dimmed", ["Abgedunkelt", "Atenuadas", "Assombries"]),
+    ("Inverted", ["Invertiert", "Invertidas", "Inversées"]),
+    (
+        "Syntax Theme",
+        ["Syntax-Farbschema", "Tema de sintaxis", "Thème de syntaxe"],
+    ),
+    ("Dark Mode", ["Dunkler Modus", "Modo oscuro", "Mode sombre"]),
+    ("Light Mode", ["Heller Modus", "Modo claro", "Mode clair"]),
+    (
+        "Interface Scale",
+        [
+            "Oberflächengröße",
+            "Escala de la interfaz",
+            "Échelle de l'interface",
+        ],
+    ),
+    (
+        "Reduce Motion",
+        [
+            "Bewegung reduzieren",
+            "Reducir movimiento",
+            "Réduire les animations",
+        ],
+    ),
+    (
+        "Snap Scrolling to Headings",
+        [
+            "Bildlauf an Überschriften ausrichten",
+            "Ajustar el desplazamiento a los encabezados",
+            "Caler le défilement sur les titres",
+        ],
+    ),
+    ("Zoom In", ["Vergrößern", "Acercar", "Zoom avant"]),
+    ("Zoom Out", ["Verkleinern", "Alejar", "Zoom arrière"]),
+    (
+        "Reset Zoom",
+        [
+            "Zoom zurücksetzen",
+            "Restablecer zoom",
+            "Réinitialiser le zoom",
+        ],
+    ),
+    (
+        "Floating Contents",
+        [
+            "Schwebendes Inhaltsverzeichnis",
+            "Índice flotante",
+            "Sommaire flottant",
+        ],
+    ),
+    (
+        "Reorder Sections in Outline",
+        [
+            "Abschnitte in der Gliederung verschieben",
+            "Reordenar secciones en el esquema",
+            "Réorganiser les sections dans le plan",
+        ],
+    ),
+    ("Language", ["Sprache", "Idioma", "Langue"]),
+    ("Automatic", ["Automatisch", "Automático", "Automatique"]),
+];
+
+/// The language code of a locale such as `de_DE.UTF-8` or `pt-BR`: its
+/// lowercase first part, or `None` for the C/POSIX locale.
+fn locale_language(locale: &str) -> Option<String> {
+    let language = locale.split(['_', '-', '.', '@']).next()?.to_lowercase();
+    (!language.is_empty() && language != "c" && language != "posix").then_some(language)
+}
+
+/// The language of the user's locale, from `LC_ALL`, `LC_MESSAGES` or
+/// `LANG`; "en" when none of them names one.
+pub fn system_language() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|locale| locale_language(&locale))
+        .unwrap_or_else(|| "en".to_string())
+}
+
+/// Name of `language` in itself, for the language menu.
+pub fn language_name(language: &str) -> &str {
+    LANGUAGES
+        .iter()
+        .find(|(code, _)| *code == language)
+        .map_or("English", |(_, name)| name)
+}
+
+/// `english` in `language`, or `english` itself without a translation.
+pub fn tr(language: &str, english: &'static str) -> &'static str {
+    let Some(column) = MENU_LANGUAGES.iter().position(|code| *code == language) else {
+        return english;
+    };
+    MENU_STRINGS
+        .iter()
+        .find(|(key, _)| *key == english)
+        .map_or(english, |(_, translations)| translations[column])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_strings_fall_back_to_english() {
+        assert_eq!(tr("de", "Close Tab"), "Tab schließen");
+        assert_eq!(tr("fr", "View"), "Affichage");
+        assert_eq!(tr("ja", "View"), "View");
+        assert_eq!(tr("de", "Not translated"), "Not translated");
+
+        let mut keys: Vec<&str> = MENU_STRINGS.iter().map(|(key, _)| *key).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), MENU_STRINGS.len());
+        for code in MENU_LANGUAGES {
+            assert_ne!(language_name(code), "English");
+        }
+    }
+
+    #[test]
+    fn locales_name_their_language() {
+        assert_eq!(locale_language("de_DE.UTF-8").as_deref(), Some("de"));
+        assert_eq!(locale_language("pt-BR").as_deref(), Some("pt"));
+        assert_eq!(locale_language("sr@latin").as_deref(), Some("sr"));
+        assert_eq!(locale_language("C.UTF-8"), None);
+        assert_eq!(locale_language("POSIX"), None);
+        assert_eq!(language_name("zh"), "中文");
+    }
+}
@@ -0,0 +1,598 @@
+//! Every keyboard shortcut, in one table that both the key handling in
+//! `update()` and the shortcut overlay (F1 or `?`) read.
+//!
+//! Rows that describe mouse gestures or egui's own focus handling list no
+//! chords and carry no [`Action`]; they are there for the overlay and
+//! docs/KEYBOARD_SHORTCUTS.md, which a test keeps in step with [`KEYMAP`].
+
+use eframe::egui::{self, Key, Modifiers};
+
+/// A key together with the modifiers it needs. A modifier left as `None`
+/// may be held or not.
+#[derive(Clone, Copy, Debug)]
+pub struct Chord {
+    pub key: Key,
+    pub ctrl: Option<bool>,
+    pub shift: Option<bool>,
+    pub alt: Option<bool>,
+    /// Ignored while a text field has the keyboard, for keys that type
+    pub unless_typing: bool,
+}
+
+impl Chord {
+    const fn key(key: Key) -> Self {
+        Self {
+            key,
+            ctrl: None,
+            shift: None,
+            alt: None,
+            unless_typing: false,
+        }
+    }
+
+    const fn ctrl(key: Key) -> Self {
+        Self {
+            ctrl: Some(true),
+            ..Self::key(key)
+        }
+    }
+
+    const fn alt(key: Key) -> Self {
+        Self {
+            alt: Some(true),
+            ..Self::key(key)
+        }
+    }
+
+    /// Neither Ctrl (or Cmd) nor Alt held.
+    const fn plain(key: Key) -> Self {
+        Self {
+            ctrl: Some(false),
+            alt: Some(false),
+            ..Self::key(key)
+        }
+    }
+
+    const fn shift(self) -> Self {
+        Self {
+            shift: Some(true),
+            ..self
+        }
+    }
+
+    const fn no_shift(self) -> Self {
+        Self {
+            shift: Some(false),
+            ..self
+        }
+    }
+
+    const fn unless_typing(self) -> Self {
+        Self {
+            unless_typing: true,
+            ..self
+        }
+    }
+
+    fn matches(&self, modifiers: Modifiers, typing: bool) -> bool {
+        let ctrl = match self.ctrl {
+            Some(false) => !modifiers.ctrl && !modifiers.command,
+            Some(true) => modifiers.ctrl,
+            None => true,
+        };
+        ctrl && self.shift.map_or(true, |shift| shift == modifiers.shift)
+            && self.alt.map_or(true, |alt| alt == modifiers.alt)
+            && !(self.unless_typing && typing)
+    }
+}
+
+/// What a shortcut does, carried out by `update()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    NewTab,
+    ClipboardTab,
+    CloseTab,
+    NextTab,
+    PrevTab,
+    /// The tab numbered by the index of the chord pressed
+    FocusTab,
+    OpenFile,
+    OpenInEditor,
+    ToggleWatch,
+    TogglePalette,
+    Quit,
+    Back,
+    Forward,
+    OpenSearch,
+    NextMatch,
+    PrevMatch,
+    CloseSearch,
+    ToggleDark,
+    ToggleExplorer,
+    ToggleOutline,
+    ToggleTasks,
+    ToggleProblems,
+    ToggleSource,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    /// Up by a line for the first chord, down for the second
+    ScrollLine,
+    /// Up by a page for the first chord, down for the second
+    ScrollPage,
+    ToggleShortcuts,
+}
+
+/// When a binding applies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum When {
+    /// Even over the image lightbox
+    Always,
+    /// While no lightbox covers the document
+    Document,
+    /// While the find bar is open
+    Searching,
+    /// While the find bar is closed
+    NotSearching,
+}
+
+/// One row of the shortcut overlay.
+pub struct Binding {
+    pub category: &'static str,
+    /// How the overlay and the docs write the keys
+    pub keys: &'static str,
+    pub description: &'static str,
+    pub chords: &'static [Chord],
+    pub when: When,
+    pub action: Option<Action>,
+}
+
+/// The state of the window that decides which bindings apply.
+pub struct State {
+    pub lightbox: bool,
+    pub searching: bool,
+    /// A text field has the keyboard
+    pub typing: bool,
+}
+
+const fn binding(
+    category: &'static str,
+    keys: &'static str,
+    description: &'static str,
+    chords: &'static [Chord],
+    when: When,
+    action: Action,
+) -> Binding {
+    Binding {
+        category,
+        keys,
+        description,
+        chords,
+        when,
+        action: Some(action),
+    }
+}
+
+/// A row the overlay lists but that the app doesn't handle as a key.
+const fn gesture(category: &'static str, keys: &'static str, description: &'static str) -> Binding {
+    Binding {
+        category,
+        keys,
+        description,
+        chords: &[],
+        when: When::Document,
+        action: None,
+    }
+}
+
+const TABS: &str = "Tab Management";
+const FILES: &str = "File Operations";
+const NAVIGATION: &str = "Navigation";
+const SEARCH: &str = "Search";
+const VIEW: &str = "View";
+
+/// Every binding, grouped by category in the order the overlay shows them.
+pub const KEYMAP: &[Binding] = &[
+    binding(
+        TABS,
+        "Ctrl+T",
+        "New tab (open file dialog)",
+        &[Chord::ctrl(Key::T)],
+        When::Document,
+        Action::NewTab,
+    ),
+    binding(
+        TABS,
+        "Ctrl+Shift+V",
+        "New tab from the clipboard",
+        &[Chord::ctrl(Key::V).shift()],
+        When::Document,
+        Action::ClipboardTab,
+    ),
+    binding(
+        TABS,
+        "Ctrl+W",
+        "Close current tab",
+        &[Chord::ctrl(Key::W)],
+        When::Document,
+        Action::CloseTab,
+    ),
+    binding(
+        TABS,
+        "Ctrl+Tab",
+        "Next tab",
+        &[Chord::ctrl(Key::Tab).no_shift()],
+        When::Document,
+        Action::NextTab,
+    ),
+    binding(
+        TABS,
+        "Ctrl+Shift+Tab",
+        "Previous tab",
+        &[Chord::ctrl(Key::Tab).shift()],
+        When::Document,
+        Action::PrevTab,
+    ),
+    binding(
+        TABS,
+        "Ctrl+1-9",
+        "Switch to tab 1-9",
+        &[
+            Chord::ctrl(Key::Num1),
+            Chord::ctrl(Key::Num2),
+            Chord::ctrl(Key::Num3),
+            Chord::ctrl(Key::Num4),
+            Chord::ctrl(Key::Num5),
+            Chord::ctrl(Key::Num6),
+            Chord::ctrl(Key::Num7),
+            Chord::ctrl(Key::Num8),
+            Chord::ctrl(Key::Num9),
+        ],
+        When::Document,
+        Action::FocusTab,
+    ),
+    binding(
+        FILES,
+        "Ctrl+O",
+        "Open file dialog",
+        &[Chord::ctrl(Key::O).no_shift()],
+        When::Document,
+        Action::OpenFile,
+    ),
+    binding(
+        FILES,
+        "Ctrl+E",
+        "Open in external editor at the current line",
+        &[Chord::ctrl(Key::E).no_shift()],
+        When::Document,
+        Action::OpenInEditor,
+    ),
+    binding(
+        FILES,
+        "F5",
+        "Toggle file watching",
+        &[Chord::key(Key::F5)],
+        When::Document,
+        Action::ToggleWatch,
+    ),
+    binding(
+        FILES,
+        "Ctrl+Shift+P",
+        "Command palette (script commands)",
+        &[Chord::ctrl(Key::P).shift()],
+        When::Document,
+        Action::TogglePalette,
+    ),
+    binding(
+        FILES,
+        "Ctrl+Q",
+        "Quit application",
+        &[Chord::ctrl(Key::Q)],
+        When::Document,
+        Action::Quit,
+    ),
+    binding(
+        NAVIGATION,
+        "Alt+Left",
+        "Navigate back in history",
+        &[Chord::alt(Key::ArrowLeft)],
+        When::Document,
+        Action::Back,
+    ),
+    binding(
+        NAVIGATION,
+        "Alt+Right",
+        "Navigate forward in history",
+        &[Chord::alt(Key::ArrowRight)],
+        When::Document,
+        Action::Forward,
+    ),
+    gesture(NAVIGATION, "Click link", "Navigate in current tab"),
+    gesture(NAVIGATION, "Ctrl+Click link", "Open link in new tab"),
+    gesture(
+        NAVIGATION,
+        "Middle-click / Ctrl+Shift+Click link",
+        "Open link in a background tab",
+    ),
+    binding(
+        SEARCH,
+        "Ctrl+F",
+        "Open find bar (or refocus the input if already open)",
+        &[Chord::ctrl(Key::F).no_shift()],
+        When::Document,
+        Action::OpenSearch,
+    ),
+    // The find bar's singleline input doesn't move its cursor with the
+    // vertical arrows, so they are free to step through matches
+    binding(
+        SEARCH,
+        "Enter or ↓",
+        "Jump to the next match in the current document",
+        &[
+            Chord::key(Key::Enter).no_shift(),
+            Chord::key(Key::ArrowDown),
+        ],
+        When::Searching,
+        Action::NextMatch,
+    ),
+    binding(
+        SEARCH,
+        "Shift+Enter or ↑",
+        "Jump to the previous match in the current document",
+        &[Chord::key(Key::Enter).shift(), Chord::key(Key::ArrowUp)],
+        When::Searching,
+        Action::PrevMatch,
+    ),
+    binding(
+        SEARCH,
+        "Esc",
+        "Close the find bar and clear highlights",
+        &[Chord::key(Key::Escape)],
+        When::Searching,
+        Action::CloseSearch,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+D",
+        "Toggle dark/light mode (leaves Follow System Theme)",
+        &[Chord::ctrl(Key::D)],
+        When::Document,
+        Action::ToggleDark,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+Shift+E",
+        "Toggle file explorer",
+        &[Chord::ctrl(Key::E).shift()],
+        When::Document,
+        Action::ToggleExplorer,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+Shift+O",
+        "Toggle outline sidebar",
+        &[Chord::ctrl(Key::O).shift()],
+        When::Document,
+        Action::ToggleOutline,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+Shift+K",
+        "Toggle task panel",
+        &[Chord::ctrl(Key::K).shift()],
+        When::Document,
+        Action::ToggleTasks,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+Shift+M",
+        "Toggle problems panel",
+        &[Chord::ctrl(Key::M).shift()],
+        When::Document,
+        Action::ToggleProblems,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+U",
+        "Toggle the source view, with line numbers",
+        &[Chord::ctrl(Key::U)],
+        When::Document,
+        Action::ToggleSource,
+    ),
+    gesture(
+        VIEW,
+        "Tab / Shift+Tab",
+        "Move focus to the next / previous link",
+    ),
+    gesture(VIEW, "Enter", "Open the focused link"),
+    binding(
+        VIEW,
+        "Ctrl++ / Ctrl+=",
+        "Zoom in",
+        &[Chord::ctrl(Key::Plus), Chord::ctrl(Key::Equals)],
+        When::Always,
+        Action::ZoomIn,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+-",
+        "Zoom out",
+        &[Chord::ctrl(Key::Minus)],
+        When::Always,
+        Action::ZoomOut,
+    ),
+    binding(
+        VIEW,
+        "Ctrl+0",
+        "Reset zoom to 100%",
+        &[Chord::ctrl(Key::Num0)],
+        When::Always,
+        Action::ZoomReset,
+    ),
+    binding(
+        VIEW,
+        "↑ / ↓ (when find bar is closed)",
+        "Scroll document up/down by line",
+        &[Chord::plain(Key::ArrowUp), Chord::plain(Key::ArrowDown)],
+        When::NotSearching,
+        Action::ScrollLine,
+    ),
+    binding(
+        VIEW,
+        "Page Up / Page Down",
+        "Scroll document up/down by page",
+        &[Chord::plain(Key::PageUp), Chord::plain(Key::PageDown)],
+        When::Document,
+        Action::ScrollPage,
+    ),
+    gesture(VIEW, "Ctrl+Scroll", "Zoom in/out with mouse wheel"),
+    gesture(
+        VIEW,
+        "Shift+Scroll over a wide table",
+        "Scroll the table horizontally",
+    ),
+    binding(
+        VIEW,
+        "F1 / ?",
+        "Show these keyboard shortcuts",
+        &[
+            Chord::key(Key::F1),
+            Chord::key(Key::Questionmark).unless_typing(),
+        ],
+        When::Document,
+        Action::ToggleShortcuts,
+    ),
+];
+
+/// The actions whose keys were pressed this frame, each with the index of
+/// the chord that fired it, in [`KEYMAP`] order.
+pub fn pressed(input: &egui::InputState, state: &State) -> Vec<(Action, usize)> {
+    fired(|key| input.key_pressed(key), input.modifiers, state)
+}
+
+fn fired(
+    key_pressed: impl Fn(Key) -> bool,
+    modifiers: Modifiers,
+    state: &State,
+) -> Vec<(Action, usize)> {
+    KEYMAP
+        .iter()
+        .filter(|binding| match binding.when {
+            When::Always => true,
+            When::Document => !state.lightbox,
+            When::Searching => !state.lightbox && state.searching,
+            When::NotSearching => !state.lightbox && !state.searching,
+        })
+        .filter_map(|binding| {
+            let action = binding.action?;
+            let chord = binding.chords.iter().position(|chord| {
+                key_pressed(chord.key) && chord.matches(modifiers, state.typing)
+            })?;
+            Some((action, chord))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE: State = State {
+        lightbox: false,
+        searching: false,
+        typing: false,
+    };
+
+    fn press(key: Key, modifiers: Modifiers, state: &State) -> Vec<(Action, usize)> {
+        fired(|pressed| pressed == key, modifiers, state)
+    }
+
+    #[test]
+    fn overlay_matches_the_shortcut_docs() {
+        let docs = include_str!("../docs/KEYBOARD_SHORTCUTS.md");
+        let mut documented = Vec::new();
+        let mut category = "";
+        for line in docs.lines() {
+            if let Some(heading) = line.strip_prefix("## ") {
+                category = heading;
+            } else if let Some(row) = line.strip_prefix("| ") {
+                let (keys, action) = row.trim_end_matches(" |").split_once(" | ").unwrap();
+                if keys != "Shortcut" {
+                    documented.push((category, keys, action));
+                }
+            }
+        }
+        let listed: Vec<_> = KEYMAP
+            .iter()
+            .map(|binding| (binding.category, binding.keys, binding.description))
+            .collect();
+        assert_eq!(listed, documented);
+    }
+
+    #[test]
+    fn only_gestures_go_without_chords() {
+        for binding in KEYMAP {
+            assert_eq!(
+                binding.action.is_some(),
+                !binding.chords.is_empty(),
+                "{}",
+                binding.keys
+            );
+        }
+    }
+
+    #[test]
+    fn modifiers_pick_between_bindings_on_one_key() {
+        assert_eq!(
+            press(Key::O, Modifiers::CTRL, &IDLE),
+            [(Action::OpenFile, 0)]
+        );
+        assert_eq!(
+            press(Key::O, Modifiers::CTRL | Modifiers::SHIFT, &IDLE),
+            [(Action::ToggleOutline, 0)]
+        );
+        assert_eq!(
+            press(Key::Num3, Modifiers::CTRL, &IDLE),
+            [(Action::FocusTab, 2)]
+        );
+        assert!(press(Key::O, Modifiers::NONE, &IDLE).is_empty());
+    }
+
+    #[test]
+    fn state_decides_what_the_arrows_do() {
+        let searching = State {
+            searching: true,
+            ..IDLE
+        };
+        let lightbox = State {
+            lightbox: true,
+            ..IDLE
+        };
+        assert_eq!(
+            press(Key::ArrowDown, Modifiers::NONE, &IDLE),
+            [(Action::ScrollLine, 1)]
+        );
+        assert_eq!(
+            press(Key::ArrowDown, Modifiers::NONE, &searching),
+            [(Action::NextMatch, 1)]
+        );
+        assert!(press(Key::ArrowDown, Modifiers::NONE, &lightbox).is_empty());
+        assert_eq!(
+            press(Key::Minus, Modifiers::CTRL, &lightbox),
+            [(Action::ZoomOut, 0)]
+        );
+    }
+
+    #[test]
+    fn question_mark_types_in_text_fields() {
+        let typing = State {
+            typing: true,
+            ..IDLE
+        };
+        assert!(press(Key::Questionmark, Modifiers::NONE, &typing).is_empty());
+        assert_eq!(
+            press(Key::F1, Modifiers::NONE, &typing),
+            [(Action::ToggleShortcuts, 0)]
+        );
+    }
+}
@@ -1,139 +1,201 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io::{self, IsTerminal};
+use std::io::{self, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Receiver};
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
 use std::time::{Duration, Instant};
 
 use clap::Parser;
 use eframe::egui;
-use egui_commonmark_extended::{CommonMarkCache, CommonMarkViewer, STRONG_FONT_FAMILY};
+use egui_commonmark_extended::{
+    heading_slug, is_page_break, AlertBundle, AnchorSlugger, BlockKind, CodeBlockFooterFn,
+    CommonMarkCache, CommonMarkViewer, DarkModeImages, HeaderKey, ImageCaptions, LinkClick,
+    Sandbox, SourceBlock, STRONG_FONT_FAMILY,
+};
+use encoding_rs::Encoding;
+use i18n::tr;
 use notify::{PollWatcher, RecommendedWatcher};
 use notify_debouncer_mini::{new_debouncer, new_debouncer_opt, DebouncedEventKind, Debouncer};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+mod background;
+mod citations;
+mod compare;
+mod custom_alerts;
+mod figures;
+mod footnotes;
+mod graph;
+mod i18n;
+mod keymap;
+mod pages;
+mod plugins;
+mod problems;
+mod reader;
+mod remote;
+mod scripting;
+mod site;
+mod toc;
+
 #[cfg(feature = "mcp")]
 use egui_mcp_bridge::{McpBridge, McpUiExt};
 
 const APP_KEY: &str = "md-viewer-state";
+const APP_NAME: &str = "md-viewer";
+const DEFAULT_SYNTAX_THEME_DARK: &str = "base16-ocean.dark";
+const DEFAULT_SYNTAX_THEME_LIGHT: &str = "base16-ocean.light";
 
 // Welcome page recent-files: how many to keep, and how many to show before "Show more".
 const RECENT_FILES_CAP: usize = 20;
+/// How many explorer root folders the explorer header offers to switch to
+const RECENT_ROOTS_CAP: usize = 10;
+/// How many documents' reading positions are remembered
+const READING_POSITIONS_CAP: usize = 200;
 const RECENT_SHOWN: usize = 6;
 
-/// Compiled regex for parsing markdown headers (lazy, compiled once)
-static HEADER_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(#{1,6})\s+(.+)$").unwrap());
-
 /// Compiled regex for parsing markdown links (lazy, compiled once)
 static LINK_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([^\]]*)\]\(([^)]+)\)").unwrap());
 
-/// System font paths for a real bold face used by Markdown strong text.
-const STRONG_FONT_PATHS: &[&str] = &[
-    "/usr/share/fonts/noto/NotoSans-Bold.ttf",
-    "/usr/share/fonts/TTF/NotoSans-Bold.ttf",
-    "/usr/share/fonts/truetype/noto/NotoSans-Bold.ttf",
-];
+/// A fallback face for characters egui's bundled fonts lack: the first
+/// installed family of `families` with a glyph for `sample`, registered
+/// under `name`.
+struct FontFallback {
+    name: &'static str,
+    sample: char,
+    families: &'static [&'static str],
+}
 
-/// System font paths for fallback (Linux and Windows common paths)
-const SYSTEM_FONT_PATHS: &[(&str, &str)] = &[
+/// Fallback faces in the order egui tries them. Family names cover the
+/// usual Linux (Noto, DejaVu), Windows and macOS fonts, then the faces of
+/// the `bundled-fonts` feature; a slot is skipped when a face picked for an
+/// earlier one already has its sample glyph.
+const FONT_FALLBACKS: &[FontFallback] = &[
     // Noto Sans for extended Latin, Greek, Cyrillic
-    ("NotoSans", "/usr/share/fonts/noto/NotoSans-Regular.ttf"),
-    ("NotoSans", "/usr/share/fonts/TTF/NotoSans-Regular.ttf"),
-    (
-        "NotoSans",
-        "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
-    ),
-    // Linux CJK fonts (Chinese, Japanese, Korean)
-    (
-        "NotoSansCJK",
-        "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
-    ),
-    (
-        "NotoSansCJK",
-        "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
-    ),
-    (
-        "NotoSansCJK",
-        "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
-    ),
-    // Windows CJK fonts (Chinese, Japanese, Korean)
-    ("MicrosoftYaHei", "C:/Windows/Fonts/msyh.ttc"),
-    ("MicrosoftYaHeiUI", "C:/Windows/Fonts/msyh.ttc"),
-    ("SimSun", "C:/Windows/Fonts/simsun.ttc"),
-    ("NSimSun", "C:/Windows/Fonts/simsun.ttc"),
-    ("DengXian", "C:/Windows/Fonts/Deng.ttf"),
-    ("MicrosoftJhengHei", "C:/Windows/Fonts/msjh.ttc"),
-    ("YuGothic", "C:/Windows/Fonts/YuGothM.ttc"),
-    ("MalgunGothic", "C:/Windows/Fonts/malgun.ttf"),
-    // Arabic
-    (
-        "NotoSansArabic",
-        "/usr/share/fonts/noto/NotoSansArabic-Regular.ttf",
-    ),
-    (
-        "NotoSansArabic",
-        "/usr/share/fonts/TTF/NotoSansArabic-Regular.ttf",
-    ),
-    // Hebrew
-    (
-        "NotoSansHebrew",
-        "/usr/share/fonts/noto/NotoSansHebrew-Regular.ttf",
-    ),
-    (
-        "NotoSansHebrew",
-        "/usr/share/fonts/TTF/NotoSansHebrew-Regular.ttf",
-    ),
+    FontFallback {
+        name: "NotoSans",
+        sample: 'Ж',
+        families: &["Noto Sans"],
+    },
+    // Chinese, Japanese, Korean
+    FontFallback {
+        name: "CJK",
+        sample: '漢',
+        families: &[
+            "Noto Sans CJK SC",
+            "Noto Sans CJK JP",
+            "Source Han Sans SC",
+            "WenQuanYi Micro Hei",
+            "Droid Sans Fallback",
+            "Microsoft YaHei",
+            "Yu Gothic",
+            "PingFang SC",
+            "Hiragino Sans",
+        ],
+    },
+    FontFallback {
+        name: "Hangul",
+        sample: '한',
+        families: &[
+            "Noto Sans CJK KR",
+            "NanumGothic",
+            "Malgun Gothic",
+            "Apple SD Gothic Neo",
+        ],
+    },
+    FontFallback {
+        name: "Arabic",
+        sample: 'ع',
+        families: &[
+            "Noto Sans Arabic",
+            "Noto Naskh Arabic",
+            "DejaVu Sans",
+            "Segoe UI",
+            "Geeza Pro",
+            "DejaVu Sans Mono",
+        ],
+    },
+    FontFallback {
+        name: "Hebrew",
+        sample: 'א',
+        families: &[
+            "Noto Sans Hebrew",
+            "DejaVu Sans",
+            "Segoe UI",
+            "Arial Hebrew",
+            "Libertinus Serif",
+        ],
+    },
     // Devanagari (Hindi, Sanskrit)
-    (
-        "NotoSansDevanagari",
-        "/usr/share/fonts/noto/NotoSansDevanagari-Regular.ttf",
-    ),
-    (
-        "NotoSansDevanagari",
-        "/usr/share/fonts/TTF/NotoSansDevanagari-Regular.ttf",
-    ),
-    // Thai
-    (
-        "NotoSansThai",
-        "/usr/share/fonts/noto/NotoSansThai-Regular.ttf",
-    ),
-    (
-        "NotoSansThai",
-        "/usr/share/fonts/TTF/NotoSansThai-Regular.ttf",
-    ),
-    // Symbols (math, arrows, etc.)
-    (
-        "NotoSansSymbols",
-        "/usr/share/fonts/noto/NotoSansSymbols-Regular.ttf",
-    ),
-    (
-        "NotoSansSymbols",
-        "/usr/share/fonts/TTF/NotoSansSymbols-Regular.ttf",
-    ),
-    (
-        "NotoSansSymbols2",
-        "/usr/share/fonts/noto/NotoSansSymbols2-Regular.ttf",
-    ),
-    (
-        "NotoSansSymbols2",
-        "/usr/share/fonts/TTF/NotoSansSymbols2-Regular.ttf",
-    ),
-    // DejaVu Sans - covers warning sign (U+26A0) and other misc symbols
-    ("DejaVuSans", "/usr/share/fonts/TTF/DejaVuSans.ttf"),
-    ("DejaVuSans", "/usr/share/fonts/dejavu/DejaVuSans.ttf"),
-    (
-        "DejaVuSans",
-        "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
-    ),
+    FontFallback {
+        name: "Devanagari",
+        sample: 'अ',
+        families: &[
+            "Noto Sans Devanagari",
+            "Lohit Devanagari",
+            "Nirmala UI",
+            "Kohinoor Devanagari",
+        ],
+    },
+    FontFallback {
+        name: "Thai",
+        sample: 'ก',
+        families: &["Noto Sans Thai", "Leelawadee UI", "Thonburi"],
+    },
+    // Symbols (math, arrows, chess, etc.)
+    FontFallback {
+        name: "Symbols",
+        sample: '∰',
+        families: &[
+            "Noto Sans Symbols",
+            "Noto Sans Math",
+            "DejaVu Sans",
+            "Cambria Math",
+            "Segoe UI Symbol",
+            "Apple Symbols",
+            "NewComputerModernMath",
+        ],
+    },
+    FontFallback {
+        name: "Symbols2",
+        sample: '♔',
+        families: &[
+            "Noto Sans Symbols 2",
+            "DejaVu Sans",
+            "Segoe UI Symbol",
+            "Apple Symbols",
+            "DejaVu Sans Mono",
+        ],
+    },
+    // Covers the warning sign (U+26A0) and other misc symbols
+    FontFallback {
+        name: "MiscSymbols",
+        sample: '⚠',
+        families: &[
+            "DejaVu Sans",
+            "Segoe UI Symbol",
+            "Apple Symbols",
+            "DejaVu Sans Mono",
+        ],
+    },
+    // Emoji newer than egui's bundled Noto Emoji; color-only faces (Noto
+    // Color Emoji, Apple Color Emoji) have no outlines egui can draw
+    FontFallback {
+        name: "Emoji",
+        sample: '🫠',
+        families: &["Noto Emoji", "Segoe UI Emoji"],
+    },
 ];
+/// Family of the real bold face used by Markdown strong text.
+const STRONG_FONT_SOURCE: &str = "Noto Sans";
 const MAX_WATCHER_RETRIES: u32 = 3;
-const FLASH_DURATION_MS: u64 = 600;
 
 // Optimal widths for initial window sizing (based on typography research)
 // Content: 600px optimal for 55-75 CPL readability
@@ -141,10 +203,47 @@ const FLASH_DURATION_MS: u64 = 600;
 // Outline: 200px default with 8px inner margins
 // Plus ~16px for panel separators
 const CONTENT_OPTIMAL_WIDTH: f32 = 600.0;
+// User-adjustable content column width (View → Content Width)
+const CONTENT_WIDTH_RANGE: std::ops::RangeInclusive<f32> = 400.0..=1600.0;
+const CONTENT_WIDTH_PRESETS: [f32; 4] = [600.0, 800.0, 1000.0, 1200.0];
+// Interface scale (menus, tabs, panels) is independent of Ctrl+/- content zoom
+const UI_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.5..=2.0;
+const UI_SCALE_PRESETS: [f32; 7] = [0.8, 0.9, 1.0, 1.1, 1.25, 1.5, 2.0];
+// Width of the optional source line number gutter left of the content column
+const LINE_NUMBER_GUTTER_WIDTH: f32 = 44.0;
 const EXPLORER_DEFAULT_WIDTH: f32 = 216.0; // 200 + 16 margins
 const OUTLINE_DEFAULT_WIDTH: f32 = 208.0; // 200 + 8 margins
 const PANEL_SEPARATORS: f32 = 16.0;
 const OPTIMAL_WINDOW_HEIGHT: f32 = 750.0;
+// Files larger than this open with only their first chunk rendered, and
+// "Load next" adds one chunk at a time
+const LARGE_FILE_BYTES: u64 = 4 * 1024 * 1024;
+const LARGE_FILE_CHUNK_BYTES: usize = 1024 * 1024;
+// A tab whose file disappears keeps its content and is retried this often
+// for the grace period (atomic saves, network share hiccups) before a banner
+// says the file is gone
+const MISSING_FILE_RETRY: Duration = Duration::from_millis(500);
+const MISSING_FILE_GRACE: Duration = Duration::from_secs(5);
+
+// How long New Tab from Clipboard waits for the clipboard's text
+const CLIPBOARD_PASTE_TIMEOUT: Duration = Duration::from_secs(1);
+// The session is also snapshotted to its own file this often, and the file
+// removed on a clean exit: finding it at startup means the last run crashed
+// or was killed before eframe flushed its storage
+const SESSION_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+// Email quote style: a bar color per reply level, and the older messages of a
+// thread, quoted more than this many levels deep, collapsed
+const EMAIL_QUOTE_COLORS: [egui::Color32; 4] = [
+    egui::Color32::from_rgb(0x4A, 0x90, 0xD9),
+    egui::Color32::from_rgb(0x5C, 0xB8, 0x5C),
+    egui::Color32::from_rgb(0xD9, 0x8C, 0x3A),
+    egui::Color32::from_rgb(0xA8, 0x6C, 0xC4),
+];
+const EMAIL_QUOTE_DEPTH: usize = 2;
+// With snapping on, this long after the last wheel step a heading within
+// the share of the viewport of the top is scrolled to it
+const HEADING_SNAP_DELAY: Duration = Duration::from_millis(200);
+const HEADING_SNAP_RANGE: f32 = 0.25;
 
 // Keyboard document scroll deltas are centralized so shortcut wiring and tests
 // share the same line/page behavior.
@@ -180,4641 +279,12368 @@ fn keyboard_scroll_target(
     (current_offset + delta).clamp(0.0, max_scroll)
 }
 
-fn content_default_width(full_width_content: bool) -> Option<usize> {
+/// Offset scrolling the heading at content y in `headings` nearest to the
+/// top of the viewport there, if one is within [`HEADING_SNAP_RANGE`] of it.
+fn heading_snap_offset(current_offset: f32, viewport_height: f32, headings: &[f32]) -> Option<f32> {
+    let range = viewport_height * HEADING_SNAP_RANGE;
+    headings
+        .iter()
+        .map(|y| (y - 50.0).max(0.0))
+        .filter(|target| (target - current_offset).abs() <= range)
+        .min_by(|a, b| {
+            (a - current_offset)
+                .abs()
+                .total_cmp(&(b - current_offset).abs())
+        })
+        .filter(|target| (target - current_offset).abs() > 1.0)
+}
+
+fn content_default_width(full_width_content: bool, content_width: f32) -> Option<usize> {
     if full_width_content {
         None
     } else {
-        Some(CONTENT_OPTIMAL_WIDTH as usize)
+        Some(content_width.round() as usize)
     }
 }
 
-/// A recently opened file, for the welcome page's "Recent" list.
-#[derive(Serialize, Deserialize, Clone)]
-struct RecentEntry {
-    path: PathBuf,
-    /// Seconds since the Unix epoch when the file was last opened.
-    last_opened: u64,
+// Left gutter that centers a capped content column in the available width.
+// Zero when centering is off, in full-width mode, or when the window is
+// narrower than the column (the renderer then wraps to the available width).
+fn content_center_margin(available_width: f32, default_width: Option<usize>, center: bool) -> f32 {
+    match default_width {
+        Some(width) if center => ((available_width - width as f32) / 2.0).max(0.0),
+        _ => 0.0,
+    }
 }
 
-/// Current time in seconds since the Unix epoch (0 if the clock is before epoch).
-fn now_epoch_secs() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0)
+/// Every animation duration in the app, so reduced motion is a single switch
+/// (View → Reduce Motion) rather than a check at each animated widget.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct MotionConfig {
+    /// egui widget animations such as collapsing headers and toggles
+    animation_time: f32,
+    /// Animated `ScrollArea` jumps (`scroll_to_*`)
+    scroll_animation: egui::style::ScrollAnimation,
+    /// Green explorer flash after a watched file changes; zero disables it
+    flash_duration: Duration,
 }
 
-/// Push a path to the front of the recent list, deduped and capped (most-recent first).
-fn push_recent(list: &mut Vec<RecentEntry>, path: &Path, now: u64) {
-    list.retain(|e| e.path.as_path() != path);
-    list.insert(
-        0,
-        RecentEntry {
-            path: path.to_path_buf(),
-            last_opened: now,
-        },
-    );
-    list.truncate(RECENT_FILES_CAP);
-}
+impl MotionConfig {
+    fn new(reduce_motion: bool) -> Self {
+        if reduce_motion {
+            Self {
+                animation_time: 0.0,
+                scroll_animation: egui::style::ScrollAnimation::none(),
+                flash_duration: Duration::ZERO,
+            }
+        } else {
+            Self {
+                animation_time: 0.15,
+                scroll_animation: egui::style::ScrollAnimation {
+                    points_per_second: 1500.0,
+                    ..Default::default()
+                },
+                flash_duration: Duration::from_millis(600),
+            }
+        }
+    }
 
-/// Format an epoch-seconds timestamp as a short relative time ("3m ago").
-fn format_relative_time(epoch_secs: u64, now: u64) -> String {
-    let diff = now.saturating_sub(epoch_secs);
-    if diff < 60 {
-        "just now".to_string()
-    } else if diff < 3600 {
-        format!("{}m ago", diff / 60)
-    } else if diff < 86_400 {
-        format!("{}h ago", diff / 3600)
-    } else if diff < 172_800 {
-        "yesterday".to_string()
-    } else if diff < 604_800 {
-        format!("{}d ago", diff / 86_400)
-    } else if diff < 2_592_000 {
-        format!("{}w ago", diff / 604_800)
-    } else {
-        format!("{}mo ago", diff / 2_592_000)
+    fn apply(&self, style: &mut egui::Style) {
+        style.animation_time = self.animation_time;
+        style.scroll_animation = self.scroll_animation;
     }
-}
 
-/// Persisted state saved between sessions
-#[derive(Serialize, Deserialize, Default)]
-struct PersistedState {
-    dark_mode: Option<bool>,
-    zoom_level: Option<f32>,
-    show_outline: Option<bool>,
-    full_width_content: Option<bool>,
-    open_tabs: Option<Vec<PathBuf>>,
-    active_tab: Option<usize>,
-    // File explorer state
-    show_explorer: Option<bool>,
-    explorer_root: Option<PathBuf>,
-    expanded_dirs: Option<Vec<PathBuf>>,
-    explorer_sort_order: Option<SortOrder>,
-    recent_files: Option<Vec<RecentEntry>>,
+    /// Flash intensity `elapsed` after a change (1.0 = full, 0.0 = none)
+    fn flash_intensity(&self, elapsed: Duration) -> f32 {
+        if elapsed < self.flash_duration {
+            // Fade out: 1.0 -> 0.0 over the duration
+            1.0 - elapsed.as_secs_f32() / self.flash_duration.as_secs_f32()
+        } else {
+            0.0
+        }
+    }
 }
 
-/// Build the composite cache key for a header position lookup. Combines the
-/// normalized (lowercased) title with the occurrence index so duplicate-titled
-/// headers map to distinct entries in `CommonMarkCache::header_positions`.
-/// Both the parser (which assigns `nth_with_same_text` to each `Header`) and
-/// the renderer (which records positions while painting) use this function so
-/// keys agree across the read/write boundary.
-pub fn header_position_key(normalized_title: &str, nth_with_same_text: usize) -> String {
-    if nth_with_same_text == 0 {
-        normalized_title.to_string()
-    } else {
-        format!("{normalized_title}#{nth_with_same_text}")
+// Content zoom: scale every text style of the document `Ui` only, leaving the
+// chrome at the interface scale set through `Context::set_zoom_factor`.
+fn scale_text_styles(style: &mut egui::Style, factor: f32) {
+    for font_id in style.text_styles.values_mut() {
+        font_id.size *= factor;
     }
 }
 
-/// Represents a markdown header for the outline
-#[derive(Clone)]
-struct Header {
-    level: u8,
-    title: String,
-    /// Pre-computed truncated display title for outline sidebar
-    display_title: String,
-    /// Pre-computed lowercase key for header position cache lookups
-    normalized_title: String,
-    /// Occurrence index among headers with the same `normalized_title`.
-    /// The first `## Installation` is 0, the second is 1, etc. Combined
-    /// with `normalized_title` into the composite cache key so duplicates
-    /// scroll to the correct (different) y positions.
-    nth_with_same_text: usize,
-    line_number: usize,
+// Paint the 1-based source line of each visible top-level block, vertically
+// centred on the block's first row. Block y values are content-relative, so
+// `scroll_offset` maps them into the gutter's screen space.
+fn paint_line_number_gutter(
+    ui: &egui::Ui,
+    gutter: egui::Rect,
+    blocks: &[SourceBlock],
+    scroll_offset: f32,
+) {
+    let painter = ui.painter_at(gutter);
+    let font = egui::TextStyle::Small.resolve(ui.style());
+    let color = ui.visuals().weak_text_color();
+    let row_height = ui.text_style_height(&egui::TextStyle::Body);
+    let first = blocks.partition_point(|b| b.y_range.max < scroll_offset);
+    for block in &blocks[first..] {
+        let y = gutter.top() + block.y_range.min - scroll_offset + row_height / 2.0;
+        if y > gutter.bottom() {
+            break;
+        }
+        painter.text(
+            egui::pos2(gutter.right() - 8.0, y),
+            egui::Align2::RIGHT_CENTER,
+            block.line.to_string(),
+            font.clone(),
+            color,
+        );
+    }
 }
 
-/// Result of parsing markdown headers
-struct ParsedHeaders {
-    /// Document title (first h1, if any)
-    document_title: Option<String>,
-    /// Outline headers (excludes the first h1)
-    outline_headers: Vec<Header>,
+/// The page preview's pages, `rect` around the whole document with the
+/// margins, behind it in the `sheet` shape: a dashed rule and the page's
+/// number where each page after the first starts, at `page_starts` on screen.
+fn paint_pages(
+    ui: &egui::Ui,
+    sheet: egui::layers::ShapeIdx,
+    rect: egui::Rect,
+    viewport: egui::Rect,
+    page_starts: &[f32],
+) {
+    let visuals = ui.visuals();
+    ui.painter().set(
+        sheet,
+        egui::epaint::RectShape::new(
+            rect,
+            2.0,
+            visuals.faint_bg_color,
+            visuals.widgets.noninteractive.bg_stroke,
+            egui::StrokeKind::Outside,
+        ),
+    );
+    let painter = ui.painter_at(viewport);
+    let color = visuals.weak_text_color();
+    let font = egui::TextStyle::Small.resolve(ui.style());
+    let pages = page_starts.len() + 1;
+    for (i, &y) in page_starts.iter().enumerate() {
+        if y < viewport.top() - 20.0 || y > viewport.bottom() + 20.0 {
+            continue;
+        }
+        let rule = [egui::pos2(rect.left(), y), egui::pos2(rect.right(), y)];
+        painter.extend(egui::Shape::dashed_line(
+            &rule,
+            egui::Stroke::new(1.0, color),
+            6.0,
+            4.0,
+        ));
+        painter.text(
+            egui::pos2(rect.right() - 6.0, y + 2.0),
+            egui::Align2::RIGHT_TOP,
+            format!("Page {} of {pages}", i + 2),
+            font.clone(),
+            color,
+        );
+    }
 }
 
-/// A single match in a tab's content, identified by byte range and 1-based line number
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct SearchMatch {
-    byte_start: usize,
-    byte_end: usize,
-    line_number: usize,
+/// `graph` fitted into the rest of `ui`: links as lines and documents as
+/// dots, named when they're the `active` one, linked to or from it, or
+/// hovered. Returns the document clicked.
+fn show_link_graph(
+    ui: &mut egui::Ui,
+    graph: &graph::Graph,
+    root: &Path,
+    active: Option<&Path>,
+) -> Option<PathBuf> {
+    const RADIUS: f32 = 5.0;
+    let (response, painter) = ui.allocate_painter(ui.available_size(), egui::Sense::click());
+    let area = response.rect.shrink(32.0);
+    let positions: Vec<egui::Pos2> = graph.nodes.iter().map(|node| node.pos).collect();
+    let bounds = egui::Rect::from_points(&positions);
+    let scale =
+        (area.width() / bounds.width().max(1.0)).min(area.height() / bounds.height().max(1.0));
+    let positions: Vec<egui::Pos2> = positions
+        .into_iter()
+        .map(|pos| area.center() + (pos - bounds.center()) * scale)
+        .collect();
+    let active = active.and_then(|path| graph.index_of(path));
+    let neighbors = active.map(|i| graph.neighbors(i)).unwrap_or_default();
+    let hovered = response.hover_pos().and_then(|pointer| {
+        positions
+            .iter()
+            .position(|pos| pos.distance(pointer) <= RADIUS + 3.0)
+    });
+
+    let visuals = ui.visuals();
+    // The active document's links go over the others
+    for highlighted in [false, true] {
+        for &(from, to) in &graph.edges {
+            if (active == Some(from) || active == Some(to)) != highlighted {
+                continue;
+            }
+            let stroke = if highlighted {
+                egui::Stroke::new(1.5, visuals.hyperlink_color)
+            } else {
+                visuals.widgets.noninteractive.bg_stroke
+            };
+            painter.line_segment([positions[from], positions[to]], stroke);
+        }
+    }
+    let font = egui::TextStyle::Small.resolve(ui.style());
+    for (i, (node, &pos)) in graph.nodes.iter().zip(&positions).enumerate() {
+        let (radius, color) = if active == Some(i) {
+            (RADIUS + 2.0, visuals.selection.bg_fill)
+        } else if neighbors.contains(&i) {
+            (RADIUS, visuals.hyperlink_color)
+        } else {
+            (RADIUS, visuals.weak_text_color())
+        };
+        painter.circle_filled(pos, radius, color);
+        if active == Some(i) || neighbors.contains(&i) || hovered == Some(i) {
+            let name = node.path.file_stem().unwrap_or_default().to_string_lossy();
+            painter.text(
+                pos + egui::vec2(0.0, radius + 2.0),
+                egui::Align2::CENTER_TOP,
+                name,
+                font.clone(),
+                visuals.text_color(),
+            );
+        }
+    }
+
+    let node = hovered.map(|i| &graph.nodes[i])?;
+    let response = response
+        .on_hover_cursor(egui::CursorIcon::PointingHand)
+        .on_hover_text(
+            node.path
+                .strip_prefix(root)
+                .unwrap_or(&node.path)
+                .display()
+                .to_string(),
+        );
+    response.clicked().then(|| node.path.clone())
 }
 
-/// Per-frame return from `render_search_bar`
-#[derive(Default)]
-struct SearchBarOutcome {
-    close_requested: bool,
-    prev_clicked: bool,
-    next_clicked: bool,
+/// The rows of a compare tab: the blocks of the two documents side by side,
+/// or the changes one after the other, removed blocks tinted red and added
+/// ones green. Only the rows in view are laid out. `path` is the newer
+/// document.
+fn show_comparison(
+    ui: &mut egui::Ui,
+    comparison: &mut Comparison,
+    cache: &mut CommonMarkCache,
+    path: &Path,
+) {
+    let changes = comparison
+        .rows
+        .iter()
+        .filter(|row| matches!(row, compare::Row::Changed { .. }))
+        .count();
+    let was_side_by_side = comparison.side_by_side;
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut comparison.side_by_side, true, "Side by Side");
+        ui.selectable_value(&mut comparison.side_by_side, false, "Inline");
+        ui.separator();
+        ui.label(match changes {
+            0 => "No changes".to_string(),
+            1 => "1 change".to_string(),
+            n => format!("{n} changes"),
+        });
+        if comparison.diff.is_some() {
+            ui.spinner().on_hover_text("Comparing the documents again");
+        }
+        ui.separator();
+        ui.weak(format!("{} → {}", comparison.old.display(), path.display()));
+    });
+    ui.separator();
+    if comparison.side_by_side != was_side_by_side {
+        comparison.heights.clear();
+    }
+
+    // The tints of removed and added lines in diff code blocks
+    let (removed, added) = if ui.visuals().dark_mode {
+        (
+            egui::Color32::from_rgb(0x5a, 0x22, 0x26),
+            egui::Color32::from_rgb(0x1f, 0x4a, 0x2c),
+        )
+    } else {
+        (
+            egui::Color32::from_rgb(0xff, 0xdc, 0xe0),
+            egui::Color32::from_rgb(0xd7, 0xf5, 0xdd),
+        )
+    };
+    let old_uri = Tab::compute_base_uri(&comparison.old);
+    let new_uri = Tab::compute_base_uri(path);
+    let block = |ui: &mut egui::Ui,
+                 cache: &mut CommonMarkCache,
+                 block: &compare::Block,
+                 base_uri: &str,
+                 tint: Option<egui::Color32>| {
+        egui::Frame::NONE
+            .fill(tint.unwrap_or_default())
+            .inner_margin(egui::Margin::symmetric(6, 2))
+            .corner_radius(3.0)
+            .show(ui, |ui| {
+                ui.set_width(ui.available_width());
+                ui.push_id(block.line, |ui| {
+                    CommonMarkViewer::new()
+                        .default_implicit_uri_scheme(base_uri)
+                        .max_image_width(Some(ui.available_width() as usize))
+                        .indentation_spaces(2)
+                        .show(ui, cache, &block.source);
+                });
+            })
+            .response
+            .on_hover_text(format!("Line {}", block.line));
+    };
+    let side_by_side = comparison.side_by_side;
+    let row = |ui: &mut egui::Ui, cache: &mut CommonMarkCache, row: &compare::Row| match row {
+        compare::Row::Same(old, new) if side_by_side => {
+            ui.columns(2, |columns| {
+                block(&mut columns[0], cache, old, &old_uri, None);
+                block(&mut columns[1], cache, new, &new_uri, None);
+            });
+        }
+        compare::Row::Same(_, new) => block(ui, cache, new, &new_uri, None),
+        compare::Row::Changed { old, new } if side_by_side => {
+            ui.columns(2, |columns| {
+                for old in old {
+                    block(&mut columns[0], cache, old, &old_uri, Some(removed));
+                }
+                for new in new {
+                    block(&mut columns[1], cache, new, &new_uri, Some(added));
+                }
+            });
+        }
+        compare::Row::Changed { old, new } => {
+            for old in old {
+                block(ui, cache, old, &old_uri, Some(removed));
+            }
+            for new in new {
+                block(ui, cache, new, &new_uri, Some(added));
+            }
+        }
+    };
+
+    // Rows not shown yet are as high as their lines, until they are
+    let line_height = ui.text_style_height(&egui::TextStyle::Body) * 1.5;
+    let rows = &comparison.rows;
+    let heights = &mut comparison.heights;
+    if heights.len() != rows.len() {
+        *heights = rows
+            .iter()
+            .map(|row| estimated_row_height(row, side_by_side, line_height))
+            .collect();
+    }
+    let tops: Vec<f32> = std::iter::once(0.0)
+        .chain(heights.iter().scan(0.0, |top, height| {
+            *top += height;
+            Some(*top)
+        }))
+        .collect();
+    egui::ScrollArea::vertical()
+        .id_salt(("compare", path))
+        .auto_shrink(false)
+        .show_viewport(ui, |ui, viewport| {
+            ui.set_min_height(tops[rows.len()]);
+            let origin = ui.max_rect().min;
+            let first = tops
+                .partition_point(|&top| top <= viewport.min.y)
+                .saturating_sub(1);
+            let last = tops
+                .partition_point(|&top| top < viewport.max.y)
+                .min(rows.len());
+            let mut measured = false;
+            for i in first..last {
+                let rect = egui::Rect::from_min_size(
+                    origin + egui::vec2(0.0, tops[i]),
+                    egui::vec2(ui.available_width(), heights[i]),
+                );
+                let response = ui
+                    .scope_builder(egui::UiBuilder::new().max_rect(rect).id_salt(i), |ui| {
+                        row(ui, cache, &rows[i]);
+                    })
+                    .response;
+                let height = response.rect.height() + ui.spacing().item_spacing.y;
+                if (height - heights[i]).abs() > 0.5 {
+                    heights[i] = height;
+                    measured = true;
+                }
+            }
+            // Lay the rows below out again where they really are
+            if measured {
+                ui.ctx().request_repaint();
+            }
+        });
 }
 
-/// App-level search state — only one find bar is visible at a time
-#[derive(Default)]
-struct SearchState {
-    is_open: bool,
-    query: String,
-    /// Shadow copy of `query` used to detect changes across frames
-    last_query: String,
-    /// Tab index the cached matches were built for; `None` forces rebuild
-    last_tab: Option<usize>,
-    /// Set after Ctrl+F so the text input is focused next frame
-    focus_requested: bool,
-    /// Index into the active tab's `search_matches`
-    active_match_index: usize,
+/// The height a row of a comparison likely takes, before it's shown.
+fn estimated_row_height(row: &compare::Row, side_by_side: bool, line_height: f32) -> f32 {
+    let lines = |block: &compare::Block| block.source.lines().count() + 1;
+    let lines = match row {
+        compare::Row::Same(old, new) if side_by_side => lines(old).max(lines(new)),
+        compare::Row::Same(_, new) => lines(new),
+        compare::Row::Changed { old, new } => {
+            let old: usize = old.iter().map(lines).sum();
+            let new: usize = new.iter().map(lines).sum();
+            if side_by_side {
+                old.max(new)
+            } else {
+                old + new
+            }
+        }
+    };
+    lines as f32 * line_height
 }
 
-/// Action from file explorer interaction
-#[derive(Default)]
-struct ExplorerAction {
-    /// File to open in a new tab (left-click)
-    file_to_open: Option<PathBuf>,
-    /// File to close (middle-click on open file)
-    file_to_close: Option<PathBuf>,
-    /// Directory to toggle expansion (deferred to avoid clone)
-    dir_to_toggle: Option<PathBuf>,
+/// GUI editors `$VISUAL` or `$EDITOR` may name, with the arguments opening
+/// a file at a line.
+const GUI_EDITORS: &[(&str, &str)] = &[
+    ("code", "--goto {file}:{line}"),
+    ("code-insiders", "--goto {file}:{line}"),
+    ("codium", "--goto {file}:{line}"),
+    ("subl", "{file}:{line}"),
+    ("zed", "{file}:{line}"),
+    ("gvim", "+{line} {file}"),
+    ("mvim", "+{line} {file}"),
+    ("gedit", "+{line} {file}"),
+    ("kate", "--line {line} {file}"),
+];
+
+/// The editor command used until one is set in the File menu. A GUI editor
+/// in `$VISUAL` or `$EDITOR` opens at the line. A terminal one (vim, nano,
+/// …) needs a terminal, as the viewer starts editors without one, so it runs
+/// in `$TERMINAL` when that is set. Otherwise the system's default app opens
+/// the file.
+fn default_editor_command(
+    visual: Option<&str>,
+    editor: Option<&str>,
+    terminal: Option<&str>,
+) -> String {
+    let editor = [visual, editor]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .find(|e| !e.is_empty());
+    let program = editor
+        .and_then(|e| e.split_whitespace().next())
+        .and_then(|program| Path::new(program).file_name())
+        .map(|name| name.to_string_lossy().into_owned());
+    let gui = program.and_then(|program| GUI_EDITORS.iter().find(|(name, _)| *name == program));
+    let terminal = terminal.map(str::trim).filter(|t| !t.is_empty());
+    match (editor, gui, terminal) {
+        (Some(editor), Some((_, args)), _) => format!("{editor} {args}"),
+        (Some(editor), None, Some(terminal)) => {
+            format!("{terminal} -e {editor} +{{line}} {{file}}")
+        }
+        _ if cfg!(windows) => "notepad {file}".to_string(),
+        _ if cfg!(target_os = "macos") => "open -t {file}".to_string(),
+        _ => "xdg-open {file}".to_string(),
+    }
 }
 
-/// Sort order for file explorer
-#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
-enum SortOrder {
-    #[default]
-    NameAsc,
-    NameDesc,
-    DateAsc,
-    DateDesc,
+/// Split an editor command `template` into the program and its arguments,
+/// then fill in `{file}` and `{line}`. Splitting first keeps paths with
+/// spaces in one argument.
+fn editor_command_args(template: &str, file: &Path, line: usize) -> Vec<String> {
+    let file = file.display().to_string();
+    let line = line.to_string();
+    template
+        .split_whitespace()
+        .map(|arg| arg.replace("{file}", &file).replace("{line}", &line))
+        .collect()
 }
 
-impl SortOrder {
-    fn label(&self) -> &'static str {
-        match self {
-            SortOrder::NameAsc => "Name A-Z",
-            SortOrder::NameDesc => "Name Z-A",
-            SortOrder::DateAsc => "Oldest First",
-            SortOrder::DateDesc => "Newest First",
+/// A `file://` URI for `path`: forward slashes, a leading slash before
+/// Windows drive letters, and bytes outside unreserved URI characters
+/// percent-encoded.
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
         }
     }
+    uri
 }
 
-/// A node in the file explorer tree
-#[derive(Clone)]
-enum FileTreeNode {
-    File {
-        path: PathBuf,
-        name: String,
-        display_name: String,
-        modified: Option<std::time::SystemTime>,
-    },
-    Directory {
-        path: PathBuf,
-        name: String,
-        display_name: String,
-        modified: Option<std::time::SystemTime>,
-        /// None = not yet loaded, Some = loaded (may be empty)
-        children: Option<Vec<FileTreeNode>>,
-    },
+/// Show `path` selected in the system file manager: Explorer on Windows,
+/// Finder on macOS, and elsewhere the file manager answering the freedesktop
+/// `FileManager1` D-Bus interface, falling back to opening the containing
+/// folder. Runs in the background since D-Bus can take a moment to answer.
+fn reveal_in_file_manager(path: &Path) {
+    let path = path.to_path_buf();
+    std::thread::spawn(move || {
+        let status = if cfg!(windows) {
+            let mut select = OsString::from("/select,");
+            select.push(&path);
+            Command::new("explorer").arg(select).status()
+        } else if cfg!(target_os = "macos") {
+            Command::new("open").arg("-R").arg(&path).status()
+        } else {
+            let shown = Command::new("dbus-send")
+                .args([
+                    "--session",
+                    "--print-reply",
+                    "--dest=org.freedesktop.FileManager1",
+                    "/org/freedesktop/FileManager1",
+                    "org.freedesktop.FileManager1.ShowItems",
+                ])
+                .arg(format!("array:string:{}", file_uri(&path)))
+                .arg("string:")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+            if shown {
+                return;
+            }
+            let folder = path.parent().unwrap_or(&path);
+            Command::new("xdg-open").arg(folder).status()
+        };
+        if let Err(e) = status {
+            log::warn!(
+                "Failed to reveal {} in the file manager: {e}",
+                path.display()
+            );
+        }
+    });
 }
 
-impl FileTreeNode {
-    fn name(&self) -> &str {
-        match self {
-            FileTreeNode::File { name, .. } => name,
-            FileTreeNode::Directory { name, .. } => name,
+/// The 1-based source line of the first block reaching below `scroll_offset`.
+fn line_at_scroll(blocks: &[SourceBlock], scroll_offset: f32) -> usize {
+    let first = blocks.partition_point(|b| b.y_range.max <= scroll_offset);
+    blocks.get(first).or(blocks.last()).map_or(1, |b| b.line)
+}
+
+/// Runs Rust code blocks until another command is set in the File menu.
+const DEFAULT_RUST_RUNNER: &str = "rust-script {file}";
+
+/// How a fenced code block runs.
+#[derive(Clone, Debug, PartialEq)]
+enum CodeRunner {
+    /// `shell -c <code>`
+    Shell(&'static str),
+    /// A command template such as `rust-script {file}`, with `{file}` filled
+    /// in with a temporary `.rs` file holding the code
+    Rust(String),
+}
+
+/// How a fenced code block tagged `lang` runs, for the languages that get a
+/// Run button. Rust blocks need a `rust_runner` command, and rustdoc's
+/// `ignore`, `no_run` and `compile_fail` blocks aren't run.
+fn code_block_runner(lang: &str, rust_runner: &str) -> Option<CodeRunner> {
+    let mut attributes = lang.split(',').map(str::trim);
+    match attributes.next()? {
+        "bash" => Some(CodeRunner::Shell("bash")),
+        "sh" | "shell" => Some(CodeRunner::Shell("sh")),
+        "rust" if !rust_runner.trim().is_empty() => {
+            let runnable = attributes.all(|a| !matches!(a, "ignore" | "no_run" | "compile_fail"));
+            runnable.then(|| CodeRunner::Rust(rust_runner.to_string()))
         }
+        _ => None,
     }
+}
 
-    fn modified(&self) -> Option<std::time::SystemTime> {
+impl CodeRunner {
+    /// The program running the code, for the Run button and confirmation.
+    fn program(&self) -> &str {
         match self {
-            FileTreeNode::File { modified, .. } => *modified,
-            FileTreeNode::Directory { modified, .. } => *modified,
+            Self::Shell(shell) => shell,
+            Self::Rust(template) => template.split_whitespace().next().unwrap_or_default(),
         }
     }
 
-    fn is_directory(&self) -> bool {
-        matches!(self, FileTreeNode::Directory { .. })
-    }
-}
-
-/// File explorer state
-#[derive(Default)]
-struct FileExplorer {
-    root: Option<PathBuf>,
-    tree: Vec<FileTreeNode>,
-    expanded_dirs: HashSet<PathBuf>,
-    sort_order: SortOrder,
-    /// Receiver for async directory scan results (GVFS paths scan in background)
-    pending_scan: Option<Receiver<Vec<FileTreeNode>>>,
+    /// Run `code` in `dir`, waiting for it to finish or for `stop`.
+    fn run(&self, code: &str, dir: Option<&Path>, stop: &AtomicBool) -> CodeRunOutput {
+        match self {
+            Self::Shell(shell) => {
+                let mut command = Command::new(shell);
+                command.arg("-c").arg(code);
+                command_output(command, dir, stop)
+            }
+            Self::Rust(template) => {
+                // A file of its own per run, as the same code can run twice
+                // at once; removed when dropped, whatever the run did
+                let file = tempfile::Builder::new()
+                    .prefix("md-viewer-")
+                    .suffix(".rs")
+                    .tempfile()
+                    .and_then(|mut file| {
+                        file.write_all(code.as_bytes())?;
+                        file.flush()?;
+                        Ok(file)
+                    });
+                let file = match file {
+                    Ok(file) => file,
+                    Err(e) => {
+                        return CodeRunOutput {
+                            finished: true,
+                            stderr: format!("Failed to write the code to a temporary file: {e}"),
+                            ..Default::default()
+                        };
+                    }
+                };
+                let file_arg = file.path().display().to_string();
+                let args: Vec<String> = template
+                    .split_whitespace()
+                    .map(|arg| arg.replace("{file}", &file_arg))
+                    .collect();
+                match args.split_first() {
+                    Some((program, args)) => {
+                        let mut command = Command::new(program);
+                        command.args(args);
+                        command_output(command, dir, stop)
+                    }
+                    None => CodeRunOutput::default(),
+                }
+            }
+        }
+    }
 }
 
-impl FileExplorer {
-    /// Scan a directory shallowly - only one level, subdirectories marked as unloaded
-    fn scan_directory_shallow(path: &PathBuf, sort_order: SortOrder) -> Vec<FileTreeNode> {
-        let Ok(entries) = fs::read_dir(path) else {
-            return Vec::new();
-        };
+/// Output kept per stream of a code block run; a run printing more is
+/// stopped and the rest cut off.
+const CODE_RUN_OUTPUT_LIMIT: usize = 64 * 1024;
 
-        let mut nodes: Vec<FileTreeNode> = Vec::new();
+/// How long a code block may run before it is stopped.
+const CODE_RUN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 
-        for entry in entries.flatten() {
-            let entry_path = entry.path();
-            let name = entry.file_name().to_string_lossy().to_string();
+/// What running a code block printed, filled in by the thread running it.
+#[derive(Default)]
+struct CodeRunOutput {
+    finished: bool,
+    stdout: String,
+    stderr: String,
+    /// Exit code, `None` while running or when killed by a signal
+    status: Option<i32>,
+    /// Set by the Stop button to kill the run
+    stop: Arc<AtomicBool>,
+}
 
-            // Skip hidden files
-            if name.starts_with('.') {
-                continue;
-            }
+/// Code blocks the user ran from documents, by block id.
+#[derive(Default)]
+struct CodeRuns {
+    /// The block waiting for the user to confirm running it: its id, runner,
+    /// code and the folder it runs in
+    pending: Option<(egui::Id, CodeRunner, String, Option<PathBuf>)>,
+    outputs: HashMap<egui::Id, Arc<Mutex<CodeRunOutput>>>,
+}
 
-            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+/// Cut `text` to `CODE_RUN_OUTPUT_LIMIT` bytes on a character boundary.
+fn limit_output(mut text: String) -> String {
+    if text.len() > CODE_RUN_OUTPUT_LIMIT {
+        let mut end = CODE_RUN_OUTPUT_LIMIT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n[output truncated]");
+    }
+    text
+}
 
-            if entry_path.is_dir() {
-                // Show all directories - let users expand what they want
-                // (Avoids O(n×m) scanning during initial directory scan)
-                let display_name = truncate_display_name(&name, 22);
-                nodes.push(FileTreeNode::Directory {
-                    path: entry_path,
-                    name,
-                    display_name,
-                    modified,
-                    children: None, // Lazy - not loaded yet
-                });
-            } else if Self::is_markdown_file(&entry_path) {
-                let display_name = truncate_display_name(&name, 25);
-                nodes.push(FileTreeNode::File {
-                    path: entry_path,
-                    name,
-                    display_name,
-                    modified,
-                });
+/// Run `command` in `dir`, waiting for it to finish. It is killed, with
+/// whatever it started, on `stop`, after `CODE_RUN_TIMEOUT` or once it
+/// printed more than `CODE_RUN_OUTPUT_LIMIT`.
+fn command_output(mut command: Command, dir: Option<&Path>, stop: &AtomicBool) -> CodeRunOutput {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    // A group of its own, to kill what a shell started along with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            return CodeRunOutput {
+                finished: true,
+                stderr: format!("Failed to start {:?}: {e}", command.get_program()),
+                ..Default::default()
+            };
+        }
+    };
+    let full = Arc::new(AtomicBool::new(false));
+    let stdout = child
+        .stdout
+        .take()
+        .map(|pipe| read_run_output(pipe, full.clone()));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|pipe| read_run_output(pipe, full.clone()));
+    let started = Instant::now();
+    let mut stopped = None;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.code(),
+            Ok(None) => {}
+            Err(_) => break None,
+        }
+        if stopped.is_none() {
+            stopped = if stop.load(Ordering::Relaxed) {
+                Some("stopped")
+            } else if full.load(Ordering::Relaxed) {
+                Some("stopped after printing too much")
+            } else if started.elapsed() > CODE_RUN_TIMEOUT {
+                Some("stopped after running too long")
+            } else {
+                None
+            };
+            if stopped.is_some() {
+                kill_run(&mut child);
             }
         }
-
-        Self::sort_nodes(&mut nodes, sort_order);
-        nodes
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    if stopped.is_none() {
+        // Whatever it left running in the background holds on to the pipes
+        kill_run(&mut child);
+        // The pipe closed once full may have ended it first
+        if full.load(Ordering::Relaxed) {
+            stopped = Some("stopped after printing too much");
+        }
+    }
+    let join = |reader: Option<std::thread::JoinHandle<String>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+    };
+    let mut stderr = join(stderr);
+    if let Some(stopped) = stopped {
+        stderr.push_str(&format!("\n[{stopped}]"));
+    }
+    CodeRunOutput {
+        finished: true,
+        stdout: join(stdout),
+        stderr,
+        status,
+        ..Default::default()
     }
+}
 
-    /// Sort nodes according to the given sort order (directories always on top)
-    fn sort_nodes(nodes: &mut [FileTreeNode], sort_order: SortOrder) {
-        nodes.sort_by(|a, b| {
-            // Directories always come first
-            match (a.is_directory(), b.is_directory()) {
-                (true, false) => return std::cmp::Ordering::Less,
-                (false, true) => return std::cmp::Ordering::Greater,
-                _ => {}
-            }
+/// Read `pipe` on a thread, up to `CODE_RUN_OUTPUT_LIMIT`; `full` is set
+/// when there was more.
+fn read_run_output(
+    pipe: impl Read + Send + 'static,
+    full: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe
+            .take(CODE_RUN_OUTPUT_LIMIT as u64 + 1)
+            .read_to_end(&mut bytes);
+        if bytes.len() > CODE_RUN_OUTPUT_LIMIT {
+            full.store(true, Ordering::Relaxed);
+        }
+        limit_output(String::from_utf8_lossy(&bytes).into_owned())
+    })
+}
 
-            // Within the same type, sort by the selected criteria
-            match sort_order {
-                SortOrder::NameAsc => a.name().cmp(b.name()),
-                SortOrder::NameDesc => b.name().cmp(a.name()),
-                SortOrder::DateAsc => {
-                    // Oldest first: None (unknown) sorts last
-                    match (a.modified(), b.modified()) {
-                        (Some(a_time), Some(b_time)) => a_time.cmp(&b_time),
-                        (Some(_), None) => std::cmp::Ordering::Less,
-                        (None, Some(_)) => std::cmp::Ordering::Greater,
-                        (None, None) => a.name().cmp(b.name()),
-                    }
-                }
-                SortOrder::DateDesc => {
-                    // Newest first: None (unknown) sorts last
-                    match (a.modified(), b.modified()) {
-                        (Some(a_time), Some(b_time)) => b_time.cmp(&a_time),
-                        (Some(_), None) => std::cmp::Ordering::Less,
-                        (None, Some(_)) => std::cmp::Ordering::Greater,
-                        (None, None) => a.name().cmp(b.name()),
-                    }
-                }
-            }
-        });
+/// Kill a code block run and the processes it started.
+#[cfg(unix)]
+fn kill_run(child: &mut std::process::Child) {
+    extern "C" {
+        fn kill(pid: i32, signal: i32) -> i32;
     }
-
-    fn is_markdown_file(path: &Path) -> bool {
-        path.extension()
-            .map(|ext| {
-                let ext = ext.to_string_lossy().to_lowercase();
-                ext == "md" || ext == "markdown" || ext == "txt"
-            })
-            .unwrap_or(false)
+    const SIGKILL: i32 = 9;
+    if let Ok(pid) = i32::try_from(child.id()) {
+        // SAFETY: the run is the leader of its own process group
+        unsafe { kill(-pid, SIGKILL) };
     }
+    let _ = child.kill();
+}
 
-    /// Set root directory and rescan (shallow).
-    /// For GVFS paths, scan runs in a background thread to avoid blocking the UI.
-    fn set_root(&mut self, path: PathBuf) {
-        // Convert empty path to current directory
-        let path = if path.as_os_str().is_empty() {
-            std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
-        } else {
-            path
-        };
-        self.root = Some(path.clone());
-        if is_gvfs_path(&path) {
-            // Scan in background thread — tree populates when ready
-            let sort_order = self.sort_order;
-            let (tx, rx) = mpsc::channel();
-            std::thread::Builder::new()
-                .name("gvfs-scan".into())
-                .spawn(move || {
-                    let tree = Self::scan_directory_shallow(&path, sort_order);
-                    let _ = tx.send(tree);
-                })
-                .expect("failed to spawn GVFS scan thread");
-            self.pending_scan = Some(rx);
-        } else {
-            self.tree = Self::scan_directory_shallow(&path, self.sort_order);
-        }
-    }
+#[cfg(not(unix))]
+fn kill_run(child: &mut std::process::Child) {
+    let _ = child.kill();
+}
 
-    /// Check if a background scan completed and apply results
-    fn poll_pending_scan(&mut self) -> bool {
-        if let Some(rx) = &self.pending_scan {
-            if let Ok(tree) = rx.try_recv() {
-                self.tree = tree;
-                self.pending_scan = None;
-                return true;
+/// The footer with the Run button for the code blocks of a tab: only when
+/// running code is allowed and the tab isn't sandboxed, as an untrusted
+/// document shouldn't be one click away from running its code.
+fn code_run_footer(
+    allowed: bool,
+    sandboxed: bool,
+    footer: &CodeBlockFooterFn,
+) -> Option<&CodeBlockFooterFn> {
+    (allowed && !sandboxed).then_some(footer)
+}
+
+/// The Run button below a shell or Rust code block, and what its last run
+/// printed.
+fn code_block_run_footer(
+    ui: &mut egui::Ui,
+    runs: &RefCell<CodeRuns>,
+    dir: Option<&Path>,
+    rust_runner: &str,
+    (lang, code, id): (&str, &str, egui::Id),
+) {
+    let Some(runner) = code_block_runner(lang, rust_runner) else {
+        return;
+    };
+    let output = runs.borrow().outputs.get(&id).cloned();
+    let mut clear = false;
+    ui.horizontal(|ui| {
+        let running = output
+            .as_ref()
+            .is_some_and(|output| !output.lock().is_ok_and(|output| output.finished));
+        if running {
+            ui.spinner();
+            ui.label(egui::RichText::new("Running…").weak().small());
+            if ui.small_button("■ Stop").clicked() {
+                if let Some(output) = output.as_ref().and_then(|output| output.lock().ok()) {
+                    output.stop.store(true, Ordering::Relaxed);
+                }
+            }
+        } else if ui
+            .small_button("▶ Run")
+            .on_hover_text(format!(
+                "Run with {} in the document's folder",
+                runner.program()
+            ))
+            .clicked()
+        {
+            let dir = dir.map(Path::to_path_buf);
+            runs.borrow_mut().pending = Some((id, runner, code.to_string(), dir));
+        }
+        if let Some(output) = output.as_ref().and_then(|output| output.lock().ok()) {
+            if output.finished {
+                let status = match output.status {
+                    Some(code) => format!("exit {code}"),
+                    None => "terminated".to_string(),
+                };
+                ui.label(egui::RichText::new(status).weak().small());
+                clear = ui.small_button("Clear").clicked();
             }
         }
-        false
+    });
+    if clear {
+        runs.borrow_mut().outputs.remove(&id);
+        return;
     }
-
-    /// Refresh the file tree (clears loaded state, rescans shallowly).
-    /// For GVFS paths, runs in background to avoid blocking the UI thread.
-    fn refresh(&mut self) {
-        if let Some(root) = &self.root.clone() {
-            if is_gvfs_path(root) {
-                // Re-scan in background
-                let sort_order = self.sort_order;
-                let root = root.clone();
-                let (tx, rx) = mpsc::channel();
-                std::thread::Builder::new()
-                    .name("gvfs-refresh".into())
-                    .spawn(move || {
-                        let tree = Self::scan_directory_shallow(&root, sort_order);
-                        let _ = tx.send(tree);
-                    })
-                    .expect("failed to spawn GVFS refresh thread");
-                self.pending_scan = Some(rx);
-                return;
+    let Some(output) = output else {
+        return;
+    };
+    let Ok(output) = output.lock() else {
+        return;
+    };
+    if output.stdout.is_empty() && output.stderr.is_empty() {
+        return;
+    }
+    egui::CollapsingHeader::new("Output")
+        .id_salt(id.with("run_output"))
+        .default_open(true)
+        .show(ui, |ui| {
+            if !output.stdout.is_empty() {
+                ui.label(egui::RichText::new(output.stdout.trim_end()).monospace());
             }
-            self.tree = Self::scan_directory_shallow(root, self.sort_order);
-            // Re-load children for currently expanded directories
-            let expanded: Vec<PathBuf> = self.expanded_dirs.iter().cloned().collect();
-            for dir_path in expanded {
-                self.load_children(&dir_path);
+            if !output.stderr.is_empty() {
+                let color = ui.visuals().error_fg_color;
+                ui.label(
+                    egui::RichText::new(output.stderr.trim_end())
+                        .monospace()
+                        .color(color),
+                );
             }
-        }
+        });
+}
+
+/// Ask where to save an image picked in the image context menu and write it.
+fn save_image_as(name: &str, bytes: &[u8]) {
+    let Some(path) = rfd::FileDialog::new().set_file_name(name).save_file() else {
+        return;
+    };
+    if let Err(e) = std::fs::write(&path, bytes) {
+        log::warn!("Failed to save image to {}: {e}", path.display());
     }
+}
 
-    /// Load children for a specific directory (lazy loading)
-    fn load_children(&mut self, dir_path: &PathBuf) {
-        Self::load_children_in_tree(&mut self.tree, dir_path, self.sort_order);
+/// `$XDG_CONFIG_HOME/md-viewer`, or `~/.config/md-viewer`, holding the
+/// user's plugins, scripts, syntax themes and custom alerts.
+fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("md-viewer"))
+}
+
+/// Where session snapshots are written while the app runs: next to eframe's
+/// persisted state, one per running viewer (e.g.
+/// `~/.local/share/md-viewer/session-snapshot-<pid>.json`).
+fn session_snapshot_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_NAME)
+}
+
+/// The session snapshot of the viewer with `pid`.
+fn session_snapshot_file(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("session-snapshot-{pid}.json"))
+}
+
+/// The pid of the viewer that wrote the session snapshot at `path`.
+fn session_snapshot_owner(path: &Path) -> Option<u32> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("session-snapshot-")?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+/// Take over the latest session snapshot in `dir` whose viewer is no longer
+/// running, i.e. didn't exit cleanly. It becomes the snapshot of the viewer
+/// with `pid`; as only one viewer can move it, a crashed session is offered
+/// once however many viewers start. Older snapshots of viewers that are gone
+/// are removed.
+fn claim_crashed_session(
+    dir: &Path,
+    pid: u32,
+    is_running: impl Fn(u32) -> bool,
+) -> Option<PersistedState> {
+    let own = session_snapshot_file(dir, pid);
+    let mut crashed: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            // A snapshot under our own pid is from an earlier process
+            session_snapshot_owner(path).is_some_and(|owner| owner == pid || !is_running(owner))
+        })
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .collect();
+    crashed.sort();
+    let mut older = crashed.into_iter().rev().map(|(_, path)| path);
+    let session = older.find_map(|path| {
+        fs::rename(&path, &own).ok()?;
+        read_session_snapshot(&own)
+            .filter(|session| session.open_tabs.as_ref().is_some_and(|t| !t.is_empty()))
+    })?;
+    // Older crashed sessions would otherwise be offered on every later start
+    for path in older {
+        let _ = fs::remove_file(path);
+    }
+    Some(session)
+}
+
+/// Whether the process with `pid` still runs.
+#[cfg(unix)]
+fn process_is_running(pid: u32) -> bool {
+    extern "C" {
+        fn kill(pid: i32, signal: i32) -> i32;
     }
+    let Some(pid) = i32::try_from(pid).ok().filter(|&pid| pid > 0) else {
+        return false;
+    };
+    // SAFETY: signal 0 sends nothing, it only checks that the process exists
+    let exists = unsafe { kill(pid, 0) } == 0;
+    // Not ours to signal, but running
+    exists || io::Error::last_os_error().kind() == io::ErrorKind::PermissionDenied
+}
 
-    /// Recursively find and load children for a directory in the tree
-    fn load_children_in_tree(
-        nodes: &mut [FileTreeNode],
-        target_path: &PathBuf,
-        sort_order: SortOrder,
-    ) -> bool {
-        for node in nodes.iter_mut() {
-            if let FileTreeNode::Directory { path, children, .. } = node {
-                if path == target_path {
-                    // Found the target directory - load its children if not loaded
-                    if children.is_none() {
-                        *children = Some(Self::scan_directory_shallow(path, sort_order));
-                    }
-                    return true;
-                }
-                // Recurse into loaded children
-                if let Some(ref mut child_nodes) = children {
-                    if Self::load_children_in_tree(child_nodes, target_path, sort_order) {
-                        return true;
-                    }
-                }
-            }
-        }
-        false
+#[cfg(windows)]
+fn process_is_running(pid: u32) -> bool {
+    use std::ffi::c_void;
+
+    extern "system" {
+        fn OpenProcess(access: u32, inherit: i32, pid: u32) -> *mut c_void;
+        fn GetExitCodeProcess(process: *mut c_void, code: *mut u32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
     }
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+    const STILL_ACTIVE: u32 = 259;
 
-    /// Toggle directory expansion (loads children if not yet loaded)
-    fn toggle_expanded(&mut self, path: &PathBuf) {
-        if self.expanded_dirs.contains(path) {
-            self.expanded_dirs.remove(path);
-        } else {
-            // Load children before expanding if not yet loaded
-            self.load_children(path);
-            self.expanded_dirs.insert(path.clone());
+    // SAFETY: the handle is checked before use and closed after
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return false;
         }
+        let mut code = 0;
+        let running = GetExitCodeProcess(process, &mut code) != 0 && code == STILL_ACTIVE;
+        CloseHandle(process);
+        running
     }
+}
 
-    /// Check if a directory is expanded
-    fn is_expanded(&self, path: &PathBuf) -> bool {
-        self.expanded_dirs.contains(path)
-    }
+#[cfg(not(any(unix, windows)))]
+fn process_is_running(_pid: u32) -> bool {
+    false
+}
 
-    /// Set sort order and re-sort the tree in place
-    fn set_sort_order(&mut self, order: SortOrder) {
-        if self.sort_order != order {
-            self.sort_order = order;
-            Self::resort_tree_recursive(&mut self.tree, order);
-        }
+/// The session snapshot at `path`, left behind by a run that didn't exit
+/// cleanly.
+fn read_session_snapshot(path: &Path) -> Option<PersistedState> {
+    let json = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json)
+        .map_err(|e| log::warn!("Ignoring session snapshot {}: {e}", path.display()))
+        .ok()
+}
+
+/// Write `json` to `path` through a temporary file, so a crash mid-write
+/// leaves the previous snapshot intact.
+fn write_session_snapshot(path: &Path, json: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
     }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(tmp, path)
+}
 
-    /// Recursively re-sort all nodes in the tree
-    fn resort_tree_recursive(nodes: &mut [FileTreeNode], sort_order: SortOrder) {
-        Self::sort_nodes(nodes, sort_order);
-        for node in nodes.iter_mut() {
-            if let FileTreeNode::Directory {
-                children: Some(ref mut child_nodes),
-                ..
-            } = node
-            {
-                Self::resort_tree_recursive(child_nodes, sort_order);
-            }
-        }
-    }
-
-    /// Get children for a directory by path (looks up in original tree, not clone)
-    fn get_children(&self, target_path: &PathBuf) -> Option<&Vec<FileTreeNode>> {
-        Self::find_children_in_tree(&self.tree, target_path)
-    }
+/// Tabs for the files of a saved session that still exist, with their
+/// history and scrolled to where they were read.
+fn session_tabs(
+    paths: Vec<PathBuf>,
+    histories: Vec<TabHistory>,
+    reading_positions: &[ReadingPosition],
+) -> Vec<Tab> {
+    let mut histories = histories.into_iter();
+    paths
+        .into_iter()
+        .filter_map(|p| {
+            let history = histories.next().unwrap_or_default();
+            p.exists().then(|| {
+                let mut tab = Tab::new(p);
+                tab.restore_history(history);
+                tab.pending_line = saved_reading_line(reading_positions, &tab.path);
+                tab
+            })
+        })
+        .collect()
+}
 
-    /// Recursively find children for a directory in the tree
-    fn find_children_in_tree<'a>(
-        nodes: &'a [FileTreeNode],
-        target_path: &PathBuf,
-    ) -> Option<&'a Vec<FileTreeNode>> {
-        for node in nodes {
-            if let FileTreeNode::Directory { path, children, .. } = node {
-                if path == target_path {
-                    return children.as_ref();
-                }
-                // Recurse into loaded children
-                if let Some(child_nodes) = children {
-                    if let Some(found) = Self::find_children_in_tree(child_nodes, target_path) {
-                        return Some(found);
-                    }
-                }
-            }
-        }
-        None
-    }
+/// Folder scanned for extra `.tmTheme` syntax themes:
+/// `$XDG_CONFIG_HOME/md-viewer/syntax-themes`, or `~/.config/md-viewer/syntax-themes`.
+fn user_syntax_theme_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("syntax-themes"))
+}
 
-    /// Maximum depth for expand_all to prevent excessive recursion
-    const MAX_EXPAND_DEPTH: usize = 10;
+/// Web pages opened by URL, downloaded to be shown in reader view.
+fn article_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_NAME).map(|dir| dir.join("articles"))
+}
 
-    /// Expand all directories in the tree (loads all children recursively up to MAX_EXPAND_DEPTH)
-    fn expand_all(&mut self) {
-        // First, recursively load all directories (with depth limit)
-        Self::load_all_children(&mut self.tree, self.sort_order, 0);
-        // Then collect all directory paths
-        self.expanded_dirs = Self::collect_all_dirs(&self.tree);
-    }
+/// Remote images downloaded by any tab, kept for later runs and offline use.
+fn image_cache_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_NAME).map(|dir| dir.join("image-cache"))
+}
 
-    /// Recursively load all unloaded directories (up to MAX_EXPAND_DEPTH)
-    fn load_all_children(nodes: &mut [FileTreeNode], sort_order: SortOrder, depth: usize) {
-        if depth >= Self::MAX_EXPAND_DEPTH {
-            return;
-        }
-        for node in nodes.iter_mut() {
-            if let FileTreeNode::Directory { path, children, .. } = node {
-                // Load children if not yet loaded
-                if children.is_none() {
-                    *children = Some(Self::scan_directory_shallow(path, sort_order));
-                }
-                // Recurse into children
-                if let Some(ref mut child_nodes) = children {
-                    Self::load_all_children(child_nodes, sort_order, depth + 1);
-                }
-            }
+/// Syntax themes from the user folder, loaded once and shared by every
+/// document's render cache.
+static DOCUMENT_RESOURCES: LazyLock<CommonMarkCache> = LazyLock::new(|| {
+    let mut cache = CommonMarkCache::default();
+    if let Some(dir) = user_syntax_theme_dir().filter(|d| d.is_dir()) {
+        if let Err(e) = cache.add_syntax_themes_from_folder(&dir) {
+            log::warn!("Failed to load syntax themes from {}: {e}", dir.display());
         }
     }
+    cache
+});
 
-    /// Collapse all directories in the tree
-    fn collapse_all(&mut self) {
-        self.expanded_dirs.clear();
+/// Fresh per-document render cache with the user syntax themes, so the theme
+/// picker and every tab agree on the available set.
+fn new_document_cache() -> CommonMarkCache {
+    DOCUMENT_RESOURCES.share_resources()
+}
+
+/// `text` with a checkmark in front when `checked`, for toggles in menus.
+fn checked_text(checked: bool, text: &str) -> String {
+    if checked {
+        format!("✓ {text}")
+    } else {
+        text.to_string()
     }
+}
 
-    /// Collect all directory paths from a tree recursively (only loaded directories)
-    fn collect_all_dirs(nodes: &[FileTreeNode]) -> HashSet<PathBuf> {
-        let mut dirs = HashSet::new();
-        for node in nodes {
-            if let FileTreeNode::Directory { path, children, .. } = node {
-                dirs.insert(path.clone());
-                // Only recurse into loaded children
-                if let Some(child_nodes) = children {
-                    dirs.extend(Self::collect_all_dirs(child_nodes));
+/// Checkmark list of syntax theme names; returns true when the selection changed.
+fn syntax_theme_list(ui: &mut egui::Ui, names: &[String], selected: &mut String) -> bool {
+    let mut changed = false;
+    egui::ScrollArea::vertical()
+        .max_height(400.0)
+        .show(ui, |ui| {
+            for name in names {
+                if ui.button(checked_text(name == selected, name)).clicked() && name != selected {
+                    selected.clone_from(name);
+                    changed = true;
                 }
             }
-        }
-        dirs
-    }
+        });
+    changed
 }
 
-/// Per-tab state for a document
-struct Tab {
-    id: egui::Id,
-    path: PathBuf,
-    content: String,
-    cache: CommonMarkCache,
-    document_title: Option<String>,
-    outline_headers: Vec<Header>,
-    /// Set of header indices that are collapsed in the outline
-    collapsed_headers: HashSet<usize>,
-    scroll_offset: f32,
-    pending_scroll_offset: Option<f32>,
-    /// Composite header-position key (see `header_position_key`) waiting for
-    /// a corrective scroll. Set when the outline-click handler used the
-    /// line-ratio fallback because the cache didn't yet have the precise y
-    /// for this key. Cleared once the post-render corrective step has
-    /// snapped the viewport to the recorded position.
-    pending_header_click_key: Option<String>,
-    /// One-shot permission for the post-render corrective scroll that snaps
-    /// the active search match into view. Set by `scroll_to_active_match`
-    /// (jump_match / search-open / tab-switch / query-rebuild) and cleared
-    /// after the corrective block runs once. Without this gate, the block
-    /// would re-fire every frame while `active_search_y` remains populated,
-    /// fighting the user's wheel input and locking the view to the active
-    /// match — see issue #19 / docs/devlog/031-search-scroll-lock.md.
-    correct_active_search_pending: bool,
-    last_content_height: f32,
-    last_viewport_height: f32,
-    content_lines: usize,
-    local_links: Vec<String>,
-    /// Cached base URI for markdown image/link resolution (e.g. "file:///path/to/dir/")
-    base_uri: String,
-    history_back: Vec<PathBuf>,
-    history_forward: Vec<PathBuf>,
-    /// Cached matches for the current search query; empty when bar is closed or query is empty
-    search_matches: Vec<SearchMatch>,
-    /// Monotonic counter bumped on every content load/reload. Used as the
-    /// invalidation key for the renderer's per-document scroll cache so
-    /// parsed events and split_points can survive across frames without
-    /// re-hashing the entire content.
-    content_version: u64,
+/// How images are adjusted in the dark theme, saved between sessions and
+/// handed to the renderer as [`DarkModeImages`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+enum DarkImages {
+    Original,
+    Dimmed,
+    Inverted,
 }
 
-impl Tab {
-    fn compute_base_uri(path: &std::path::Path) -> String {
-        path.parent()
-            .map(|p| format!("file://{}/", p.display()))
-            .unwrap_or_else(|| "file://".to_string())
-    }
-
-    fn new(path: PathBuf) -> Self {
-        // Canonicalize path for consistent comparison with watcher events
-        let path = path.canonicalize().unwrap_or(path);
-        let content = fs::read_to_string(&path).unwrap_or_default();
-        let parsed = parse_headers(&content);
-        let local_links = parse_local_links(&content);
-        let content_lines = content.lines().count();
-        let base_uri = Self::compute_base_uri(&path);
+impl DarkImages {
+    const ALL: [Self; 3] = [Self::Original, Self::Dimmed, Self::Inverted];
 
-        let mut cache = CommonMarkCache::default();
-        for link in &local_links {
-            cache.add_link_hook(link);
+    fn label(self) -> &'static str {
+        match self {
+            Self::Original => "Original",
+            Self::Dimmed => "Dimmed",
+            Self::Inverted => "Inverted",
         }
+    }
 
-        Self {
-            id: egui::Id::new(&path),
-            path,
-            content,
-            cache,
-            document_title: parsed.document_title,
-            outline_headers: parsed.outline_headers,
-            collapsed_headers: HashSet::new(),
-            scroll_offset: 0.0,
-            pending_scroll_offset: None,
-            pending_header_click_key: None,
-            correct_active_search_pending: false,
-            last_content_height: 0.0,
-            last_viewport_height: 0.0,
-            content_lines,
-            local_links,
-            base_uri,
-            history_back: Vec::new(),
-            history_forward: Vec::new(),
-            search_matches: Vec::new(),
-            content_version: 1,
+    fn renderer_mode(self) -> DarkModeImages {
+        match self {
+            Self::Original => DarkModeImages::Off,
+            Self::Dimmed => DarkModeImages::Dim,
+            Self::Inverted => DarkModeImages::Invert,
         }
     }
+}
 
-    fn title(&self) -> String {
-        self.path
-            .file_name()
-            .map(|n| n.to_string_lossy().to_string())
-            .unwrap_or_else(|| "Unknown".to_string())
-    }
+/// Theme preference. `Auto` tracks the OS light/dark setting reported by egui;
+/// `Light`/`Dark` pin the theme regardless of the system.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+enum ThemeMode {
+    Light,
+    Dark,
+    Auto,
+}
 
-    fn reload(&mut self) {
-        if !self.path.exists() {
-            return;
+impl ThemeMode {
+    /// Migrate sessions saved before the tri-state existed: a stored bool was
+    /// always an explicit choice, and no stored value means "follow the OS".
+    fn from_legacy_dark_mode(dark_mode: Option<bool>) -> Self {
+        match dark_mode {
+            Some(true) => ThemeMode::Dark,
+            Some(false) => ThemeMode::Light,
+            None => ThemeMode::Auto,
         }
+    }
 
-        if let Ok(bytes) = fs::read(&self.path) {
-            let content = String::from_utf8_lossy(&bytes);
-            self.content_lines = content.lines().count();
-            self.content = content.into_owned();
-            self.cache = CommonMarkCache::default();
-            self.content_version = self.content_version.wrapping_add(1);
-            self.base_uri = Self::compute_base_uri(&self.path);
-
-            let parsed = parse_headers(&self.content);
-            self.document_title = parsed.document_title;
-            self.outline_headers = parsed.outline_headers;
-            self.collapsed_headers.clear();
-
-            self.local_links = parse_local_links(&self.content);
-            for link in &self.local_links {
-                self.cache.add_link_hook(link);
-            }
-
-            // Stale byte ranges; caller rebuilds if search bar is open
-            self.search_matches.clear();
+    /// Resolve to a concrete dark flag. `Auto` keeps `current_dark` when the
+    /// platform doesn't report a system theme (e.g. some X11 setups).
+    fn resolve_dark(self, system_theme: Option<egui::Theme>, current_dark: bool) -> bool {
+        match self {
+            ThemeMode::Light => false,
+            ThemeMode::Dark => true,
+            ThemeMode::Auto => system_theme.map_or(current_dark, |t| t == egui::Theme::Dark),
         }
     }
 
-    /// Rebuild `search_matches` for `query`. Empty query clears matches.
-    fn rebuild_search(&mut self, query: &str) {
-        self.search_matches = find_matches(&self.content, query);
+    /// Ctrl+D always flips what is on screen; from `Auto` that pins the
+    /// opposite of the currently resolved theme.
+    fn toggled(current_dark: bool) -> Self {
+        if current_dark {
+            ThemeMode::Light
+        } else {
+            ThemeMode::Dark
+        }
     }
+}
 
-    fn load_file(&mut self, path: &PathBuf) {
-        if !path.exists() {
-            return;
-        }
+/// A recently opened file, for the welcome page's "Recent" list.
+#[derive(Serialize, Deserialize, Clone)]
+struct RecentEntry {
+    path: PathBuf,
+    /// Seconds since the Unix epoch when the file was last opened.
+    last_opened: u64,
+}
 
-        if let Ok(bytes) = fs::read(path) {
-            let content = String::from_utf8_lossy(&bytes);
-            self.content_lines = content.lines().count();
-            self.content = content.into_owned();
-            self.path = path.clone();
-            self.id = egui::Id::new(path);
-            self.cache = CommonMarkCache::default();
-            self.content_version = self.content_version.wrapping_add(1);
-            self.scroll_offset = 0.0;
-            self.pending_scroll_offset = None;
-            self.base_uri = Self::compute_base_uri(&self.path);
+/// Current time in seconds since the Unix epoch (0 if the clock is before epoch).
+fn now_epoch_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
-            let parsed = parse_headers(&self.content);
-            self.document_title = parsed.document_title;
-            self.outline_headers = parsed.outline_headers;
-            self.collapsed_headers.clear();
+/// Push a path to the front of the recent list, deduped and capped (most-recent first).
+fn push_recent(list: &mut Vec<RecentEntry>, path: &Path, now: u64) {
+    list.retain(|e| e.path.as_path() != path);
+    list.insert(
+        0,
+        RecentEntry {
+            path: path.to_path_buf(),
+            last_opened: now,
+        },
+    );
+    list.truncate(RECENT_FILES_CAP);
+}
 
-            self.local_links = parse_local_links(&self.content);
-            for link in &self.local_links {
-                self.cache.add_link_hook(link);
-            }
+/// Put `root` first in the recent explorer roots (deduped, capped).
+fn push_recent_root(roots: &mut Vec<PathBuf>, root: &Path) {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    roots.retain(|r| *r != root);
+    roots.insert(0, root);
+    roots.truncate(RECENT_ROOTS_CAP);
+}
 
-            // Stale byte ranges; caller rebuilds if search bar is open
-            self.search_matches.clear();
-        }
-    }
+/// The directories from the filesystem root down to `root`, each with the
+/// name its breadcrumb shows.
+fn root_breadcrumb(root: &Path) -> Vec<(String, PathBuf)> {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let mut crumbs: Vec<(String, PathBuf)> = root
+        .ancestors()
+        .map(|dir| {
+            let name = dir
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| dir.display().to_string());
+            (name, dir.to_path_buf())
+        })
+        .collect();
+    crumbs.reverse();
+    crumbs
+}
 
-    fn navigate_to_link(&mut self, link: &str) {
-        if link.starts_with('#') {
-            return;
-        }
+/// Whether a link click opens its target in a tab behind the current one:
+/// a middle click, or Ctrl+Shift+click.
+fn opens_in_background(click: LinkClick) -> bool {
+    click.button == egui::PointerButton::Middle
+        || (click.modifiers.command && click.modifiers.shift)
+}
 
-        let Some(current_dir) = self.path.parent() else {
-            return;
-        };
+/// Where a document was last read, to scroll back to when it is opened again.
+/// Also an entry of a tab's back/forward history.
+#[derive(Serialize, Deserialize, Clone)]
+struct ReadingPosition {
+    path: PathBuf,
+    /// 1-based source line at the top of the viewport.
+    line: usize,
+}
 
-        let path_part = link.split('#').next().unwrap_or(link);
-        let target_path = current_dir.join(path_part);
+/// Remember `line` for `path` (most-recent first, deduped, capped).
+fn push_reading_position(list: &mut Vec<ReadingPosition>, path: &Path, line: usize) {
+    list.retain(|p| p.path.as_path() != path);
+    list.insert(
+        0,
+        ReadingPosition {
+            path: path.to_path_buf(),
+            line,
+        },
+    );
+    list.truncate(READING_POSITIONS_CAP);
+}
 
-        let target_path = match target_path.canonicalize() {
-            Ok(p) => p,
-            Err(_) => return,
-        };
+/// A tab's back/forward history, saved with the session.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct TabHistory {
+    back: Vec<ReadingPosition>,
+    forward: Vec<ReadingPosition>,
+}
 
-        self.history_back.push(self.path.clone());
-        self.history_forward.clear();
-        self.load_file(&target_path);
-    }
+/// The line `path` was last read at, `None` when it was at the top.
+/// The `README.md`, or else `index.md`, of `dir`, in any letter case, to
+/// preview when the directory is selected.
+fn directory_readme(dir: &Path) -> Option<PathBuf> {
+    let files: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    ["readme.md", "readme.markdown", "index.md"]
+        .iter()
+        .find_map(|name| {
+            files.iter().find(|path| {
+                path.file_name()
+                    .is_some_and(|file| file.to_string_lossy().eq_ignore_ascii_case(name))
+            })
+        })
+        .cloned()
+}
 
-    fn check_link_hooks(&self) -> Option<String> {
-        for link in &self.local_links {
-            if let Some(true) = self.cache.get_link_hook(link) {
-                return Some(link.clone());
-            }
-        }
-        None
-    }
+fn saved_reading_line(list: &[ReadingPosition], path: &Path) -> Option<usize> {
+    list.iter()
+        .find(|p| p.path.as_path() == path)
+        .map(|p| p.line)
+        .filter(|&line| line > 1)
+}
 
-    fn can_go_back(&self) -> bool {
-        !self.history_back.is_empty()
+/// Format an epoch-seconds timestamp as a short relative time ("3m ago").
+fn format_relative_time(epoch_secs: u64, now: u64) -> String {
+    let diff = now.saturating_sub(epoch_secs);
+    if diff < 60 {
+        "just now".to_string()
+    } else if diff < 3600 {
+        format!("{}m ago", diff / 60)
+    } else if diff < 86_400 {
+        format!("{}h ago", diff / 3600)
+    } else if diff < 172_800 {
+        "yesterday".to_string()
+    } else if diff < 604_800 {
+        format!("{}d ago", diff / 86_400)
+    } else if diff < 2_592_000 {
+        format!("{}w ago", diff / 604_800)
+    } else {
+        format!("{}mo ago", diff / 2_592_000)
     }
+}
 
-    fn can_go_forward(&self) -> bool {
-        !self.history_forward.is_empty()
-    }
+/// Commits of a file shown in the git log panel.
+const GIT_LOG_LIMIT: usize = 100;
+
+/// One commit that touched a file.
+#[derive(Clone, Debug, PartialEq)]
+struct GitLogEntry {
+    hash: String,
+    author: String,
+    /// Commit time in epoch seconds
+    time: u64,
+    subject: String,
+}
 
-    fn navigate_back(&mut self) {
-        if let Some(prev_path) = self.history_back.pop() {
-            self.history_forward.push(self.path.clone());
-            self.load_file(&prev_path);
-        }
-    }
+/// What git knows about a file, for the status bar and the git log panel.
+#[derive(Debug, Default, PartialEq)]
+struct GitFileInfo {
+    /// Newest first; empty when the file was never committed
+    log: Vec<GitLogEntry>,
+    /// "Modified" or "Untracked" when the working copy differs from the last
+    /// commit
+    badge: Option<&'static str>,
+}
 
-    fn navigate_forward(&mut self) {
-        if let Some(next_path) = self.history_forward.pop() {
-            self.history_back.push(self.path.clone());
-            self.load_file(&next_path);
-        }
-    }
+/// Parse `git log --format=%h%x00%an%x00%ct%x00%s` output.
+fn parse_git_log(stdout: &str) -> Vec<GitLogEntry> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\0');
+            Some(GitLogEntry {
+                hash: fields.next()?.to_string(),
+                author: fields.next()?.to_string(),
+                time: fields.next()?.parse().ok()?,
+                subject: fields.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
 
-    fn resolve_link(&self, link: &str) -> Option<PathBuf> {
-        if link.starts_with('#') {
-            return None;
-        }
+/// The status bar badge for `git status --porcelain` output about one file.
+fn git_status_badge(porcelain: &str) -> Option<&'static str> {
+    let line = porcelain.lines().next()?;
+    Some(if line.starts_with("??") {
+        "Untracked"
+    } else {
+        "Modified"
+    })
+}
 
-        let current_dir = self.path.parent()?;
-        let path_part = link.split('#').next().unwrap_or(link);
-        let target_path = current_dir.join(path_part);
-        target_path.canonicalize().ok()
-    }
+/// Ask git about `path`. `None` when it isn't in a repository or git isn't
+/// installed.
+fn load_git_file_info(path: &Path) -> Option<GitFileInfo> {
+    let dir = path.parent()?;
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .arg("--")
+            .arg(path)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let limit = format!("-n{GIT_LOG_LIMIT}");
+    let log = git(&["log", "--follow", &limit, "--format=%h%x00%an%x00%ct%x00%s"])?;
+    let status = git(&["status", "--porcelain"]).unwrap_or_default();
+    Some(GitFileInfo {
+        log: parse_git_log(&log),
+        badge: git_status_badge(&status),
+    })
 }
 
-/// Parse local markdown file links and anchor links from content, skipping code blocks.
-fn parse_local_links(content: &str) -> Vec<String> {
-    let link_re = &*LINK_RE;
-    let mut links = Vec::new();
-    let mut in_code_block = false;
+/// Files read at most when scanning the explorer root for tasks.
+const TASK_SCAN_FILE_LIMIT: usize = 5000;
 
-    for line in content.lines() {
-        if line.trim_start().starts_with("```") {
-            in_code_block = !in_code_block;
-            continue;
-        }
+/// A task list item (`- [ ] ...`) in a markdown file.
+#[derive(Clone, Debug, PartialEq)]
+struct TaskItem {
+    /// 1-based source line
+    line: usize,
+    done: bool,
+    text: String,
+}
 
-        if in_code_block {
-            continue;
-        }
+/// The tasks of one file under the explorer root, for the task panel.
+#[derive(Clone, Debug, PartialEq)]
+struct FileTasks {
+    path: PathBuf,
+    tasks: Vec<TaskItem>,
+}
 
-        for cap in link_re.captures_iter(line) {
-            let destination = &cap[2];
-            if is_local_markdown_link(destination) || destination.starts_with('#') {
-                links.push(destination.to_string());
-            }
+/// Byte offset of the `[ ]`/`[x]` box of a task list item on `line`, or
+/// `None` when the line isn't one.
+fn task_box_offset(line: &str) -> Option<usize> {
+    let item = line.trim_start_matches([' ', '\t', '>']);
+    let marker_len = if item.starts_with(['-', '*', '+']) {
+        1
+    } else {
+        let digits = item.len() - item.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 || !item[digits..].starts_with(['.', ')']) {
+            return None;
         }
+        digits + 1
+    };
+    let after_marker = &item[marker_len..];
+    let spaces = after_marker.len() - after_marker.trim_start_matches(' ').len();
+    let task = &after_marker[spaces..];
+    if spaces == 0 || !matches!(task.get(..3), Some("[ ]" | "[x]" | "[X]")) {
+        return None;
     }
-
-    links
-}
-
-/// Check if a link destination points to a local markdown file
-fn is_local_markdown_link(destination: &str) -> bool {
-    if destination.starts_with("http://")
-        || destination.starts_with("https://")
-        || destination.starts_with("mailto:")
-        || destination.starts_with("tel:")
-        || destination.starts_with("ftp://")
-        || destination.starts_with('#')
-    {
-        return false;
+    if !task[3..].is_empty() && !task[3..].starts_with([' ', '\t', '\r', '\n']) {
+        return None;
     }
+    Some(line.len() - item.len() + marker_len + spaces)
+}
 
-    let path_part = destination.split('#').next().unwrap_or(destination);
-    let path = std::path::Path::new(path_part);
-    path.extension()
-        .map(|ext| {
-            let ext = ext.to_string_lossy().to_lowercase();
-            ext == "md" || ext == "markdown" || ext == "txt"
+/// The task list items of a markdown document, outside fenced code.
+fn parse_tasks(content: &str) -> Vec<TaskItem> {
+    let mut fence: Option<&str> = None;
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let trimmed = line.trim_start();
+            if let Some(open) = fence {
+                if trimmed.starts_with(open) {
+                    fence = None;
+                }
+                return None;
+            }
+            if let Some(open) = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f)) {
+                fence = Some(open);
+                return None;
+            }
+            let offset = task_box_offset(line)?;
+            Some(TaskItem {
+                line: i + 1,
+                done: &line[offset..offset + 3] != "[ ]",
+                text: line[offset + 3..].trim().to_string(),
+            })
         })
-        .unwrap_or(false)
+        .collect()
 }
 
-/// Truncate a string for display, adding "..." if it exceeds max_len.
-/// Respects char boundaries for UTF-8 safety.
-fn truncate_display_name(s: &str, max_len: usize) -> String {
-    if s.len() > max_len {
-        let mut end = (max_len - 3).min(s.len());
-        while end > 0 && !s.is_char_boundary(end) {
-            end -= 1;
-        }
-        format!("{}...", &s[..end])
+/// Indentation of a list item line (`- `, `* `, `+ `, `1. `, `1) `), or
+/// `None` when the line isn't one.
+fn list_item_indent(line: &str) -> Option<usize> {
+    let item = line.trim_start_matches([' ', '\t']);
+    let digits = item.len() - item.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = if digits > 0 {
+        item[digits..].strip_prefix(['.', ')'])?
     } else {
-        s.to_string()
-    }
+        item.strip_prefix(['-', '*', '+'])?
+    };
+    (rest.is_empty() || rest.starts_with([' ', '\t'])).then_some(line.len() - item.len())
 }
 
-/// Parse markdown headers from content, skipping code blocks.
-fn parse_headers(content: &str) -> ParsedHeaders {
-    let re = &*HEADER_RE;
-    let mut all_headers: Vec<Header> = Vec::new();
-    let mut in_code_block = false;
+/// `content` as one code block in `language`, for reading the source with
+/// syntax highlighting and line numbers. The opening fence is a line of its
+/// own, so line `n` of `content` is line `n + 1` of the view.
+fn source_view(content: &str, language: &str) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    let content = content.strip_suffix('\n').unwrap_or(content);
+    format!("{fence}{language}\n{content}\n{fence}\n")
+}
 
-    for (line_number, line) in content.lines().enumerate() {
-        if line.trim_start().starts_with("```") {
-            in_code_block = !in_code_block;
+/// `content` reduced to its task list items, the list items they are nested
+/// in and the headings above them, for reading just the tasks of a document.
+fn task_list_view(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut keep = vec![false; lines.len()];
+    // Headings and list items the next task may be nested in
+    let mut headings: Vec<(usize, usize)> = Vec::new();
+    let mut items: Vec<(usize, usize)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(open) = fence {
+            if trimmed.starts_with(open) {
+                fence = None;
+            }
             continue;
         }
-
-        if in_code_block {
+        if let Some(open) = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f)) {
+            fence = Some(open);
             continue;
         }
-
-        if let Some(caps) = re.captures(line) {
-            let title = caps[2].trim().to_string();
-            let normalized_title = title.to_lowercase();
-            let display_title = truncate_display_name(&title, 35);
-            // Count prior headers with the same normalized title so each
-            // duplicate gets a distinct composite cache key.
-            let nth_with_same_text = all_headers
-                .iter()
-                .filter(|h| h.normalized_title == normalized_title)
-                .count();
-            all_headers.push(Header {
-                level: caps[1].len() as u8,
-                title,
-                display_title,
-                normalized_title,
-                nth_with_same_text,
-                line_number,
-            });
+        let level = line.len() - line.trim_start_matches('#').len();
+        if (1..=6).contains(&level) && line[level..].starts_with([' ', '\t']) {
+            headings.retain(|&(other, _)| other < level);
+            headings.push((level, i));
+            items.clear();
+            continue;
+        }
+        let Some(indent) = list_item_indent(line) else {
+            // Paragraphs after a list end it; continuation lines don't
+            if !trimmed.is_empty() && !line.starts_with([' ', '\t']) {
+                items.clear();
+            }
+            continue;
+        };
+        items.retain(|&(other, _)| other < indent);
+        if task_box_offset(line).is_some() {
+            for &(_, at) in headings.iter().chain(&items) {
+                keep[at] = true;
+            }
+            keep[i] = true;
         }
+        items.push((indent, i));
     }
 
-    let document_title = all_headers
-        .iter()
-        .find(|h| h.level == 1)
-        .map(|h| h.title.clone());
-    let outline_headers = all_headers;
-
-    ParsedHeaders {
-        document_title,
-        outline_headers,
+    let mut view = String::new();
+    for (i, line) in lines.iter().enumerate().filter(|&(i, _)| keep[i]) {
+        // Headings stand apart from the lists around them
+        let heading = line.starts_with('#');
+        if heading && !view.is_empty() {
+            view.push('\n');
+        }
+        view.push_str(line);
+        view.push('\n');
+        if heading && keep.get(i + 1).is_some_and(|next| *next) {
+            view.push('\n');
+        }
+    }
+    if view.is_empty() {
+        view.push_str("*No tasks in this document.*\n");
     }
+    view
 }
 
-/// Find all occurrences of `query` in `content`, case-insensitive (ASCII only).
-///
-/// Byte offsets are 1:1 with `content` because `to_ascii_lowercase` does not change
-/// byte length. Non-ASCII letters match literally (`É` does not match `é`) — see the
-/// devlog future-improvements list for the case-toggle and full Unicode case folding.
-///
-/// Matches spanning a newline are excluded (a search bar should not jump to results
-/// the user cannot interpret as a single line).
-///
-/// Matches inside markdown that's not visibly rendered are also excluded:
-/// - Image alt-text `![alt](url)` — alt is hover/screen-reader only
-/// - Image URL `![alt](url)` — never visible
-/// - Link URL `[text](url)` — never visible (only the text part is)
-///
-/// Without this, cycling lands on invisible matches: the user sees no visible
-/// highlight change and the scroll target points to a paragraph where the rendered
-/// text doesn't contain the searched bytes.
-fn find_matches(content: &str, query: &str) -> Vec<SearchMatch> {
-    if query.is_empty() || content.is_empty() {
-        return Vec::new();
-    }
+/// `content` with `task` checked or unchecked, or `None` when its line no
+/// longer holds that task, as when the file changed since it was listed.
+fn toggle_task(content: &str, task: &TaskItem) -> Option<String> {
+    if !parse_tasks(content).contains(task) {
+        return None;
+    }
+    let start: usize = content
+        .split_inclusive('\n')
+        .take(task.line.checked_sub(1)?)
+        .map(str::len)
+        .sum();
+    let text = content[start..].split_inclusive('\n').next()?;
+    let at = start + task_box_offset(text)?;
+    let mark = if &content[at..at + 3] == "[ ]" {
+        "[x]"
+    } else {
+        "[ ]"
+    };
+    let mut toggled = content.to_string();
+    toggled.replace_range(at..at + 3, mark);
+    Some(toggled)
+}
 
-    let content_lc = content.to_ascii_lowercase();
-    let query_lc = query.to_ascii_lowercase();
-    let query_len = query_lc.len();
+/// The file `bytes` decoded as `encoding`, or the detected one, with `task`
+/// toggled, encoded back.
+fn toggle_task_in_document(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+    task: &TaskItem,
+) -> Result<Vec<u8>, String> {
+    let (source, encoding) = decode_document(bytes, encoding);
+    let toggled = toggle_task(&source, task)
+        .ok_or_else(|| "the file changed, refresh the task list".to_string())?;
+    encode_document(&toggled, encoding, bytes)
+        .ok_or_else(|| format!("the text can't be saved as {}", encoding.name()))
+}
 
-    // Identify byte ranges of non-renderable markdown parts so we can skip matches
-    // inside them. Pattern: `(!?)[alt-or-text](url)`.
-    // - Group 1 = `!` for images, empty for links
-    // - Group 2 = alt (image) or text (link); exclude only if image (group 1 = `!`)
-    // - Group 3 = url; always exclude (URLs are never visible inline)
-    let skip_spans: Vec<std::ops::Range<usize>> = {
-        static MD_LINK_RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
-        let re = MD_LINK_RE.get_or_init(|| {
-            Regex::new(r"(!?)\[([^\]]*)\]\(([^)]*)\)").expect("static regex must compile")
-        });
-        let mut spans = Vec::new();
-        for cap in re.captures_iter(content) {
-            let is_image = cap.get(1).map(|m| !m.as_str().is_empty()).unwrap_or(false);
-            // Always exclude URL (group 3)
-            if let Some(url) = cap.get(3) {
-                spans.push(url.start()..url.end());
+/// Tasks of the markdown files under `root` that have any, sorted by path.
+fn scan_workspace_tasks(root: &Path, progress: &background::Progress) -> Vec<FileTasks> {
+    let files = markdown_files_under(root, TASK_SCAN_FILE_LIMIT, progress);
+    progress.set_total(files.len());
+    files
+        .into_iter()
+        .take_while(|_| !progress.is_cancelled())
+        .filter_map(|path| {
+            progress.advance();
+            let (source, _) = decode_document(&fs::read(&path).ok()?, None);
+            let tasks = parse_tasks(&source);
+            (!tasks.is_empty()).then_some(FileTasks { path, tasks })
+        })
+        .collect()
+}
+
+/// Up to `limit` markdown files under `root`, sorted by path. Hidden folders
+/// and symlinks are skipped.
+fn markdown_files_under(
+    root: &Path,
+    limit: usize,
+    progress: &background::Progress,
+) -> Vec<PathBuf> {
+    let mut dirs = vec![root.to_path_buf()];
+    let mut files = Vec::new();
+    while let Some(dir) = dirs.pop() {
+        if progress.is_cancelled() {
+            return Vec::new();
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
             }
-            // For images, also exclude alt (group 2)
-            if is_image {
-                if let Some(alt) = cap.get(2) {
-                    spans.push(alt.start()..alt.end());
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => dirs.push(path),
+                Ok(kind) if kind.is_file() && FileExplorer::is_markdown_file(&path) => {
+                    files.push(path)
                 }
+                _ => {}
             }
         }
-        spans
-    };
-
-    let in_skip = |start: usize, end: usize| -> bool {
-        skip_spans.iter().any(|s| start >= s.start && end <= s.end)
-    };
+        if files.len() >= limit {
+            break;
+        }
+    }
+    files.sort();
+    files.truncate(limit);
+    files
+}
 
-    let mut matches = Vec::new();
-    let mut line_number = 1usize;
-    let mut cursor = 0usize;
+/// Persisted state saved between sessions
+#[derive(Serialize, Deserialize, Default)]
+struct PersistedState {
+    dark_mode: Option<bool>,
+    theme_mode: Option<ThemeMode>,
+    zoom_level: Option<f32>,
+    ui_scale: Option<f32>,
+    reduce_motion: Option<bool>,
+    snap_to_headings: Option<bool>,
+    show_outline: Option<bool>,
+    show_tasks: Option<bool>,
+    show_problems: Option<bool>,
+    show_footnotes: Option<bool>,
+    show_figures: Option<bool>,
+    show_link_graph: Option<bool>,
+    show_floating_toc: Option<bool>,
+    outline_reordering: Option<bool>,
+    watch_always_poll: Option<bool>,
+    watch_poll_interval: Option<u64>,
+    watch_debounce_ms: Option<u64>,
+    watch_min_reload_ms: Option<u64>,
+    watch_focused_only: Option<bool>,
+    // `None` follows the locale
+    language: Option<String>,
+    full_width_content: Option<bool>,
+    content_width: Option<f32>,
+    center_content: Option<bool>,
+    syntax_theme_dark: Option<String>,
+    syntax_theme_light: Option<String>,
+    show_line_numbers: Option<bool>,
+    show_color_swatches: Option<bool>,
+    copy_blocks: Option<bool>,
+    dark_images: Option<DarkImages>,
+    email_quotes: Option<bool>,
+    equation_numbers: Option<bool>,
+    open_tabs: Option<Vec<PathBuf>>,
+    // Back/forward history of each of `open_tabs`
+    tab_histories: Option<Vec<TabHistory>>,
+    active_tab: Option<usize>,
+    // File explorer state
+    show_explorer: Option<bool>,
+    explorer_root: Option<PathBuf>,
+    recent_roots: Option<Vec<PathBuf>>,
+    expanded_dirs: Option<Vec<PathBuf>>,
+    explorer_sort_order: Option<SortOrder>,
+    explorer_skip_symlinks: Option<bool>,
+    explorer_preview_readmes: Option<bool>,
+    explorer_root_relative_links: Option<bool>,
+    recent_files: Option<Vec<RecentEntry>>,
+    reading_positions: Option<Vec<ReadingPosition>>,
+    editor_command: Option<String>,
+    allow_running_code: Option<bool>,
+    rust_runner: Option<String>,
+    restrict_untrusted: Option<bool>,
+    trusted_folders: Option<Vec<PathBuf>>,
+    export_toc_depth: Option<u8>,
+    number_headings: Option<bool>,
+    page_preview: Option<bool>,
+    page_size: Option<pages::PageSize>,
+    page_margins: Option<pages::Margins>,
+}
 
-    for (byte_start, _) in content_lc.match_indices(&query_lc) {
-        // Count newlines between cursor and this match's start
-        line_number += content[cursor..byte_start]
-            .bytes()
-            .filter(|&b| b == b'\n')
-            .count();
-        cursor = byte_start;
+/// Represents a markdown header for the outline
+#[derive(Clone)]
+struct Header {
+    level: u8,
+    title: String,
+    /// Pre-computed truncated display title for outline sidebar
+    display_title: String,
+    /// Pre-computed anchor slug for header position cache lookups
+    slug: String,
+    /// Occurrence index among headers with the same `slug`.
+    /// The first `## Installation` is 0, the second is 1, etc. Part of the
+    /// `HeaderKey` so duplicates scroll to the correct (different) y positions.
+    nth_with_same_slug: usize,
+    line_number: usize,
+    /// Byte offset of the heading in the content
+    start: usize,
+    /// Section number View → Number Headings shows, `None` for the title
+    number: Option<String>,
+    /// Words in the section, its subsections included
+    words: usize,
+}
 
-        let byte_end = byte_start + query_len;
-        if content[byte_start..byte_end].contains('\n') {
-            continue; // Skip matches that cross line boundaries
-        }
-        if in_skip(byte_start, byte_end) {
-            continue; // Skip matches inside image alt-text or URL portions of links/images
+impl Header {
+    /// The title shown in the outline, after the section number when
+    /// headings are numbered.
+    fn outline_title(&self, numbered: bool) -> String {
+        match &self.number {
+            Some(number) if numbered => format!("{number} {}", self.display_title),
+            _ => self.display_title.clone(),
         }
+    }
 
-        matches.push(SearchMatch {
-            byte_start,
-            byte_end,
-            line_number,
-        });
+    /// The anchor `goto` and links scroll to it by, numbered like GitHub's
+    /// when an earlier heading has the same one.
+    fn anchor(&self) -> String {
+        match self.nth_with_same_slug {
+            0 => self.slug.clone(),
+            nth => format!("{}-{nth}", self.slug),
+        }
     }
+}
 
-    matches
+/// Result of parsing markdown headers
+struct ParsedHeaders {
+    /// Document title (first h1, if any)
+    document_title: Option<String>,
+    /// Outline headers (excludes the first h1)
+    outline_headers: Vec<Header>,
 }
 
-/// Check if header at `index` should be hidden because an ancestor is collapsed
-fn header_is_hidden(headers: &[Header], index: usize, collapsed: &HashSet<usize>) -> bool {
-    if index == 0 || index >= headers.len() {
-        return false;
-    }
-    let mut search_level = headers[index].level;
-    // Walk backwards to find ancestors
-    for i in (0..index).rev() {
-        let h = &headers[i];
-        // Only consider headers with lower level than what we're searching for
-        if h.level < search_level {
-            // Found an ancestor
-            if collapsed.contains(&i) {
-                return true;
-            }
-            // This ancestor is not collapsed, but check its ancestors too
-            // Update search_level to only look for even lower level headers
-            search_level = h.level;
-        }
-        // Headers at same or higher level are siblings/cousins, skip them
-    }
-    false
+/// A single match in a tab's content, identified by byte range and 1-based line number
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SearchMatch {
+    byte_start: usize,
+    byte_end: usize,
+    line_number: usize,
 }
 
-/// Check if a header has any children (headers with higher level immediately following)
-fn header_has_children(headers: &[Header], index: usize) -> bool {
-    if index >= headers.len() {
-        return false;
-    }
-    let current_level = headers[index].level;
-    // Look at the next header
-    if let Some(next) = headers.get(index + 1) {
-        // A child has a higher level number (e.g., h3 is child of h2)
-        next.level > current_level
-    } else {
-        false
-    }
+/// Per-frame return from `render_search_bar`
+#[derive(Default)]
+struct SearchBarOutcome {
+    close_requested: bool,
+    prev_clicked: bool,
+    next_clicked: bool,
+    copy_report: bool,
+    save_report: bool,
 }
 
-/// Check if any header in the list has children
-fn any_header_has_children(headers: &[Header]) -> bool {
-    for i in 0..headers.len() {
-        if header_has_children(headers, i) {
-            return true;
+/// App-level search state — only one find bar is visible at a time
+#[derive(Default)]
+struct SearchState {
+    is_open: bool,
+    query: String,
+    /// Shadow copy of `query` used to detect changes across frames
+    last_query: String,
+    /// Tab index the cached matches were built for; `None` forces rebuild
+    last_tab: Option<usize>,
+    /// Set after Ctrl+F so the text input is focused next frame
+    focus_requested: bool,
+    /// Index into the active tab's `search_matches`
+    active_match_index: usize,
+}
+
+/// Action from file explorer interaction
+#[derive(Default)]
+struct ExplorerAction {
+    /// File to open in a new tab (left-click)
+    file_to_open: Option<PathBuf>,
+    /// File to close (middle-click on open file)
+    file_to_close: Option<PathBuf>,
+    /// Directory to toggle expansion (deferred to avoid clone)
+    dir_to_toggle: Option<PathBuf>,
+    /// Directory whose README to preview (left-click on its name)
+    dir_to_preview: Option<PathBuf>,
+}
+
+/// Sort order for file explorer
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum SortOrder {
+    #[default]
+    NameAsc,
+    NameDesc,
+    DateAsc,
+    DateDesc,
+}
+
+impl SortOrder {
+    fn label(&self) -> &'static str {
+        match self {
+            SortOrder::NameAsc => "Name A-Z",
+            SortOrder::NameDesc => "Name Z-A",
+            SortOrder::DateAsc => "Oldest First",
+            SortOrder::DateDesc => "Newest First",
         }
     }
-    false
 }
 
-/// Register a named bold font family for Markdown strong text.
-fn setup_strong_font_family(fonts: &mut egui::FontDefinitions) {
-    let mut strong_family = Vec::new();
+/// A node in the file explorer tree
+#[derive(Clone)]
+enum FileTreeNode {
+    File {
+        path: PathBuf,
+        /// `path` with links resolved, as tabs and watcher events name it
+        canonical: PathBuf,
+        is_symlink: bool,
+        name: String,
+        display_name: String,
+        modified: Option<std::time::SystemTime>,
+    },
+    Directory {
+        path: PathBuf,
+        /// `path` with links resolved, to spot links back to an ancestor
+        canonical: PathBuf,
+        is_symlink: bool,
+        name: String,
+        display_name: String,
+        modified: Option<std::time::SystemTime>,
+        /// None = not yet loaded, Some = loaded (may be empty)
+        children: Option<Vec<FileTreeNode>>,
+    },
+}
 
-    // Try to load a true bold face before appending proportional fallbacks.
-    for font_path in STRONG_FONT_PATHS {
-        let path = Path::new(font_path);
-        if path.exists() {
-            match fs::read(path) {
-                Ok(font_data) => {
-                    log::info!("Loaded Markdown strong font: {}", font_path);
-                    fonts.font_data.insert(
-                        STRONG_FONT_FAMILY.to_string(),
-                        egui::FontData::from_owned(font_data).into(),
-                    );
-                    strong_family.push(STRONG_FONT_FAMILY.to_string());
-                    break;
-                }
-                Err(e) => {
-                    log::debug!("Failed to read Markdown strong font {}: {}", font_path, e);
-                }
-            }
+impl FileTreeNode {
+    fn name(&self) -> &str {
+        match self {
+            FileTreeNode::File { name, .. } => name,
+            FileTreeNode::Directory { name, .. } => name,
         }
     }
 
-    // Keep normal proportional fallbacks so bold text can still render broad Unicode.
-    if let Some(proportional) = fonts.families.get(&egui::FontFamily::Proportional) {
-        strong_family.extend(
-            proportional
-                .iter()
-                .filter(|font_name| font_name.as_str() != STRONG_FONT_FAMILY)
-                .cloned(),
-        );
+    fn modified(&self) -> Option<std::time::SystemTime> {
+        match self {
+            FileTreeNode::File { modified, .. } => *modified,
+            FileTreeNode::Directory { modified, .. } => *modified,
+        }
     }
 
-    if !matches!(
-        strong_family.first(),
-        Some(font_name) if font_name.as_str() == STRONG_FONT_FAMILY
-    ) {
-        log::warn!("No Markdown strong font found. Install NotoSans-Bold for true bold rendering.");
+    fn is_directory(&self) -> bool {
+        matches!(self, FileTreeNode::Directory { .. })
     }
+}
 
-    // The renderer selects this named family for strong spans; register it even
-    // without a bold face so documents do not panic on systems missing Noto Sans Bold.
-    fonts.families.insert(
-        egui::FontFamily::Name(STRONG_FONT_FAMILY.into()),
-        strong_family,
-    );
+/// A link badge after an explorer entry, naming the link's target on hover.
+fn symlink_badge(ui: &mut egui::Ui, target: &Path) {
+    ui.label(egui::RichText::new("↪").weak())
+        .on_hover_text(format!("Link to {}", target.display()));
 }
 
-/// Setup custom fonts with system font fallbacks for Unicode support.
-/// Loads Noto fonts from system for extended character coverage.
-fn setup_fonts(ctx: &egui::Context) {
-    let mut fonts = egui::FontDefinitions::default();
-    let mut loaded_fonts: HashSet<String> = HashSet::new();
+/// File explorer state
+#[derive(Default)]
+struct FileExplorer {
+    root: Option<PathBuf>,
+    tree: Vec<FileTreeNode>,
+    expanded_dirs: HashSet<PathBuf>,
+    sort_order: SortOrder,
+    /// Leave symlinked files and directories out of the tree
+    skip_symlinks: bool,
+    /// Clicking a directory's name previews its README instead of expanding
+    /// it
+    preview_readmes: bool,
+    /// Links starting with `/` point into `root` rather than the filesystem
+    root_relative_links: bool,
+    /// Receiver for async directory scan results (GVFS paths scan in background)
+    pending_scan: Option<Receiver<Vec<FileTreeNode>>>,
+}
 
-    // Try to load each font from its possible paths
-    for (font_name, font_path) in SYSTEM_FONT_PATHS {
-        // Skip if we already loaded this font
-        if loaded_fonts.contains(*font_name) {
-            continue;
-        }
+impl FileExplorer {
+    /// Where links starting with `/` are resolved from, if that's turned on
+    fn link_root(&self) -> Option<&Path> {
+        self.root.as_deref().filter(|_| self.root_relative_links)
+    }
 
-        let path = Path::new(font_path);
-        if path.exists() {
-            match fs::read(path) {
-                Ok(font_data) => {
-                    log::info!("Loaded font fallback: {} from {}", font_name, font_path);
+    /// Scan a directory shallowly - only one level, subdirectories marked as unloaded
+    fn scan_directory_shallow(
+        path: &PathBuf,
+        sort_order: SortOrder,
+        skip_symlinks: bool,
+    ) -> Vec<FileTreeNode> {
+        let Ok(entries) = fs::read_dir(path) else {
+            return Vec::new();
+        };
 
-                    fonts.font_data.insert(
-                        font_name.to_string(),
-                        egui::FontData::from_owned(font_data).into(),
-                    );
+        let mut nodes: Vec<FileTreeNode> = Vec::new();
 
-                    // Noto Sans is the primary body face so regular text shares the
-                    // Noto Sans family — and its baseline/ascent metrics — with the
-                    // Noto Sans Bold used for `**strong**` (issue #39). Otherwise
-                    // regular text stays on egui's bundled Ubuntu-Light and bold spans
-                    // sit on a slightly different baseline. Other scripts (CJK, Arabic,
-                    // …) remain appended fallbacks.
-                    if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Proportional) {
-                        if *font_name == "NotoSans" {
-                            family.insert(0, font_name.to_string());
-                        } else {
-                            family.push(font_name.to_string());
-                        }
-                    }
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
 
-                    // Also add text fonts to monospace for code blocks with Unicode
-                    if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Monospace) {
-                        family.push(font_name.to_string());
-                    }
+            // Skip hidden files
+            if name.starts_with('.') {
+                continue;
+            }
 
-                    loaded_fonts.insert(font_name.to_string());
-                }
-                Err(e) => {
-                    log::debug!("Failed to read font {}: {}", font_path, e);
-                }
+            let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+            if is_symlink && skip_symlinks {
+                continue;
+            }
+            let canonical = entry_path
+                .canonicalize()
+                .unwrap_or_else(|_| entry_path.clone());
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+
+            if entry_path.is_dir() {
+                // Show all directories - let users expand what they want
+                // (Avoids O(n×m) scanning during initial directory scan)
+                let display_name = truncate_display_name(&name, 22);
+                nodes.push(FileTreeNode::Directory {
+                    path: entry_path,
+                    canonical,
+                    is_symlink,
+                    name,
+                    display_name,
+                    modified,
+                    children: None, // Lazy - not loaded yet
+                });
+            } else if Self::is_markdown_file(&entry_path) {
+                let display_name = truncate_display_name(&name, 25);
+                nodes.push(FileTreeNode::File {
+                    path: entry_path,
+                    canonical,
+                    is_symlink,
+                    name,
+                    display_name,
+                    modified,
+                });
             }
         }
-    }
 
-    if loaded_fonts.is_empty() {
-        log::warn!("No system fonts loaded. Unicode characters may show as red triangles.");
-        log::warn!("Install noto-fonts and noto-fonts-cjk for full Unicode support.");
-    } else {
-        log::info!(
-            "Loaded {} font fallbacks for Unicode support",
-            loaded_fonts.len()
-        );
+        Self::sort_nodes(&mut nodes, sort_order);
+        nodes
     }
 
-    setup_strong_font_family(&mut fonts);
+    /// Sort nodes according to the given sort order (directories always on top)
+    fn sort_nodes(nodes: &mut [FileTreeNode], sort_order: SortOrder) {
+        nodes.sort_by(|a, b| {
+            // Directories always come first
+            match (a.is_directory(), b.is_directory()) {
+                (true, false) => return std::cmp::Ordering::Less,
+                (false, true) => return std::cmp::Ordering::Greater,
+                _ => {}
+            }
 
-    ctx.set_fonts(fonts);
-}
+            // Within the same type, sort by the selected criteria
+            match sort_order {
+                SortOrder::NameAsc => a.name().cmp(b.name()),
+                SortOrder::NameDesc => b.name().cmp(a.name()),
+                SortOrder::DateAsc => {
+                    // Oldest first: None (unknown) sorts last
+                    match (a.modified(), b.modified()) {
+                        (Some(a_time), Some(b_time)) => a_time.cmp(&b_time),
+                        (Some(_), None) => std::cmp::Ordering::Less,
+                        (None, Some(_)) => std::cmp::Ordering::Greater,
+                        (None, None) => a.name().cmp(b.name()),
+                    }
+                }
+                SortOrder::DateDesc => {
+                    // Newest first: None (unknown) sorts last
+                    match (a.modified(), b.modified()) {
+                        (Some(a_time), Some(b_time)) => b_time.cmp(&a_time),
+                        (Some(_), None) => std::cmp::Ordering::Less,
+                        (None, Some(_)) => std::cmp::Ordering::Greater,
+                        (None, None) => a.name().cmp(b.name()),
+                    }
+                }
+            }
+        });
+    }
 
-#[global_allocator]
-static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
-
-#[derive(Parser, Debug)]
-#[command(name = "md-viewer")]
-#[command(about = "A lightweight markdown viewer", long_about = None)]
-struct Args {
-    /// Markdown file to open
-    file: Option<PathBuf>,
-
-    /// Disable live reload (watching is enabled by default)
-    #[arg(long)]
-    no_watch: bool,
-
-    /// Keep the GUI process attached to the terminal for debugging/logs
-    #[arg(long)]
-    foreground: bool,
-
-    /// Internal marker used by the detached child process to avoid respawn loops
-    #[arg(long, hide = true)]
-    no_detach: bool,
-}
-
-fn should_detach(args: &Args, launched_from_terminal: bool) -> bool {
-    launched_from_terminal && !args.foreground && !args.no_detach
-}
-
-fn launched_from_terminal() -> bool {
-    std::io::stdin().is_terminal()
-        || std::io::stdout().is_terminal()
-        || std::io::stderr().is_terminal()
-}
-
-fn child_args_with_no_detach<I>(args: I) -> Vec<OsString>
-where
-    I: IntoIterator<Item = OsString>,
-{
-    let mut child_args: Vec<OsString> = args.into_iter().skip(1).collect();
-    let marker_position = child_args
-        .iter()
-        .position(|arg| arg == OsStr::new("--"))
-        .unwrap_or(child_args.len());
-    child_args.insert(marker_position, OsString::from("--no-detach"));
-    child_args
-}
-
-fn spawn_detached_child() -> io::Result<()> {
-    let exe = std::env::current_exe()?;
-    let child_args = child_args_with_no_detach(std::env::args_os());
-    let mut command = Command::new(exe);
-
-    command
-        .args(child_args)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null());
+    fn is_markdown_file(path: &Path) -> bool {
+        path.extension()
+            .map(|ext| {
+                let ext = ext.to_string_lossy().to_lowercase();
+                ext == "md" || ext == "markdown" || ext == "txt"
+            })
+            .unwrap_or(false)
+    }
 
-    #[cfg(unix)]
-    unsafe {
-        use std::os::unix::process::CommandExt;
+    /// Set root directory and rescan (shallow).
+    /// For GVFS paths, scan runs in a background thread to avoid blocking the UI.
+    fn set_root(&mut self, path: PathBuf) {
+        // Convert empty path to current directory
+        let path = if path.as_os_str().is_empty() {
+            std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+        } else {
+            path
+        };
+        self.root = Some(path.clone());
+        if is_gvfs_path(&path) {
+            // Scan in background thread — tree populates when ready
+            let (sort_order, skip_symlinks) = (self.sort_order, self.skip_symlinks);
+            let (tx, rx) = mpsc::channel();
+            std::thread::Builder::new()
+                .name("gvfs-scan".into())
+                .spawn(move || {
+                    let tree = Self::scan_directory_shallow(&path, sort_order, skip_symlinks);
+                    let _ = tx.send(tree);
+                })
+                .expect("failed to spawn GVFS scan thread");
+            self.pending_scan = Some(rx);
+        } else {
+            self.tree = Self::scan_directory_shallow(&path, self.sort_order, self.skip_symlinks);
+        }
+    }
 
-        // Start a new process group/session so the child survives after the
-        // launching terminal hands control back to the shell.
-        command.pre_exec(|| {
-            extern "C" {
-                fn setsid() -> i32;
+    /// Check if a background scan completed and apply results
+    fn poll_pending_scan(&mut self) -> bool {
+        if let Some(rx) = &self.pending_scan {
+            if let Ok(tree) = rx.try_recv() {
+                self.tree = tree;
+                self.pending_scan = None;
+                return true;
             }
+        }
+        false
+    }
 
-            if setsid() == -1 {
-                Err(io::Error::last_os_error())
-            } else {
-                Ok(())
+    /// Refresh the file tree (clears loaded state, rescans shallowly).
+    /// For GVFS paths, runs in background to avoid blocking the UI thread.
+    fn refresh(&mut self) {
+        if let Some(root) = &self.root.clone() {
+            if is_gvfs_path(root) {
+                // Re-scan in background
+                let (sort_order, skip_symlinks) = (self.sort_order, self.skip_symlinks);
+                let root = root.clone();
+                let (tx, rx) = mpsc::channel();
+                std::thread::Builder::new()
+                    .name("gvfs-refresh".into())
+                    .spawn(move || {
+                        let tree = Self::scan_directory_shallow(&root, sort_order, skip_symlinks);
+                        let _ = tx.send(tree);
+                    })
+                    .expect("failed to spawn GVFS refresh thread");
+                self.pending_scan = Some(rx);
+                return;
             }
-        });
+            self.tree = Self::scan_directory_shallow(root, self.sort_order, self.skip_symlinks);
+            // Re-load children for currently expanded directories
+            let expanded: Vec<PathBuf> = self.expanded_dirs.iter().cloned().collect();
+            for dir_path in expanded {
+                self.load_children(&dir_path);
+            }
+        }
     }
 
-    command.spawn()?;
-    Ok(())
-}
-
-fn main() -> eframe::Result<()> {
-    env_logger::init();
+    /// Load children for a specific directory (lazy loading)
+    fn load_children(&mut self, dir_path: &PathBuf) {
+        let (sort_order, skip_symlinks) = (self.sort_order, self.skip_symlinks);
+        Self::load_children_in_tree(&mut self.tree, dir_path, sort_order, skip_symlinks);
+    }
 
-    let args = Args::parse();
+    /// Recursively find and load children for a directory in the tree
+    fn load_children_in_tree(
+        nodes: &mut [FileTreeNode],
+        target_path: &PathBuf,
+        sort_order: SortOrder,
+        skip_symlinks: bool,
+    ) -> bool {
+        for node in nodes.iter_mut() {
+            if let FileTreeNode::Directory { path, children, .. } = node {
+                if path == target_path {
+                    // Found the target directory - load its children if not loaded
+                    if children.is_none() {
+                        *children = Some(Self::scan_directory_shallow(
+                            path,
+                            sort_order,
+                            skip_symlinks,
+                        ));
+                    }
+                    return true;
+                }
+                // Recurse into loaded children
+                if let Some(ref mut child_nodes) = children {
+                    if Self::load_children_in_tree(
+                        child_nodes,
+                        target_path,
+                        sort_order,
+                        skip_symlinks,
+                    ) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
 
-    if should_detach(&args, launched_from_terminal()) {
-        if let Err(err) = spawn_detached_child() {
-            eprintln!("Failed to detach md-viewer process: {err}. Running in foreground.");
+    /// Toggle directory expansion (loads children if not yet loaded)
+    fn toggle_expanded(&mut self, path: &PathBuf) {
+        if self.expanded_dirs.contains(path) {
+            self.expanded_dirs.remove(path);
         } else {
-            return Ok(());
+            // Load children before expanding if not yet loaded
+            self.load_children(path);
+            self.expanded_dirs.insert(path.clone());
         }
     }
 
-    // Calculate optimal window width assuming both sidebars are shown (the default)
-    let optimal_width =
-        CONTENT_OPTIMAL_WIDTH + EXPLORER_DEFAULT_WIDTH + OUTLINE_DEFAULT_WIDTH + PANEL_SEPARATORS;
+    /// Check if a directory is expanded
+    fn is_expanded(&self, path: &PathBuf) -> bool {
+        self.expanded_dirs.contains(path)
+    }
 
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([optimal_width, OPTIMAL_WINDOW_HEIGHT])
-            .with_min_inner_size([400.0, 300.0])
-            .with_title("Markdown Viewer")
-            .with_drag_and_drop(true),
-        ..Default::default()
-    };
+    /// Set sort order and re-sort the tree in place
+    fn set_sort_order(&mut self, order: SortOrder) {
+        if self.sort_order != order {
+            self.sort_order = order;
+            Self::resort_tree_recursive(&mut self.tree, order);
+        }
+    }
 
-    eframe::run_native(
-        "md-viewer",
-        options,
-        Box::new(move |cc| Ok(Box::new(MarkdownApp::new(cc, args.file, !args.no_watch)))),
-    )
-}
+    /// Recursively re-sort all nodes in the tree
+    fn resort_tree_recursive(nodes: &mut [FileTreeNode], sort_order: SortOrder) {
+        Self::sort_nodes(nodes, sort_order);
+        for node in nodes.iter_mut() {
+            if let FileTreeNode::Directory {
+                children: Some(ref mut child_nodes),
+                ..
+            } = node
+            {
+                Self::resort_tree_recursive(child_nodes, sort_order);
+            }
+        }
+    }
 
-/// Source of a lightbox texture. Mermaid pre-rasterizes its own texture so we
-/// own the handle to keep it alive; loader-managed images (egui_extras) just
-/// give us an id whose lifetime is governed by the loader.
-enum LightboxTexture {
-    Owned(egui::TextureHandle),
-    Loaded(egui::TextureId),
-}
+    /// Get children for a directory by path (looks up in original tree, not clone)
+    fn get_children(&self, target_path: &PathBuf) -> Option<&Vec<FileTreeNode>> {
+        Self::find_children_in_tree(&self.tree, target_path)
+    }
 
-impl LightboxTexture {
-    fn id(&self) -> egui::TextureId {
-        match self {
-            Self::Owned(handle) => handle.id(),
-            Self::Loaded(id) => *id,
+    /// Recursively find children for a directory in the tree
+    fn find_children_in_tree<'a>(
+        nodes: &'a [FileTreeNode],
+        target_path: &PathBuf,
+    ) -> Option<&'a Vec<FileTreeNode>> {
+        for node in nodes {
+            if let FileTreeNode::Directory { path, children, .. } = node {
+                if path == target_path {
+                    return children.as_ref();
+                }
+                // Recurse into loaded children
+                if let Some(child_nodes) = children {
+                    if let Some(found) = Self::find_children_in_tree(child_nodes, target_path) {
+                        return Some(found);
+                    }
+                }
+            }
         }
+        None
     }
-}
 
-struct LightboxState {
-    /// GPU-resident texture (mermaid-owned or loader-owned). Zoom scales it — instant.
-    texture: LightboxTexture,
-    /// Original rasterized pixel dimensions (before any zoom)
-    base_size: egui::Vec2,
-    zoom: f32,
-    /// Unique ID per open — prevents stale egui Area/ScrollArea state between opens
-    open_id: u64,
-    /// Tracks scroll offset for zoom-to-cursor (we maintain our own copy because
-    /// egui's ScrollArea may clamp the old offset when content size changes mid-zoom)
-    scroll_offset: egui::Vec2,
-}
-
-/// Check if a path is on a GVFS FUSE mount (e.g., SFTP via Thunar/Nautilus).
-fn is_gvfs_path(path: &Path) -> bool {
-    path.starts_with("/run/user/") && path.components().any(|c| c.as_os_str() == "gvfs")
-}
+    /// Maximum depth for expand_all to prevent excessive recursion
+    const MAX_EXPAND_DEPTH: usize = 10;
 
-/// Wrapper for file watchers that supports both inotify (local) and poll (GVFS/remote).
-enum FileWatcher {
-    Inotify(Debouncer<RecommendedWatcher>),
-    Poll(Debouncer<PollWatcher>),
-    Dual {
-        inotify: Debouncer<RecommendedWatcher>,
-        poll: Debouncer<PollWatcher>,
-    },
-}
+    /// Expand all directories in the tree (loads all children recursively up to MAX_EXPAND_DEPTH)
+    fn expand_all(&mut self) {
+        // First, recursively load all directories (with depth limit)
+        let mut ancestors: Vec<PathBuf> = self
+            .root
+            .iter()
+            .filter_map(|r| r.canonicalize().ok())
+            .collect();
+        let (sort_order, skip_symlinks) = (self.sort_order, self.skip_symlinks);
+        Self::load_all_children(&mut self.tree, sort_order, skip_symlinks, &mut ancestors);
+        // Then collect all directory paths
+        self.expanded_dirs = Self::collect_all_dirs(&self.tree);
+    }
 
-impl FileWatcher {
-    fn inotify_watcher(&mut self) -> Option<&mut dyn notify::Watcher> {
-        match self {
-            FileWatcher::Inotify(d) | FileWatcher::Dual { inotify: d, .. } => Some(d.watcher()),
-            FileWatcher::Poll(_) => None,
+    /// Recursively load all unloaded directories (up to MAX_EXPAND_DEPTH).
+    /// Links back to one of the `ancestors` (canonical paths of the
+    /// directories above) are left unloaded so cyclic links end the descent.
+    fn load_all_children(
+        nodes: &mut [FileTreeNode],
+        sort_order: SortOrder,
+        skip_symlinks: bool,
+        ancestors: &mut Vec<PathBuf>,
+    ) {
+        if ancestors.len() > Self::MAX_EXPAND_DEPTH {
+            return;
+        }
+        for node in nodes.iter_mut() {
+            if let FileTreeNode::Directory {
+                path,
+                canonical,
+                children,
+                ..
+            } = node
+            {
+                if ancestors.contains(canonical) {
+                    continue;
+                }
+                // Load children if not yet loaded
+                if children.is_none() {
+                    *children = Some(Self::scan_directory_shallow(
+                        path,
+                        sort_order,
+                        skip_symlinks,
+                    ));
+                }
+                // Recurse into children
+                if let Some(ref mut child_nodes) = children {
+                    ancestors.push(canonical.clone());
+                    Self::load_all_children(child_nodes, sort_order, skip_symlinks, ancestors);
+                    ancestors.pop();
+                }
+            }
         }
     }
 
-    fn poll_watcher(&mut self) -> Option<&mut dyn notify::Watcher> {
-        match self {
-            FileWatcher::Poll(d) | FileWatcher::Dual { poll: d, .. } => Some(d.watcher()),
-            FileWatcher::Inotify(_) => None,
-        }
+    /// Collapse all directories in the tree
+    fn collapse_all(&mut self) {
+        self.expanded_dirs.clear();
     }
-}
 
-struct MarkdownApp {
-    tabs: Vec<Tab>,
-    active_tab: usize,
-    dark_mode: bool,
-    zoom_level: f32,
-    show_outline: bool,
-    full_width_content: bool,
-    watch_enabled: bool,
-    error_message: Option<String>,
-    is_dragging: bool,
-    // File watcher state (inotify for local, poll for GVFS/remote)
-    watcher: Option<FileWatcher>,
-    watcher_rx: Option<Receiver<Result<Vec<notify_debouncer_mini::DebouncedEvent>, notify::Error>>>,
-    watcher_retry_count: u32,
-    // Set of paths being watched (individual tab files)
-    watched_paths: HashSet<PathBuf>,
-    // Explorer directories being watched non-recursively: the root plus each
-    // currently-expanded directory. Mirrors the lazy explorer tree so startup
-    // doesn't recursively walk the whole root subtree (issue: ~6s hang on a
-    // huge home directory).
-    watched_explorer_dirs: HashSet<PathBuf>,
-    // Tab being hovered for close button
-    hovered_tab: Option<usize>,
-    // File explorer state
-    file_explorer: FileExplorer,
-    show_explorer: bool,
-    // Flash effect for updated files (path -> start time)
-    flashing_paths: HashMap<PathBuf, Instant>,
-    // True if running on virtual display (e.g., Xvfb :99) - limits frame rate
-    is_virtual_display: bool,
-    // Stored context for waking egui from the watcher bridge thread
-    egui_ctx: egui::Context,
-    // Track state to avoid unconditional repaints
-    last_applied_dark_mode: Option<bool>,
-    last_window_title: String,
-    title_dirty: bool,
-    /// Cached set of open tab paths for file explorer highlighting (avoids per-frame syscalls)
-    open_tab_paths: HashSet<PathBuf>,
-    // Lightbox overlay for enlarged mermaid diagrams
-    lightbox: Option<LightboxState>,
-    // Scroll delta captured from raw_input_hook for lightbox zoom (stripped from RawInput)
-    lightbox_scroll: f32,
-    // Counter for unique lightbox IDs (prevents stale egui state between opens)
-    lightbox_open_count: u64,
-    // Find-bar state (current-document search)
-    search: SearchState,
-    // Recently opened files (most-recent first), shown on the welcome page
-    recent_files: Vec<RecentEntry>,
-    // Welcome page: whether the recent list is expanded ("Show more")
-    welcome_show_all: bool,
-    // MCP bridge for E2E testing
-    #[cfg(feature = "mcp")]
-    mcp_bridge: McpBridge,
+    /// Collect all loaded directory paths from a tree recursively
+    fn collect_all_dirs(nodes: &[FileTreeNode]) -> HashSet<PathBuf> {
+        let mut dirs = HashSet::new();
+        for node in nodes {
+            // Unloaded directories (cyclic links, beyond the depth limit) stay
+            // collapsed so rendering doesn't load them
+            if let FileTreeNode::Directory {
+                path,
+                children: Some(child_nodes),
+                ..
+            } = node
+            {
+                dirs.insert(path.clone());
+                dirs.extend(Self::collect_all_dirs(child_nodes));
+            }
+        }
+        dirs
+    }
 }
 
-impl MarkdownApp {
-    fn new(cc: &eframe::CreationContext<'_>, file: Option<PathBuf>, watch: bool) -> Self {
-        // Setup fonts with system font fallbacks for Unicode support
-        setup_fonts(&cc.egui_ctx);
+/// Encodings offered in the status bar's encoding menu.
+const ENCODING_CHOICES: [&Encoding; 13] = [
+    encoding_rs::UTF_8,
+    encoding_rs::UTF_16LE,
+    encoding_rs::UTF_16BE,
+    encoding_rs::WINDOWS_1252,
+    encoding_rs::ISO_8859_2,
+    encoding_rs::ISO_8859_15,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::KOI8_R,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_JP,
+    encoding_rs::GBK,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_KR,
+];
 
-        // Clear stale egui widget data loaded from disk (scroll offsets, panel sizes, etc.)
-        // We don't persist egui memory (see persist_egui_memory), but eframe always
-        // loads it if present. This purges the old blob so it doesn't waste startup time/RAM.
-        cc.egui_ctx.memory_mut(|mem| mem.data = Default::default());
+/// Guess the encoding of a document: its byte order mark, UTF-8 when the
+/// bytes are valid UTF-8, UTF-16 without a BOM when every other byte of
+/// mostly-ASCII text is NUL, and windows-1252 (a superset of Latin-1)
+/// otherwise.
+fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return encoding_rs::UTF_8;
+    }
+    let sample = &bytes[..bytes.len().min(4096)];
+    let nuls = |start: usize| {
+        sample
+            .iter()
+            .skip(start)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let pairs = sample.len() / 2;
+    let (even, odd) = (nuls(0), nuls(1));
+    if odd * 2 > pairs && even == 0 {
+        encoding_rs::UTF_16LE
+    } else if even * 2 > pairs && odd == 0 {
+        encoding_rs::UTF_16BE
+    } else {
+        encoding_rs::WINDOWS_1252
+    }
+}
 
-        // Disable egui's built-in Ctrl+/- zoom — we handle zoom ourselves
-        cc.egui_ctx
-            .options_mut(|opt| opt.zoom_with_keyboard = false);
+/// Read `path`, or only its first `limit` bytes cut back to the last line
+/// break. Returns the bytes and whether the file continues past them.
+fn read_document_bytes(path: &Path, limit: Option<usize>) -> io::Result<(Vec<u8>, bool)> {
+    let Some(limit) = limit else {
+        return fs::read(path).map(|bytes| (bytes, false));
+    };
+    let mut bytes = Vec::new();
+    fs::File::open(path)?
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() <= limit {
+        return Ok((bytes, false));
+    }
+    bytes.truncate(limit);
+    if let Some(newline) = bytes.iter().rposition(|&b| b == b'\n') {
+        // Keep the NUL after a UTF-16LE line break
+        let end = newline + 1 + usize::from(bytes.get(newline + 1) == Some(&0));
+        bytes.truncate(end);
+    }
+    Ok((bytes, true))
+}
 
-        // Set constant styles once at init (never changes at runtime)
-        cc.egui_ctx.style_mut(|style| {
-            style.url_in_tooltip = true;
-            use egui::{FontId, TextStyle};
-            style
-                .text_styles
-                .insert(TextStyle::Body, FontId::proportional(16.0));
-            style
-                .text_styles
-                .insert(TextStyle::Heading, FontId::proportional(32.0));
-            style
-                .text_styles
-                .insert(TextStyle::Small, FontId::proportional(13.0));
-            style
-                .text_styles
-                .insert(TextStyle::Monospace, FontId::monospace(14.0));
+/// `bytes` as `1.5 MB`, `12 KB` or `300 bytes`.
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} bytes"),
+        1024..1_048_576 => format!("{} KB", bytes / 1024),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
 
-            // Smoother scroll animation
-            style.animation_time = 0.15;
-            style.scroll_animation.points_per_second = 1500.0;
+/// Decode a document as `encoding`, or as the detected one when `None`.
+/// Returns the text without its BOM and the encoding used.
+fn decode_document(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> (String, &'static Encoding) {
+    let encoding = encoding.unwrap_or_else(|| detect_encoding(bytes));
+    let (text, _) = encoding.decode_with_bom_removal(bytes);
+    (text.into_owned(), encoding)
+}
 
-            // Reduce resize grab radius to prevent overlap with adjacent scrollbars
-            style.interaction.resize_grab_radius_side = 2.0;
-        });
+/// `text` encoded as `encoding` for writing back over the file `original`,
+/// keeping the BOM it started with. `None` when `encoding` can't hold every
+/// character of `text`.
+fn encode_document(text: &str, encoding: &'static Encoding, original: &[u8]) -> Option<Vec<u8>> {
+    let bom = Encoding::for_bom(original)
+        .filter(|(bom_encoding, _)| *bom_encoding == encoding)
+        .map_or(0, |(_, len)| len);
+    let mut bytes = original[..bom].to_vec();
+    // encoding_rs only encodes to UTF-16 by way of UTF-8
+    if encoding == encoding_rs::UTF_16LE {
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    } else if encoding == encoding_rs::UTF_16BE {
+        bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+    } else {
+        let (encoded, used, unmappable) = encoding.encode(text);
+        if used != encoding || unmappable {
+            return None;
+        }
+        bytes.extend_from_slice(&encoded);
+    }
+    Some(bytes)
+}
 
-        // Load persisted state
-        let persisted: PersistedState = cc
-            .storage
-            .and_then(|s| eframe::get_value(s, APP_KEY))
-            .unwrap_or_default();
+/// Per-tab state for a document
+struct Tab {
+    id: egui::Id,
+    path: PathBuf,
+    content: String,
+    /// Encoding the file was decoded with, and the one picked in the status
+    /// bar when it overrides detection
+    encoding: &'static Encoding,
+    encoding_override: Option<&'static Encoding>,
+    /// Bytes of a large file read so far, `None` once it is read in full;
+    /// `truncated` while the file continues past them
+    load_limit: Option<usize>,
+    truncated: bool,
+    file_size: u64,
+    /// When a reload first found the file missing or unreadable; the last
+    /// content stays shown meanwhile
+    missing_since: Option<Instant>,
+    cache: CommonMarkCache,
+    document_title: Option<String>,
+    outline_headers: Vec<Header>,
+    /// Set of header indices that are collapsed in the outline
+    collapsed_headers: HashSet<usize>,
+    scroll_offset: f32,
+    pending_scroll_offset: Option<f32>,
+    /// Source line to scroll to once the document has been painted, for
+    /// jumps from the task and problems panels.
+    pending_line: Option<usize>,
+    /// Structural problems of `content`, found when the problems panel
+    /// first shows them
+    problems: Option<Vec<problems::Problem>>,
+    /// Footnotes of `content`, found when the footnotes panel first shows them
+    footnotes: Option<Vec<footnotes::Footnote>>,
+    /// Figures and tables of `content`, found when their panel first shows them
+    figures: Option<Vec<figures::Figure>>,
+    /// Header-position key waiting for a corrective scroll. Set when the outline-click handler used the
+    /// line-ratio fallback because the cache didn't yet have the precise y
+    /// for this key. Cleared once the post-render corrective step has
+    /// snapped the viewport to the recorded position.
+    pending_header_click_key: Option<HeaderKey>,
+    /// One-shot permission for the post-render corrective scroll that snaps
+    /// the active search match into view. Set by `scroll_to_active_match`
+    /// (jump_match / search-open / tab-switch / query-rebuild) and cleared
+    /// after the corrective block runs once. Without this gate, the block
+    /// would re-fire every frame while `active_search_y` remains populated,
+    /// fighting the user's wheel input and locking the view to the active
+    /// match — see issue #19 / docs/devlog/031-search-scroll-lock.md.
+    correct_active_search_pending: bool,
+    last_content_height: f32,
+    last_viewport_height: f32,
+    content_lines: usize,
+    local_links: Vec<String>,
+    /// Cached base URI for markdown image/link resolution (e.g. "file:///path/to/dir/")
+    base_uri: String,
+    history_back: Vec<ReadingPosition>,
+    history_forward: Vec<ReadingPosition>,
+    /// Cached matches for the current search query; empty when bar is closed or query is empty
+    search_matches: Vec<SearchMatch>,
+    /// `content` reduced to its tasks by `task_list_view`, shown in its place
+    /// while the task filter is on
+    task_view: Option<String>,
+    /// The text of the file as decoded, or of the section the tab shows,
+    /// before the reader, plugins, scripts, page breaks and citations
+    /// changed it into `content`
+    source: String,
+    /// `source` as a code block by `source_view`, shown in place of
+    /// `content` while View Source is on
+    source_view: Option<String>,
+    /// Monotonic counter bumped on every content load/reload. Used as the
+    /// invalidation key for the renderer's per-document scroll cache so
+    /// parsed events and measured blocks can survive across frames without
+    /// re-hashing the entire content.
+    content_version: u64,
+    /// When the wheel last scrolled the document, until it is snapped to a
+    /// heading
+    last_wheel_scroll: Option<Instant>,
+    /// A lightweight tab showing a directory's README, replaced by the next
+    /// preview until the document is opened for real
+    preview: bool,
+    /// The only section of the document a section tab shows
+    section: Option<TabSection>,
+    /// Source of a tab not saved to a file yet, such as one pasted from the
+    /// clipboard; its path is where it would be saved
+    unsaved: Option<String>,
+    /// The comparison a compare tab shows in place of its document, the
+    /// newer of the two
+    compare: Option<Comparison>,
+}
 
-        let dark_mode = persisted
-            .dark_mode
-            .unwrap_or_else(|| cc.egui_ctx.style().visuals.dark_mode);
-        let zoom_level = persisted.zoom_level.unwrap_or(1.0).clamp(0.5, 3.0);
-        let show_outline = persisted.show_outline.unwrap_or(true);
-        let full_width_content = persisted.full_width_content.unwrap_or(false);
-        let show_explorer = persisted.show_explorer.unwrap_or(true);
+/// Two documents compared block by block, by a compare tab.
+struct Comparison {
+    /// The older document
+    old: PathBuf,
+    rows: Vec<compare::Row>,
+    /// Height of each row as last shown, or estimated from its lines
+    heights: Vec<f32>,
+    /// The two documents in columns, or the changes one after the other
+    side_by_side: bool,
+    /// Text of the two documents, to compare again since one of them changed
+    pending: Option<(String, String)>,
+    /// The comparison running in the background
+    diff: Option<background::Job<Vec<compare::Row>>>,
+}
 
-        // Determine initial tabs
-        let initial_tabs: Vec<Tab> = if let Some(ref path) = file {
-            // CLI argument takes priority
-            vec![Tab::new(path.clone())]
-        } else if let Some(paths) = persisted.open_tabs {
-            // Restore previous session tabs
-            paths
-                .into_iter()
-                .filter(|p| p.exists())
-                .map(Tab::new)
-                .collect()
-        } else {
-            // No file and no saved session → start empty (welcome page).
-            Vec::new()
+impl Comparison {
+    /// Compare the documents again in the background once one changed, and
+    /// take the rows of the comparison once it's done.
+    fn poll(&mut self, jobs: &mut background::Jobs, ctx: &egui::Context) {
+        if let Some((old, new)) = self.pending.take() {
+            // The comparison of the text before is of no use any more
+            self.diff = Some(jobs.spawn(ctx, "Comparing documents", move |_| {
+                compare::compare(&old, &new)
+            }));
+        }
+        let Some(job) = &self.diff else {
+            return;
         };
+        if let Some(rows) = job.try_take() {
+            self.heights.clear();
+            self.rows = rows;
+            self.diff = None;
+        } else if job.is_cancelled() {
+            self.diff = None;
+        }
+    }
+}
 
-        let tabs = initial_tabs;
+/// The section of its document a section tab shows. It is found again by its
+/// heading when the file changes, or else by the line the heading was on.
+#[derive(Clone)]
+struct TabSection {
+    title: String,
+    slug: String,
+    nth_with_same_slug: usize,
+    /// 0-based line of the heading in the document
+    line: usize,
+}
 
-        let active_tab = persisted
-            .active_tab
-            .unwrap_or(0)
-            .min(tabs.len().saturating_sub(1));
+impl Tab {
+    fn compute_base_uri(path: &std::path::Path) -> String {
+        path.parent()
+            .map(|p| format!("file://{}/", p.display()))
+            .unwrap_or_else(|| "file://".to_string())
+    }
 
-        // Initialize file explorer
-        let mut file_explorer = FileExplorer::default();
+    fn new(path: PathBuf) -> Self {
+        // Canonicalize path for consistent comparison with watcher events
+        let path = path.canonicalize().unwrap_or(path);
+        let file_size = fs::metadata(&path).map_or(0, |m| m.len());
+        let load_limit = (file_size > LARGE_FILE_BYTES).then_some(LARGE_FILE_CHUNK_BYTES);
+        let (bytes, truncated) = read_document_bytes(&path, load_limit).unwrap_or_default();
+        let (source, encoding) = decode_document(&bytes, None);
+        let content = reader::process_document(&path, source.clone());
+        let content = plugins::process_document(content);
+        let content = scripting::on_document_load(&path, content);
+        let content = pages::process_document(content);
+        let content = citations::process_document(&path, content);
+        let parsed = parse_headers(&content);
+        let local_links = parse_local_links(&content);
+        let content_lines = content.lines().count();
+        let base_uri = Self::compute_base_uri(&path);
 
-        // Restore sort order before scanning (so initial scan uses correct order)
-        if let Some(sort_order) = persisted.explorer_sort_order {
-            file_explorer.sort_order = sort_order;
+        let mut cache = new_document_cache();
+        for link in &local_links {
+            cache.add_link_hook(link);
         }
 
-        // Determine explorer root:
-        // 1. From CLI file path
-        // 2. From persisted state
-        // 3. From first open tab
-        // 4. Current working directory as fallback
-        let explorer_root = file
-            .as_ref()
-            .and_then(|p| p.parent().map(|p| p.to_path_buf()))
-            .or(persisted.explorer_root.filter(|p| p.exists()))
-            .or_else(|| {
-                tabs.first()
-                    .and_then(|t| t.path.parent().map(|p| p.to_path_buf()))
-            })
-            .or_else(|| std::env::current_dir().ok());
+        Self {
+            id: egui::Id::new(&path),
+            path,
+            content,
+            source,
+            encoding,
+            encoding_override: None,
+            load_limit,
+            truncated,
+            file_size,
+            missing_since: None,
+            cache,
+            document_title: parsed.document_title,
+            outline_headers: parsed.outline_headers,
+            collapsed_headers: HashSet::new(),
+            scroll_offset: 0.0,
+            pending_scroll_offset: None,
+            pending_line: None,
+            problems: None,
+            footnotes: None,
+            figures: None,
+            pending_header_click_key: None,
+            correct_active_search_pending: false,
+            last_content_height: 0.0,
+            last_viewport_height: 0.0,
+            content_lines,
+            local_links,
+            base_uri,
+            history_back: Vec::new(),
+            history_forward: Vec::new(),
+            search_matches: Vec::new(),
+            task_view: None,
+            source_view: None,
+            content_version: 1,
+            last_wheel_scroll: None,
+            preview: false,
+            section: None,
+            unsaved: None,
+            compare: None,
+        }
+    }
+
+    /// A tab showing just the section of `path` whose heading is on 0-based
+    /// `line`, or `None` when there's no heading there.
+    fn new_section(path: PathBuf, line: usize) -> Option<Self> {
+        let mut tab = Self::new(path);
+        tab.section = Some(TabSection {
+            title: String::new(),
+            slug: String::new(),
+            nth_with_same_slug: 0,
+            line,
+        });
+        // The section may lie past the first chunk of a large file
+        tab.load_limit = None;
+        tab.reload();
+        tab.section.is_some().then_some(tab)
+    }
+
+    /// A tab comparing the document at `old` with the one at `new`.
+    fn new_comparison(old: PathBuf, new: PathBuf) -> Self {
+        let mut tab = Self::new(new);
+        tab.compare = Some(Comparison {
+            old: old.canonicalize().unwrap_or(old),
+            rows: Vec::new(),
+            heights: Vec::new(),
+            side_by_side: true,
+            pending: None,
+            diff: None,
+        });
+        tab.reload();
+        tab
+    }
 
-        if let Some(ref root) = explorer_root {
-            file_explorer.set_root(root.clone());
-        }
+    /// A tab showing `text` that isn't saved yet, named after `path`.
+    fn new_unsaved(path: PathBuf, text: String) -> Self {
+        let mut tab = Self::new(path);
+        tab.unsaved = Some(text);
+        tab.reload();
+        tab
+    }
 
-        // Restore expanded directories (children will lazy-load on first render)
-        if let Some(expanded) = persisted.expanded_dirs {
-            file_explorer.expanded_dirs = expanded.into_iter().collect();
+    fn title(&self) -> String {
+        let name = self
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Unknown".to_string());
+        match (&self.section, &self.compare) {
+            (Some(section), _) => format!("{name} › {}", section.title),
+            (None, Some(comparison)) => {
+                let old = comparison.old.file_name().unwrap_or_default();
+                format!("{} ↔ {name}", old.to_string_lossy())
+            }
+            (None, None) => name,
         }
+    }
 
-        #[cfg(feature = "mcp")]
-        let mcp_bridge = McpBridge::builder().port(9877).build();
-        #[cfg(feature = "mcp")]
-        log::info!("MCP bridge listening on port {}", mcp_bridge.port());
-
-        // Detect virtual display (e.g., Xvfb :99) to limit frame rate
-        // Virtual displays lack vsync, causing unlimited FPS and high CPU
-        let is_virtual_display = std::env::var("DISPLAY")
-            .map(|d| d != ":0" && d != ":0.0" && !d.is_empty())
-            .unwrap_or(false);
+    fn reload(&mut self) {
+        if let Some(text) = self.unsaved.take() {
+            self.apply_reloaded(text.as_bytes(), false);
+            self.unsaved = Some(text);
+            return;
+        }
+        match read_document_bytes(&self.path, self.load_limit) {
+            Ok((bytes, truncated)) => {
+                self.missing_since = None;
+                self.apply_reloaded(&bytes, truncated);
+                if let Some(comparison) = &mut self.compare {
+                    let (new, _) = decode_document(&bytes, None);
+                    let old = fs::read(&comparison.old)
+                        .map(|old| decode_document(&old, None).0)
+                        .unwrap_or_default();
+                    comparison.pending = Some((old, new));
+                }
+            }
+            Err(e) => {
+                if self.missing_since.is_none() {
+                    log::warn!(
+                        "Failed to reload {:?}, keeping its content: {}",
+                        self.path,
+                        e
+                    );
+                    self.missing_since = Some(Instant::now());
+                }
+            }
+        }
+    }
 
-        let mut app = Self {
-            tabs,
-            active_tab,
-            dark_mode,
-            zoom_level,
-            show_outline,
-            full_width_content,
-            watch_enabled: watch,
-            error_message: None,
-            is_dragging: false,
-            watcher: None,
-            watcher_rx: None,
-            watcher_retry_count: 0,
-            watched_paths: HashSet::new(),
-            watched_explorer_dirs: HashSet::new(),
-            hovered_tab: None,
-            file_explorer,
-            show_explorer,
-            flashing_paths: HashMap::new(),
-            is_virtual_display,
-            egui_ctx: cc.egui_ctx.clone(),
-            last_applied_dark_mode: None,
-            last_window_title: String::new(),
-            title_dirty: true,
-            open_tab_paths: HashSet::new(),
-            lightbox: None,
-            lightbox_scroll: 0.0,
-            lightbox_open_count: 0,
-            search: SearchState::default(),
-            recent_files: persisted.recent_files.unwrap_or_default(),
-            welcome_show_all: false,
-            #[cfg(feature = "mcp")]
-            mcp_bridge,
+    /// Scroll to the `idx`th outline header.
+    fn scroll_to_outline_header(&mut self, idx: usize) {
+        let Some(header) = self.outline_headers.get(idx) else {
+            return;
         };
-
-        app.refresh_open_tab_paths();
-
-        // Record a CLI-provided file as recent (an explicit open). Session-restored
-        // tabs are not treated as explicit opens, so they are not recorded here.
-        if let Some(f) = &file {
-            let f = f.canonicalize().unwrap_or_else(|_| f.clone());
-            app.record_recent(&f);
+        // The key disambiguates duplicate-titled headers (e.g. two
+        // `## Installation` sections). Each occurrence has its own
+        // `nth_with_same_slug` index assigned at parse time, and the
+        // renderer numbers the headings it records the same way.
+        let key = HeaderKey::new(self.id, header.nth_with_same_slug, &header.slug);
+        // Try to get actual rendered position from cache first.
+        // With virtualization, the cache may hold a stale value from a
+        // partial render — record the key for the post-render
+        // corrective step which re-checks after the bootstrap full paint.
+        if let Some(y_pos) = self.cache.get_header_position(&key) {
+            self.pending_scroll_offset = Some((y_pos - 50.0).max(0.0));
+        } else if self.last_content_height > 0.0 && self.content_lines > 0 {
+            // Fallback: estimate position based on line number ratio
+            let estimated_y =
+                (header.line_number as f32 / self.content_lines as f32) * self.last_content_height;
+            self.pending_scroll_offset = Some((estimated_y - 50.0).max(0.0));
+        }
+        // Remember the click target — once the bootstrap full paint
+        // triggered by `pending_scroll_offset` populates the cache, the
+        // corrective step in `render_tab_content` snaps to the precise y.
+        self.pending_header_click_key = Some(key);
+    }
+
+    /// The source line at the top of the viewport, or the one still to be
+    /// scrolled to when the tab hasn't been shown yet.
+    fn reading_line(&self) -> usize {
+        self.pending_line
+            .unwrap_or_else(|| self.line_at_offset(self.scroll_offset))
+    }
+
+    /// Whether the file has been missing for longer than the grace period.
+    fn is_missing(&self) -> bool {
+        self.missing_since
+            .is_some_and(|since| since.elapsed() >= MISSING_FILE_GRACE)
+    }
+
+    /// Replace the content with freshly read `bytes`.
+    fn apply_reloaded(&mut self, bytes: &[u8], truncated: bool) {
+        self.truncated = truncated;
+        self.file_size = fs::metadata(&self.path).map_or(0, |m| m.len());
+        let (source, encoding) = decode_document(bytes, self.encoding_override);
+        self.encoding = encoding;
+        let content = reader::process_document(&self.path, source.clone());
+        let content = plugins::process_document(content);
+        let content = scripting::on_document_load(&self.path, content);
+        let content = pages::process_document(content);
+        let content = citations::process_document(&self.path, content);
+        self.content = match &mut self.section {
+            Some(section) => match cut_section(&content, section) {
+                Some(cut) => cut,
+                None => {
+                    log::warn!(
+                        "Section {:?} is gone from {:?}, showing all of it",
+                        section.title,
+                        self.path
+                    );
+                    self.section = None;
+                    content
+                }
+            },
+            None => content,
+        };
+        // Scripts may have added the heading, then the file has no such section
+        self.source = match &self.section {
+            Some(section) => cut_section(&source, &mut section.clone()).unwrap_or(source),
+            None => source,
+        };
+        self.content_lines = self.content.lines().count();
+        // The cache is kept: the renderer drops the header positions of
+        // the old text itself, and images and highlighting carry over.
+        self.content_version = self.content_version.wrapping_add(1);
+        self.base_uri = Self::compute_base_uri(&self.path);
+
+        let parsed = parse_headers(&self.content);
+        self.document_title = parsed.document_title;
+        self.outline_headers = parsed.outline_headers;
+        self.collapsed_headers.clear();
+
+        self.local_links = parse_local_links(&self.content);
+        for link in &self.local_links {
+            self.cache.add_link_hook(link);
         }
 
-        if watch {
-            app.start_watching();
+        // Stale byte ranges; caller rebuilds if search bar is open
+        self.search_matches.clear();
+        self.problems = None;
+        self.footnotes = None;
+        self.figures = None;
+        if self.task_view.is_some() {
+            self.task_view = Some(task_list_view(&self.content));
         }
+        if self.source_view.is_some() {
+            self.source_view = Some(source_view(&self.source, self.source_language()));
+        }
+    }
 
-        app
+    /// Show only the tasks of the document, or all of it again.
+    fn toggle_task_view(&mut self) {
+        self.task_view = match self.task_view {
+            Some(_) => None,
+            None => Some(task_list_view(&self.content)),
+        };
+        self.source_view = None;
+        self.content_version = self.content_version.wrapping_add(1);
     }
 
-    fn window_title(&self) -> String {
-        if let Some(tab) = self.tabs.get(self.active_tab) {
-            format!("{} - Markdown Viewer", tab.title())
+    /// Show the source of the document, or the rendered document again,
+    /// at the same line.
+    fn toggle_source_view(&mut self) {
+        let line = match self.task_view {
+            Some(_) => None,
+            None => Some(self.reading_line()),
+        };
+        self.source_view = match self.source_view {
+            Some(_) => None,
+            None => Some(source_view(&self.source, self.source_language())),
+        };
+        self.task_view = None;
+        self.content_version = self.content_version.wrapping_add(1);
+        // The blocks measured are those of the other view until the next paint
+        self.cache.clear_source_blocks(egui::Id::new(self.id));
+        self.pending_line = line.filter(|&line| line > 1);
+    }
+
+    /// The language the source view highlights `source` as.
+    fn source_language(&self) -> &'static str {
+        if reader::is_html(&self.path) {
+            "html"
         } else {
-            "Markdown Viewer".to_string()
+            "markdown"
         }
     }
 
-    fn is_markdown_file(path: &std::path::Path) -> bool {
-        path.extension()
-            .map(|ext| {
-                let ext = ext.to_string_lossy().to_lowercase();
-                ext == "md" || ext == "markdown" || ext == "txt"
-            })
-            .unwrap_or(false)
+    /// The 1-based source line at `scroll_offset`, that of the line shown
+    /// there in the source view. The source view is a single code block, so
+    /// its line is interpolated within it, less the line of the fence.
+    fn line_at_offset(&self, scroll_offset: f32) -> usize {
+        match self.source_view {
+            Some(_) => self
+                .cache
+                .line_for_y(self.id, scroll_offset)
+                .map_or(1, |line| line.saturating_sub(1).max(1)),
+            None => line_at_scroll(self.cache.source_blocks(self.id), scroll_offset),
+        }
     }
 
-    fn open_file_dialog(&mut self) {
-        if let Some(path) = rfd::FileDialog::new()
-            .add_filter("Markdown", &["md", "markdown"])
-            .add_filter("Text", &["txt"])
-            .add_filter("All Files", &["*"])
-            .pick_file()
-        {
-            self.open_in_new_tab(path);
-        }
+    /// Read the next chunk of a large file, or the rest of it when `all`.
+    fn load_more(&mut self, all: bool) {
+        self.load_limit = match self.load_limit {
+            Some(limit) if !all => Some(limit + LARGE_FILE_CHUNK_BYTES),
+            _ => None,
+        };
+        self.reload();
     }
 
-    /// Open a native folder picker and point the file explorer at the chosen
-    /// directory (issue #28). The chosen root is persisted via `save()`.
-    fn open_folder_dialog(&mut self) {
-        if let Some(path) = rfd::FileDialog::new().pick_folder() {
-            self.file_explorer.set_root(path);
-            // Make sure the explorer is visible so the result is seen.
-            self.show_explorer = true;
-            // Rebuild the watcher so the new root is watched recursively
-            // (`update_watched_paths` only reconciles tab paths, not the root).
-            if self.watch_enabled {
-                self.start_watching();
-            }
-        }
+    /// Rebuild `search_matches` for `query`, in `source` while View Source
+    /// is on. Empty query clears matches.
+    fn rebuild_search(&mut self, query: &str) {
+        let text = match self.source_view {
+            Some(_) => &self.source,
+            None => &self.content,
+        };
+        self.search_matches = find_matches(text, query);
     }
 
-    fn open_in_new_tab(&mut self, path: PathBuf) {
-        // Canonicalize for consistent comparison with existing tabs
-        let path = path.canonicalize().unwrap_or(path);
-        self.record_recent(&path);
-        // Check if already open
-        if let Some(idx) = self.tabs.iter().position(|t| t.path == path) {
-            self.active_tab = idx;
-            self.title_dirty = true;
+    fn load_file(&mut self, path: &PathBuf) {
+        if !path.exists() {
             return;
         }
 
-        // Add new tab
-        let tab = Tab::new(path);
-        self.tabs.push(tab);
-        self.active_tab = self.tabs.len() - 1;
-        self.title_dirty = true;
-        self.refresh_open_tab_paths();
+        let file_size = fs::metadata(path).map_or(0, |m| m.len());
+        let load_limit = (file_size > LARGE_FILE_BYTES).then_some(LARGE_FILE_CHUNK_BYTES);
+        if let Ok((bytes, truncated)) = read_document_bytes(path, load_limit) {
+            self.section = None;
+            self.unsaved = None;
+            self.file_size = file_size;
+            self.load_limit = load_limit;
+            self.truncated = truncated;
+            let (source, encoding) = decode_document(&bytes, None);
+            self.encoding = encoding;
+            self.encoding_override = None;
+            let content = reader::process_document(path, source.clone());
+            let content = plugins::process_document(content);
+            let content = scripting::on_document_load(path, content);
+            let content = pages::process_document(content);
+            self.content = citations::process_document(path, content);
+            self.source = source;
+            self.content_lines = self.content.lines().count();
+            self.path = path.clone();
+            self.id = egui::Id::new(path);
+            self.cache = new_document_cache();
+            self.content_version = self.content_version.wrapping_add(1);
+            self.scroll_offset = 0.0;
+            self.pending_scroll_offset = None;
+            self.base_uri = Self::compute_base_uri(&self.path);
 
-        // Update watcher if enabled
-        if self.watch_enabled {
-            self.update_watched_paths();
-        }
-    }
+            let parsed = parse_headers(&self.content);
+            self.document_title = parsed.document_title;
+            self.outline_headers = parsed.outline_headers;
+            self.collapsed_headers.clear();
 
-    /// Record a file in the recent list (most-recent first, deduped, capped).
-    fn record_recent(&mut self, path: &Path) {
-        push_recent(&mut self.recent_files, path, now_epoch_secs());
+            self.local_links = parse_local_links(&self.content);
+            for link in &self.local_links {
+                self.cache.add_link_hook(link);
+            }
+
+            // Stale byte ranges; caller rebuilds if search bar is open
+            self.search_matches.clear();
+            self.problems = None;
+            self.footnotes = None;
+            self.figures = None;
+            self.task_view = None;
+            self.source_view = None;
+        }
     }
 
-    fn close_tab(&mut self, idx: usize) {
-        if idx >= self.tabs.len() {
+    /// Follow `link` in this tab. `root`, when set, is where links starting
+    /// with `/` are resolved from, see [`Tab::resolve_link`].
+    fn navigate_to_link(&mut self, link: &str, root: Option<&Path>) {
+        if let Some(anchor) = link.strip_prefix('#') {
+            self.cache.scroll_to_heading(self.id, anchor);
             return;
         }
 
-        self.tabs.remove(idx);
-        self.title_dirty = true;
-        self.refresh_open_tab_paths();
+        let Some(target_path) = self.resolve_link(link, root) else {
+            return;
+        };
 
-        // Adjust active tab index. Closing the last tab leaves `tabs` empty,
-        // which renders the welcome page (active_tab kept in range / at 0).
-        if self.tabs.is_empty() {
-            self.active_tab = 0;
-        } else if self.active_tab >= self.tabs.len() {
-            self.active_tab = self.tabs.len() - 1;
-        } else if self.active_tab > idx {
-            self.active_tab -= 1;
+        self.history_back.push(self.history_entry());
+        self.history_forward.clear();
+        self.pending_line = None;
+        self.load_file(&target_path);
+        if let Some((_, anchor)) = link.split_once('#') {
+            self.cache.scroll_to_heading(self.id, anchor);
         }
+    }
 
-        // Update watcher
-        if self.watch_enabled {
-            self.update_watched_paths();
+    fn check_link_hooks(&self) -> Option<String> {
+        for link in &self.local_links {
+            if let Some(true) = self.cache.get_link_hook(link) {
+                return Some(link.clone());
+            }
         }
+        None
     }
 
-    fn close_active_tab(&mut self) {
-        self.close_tab(self.active_tab);
+    fn can_go_back(&self) -> bool {
+        !self.history_back.is_empty()
     }
 
-    fn next_tab(&mut self) {
-        if self.tabs.len() > 1 {
-            self.active_tab = (self.active_tab + 1) % self.tabs.len();
-            self.title_dirty = true;
+    fn can_go_forward(&self) -> bool {
+        !self.history_forward.is_empty()
+    }
+
+    fn navigate_back(&mut self) {
+        if let Some(prev) = self.history_back.pop() {
+            self.history_forward.push(self.history_entry());
+            self.return_to(prev);
         }
     }
 
-    fn prev_tab(&mut self) {
-        if self.tabs.len() > 1 {
-            self.active_tab = if self.active_tab == 0 {
-                self.tabs.len() - 1
-            } else {
-                self.active_tab - 1
-            };
-            self.title_dirty = true;
+    fn navigate_forward(&mut self) {
+        if let Some(next) = self.history_forward.pop() {
+            self.history_back.push(self.history_entry());
+            self.return_to(next);
         }
     }
 
-    fn focus_tab(&mut self, idx: usize) {
-        if idx < self.tabs.len() {
-            self.active_tab = idx;
-            self.title_dirty = true;
+    /// The current document and line, to come back to through the history.
+    fn history_entry(&self) -> ReadingPosition {
+        ReadingPosition {
+            path: self.path.clone(),
+            line: self.reading_line(),
         }
     }
 
-    fn get_open_tab_paths(&self) -> Vec<PathBuf> {
-        self.tabs
-            .iter()
-            .filter(|t| t.path.exists())
-            .map(|t| t.path.clone())
-            .collect()
+    /// Load a history entry's document, scrolled to where it was left.
+    fn return_to(&mut self, entry: ReadingPosition) {
+        self.load_file(&entry.path);
+        self.pending_line = (entry.line > 1).then_some(entry.line);
     }
 
-    /// Rebuild the cached open_tab_paths set (call after tab open/close/navigate)
-    fn refresh_open_tab_paths(&mut self) {
-        self.open_tab_paths.clear();
-        for tab in &self.tabs {
-            self.open_tab_paths.insert(tab.path.clone());
+    fn history(&self) -> TabHistory {
+        TabHistory {
+            back: self.history_back.clone(),
+            forward: self.history_forward.clone(),
         }
     }
 
-    fn start_watching(&mut self) {
-        self.stop_watching();
+    fn restore_history(&mut self, history: TabHistory) {
+        self.history_back = history.back;
+        self.history_forward = history.forward;
+    }
 
-        let tab_paths = self.get_open_tab_paths();
-        let explorer_root = self.file_explorer.root.clone();
+    /// The file `link` points at. Links starting with `/` point into `root`
+    /// when it's set, like static site generators and wikis read them, and
+    /// fall back to the filesystem root for paths that aren't in it.
+    fn resolve_link(&self, link: &str, root: Option<&Path>) -> Option<PathBuf> {
+        if link.starts_with('#') {
+            return None;
+        }
 
-        // Need something to watch
-        if tab_paths.is_empty() && explorer_root.is_none() {
-            return;
+        let current_dir = self.path.parent()?;
+        let path_part = link.split('#').next().unwrap_or(link);
+        let rooted = root.zip(path_part.strip_prefix('/'));
+        if let Some(target_path) =
+            rooted.and_then(|(root, rest)| root.join(rest).canonicalize().ok())
+        {
+            return Some(target_path);
         }
+        let target_path = current_dir.join(path_part);
+        target_path.canonicalize().ok()
+    }
+}
 
-        // Partition tab paths into local (inotify) and GVFS (poll)
-        let (gvfs_paths, local_paths): (Vec<_>, Vec<_>) =
-            tab_paths.iter().partition(|p| is_gvfs_path(p));
-        let explorer_is_gvfs = explorer_root.as_ref().is_some_and(|r| is_gvfs_path(r));
-        // Explorer root only watched via inotify (local) — GVFS explorer roots are
-        // skipped to avoid costly recursive polling over SFTP
-        let has_local = !local_paths.is_empty() || (explorer_root.is_some() && !explorer_is_gvfs);
-        let has_gvfs = !gvfs_paths.is_empty();
+/// Parse local markdown file links and anchor links from content, skipping code blocks.
+fn parse_local_links(content: &str) -> Vec<String> {
+    let link_re = &*LINK_RE;
+    let mut links = Vec::new();
+    let mut in_code_block = false;
 
-        // Non-recursive: mirror the lazy explorer tree. Watching the whole root
-        // subtree recursively walks every directory on the main thread at startup
-        // (~6s hang on /home/ahmet, ~455k dirs). We only need change events for
-        // the directories the user can actually see: the root plus each expanded
-        // directory. GVFS explorer roots stay unwatched (costly over SFTP).
-        let mut explorer_dirs: Vec<PathBuf> = Vec::new();
-        if let Some(ref root) = explorer_root {
-            if !explorer_is_gvfs {
-                explorer_dirs.push(root.clone());
-                explorer_dirs.extend(
-                    self.file_explorer
-                        .expanded_dirs
-                        .iter()
-                        .filter(|d| !is_gvfs_path(d) && d.is_dir())
-                        .cloned(),
-                );
-            }
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
         }
 
-        // Both debouncers send to the same channel
-        let (tx, debouncer_rx) = mpsc::channel();
-
-        // Create inotify debouncer for local paths
-        let inotify_debouncer = if has_local {
-            match new_debouncer(Duration::from_millis(200), tx.clone()) {
-                Ok(mut debouncer) => {
-                    for path in &local_paths {
-                        if let Err(e) = debouncer
-                            .watcher()
-                            .watch(path, notify::RecursiveMode::NonRecursive)
-                        {
-                            log::error!("Failed to watch file {:?}: {}", path, e);
-                        } else {
-                            self.watched_paths.insert((*path).clone());
-                        }
-                    }
-                    for dir in &explorer_dirs {
-                        if let Err(e) = debouncer
-                            .watcher()
-                            .watch(dir, notify::RecursiveMode::NonRecursive)
-                        {
-                            log::error!("Failed to watch explorer dir {:?}: {}", dir, e);
-                        } else {
-                            self.watched_explorer_dirs.insert(dir.clone());
-                        }
-                    }
-                    Some(debouncer)
-                }
-                Err(e) => {
-                    log::error!("Failed to create inotify watcher: {}", e);
-                    None
-                }
-            }
-        } else {
-            None
-        };
+        if in_code_block {
+            continue;
+        }
 
-        // Create poll debouncer for GVFS/remote paths
-        let poll_debouncer = if has_gvfs {
-            let poll_config = notify_debouncer_mini::Config::default()
-                .with_timeout(Duration::from_millis(200))
-                .with_notify_config(
-                    notify::Config::default().with_poll_interval(Duration::from_secs(2)),
-                );
-            match new_debouncer_opt::<_, PollWatcher>(poll_config, tx.clone()) {
-                Ok(mut debouncer) => {
-                    for path in &gvfs_paths {
-                        if let Err(e) = debouncer
-                            .watcher()
-                            .watch(path, notify::RecursiveMode::NonRecursive)
-                        {
-                            log::error!("Failed to poll-watch file {:?}: {}", path, e);
-                        } else {
-                            self.watched_paths.insert((*path).clone());
-                        }
-                    }
-                    // Skip recursive watching of GVFS explorer root — polling an
-                    // entire remote directory tree every 2s causes lag from SFTP
-                    // roundtrips (stat + read_dir for each entry). Users can refresh
-                    // the explorer manually instead.
-                    Some(debouncer)
-                }
-                Err(e) => {
-                    log::error!("Failed to create poll watcher: {}", e);
-                    None
-                }
+        for cap in link_re.captures_iter(line) {
+            let destination = &cap[2];
+            if is_local_markdown_link(destination) || destination.starts_with('#') {
+                links.push(destination.to_string());
             }
-        } else {
-            None
-        };
+        }
+    }
 
-        // Build FileWatcher enum from whichever debouncers succeeded
-        let file_watcher = match (inotify_debouncer, poll_debouncer) {
-            (Some(i), Some(p)) => Some(FileWatcher::Dual {
-                inotify: i,
-                poll: p,
-            }),
-            (Some(d), None) => Some(FileWatcher::Inotify(d)),
-            (None, Some(d)) => Some(FileWatcher::Poll(d)),
-            (None, None) => None,
-        };
+    links
+}
 
-        if let Some(fw) = file_watcher {
-            let local_count = local_paths.len();
-            let gvfs_count = gvfs_paths.len();
-            log::info!(
-                "Started watching {} local (inotify) + {} GVFS (poll) files, {} explorer dirs",
-                local_count,
-                gvfs_count,
-                self.watched_explorer_dirs.len()
-            );
+/// Check if a link destination points to a local markdown file
+fn is_local_markdown_link(destination: &str) -> bool {
+    if destination.starts_with("http://")
+        || destination.starts_with("https://")
+        || destination.starts_with("mailto:")
+        || destination.starts_with("tel:")
+        || destination.starts_with("ftp://")
+        || destination.starts_with('#')
+    {
+        return false;
+    }
 
-            // Bridge thread: forward events and wake egui on demand
-            let (bridge_tx, bridge_rx) = mpsc::channel();
-            let ctx = self.egui_ctx.clone();
-            std::thread::Builder::new()
-                .name("watcher-bridge".into())
-                .spawn(move || {
-                    while let Ok(event) = debouncer_rx.recv() {
-                        let _ = bridge_tx.send(event);
-                        ctx.request_repaint();
-                    }
-                })
-                .expect("failed to spawn watcher bridge thread");
+    let path_part = destination.split('#').next().unwrap_or(destination);
+    let path = std::path::Path::new(path_part);
+    path.extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy().to_lowercase();
+            ext == "md" || ext == "markdown" || ext == "txt"
+        })
+        .unwrap_or(false)
+}
 
-            self.watcher = Some(fw);
-            self.watcher_rx = Some(bridge_rx);
-            self.watch_enabled = true;
-            self.watcher_retry_count = 0;
-        } else {
-            log::error!("Failed to create any file watcher");
-            self.error_message = Some("Failed to create file watcher".to_string());
+/// Truncate a string for display, adding "..." if it exceeds max_len.
+/// Respects char boundaries for UTF-8 safety.
+fn truncate_display_name(s: &str, max_len: usize) -> String {
+    if s.len() > max_len {
+        let mut end = (max_len - 3).min(s.len());
+        while end > 0 && !s.is_char_boundary(end) {
+            end -= 1;
         }
+        format!("{}...", &s[..end])
+    } else {
+        s.to_string()
     }
+}
 
-    fn stop_watching(&mut self) {
-        if self.watcher.is_some() {
-            log::info!("Stopped watching files");
+/// Collect the document's headings from the renderer's own parse, so the
+/// outline matches what is displayed (setext headings included, headings in
+/// code blocks excluded).
+fn parse_headers(content: &str) -> ParsedHeaders {
+    let mut all_headers: Vec<Header> = Vec::new();
+    // Words between each heading and the next one
+    let mut own_words: Vec<usize> = Vec::new();
+
+    for block in CommonMarkViewer::new().parse_document(content) {
+        let words = match &block.kind {
+            BlockKind::Heading { .. } => 0,
+            BlockKind::Paragraph { text } => text.split_whitespace().count(),
+            BlockKind::CodeBlock { code, .. } => code.split_whitespace().count(),
+            BlockKind::Table { header, rows } => header
+                .iter()
+                .chain(rows.iter().flatten())
+                .map(|cell| cell.split_whitespace().count())
+                .sum(),
+        };
+        if let Some(last) = own_words.last_mut() {
+            *last += words;
         }
-        self.watcher = None;
-        self.watcher_rx = None;
-        self.watched_paths.clear();
-        self.watched_explorer_dirs.clear();
+        let BlockKind::Heading { level, text, .. } = block.kind else {
+            continue;
+        };
+        own_words.push(0);
+        let title = text;
+        let slug = heading_slug(&title);
+        let display_title = truncate_display_name(&title, 35);
+        // Count prior headers with the same slug, the same way the renderer
+        // numbers them, so each duplicate gets a distinct cache key.
+        let nth_with_same_slug = all_headers.iter().filter(|h| h.slug == slug).count();
+        all_headers.push(Header {
+            level,
+            title,
+            display_title,
+            slug,
+            nth_with_same_slug,
+            line_number: block.line - 1,
+            start: block.byte_range.start,
+            number: None,
+            words: 0,
+        });
+    }
+    let levels: Vec<u8> = all_headers.iter().map(|h| h.level).collect();
+    for (header, number) in all_headers.iter_mut().zip(toc::section_numbers(&levels)) {
+        header.number = number;
+    }
+    for i in 0..all_headers.len() {
+        let level = all_headers[i].level;
+        let subsections = all_headers[i + 1..]
+            .iter()
+            .take_while(|h| h.level > level)
+            .count();
+        all_headers[i].words = own_words[i..=i + subsections].iter().sum();
     }
 
-    fn update_watched_paths(&mut self) {
-        if !self.watch_enabled {
-            return;
-        }
+    let document_title = all_headers
+        .iter()
+        .find(|h| h.level == 1)
+        .map(|h| h.title.clone());
+    let outline_headers = all_headers;
 
-        let current_paths: HashSet<PathBuf> = self.get_open_tab_paths().into_iter().collect();
+    ParsedHeaders {
+        document_title,
+        outline_headers,
+    }
+}
 
-        if let Some(fw) = &mut self.watcher {
-            // Check if we need a watcher type that doesn't currently exist
-            let needs_poll = current_paths.iter().any(|p| is_gvfs_path(p));
-            let needs_inotify = current_paths.iter().any(|p| !is_gvfs_path(p));
-            let has_poll = fw.poll_watcher().is_some();
-            let has_inotify = fw.inotify_watcher().is_some();
+/// `content` with the section of `headers[from]`, subsections included,
+/// moved in front of the heading `headers[to]`, or to the end of the document
+/// for `to` past the last heading. `None` if the section wouldn't move.
+fn move_section(content: &str, headers: &[Header], from: usize, to: usize) -> Option<String> {
+    headers.get(from)?;
+    let end = section_end(headers, from);
+    if (from..=end).contains(&to) || to > headers.len() {
+        return None;
+    }
+    let offsets = header_offsets(content, headers);
+    let (start, stop) = (offsets[from], offsets[end]);
+    let mut section = content[start..stop].to_string();
+    if !section.ends_with('\n') {
+        section.push('\n');
+    }
+
+    let mut moved = format!("{}{}", &content[..start], &content[stop..]);
+    let mut at = offsets[to];
+    if at > start {
+        at -= stop - start;
+    }
+    if at == moved.len() && !moved.is_empty() && !moved.ends_with('\n') {
+        moved.push('\n');
+        at += 1;
+    }
+    moved.insert_str(at, &section);
+    Some(moved)
+}
 
-            if (needs_poll && !has_poll) || (needs_inotify && !has_inotify) {
-                // Watcher configuration changed (e.g., first GVFS tab added), restart
-                log::info!("Watcher type mismatch, restarting watchers");
-                self.start_watching();
-                return;
-            }
+/// The file `bytes` decoded as `encoding`, with the section of the shown
+/// heading `from` moved in front of the shown heading `to`, encoded back.
+///
+/// The shown headings are those of the document after plugins, scripts and
+/// citations; the move is made in the file's own text, so none of what they
+/// added or rewrote is saved. The file's headings have to lead the shown
+/// ones, as with a references section appended, to tell which sections
+/// move. `Ok(None)` if the section wouldn't move.
+fn move_section_in_file(
+    bytes: &[u8],
+    encoding: &'static Encoding,
+    shown: &[Header],
+    from: usize,
+    to: usize,
+) -> Result<Option<Vec<u8>>, String> {
+    let (source, _) = decode_document(bytes, Some(encoding));
+    let headers = parse_headers(&source).outline_headers;
+    let leads = headers.len() <= shown.len()
+        && headers
+            .iter()
+            .zip(shown)
+            .all(|(file, shown)| file.level == shown.level && file.title == shown.title);
+    if !leads || from >= headers.len() {
+        return Err("the headings of the file differ from the outline".to_string());
+    }
+    let Some(moved) = move_section(&source, &headers, from, to.min(headers.len())) else {
+        return Ok(None);
+    };
+    encode_document(&moved, encoding, bytes)
+        .map(Some)
+        .ok_or_else(|| format!("the text can't be saved as {}", encoding.name()))
+}
 
-            // Add new paths to the appropriate watcher
-            for path in current_paths.difference(&self.watched_paths) {
-                let watcher = if is_gvfs_path(path) {
-                    fw.poll_watcher()
-                } else {
-                    fw.inotify_watcher()
-                };
-                if let Some(w) = watcher {
-                    if let Err(e) = w.watch(path, notify::RecursiveMode::NonRecursive) {
-                        log::error!("Failed to watch file {:?}: {}", path, e);
-                    }
-                }
-            }
+/// Index of the first header after the section of `headers[index]` and its
+/// subsections, `headers.len()` when it runs to the end.
+fn section_end(headers: &[Header], index: usize) -> usize {
+    let level = headers[index].level;
+    index
+        + 1
+        + headers[index + 1..]
+            .iter()
+            .take_while(|h| h.level > level)
+            .count()
+}
 
-            // Remove old paths from the appropriate watcher
-            for path in self.watched_paths.difference(&current_paths) {
-                let watcher = if is_gvfs_path(path) {
-                    fw.poll_watcher()
-                } else {
-                    fw.inotify_watcher()
-                };
-                if let Some(w) = watcher {
-                    let _ = w.unwatch(path);
-                }
-            }
-        }
+/// Byte offset in `content` of the line of each header, followed by the
+/// length of `content`.
+fn header_offsets(content: &str, headers: &[Header]) -> Vec<usize> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.split_inclusive('\n').scan(0, |at, line| {
+            *at += line.len();
+            Some(*at)
+        }))
+        .collect();
+    headers
+        .iter()
+        .map(|h| {
+            line_starts
+                .get(h.line_number)
+                .copied()
+                .unwrap_or(content.len())
+        })
+        .chain(std::iter::once(content.len()))
+        .collect()
+}
 
-        self.watched_paths = current_paths;
-    }
+/// Byte range in `content` of the section of `headers[index]`, its
+/// subsections included.
+fn section_range(
+    content: &str,
+    headers: &[Header],
+    index: usize,
+) -> Option<std::ops::Range<usize>> {
+    headers.get(index)?;
+    let offsets = header_offsets(content, headers);
+    Some(offsets[index]..offsets[section_end(headers, index)])
+}
 
-    /// Reconcile the non-recursive explorer-directory watches (root + expanded
-    /// dirs) against the live watcher after the expanded set changes. Mirrors
-    /// `update_watched_paths`'s incremental diff so expand/collapse doesn't tear
-    /// down and rebuild the debouncer + bridge thread.
-    fn reconcile_explorer_watches(&mut self) {
-        if !self.watch_enabled {
-            return;
-        }
+/// The part of `content` that `section` covers, with `section` updated to
+/// where its heading is now. `None` when the heading is gone.
+fn cut_section(content: &str, section: &mut TabSection) -> Option<String> {
+    let headers = parse_headers(content).outline_headers;
+    let index = headers
+        .iter()
+        .position(|h| {
+            !section.slug.is_empty()
+                && h.slug == section.slug
+                && h.nth_with_same_slug == section.nth_with_same_slug
+        })
+        .or_else(|| headers.iter().position(|h| h.line_number == section.line))?;
+    let range = section_range(content, &headers, index)?;
+    let header = &headers[index];
+    section.title = header.title.clone();
+    section.slug = header.slug.clone();
+    section.nth_with_same_slug = header.nth_with_same_slug;
+    section.line = header.line_number;
+    Some(content[range].to_string())
+}
 
-        // Desired set: root + each expanded dir (local, still existing).
-        let mut desired: HashSet<PathBuf> = HashSet::new();
-        if let Some(root) = &self.file_explorer.root {
-            if !is_gvfs_path(root) {
-                desired.insert(root.clone());
+/// Drag-and-drop payload of an outline heading being moved, its index
+struct OutlineDrag(usize);
+
+/// Reading speed the outline's section reading times are estimated at.
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Estimated reading time of `words`, e.g. "3 min".
+fn reading_time(words: usize) -> String {
+    format!("{} min", words.div_ceil(WORDS_PER_MINUTE).max(1))
+}
+
+/// A command sent over the remote control socket, or typed into the
+/// `Automation: Command` textbox by E2E agents since clicks on
+/// bridge-registered widgets are unreliable.
+#[derive(Debug, PartialEq)]
+enum AutomationCommand {
+    /// `open <path>` opens a file, or focuses the tab already showing it
+    Open(PathBuf),
+    /// `tab <n>` switches to the nth tab, counting from 1
+    Tab(usize),
+    /// `heading <title>`, `heading #<anchor>` or `goto <anchor>` scrolls to a
+    /// heading
+    Heading(String),
+    /// `reload` re-reads the active document
+    Reload,
+    /// `tabs` lists the open tabs by number, marking the active one
+    Tabs,
+    /// `outline` lists the headings of the active document with their
+    /// anchors, for `goto`
+    Outline,
+    /// `export <format> [path]` writes the active document as `html` or
+    /// `markdown`, HTML next to the document unless a path is given
+    Export(String, Option<PathBuf>),
+}
+
+impl AutomationCommand {
+    fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (verb, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        match verb {
+            "open" | "tab" | "heading" | "goto" | "export" if argument.is_empty() => {
+                Err(format!("`{verb}` needs an argument"))
             }
-        }
-        for d in &self.file_explorer.expanded_dirs {
-            if !is_gvfs_path(d) && d.is_dir() {
-                desired.insert(d.clone());
+            "open" => Ok(Self::Open(PathBuf::from(argument))),
+            "tab" => argument
+                .parse()
+                .ok()
+                .filter(|&number| number > 0)
+                .map(Self::Tab)
+                .ok_or_else(|| format!("not a tab number: {argument}")),
+            "heading" => Ok(Self::Heading(argument.to_string())),
+            "goto" => Ok(Self::Heading(format!(
+                "#{}",
+                argument.trim_start_matches('#')
+            ))),
+            "reload" => Ok(Self::Reload),
+            "tabs" => Ok(Self::Tabs),
+            "outline" => Ok(Self::Outline),
+            "export" => {
+                let (format, path) = argument.split_once(' ').unwrap_or((argument, ""));
+                let path = Some(path.trim())
+                    .filter(|p| !p.is_empty())
+                    .map(PathBuf::from);
+                Ok(Self::Export(format.to_string(), path))
             }
+            _ => Err(format!("unknown command: {verb}")),
         }
+    }
+}
 
-        if let Some(fw) = &mut self.watcher {
-            if let Some(w) = fw.inotify_watcher() {
-                for path in desired.difference(&self.watched_explorer_dirs) {
-                    if let Err(e) = w.watch(path, notify::RecursiveMode::NonRecursive) {
-                        log::error!("Failed to watch explorer dir {:?}: {}", path, e);
-                    }
-                }
-                for path in self.watched_explorer_dirs.difference(&desired) {
-                    let _ = w.unwatch(path);
-                }
-            }
+/// `content` exported as `format`: a standalone HTML page titled `title`, with
+/// contents and section numbers as `toc` asks, or the markdown as shown, with
+/// plugins and citations applied.
+fn export_document(
+    format: &str,
+    title: &str,
+    content: &str,
+    toc: toc::Options,
+) -> Result<String, String> {
+    match format {
+        "html" => {
+            let events = toc::apply(with_heading_ids(export_events(content)).collect(), toc);
+            let mut body = String::new();
+            pulldown_cmark::html::push_html(&mut body, events.into_iter());
+            Ok(format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+                 <title>{}</title>\n</head>\n<body>\n{body}</body>\n</html>\n",
+                escape_html(title)
+            ))
         }
-
-        self.watched_explorer_dirs = desired;
+        "markdown" | "md" => Ok(content.to_string()),
+        _ => Err(format!("unknown export format: {format} (html, markdown)")),
     }
+}
 
-    fn check_file_changes(&mut self) -> Vec<PathBuf> {
-        let Some(rx) = &self.watcher_rx else {
-            // Attempt recovery if watching is enabled and there's something to watch
-            // Check actual tabs and explorer root, not watched_paths (which may be empty after failure)
-            let has_watchable = !self.tabs.is_empty() || self.file_explorer.root.is_some();
-            if self.watch_enabled && has_watchable && self.watcher_retry_count < MAX_WATCHER_RETRIES
-            {
-                log::info!(
-                    "Attempting to recover file watcher (attempt {})",
-                    self.watcher_retry_count + 1
-                );
-                self.watcher_retry_count += 1;
-                self.start_watching();
-                self.egui_ctx.request_repaint_after(Duration::from_secs(2));
-            }
-            return Vec::new();
-        };
+/// `content` parsed with the extensions HTML export supports.
+fn export_events(content: &str) -> Vec<pulldown_cmark::Event<'_>> {
+    use pulldown_cmark::Options;
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_DEFINITION_LIST
+        | Options::ENABLE_MATH
+        | Options::ENABLE_GFM;
+    pulldown_cmark::Parser::new_ext(content, options).collect()
+}
 
-        let mut changed_paths = Vec::new();
+/// `text` escaped for HTML, the way exported pages escape text.
+fn escape_html(text: &str) -> String {
+    let mut html = String::new();
+    let text = pulldown_cmark::Event::Text(text.into());
+    pulldown_cmark::html::push_html(&mut html, std::iter::once(text));
+    html
+}
 
-        while let Ok(result) = rx.try_recv() {
-            match result {
-                Ok(events) => {
-                    self.watcher_retry_count = 0;
-                    for event in events {
-                        if event.kind == DebouncedEventKind::Any {
-                            log::debug!("File change detected: {:?}", event.path);
-                            changed_paths.push(event.path);
-                        }
-                    }
+/// `events` with an `id` on each heading, the same anchor the viewer
+/// navigates to, so `#links` keep working in exported HTML.
+fn with_heading_ids(
+    mut events: Vec<pulldown_cmark::Event<'_>>,
+) -> impl Iterator<Item = pulldown_cmark::Event<'_>> {
+    use pulldown_cmark::{Event, Tag, TagEnd};
+    let mut slugger = AnchorSlugger::default();
+    let mut heading: Option<(usize, String)> = None;
+    for i in 0..events.len() {
+        match &events[i] {
+            Event::Start(Tag::Heading { .. }) => heading = Some((i, String::new())),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, title)) = &mut heading {
+                    title.push_str(text);
                 }
-                Err(e) => {
-                    log::error!("File watcher error: {}", e);
-                    self.watcher = None;
-                    self.watcher_rx = None;
-
-                    if self.watcher_retry_count < MAX_WATCHER_RETRIES {
-                        self.watcher_retry_count += 1;
-                        log::info!(
-                            "Attempting watcher recovery (attempt {})",
-                            self.watcher_retry_count
-                        );
-                        self.start_watching();
-                        self.egui_ctx.request_repaint_after(Duration::from_secs(2));
-                    } else {
-                        self.error_message = Some(format!(
-                            "File watcher failed after {} retries: {}",
-                            MAX_WATCHER_RETRIES, e
-                        ));
-                        self.watch_enabled = false;
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let Some((start, title)) = heading.take() else {
+                    continue;
+                };
+                if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+                    let slug = slugger.slug(&title);
+                    if id.is_none() && !slug.is_empty() {
+                        *id = Some(slug.into());
                     }
-                    return Vec::new();
                 }
             }
+            _ => {}
         }
-
-        changed_paths
     }
+    events.into_iter()
+}
 
-    fn reload_changed_tabs(&mut self, changed_paths: Vec<PathBuf>) {
-        let now = Instant::now();
-        let mut refresh_tree = false;
-        // If the active tab gets reloaded while the find bar is open, its
-        // `search_matches` will be cleared by `Tab::reload`. Force a rebuild
-        // on the next frame by invalidating the cache-validity shadow state.
-        let active_path = self.tabs.get(self.active_tab).map(|t| t.path.clone());
+/// The outline as E2E agents read it: one line per heading, its level in
+/// `#`s followed by its title, after the document title.
+#[cfg_attr(not(feature = "mcp"), allow(dead_code))]
+fn outline_text(document_title: Option<&str>, headers: &[Header]) -> String {
+    let mut text = String::new();
+    if let Some(title) = document_title {
+        text.push_str(&format!("title: {title}\n"));
+    }
+    for header in headers {
+        let hashes = "#".repeat(header.level as usize);
+        text.push_str(&format!("{hashes} {}\n", header.title));
+    }
+    text
+}
 
-        for path in changed_paths {
-            // Trigger flash effect for the changed file (use canonical path for consistent lookup)
-            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
-            self.flashing_paths.insert(canonical, now);
+/// The source of the top-level blocks overlapping the viewport, which spans
+/// `height` from `scroll_offset` in content coordinates.
+#[cfg_attr(not(feature = "mcp"), allow(dead_code))]
+fn visible_source(
+    content: &str,
+    blocks: &[SourceBlock],
+    scroll_offset: f32,
+    height: f32,
+) -> String {
+    let viewport = egui::Rangef::new(scroll_offset, scroll_offset + height);
+    blocks
+        .iter()
+        .filter(|block| block.y_range.min < viewport.max && block.y_range.max > viewport.min)
+        .filter_map(|block| content.get(block.byte_range.clone()))
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
 
-            // Also flash parent directories up to the explorer root
-            if let Some(root) = &self.file_explorer.root {
-                // Check if the changed path is within the explorer root
-                if path.starts_with(root) {
-                    refresh_tree = true;
-                }
+/// Find all occurrences of `query` in `content`, case-insensitive (ASCII only).
+///
+/// Byte offsets are 1:1 with `content` because `to_ascii_lowercase` does not change
+/// byte length. Non-ASCII letters match literally (`É` does not match `é`) — see the
+/// devlog future-improvements list for the case-toggle and full Unicode case folding.
+///
+/// Matches spanning a newline are excluded (a search bar should not jump to results
+/// the user cannot interpret as a single line).
+///
+/// Link and image URLs `[text](url)` are never visible, so matches inside them are
+/// excluded; without this, cycling lands on matches the user can't see at all.
+/// Image alt text, link titles `[text](url "title")` and code fence info strings
+/// are kept: they describe what they're on, and the renderer marks the whole
+/// image, link or code block when a match is in them.
+fn find_matches(content: &str, query: &str) -> Vec<SearchMatch> {
+    if query.is_empty() || content.is_empty() {
+        return Vec::new();
+    }
 
-                let mut current = path.parent();
-                while let Some(parent) = current {
-                    if parent.starts_with(root) || parent == root {
-                        self.flashing_paths.insert(
-                            parent
-                                .canonicalize()
-                                .unwrap_or_else(|_| parent.to_path_buf()),
-                            now,
-                        );
-                    }
-                    if parent == root {
-                        break;
-                    }
-                    current = parent.parent();
-                }
-            }
+    let content_lc = content.to_ascii_lowercase();
+    let query_lc = query.to_ascii_lowercase();
+    let query_len = query_lc.len();
 
-            // Reload the tab content
-            let mut active_was_reloaded = false;
-            for tab in &mut self.tabs {
-                if tab.path == path {
-                    log::info!("Reloading tab: {:?}", path);
-                    tab.reload();
-                    if Some(&tab.path) == active_path.as_ref() {
-                        active_was_reloaded = true;
-                    }
-                }
-            }
-            if active_was_reloaded && self.search.is_open {
-                // Invalidate maybe_rebuild_search's "cached for" shadow
-                self.search.last_tab = None;
+    // Identify byte ranges of non-renderable markdown parts so we can skip matches
+    // inside them. Pattern: `(!?)[alt-or-text](url "title")`.
+    // - Group 3 = url and title; exclude the url, up to the title
+    let skip_spans: Vec<std::ops::Range<usize>> = {
+        static MD_LINK_RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+        let re = MD_LINK_RE.get_or_init(|| {
+            Regex::new(r"(!?)\[([^\]]*)\]\(([^)]*)\)").expect("static regex must compile")
+        });
+        let mut spans = Vec::new();
+        for cap in re.captures_iter(content) {
+            if let Some(url) = cap.get(3) {
+                let len = url.as_str().find(char::is_whitespace).unwrap_or(url.len());
+                spans.push(url.start()..url.start() + len);
             }
         }
-
-        // Refresh the file explorer tree if any changes were within the explorer root
-        if refresh_tree {
-            log::info!("Refreshing file explorer tree");
-            self.file_explorer.refresh();
+        // Code shows link syntax as it is, so matches in it are all kept
+        if !spans.is_empty() {
+            use pulldown_cmark::{Event, Parser, Tag};
+            let code: Vec<std::ops::Range<usize>> = Parser::new(content)
+                .into_offset_iter()
+                .filter(|(event, _)| {
+                    matches!(event, Event::Start(Tag::CodeBlock(_)) | Event::Code(_))
+                })
+                .map(|(_, range)| range)
+                .collect();
+            spans.retain(|span| {
+                !code
+                    .iter()
+                    .any(|code| code.start <= span.start && span.end <= code.end)
+            });
         }
-    }
+        spans
+    };
 
-    /// Outcome of rendering the find bar (consumed after panel renders, before global input handler runs)
-    fn render_search_bar(&mut self, ctx: &egui::Context) -> SearchBarOutcome {
-        let mut outcome = SearchBarOutcome::default();
-        if !self.search.is_open {
-            return outcome;
-        }
+    let in_skip = |start: usize, end: usize| -> bool {
+        skip_spans.iter().any(|s| start >= s.start && end <= s.end)
+    };
 
-        let input_id = egui::Id::new("search_input");
-        let total_matches = self
-            .tabs
-            .get(self.active_tab)
-            .map(|t| t.search_matches.len())
-            .unwrap_or(0);
-        let active_idx = self.search.active_match_index;
+    let mut matches = Vec::new();
+    let mut line_number = 1usize;
+    let mut cursor = 0usize;
 
-        egui::TopBottomPanel::top("search_bar").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                ui.label("🔍");
+    for (byte_start, _) in content_lc.match_indices(&query_lc) {
+        // Count newlines between cursor and this match's start
+        line_number += content[cursor..byte_start]
+            .bytes()
+            .filter(|&b| b == b'\n')
+            .count();
+        cursor = byte_start;
 
-                let text_edit = egui::TextEdit::singleline(&mut self.search.query)
-                    .id(input_id)
-                    .hint_text("Find in document")
-                    .desired_width(280.0);
-                let response = ui.add(text_edit);
+        let byte_end = byte_start + query_len;
+        if content[byte_start..byte_end].contains('\n') {
+            continue; // Skip matches that cross line boundaries
+        }
+        if in_skip(byte_start, byte_end) {
+            continue; // Skip matches inside the URLs of links and images
+        }
 
-                #[cfg(feature = "mcp")]
-                self.mcp_bridge
-                    .register_widget("Search: Input", "textbox", &response, None);
+        matches.push(SearchMatch {
+            byte_start,
+            byte_end,
+            line_number,
+        });
+    }
 
-                if self.search.focus_requested {
-                    response.request_focus();
-                    self.search.focus_requested = false;
-                }
+    matches
+}
 
-                ui.add_space(8.0);
+/// Longest snippet shown per match in a search report.
+const REPORT_SNIPPET_CHARS: usize = 160;
+
+/// The `matches` of `query` in the document at `path` as a markdown report,
+/// one table row per match with its line and the source line it is on.
+fn search_report(query: &str, path: &Path, content: &str, matches: &[SearchMatch]) -> String {
+    let mut report = format!(
+        "# Search results for \"{query}\"\n\n{} {} in `{}`\n\n",
+        matches.len(),
+        if matches.len() == 1 {
+            "match"
+        } else {
+            "matches"
+        },
+        path.display()
+    );
+    if matches.is_empty() {
+        return report;
+    }
+    report.push_str("| File | Line | Snippet |\n| --- | --- | --- |\n");
+    let file = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    for m in matches {
+        let start = content[..m.byte_start].rfind('\n').map_or(0, |i| i + 1);
+        let end = content[m.byte_end..]
+            .find('\n')
+            .map_or(content.len(), |i| m.byte_end + i);
+        let line = content[start..end].trim();
+        let mut snippet: String = line.chars().take(REPORT_SNIPPET_CHARS).collect();
+        if snippet.len() < line.len() {
+            snippet.push('…');
+        }
+        let snippet = snippet.replace('|', "\\|");
+        report.push_str(&format!("| {file} | {} | {snippet} |\n", m.line_number));
+    }
+    report
+}
 
-                let label_text = if self.search.query.is_empty() {
-                    String::new()
-                } else if total_matches == 0 {
-                    "0 matches".to_string()
-                } else {
-                    format!("{} / {}", active_idx + 1, total_matches)
-                };
-                let label_response = ui.label(
-                    egui::RichText::new(&label_text)
-                        .color(ui.style().visuals.weak_text_color())
-                        .small(),
-                );
+/// Check if header at `index` should be hidden because an ancestor is collapsed
+fn header_is_hidden(headers: &[Header], index: usize, collapsed: &HashSet<usize>) -> bool {
+    if index == 0 || index >= headers.len() {
+        return false;
+    }
+    let mut search_level = headers[index].level;
+    // Walk backwards to find ancestors
+    for i in (0..index).rev() {
+        let h = &headers[i];
+        // Only consider headers with lower level than what we're searching for
+        if h.level < search_level {
+            // Found an ancestor
+            if collapsed.contains(&i) {
+                return true;
+            }
+            // This ancestor is not collapsed, but check its ancestors too
+            // Update search_level to only look for even lower level headers
+            search_level = h.level;
+        }
+        // Headers at same or higher level are siblings/cousins, skip them
+    }
+    false
+}
 
-                #[cfg(feature = "mcp")]
-                self.mcp_bridge.register_widget(
-                    "Search: Match Count",
-                    "label",
-                    &label_response,
-                    Some(&label_text),
-                );
-                #[cfg(not(feature = "mcp"))]
-                let _ = label_response;
+/// Check if a header has any children (headers with higher level immediately following)
+fn header_has_children(headers: &[Header], index: usize) -> bool {
+    if index >= headers.len() {
+        return false;
+    }
+    let current_level = headers[index].level;
+    // Look at the next header
+    if let Some(next) = headers.get(index + 1) {
+        // A child has a higher level number (e.g., h3 is child of h2)
+        next.level > current_level
+    } else {
+        false
+    }
+}
 
-                ui.add_space(4.0);
-                let prev_btn = ui
-                    .add_enabled(total_matches > 0, egui::Button::new("↑").small())
-                    .on_hover_text("Previous match (Shift+Enter / ↑)");
-                #[cfg(feature = "mcp")]
-                self.mcp_bridge
-                    .register_widget("Search: Previous", "button", &prev_btn, None);
-                if prev_btn.clicked() {
-                    outcome.prev_clicked = true;
-                }
+/// Check if any header in the list has children
+fn any_header_has_children(headers: &[Header]) -> bool {
+    for i in 0..headers.len() {
+        if header_has_children(headers, i) {
+            return true;
+        }
+    }
+    false
+}
 
-                let next_btn = ui
-                    .add_enabled(total_matches > 0, egui::Button::new("↓").small())
-                    .on_hover_text("Next match (Enter / ↓)");
-                #[cfg(feature = "mcp")]
-                self.mcp_bridge
-                    .register_widget("Search: Next", "button", &next_btn, None);
-                if next_btn.clicked() {
-                    outcome.next_clicked = true;
-                }
+/// Installed fonts, found through fontconfig on Linux and the system font
+/// directories on Windows and macOS, plus the faces compiled in with the
+/// `bundled-fonts` feature as a last resort.
+fn system_font_database() -> fontdb::Database {
+    let mut db = fontdb::Database::new();
+    db.load_system_fonts();
+    #[cfg(feature = "bundled-fonts")]
+    for data in typst_assets::fonts() {
+        db.load_font_source(fontdb::Source::Binary(Arc::new(data)));
+    }
+    log::debug!("Found {} system font faces", db.len());
+    db
+}
 
-                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    let close_btn = ui.small_button("✕").on_hover_text("Close (Esc)");
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge
-                        .register_widget("Search: Close", "button", &close_btn, None);
-                    if close_btn.clicked() {
-                        outcome.close_requested = true;
-                    }
-                });
-            });
-        });
+/// Whether face `id` draws `c` with outlines egui can render.
+fn face_has_glyph(db: &fontdb::Database, id: fontdb::ID, c: char) -> bool {
+    db.with_face_data(id, |data, index| {
+        ttf_parser::Face::parse(data, index).is_ok_and(|face| {
+            let tables = face.tables();
+            let outlines = tables.glyf.is_some() || tables.cff.is_some() || tables.cff2.is_some();
+            outlines && face.glyph_index(c).is_some()
+        })
+    })
+    .unwrap_or(false)
+}
 
-        outcome
-    }
+/// The first face of `families` with the given weight and a glyph for `c`.
+fn find_face(
+    db: &fontdb::Database,
+    families: &[&str],
+    weight: fontdb::Weight,
+    c: char,
+) -> Option<fontdb::ID> {
+    families.iter().find_map(|family| {
+        let id = db.query(&fontdb::Query {
+            families: &[fontdb::Family::Name(family)],
+            weight,
+            ..fontdb::Query::default()
+        })?;
+        let exact = db.face(id).is_some_and(|face| face.weight == weight);
+        (exact && face_has_glyph(db, id, c)).then_some(id)
+    })
+}
 
-    /// Move the active match index forward (dir > 0) or backward (dir < 0), wrapping
-    /// around, and request a scroll-to-line via `pending_scroll_offset`.
-    fn jump_match(&mut self, dir: i32) {
-        let n = self
-            .tabs
-            .get(self.active_tab)
-            .map(|t| t.search_matches.len())
-            .unwrap_or(0);
-        if n == 0 {
-            return;
+/// Copy face `id` out of `db` for egui.
+fn load_face(db: &fontdb::Database, id: fontdb::ID) -> Option<egui::FontData> {
+    db.with_face_data(id, |data, index| egui::FontData {
+        index,
+        ..egui::FontData::from_owned(data.to_vec())
+    })
+}
+
+/// Register a named bold font family for Markdown strong text.
+fn setup_strong_font_family(fonts: &mut egui::FontDefinitions, db: &fontdb::Database) {
+    let mut strong_family = Vec::new();
+
+    // Try to load a true bold face before appending proportional fallbacks.
+    if let Some(id) = find_face(db, &[STRONG_FONT_SOURCE], fontdb::Weight::BOLD, 'A') {
+        if let Some(font_data) = load_face(db, id) {
+            log::info!("Loaded Markdown strong font: {} Bold", STRONG_FONT_SOURCE);
+            fonts
+                .font_data
+                .insert(STRONG_FONT_FAMILY.to_string(), font_data.into());
+            strong_family.push(STRONG_FONT_FAMILY.to_string());
         }
-        let new_idx = if dir >= 0 {
-            (self.search.active_match_index + 1) % n
-        } else if self.search.active_match_index == 0 {
-            n - 1
-        } else {
-            self.search.active_match_index - 1
-        };
-        self.search.active_match_index = new_idx;
-        self.scroll_to_active_match();
     }
 
-    /// Request a scroll-to-line for the current `active_match_index`. No-op when
-    /// there's no active tab, no matches, or content height hasn't been measured yet.
-    /// Used both by `jump_match` (cycling) and by `maybe_rebuild_search` (so typing a
-    /// fresh query or switching tabs lands the view on the first match instead of
-    /// leaving the view at wherever the user previously scrolled).
-    fn scroll_to_active_match(&mut self) {
-        let idx = self.search.active_match_index;
-        let tab = match self.tabs.get_mut(self.active_tab) {
-            Some(t) => t,
-            None => return,
-        };
-        let Some(m) = tab.search_matches.get(idx) else {
-            return;
-        };
-        if tab.last_content_height <= 0.0 || tab.content_lines == 0 {
-            return;
-        }
-        // Line-ratio estimate gets the view roughly in the right area. The renderer
-        // records the actual y of the active match during paint; render_tab_content
-        // schedules a corrective scroll next frame if this estimate was off.
-        let estimated_y =
-            (m.line_number as f32 / tab.content_lines as f32) * tab.last_content_height;
-        let margin = if tab.last_viewport_height > 0.0 {
-            tab.last_viewport_height * 0.35
-        } else {
-            100.0
-        };
-        tab.pending_scroll_offset = Some((estimated_y - margin).max(0.0));
-        // Grant the post-render corrective block one frame of permission to
-        // snap to the renderer-recorded `active_search_y`. The block clears
-        // the flag after it runs, so subsequent frames (e.g. user wheeling
-        // away from the match) won't re-trigger snap-back. See Tab field doc.
-        tab.correct_active_search_pending = true;
+    // Keep normal proportional fallbacks so bold text can still render broad Unicode.
+    if let Some(proportional) = fonts.families.get(&egui::FontFamily::Proportional) {
+        strong_family.extend(
+            proportional
+                .iter()
+                .filter(|font_name| font_name.as_str() != STRONG_FONT_FAMILY)
+                .cloned(),
+        );
     }
 
-    /// Rebuild the active tab's search matches if the query or active tab has changed.
-    /// Cheap (no-op) when nothing changed.
-    fn maybe_rebuild_search(&mut self) {
-        if !self.search.is_open {
-            return;
-        }
-        let tab_idx = self.active_tab;
-        let needs_rebuild =
-            self.search.query != self.search.last_query || self.search.last_tab != Some(tab_idx);
-        if !needs_rebuild {
-            return;
+    if !matches!(
+        strong_family.first(),
+        Some(font_name) if font_name.as_str() == STRONG_FONT_FAMILY
+    ) {
+        log::warn!(
+            "No Markdown strong font found. Install Noto Sans Bold for true bold rendering."
+        );
+    }
+
+    // The renderer selects this named family for strong spans; register it even
+    // without a bold face so documents do not panic on systems missing Noto Sans Bold.
+    fonts.families.insert(
+        egui::FontFamily::Name(STRONG_FONT_FAMILY.into()),
+        strong_family,
+    );
+}
+
+/// Setup custom fonts with system font fallbacks for Unicode support.
+/// Picks a face per script from the installed fonts for extended character coverage.
+fn setup_fonts(ctx: &egui::Context) {
+    let mut fonts = egui::FontDefinitions::default();
+    let db = system_font_database();
+    let mut loaded: Vec<fontdb::ID> = Vec::new();
+
+    for fallback in FONT_FALLBACKS {
+        let sample = fallback.sample;
+        if loaded.iter().any(|id| face_has_glyph(&db, *id, sample)) {
+            log::debug!(
+                "Font fallback {} is covered by an earlier one",
+                fallback.name
+            );
+            continue;
         }
-        let q = self.search.query.clone();
-        if let Some(tab) = self.tabs.get_mut(tab_idx) {
-            tab.rebuild_search(&q);
+        let Some(id) = find_face(&db, fallback.families, fontdb::Weight::NORMAL, sample) else {
+            log::debug!("No installed font for fallback {}", fallback.name);
+            continue;
+        };
+        let Some(font_data) = load_face(&db, id) else {
+            continue;
+        };
+        let family_name = db
+            .face(id)
+            .and_then(|face| face.families.first())
+            .map_or("", |(family, _)| family.as_str());
+        log::info!(
+            "Loaded font fallback: {} from {}",
+            fallback.name,
+            family_name
+        );
+
+        let font_name = fallback.name.to_string();
+        fonts.font_data.insert(font_name.clone(), font_data.into());
+
+        // Noto Sans is the primary body face so regular text shares the
+        // Noto Sans family — and its baseline/ascent metrics — with the
+        // Noto Sans Bold used for `**strong**` (issue #39). Otherwise
+        // regular text stays on egui's bundled Ubuntu-Light and bold spans
+        // sit on a slightly different baseline. Other scripts (CJK, Arabic,
+        // …) remain appended fallbacks.
+        if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Proportional) {
+            if fallback.name == "NotoSans" {
+                family.insert(0, font_name.clone());
+            } else {
+                family.push(font_name.clone());
+            }
         }
-        self.search.last_query = q;
-        self.search.last_tab = Some(tab_idx);
-        // Always start at the first match after any rebuild — query change, tab
-        // change, or watcher reload. Match indices are not semantically comparable
-        // across rebuilds; preserving a numeric index across docs is coincidental
-        // (matches Firefox / Chrome / VS Code behavior).
-        self.search.active_match_index = 0;
-        // Scroll to the new first match so the user sees the active highlight
-        // immediately rather than wherever they were previously scrolled. Without
-        // this, the visible match (somewhere else on screen) gets the regular
-        // highlight color, leaving the user with the impression that the active
-        // highlight is "stuck on the previous result".
-        self.scroll_to_active_match();
-    }
 
-    /// Close the find bar and clear highlight state on every tab.
-    fn close_search(&mut self) {
-        self.search.is_open = false;
-        self.search.query.clear();
-        self.search.last_query.clear();
-        self.search.last_tab = None;
-        self.search.focus_requested = false;
-        self.search.active_match_index = 0;
-        for tab in self.tabs.iter_mut() {
-            tab.search_matches.clear();
-            tab.cache.clear_search_ranges();
+        // Also add text fonts to monospace for code blocks with Unicode
+        if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Monospace) {
+            family.push(font_name);
         }
+
+        loaded.push(id);
     }
 
-    /// Render the custom tab bar
-    fn render_tab_bar(&mut self, ui: &mut egui::Ui) -> Option<usize> {
-        let mut tab_to_close: Option<usize> = None;
-        let mut new_active: Option<usize> = None;
-        let mut close_others: Option<usize> = None;
+    if loaded.is_empty() {
+        log::warn!("No system fonts loaded. Unicode characters may show as red triangles.");
+        log::warn!("Install noto-fonts and noto-fonts-cjk for full Unicode support.");
+    } else {
+        log::info!("Loaded {} font fallbacks for Unicode support", loaded.len());
+    }
 
-        // Collect tab info first to avoid borrow issues
-        let tab_info: Vec<(String, bool)> = self
-            .tabs
-            .iter()
-            .enumerate()
-            .map(|(idx, tab)| (tab.title(), idx == self.active_tab))
-            .collect();
+    setup_strong_font_family(&mut fonts, &db);
 
-        let tab_count = tab_info.len();
-        let hovered_tab = self.hovered_tab;
+    ctx.set_fonts(fonts);
+}
 
-        // Collect widget data for MCP registration (name, widget_type, rect, value)
-        #[cfg(feature = "mcp")]
-        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-        ui.horizontal(|ui| {
-            // Scrollable tab area
-            egui::ScrollArea::horizontal()
-                .max_width(ui.available_width() - 30.0)
-                .show(ui, |ui| {
-                    ui.horizontal(|ui| {
-                        for (idx, (title, is_active)) in tab_info.iter().enumerate() {
-                            let is_hovered = hovered_tab == Some(idx);
+#[derive(Parser, Debug)]
+#[command(name = "md-viewer")]
+#[command(about = "A lightweight markdown viewer", long_about = None)]
+struct Args {
+    /// Markdown file to open, or an HTML page or http(s) URL to read
+    file: Option<PathBuf>,
 
-                            // Tab frame
-                            let tab_response = ui.horizontal(|ui| {
-                                // Tab button
-                                let text = egui::RichText::new(title);
-                                let text = if *is_active { text.strong() } else { text };
+    /// Disable live reload (watching is enabled by default)
+    #[arg(long)]
+    no_watch: bool,
 
-                                let response = ui.selectable_label(*is_active, text);
+    /// Keep the GUI process attached to the terminal for debugging/logs
+    #[arg(long)]
+    foreground: bool,
 
-                                // Collect tab widget data for MCP
-                                #[cfg(feature = "mcp")]
-                                widget_data.push((
-                                    format!("Tab: {}", title),
-                                    "tab",
-                                    response.rect,
-                                    Some(if *is_active {
-                                        "active".to_string()
-                                    } else {
-                                        "".to_string()
-                                    }),
-                                ));
+    /// Internal marker used by the detached child process to avoid respawn loops
+    #[arg(long, hide = true)]
+    no_detach: bool,
 
-                                if response.clicked() {
-                                    new_active = Some(idx);
-                                }
+    /// Load a WASM plugin, besides those in ~/.config/md-viewer/plugins (repeatable)
+    #[arg(long = "plugin", value_name = "FILE")]
+    plugins: Vec<PathBuf>,
 
-                                // Middle-click to close
-                                if response.middle_clicked() {
-                                    tab_to_close = Some(idx);
-                                }
+    /// Load a Rhai script, besides those in ~/.config/md-viewer/scripts (repeatable; needs the scripting feature)
+    #[arg(long = "script", value_name = "FILE")]
+    scripts: Vec<PathBuf>,
 
-                                // Close button (show on hover or active)
-                                if *is_active || is_hovered {
-                                    let close_btn = ui.small_button("×");
+    /// Send a command (open, tab, goto, reload, export) to the running viewer and print its answer
+    #[arg(long, value_name = "COMMAND")]
+    remote: Option<String>,
+}
 
-                                    // Collect close button widget data for MCP
-                                    #[cfg(feature = "mcp")]
-                                    widget_data.push((
-                                        format!("Close Tab: {}", title),
-                                        "button",
-                                        close_btn.rect,
-                                        None,
-                                    ));
+fn should_detach(args: &Args, launched_from_terminal: bool) -> bool {
+    launched_from_terminal && !args.foreground && !args.no_detach
+}
 
-                                    if close_btn.clicked() {
-                                        tab_to_close = Some(idx);
-                                    }
-                                }
+fn launched_from_terminal() -> bool {
+    std::io::stdin().is_terminal()
+        || std::io::stdout().is_terminal()
+        || std::io::stderr().is_terminal()
+}
 
-                                // Context menu
-                                response.context_menu(|ui| {
-                                    if ui.button("Close").clicked() {
-                                        tab_to_close = Some(idx);
-                                        ui.close();
-                                    }
-                                    if tab_count > 1 && ui.button("Close Others").clicked() {
-                                        close_others = Some(idx);
-                                        ui.close();
-                                    }
-                                });
+fn child_args_with_no_detach<I>(args: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let mut child_args: Vec<OsString> = args.into_iter().skip(1).collect();
+    let marker_position = child_args
+        .iter()
+        .position(|arg| arg == OsStr::new("--"))
+        .unwrap_or(child_args.len());
+    child_args.insert(marker_position, OsString::from("--no-detach"));
+    child_args
+}
 
-                                response
-                            });
+fn spawn_detached_child() -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let child_args = child_args_with_no_detach(std::env::args_os());
+    let mut command = Command::new(exe);
 
-                            // Track hover state
-                            if tab_response.response.hovered() {
-                                self.hovered_tab = Some(idx);
-                            }
+    command
+        .args(child_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
 
-                            ui.separator();
-                        }
-                    });
-                });
+    #[cfg(unix)]
+    unsafe {
+        use std::os::unix::process::CommandExt;
 
-            // Placeholder hint when no document is open (the welcome page is showing)
-            if tab_count == 0 {
-                ui.add_space(4.0);
-                ui.label(egui::RichText::new("No file open").weak());
-                ui.add_space(4.0);
+        // Start a new process group/session so the child survives after the
+        // launching terminal hands control back to the shell.
+        command.pre_exec(|| {
+            extern "C" {
+                fn setsid() -> i32;
             }
 
-            // New tab button
-            let new_tab_btn = ui.button("+").on_hover_text("New Tab (Ctrl+T)");
-
-            // Collect new tab button widget data for MCP
-            #[cfg(feature = "mcp")]
-            widget_data.push(("New Tab".to_string(), "button", new_tab_btn.rect, None));
-
-            if new_tab_btn.clicked() {
-                self.open_file_dialog();
+            if setsid() == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
             }
         });
+    }
 
-        // Register all collected widgets with MCP bridge
-        #[cfg(feature = "mcp")]
-        for (name, widget_type, rect, value) in widget_data {
-            self.mcp_bridge
-                .register_widget_rect(&name, widget_type, rect, value.as_deref());
-        }
+    command.spawn()?;
+    Ok(())
+}
 
-        // Apply new active tab
-        if let Some(idx) = new_active {
-            self.active_tab = idx;
-            self.title_dirty = true;
-        }
+fn main() -> eframe::Result<()> {
+    env_logger::init();
 
-        // Handle close others
-        if let Some(keep_idx) = close_others {
-            let kept = self.tabs.remove(keep_idx);
-            self.tabs.clear();
-            self.tabs.push(kept);
-            self.active_tab = 0;
-            self.title_dirty = true;
-            self.refresh_open_tab_paths();
-            if self.watch_enabled {
-                self.update_watched_paths();
+    let args = Args::parse();
+
+    if let Some(command) = &args.remote {
+        match remote::send(command) {
+            Ok(answer) => {
+                println!("{answer}");
+                std::process::exit(if answer.starts_with("ok") { 0 } else { 1 });
+            }
+            Err(e) => {
+                eprintln!("No running md-viewer to send to ({e})");
+                std::process::exit(1);
             }
-            return None; // Don't close any tab, we already handled it
         }
-
-        tab_to_close
     }
 
-    /// Render the outline sidebar (right panel)
-    /// Rendered at top level for proper layout space allocation
-    fn render_outline(&mut self, ctx: &egui::Context) {
-        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
-            return;
-        };
-
-        if !self.show_outline || tab.outline_headers.is_empty() {
-            return;
+    if should_detach(&args, launched_from_terminal()) {
+        if let Err(err) = spawn_detached_child() {
+            eprintln!("Failed to detach md-viewer process: {err}. Running in foreground.");
+        } else {
+            return Ok(());
         }
+    }
 
-        // Handle outline header click (store index to access both title and line_number)
-        let mut clicked_header_index: Option<usize> = None;
-
-        // Collect widget data for MCP registration (name, widget_type, rect, value)
-        #[cfg(feature = "mcp")]
-        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
+    // Calculate optimal window width assuming both sidebars are shown (the default)
+    let optimal_width =
+        CONTENT_OPTIMAL_WIDTH + EXPLORER_DEFAULT_WIDTH + OUTLINE_DEFAULT_WIDTH + PANEL_SEPARATORS;
 
-        let is_dragging = ctx.input(|i| i.pointer.any_down());
+    plugins::init(&args.plugins);
+    scripting::init(&args.scripts);
 
-        egui::SidePanel::right("outline")
-            .resizable(true)
-            .default_width(200.0)
-            .min_width(120.0)
-            .max_width(400.0)
-            .frame(
-                egui::Frame::side_top_panel(&ctx.style()).inner_margin(egui::Margin {
-                    left: 8,
-                    right: 0,
-                    top: 8,
-                    bottom: 0,
-                }),
-            )
-            .show(ctx, |ui| {
-                // Expand/Collapse All buttons (only if there are nested headers)
-                let has_nested = any_header_has_children(&tab.outline_headers);
-                if has_nested {
-                    ui.horizontal(|ui| {
-                        ui.add_space(6.0);
-                        let expand_btn = ui.small_button("Expand All");
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([optimal_width, OPTIMAL_WINDOW_HEIGHT])
+            .with_min_inner_size([400.0, 300.0])
+            .with_title("Markdown Viewer")
+            .with_drag_and_drop(true),
+        ..Default::default()
+    };
 
-                        // Collect Expand All button for MCP
-                        #[cfg(feature = "mcp")]
-                        widget_data.push((
-                            "Outline: Expand All".to_string(),
-                            "button",
-                            expand_btn.rect,
-                            None,
-                        ));
+    eframe::run_native(
+        APP_NAME,
+        options,
+        Box::new(move |cc| Ok(Box::new(MarkdownApp::new(cc, args.file, !args.no_watch)))),
+    )
+}
 
-                        if expand_btn.clicked() {
-                            tab.collapsed_headers.clear();
-                        }
+/// Source of a lightbox texture. Mermaid pre-rasterizes its own texture so we
+/// own the handle to keep it alive; loader-managed images (egui_extras) just
+/// give us an id whose lifetime is governed by the loader.
+enum LightboxTexture {
+    Owned(egui::TextureHandle),
+    Loaded(egui::TextureId),
+}
 
-                        let collapse_btn = ui.small_button("Collapse All");
+impl LightboxTexture {
+    fn id(&self) -> egui::TextureId {
+        match self {
+            Self::Owned(handle) => handle.id(),
+            Self::Loaded(id) => *id,
+        }
+    }
+}
 
-                        // Collect Collapse All button for MCP
-                        #[cfg(feature = "mcp")]
-                        widget_data.push((
-                            "Outline: Collapse All".to_string(),
-                            "button",
-                            collapse_btn.rect,
-                            None,
-                        ));
+struct LightboxState {
+    /// GPU-resident texture (mermaid-owned or loader-owned). Zoom scales it — instant.
+    texture: LightboxTexture,
+    /// Original rasterized pixel dimensions (before any zoom)
+    base_size: egui::Vec2,
+    zoom: f32,
+    /// Unique ID per open — prevents stale egui Area/ScrollArea state between opens
+    open_id: u64,
+    /// Tracks scroll offset for zoom-to-cursor (we maintain our own copy because
+    /// egui's ScrollArea may clamp the old offset when content size changes mid-zoom)
+    scroll_offset: egui::Vec2,
+    /// Set by a double-click; applied like the reset button on the next frame
+    pending_reset: bool,
+}
 
-                        if collapse_btn.clicked() {
-                            for i in 0..tab.outline_headers.len() {
-                                if header_has_children(&tab.outline_headers, i) {
-                                    tab.collapsed_headers.insert(i);
-                                }
-                            }
-                        }
-                    });
-                    ui.separator();
-                }
-                // Pre-compute the visible header indices (skip those hidden
-                // by collapsed ancestors). This lets us virtualize via
-                // show_rows, paying O(visible-on-screen) instead of
-                // O(total-headers) per frame. On a 100k-line doc with ~15k
-                // headers this is the difference between visibly laggy and
-                // smooth outline interactions.
-                let visible_indices: Vec<usize> = (0..tab.outline_headers.len())
-                    .filter(|&i| !header_is_hidden(&tab.outline_headers, i, &tab.collapsed_headers))
-                    .collect();
-                let show_fold_indicators = any_header_has_children(&tab.outline_headers);
-                // Row height: fold indicator is 20px tall, fold-indicator-less
-                // rows fall back to the standard interact_size which is
-                // typically 18–20px anyway. A small fudge keeps neighboring
-                // rows from clipping into each other.
-                let row_height = ui.spacing().interact_size.y.max(20.0);
+/// Zoom factor of one step of the lightbox's zoom buttons.
+const LIGHTBOX_ZOOM_STEP: f32 = 1.25;
 
-                egui::ScrollArea::vertical()
-                    .scroll_bar_visibility(egui::scroll_area::ScrollBarVisibility::AlwaysHidden)
-                    .id_salt("outline")
-                    .show_rows(ui, row_height, visible_indices.len(), |ui, row_range| {
-                        let mut toggle_index: Option<usize> = None;
-                        for &idx in &visible_indices[row_range] {
-                            let header = &tab.outline_headers[idx];
+/// Parent directories of `paths`, for watching files that get replaced.
+fn tab_dirs(paths: &[&PathBuf]) -> HashSet<PathBuf> {
+    paths
+        .iter()
+        .filter_map(|path| path.parent())
+        .map(Path::to_path_buf)
+        .collect()
+}
 
-                            let has_children = header_has_children(&tab.outline_headers, idx);
-                            let is_collapsed = tab.collapsed_headers.contains(&idx);
+/// Check if a path is on a GVFS FUSE mount (e.g., SFTP via Thunar/Nautilus).
+fn is_gvfs_path(path: &Path) -> bool {
+    path.starts_with("/run/user/") && path.components().any(|c| c.as_os_str() == "gvfs")
+}
 
-                            // Indent based on header level (h2 = 0, h3 = 1 indent, etc.)
-                            let indent = (header.level.saturating_sub(2) as usize) * 12;
+/// Filesystem types inotify can't see remote changes on: network shares,
+/// VM and container shares, and FUSE filesystems backed by a remote
+const POLLED_FS_TYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "virtiofs",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+    "fuse.grpcfuse",
+    "fuse.gvfsd-fuse",
+];
 
-                            ui.horizontal(|ui| {
-                                // Add base indent
-                                if indent > 0 {
-                                    ui.add_space(indent as f32);
-                                }
+/// Mount points in `/proc/self/mountinfo` format, each with whether it is a
+/// filesystem that needs polling.
+fn parse_mounts(mountinfo: &str) -> Vec<(PathBuf, bool)> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, fs) = line.split_once(" - ")?;
+            let point = mount.split(' ').nth(4)?.replace("\\040", " ");
+            let fs_type = fs.split(' ').next()?;
+            Some((PathBuf::from(point), POLLED_FS_TYPES.contains(&fs_type)))
+        })
+        .collect()
+}
 
-                                // Fold indicator (fixed width area for alignment)
-                                // Only allocate space if any header has children
-                                if show_fold_indicators {
-                                    let (rect, response) = ui.allocate_exact_size(
-                                        egui::vec2(20.0, 20.0),
-                                        egui::Sense::click(),
-                                    );
-                                    if has_children {
-                                        let indicator = if is_collapsed { "+" } else { "-" };
-                                        let text_color = if response.hovered() {
-                                            ui.visuals().strong_text_color()
-                                        } else {
-                                            ui.visuals().text_color()
-                                        };
-                                        ui.painter().text(
-                                            rect.center(),
-                                            egui::Align2::CENTER_CENTER,
-                                            indicator,
-                                            egui::FontId::monospace(16.0),
-                                            text_color,
-                                        );
+/// The current mount table; empty where there's no `/proc`.
+fn read_mounts() -> Vec<(PathBuf, bool)> {
+    fs::read_to_string("/proc/self/mountinfo")
+        .map(|info| parse_mounts(&info))
+        .unwrap_or_default()
+}
 
-                                        // Collect fold indicator for MCP
-                                        #[cfg(feature = "mcp")]
-                                        widget_data.push((
-                                            format!("Toggle: {}", header.title),
-                                            "button",
-                                            rect,
-                                            Some(if is_collapsed {
-                                                "collapsed".to_string()
-                                            } else {
-                                                "expanded".to_string()
-                                            }),
-                                        ));
+/// Whether `path` is in one of the `trusted` folders.
+fn is_trusted(path: &Path, trusted: &[PathBuf]) -> bool {
+    trusted.iter().any(|folder| path.starts_with(folder))
+}
 
-                                        if !is_dragging && response.clicked() {
-                                            toggle_index = Some(idx);
-                                        }
-                                    }
-                                }
+/// Whether `path` is on a network filesystem, going by its innermost mount.
+fn on_polled_mount(mounts: &[(PathBuf, bool)], path: &Path) -> bool {
+    mounts
+        .iter()
+        .filter(|(point, _)| path.starts_with(point))
+        .max_by_key(|(point, _)| point.components().count())
+        .is_some_and(|(_, polled)| *polled)
+}
 
-                                // Header title (pre-computed truncation)
-                                let response = ui.selectable_label(false, &header.display_title);
+/// Which paths get the poll watcher instead of inotify.
+struct PollPolicy {
+    // File → Watch Mode → Always Poll
+    always: bool,
+    interval_secs: u64,
+    mounts: Vec<(PathBuf, bool)>,
+    // inotify couldn't be started at all (e.g. some containers)
+    inotify_unavailable: bool,
+    // Local paths inotify refused to watch (e.g. out of watches)
+    fallback: HashSet<PathBuf>,
+}
 
-                                // Collect header for MCP
-                                #[cfg(feature = "mcp")]
-                                widget_data.push((
-                                    format!("Header: {}", header.title),
-                                    "header",
-                                    response.rect,
-                                    Some(format!("h{}", header.level)),
-                                ));
+impl PollPolicy {
+    fn polls(&self, path: &Path) -> bool {
+        self.always
+            || self.inotify_unavailable
+            || is_gvfs_path(path)
+            || self.fallback.contains(path)
+            || on_polled_mount(&self.mounts, path)
+    }
+
+    /// Why files are polled, for the status bar.
+    fn reason(&self) -> &'static str {
+        if self.always {
+            "Always Poll is on"
+        } else if self.inotify_unavailable {
+            "File notifications are unavailable"
+        } else if !self.fallback.is_empty() {
+            "Ran out of file notification watches"
+        } else {
+            "Files on a network or remote filesystem"
+        }
+    }
+}
 
-                                if !is_dragging && response.clicked() {
-                                    clicked_header_index = Some(idx);
-                                }
-                            });
-                        }
-                        // Apply toggle after iteration to avoid borrow issues
-                        if let Some(idx) = toggle_index {
-                            if tab.collapsed_headers.contains(&idx) {
-                                tab.collapsed_headers.remove(&idx);
-                            } else {
-                                tab.collapsed_headers.insert(idx);
-                            }
-                        }
-                    });
-            });
+/// How file changes turn into reloads, for files rewritten over and over,
+/// such as by a documentation generator (File → Watch Mode).
+struct ReloadPolicy {
+    /// How long writes to a file must pause before it counts as changed
+    debounce_ms: u64,
+    /// Least time between two reloads of a file, 0 for no limit
+    min_reload_ms: u64,
+    /// Hold changes back while the window isn't focused
+    focused_only: bool,
+}
 
-        // Register all collected widgets with MCP bridge
-        #[cfg(feature = "mcp")]
-        for (name, widget_type, rect, value) in widget_data {
-            self.mcp_bridge
-                .register_widget_rect(&name, widget_type, rect, value.as_deref());
-        }
+impl ReloadPolicy {
+    fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+}
 
-        // Calculate scroll target if header was clicked
-        if let Some(idx) = clicked_header_index {
-            if let Some(header) = tab.outline_headers.get(idx) {
-                // Composite key disambiguates duplicate-titled headers (e.g. two
-                // `## Installation` sections). Each occurrence has its own
-                // `nth_with_same_text` index assigned at parse time, and the
-                // renderer records positions under the same composite scheme.
-                let key = header_position_key(&header.normalized_title, header.nth_with_same_text);
-                // Try to get actual rendered position from cache first.
-                // With virtualization, the cache may hold a stale value from a
-                // partial render — record the key for the post-render
-                // corrective step which re-checks after the bootstrap full paint.
-                if let Some(y_pos) = tab.cache.get_header_position(&key) {
-                    tab.pending_scroll_offset = Some((y_pos - 50.0).max(0.0));
-                } else if tab.last_content_height > 0.0 && tab.content_lines > 0 {
-                    // Fallback: estimate position based on line number ratio
-                    let estimated_y = (header.line_number as f32 / tab.content_lines as f32)
-                        * tab.last_content_height;
-                    tab.pending_scroll_offset = Some((estimated_y - 50.0).max(0.0));
-                }
-                // Remember the click target — once the bootstrap full paint
-                // triggered by `pending_scroll_offset` populates the cache, the
-                // corrective step in `render_tab_content` snaps to the precise y.
-                tab.pending_header_click_key = Some(key);
+/// Changed files whose reload is held back by the [`ReloadPolicy`], and when
+/// each file was last reloaded.
+#[derive(Default)]
+struct ReloadBatch {
+    held: Vec<PathBuf>,
+    last_reload: HashMap<PathBuf, Instant>,
+}
+
+impl ReloadBatch {
+    /// Hold `changed` with the files held already, and take those due for
+    /// reloading at `now`: none while the window isn't `focused` and the
+    /// policy waits for it, else those not reloaded within its limit.
+    fn take_due(
+        &mut self,
+        changed: Vec<PathBuf>,
+        policy: &ReloadPolicy,
+        focused: bool,
+        now: Instant,
+    ) -> Vec<PathBuf> {
+        for path in changed {
+            if !self.held.contains(&path) {
+                self.held.push(path);
             }
         }
+        if policy.focused_only && !focused {
+            return Vec::new();
+        }
+        let limit = Duration::from_millis(policy.min_reload_ms);
+        let (due, held): (Vec<PathBuf>, Vec<PathBuf>) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|path| {
+                self.last_reload
+                    .get(path)
+                    .map_or(true, |last| now.duration_since(*last) >= limit)
+            });
+        self.held = held;
+        for path in &due {
+            self.last_reload.insert(path.clone(), now);
+        }
+        due
     }
 
-    /// Render the active tab's content
-    /// Render the welcome / idle page shown when no document is open (issue #28).
-    fn render_welcome(&mut self, ui: &mut egui::Ui) {
-        let mut do_open_file = false;
-        let mut do_open_folder = false;
-        let mut open_path: Option<PathBuf> = None;
-        let mut toggle_show_all = false;
-        let now = now_epoch_secs();
-
-        // Snapshot the entries to display so we don't borrow self while acting on clicks.
-        let shown = if self.welcome_show_all {
-            RECENT_FILES_CAP
-        } else {
-            RECENT_SHOWN
-        };
-        let recents: Vec<(RecentEntry, bool)> = self
-            .recent_files
+    /// How long until the first held file may reload, while the window is
+    /// focused.
+    fn next_due(&self, policy: &ReloadPolicy, now: Instant) -> Option<Duration> {
+        let limit = Duration::from_millis(policy.min_reload_ms);
+        self.held
             .iter()
-            .take(shown)
-            .map(|e| (e.clone(), e.path.exists()))
-            .collect();
-        let total_recent = self.recent_files.len();
-        let show_all = self.welcome_show_all;
+            .filter_map(|path| self.last_reload.get(path))
+            .map(|last| limit.saturating_sub(now.duration_since(*last)))
+            .min()
+    }
+}
 
-        egui::ScrollArea::vertical()
-            .auto_shrink([false, false])
-            .show(ui, |ui| {
-                ui.add_space(64.0);
-                ui.vertical_centered(|ui| {
-                    ui.label(egui::RichText::new("📄").size(56.0).weak());
-                    ui.add_space(10.0);
-                    ui.label(
-                        egui::RichText::new("Open a file or folder")
-                            .size(22.0)
-                            .strong(),
-                    );
-                    ui.add_space(2.0);
-                    ui.label(egui::RichText::new("No document is open.").weak());
-                });
-                ui.add_space(18.0);
-                // Center the button row manually: a horizontal inside
-                // vertical_centered expands to full width, defeating centering.
-                ui.horizontal(|ui| {
-                    let avail = ui.available_width();
-                    ui.add_space(((avail - 230.0) / 2.0).max(0.0));
-                    if ui
-                        .button(egui::RichText::new("📂  Open File").size(15.0))
-                        .clicked()
-                    {
-                        do_open_file = true;
-                    }
-                    ui.add_space(10.0);
-                    if ui
-                        .button(egui::RichText::new("📁  Open Folder").size(15.0))
-                        .clicked()
-                    {
-                        do_open_folder = true;
-                    }
-                });
+/// Device and inode of a file. Editors that save atomically (vim, most IDEs)
+/// write a temporary file and rename it over the original, so the inode
+/// changes and an inotify watch on the old one stops reporting changes.
+#[cfg(unix)]
+fn file_identity(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
 
-                if !recents.is_empty() {
-                    ui.add_space(32.0);
-                    // Center a fixed-width, left-aligned column for the recent list.
-                    let avail = ui.available_width();
-                    let col_w = 560.0_f32.min((avail - 16.0).max(200.0));
-                    let margin = ((avail - col_w) / 2.0).max(0.0);
-                    ui.horizontal(|ui| {
-                        ui.add_space(margin);
-                        ui.vertical(|ui| {
-                            ui.set_max_width(col_w);
-                            ui.label(egui::RichText::new("Recent").strong());
-                            ui.add_space(2.0);
-                            ui.separator();
-                            for (entry, exists) in &recents {
-                                let name = entry
-                                    .path
-                                    .file_name()
-                                    .map(|n| n.to_string_lossy().to_string())
-                                    .unwrap_or_else(|| entry.path.to_string_lossy().to_string());
-                                let dir = entry
-                                    .path
-                                    .parent()
-                                    .map(|p| p.to_string_lossy().to_string())
-                                    .unwrap_or_default();
-                                let when = format_relative_time(entry.last_opened, now);
-                                ui.horizontal(|ui| {
-                                    let label =
-                                        egui::RichText::new(format!("📄 {name}")).size(14.0);
-                                    let label = if *exists { label } else { label.weak() };
-                                    let resp = ui
-                                        .add_enabled(*exists, egui::Button::new(label).frame(false))
-                                        .on_hover_text(entry.path.to_string_lossy());
-                                    if resp.clicked() {
-                                        open_path = Some(entry.path.clone());
-                                    }
-                                    ui.label(
-                                        egui::RichText::new(format!("{dir}   ·   {when}"))
-                                            .weak()
-                                            .small(),
-                                    );
-                                });
-                            }
-                            if total_recent > RECENT_SHOWN {
-                                ui.add_space(6.0);
-                                let more = if show_all {
-                                    "Show less"
-                                } else {
-                                    "Show more…"
-                                };
-                                if ui.link(more).clicked() {
-                                    toggle_show_all = true;
-                                }
-                            }
-                        });
-                    });
-                }
-            });
+/// Other platforms watch files by path, so a replaced file keeps its watch.
+#[cfg(not(unix))]
+fn file_identity(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
 
-        // Deferred actions (run after the borrow on self.recent_files is released).
-        if do_open_file {
-            self.open_file_dialog();
-        }
-        if do_open_folder {
-            self.open_folder_dialog();
-        }
-        if let Some(p) = open_path {
-            self.open_in_new_tab(p);
-        }
-        if toggle_show_all {
-            self.welcome_show_all = !self.welcome_show_all;
+/// Wrapper for file watchers that supports both inotify (local) and poll (GVFS/remote).
+enum FileWatcher {
+    Inotify(Debouncer<RecommendedWatcher>),
+    Poll(Debouncer<PollWatcher>),
+    Dual {
+        inotify: Debouncer<RecommendedWatcher>,
+        poll: Debouncer<PollWatcher>,
+    },
+}
+
+impl FileWatcher {
+    fn inotify_watcher(&mut self) -> Option<&mut dyn notify::Watcher> {
+        match self {
+            FileWatcher::Inotify(d) | FileWatcher::Dual { inotify: d, .. } => Some(d.watcher()),
+            FileWatcher::Poll(_) => None,
         }
     }
 
-    fn render_tab_content(&mut self, ui: &mut egui::Ui, ctrl_held: bool) -> Option<PathBuf> {
-        let mut open_in_new_tab: Option<PathBuf> = None;
+    fn poll_watcher(&mut self) -> Option<&mut dyn notify::Watcher> {
+        match self {
+            FileWatcher::Poll(d) | FileWatcher::Dual { poll: d, .. } => Some(d.watcher()),
+            FileWatcher::Inotify(_) => None,
+        }
+    }
 
-        // Snapshot search state before taking a mutable borrow on the active tab
-        let search_is_open = self.search.is_open;
-        let active_idx = self.search.active_match_index;
+    fn is_polling(&self) -> bool {
+        !matches!(self, FileWatcher::Inotify(_))
+    }
+}
 
-        // No document open → render the welcome / idle page instead.
-        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
-            self.render_welcome(ui);
-            return None;
-        };
+struct MarkdownApp {
+    tabs: Vec<Tab>,
+    active_tab: usize,
+    // Resolved theme for this frame; derived from `theme_mode` in `update`
+    dark_mode: bool,
+    theme_mode: ThemeMode,
+    // Content zoom (Ctrl+/-); scales document text only
+    zoom_level: f32,
+    // Interface scale for menus, tabs and panels (egui zoom factor)
+    ui_scale: f32,
+    // Language picked in View → Language, `None` to follow the locale; the
+    // one in use for menus, and alerts named in it
+    language: Option<String>,
+    ui_language: String,
+    alerts: AlertBundle,
+    // Accessibility: disable smooth scrolling, flashes and collapse animations
+    reduce_motion: bool,
+    motion: MotionConfig,
+    // Wheel scrolling settles on a heading near the top of the viewport
+    snap_to_headings: bool,
+    show_outline: bool,
+    full_width_content: bool,
+    // Content column width in points when not in full-width mode
+    content_width: f32,
+    center_content: bool,
+    // Syntect theme names for code blocks, applied to every tab
+    syntax_theme_dark: String,
+    syntax_theme_light: String,
+    // Source line number gutter beside the rendered content
+    show_line_numbers: bool,
+    // Swatches beside color codes in inline code
+    show_color_swatches: bool,
+    // Copy buttons on hovered paragraphs and list items
+    copy_blocks: bool,
+    // Images dimmed or inverted in the dark theme
+    dark_images: DarkImages,
+    // Colored bars per quote level and deep quotes collapsed, for email threads
+    email_quotes: bool,
+    // Display equations numbered, with `\ref{}`s resolved to them
+    equation_numbers: bool,
+    // "Open in Editor" command template, with `{file}` and `{line}` filled in
+    editor_command: String,
+    // Git details of open files, filled in by background threads; `None`
+    // inside when the file isn't in a repository
+    git_info: HashMap<PathBuf, Arc<OnceLock<Option<GitFileInfo>>>>,
+    show_git_log: bool,
+    // Task panel: task list items under the explorer root, the root they were
+    // scanned from and the scan running in the background
+    show_tasks: bool,
+    // Problems panel below the document
+    show_problems: bool,
+    // Footnotes of the active document (right)
+    show_footnotes: bool,
+    // Figures and tables of the active document (right)
+    show_figures: bool,
+    show_link_graph: bool,
+    // Keyboard shortcut overlay (F1 or `?`)
+    show_shortcuts: bool,
+    /// What's typed into the command palette (Ctrl+Shift+P) while it's open
+    command_palette: Option<String>,
+    // Contents button in the corner of the document, and whether its
+    // heading list is expanded
+    show_floating_toc: bool,
+    floating_toc_open: bool,
+    // Dragging outline headings moves their sections in the file
+    outline_reordering: bool,
+    workspace_tasks: Vec<FileTasks>,
+    tasks_root: Option<PathBuf>,
+    task_scan: Option<background::Job<Vec<FileTasks>>>,
+    /// Documents under the explorer root and their links, for the link graph
+    link_graph: Option<graph::Graph>,
+    graph_root: Option<PathBuf>,
+    graph_scan: Option<background::Job<graph::Graph>>,
+    /// Web pages being downloaded to open in reader view
+    article_downloads: Vec<background::Job<Result<PathBuf, String>>>,
+    /// Static site being exported, finishing with its index page
+    site_export: Option<background::Job<Result<PathBuf, String>>>,
+    /// When New Tab from Clipboard asked for the clipboard's text
+    clipboard_tab_requested: Option<Instant>,
+    // Long-running work shown in the status bar
+    jobs: background::Jobs,
+    // Opt-in "Run" button below shell code blocks, and the runs it started
+    allow_running_code: bool,
+    // Command running Rust code blocks, with `{file}` filled in; empty turns it off
+    rust_runner: String,
+    // Documents outside the trusted folders are shown sandboxed
+    restrict_untrusted: bool,
+    trusted_folders: Vec<PathBuf>,
+    // Levels in the contents HTML exports start with, 0 for none
+    export_toc_depth: u8,
+    // Headings numbered 1, 1.1, … in the outline, the document and exports
+    number_headings: bool,
+    // View → Page Preview lays the document out on pages of `page_setup`
+    page_preview: bool,
+    page_setup: pages::PageSetup,
+    code_runs: Rc<RefCell<CodeRuns>>,
+    watch_enabled: bool,
+    error_message: Option<String>,
+    is_dragging: bool,
+    // File watcher state (inotify for local, poll for GVFS/remote)
+    watcher: Option<FileWatcher>,
+    watcher_rx: Option<Receiver<Result<Vec<notify_debouncer_mini::DebouncedEvent>, notify::Error>>>,
+    watcher_retry_count: u32,
+    poll_policy: PollPolicy,
+    reload_policy: ReloadPolicy,
+    reload_batch: ReloadBatch,
+    // Set of paths being watched (individual tab files)
+    watched_paths: HashSet<PathBuf>,
+    // Inode of each watched local tab file when its watch was added, to
+    // re-watch files replaced by an atomic save
+    watched_file_ids: HashMap<PathBuf, (u64, u64)>,
+    // Parent directories of local tab files, watched non-recursively so a
+    // file renamed into place is still seen
+    watched_tab_dirs: HashSet<PathBuf>,
+    // Explorer directories being watched non-recursively: the root plus each
+    // currently-expanded directory. Mirrors the lazy explorer tree so startup
+    // doesn't recursively walk the whole root subtree (issue: ~6s hang on a
+    // huge home directory).
+    watched_explorer_dirs: HashSet<PathBuf>,
+    // Tab being hovered for close button
+    hovered_tab: Option<usize>,
+    // File explorer state
+    file_explorer: FileExplorer,
+    show_explorer: bool,
+    // Flash effect for updated files (path -> start time)
+    flashing_paths: HashMap<PathBuf, Instant>,
+    // True if running on virtual display (e.g., Xvfb :99) - limits frame rate
+    is_virtual_display: bool,
+    // Stored context for waking egui from the watcher bridge thread
+    egui_ctx: egui::Context,
+    // Commands from the remote control socket; `None` when another viewer
+    // already listens on it
+    remote: Option<Receiver<remote::Request>>,
+    // Last retry of tabs whose file went missing
+    last_missing_retry: Instant,
+    // Track state to avoid unconditional repaints
+    last_applied_dark_mode: Option<bool>,
+    last_window_title: String,
+    title_dirty: bool,
+    /// Cached set of open tab paths for file explorer highlighting (avoids per-frame syscalls)
+    open_tab_paths: HashSet<PathBuf>,
+    // Lightbox overlay for enlarged mermaid diagrams
+    lightbox: Option<LightboxState>,
+    // Scroll delta captured from raw_input_hook for lightbox zoom (stripped from RawInput)
+    lightbox_scroll: f32,
+    // Counter for unique lightbox IDs (prevents stale egui state between opens)
+    lightbox_open_count: u64,
+    // Find-bar state (current-document search)
+    search: SearchState,
+    // Recently opened files (most-recent first), shown on the welcome page
+    recent_files: Vec<RecentEntry>,
+    // Explorer root folders used before (most-recent first)
+    recent_roots: Vec<PathBuf>,
+    // Where closed and open documents were last read (most-recent first)
+    reading_positions: Vec<ReadingPosition>,
+    // Session of a run that crashed, offered for restoring at startup
+    recovered_session: Option<PersistedState>,
+    // When the session was last snapshotted, and the snapshot written then
+    last_session_snapshot: Instant,
+    session_snapshot: String,
+    // Welcome page: whether the recent list is expanded ("Show more")
+    welcome_show_all: bool,
+    // MCP bridge for E2E testing
+    #[cfg(feature = "mcp")]
+    mcp_bridge: McpBridge,
+    // Automation textbox: the command being typed and the last outcome
+    #[cfg(feature = "mcp")]
+    automation_input: String,
+    #[cfg(feature = "mcp")]
+    automation_result: String,
+}
 
-        // Push current search match ranges into the cache so the renderer can paint highlights
-        if search_is_open && !tab.search_matches.is_empty() {
-            let ranges: Vec<_> = tab
-                .search_matches
-                .iter()
-                .map(|m| m.byte_start..m.byte_end)
-                .collect();
-            let active = tab
-                .search_matches
-                .get(active_idx)
-                .map(|m| m.byte_start..m.byte_end);
-            tab.cache.set_search_ranges(ranges);
-            tab.cache.set_active_search_range(active);
-        } else {
-            tab.cache.clear_search_ranges();
-        }
+impl MarkdownApp {
+    fn new(cc: &eframe::CreationContext<'_>, file: Option<PathBuf>, watch: bool) -> Self {
+        // Setup fonts with system font fallbacks for Unicode support
+        setup_fonts(&cc.egui_ctx);
 
-        // Content area (no inner CentralPanel needed - we're already in one)
-        // Left margin for breathing room, right margin prevents scrollbar/resize-handle overlap jitter
-        egui::Frame::NONE
-            .inner_margin(egui::Margin {
-                left: 8,
-                right: 3,
-                ..Default::default()
-            })
-            .show(ui, |ui| {
-                // Capture scroll input for manual handling during selection
-                let raw_scroll = ui.ctx().input(|i| i.raw_scroll_delta.y);
-                let content_rect = ui.available_rect_before_wrap();
+        // A URL is downloaded once the app is up, and opened from there
+        let url = file
+            .as_ref()
+            .map(|f| f.to_string_lossy().into_owned())
+            .filter(|f| reader::is_url(f));
+        let file = file.filter(|_| url.is_none());
 
-                // The renderer owns the ScrollArea now (via show_scrollable),
-                // so we configure scroll_source / pending offset / content
-                // version through builder methods. The returned ScrollAreaOutput
-                // exposes state.offset and inner_rect for the post-render
-                // selection-preserving wheel hack below.
-                let pending = tab.pending_scroll_offset.take();
-                let default_width = content_default_width(self.full_width_content);
-                let mut scroll_output = CommonMarkViewer::new()
-                    .default_implicit_uri_scheme(&tab.base_uri)
-                    .max_image_width(Some(800))
-                    .default_width(default_width)
-                    .indentation_spaces(2)
-                    .use_strong_font_family(true)
-                    .show_alt_text_on_hover(true)
-                    .syntax_theme_dark("base16-ocean.dark")
-                    .syntax_theme_light("base16-ocean.light")
-                    .line_height(1.5)
-                    .code_line_height(1.3)
-                    .paragraph_spacing(2.0)
-                    .heading_spacing_above(2.0)
-                    .heading_spacing_below(0.75)
-                    .content_version(tab.content_version)
-                    .pending_scroll_offset(pending)
-                    .scroll_source(egui::scroll_area::ScrollSource {
-                        scroll_bar: true,
-                        drag: false,
-                        mouse_wheel: true,
-                    })
-                    .show_scrollable(tab.id, ui, &mut tab.cache, &tab.content);
+        // Clear stale egui widget data loaded from disk (scroll offsets, panel sizes, etc.)
+        // We don't persist egui memory (see persist_egui_memory), but eframe always
+        // loads it if present. This purges the old blob so it doesn't waste startup time/RAM.
+        cc.egui_ctx.memory_mut(|mem| mem.data = Default::default());
 
-                tab.scroll_offset = scroll_output.state.offset.y;
-                tab.last_viewport_height = scroll_output.inner_rect.height();
-                tab.last_content_height = scroll_output.content_size.y;
+        egui_commonmark_extended::http_loader::set_disk_cache(
+            &cc.egui_ctx,
+            image_cache_dir().map(egui_commonmark_extended::http_loader::DiskCache::new),
+        );
 
-                // If the renderer recorded an exact y for the active match, check
-                // whether the current scroll position keeps it visible. If not,
-                // schedule a corrective scroll using the recorded y — this fixes
-                // line-ratio overshoot/undershoot in image-heavy documents.
-                // Corrective scroll for search-match jump. Gated by the
-                // one-shot `correct_active_search_pending` flag (set by
-                // `scroll_to_active_match`) so it fires at most once per
-                // jump — without this gate the block would re-trigger every
-                // frame the active match is off-screen, fighting the user's
-                // wheel input and locking the view (issue #19).
-                if tab.correct_active_search_pending {
-                    if let Some(actual_y) = tab.cache.active_search_y() {
-                        let current_scroll = scroll_output.state.offset.y;
-                        let viewport_top = current_scroll;
-                        let viewport_bottom = current_scroll + tab.last_viewport_height;
-                        // Consider "not visible" if outside the viewport with a small margin
-                        let margin_outside = 20.0_f32;
-                        let needs_correction = actual_y < viewport_top + margin_outside
-                            || actual_y > viewport_bottom - margin_outside;
-                        if needs_correction && tab.last_viewport_height > 0.0 {
-                            // Place the active match ~35% from the top of the viewport
-                            let inset = tab.last_viewport_height * 0.35;
-                            let want_scroll = (actual_y - inset).max(0.0);
-                            // Avoid stomping if we're already at the target (within a frame)
-                            if (want_scroll - current_scroll).abs() > 2.0 {
-                                tab.pending_scroll_offset = Some(want_scroll);
-                            }
-                        }
-                        // Clear the one-shot regardless of which branch ran;
-                        // the corrective block has now had its chance to snap.
-                        tab.correct_active_search_pending = false;
-                    }
-                }
+        // Disable egui's built-in Ctrl+/- zoom — we handle zoom ourselves
+        cc.egui_ctx
+            .options_mut(|opt| opt.zoom_with_keyboard = false);
 
-                // Corrective scroll for outline-click. The bootstrap full paint
-                // triggered above by `pending_scroll_offset` recorded every
-                // heading's precise y under its composite cache key. If the
-                // line-ratio first-frame estimate left the target outside the
-                // viewport (common in image-heavy docs and the failure mode for
-                // duplicate-titled headers before this), snap the next frame to
-                // the recorded y. Mirrors the search-match corrective above.
-                if let Some(key) = tab.pending_header_click_key.take() {
-                    if let Some(actual_y) = tab.cache.get_header_position(&key) {
-                        let current_scroll = scroll_output.state.offset.y;
-                        let want_scroll = (actual_y - 50.0).max(0.0);
-                        if (want_scroll - current_scroll).abs() > 2.0 {
-                            tab.pending_scroll_offset = Some(want_scroll);
-                        }
-                    }
-                }
+        // Set constant styles once at init (never changes at runtime). Both
+        // theme styles get them, since the theme toggle switches between them.
+        cc.egui_ctx.all_styles_mut(|style| {
+            style.url_in_tooltip = true;
+            use egui::{FontId, TextStyle};
+            style
+                .text_styles
+                .insert(TextStyle::Body, FontId::proportional(16.0));
+            style
+                .text_styles
+                .insert(TextStyle::Heading, FontId::proportional(32.0));
+            style
+                .text_styles
+                .insert(TextStyle::Small, FontId::proportional(13.0));
+            style
+                .text_styles
+                .insert(TextStyle::Monospace, FontId::monospace(14.0));
 
-                // Manual scroll handling for mouse wheel during text selection
-                let pointer_over_content = ui.ctx().input(|i| {
-                    i.pointer
-                        .hover_pos()
-                        .is_some_and(|pos| content_rect.contains(pos))
-                });
-                if raw_scroll.abs() > 0.0 && pointer_over_content {
-                    let current_offset = scroll_output.state.offset.y;
-                    let max_scroll = (tab.last_content_height - content_rect.height()).max(0.0);
-                    let new_offset = (current_offset - raw_scroll).clamp(0.0, max_scroll);
+            // Reduce resize grab radius to prevent overlap with adjacent scrollbars
+            style.interaction.resize_grab_radius_side = 2.0;
+        });
 
-                    // Don't store at boundaries (can break selection)
-                    let would_hit_top = new_offset < 0.5;
-                    let would_hit_bottom = new_offset > max_scroll - 0.5;
-                    let offset_changed = (new_offset - current_offset).abs() > 0.5;
+        // Load persisted state
+        let persisted: PersistedState = cc
+            .storage
+            .and_then(|s| eframe::get_value(s, APP_KEY))
+            .unwrap_or_default();
 
-                    if offset_changed && !would_hit_top && !would_hit_bottom {
-                        scroll_output.state.offset.y = new_offset;
-                        scroll_output.state.store(ui.ctx(), scroll_output.id);
-                        ui.ctx().request_repaint();
-                    }
-                }
+        let theme_mode = persisted
+            .theme_mode
+            .unwrap_or_else(|| ThemeMode::from_legacy_dark_mode(persisted.dark_mode));
+        let dark_mode = theme_mode.resolve_dark(
+            cc.egui_ctx.system_theme(),
+            cc.egui_ctx.style().visuals.dark_mode,
+        );
+        let zoom_level = persisted.zoom_level.unwrap_or(1.0).clamp(0.5, 3.0);
+        let ui_scale = persisted
+            .ui_scale
+            .unwrap_or(1.0)
+            .clamp(*UI_SCALE_RANGE.start(), *UI_SCALE_RANGE.end());
+        let reduce_motion = persisted.reduce_motion.unwrap_or(false);
+        let motion = MotionConfig::new(reduce_motion);
+        cc.egui_ctx.all_styles_mut(|style| motion.apply(style));
+        let show_outline = persisted.show_outline.unwrap_or(true);
+        let show_tasks = persisted.show_tasks.unwrap_or(false);
+        let show_problems = persisted.show_problems.unwrap_or(false);
+        let show_footnotes = persisted.show_footnotes.unwrap_or(false);
+        let show_figures = persisted.show_figures.unwrap_or(false);
+        let show_link_graph = persisted.show_link_graph.unwrap_or(false);
+        let show_floating_toc = persisted.show_floating_toc.unwrap_or(false);
+        let full_width_content = persisted.full_width_content.unwrap_or(false);
+        let content_width = persisted
+            .content_width
+            .unwrap_or(CONTENT_OPTIMAL_WIDTH)
+            .clamp(*CONTENT_WIDTH_RANGE.start(), *CONTENT_WIDTH_RANGE.end());
+        let center_content = persisted.center_content.unwrap_or(false);
+        let show_line_numbers = persisted.show_line_numbers.unwrap_or(false);
+        let show_color_swatches = persisted.show_color_swatches.unwrap_or(true);
+        let copy_blocks = persisted.copy_blocks.unwrap_or(false);
+        let dark_images = persisted.dark_images.unwrap_or(DarkImages::Original);
+        let email_quotes = persisted.email_quotes.unwrap_or(false);
+        let equation_numbers = persisted.equation_numbers.unwrap_or(false);
+        let syntax_theme_dark = persisted
+            .syntax_theme_dark
+            .unwrap_or_else(|| DEFAULT_SYNTAX_THEME_DARK.to_string());
+        let syntax_theme_light = persisted
+            .syntax_theme_light
+            .unwrap_or_else(|| DEFAULT_SYNTAX_THEME_LIGHT.to_string());
+        let show_explorer = persisted.show_explorer.unwrap_or(true);
+        let editor_command = persisted.editor_command.unwrap_or_else(|| {
+            default_editor_command(
+                std::env::var("VISUAL").ok().as_deref(),
+                std::env::var("EDITOR").ok().as_deref(),
+                std::env::var("TERMINAL").ok().as_deref(),
+            )
+        });
 
-                // Request repaint during smooth scrolling
-                if ui.ctx().input(|i| i.smooth_scroll_delta.length_sq() > 0.0) {
-                    ui.ctx().request_repaint();
-                }
-            });
+        // Determine initial tabs
+        let reading_positions = persisted.reading_positions.unwrap_or_default();
+        let tabs: Vec<Tab> = if let Some(ref path) = file {
+            // CLI argument takes priority
+            let mut tab = Tab::new(path.clone());
+            tab.pending_line = saved_reading_line(&reading_positions, &tab.path);
+            vec![tab]
+        } else if let Some(paths) = persisted.open_tabs {
+            // Restore previous session tabs along with their history
+            let histories = persisted.tab_histories.unwrap_or_default();
+            session_tabs(paths, histories, &reading_positions)
+        } else {
+            // No file and no saved session → start empty (welcome page).
+            Vec::new()
+        };
 
-        // Check for clicked links
-        if let Some(clicked_link) = tab.check_link_hooks() {
-            if ctrl_held {
-                // Open in new tab
-                if let Some(target_path) = tab.resolve_link(&clicked_link) {
-                    open_in_new_tab = Some(target_path);
-                }
-            } else {
-                // Navigate in current tab
-                tab.navigate_to_link(&clicked_link);
-            }
-        }
+        let recovered_session = session_snapshot_dir()
+            .and_then(|dir| claim_crashed_session(&dir, std::process::id(), process_is_running));
 
-        open_in_new_tab
-    }
+        let active_tab = persisted
+            .active_tab
+            .unwrap_or(0)
+            .min(tabs.len().saturating_sub(1));
 
-    /// Render the file explorer sidebar
-    /// Returns actions for files to open or close
-    fn render_file_explorer(&mut self, ctx: &egui::Context) -> ExplorerAction {
-        let mut action = ExplorerAction::default();
+        // Initialize file explorer
+        let mut file_explorer = FileExplorer::default();
 
-        if !self.show_explorer {
-            return action;
+        // Restore sort order before scanning (so initial scan uses correct order)
+        if let Some(sort_order) = persisted.explorer_sort_order {
+            file_explorer.sort_order = sort_order;
         }
+        file_explorer.skip_symlinks = persisted.explorer_skip_symlinks.unwrap_or(false);
+        file_explorer.preview_readmes = persisted.explorer_preview_readmes.unwrap_or(true);
+        file_explorer.root_relative_links = persisted.explorer_root_relative_links.unwrap_or(false);
 
-        egui::SidePanel::left("file_explorer")
-            .resizable(true)
-            .default_width(200.0)
-            .min_width(150.0)
-            .max_width(300.0)
-            .frame(
-                egui::Frame::side_top_panel(&ctx.style()).inner_margin(egui::Margin {
-                    left: 8,
-                    right: 8,
-                    top: 8,
-                    bottom: 8,
-                }),
-            )
-            .show(ctx, |ui| {
-                // Header with folder name - OUTSIDE ScrollArea
-                if let Some(root) = &self.file_explorer.root {
-                    let folder_name = root
-                        .file_name()
-                        .map(|n| n.to_string_lossy().to_string())
-                        .unwrap_or_else(|| root.display().to_string());
-                    ui.strong(&folder_name);
-                } else {
-                    ui.strong("No folder");
-                }
-
-                // Expand/collapse/refresh buttons - OUTSIDE ScrollArea
-                ui.horizontal(|ui| {
-                    let expand_btn = ui.small_button("⊞").on_hover_text("Expand all directories");
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Explorer: Expand All",
-                        "button",
-                        &expand_btn,
-                        None,
-                    );
-                    if expand_btn.clicked() {
-                        self.file_explorer.expand_all();
-                        self.reconcile_explorer_watches();
-                    }
+        // Determine explorer root:
+        // 1. From CLI file path
+        // 2. From persisted state
+        // 3. From first open tab
+        // 4. Current working directory as fallback
+        let explorer_root = file
+            .as_ref()
+            .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+            .or(persisted.explorer_root.filter(|p| p.exists()))
+            .or_else(|| {
+                tabs.first()
+                    .and_then(|t| t.path.parent().map(|p| p.to_path_buf()))
+            })
+            .or_else(|| std::env::current_dir().ok());
 
-                    let collapse_btn = ui
-                        .small_button("⊟")
-                        .on_hover_text("Collapse all directories");
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Explorer: Collapse All",
-                        "button",
-                        &collapse_btn,
-                        None,
-                    );
-                    if collapse_btn.clicked() {
-                        self.file_explorer.collapse_all();
-                        self.reconcile_explorer_watches();
-                    }
+        let mut recent_roots = persisted.recent_roots.unwrap_or_default();
+        if let Some(ref root) = explorer_root {
+            file_explorer.set_root(root.clone());
+        }
+        if let Some(root) = &file_explorer.root {
+            push_recent_root(&mut recent_roots, root);
+        }
 
-                    if ui.small_button("↻").on_hover_text("Refresh").clicked() {
-                        self.file_explorer.refresh();
-                    }
-                });
+        // Restore expanded directories (children will lazy-load on first render)
+        if let Some(expanded) = persisted.expanded_dirs {
+            file_explorer.expanded_dirs = expanded.into_iter().collect();
+        }
 
-                // Sort order dropdown
-                ui.horizontal(|ui| {
-                    ui.label("Sort:");
-                    #[allow(unused_variables)]
-                    let current_label = self.file_explorer.sort_order.label();
-                    let combo_response = egui::ComboBox::from_id_salt("explorer_sort")
-                        .selected_text(self.file_explorer.sort_order.label())
-                        .show_ui(ui, |ui| {
-                            for order in [
-                                SortOrder::NameAsc,
-                                SortOrder::NameDesc,
-                                SortOrder::DateAsc,
-                                SortOrder::DateDesc,
-                            ] {
-                                let is_selected = self.file_explorer.sort_order == order;
-                                if ui.selectable_label(is_selected, order.label()).clicked() {
-                                    self.file_explorer.set_sort_order(order);
-                                }
-                            }
-                        });
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Explorer: Sort Order",
-                        "combobox",
-                        &combo_response.response,
-                        Some(current_label),
-                    );
-                    #[cfg(not(feature = "mcp"))]
-                    let _ = combo_response;
-                });
+        #[cfg(feature = "mcp")]
+        let mcp_bridge = McpBridge::builder().port(9877).build();
+        #[cfg(feature = "mcp")]
+        log::info!("MCP bridge listening on port {}", mcp_bridge.port());
 
-                ui.separator();
+        // Detect virtual display (e.g., Xvfb :99) to limit frame rate
+        // Virtual displays lack vsync, causing unlimited FPS and high CPU
+        let is_virtual_display = std::env::var("DISPLAY")
+            .map(|d| d != ":0" && d != ":0.0" && !d.is_empty())
+            .unwrap_or(false);
 
-                // Pre-load children for all expanded dirs to avoid mutation during render
-                for dir in self
-                    .file_explorer
-                    .expanded_dirs
-                    .iter()
-                    .cloned()
-                    .collect::<Vec<_>>()
-                {
-                    if self.file_explorer.get_children(&dir).is_none() {
-                        self.file_explorer.load_children(&dir);
-                    }
-                }
+        let mut app = Self {
+            tabs,
+            active_tab,
+            dark_mode,
+            theme_mode,
+            zoom_level,
+            ui_scale,
+            language: None,
+            ui_language: String::new(),
+            alerts: AlertBundle::gfm(),
+            reduce_motion,
+            snap_to_headings: persisted.snap_to_headings.unwrap_or(false),
+            motion,
+            show_outline,
+            full_width_content,
+            content_width,
+            center_content,
+            syntax_theme_dark,
+            syntax_theme_light,
+            show_line_numbers,
+            show_color_swatches,
+            copy_blocks,
+            dark_images,
+            email_quotes,
+            equation_numbers,
+            editor_command,
+            allow_running_code: persisted.allow_running_code.unwrap_or(false),
+            rust_runner: persisted
+                .rust_runner
+                .unwrap_or_else(|| DEFAULT_RUST_RUNNER.to_string()),
+            restrict_untrusted: persisted.restrict_untrusted.unwrap_or(false),
+            trusted_folders: persisted.trusted_folders.unwrap_or_default(),
+            export_toc_depth: persisted.export_toc_depth.unwrap_or(0),
+            number_headings: persisted.number_headings.unwrap_or(false),
+            page_preview: persisted.page_preview.unwrap_or(false),
+            page_setup: pages::PageSetup {
+                size: persisted.page_size.unwrap_or(pages::PageSize::A4),
+                margins: persisted.page_margins.unwrap_or(pages::Margins::Normal),
+            },
+            code_runs: Rc::default(),
+            git_info: HashMap::new(),
+            show_git_log: false,
+            show_tasks,
+            show_problems,
+            show_footnotes,
+            show_figures,
+            show_link_graph,
+            show_shortcuts: false,
+            command_palette: None,
+            show_floating_toc,
+            floating_toc_open: false,
+            outline_reordering: persisted.outline_reordering.unwrap_or(false),
+            workspace_tasks: Vec::new(),
+            tasks_root: None,
+            task_scan: None,
+            link_graph: None,
+            graph_root: None,
+            graph_scan: None,
+            article_downloads: Vec::new(),
+            site_export: None,
+            clipboard_tab_requested: None,
+            jobs: background::Jobs::default(),
+            watch_enabled: watch,
+            error_message: None,
+            is_dragging: false,
+            watcher: None,
+            watcher_rx: None,
+            watcher_retry_count: 0,
+            poll_policy: PollPolicy {
+                always: persisted.watch_always_poll.unwrap_or(false),
+                interval_secs: persisted.watch_poll_interval.unwrap_or(2).clamp(1, 60),
+                mounts: Vec::new(),
+                inotify_unavailable: false,
+                fallback: HashSet::new(),
+            },
+            reload_policy: ReloadPolicy {
+                debounce_ms: persisted.watch_debounce_ms.unwrap_or(200).clamp(50, 5000),
+                min_reload_ms: persisted.watch_min_reload_ms.unwrap_or(0).min(60_000),
+                focused_only: persisted.watch_focused_only.unwrap_or(false),
+            },
+            reload_batch: ReloadBatch::default(),
+            watched_paths: HashSet::new(),
+            watched_file_ids: HashMap::new(),
+            watched_tab_dirs: HashSet::new(),
+            watched_explorer_dirs: HashSet::new(),
+            hovered_tab: None,
+            file_explorer,
+            show_explorer,
+            flashing_paths: HashMap::new(),
+            is_virtual_display,
+            egui_ctx: cc.egui_ctx.clone(),
+            remote: remote::listen(cc.egui_ctx.clone()),
+            last_missing_retry: Instant::now(),
+            last_applied_dark_mode: None,
+            last_window_title: String::new(),
+            title_dirty: true,
+            open_tab_paths: HashSet::new(),
+            lightbox: None,
+            lightbox_scroll: 0.0,
+            lightbox_open_count: 0,
+            search: SearchState::default(),
+            recent_files: persisted.recent_files.unwrap_or_default(),
+            reading_positions,
+            recent_roots,
+            recovered_session,
+            last_session_snapshot: Instant::now(),
+            session_snapshot: String::new(),
+            welcome_show_all: false,
+            #[cfg(feature = "mcp")]
+            mcp_bridge,
+            #[cfg(feature = "mcp")]
+            automation_input: String::new(),
+            #[cfg(feature = "mcp")]
+            automation_result: String::new(),
+        };
+        app.set_language(persisted.language.clone());
 
-                // Take tree to iterate without cloning, then put it back
-                let tree = std::mem::take(&mut self.file_explorer.tree);
-                // Clone the small set of open tab paths (avoids borrow conflict with &mut self)
-                let open_paths = self.open_tab_paths.clone();
+        app.refresh_open_tab_paths();
 
-                // File tree inside ScrollArea
-                egui::ScrollArea::vertical()
-                    .auto_shrink([false, false])
-                    .id_salt("file_explorer")
-                    .show(ui, |ui| {
-                        for node in &tree {
-                            let node_action = self.render_tree_node(ui, node, 0, &open_paths);
-                            if node_action.file_to_open.is_some() {
-                                action.file_to_open = node_action.file_to_open;
-                            }
-                            if node_action.file_to_close.is_some() {
-                                action.file_to_close = node_action.file_to_close;
-                            }
-                            if node_action.dir_to_toggle.is_some() {
-                                action.dir_to_toggle = node_action.dir_to_toggle;
-                            }
-                        }
-                    });
+        // Record a CLI-provided file as recent (an explicit open). Session-restored
+        // tabs are not treated as explicit opens, so they are not recorded here.
+        if let Some(f) = &file {
+            let f = f.canonicalize().unwrap_or_else(|_| f.clone());
+            app.record_recent(&f);
+        }
 
-                // Put tree back and apply deferred toggle
-                self.file_explorer.tree = tree;
-                if let Some(ref dir_path) = action.dir_to_toggle {
-                    self.file_explorer.toggle_expanded(dir_path);
-                    // Keep the non-recursive explorer watches in sync with the
-                    // newly expanded/collapsed directory.
-                    self.reconcile_explorer_watches();
-                }
-            });
+        if watch {
+            app.start_watching();
+        }
+        if let Some(url) = url {
+            app.download_article(url);
+        }
 
-        action
+        app
     }
 
-    /// Calculate flash intensity for a path (0.0 = no flash, 1.0 = full flash)
-    fn get_flash_intensity(&self, path: &PathBuf) -> f32 {
-        if self.flashing_paths.is_empty() {
-            return 0.0;
+    fn window_title(&self) -> String {
+        let title = if let Some(tab) = self.tabs.get(self.active_tab) {
+            format!("{} - Markdown Viewer", tab.title())
+        } else {
+            "Markdown Viewer".to_string()
+        };
+        match self.jobs.overall_fraction() {
+            Some(fraction) => format!("[{:.0}%] {title}", fraction * 100.0),
+            None => title,
         }
+    }
 
-        if let Some(start_time) = self.flashing_paths.get(path) {
-            let elapsed = start_time.elapsed().as_millis() as u64;
-            if elapsed < FLASH_DURATION_MS {
-                // Fade out: 1.0 -> 0.0 over the duration
-                1.0 - (elapsed as f32 / FLASH_DURATION_MS as f32)
-            } else {
-                0.0
-            }
-        } else {
-            0.0
-        }
+    fn is_markdown_file(path: &std::path::Path) -> bool {
+        path.extension()
+            .map(|ext| {
+                let ext = ext.to_string_lossy().to_lowercase();
+                ext == "md" || ext == "markdown" || ext == "txt"
+            })
+            .unwrap_or(false)
     }
 
-    /// Render a single node in the file tree (recursive)
-    fn render_tree_node(
-        &mut self,
-        ui: &mut egui::Ui,
-        node: &FileTreeNode,
-        depth: usize,
-        open_paths: &HashSet<PathBuf>,
-    ) -> ExplorerAction {
-        let mut action = ExplorerAction::default();
-        let indent = depth * 16;
+    fn open_file_dialog(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Markdown", &["md", "markdown"])
+            .add_filter("Text", &["txt"])
+            .add_filter("HTML", &["html", "htm"])
+            .add_filter("All Files", &["*"])
+            .pick_file()
+        {
+            self.open_in_new_tab(path);
+        }
+    }
 
-        match node {
-            FileTreeNode::File {
-                path,
-                name,
-                display_name,
-                ..
-            } => {
-                // Calculate flash intensity for this file
-                let flash_intensity = self.get_flash_intensity(path);
-                let dark_mode = self.dark_mode;
+    /// Open a native folder picker and point the file explorer at the chosen
+    /// directory (issue #28). The chosen root is persisted via `save()`.
+    fn open_folder_dialog(&mut self) {
+        if let Some(path) = rfd::FileDialog::new().pick_folder() {
+            self.set_explorer_root(path);
+        }
+    }
 
-                // Render file row and get its rect
-                let row_response = ui.horizontal(|ui| {
-                    ui.add_space(indent as f32);
+    /// Point the file explorer at `path` and remember it in the recent roots.
+    fn set_explorer_root(&mut self, path: PathBuf) {
+        push_recent_root(&mut self.recent_roots, &path);
+        self.file_explorer.set_root(path);
+        // Make sure the explorer is visible so the result is seen.
+        self.show_explorer = true;
+        // Rebuild the watcher so the new root is watched recursively
+        // (`update_watched_paths` only reconciles tab paths, not the root).
+        if self.watch_enabled {
+            self.start_watching();
+        }
+    }
 
-                    // File icon
-                    ui.label("📄");
+    fn open_in_new_tab(&mut self, path: PathBuf) {
+        self.open_tab(path, true);
+    }
 
-                    // Highlight if file is open in a tab
-                    let is_open = open_paths.contains(path);
-                    let text = if is_open {
-                        egui::RichText::new(display_name.as_str()).strong()
-                    } else {
-                        egui::RichText::new(display_name.as_str())
-                    };
+    /// Download the web page at `url` in the background and open it in
+    /// reader view once it's there.
+    fn download_article(&mut self, url: String) {
+        let Some(dir) = article_dir() else {
+            self.error_message = Some(format!("Nowhere to download {url} to"));
+            return;
+        };
+        let job = self
+            .jobs
+            .spawn(&self.egui_ctx, "Downloading page", move |_| {
+                reader::download(&url, &dir).map_err(|e| format!("Failed to download {url}: {e}"))
+            });
+        self.article_downloads.push(job);
+    }
 
-                    let response = ui.selectable_label(is_open, text);
-                    #[cfg(feature = "mcp")]
-                    {
-                        let state_value = if is_open { "open" } else { "" };
-                        self.mcp_bridge.register_widget(
-                            &format!("File: {}", name),
-                            "button",
-                            &response,
-                            Some(state_value),
-                        );
-                    }
+    /// Contents and section numbers of HTML exports, numbered like the
+    /// headings in the app.
+    fn export_toc(&self) -> toc::Options {
+        toc::Options {
+            depth: self.export_toc_depth,
+            numbered: self.number_headings,
+        }
+    }
 
-                    // Show full name on hover if truncated
-                    if display_name.len() != name.len() {
-                        response.clone().on_hover_text(name);
-                    }
-                    if response.clicked() {
-                        action.file_to_open = Some(path.clone());
+    /// Export the markdown files under the explorer root, or else the open
+    /// documents, as a static HTML site into a folder the user picks.
+    fn export_site(&mut self, ctx: &egui::Context) {
+        let Some(out) = rfd::FileDialog::new()
+            .set_title("Export Site To")
+            .pick_folder()
+        else {
+            return;
+        };
+        let stylesheet = site::stylesheet(&ctx.style().visuals);
+        let toc = self.export_toc();
+        let root = self.file_explorer.root.clone();
+        let documents: Vec<PathBuf> = self
+            .tabs
+            .iter()
+            .map(|tab| tab.path.clone())
+            .filter(|path| FileExplorer::is_markdown_file(path))
+            .collect();
+        let job = self
+            .jobs
+            .spawn(&self.egui_ctx, "Exporting site", move |progress| {
+                let (root, files) = match root {
+                    Some(root) => {
+                        let files = markdown_files_under(&root, usize::MAX, progress);
+                        (root, files)
                     }
-                    // Middle-click to close tab (only if file is open)
-                    if response.middle_clicked() && is_open {
-                        action.file_to_close = Some(path.clone());
+                    None => {
+                        let root = site::common_folder(&documents)
+                            .ok_or_else(|| "No documents to export".to_string())?;
+                        (root, documents)
                     }
+                };
+                site::export(&root, &files, &out, &stylesheet, toc, progress)
+                    .map_err(|e| format!("Failed to export site: {e}"))
+            });
+        self.site_export = Some(job);
+    }
 
-                    // Context menu for file actions
-                    response.context_menu(|ui| {
-                        if ui.button("Copy Contents").clicked() {
-                            if let Ok(contents) = fs::read_to_string(path) {
-                                ui.ctx().copy_text(contents);
-                            }
-                            ui.close();
-                        }
-                        if ui.button("Copy Path").clicked() {
-                            ui.ctx().copy_text(path.display().to_string());
-                            ui.close();
-                        }
-                        if ui.button("Copy File URI").clicked() {
-                            ui.ctx().copy_text(format!("file://{}", path.display()));
-                            ui.close();
-                        }
-                    });
-                });
+    /// Open the index of a finished site export.
+    fn poll_site_export(&mut self) {
+        let Some(job) = &self.site_export else {
+            return;
+        };
+        match job.try_take() {
+            Some(Ok(index)) => self.open_in_new_tab(index),
+            Some(Err(e)) => self.error_message = Some(e),
+            None if job.is_cancelled() => {}
+            None => return,
+        }
+        self.site_export = None;
+    }
 
-                // Paint flash overlay on top using the row rect
-                if flash_intensity > 0.0 {
-                    let alpha = ((flash_intensity * 180.0) as u8).max(60);
-                    let flash_color = if dark_mode {
-                        egui::Color32::from_rgba_unmultiplied(60, 200, 60, alpha)
-                    } else {
-                        egui::Color32::from_rgba_unmultiplied(80, 200, 80, alpha)
-                    };
-                    let rect = row_response.response.rect;
-                    // Use debug_painter which draws on top of everything
-                    ui.ctx().debug_painter().rect_filled(rect, 4.0, flash_color);
-                }
+    /// Open the pages whose download finished.
+    fn poll_article_downloads(&mut self) {
+        let mut finished = Vec::new();
+        self.article_downloads.retain(|job| match job.try_take() {
+            Some(result) => {
+                finished.push(result);
+                false
             }
-            FileTreeNode::Directory {
-                path,
-                name,
-                display_name,
-                ..
-            } => {
-                // Calculate flash intensity for this directory
-                let flash_intensity = self.get_flash_intensity(path);
-                let dark_mode = self.dark_mode;
-
-                // Track if we should toggle this frame (detected in closure, applied after)
-                let mut should_toggle = false;
-
-                // Get current expansion state
-                let is_expanded = self.file_explorer.is_expanded(path);
-
-                // Render directory row
-                let row_response = ui.horizontal(|ui| {
-                    ui.add_space(indent as f32);
+            None => !job.is_cancelled(),
+        });
+        for result in finished {
+            match result {
+                Ok(path) => self.open_in_new_tab(path),
+                Err(e) => self.error_message = Some(e),
+            }
+        }
+    }
 
-                    // Expand/collapse indicator
-                    let indicator = if is_expanded { "v" } else { ">" };
+    /// Open `path` in a new tab, switching to it when `activate` is set.
+    /// An already open document gets no second tab.
+    fn open_tab(&mut self, path: PathBuf, activate: bool) {
+        // Canonicalize for consistent comparison with existing tabs
+        let path = path.canonicalize().unwrap_or(path);
+        self.record_recent(&path);
+        // Check if already open
+        if let Some(idx) = self
+            .tabs
+            .iter()
+            .position(|t| t.path == path && t.section.is_none())
+        {
+            self.tabs[idx].preview = false;
+            if activate {
+                self.active_tab = idx;
+                self.title_dirty = true;
+            }
+            return;
+        }
 
-                    #[cfg(feature = "mcp")]
-                    let expand_btn = ui.mcp_small_button(format!("Toggle: {}", name), indicator);
-                    #[cfg(not(feature = "mcp"))]
-                    let expand_btn = ui.small_button(indicator);
+        // Add new tab, scrolled to where the file was last read
+        let mut tab = Tab::new(path);
+        tab.pending_line = saved_reading_line(&self.reading_positions, &tab.path);
+        self.tabs.push(tab);
+        if activate {
+            self.active_tab = self.tabs.len() - 1;
+            self.title_dirty = true;
+        }
+        self.refresh_open_tab_paths();
 
-                    if expand_btn.clicked() {
-                        should_toggle = true;
-                    }
+        // Update watcher if enabled
+        if self.watch_enabled {
+            self.update_watched_paths();
+        }
+    }
 
-                    // Folder icon
-                    let folder_icon = if is_expanded { "📂" } else { "📁" };
-                    ui.label(folder_icon);
+    /// Open the section of `path` whose heading is on 0-based `line` in a tab
+    /// of its own, or switch to the tab already showing it.
+    fn open_section_tab(&mut self, path: PathBuf, line: usize) {
+        if let Some(idx) = self
+            .tabs
+            .iter()
+            .position(|t| t.path == path && t.section.as_ref().is_some_and(|s| s.line == line))
+        {
+            self.active_tab = idx;
+            self.title_dirty = true;
+            return;
+        }
+        let Some(tab) = Tab::new_section(path, line) else {
+            self.error_message = Some("That heading is no longer in the document".to_string());
+            return;
+        };
+        self.tabs.push(tab);
+        self.active_tab = self.tabs.len() - 1;
+        self.title_dirty = true;
+        self.refresh_open_tab_paths();
+        if self.watch_enabled {
+            self.update_watched_paths();
+        }
+    }
 
-                    let response = ui.add(
-                        egui::Label::new(display_name.as_str())
-                            .selectable(false)
-                            .sense(egui::Sense::click()),
-                    );
-                    #[cfg(feature = "mcp")]
-                    {
-                        let state_value = if is_expanded { "expanded" } else { "collapsed" };
-                        self.mcp_bridge.register_widget(
-                            &format!("Directory: {}", name),
-                            "button",
-                            &response,
-                            Some(state_value),
-                        );
-                    }
+    /// Pick two documents and open a tab comparing them block by block.
+    fn compare_files_dialog(&mut self) {
+        let dialog = |title: &str| {
+            rfd::FileDialog::new()
+                .set_title(title)
+                .add_filter("Markdown", &["md", "markdown"])
+                .add_filter("All Files", &["*"])
+        };
+        let Some(old) = dialog("Compare: Original Document").pick_file() else {
+            return;
+        };
+        let Some(new) = dialog("Compare With: Revised Document").pick_file() else {
+            return;
+        };
+        self.tabs.push(Tab::new_comparison(old, new));
+        self.active_tab = self.tabs.len() - 1;
+        self.title_dirty = true;
+        self.refresh_open_tab_paths();
+        if self.watch_enabled {
+            self.update_watched_paths();
+        }
+    }
 
-                    // Show full name on hover if truncated
-                    if display_name.len() != name.len() {
-                        response.clone().on_hover_text(name);
-                    }
+    /// Ask for the clipboard's text, which arrives as a paste event that
+    /// [`Self::poll_clipboard_tab`] opens in a new tab.
+    fn request_clipboard_tab(&mut self, ctx: &egui::Context) {
+        self.clipboard_tab_requested = Some(Instant::now());
+        ctx.send_viewport_cmd(egui::ViewportCommand::RequestPaste);
+    }
 
-                    // Click directory name to toggle expansion
-                    if response.clicked() {
-                        should_toggle = true;
-                    }
-                });
+    /// Open the text pasted for a clipboard tab, giving up when none comes.
+    fn poll_clipboard_tab(&mut self, ctx: &egui::Context) {
+        let Some(requested) = self.clipboard_tab_requested else {
+            return;
+        };
+        let pasted = ctx.input(|i| {
+            i.events.iter().find_map(|event| match event {
+                egui::Event::Paste(text) => Some(text.clone()),
+                _ => None,
+            })
+        });
+        match pasted {
+            Some(text) => {
+                self.clipboard_tab_requested = None;
+                self.open_unsaved_tab("Clipboard", text);
+            }
+            None if requested.elapsed() >= CLIPBOARD_PASTE_TIMEOUT => {
+                self.clipboard_tab_requested = None;
+                self.error_message = Some("The clipboard holds no text".to_string());
+            }
+            None => ctx.request_repaint_after(Duration::from_millis(50)),
+        }
+    }
 
-                // Defer toggle to after tree is restored (avoids clone)
-                if should_toggle {
-                    action.dir_to_toggle = Some(path.clone());
-                }
+    /// Open `text` in a new unsaved tab, named `name` and numbered so it can
+    /// be saved next to the explorer's folder.
+    fn open_unsaved_tab(&mut self, name: &str, text: String) {
+        let dir = self
+            .file_explorer
+            .root
+            .clone()
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        let path = (1..)
+            .map(|n| dir.join(format!("{name} {n}.md")))
+            .find(|path| !path.exists() && !self.tabs.iter().any(|t| &t.path == path))
+            .unwrap_or_default();
+        self.tabs.push(Tab::new_unsaved(path, text));
+        self.active_tab = self.tabs.len() - 1;
+        self.title_dirty = true;
+    }
 
-                // Paint flash overlay on top using the row rect
-                if flash_intensity > 0.0 {
-                    let alpha = ((flash_intensity * 180.0) as u8).max(60);
-                    let flash_color = if dark_mode {
-                        egui::Color32::from_rgba_unmultiplied(60, 200, 60, alpha)
-                    } else {
-                        egui::Color32::from_rgba_unmultiplied(80, 200, 80, alpha)
-                    };
-                    let rect = row_response.response.rect;
-                    // Use debug_painter which draws on top of everything
-                    ui.ctx().debug_painter().rect_filled(rect, 4.0, flash_color);
-                }
-
-                // Render children if expanded (read from node directly, no clone needed)
-                if self.file_explorer.is_expanded(path) {
-                    if let FileTreeNode::Directory {
-                        children: Some(ref child_nodes),
-                        ..
-                    } = node
-                    {
-                        for child in child_nodes {
-                            let child_action =
-                                self.render_tree_node(ui, child, depth + 1, open_paths);
-                            if child_action.file_to_open.is_some() {
-                                action.file_to_open = child_action.file_to_open;
-                            }
-                            if child_action.file_to_close.is_some() {
-                                action.file_to_close = child_action.file_to_close;
-                            }
-                            if child_action.dir_to_toggle.is_some() {
-                                action.dir_to_toggle = child_action.dir_to_toggle;
-                            }
-                        }
-                    }
-                }
-            }
+    /// Save the active unsaved tab to a file the user picks, and show that
+    /// file from then on.
+    fn save_unsaved_tab(&mut self) {
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+        let Some(text) = &tab.unsaved else {
+            return;
+        };
+        let mut dialog = rfd::FileDialog::new()
+            .set_file_name(tab.title())
+            .add_filter("Markdown", &["md", "markdown"]);
+        if let Some(dir) = tab.path.parent() {
+            dialog = dialog.set_directory(dir);
         }
-
-        action
-    }
-
-    fn render_lightbox(&mut self, ctx: &egui::Context) {
-        let Some(lightbox) = &mut self.lightbox else {
+        let Some(path) = dialog.save_file() else {
             return;
         };
+        if let Err(e) = fs::write(&path, text) {
+            self.error_message = Some(format!("Failed to save {}: {e}", path.display()));
+            return;
+        }
+        tab.unsaved = None;
+        tab.path = path.canonicalize().unwrap_or(path);
+        tab.base_uri = Tab::compute_base_uri(&tab.path);
+        tab.reload();
+        let path = tab.path.clone();
+        self.record_recent(&path);
+        self.title_dirty = true;
+        self.refresh_open_tab_paths();
+        if self.watch_enabled {
+            self.update_watched_paths();
+        }
+    }
 
-        let screen_rect = ctx.available_rect();
-        let mut should_close = false;
-
-        // 1. Semi-transparent backdrop (visual only, drawn below Tooltip layers)
-        ctx.layer_painter(egui::LayerId::new(
-            egui::Order::Foreground,
-            egui::Id::new("lightbox_backdrop"),
-        ))
-        .rect_filled(
-            screen_rect,
-            0.0,
-            egui::Color32::from_rgba_unmultiplied(0, 0, 0, 200),
-        );
+    /// Show `path` in the preview tab, replacing the document previewed
+    /// before, or switch to it when it's already open.
+    fn open_preview_tab(&mut self, path: PathBuf) {
+        let path = path.canonicalize().unwrap_or(path);
+        if let Some(idx) = self
+            .tabs
+            .iter()
+            .position(|t| t.path == path && t.section.is_none())
+        {
+            self.active_tab = idx;
+            self.title_dirty = true;
+            return;
+        }
+        let mut tab = Tab::new(path);
+        tab.preview = true;
+        match self.tabs.iter().position(|t| t.preview) {
+            Some(idx) => {
+                self.remember_reading_position(idx);
+                self.tabs[idx] = tab;
+                self.active_tab = idx;
+            }
+            None => {
+                self.tabs.push(tab);
+                self.active_tab = self.tabs.len() - 1;
+            }
+        }
+        self.title_dirty = true;
+        self.refresh_open_tab_paths();
+        if self.watch_enabled {
+            self.update_watched_paths();
+        }
+    }
 
-        // 2. Full-screen input sink at Tooltip order — blocks all input to lower layers.
-        //    Clicking on the sink (outside image/close) closes the lightbox.
-        let oid = lightbox.open_id;
-        egui::Area::new(egui::Id::new("lightbox_sink").with(oid))
-            .order(egui::Order::Tooltip)
-            .movable(false)
-            .fixed_pos(screen_rect.min)
-            .show(ctx, |ui| {
-                let r = ui.allocate_response(screen_rect.size(), egui::Sense::click());
-                if r.clicked() {
-                    should_close = true;
-                }
-            });
+    /// Record a file in the recent list (most-recent first, deduped, capped).
+    fn record_recent(&mut self, path: &Path) {
+        push_recent(&mut self.recent_files, path, now_epoch_secs());
+    }
 
-        // 3. Apply scroll-wheel zoom (proportional to scroll amount for smooth feel)
-        let old_zoom = lightbox.zoom;
-        if self.lightbox_scroll != 0.0 {
-            // ~10% per scroll notch, smooth with proportional delta
-            let factor = (1.0_f32 + self.lightbox_scroll * 0.08).clamp(0.5, 2.0);
-            lightbox.zoom = (lightbox.zoom * factor).clamp(0.1, 10.0);
+    /// Remember where the tab at `idx` is scrolled to, for when its file is
+    /// opened again.
+    fn remember_reading_position(&mut self, idx: usize) {
+        // Lines of a section tab aren't the document's, and an unsaved tab
+        // has no document yet
+        if let Some(tab) = self
+            .tabs
+            .get(idx)
+            .filter(|tab| tab.section.is_none() && tab.unsaved.is_none() && tab.compare.is_none())
+        {
+            push_reading_position(&mut self.reading_positions, &tab.path, tab.reading_line());
         }
-        let zoom_changed = (lightbox.zoom - old_zoom).abs() > f32::EPSILON;
+    }
 
-        // 4. Image — GPU texture scaling, no re-rasterization
-        let padding = 40.0;
-        let area_rect = screen_rect.shrink(padding);
-        let area_size = area_rect.size();
-        let zoom = lightbox.zoom;
-        let base_size = lightbox.base_size;
-        let display_size = base_size * zoom;
-        let tex_id = lightbox.texture.id();
-        let tex_uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+    /// Use `language` for menus and alerts, or the locale's when `None`.
+    fn set_language(&mut self, language: Option<String>) {
+        self.ui_language = language.clone().unwrap_or_else(i18n::system_language);
+        self.alerts = custom_alerts::bundle(&self.ui_language);
+        self.language = language;
+    }
 
-        // Use fixed_pos instead of anchor to avoid position jitter when content
-        // size changes (anchor recalculates position based on laid-out size,
-        // causing a one-frame shift when scrollbars appear/disappear).
-        let area_pos = egui::pos2(
-            screen_rect.center().x - area_size.x / 2.0,
-            screen_rect.center().y - area_size.y / 2.0,
-        );
+    fn close_tab(&mut self, idx: usize) {
+        if idx >= self.tabs.len() {
+            return;
+        }
 
-        // Pre-compute zoom-to-cursor offset BEFORE ScrollArea renders,
-        // so it uses the correct offset on the same frame (no one-frame jitter).
-        let pre_offset: Option<egui::Vec2> = if zoom_changed {
-            ctx.input(|i| i.pointer.latest_pos()).map(|mouse_pos| {
-                // area_pos ≈ viewport origin (no scrollbar/frame margins)
-                let mouse_in_vp = mouse_pos - area_pos;
+        self.remember_reading_position(idx);
+        self.tabs.remove(idx);
+        self.title_dirty = true;
+        self.refresh_open_tab_paths();
 
-                let old_display = base_size * old_zoom;
-                let new_display = display_size;
+        // Adjust active tab index. Closing the last tab leaves `tabs` empty,
+        // which renders the welcome page (active_tab kept in range / at 0).
+        if self.tabs.is_empty() {
+            self.active_tab = 0;
+        } else if self.active_tab >= self.tabs.len() {
+            self.active_tab = self.tabs.len() - 1;
+        } else if self.active_tab > idx {
+            self.active_tab -= 1;
+        }
 
-                let old_content = egui::vec2(
-                    old_display.x.max(area_size.x),
-                    old_display.y.max(area_size.y),
-                );
-                let new_content = egui::vec2(
-                    new_display.x.max(area_size.x),
-                    new_display.y.max(area_size.y),
-                );
+        // Update watcher
+        if self.watch_enabled {
+            self.update_watched_paths();
+        }
+    }
 
-                let old_pad = (old_content - old_display) * 0.5;
-                let new_pad = (new_content - new_display) * 0.5;
+    /// Open the active document in the external editor at the line of the
+    /// active find match, or else of the block at the top of the viewport.
+    fn open_in_editor(&mut self) {
+        let Some(tab) = self.tabs.get(self.active_tab) else {
+            return;
+        };
+        if tab.unsaved.is_some() {
+            self.error_message = Some("Save the document before editing it".to_string());
+            return;
+        }
+        let match_line = self
+            .search
+            .is_open
+            .then(|| tab.search_matches.get(self.search.active_match_index))
+            .flatten()
+            .map(|m| m.line_number);
+        let line = match_line.unwrap_or_else(|| tab.line_at_offset(tab.scroll_offset))
+            + tab.section.as_ref().map_or(0, |section| section.line);
+        let args = editor_command_args(&self.editor_command, &tab.path, line);
+        let Some((program, args)) = args.split_first() else {
+            self.error_message = Some("The editor command is empty".to_string());
+            return;
+        };
+        let spawned = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Err(e) = spawned {
+            self.error_message = Some(format!("Failed to start editor `{program}`: {e}"));
+        }
+    }
 
-                let frac = (mouse_in_vp + lightbox.scroll_offset - old_pad) / old_display;
+    /// Ask before running the code block the user clicked Run on, then run it
+    /// on a background thread.
+    fn render_run_confirmation(&mut self, ctx: &egui::Context) {
+        let Some((id, runner, code, dir)) = self.code_runs.borrow().pending.clone() else {
+            return;
+        };
+        let mut run = false;
+        let mut cancel = false;
+        let modal = egui::Modal::new(egui::Id::new("run_code_confirmation")).show(ctx, |ui| {
+            ui.set_max_width(480.0);
+            ui.heading("Run this command?");
+            if let Some(dir) = &dir {
+                ui.label(format!(
+                    "It runs with {} in {}",
+                    runner.program(),
+                    dir.display()
+                ));
+            }
+            egui::ScrollArea::vertical()
+                .max_height(240.0)
+                .show(ui, |ui| {
+                    ui.label(egui::RichText::new(code.trim_end()).monospace());
+                });
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                run = ui.button("Run").clicked();
+                cancel = ui.button("Cancel").clicked();
+            });
+        });
+        if !(run || cancel || modal.should_close()) {
+            return;
+        }
+        let mut runs = self.code_runs.borrow_mut();
+        runs.pending = None;
+        if !run {
+            return;
+        }
+        let output = Arc::new(Mutex::new(CodeRunOutput::default()));
+        runs.outputs.insert(id, output.clone());
+        let stop = output
+            .lock()
+            .map(|output| output.stop.clone())
+            .unwrap_or_default();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let result = runner.run(&code, dir.as_deref(), &stop);
+            if let Ok(mut output) = output.lock() {
+                *output = result;
+            }
+            ctx.request_repaint();
+        });
+    }
 
-                let new_offset = frac * new_display + new_pad - mouse_in_vp;
+    /// Carry out a keyboard shortcut; `chord` is the index of the one of its
+    /// binding's chords that was pressed.
+    fn run_key_action(&mut self, ctx: &egui::Context, action: keymap::Action, chord: usize) {
+        use keymap::Action;
+        match action {
+            Action::NewTab | Action::OpenFile => self.open_file_dialog(),
+            // The clipboard's text comes as this frame's paste event
+            Action::ClipboardTab => self.clipboard_tab_requested = Some(Instant::now()),
+            Action::CloseTab => self.close_active_tab(),
+            Action::NextTab => self.next_tab(),
+            Action::PrevTab => self.prev_tab(),
+            Action::FocusTab => self.focus_tab(chord),
+            Action::OpenInEditor => self.open_in_editor(),
+            Action::ToggleWatch => {
+                if self.watcher.is_some() {
+                    self.disable_watching();
+                } else {
+                    self.watch_enabled = true;
+                    self.start_watching();
+                }
+            }
+            Action::TogglePalette => {
+                self.command_palette = match self.command_palette {
+                    Some(_) => None,
+                    None => Some(String::new()),
+                };
+            }
+            Action::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            Action::Back => {
+                if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                    tab.navigate_back();
+                }
+            }
+            Action::Forward => {
+                if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                    tab.navigate_forward();
+                }
+            }
+            Action::OpenSearch => {
+                self.search.is_open = true;
+                self.search.focus_requested = true;
+            }
+            Action::NextMatch => self.jump_match(1),
+            Action::PrevMatch => self.jump_match(-1),
+            Action::CloseSearch => self.close_search(),
+            Action::ToggleDark => {
+                self.theme_mode = ThemeMode::toggled(self.dark_mode);
+                self.dark_mode = !self.dark_mode;
+            }
+            Action::ToggleExplorer => self.show_explorer = !self.show_explorer,
+            Action::ToggleOutline => self.show_outline = !self.show_outline,
+            Action::ToggleTasks => self.show_tasks = !self.show_tasks,
+            Action::ToggleProblems => self.show_problems = !self.show_problems,
+            Action::ToggleSource => self.toggle_source_view(),
+            // Zoom applies to the lightbox when open, the document otherwise
+            Action::ZoomIn | Action::ZoomOut => {
+                let zoom_in = action == Action::ZoomIn;
+                if let Some(lb) = &mut self.lightbox {
+                    let factor = if zoom_in { 1.25 } else { 1.0 / 1.25 };
+                    lb.zoom = (lb.zoom * factor).clamp(0.1, 10.0);
+                } else {
+                    let delta = if zoom_in { 0.1 } else { -0.1 };
+                    self.zoom_level = (self.zoom_level + delta).clamp(0.5, 3.0);
+                }
+            }
+            Action::ZoomReset => match &mut self.lightbox {
+                Some(lb) => lb.zoom = 1.0,
+                None => self.zoom_level = 1.0,
+            },
+            Action::ScrollLine | Action::ScrollPage => {
+                let scroll = match (action, chord) {
+                    (Action::ScrollLine, 0) => KeyboardScrollAction::LineUp,
+                    (Action::ScrollLine, _) => KeyboardScrollAction::LineDown,
+                    (_, 0) => KeyboardScrollAction::PageUp,
+                    _ => KeyboardScrollAction::PageDown,
+                };
+                if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                    let target = keyboard_scroll_target(
+                        tab.scroll_offset,
+                        tab.last_viewport_height,
+                        tab.last_content_height,
+                        scroll,
+                    );
+                    tab.pending_scroll_offset = Some(target);
+                }
+            }
+            Action::ToggleShortcuts => self.show_shortcuts = !self.show_shortcuts,
+        }
+    }
 
-                let max_offset = egui::vec2(
-                    (new_content.x - area_size.x).max(0.0),
-                    (new_content.y - area_size.y).max(0.0),
-                );
+    /// Overlay listing every key binding by category, from [`keymap::KEYMAP`].
+    fn render_shortcuts(&mut self, ctx: &egui::Context) {
+        if !self.show_shortcuts {
+            return;
+        }
+        let mut close = false;
+        let modal = egui::Modal::new(egui::Id::new("keyboard_shortcuts")).show(ctx, |ui| {
+            ui.set_max_width(560.0);
+            ui.horizontal(|ui| {
+                ui.heading("Keyboard Shortcuts");
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    close = ui.small_button("✕").on_hover_text("Close").clicked();
+                });
+            });
+            egui::ScrollArea::vertical()
+                .max_height(ctx.content_rect().height() * 0.7)
+                .show(ui, |ui| {
+                    for bindings in keymap::KEYMAP.chunk_by(|a, b| a.category == b.category) {
+                        let category = bindings[0].category;
+                        ui.add_space(6.0);
+                        ui.strong(category);
+                        egui::Grid::new(category)
+                            .num_columns(2)
+                            .spacing([16.0, 4.0])
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for binding in bindings {
+                                    ui.monospace(binding.keys);
+                                    ui.label(binding.description);
+                                    ui.end_row();
+                                }
+                            });
+                    }
+                });
+        });
+        if close || modal.should_close() {
+            self.show_shortcuts = false;
+        }
+    }
 
-                egui::vec2(
-                    new_offset.x.clamp(0.0, max_offset.x),
-                    new_offset.y.clamp(0.0, max_offset.y),
-                )
-            })
-        } else {
-            None
+    /// Palette of the commands scripts add, filtered by what's typed. Enter
+    /// runs the first one listed.
+    fn render_command_palette(&mut self, ctx: &egui::Context) {
+        let Some(filter) = &mut self.command_palette else {
+            return;
         };
+        let commands = scripting::commands();
+        let needle = filter.to_lowercase();
+        let listed: Vec<&scripting::Command> = commands
+            .iter()
+            .filter(|command| command.title.to_lowercase().contains(&needle))
+            .collect();
+        let mut run = None;
+        let modal = egui::Modal::new(egui::Id::new("command_palette")).show(ctx, |ui| {
+            ui.set_width(420.0);
+            let input = ui.add(
+                egui::TextEdit::singleline(filter)
+                    .hint_text("Run a command")
+                    .desired_width(f32::INFINITY),
+            );
+            if input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                run = listed.first().copied();
+            }
+            input.request_focus();
+            ui.separator();
+            if commands.is_empty() {
+                let hint = if cfg!(feature = "scripting") {
+                    "No commands yet. Scripts in ~/.config/md-viewer/scripts add them as \
+                     fn command_<name>(path, text)"
+                } else {
+                    "Commands come from scripts, which need a build with the scripting feature"
+                };
+                ui.label(egui::RichText::new(hint).weak());
+            }
+            for command in &listed {
+                if ui.selectable_label(false, &command.title).clicked() {
+                    run = Some(command);
+                }
+            }
+        });
+        if let Some(command) = run {
+            let command = command.clone();
+            self.command_palette = None;
+            self.run_script_command(&command);
+        } else if modal.should_close() {
+            self.command_palette = None;
+        }
+    }
 
-        if let Some(offset) = pre_offset {
-            lightbox.scroll_offset = offset;
+    /// Run the script `command` on the active document, opening the
+    /// markdown it returns in a new tab.
+    fn run_script_command(&mut self, command: &scripting::Command) {
+        let (path, content) = self
+            .tabs
+            .get(self.active_tab)
+            .map(|tab| (tab.path.clone(), tab.content.clone()))
+            .unwrap_or_default();
+        match scripting::run_command(command, &path, &content) {
+            Ok(Some(text)) => self.open_unsaved_tab(&command.title, text),
+            Ok(None) => {}
+            Err(err) => self.error_message = Some(err),
         }
+    }
 
-        egui::Area::new(egui::Id::new("lightbox_image").with(oid))
-            .order(egui::Order::Tooltip)
-            .movable(false)
-            .fixed_pos(area_pos)
-            .show(ctx, |ui| {
-                ui.set_min_size(area_size);
-                ui.set_max_size(area_size);
-
-                // Only enable scroll/drag on axes where image overflows the viewport
-                let mut scroll_area = egui::ScrollArea::new([
-                    display_size.x > area_size.x,
-                    display_size.y > area_size.y,
-                ])
-                .id_salt(egui::Id::new("lightbox_scroll").with(oid))
-                .max_width(area_size.x)
-                .max_height(area_size.y)
-                .scroll_bar_visibility(egui::scroll_area::ScrollBarVisibility::AlwaysHidden)
-                .scroll_source(egui::scroll_area::ScrollSource {
-                    drag: true,
-                    ..Default::default()
-                });
+    /// Git details of `path`, loading them in the background the first time.
+    /// `None` while loading and for files outside a repository.
+    fn git_file_info(&mut self, path: &Path) -> Option<&GitFileInfo> {
+        let info = self.git_info.entry(path.to_path_buf()).or_insert_with(|| {
+            let info = Arc::new(OnceLock::new());
+            let slot = info.clone();
+            let path = path.to_path_buf();
+            let ctx = self.egui_ctx.clone();
+            std::thread::spawn(move || {
+                let _ = slot.set(load_git_file_info(&path));
+                ctx.request_repaint();
+            });
+            info
+        });
+        info.get()?.as_ref()
+    }
 
-                // Set the pre-computed offset so ScrollArea renders at the
-                // correct position on this frame (avoids one-frame lag).
-                if let Some(offset) = pre_offset {
-                    scroll_area = scroll_area
-                        .horizontal_scroll_offset(offset.x)
-                        .vertical_scroll_offset(offset.y);
+    /// Status bar with the last commit of the active file and whether it was
+    /// modified since, and the background jobs running, plus the file's git
+    /// log when opened from its menu.
+    fn render_status_bar(&mut self, ctx: &egui::Context) {
+        let active = self
+            .tabs
+            .get(self.active_tab)
+            .map(|tab| (tab.path.clone(), tab.encoding, tab.encoding_override));
+        if active.is_none() && self.jobs.is_empty() {
+            return;
+        }
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut refresh = false;
+        let mut toggle_log = false;
+        // Some(None) goes back to detecting the encoding
+        let mut set_encoding: Option<Option<&'static Encoding>> = None;
+        #[cfg(feature = "mcp")]
+        let mut encoding_rect: Option<egui::Rect> = None;
+        let show_log = self.show_git_log;
+        // Taken out while the git details borrow `self`; cancelling a job
+        // needs only a shared borrow
+        let jobs = std::mem::take(&mut self.jobs);
+        let info = match &active {
+            Some((path, ..)) => self.git_file_info(path),
+            None => None,
+        };
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            let small = |text: &str| egui::RichText::new(text).small();
+            ui.horizontal(|ui| {
+                if let Some(info) = info {
+                    let response = ui
+                        .horizontal(|ui| {
+                            match info.log.first() {
+                                Some(last) => ui.label(small(&format!(
+                                    "Last commit by {}, {}",
+                                    last.author,
+                                    format_relative_time(last.time, now)
+                                ))),
+                                None => ui.label(small("Not committed yet").weak()),
+                            };
+                            if let Some(badge) = info.badge {
+                                let color = ui.visuals().warn_fg_color;
+                                ui.label(small(&format!("● {badge}")).color(color))
+                                    .on_hover_text("Changed since the last commit");
+                            }
+                        })
+                        .response
+                        .interact(egui::Sense::click());
+                    response.context_menu(|ui| {
+                        let text = if show_log {
+                            "Hide Git Log"
+                        } else {
+                            "Show Git Log"
+                        };
+                        if ui.button(text).clicked() {
+                            toggle_log = true;
+                            ui.close();
+                        }
+                        if ui.button("Refresh").clicked() {
+                            refresh = true;
+                            ui.close();
+                        }
+                    });
                 }
 
-                let scroll_output = scroll_area.show(ui, |ui| {
-                    // Allocate content: at least area_size for centering,
-                    // larger when image overflows (for scroll panning)
-                    let content_size = egui::vec2(
-                        display_size.x.max(area_size.x),
-                        display_size.y.max(area_size.y),
-                    );
-                    let (content_rect, response) =
-                        ui.allocate_exact_size(content_size, egui::Sense::click());
-                    // Draw image centered within the content rect
-                    let image_rect =
-                        egui::Rect::from_center_size(content_rect.center(), display_size);
-                    if ui.is_rect_visible(image_rect) {
-                        let mut mesh = egui::Mesh::with_texture(tex_id);
-                        mesh.add_rect_with_uv(image_rect, tex_uv, egui::Color32::WHITE);
-                        ui.painter().add(egui::Shape::mesh(mesh));
-                    }
-                    if response.double_clicked() {
-                        lightbox.zoom = 1.0;
-                        lightbox.scroll_offset = egui::Vec2::ZERO;
-                    }
-                    // Click outside image area → close lightbox
-                    if response.clicked() {
-                        if let Some(pos) = response.interact_pointer_pos() {
-                            if !image_rect.contains(pos) {
-                                should_close = true;
+                jobs.ui(ui);
+
+                let Some((_, encoding, encoding_override)) = active else {
+                    return;
+                };
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let menu = ui.menu_button(small(encoding.name()), |ui| {
+                        let auto = ui.selectable_label(encoding_override.is_none(), "Auto-detect");
+                        if auto.clicked() {
+                            set_encoding = Some(None);
+                            ui.close();
+                        }
+                        ui.separator();
+                        for choice in ENCODING_CHOICES {
+                            let selected = encoding_override == Some(choice);
+                            if ui.selectable_label(selected, choice.name()).clicked() {
+                                set_encoding = Some(Some(choice));
+                                ui.close();
                             }
                         }
+                    });
+                    #[cfg(feature = "mcp")]
+                    {
+                        encoding_rect = Some(menu.response.rect);
                     }
+                    menu.response.on_hover_text("Reopen with another encoding");
                 });
-
-                // Sync tracking: captures drag-to-scroll changes on non-zoom frames
-                if pre_offset.is_none() {
-                    lightbox.scroll_offset = scroll_output.state.offset;
-                }
-            });
-
-        // 5. Close button (top-right)
-        egui::Area::new(egui::Id::new("lightbox_close").with(oid))
-            .order(egui::Order::Tooltip)
-            .movable(false)
-            .fixed_pos(egui::pos2(
-                screen_rect.right() - 48.0,
-                screen_rect.top() + 8.0,
-            ))
-            .show(ctx, |ui| {
-                let btn = ui.add(
-                    egui::Button::new(
-                        egui::RichText::new("\u{2715}")
-                            .size(20.0)
-                            .color(egui::Color32::from_gray(200)),
-                    )
-                    .frame(false),
-                );
-                if btn.hovered() {
-                    ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
-                }
-                if btn.clicked() {
-                    should_close = true;
-                }
             });
+        });
 
-        // 6. Zoom indicator (bottom-center) when zoom ≠ 100%
-        let zoom_pct = (lightbox.zoom * 100.0).round() as i32;
-        if zoom_pct != 100 {
-            ctx.layer_painter(egui::LayerId::new(
-                egui::Order::Tooltip,
-                egui::Id::new("lightbox_zoom_indicator").with(oid),
-            ))
-            .text(
-                egui::pos2(screen_rect.center().x, screen_rect.bottom() - 24.0),
-                egui::Align2::CENTER_CENTER,
-                format!("{zoom_pct}%"),
-                egui::FontId::proportional(16.0),
-                egui::Color32::from_gray(180),
-            );
+        if let (true, Some(info)) = (show_log, info) {
+            egui::TopBottomPanel::bottom("git_log")
+                .resizable(true)
+                .default_height(160.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.strong("Git Log");
+                        if ui.small_button("✕").on_hover_text("Close").clicked() {
+                            toggle_log = true;
+                        }
+                    });
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        egui::Grid::new("git_log_entries")
+                            .num_columns(4)
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for entry in &info.log {
+                                    ui.monospace(&entry.hash);
+                                    ui.label(&entry.subject);
+                                    ui.weak(&entry.author);
+                                    ui.weak(format_relative_time(entry.time, now));
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                });
         }
 
-        // 7. Escape to close
-        ctx.input(|i| {
-            if i.key_pressed(egui::Key::Escape) {
-                should_close = true;
-            }
-        });
+        self.jobs = jobs;
 
-        if should_close {
-            self.lightbox = None;
+        if toggle_log {
+            self.show_git_log = !self.show_git_log;
+        }
+        if let (true, Some((path, ..))) = (refresh, &active) {
+            self.git_info.remove(path);
+        }
+        if let Some(encoding) = set_encoding {
+            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                tab.encoding_override = encoding;
+                tab.reload();
+            }
+        }
+        #[cfg(feature = "mcp")]
+        if let (Some(rect), Some((_, encoding, _))) = (encoding_rect, active) {
+            self.mcp_bridge.register_widget_rect(
+                "Status: Encoding",
+                "button",
+                rect,
+                Some(encoding.name()),
+            );
         }
     }
-}
 
-impl eframe::App for MarkdownApp {
-    fn raw_input_hook(&mut self, _ctx: &egui::Context, raw_input: &mut egui::RawInput) {
-        #[cfg(feature = "mcp")]
-        {
-            self.mcp_bridge.process_commands();
-            self.mcp_bridge.inject_raw_input(raw_input);
+    /// Structural problems of the active document and images that failed to
+    /// load, each scrolling to its line when clicked.
+    fn render_problems(&mut self, ctx: &egui::Context) {
+        if !self.show_problems {
+            return;
         }
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+        let content = &tab.content;
+        let mut all = tab
+            .problems
+            .get_or_insert_with(|| problems::find_problems(content))
+            .clone();
+        all.extend(
+            tab.cache
+                .failed_images()
+                .map(|(uri, error)| problems::image_problem(content, uri, error)),
+        );
+        all.extend(tab.cache.render_errors().map(|error| {
+            problems::render_problem(content, error.byte_range.start, &error.message)
+        }));
 
-        // When lightbox is open, intercept scroll events before they reach InputState.
-        // This prevents the document's ScrollArea from scrolling while letting us
-        // use the captured delta for lightbox zoom.
-        self.lightbox_scroll = 0.0;
-        if self.lightbox.is_some() {
-            for event in &raw_input.events {
-                if let egui::Event::MouseWheel { delta, .. } = event {
-                    self.lightbox_scroll += delta.y;
+        // Collect widget data for MCP registration (name, widget_type, rect, value)
+        #[cfg(feature = "mcp")]
+        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
+
+        let mut close = false;
+        let mut jump_to = None;
+        egui::TopBottomPanel::bottom("problems")
+            .resizable(true)
+            .default_height(120.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong("Problems");
+                    ui.weak(all.len().to_string());
+                    if ui.small_button("✕").on_hover_text("Close").clicked() {
+                        close = true;
+                    }
+                });
+                if all.is_empty() {
+                    ui.weak("No problems found in this document");
+                    return;
                 }
-            }
-            raw_input
-                .events
-                .retain(|e| !matches!(e, egui::Event::MouseWheel { .. }));
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for problem in &all {
+                        let location = problem
+                            .line
+                            .map_or_else(String::new, |line| format!("Line {line}: "));
+                        let text = format!("⚠ {location}{}", problem.message);
+                        let response = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                        #[cfg(feature = "mcp")]
+                        widget_data.push((
+                            format!("Problem: {location}{}", problem.message),
+                            "button",
+                            response.rect,
+                            None,
+                        ));
+                        if response.clicked() {
+                            jump_to = problem.line;
+                        }
+                    }
+                });
+            });
+
+        // Register all collected widgets with MCP bridge
+        #[cfg(feature = "mcp")]
+        for (name, widget_type, rect, value) in widget_data {
+            self.mcp_bridge
+                .register_widget_rect(&name, widget_type, rect, value.as_deref());
         }
-    }
 
-    fn persist_egui_memory(&self) -> bool {
-        false // Don't persist egui's internal Memory (widget states, panel sizes, etc.)
-              // Our PersistedState handles everything we need; egui's blob grows unbounded (~170KB)
+        if close {
+            self.show_problems = false;
+        }
+        if let Some(line) = jump_to {
+            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                tab.pending_line = Some(line);
+            }
+        }
     }
 
-    fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        let state = PersistedState {
-            dark_mode: Some(self.dark_mode),
-            zoom_level: Some(self.zoom_level),
-            show_outline: Some(self.show_outline),
-            full_width_content: Some(self.full_width_content),
-            open_tabs: Some(self.get_open_tab_paths()),
-            active_tab: Some(self.active_tab),
-            show_explorer: Some(self.show_explorer),
-            explorer_root: self.file_explorer.root.clone(),
-            expanded_dirs: Some(self.file_explorer.expanded_dirs.iter().cloned().collect()),
-            explorer_sort_order: Some(self.file_explorer.sort_order),
-            recent_files: Some(self.recent_files.clone()),
+    /// The footnotes of the active document with their text. The ones
+    /// referred to from the lines on screen are highlighted; clicking a note
+    /// jumps to its definition and its ↩ buttons to its references.
+    fn render_footnotes(&mut self, ctx: &egui::Context) {
+        if !self.show_footnotes {
+            return;
+        }
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
         };
-        eframe::set_value(storage, APP_KEY, &state);
-    }
+        let content = &tab.content;
+        let notes = tab
+            .footnotes
+            .get_or_insert_with(|| footnotes::find_footnotes(content))
+            .clone();
+        let first_visible = tab.line_at_offset(tab.scroll_offset);
+        let last_visible = tab.line_at_offset(tab.scroll_offset + tab.last_viewport_height);
 
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Enable AccessKit for MCP bridge
+        // Collect widget data for MCP registration (name, widget_type, rect, value)
         #[cfg(feature = "mcp")]
-        {
-            ctx.enable_accesskit();
-            self.mcp_bridge.begin_frame();
-            self.mcp_bridge.store_in_context(ctx); // Enable McpUiExt methods
-                                                   // Poll for MCP commands at reasonable rate (prevents CPU spin on virtual displays)
-            ctx.request_repaint_after(Duration::from_millis(50));
-        }
+        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
 
-        // Check for file changes and reload affected tabs
-        let changed_paths = self.check_file_changes();
-        if !changed_paths.is_empty() {
-            self.reload_changed_tabs(changed_paths);
+        let mut close = false;
+        let mut jump_to = None;
+        egui::SidePanel::right("footnotes")
+            .resizable(true)
+            .default_width(240.0)
+            .min_width(160.0)
+            .max_width(480.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong("Footnotes");
+                    ui.weak(notes.len().to_string());
+                    if ui.small_button("✕").on_hover_text("Close").clicked() {
+                        close = true;
+                    }
+                });
+                if notes.is_empty() {
+                    ui.weak("This document has no footnotes");
+                    return;
+                }
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for note in &notes {
+                        let on_screen = note.is_referenced_within(first_visible, last_visible);
+                        let fill = if on_screen {
+                            ui.visuals().selection.bg_fill.gamma_multiply(0.3)
+                        } else {
+                            egui::Color32::TRANSPARENT
+                        };
+                        egui::Frame::new()
+                            .fill(fill)
+                            .inner_margin(4)
+                            .corner_radius(4)
+                            .show(ui, |ui| {
+                                ui.set_width(ui.available_width());
+                                let text =
+                                    egui::RichText::new(format!("[^{}]", note.label)).strong();
+                                let label =
+                                    ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                                let body = ui.add(
+                                    egui::Label::new(&note.text)
+                                        .wrap()
+                                        .sense(egui::Sense::click()),
+                                );
+                                let definition = label.union(body).on_hover_text(format!(
+                                    "Go to the definition, line {}",
+                                    note.line
+                                ));
+                                #[cfg(feature = "mcp")]
+                                widget_data.push((
+                                    format!("Footnote: {}", note.label),
+                                    "button",
+                                    definition.rect,
+                                    Some(on_screen.to_string()),
+                                ));
+                                if definition.clicked() {
+                                    jump_to = Some(note.line);
+                                }
+                                ui.horizontal_wrapped(|ui| {
+                                    for (i, &line) in note.references.iter().enumerate() {
+                                        let back =
+                                            ui.small_button(format!("↩ {}", i + 1)).on_hover_text(
+                                                format!("Go to the reference, line {line}"),
+                                            );
+                                        if back.clicked() {
+                                            jump_to = Some(line);
+                                        }
+                                    }
+                                });
+                            });
+                    }
+                });
+            });
+
+        // Register all collected widgets with MCP bridge
+        #[cfg(feature = "mcp")]
+        for (name, widget_type, rect, value) in widget_data {
+            self.mcp_bridge
+                .register_widget_rect(&name, widget_type, rect, value.as_deref());
         }
 
-        // Poll for async GVFS directory scan completion
-        if self.file_explorer.pending_scan.is_some() {
-            if self.file_explorer.poll_pending_scan() {
-                log::info!("GVFS directory scan completed");
+        if close {
+            self.show_footnotes = false;
+        }
+        if let Some(line) = jump_to {
+            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                tab.pending_line = Some(line);
             }
-            ctx.request_repaint_after(Duration::from_millis(100));
         }
+    }
 
-        // Clean up expired flash effects and request repaints while animating
-        if !self.flashing_paths.is_empty() {
-            let flash_duration = Duration::from_millis(FLASH_DURATION_MS);
-            self.flashing_paths
-                .retain(|_, start_time| start_time.elapsed() < flash_duration);
+    /// The captioned figures and the tables of the active document, numbered
+    /// like a list of figures. The ones on screen are highlighted; clicking
+    /// one jumps to it.
+    fn render_figures(&mut self, ctx: &egui::Context) {
+        if !self.show_figures {
+            return;
+        }
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+        let content = &tab.content;
+        let found = tab
+            .figures
+            .get_or_insert_with(|| figures::find_figures(content))
+            .clone();
+        let first_visible = tab.line_at_offset(tab.scroll_offset);
+        let last_visible = tab.line_at_offset(tab.scroll_offset + tab.last_viewport_height);
 
-            // Request repaints while there are active flashes
-            if !self.flashing_paths.is_empty() {
-                ctx.request_repaint_after(Duration::from_millis(16)); // ~60fps for smooth animation
+        // Collect widget data for MCP registration (name, widget_type, rect, value)
+        #[cfg(feature = "mcp")]
+        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
+
+        let mut close = false;
+        let mut jump_to = None;
+        egui::SidePanel::right("figures")
+            .resizable(true)
+            .default_width(240.0)
+            .min_width(160.0)
+            .max_width(480.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong("Figures and Tables");
+                    ui.weak(found.len().to_string());
+                    if ui.small_button("✕").on_hover_text("Close").clicked() {
+                        close = true;
+                    }
+                });
+                if found.is_empty() {
+                    ui.weak("This document has no captioned figures or tables");
+                    return;
+                }
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for kind in [figures::Kind::Figure, figures::Kind::Table] {
+                        let mut entries = found.iter().filter(|f| f.kind == kind).peekable();
+                        if entries.peek().is_none() {
+                            continue;
+                        }
+                        ui.add_space(4.0);
+                        ui.weak(format!("{}s", kind.name()));
+                        for figure in entries {
+                            let on_screen = (first_visible..=last_visible).contains(&figure.line);
+                            let text =
+                                format!("{} {}  {}", kind.name(), figure.number, figure.caption);
+                            let response = ui
+                                .add(
+                                    egui::Button::selectable(on_screen, text)
+                                        .wrap_mode(egui::TextWrapMode::Wrap),
+                                )
+                                .on_hover_text(format!("Go to line {}", figure.line));
+                            #[cfg(feature = "mcp")]
+                            widget_data.push((
+                                format!("{}: {}", kind.name(), figure.number),
+                                "button",
+                                response.rect,
+                                Some(on_screen.to_string()),
+                            ));
+                            if response.clicked() {
+                                jump_to = Some(figure.line);
+                            }
+                        }
+                    }
+                });
+            });
+
+        // Register all collected widgets with MCP bridge
+        #[cfg(feature = "mcp")]
+        for (name, widget_type, rect, value) in widget_data {
+            self.mcp_bridge
+                .register_widget_rect(&name, widget_type, rect, value.as_deref());
+        }
+
+        if close {
+            self.show_figures = false;
+        }
+        if let Some(line) = jump_to {
+            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                tab.pending_line = Some(line);
             }
         }
+    }
 
-        // Limit frame rate on virtual displays (e.g., Xvfb) which lack vsync
-        // request_repaint_after alone doesn't limit actual frame rate, so we sleep
-        // This prevents 500%+ CPU usage during E2E testing
-        if self.is_virtual_display {
-            std::thread::sleep(Duration::from_millis(16)); // ~60 FPS cap
+    fn close_active_tab(&mut self) {
+        self.close_tab(self.active_tab);
+    }
+
+    fn next_tab(&mut self) {
+        if self.tabs.len() > 1 {
+            self.active_tab = (self.active_tab + 1) % self.tabs.len();
+            self.title_dirty = true;
         }
+    }
 
-        // Apply theme settings only when dark_mode changes
-        if self.last_applied_dark_mode != Some(self.dark_mode) {
-            self.last_applied_dark_mode = Some(self.dark_mode);
-            let visuals = if self.dark_mode {
-                let mut v = egui::Visuals::dark();
-                v.panel_fill = egui::Color32::from_rgb(0x12, 0x12, 0x12);
-                v.window_fill = egui::Color32::from_rgb(0x12, 0x12, 0x12);
-                v.extreme_bg_color = egui::Color32::from_rgb(0x1E, 0x1E, 0x1E);
-                v.override_text_color = Some(egui::Color32::from_rgb(0xE0, 0xE0, 0xE0));
-                v
+    fn prev_tab(&mut self) {
+        if self.tabs.len() > 1 {
+            self.active_tab = if self.active_tab == 0 {
+                self.tabs.len() - 1
             } else {
-                let mut v = egui::Visuals::light();
-                v.panel_fill = egui::Color32::from_rgb(0xF8, 0xF8, 0xF8);
-                v.window_fill = egui::Color32::from_rgb(0xF8, 0xF8, 0xF8);
-                v.extreme_bg_color = egui::Color32::from_rgb(0xF0, 0xF0, 0xF0);
-                v.override_text_color = Some(egui::Color32::from_rgb(0x33, 0x33, 0x33));
-                v
+                self.active_tab - 1
             };
-            ctx.set_visuals(visuals);
+            self.title_dirty = true;
         }
+    }
 
-        ctx.set_zoom_factor(self.zoom_level);
-
-        // Update window title only when dirty
-        if self.title_dirty {
-            self.title_dirty = false;
-            let title = self.window_title();
-            if title != self.last_window_title {
-                self.last_window_title = title;
-                ctx.send_viewport_cmd(egui::ViewportCommand::Title(self.last_window_title.clone()));
-            }
+    fn focus_tab(&mut self, idx: usize) {
+        if idx < self.tabs.len() {
+            self.active_tab = idx;
+            self.title_dirty = true;
         }
+    }
 
-        // Handle keyboard shortcuts (suppressed when lightbox is open)
-        let mut open_dialog = false;
-        let mut toggle_watch = false;
-        let mut toggle_dark = false;
-        let mut toggle_outline = false;
-        let mut toggle_explorer = false;
-        let mut quit_app = false;
-        let mut zoom_delta: f32 = 0.0;
-        let mut go_back = false;
-        let mut go_forward = false;
-        let mut close_tab = false;
-        let mut new_tab = false;
-        let mut next_tab = false;
-        let mut prev_tab = false;
-        let mut focus_tab: Option<usize> = None;
-        let mut open_search = false;
-        let mut next_match = false;
-        let mut prev_match = false;
-        let mut close_search_kb = false;
-        let mut keyboard_scroll_action: Option<KeyboardScrollAction> = None;
-
-        // Ctrl+/- zoom: applies to lightbox when open, document otherwise
-        ctx.input(|i| {
-            if i.modifiers.ctrl
-                && (i.key_pressed(egui::Key::Plus) || i.key_pressed(egui::Key::Equals))
-            {
-                zoom_delta = 0.1;
-            }
-            if i.modifiers.ctrl && i.key_pressed(egui::Key::Minus) {
-                zoom_delta = -0.1;
-            }
-            if i.modifiers.ctrl && i.key_pressed(egui::Key::Num0) {
-                if self.lightbox.is_some() {
-                    // Reset lightbox zoom
-                    zoom_delta = 0.0;
-                    if let Some(lb) = &mut self.lightbox {
-                        lb.zoom = 1.0;
-                    }
-                } else {
-                    zoom_delta = 1.0 - self.zoom_level;
-                }
-            }
-        });
-
-        // Apply zoom to lightbox or document
-        if zoom_delta != 0.0 {
-            if let Some(lb) = &mut self.lightbox {
-                let factor = if zoom_delta > 0.0 { 1.25 } else { 1.0 / 1.25 };
-                lb.zoom = (lb.zoom * factor).clamp(0.1, 10.0);
-            } else {
-                self.zoom_level = (self.zoom_level + zoom_delta).clamp(0.5, 3.0);
-            }
-        }
+    /// The open tabs and the outline of the active document as a tab list and
+    /// a tree in the accessibility tree, whether or not their panels are
+    /// shown or scrolled into view. Screen readers and the MCP bridge can
+    /// switch tabs and jump to headings with their Click action.
+    fn publish_jump_lists(&mut self, ctx: &egui::Context) {
+        use egui::accesskit::Role;
 
-        if self.lightbox.is_none() {
-            ctx.input(|i| {
-                // Ctrl+O: Open file
-                if i.modifiers.ctrl && !i.modifiers.shift && i.key_pressed(egui::Key::O) {
-                    open_dialog = true;
-                }
-                // Ctrl+Shift+O: Toggle outline
-                if i.modifiers.ctrl && i.modifiers.shift && i.key_pressed(egui::Key::O) {
-                    toggle_outline = true;
-                }
-                // Ctrl+Shift+E: Toggle file explorer
-                if i.modifiers.ctrl && i.modifiers.shift && i.key_pressed(egui::Key::E) {
-                    toggle_explorer = true;
-                }
-                // Ctrl+W: Close current tab
-                if i.modifiers.ctrl && i.key_pressed(egui::Key::W) {
-                    close_tab = true;
-                }
-                // Ctrl+T: New tab (open file dialog)
-                if i.modifiers.ctrl && i.key_pressed(egui::Key::T) {
-                    new_tab = true;
-                }
-                // Ctrl+Tab: Next tab
-                if i.modifiers.ctrl && !i.modifiers.shift && i.key_pressed(egui::Key::Tab) {
-                    next_tab = true;
-                }
-                // Ctrl+Shift+Tab: Previous tab
-                if i.modifiers.ctrl && i.modifiers.shift && i.key_pressed(egui::Key::Tab) {
-                    prev_tab = true;
-                }
-                // Ctrl+1-9: Focus tab by index
-                for (idx, key) in [
-                    egui::Key::Num1,
-                    egui::Key::Num2,
-                    egui::Key::Num3,
-                    egui::Key::Num4,
-                    egui::Key::Num5,
-                    egui::Key::Num6,
-                    egui::Key::Num7,
-                    egui::Key::Num8,
-                    egui::Key::Num9,
-                ]
-                .iter()
-                .enumerate()
-                {
-                    if i.modifiers.ctrl && i.key_pressed(*key) {
-                        focus_tab = Some(idx);
-                    }
-                }
-                // Alt+Left: Go back in history
-                if i.modifiers.alt && i.key_pressed(egui::Key::ArrowLeft) {
-                    go_back = true;
-                }
-                // Alt+Right: Go forward in history
-                if i.modifiers.alt && i.key_pressed(egui::Key::ArrowRight) {
-                    go_forward = true;
-                }
-                // Ctrl+D: Toggle dark mode
-                if i.modifiers.ctrl && i.key_pressed(egui::Key::D) {
-                    toggle_dark = true;
-                }
-                // Ctrl+Q: Quit
-                if i.modifiers.ctrl && i.key_pressed(egui::Key::Q) {
-                    quit_app = true;
-                }
-                // Ctrl + scroll wheel for zoom
-                if i.modifiers.ctrl && i.raw_scroll_delta.y != 0.0 {
-                    self.zoom_level = (self.zoom_level
-                        + if i.raw_scroll_delta.y > 0.0 {
-                            0.1
-                        } else {
-                            -0.1
-                        })
-                    .clamp(0.5, 3.0);
-                }
-                // F5: Toggle file watching
-                if i.key_pressed(egui::Key::F5) {
-                    toggle_watch = true;
-                }
-                // Ctrl+F: Open find bar (or refocus if already open)
-                if i.modifiers.ctrl && !i.modifiers.shift && i.key_pressed(egui::Key::F) {
-                    open_search = true;
+        let mut focus_tab = None;
+        let mut jump_to = None;
+        egui::Area::new(egui::Id::new("jump_lists"))
+            .fixed_pos(egui::Pos2::ZERO)
+            .interactable(false)
+            .show(ctx, |ui| {
+                // Nothing to build unless an assistive tool or the bridge is
+                // listening, when the area has a node of its own
+                if ctx.accesskit_node_builder(ui.unique_id(), |_| ()).is_none() {
+                    return;
                 }
-                // While the find bar is open, intercept Enter / Shift+Enter / ↑↓ / Esc.
-                // Up/Down are safe to bind even when the singleline TextEdit has focus
-                // because it doesn't use vertical arrows for cursor movement.
-                if self.search.is_open {
-                    if i.key_pressed(egui::Key::Enter) {
-                        if i.modifiers.shift {
-                            prev_match = true;
-                        } else {
-                            next_match = true;
+                let at = egui::Rect::from_min_size(ui.min_rect().min, egui::Vec2::ZERO);
+                ui.scope_builder(egui::UiBuilder::new().id_salt("tabs"), |ui| {
+                    ctx.accesskit_node_builder(ui.unique_id(), |node| {
+                        node.set_role(Role::TabList);
+                        node.set_label("Open tabs");
+                    });
+                    for (idx, tab) in self.tabs.iter().enumerate() {
+                        let id = ui.unique_id().with(idx);
+                        if ui.interact(at, id, egui::Sense::click()).clicked() {
+                            focus_tab = Some(idx);
                         }
+                        ctx.accesskit_node_builder(id, |node| {
+                            node.set_role(Role::Tab);
+                            node.set_label(tab.title());
+                            node.set_selected(idx == self.active_tab);
+                        });
                     }
-                    if i.key_pressed(egui::Key::ArrowDown) {
-                        next_match = true;
-                    }
-                    if i.key_pressed(egui::Key::ArrowUp) {
-                        prev_match = true;
-                    }
-                    if i.key_pressed(egui::Key::Escape) {
-                        close_search_kb = true;
-                    }
-                }
-                // Plain document scroll keys reuse the existing pending-scroll pipeline.
-                // Arrow keys stay available for search navigation while the find bar is open.
-                let no_scroll_modifier =
-                    !i.modifiers.ctrl && !i.modifiers.alt && !i.modifiers.command;
-                if no_scroll_modifier {
-                    if !self.search.is_open {
-                        if i.key_pressed(egui::Key::ArrowUp) {
-                            keyboard_scroll_action = Some(KeyboardScrollAction::LineUp);
-                        }
-                        if i.key_pressed(egui::Key::ArrowDown) {
-                            keyboard_scroll_action = Some(KeyboardScrollAction::LineDown);
+                });
+                let Some(tab) = self.tabs.get(self.active_tab) else {
+                    return;
+                };
+                ui.scope_builder(egui::UiBuilder::new().id_salt("outline"), |ui| {
+                    ctx.accesskit_node_builder(ui.unique_id(), |node| {
+                        node.set_role(Role::Tree);
+                        node.set_label(format!("Outline of {}", tab.title()));
+                    });
+                    for (idx, header) in tab.outline_headers.iter().enumerate() {
+                        let id = ui.unique_id().with(idx);
+                        if ui.interact(at, id, egui::Sense::click()).clicked() {
+                            jump_to = Some(idx);
                         }
+                        ctx.accesskit_node_builder(id, |node| {
+                            node.set_role(Role::TreeItem);
+                            node.set_label(header.title.as_str());
+                            node.set_level(header.level as usize);
+                        });
                     }
-                    if i.key_pressed(egui::Key::PageUp) {
-                        keyboard_scroll_action = Some(KeyboardScrollAction::PageUp);
-                    }
-                    if i.key_pressed(egui::Key::PageDown) {
-                        keyboard_scroll_action = Some(KeyboardScrollAction::PageDown);
-                    }
-                }
+                });
             });
-        } // end lightbox guard
 
-        if open_dialog || new_tab {
-            self.open_file_dialog();
-        }
-        if toggle_watch {
-            if self.watcher.is_some() {
-                self.stop_watching();
-                self.watch_enabled = false;
-            } else {
-                self.watch_enabled = true;
-                self.start_watching();
-            }
-        }
-        if toggle_dark {
-            self.dark_mode = !self.dark_mode;
-        }
-        if toggle_outline {
-            self.show_outline = !self.show_outline;
-        }
-        if toggle_explorer {
-            self.show_explorer = !self.show_explorer;
-        }
-        if quit_app {
-            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-        }
-        if close_tab {
-            self.close_active_tab();
-        }
-        if next_tab {
-            self.next_tab();
-        }
-        if prev_tab {
-            self.prev_tab();
-        }
         if let Some(idx) = focus_tab {
             self.focus_tab(idx);
         }
-        if go_back {
-            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                tab.navigate_back();
-            }
-        }
-        if go_forward {
-            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                tab.navigate_forward();
-            }
+        if let (Some(idx), Some(tab)) = (jump_to, self.tabs.get_mut(self.active_tab)) {
+            tab.scroll_to_outline_header(idx);
         }
+    }
 
-        // Search bar actions (Ctrl+F open, Enter/Shift+Enter cycle, Esc close)
-        if open_search {
-            self.search.is_open = true;
-            self.search.focus_requested = true;
-        }
-        if next_match {
-            self.jump_match(1);
-        }
-        if prev_match {
-            self.jump_match(-1);
-        }
-        if close_search_kb {
-            self.close_search();
-        }
-        if let Some(action) = keyboard_scroll_action {
-            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                let target = keyboard_scroll_target(
-                    tab.scroll_offset,
-                    tab.last_viewport_height,
-                    tab.last_content_height,
-                    action,
-                );
-                tab.pending_scroll_offset = Some(target);
+    /// Run a remote or automation command, returning what it did for the
+    /// client or the `Automation: Result` label.
+    fn run_automation_command(&mut self, command: AutomationCommand) -> Result<String, String> {
+        match command {
+            AutomationCommand::Open(path) => {
+                let url = path.to_string_lossy();
+                if reader::is_url(&url) {
+                    let url = url.into_owned();
+                    self.download_article(url.clone());
+                    return Ok(format!("downloading {url}"));
+                }
+                if !path.is_file() {
+                    return Err(format!("no such file: {}", path.display()));
+                }
+                self.open_in_new_tab(path);
+                Ok(format!("opened tab {}", self.active_tab + 1))
             }
-        }
-
-        // Handle drag and drop
-        self.is_dragging = false;
-        ctx.input(|i| {
-            if !i.raw.hovered_files.is_empty() {
-                self.is_dragging = true;
+            AutomationCommand::Tab(number) => {
+                if number > self.tabs.len() {
+                    return Err(format!("only {} tabs are open", self.tabs.len()));
+                }
+                self.focus_tab(number - 1);
+                Ok(format!("switched to tab {number}"))
             }
-
-            for file in &i.raw.dropped_files {
-                if let Some(path) = &file.path {
-                    if Self::is_markdown_file(path) {
-                        self.open_in_new_tab(path.clone());
-                    } else {
-                        self.error_message = Some(
-                            "Unsupported file type. Please drop a markdown file (.md, .markdown, .txt)".to_string(),
-                        );
-                    }
+            AutomationCommand::Heading(heading) => {
+                let tab = self
+                    .tabs
+                    .get_mut(self.active_tab)
+                    .ok_or("no document is open")?;
+                let anchor = heading
+                    .strip_prefix('#')
+                    .map(str::to_owned)
+                    .unwrap_or_else(|| heading_slug(&heading));
+                tab.cache.scroll_to_heading(tab.id, &anchor);
+                Ok(format!("scrolling to #{anchor}"))
+            }
+            AutomationCommand::Reload => {
+                let tab = self
+                    .tabs
+                    .get_mut(self.active_tab)
+                    .ok_or("no document is open")?;
+                tab.reload();
+                let path = tab.path.clone();
+                self.git_info.remove(&path);
+                Ok(format!("reloaded {}", path.display()))
+            }
+            AutomationCommand::Tabs => {
+                let tabs: Vec<String> = self
+                    .tabs
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, tab)| {
+                        let active = if idx == self.active_tab {
+                            " (active)"
+                        } else {
+                            ""
+                        };
+                        format!("{} {}{active}", idx + 1, tab.title())
+                    })
+                    .collect();
+                Ok(tabs.join("; "))
+            }
+            AutomationCommand::Outline => {
+                let tab = self
+                    .tabs
+                    .get(self.active_tab)
+                    .ok_or("no document is open")?;
+                let headings: Vec<String> = tab
+                    .outline_headers
+                    .iter()
+                    .map(|header| {
+                        format!("h{} {} #{}", header.level, header.title, header.anchor())
+                    })
+                    .collect();
+                Ok(headings.join("; "))
+            }
+            AutomationCommand::Export(format, path) => {
+                let tab = self
+                    .tabs
+                    .get(self.active_tab)
+                    .ok_or("no document is open")?;
+                let path = match path {
+                    Some(path) => path,
+                    None if format == "html" => tab.path.with_extension("html"),
+                    None => return Err(format!("`export {format}` needs a path")),
+                };
+                if path.canonicalize().is_ok_and(|p| p == tab.path) {
+                    return Err("won't overwrite the document itself".to_string());
                 }
+                let title = tab.document_title.clone().unwrap_or_else(|| tab.title());
+                let exported = export_document(&format, &title, &tab.content, self.export_toc())?;
+                fs::write(&path, exported)
+                    .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+                Ok(format!("exported {}", path.display()))
             }
-        });
-
-        // Get ctrl state for link handling
-        let ctrl_held = ctx.input(|i| i.modifiers.ctrl || i.modifiers.command);
+        }
+    }
 
-        // Menu bar
-        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
-            egui::MenuBar::new().ui(ui, |ui| {
-                ui.menu_button("File", |ui| {
-                    if ui
-                        .add(egui::Button::new("New Tab...").shortcut_text("Ctrl+T"))
-                        .clicked()
-                    {
-                        self.open_file_dialog();
-                        ui.close();
+    /// A small textbox E2E agents type [`AutomationCommand`]s into, with the
+    /// outcome of the last one beside it.
+    #[cfg(feature = "mcp")]
+    fn render_automation_input(&mut self, ctx: &egui::Context) {
+        egui::Area::new(egui::Id::new("automation_input"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let result = ui.small(&self.automation_result);
+                    self.mcp_bridge.register_widget(
+                        "Automation: Result",
+                        "label",
+                        &result,
+                        Some(&self.automation_result),
+                    );
+                    let input = ui.add(
+                        egui::TextEdit::singleline(&mut self.automation_input)
+                            .hint_text("Command")
+                            .desired_width(160.0),
+                    );
+                    self.mcp_bridge
+                        .register_widget("Automation: Command", "textbox", &input, None);
+                    if input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        let line = std::mem::take(&mut self.automation_input);
+                        self.automation_result = match AutomationCommand::parse(&line)
+                            .and_then(|command| self.run_automation_command(command))
+                        {
+                            Ok(message) => message,
+                            Err(error) => format!("error: {error}"),
+                        };
                     }
+                });
+            });
+    }
 
-                    if ui.button("Open Folder...").clicked() {
-                        self.open_folder_dialog();
-                        ui.close();
-                    }
+    fn get_open_tab_paths(&self) -> Vec<PathBuf> {
+        self.tabs
+            .iter()
+            .filter(|t| t.path.exists())
+            .map(|t| t.path.clone())
+            .collect()
+    }
 
-                    if ui
-                        .add(egui::Button::new("Close Tab").shortcut_text("Ctrl+W"))
-                        .clicked()
-                    {
-                        self.close_active_tab();
-                        ui.close();
-                    }
+    /// Rebuild the cached open_tab_paths set (call after tab open/close/navigate)
+    fn refresh_open_tab_paths(&mut self) {
+        self.open_tab_paths.clear();
+        for tab in &self.tabs {
+            self.open_tab_paths.insert(tab.path.clone());
+        }
+    }
 
-                    ui.separator();
+    fn start_watching(&mut self) {
+        self.stop_watching();
 
-                    if ui
-                        .add(egui::Button::new("Find...").shortcut_text("Ctrl+F"))
-                        .clicked()
-                    {
-                        self.search.is_open = true;
-                        self.search.focus_requested = true;
-                        ui.close();
-                    }
+        let tab_paths = self.get_open_tab_paths();
+        let explorer_root = self.file_explorer.root.clone();
 
-                    ui.separator();
+        // Need something to watch
+        if tab_paths.is_empty() && explorer_root.is_none() {
+            return;
+        }
 
-                    let is_watching = self.watcher.is_some();
-                    let watch_text = if is_watching {
-                        "✓ Watch Files"
-                    } else {
-                        "Watch Files"
-                    };
-                    if ui
-                        .add(egui::Button::new(watch_text).shortcut_text("F5"))
-                        .clicked()
-                    {
-                        if is_watching {
-                            self.stop_watching();
-                            self.watch_enabled = false;
+        // Re-read the mount table, a share may have been mounted since, and
+        // give inotify another chance
+        self.poll_policy.mounts = read_mounts();
+        self.poll_policy.inotify_unavailable = false;
+
+        // Non-recursive: mirror the lazy explorer tree. Watching the whole root
+        // subtree recursively walks every directory on the main thread at startup
+        // (~6s hang on /home/ahmet, ~455k dirs). We only need change events for
+        // the directories the user can actually see: the root plus each expanded
+        // directory. GVFS explorer roots stay unwatched (costly over SFTP).
+        let mut explorer_dirs: Vec<PathBuf> = Vec::new();
+        if let Some(ref root) = explorer_root {
+            if !is_gvfs_path(root) {
+                explorer_dirs.push(root.clone());
+                explorer_dirs.extend(
+                    self.file_explorer
+                        .expanded_dirs
+                        .iter()
+                        .filter(|d| !is_gvfs_path(d) && d.is_dir())
+                        .cloned(),
+                );
+            }
+        }
+
+        // Both debouncers send to the same channel
+        let (tx, debouncer_rx) = mpsc::channel();
+
+        // Create inotify debouncer for local paths. Where inotify can't be
+        // started, or runs out of watches, those paths are polled instead.
+        let wants_inotify = tab_paths
+            .iter()
+            .chain(&explorer_dirs)
+            .any(|p| !self.poll_policy.polls(p));
+        let inotify_debouncer = if wants_inotify {
+            match new_debouncer(self.reload_policy.debounce(), tx.clone()) {
+                Ok(mut debouncer) => {
+                    let local_paths: Vec<&PathBuf> = tab_paths
+                        .iter()
+                        .filter(|p| !self.poll_policy.polls(p))
+                        .collect();
+                    for path in &local_paths {
+                        if let Err(e) = debouncer
+                            .watcher()
+                            .watch(path, notify::RecursiveMode::NonRecursive)
+                        {
+                            log::warn!("Failed to watch file {:?}, polling it: {}", path, e);
+                            self.poll_policy.fallback.insert((*path).clone());
                         } else {
-                            self.watch_enabled = true;
-                            self.start_watching();
+                            self.watched_paths.insert((*path).clone());
+                            if let Some(id) = file_identity(path) {
+                                self.watched_file_ids.insert((*path).clone(), id);
+                            }
                         }
-                        ui.close();
                     }
-
-                    ui.separator();
-
-                    if ui
-                        .add(egui::Button::new("Quit").shortcut_text("Ctrl+Q"))
-                        .clicked()
-                    {
-                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                        ui.close();
+                    for dir in tab_dirs(&local_paths) {
+                        if let Err(e) = debouncer
+                            .watcher()
+                            .watch(&dir, notify::RecursiveMode::NonRecursive)
+                        {
+                            log::error!("Failed to watch directory {:?}: {}", dir, e);
+                        } else {
+                            self.watched_tab_dirs.insert(dir);
+                        }
                     }
-                });
-
-                ui.menu_button("Navigate", |ui| {
-                    let can_back = self
-                        .tabs
-                        .get(self.active_tab)
-                        .map(|t| t.can_go_back())
-                        .unwrap_or(false);
-                    if ui
-                        .add_enabled(can_back, egui::Button::new("← Back").shortcut_text("Alt+←"))
-                        .clicked()
-                    {
-                        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                            tab.navigate_back();
+                    let local_dirs: Vec<&PathBuf> = explorer_dirs
+                        .iter()
+                        .filter(|d| !self.poll_policy.polls(d))
+                        .collect();
+                    for dir in local_dirs {
+                        if let Err(e) = debouncer
+                            .watcher()
+                            .watch(dir, notify::RecursiveMode::NonRecursive)
+                        {
+                            log::warn!("Failed to watch explorer dir {:?}, polling it: {}", dir, e);
+                            self.poll_policy.fallback.insert(dir.clone());
+                        } else {
+                            self.watched_explorer_dirs.insert(dir.clone());
                         }
-                        ui.close();
                     }
+                    Some(debouncer)
+                }
+                Err(e) => {
+                    log::warn!("Failed to create inotify watcher, polling instead: {}", e);
+                    self.poll_policy.inotify_unavailable = true;
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
-                    let can_forward = self
-                        .tabs
-                        .get(self.active_tab)
-                        .map(|t| t.can_go_forward())
-                        .unwrap_or(false);
-                    if ui
-                        .add_enabled(
-                            can_forward,
-                            egui::Button::new("→ Forward").shortcut_text("Alt+→"),
-                        )
-                        .clicked()
-                    {
-                        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                            tab.navigate_forward();
+        // Create poll debouncer for GVFS/remote paths and inotify's leftovers
+        let poll_paths: Vec<&PathBuf> = tab_paths
+            .iter()
+            .filter(|p| self.poll_policy.polls(p))
+            .collect();
+        let poll_dirs: Vec<&PathBuf> = explorer_dirs
+            .iter()
+            .filter(|d| self.poll_policy.polls(d))
+            .collect();
+        let poll_debouncer = if !poll_paths.is_empty() || !poll_dirs.is_empty() {
+            let poll_config = notify_debouncer_mini::Config::default()
+                .with_timeout(self.reload_policy.debounce())
+                .with_notify_config(
+                    notify::Config::default()
+                        .with_poll_interval(Duration::from_secs(self.poll_policy.interval_secs)),
+                );
+            match new_debouncer_opt::<_, PollWatcher>(poll_config, tx.clone()) {
+                Ok(mut debouncer) => {
+                    for path in &poll_paths {
+                        if let Err(e) = debouncer
+                            .watcher()
+                            .watch(path, notify::RecursiveMode::NonRecursive)
+                        {
+                            log::error!("Failed to poll-watch file {:?}: {}", path, e);
+                        } else {
+                            self.watched_paths.insert((*path).clone());
                         }
-                        ui.close();
                     }
-                });
-
-                #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
-                let view_menu = ui.menu_button("View", |ui| {
-                    let theme_text = if self.dark_mode {
-                        "☀ Light Mode"
-                    } else {
-                        "🌙 Dark Mode"
-                    };
-                    let theme_btn = ui.add(egui::Button::new(theme_text).shortcut_text("Ctrl+D"));
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Menu: View → Dark Mode",
-                        "button",
-                        &theme_btn,
-                        Some(if self.dark_mode { "dark" } else { "light" }),
-                    );
-                    if theme_btn.clicked() {
-                        self.dark_mode = !self.dark_mode;
-                        ui.close();
+                    // GVFS explorer roots never get here — polling an entire
+                    // remote directory tree every 2s causes lag from SFTP
+                    // roundtrips (stat + read_dir for each entry). Users can refresh
+                    // the explorer manually instead.
+                    for dir in &poll_dirs {
+                        if let Err(e) = debouncer
+                            .watcher()
+                            .watch(dir, notify::RecursiveMode::NonRecursive)
+                        {
+                            log::error!("Failed to poll-watch explorer dir {:?}: {}", dir, e);
+                        } else {
+                            self.watched_explorer_dirs.insert((*dir).clone());
+                        }
                     }
+                    Some(debouncer)
+                }
+                Err(e) => {
+                    log::error!("Failed to create poll watcher: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
-                    let explorer_text = if self.show_explorer {
-                        "✓ Show Explorer"
-                    } else {
-                        "Show Explorer"
-                    };
-                    let explorer_btn =
-                        ui.add(egui::Button::new(explorer_text).shortcut_text("Ctrl+Shift+E"));
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Menu: View → Show Explorer",
-                        "button",
-                        &explorer_btn,
-                        Some(if self.show_explorer { "on" } else { "off" }),
-                    );
-                    if explorer_btn.clicked() {
-                        self.show_explorer = !self.show_explorer;
-                        ui.close();
-                    }
+        // Build FileWatcher enum from whichever debouncers succeeded
+        let file_watcher = match (inotify_debouncer, poll_debouncer) {
+            (Some(i), Some(p)) => Some(FileWatcher::Dual {
+                inotify: i,
+                poll: p,
+            }),
+            (Some(d), None) => Some(FileWatcher::Inotify(d)),
+            (None, Some(d)) => Some(FileWatcher::Poll(d)),
+            (None, None) => None,
+        };
 
-                    let outline_text = if self.show_outline {
-                        "✓ Show Outline"
-                    } else {
-                        "Show Outline"
-                    };
-                    let outline_btn =
-                        ui.add(egui::Button::new(outline_text).shortcut_text("Ctrl+Shift+O"));
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Menu: View → Show Outline",
-                        "button",
-                        &outline_btn,
-                        Some(if self.show_outline { "on" } else { "off" }),
-                    );
-                    if outline_btn.clicked() {
-                        self.show_outline = !self.show_outline;
-                        ui.close();
-                    }
+        if let Some(fw) = file_watcher {
+            log::info!(
+                "Started watching {} local (inotify) + {} polled files, {} explorer dirs",
+                tab_paths.len() - poll_paths.len(),
+                poll_paths.len(),
+                self.watched_explorer_dirs.len()
+            );
 
-                    let full_width_text = if self.full_width_content {
-                        "✓ Full Width"
-                    } else {
-                        "Full Width"
-                    };
-                    let full_width_btn = ui.add(egui::Button::new(full_width_text));
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Menu: View → Full Width",
-                        "button",
-                        &full_width_btn,
-                        Some(if self.full_width_content { "on" } else { "off" }),
-                    );
-                    if full_width_btn.clicked() {
-                        self.full_width_content = !self.full_width_content;
-                        ui.close();
+            // Bridge thread: forward events and wake egui on demand
+            let (bridge_tx, bridge_rx) = mpsc::channel();
+            let ctx = self.egui_ctx.clone();
+            std::thread::Builder::new()
+                .name("watcher-bridge".into())
+                .spawn(move || {
+                    while let Ok(event) = debouncer_rx.recv() {
+                        let _ = bridge_tx.send(event);
+                        ctx.request_repaint();
                     }
+                })
+                .expect("failed to spawn watcher bridge thread");
 
-                    ui.separator();
+            self.watcher = Some(fw);
+            self.watcher_rx = Some(bridge_rx);
+            self.watch_enabled = true;
+            self.watcher_retry_count = 0;
+        } else {
+            log::error!("Failed to create any file watcher");
+            self.error_message = Some("Failed to create file watcher".to_string());
+        }
+    }
 
-                    let zoom_in_btn = ui.add(egui::Button::new("Zoom In").shortcut_text("Ctrl++"));
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Menu: View → Zoom In",
-                        "button",
-                        &zoom_in_btn,
-                        None,
-                    );
-                    if zoom_in_btn.clicked() {
-                        self.zoom_level = (self.zoom_level + 0.1).min(3.0);
-                        ui.close();
+    /// Whether `tab` is shown sandboxed: text from the clipboard, or a file
+    /// outside the trusted folders, when untrusted documents are restricted.
+    fn is_sandboxed(&self, tab: &Tab) -> bool {
+        self.restrict_untrusted
+            && (tab.unsaved.is_some() || !is_trusted(&tab.path, &self.trusted_folders))
+    }
+
+    /// Trust the documents in `folder` and the folders below it.
+    fn trust_folder(&mut self, folder: PathBuf) {
+        let folder = folder.canonicalize().unwrap_or(folder);
+        if !is_trusted(&folder, &self.trusted_folders) {
+            self.trusted_folders
+                .retain(|trusted| !trusted.starts_with(&folder));
+            self.trusted_folders.push(folder);
+            self.trusted_folders.sort();
+        }
+    }
+
+    /// Turn live reload off. Paths that fell back to polling get another go
+    /// with inotify when it's turned back on.
+    fn disable_watching(&mut self) {
+        self.stop_watching();
+        self.watch_enabled = false;
+        self.poll_policy.fallback.clear();
+    }
+
+    fn stop_watching(&mut self) {
+        if self.watcher.is_some() {
+            log::info!("Stopped watching files");
+        }
+        self.watcher = None;
+        self.watcher_rx = None;
+        self.watched_paths.clear();
+        self.watched_file_ids.clear();
+        self.watched_tab_dirs.clear();
+        self.watched_explorer_dirs.clear();
+    }
+
+    fn update_watched_paths(&mut self) {
+        if !self.watch_enabled {
+            return;
+        }
+
+        let current_paths: HashSet<PathBuf> = self.get_open_tab_paths().into_iter().collect();
+
+        if let Some(fw) = &mut self.watcher {
+            // Check if we need a watcher type that doesn't currently exist
+            let needs_poll = current_paths.iter().any(|p| self.poll_policy.polls(p));
+            let needs_inotify = current_paths.iter().any(|p| !self.poll_policy.polls(p));
+            let has_poll = fw.poll_watcher().is_some();
+            let has_inotify = fw.inotify_watcher().is_some();
+
+            if (needs_poll && !has_poll) || (needs_inotify && !has_inotify) {
+                // Watcher configuration changed (e.g., first GVFS tab added), restart
+                log::info!("Watcher type mismatch, restarting watchers");
+                self.start_watching();
+                return;
+            }
+
+            // Add new paths to the appropriate watcher
+            let mut restart = false;
+            for path in current_paths.difference(&self.watched_paths) {
+                let polled = self.poll_policy.polls(path);
+                let watcher = if polled {
+                    fw.poll_watcher()
+                } else {
+                    fw.inotify_watcher()
+                };
+                if let Some(w) = watcher {
+                    if let Err(e) = w.watch(path, notify::RecursiveMode::NonRecursive) {
+                        if polled {
+                            log::error!("Failed to watch file {:?}: {}", path, e);
+                        } else {
+                            // Most likely out of inotify watches
+                            log::warn!("Failed to watch file {:?}, polling it: {}", path, e);
+                            self.poll_policy.fallback.insert(path.clone());
+                            restart |= !has_poll;
+                            if let Some(w) = fw.poll_watcher() {
+                                let _ = w.watch(path, notify::RecursiveMode::NonRecursive);
+                            }
+                        }
+                    } else if let Some(id) = file_identity(path).filter(|_| !polled) {
+                        self.watched_file_ids.insert(path.clone(), id);
                     }
-                    let zoom_out_btn =
-                        ui.add(egui::Button::new("Zoom Out").shortcut_text("Ctrl+-"));
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Menu: View → Zoom Out",
-                        "button",
-                        &zoom_out_btn,
-                        None,
-                    );
-                    if zoom_out_btn.clicked() {
-                        self.zoom_level = (self.zoom_level - 0.1).max(0.5);
-                        ui.close();
+                }
+            }
+            if restart {
+                self.start_watching();
+                return;
+            }
+
+            // Remove old paths from the appropriate watcher
+            for path in self.watched_paths.difference(&current_paths) {
+                let watcher = if self.poll_policy.polls(path) {
+                    fw.poll_watcher()
+                } else {
+                    fw.inotify_watcher()
+                };
+                if let Some(w) = watcher {
+                    let _ = w.unwatch(path);
+                }
+                self.watched_file_ids.remove(path);
+                self.poll_policy.fallback.remove(path);
+            }
+
+            // Watch the directories of local tabs, leaving the ones the
+            // explorer also watches in place
+            let local: Vec<&PathBuf> = current_paths
+                .iter()
+                .filter(|p| !self.poll_policy.polls(p))
+                .collect();
+            let desired_dirs = tab_dirs(&local);
+            if let Some(w) = fw.inotify_watcher() {
+                let explorer_dirs = &self.watched_explorer_dirs;
+                self.watched_tab_dirs.retain(|dir| {
+                    if desired_dirs.contains(dir) {
+                        return true;
                     }
-                    let reset_zoom_btn =
-                        ui.add(egui::Button::new("Reset Zoom").shortcut_text("Ctrl+0"));
-                    #[cfg(feature = "mcp")]
-                    self.mcp_bridge.register_widget(
-                        "Menu: View → Reset Zoom",
-                        "button",
-                        &reset_zoom_btn,
-                        None,
-                    );
-                    if reset_zoom_btn.clicked() {
-                        self.zoom_level = 1.0;
-                        ui.close();
+                    if !explorer_dirs.contains(dir) {
+                        let _ = w.unwatch(dir);
                     }
+                    false
                 });
-                #[cfg(feature = "mcp")]
-                self.mcp_bridge
-                    .register_widget("Menu: View", "button", &view_menu.response, None);
-
-                // Navigation buttons (visible arrows for back/forward)
-                ui.separator();
-                let can_back = self
-                    .tabs
-                    .get(self.active_tab)
-                    .map(|t| t.can_go_back())
-                    .unwrap_or(false);
-                let back_btn = ui.add_enabled(can_back, egui::Button::new("◀").small());
-                #[cfg(feature = "mcp")]
-                self.mcp_bridge
-                    .register_widget("Navigate Back", "button", &back_btn, None);
-                if back_btn.on_hover_text("Back (Alt+←)").clicked() {
-                    go_back = true;
+                // Only directories watched successfully count as watched, so
+                // the ones that failed are retried on the next update
+                for dir in desired_dirs {
+                    if self.watched_tab_dirs.contains(&dir) {
+                        continue;
+                    }
+                    if let Err(e) = w.watch(&dir, notify::RecursiveMode::NonRecursive) {
+                        log::error!("Failed to watch directory {:?}: {}", dir, e);
+                    } else {
+                        self.watched_tab_dirs.insert(dir);
+                    }
                 }
+            } else {
+                self.watched_tab_dirs.clear();
+            }
+        }
 
-                let can_forward = self
-                    .tabs
-                    .get(self.active_tab)
-                    .map(|t| t.can_go_forward())
-                    .unwrap_or(false);
-                let forward_btn = ui.add_enabled(can_forward, egui::Button::new("▶").small());
-                #[cfg(feature = "mcp")]
-                self.mcp_bridge
-                    .register_widget("Navigate Forward", "button", &forward_btn, None);
-                if forward_btn.on_hover_text("Forward (Alt+→)").clicked() {
-                    go_forward = true;
+        self.watched_paths = current_paths;
+    }
+
+    /// Re-watch tab files whose inode changed since they were watched, i.e.
+    /// that were replaced by an atomic save. The old watch followed the
+    /// replaced inode and would miss every later save.
+    fn rewatch_replaced_files(&mut self) {
+        let Some(w) = self.watcher.as_mut().and_then(|fw| fw.inotify_watcher()) else {
+            return;
+        };
+        for (path, id) in &mut self.watched_file_ids {
+            let Some(current) = file_identity(path).filter(|current| current != id) else {
+                continue;
+            };
+            let _ = w.unwatch(path);
+            match w.watch(path, notify::RecursiveMode::NonRecursive) {
+                Ok(()) => log::debug!("Re-watching replaced file {:?}", path),
+                Err(e) => log::error!("Failed to re-watch file {:?}: {}", path, e),
+            }
+            *id = current;
+        }
+    }
+
+    /// Reconcile the non-recursive explorer-directory watches (root + expanded
+    /// dirs) against the live watcher after the expanded set changes. Mirrors
+    /// `update_watched_paths`'s incremental diff so expand/collapse doesn't tear
+    /// down and rebuild the debouncer + bridge thread.
+    fn reconcile_explorer_watches(&mut self) {
+        if !self.watch_enabled {
+            return;
+        }
+
+        // Desired set: root + each expanded dir (local, still existing).
+        let mut desired: HashSet<PathBuf> = HashSet::new();
+        if let Some(root) = &self.file_explorer.root {
+            if !is_gvfs_path(root) {
+                desired.insert(root.clone());
+            }
+        }
+        for d in &self.file_explorer.expanded_dirs {
+            if !is_gvfs_path(d) && d.is_dir() {
+                desired.insert(d.clone());
+            }
+        }
+
+        let mut restart = false;
+        if let Some(fw) = &mut self.watcher {
+            for path in desired.difference(&self.watched_explorer_dirs) {
+                let watcher = if self.poll_policy.polls(path) {
+                    fw.poll_watcher()
+                } else {
+                    fw.inotify_watcher()
+                };
+                match watcher {
+                    Some(w) => {
+                        if let Err(e) = w.watch(path, notify::RecursiveMode::NonRecursive) {
+                            log::error!("Failed to watch explorer dir {:?}: {}", path, e);
+                        }
+                    }
+                    // First directory on a network share
+                    None => restart = true,
                 }
+            }
+            for path in self.watched_explorer_dirs.difference(&desired) {
+                if self.watched_tab_dirs.contains(path) {
+                    continue;
+                }
+                let watcher = if self.poll_policy.polls(path) {
+                    fw.poll_watcher()
+                } else {
+                    fw.inotify_watcher()
+                };
+                if let Some(w) = watcher {
+                    let _ = w.unwatch(path);
+                }
+            }
+        }
 
-                // Show status on the right
-                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    // Show zoom level if not at 100%
-                    if (self.zoom_level - 1.0).abs() > 0.01 {
-                        ui.label(
-                            egui::RichText::new(format!(
-                                "{}%",
-                                (self.zoom_level * 100.0).round() as i32
-                            ))
-                            .small()
-                            .color(ui.visuals().weak_text_color()),
-                        );
-                        ui.separator();
+        self.watched_explorer_dirs = desired;
+        if restart {
+            self.start_watching();
+        }
+    }
+
+    fn check_file_changes(&mut self) -> Vec<PathBuf> {
+        let Some(rx) = &self.watcher_rx else {
+            // Attempt recovery if watching is enabled and there's something to watch
+            // Check actual tabs and explorer root, not watched_paths (which may be empty after failure)
+            let has_watchable = !self.tabs.is_empty() || self.file_explorer.root.is_some();
+            if self.watch_enabled && has_watchable && self.watcher_retry_count < MAX_WATCHER_RETRIES
+            {
+                log::info!(
+                    "Attempting to recover file watcher (attempt {})",
+                    self.watcher_retry_count + 1
+                );
+                self.watcher_retry_count += 1;
+                self.start_watching();
+                self.egui_ctx.request_repaint_after(Duration::from_secs(2));
+            }
+            return Vec::new();
+        };
+
+        let mut changed_paths = Vec::new();
+
+        while let Ok(result) = rx.try_recv() {
+            match result {
+                Ok(events) => {
+                    self.watcher_retry_count = 0;
+                    for event in events {
+                        if event.kind != DebouncedEventKind::Any {
+                            continue;
+                        }
+                        // Tab directories are only watched for their tab
+                        // files; skip the editor's swap and backup files
+                        let only_tab_dir = event.path.parent().is_some_and(|dir| {
+                            self.watched_tab_dirs.contains(dir)
+                                && !self.watched_explorer_dirs.contains(dir)
+                        });
+                        if only_tab_dir && !self.watched_paths.contains(&event.path) {
+                            continue;
+                        }
+                        log::debug!("File change detected: {:?}", event.path);
+                        changed_paths.push(event.path);
                     }
+                }
+                Err(e) => {
+                    log::error!("File watcher error: {}", e);
+                    self.watcher = None;
+                    self.watcher_rx = None;
 
-                    if self.watcher.is_some() {
-                        ui.label(
-                            egui::RichText::new("● LIVE")
-                                .color(egui::Color32::from_rgb(100, 200, 100)),
+                    if self.watcher_retry_count < MAX_WATCHER_RETRIES {
+                        self.watcher_retry_count += 1;
+                        log::info!(
+                            "Attempting watcher recovery (attempt {})",
+                            self.watcher_retry_count
                         );
-                        ui.separator();
+                        self.start_watching();
+                        self.egui_ctx.request_repaint_after(Duration::from_secs(2));
+                    } else {
+                        self.error_message = Some(format!(
+                            "File watcher failed after {} retries: {}",
+                            MAX_WATCHER_RETRIES, e
+                        ));
+                        self.watch_enabled = false;
                     }
+                    return Vec::new();
+                }
+            }
+        }
 
-                    // Show current file path from active tab
-                    if let Some(tab) = self.tabs.get(self.active_tab) {
-                        if tab.path.exists() {
-                            ui.add(
-                                egui::Label::new(
-                                    egui::RichText::new(tab.path.display().to_string())
-                                        .small()
-                                        .color(ui.visuals().weak_text_color()),
-                                )
-                                .truncate(),
-                            );
-                        }
+        if !changed_paths.is_empty() {
+            self.rewatch_replaced_files();
+        }
+        changed_paths
+    }
+
+    fn reload_changed_tabs(&mut self, changed_paths: Vec<PathBuf>) {
+        let now = Instant::now();
+        let mut refresh_tree = false;
+        // If the active tab gets reloaded while the find bar is open, its
+        // `search_matches` will be cleared by `Tab::reload`. Force a rebuild
+        // on the next frame by invalidating the cache-validity shadow state.
+        let active_path = self.tabs.get(self.active_tab).map(|t| t.path.clone());
+
+        for path in changed_paths {
+            // Trigger flash effect for the changed file (use canonical path for consistent lookup)
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            // Its "modified" badge and its tasks may have changed
+            self.git_info.remove(&canonical);
+            self.refresh_file_tasks(&path);
+            self.refresh_file_links(&path);
+            self.flashing_paths.insert(canonical.clone(), now);
+
+            // Also flash parent directories up to the explorer root
+            if let Some(root) = &self.file_explorer.root {
+                // Check if the changed path is within the explorer root
+                if path.starts_with(root) {
+                    refresh_tree = true;
+                }
+
+                let mut current = path.parent();
+                while let Some(parent) = current {
+                    if parent.starts_with(root) || parent == root {
+                        self.flashing_paths.insert(
+                            parent
+                                .canonicalize()
+                                .unwrap_or_else(|_| parent.to_path_buf()),
+                            now,
+                        );
+                    }
+                    if parent == root {
+                        break;
+                    }
+                    current = parent.parent();
+                }
+            }
+
+            // Reload the tab content
+            let mut active_was_reloaded = false;
+            for tab in &mut self.tabs {
+                if tab.path == canonical {
+                    log::info!("Reloading tab: {:?}", path);
+                    tab.reload();
+                    if Some(&tab.path) == active_path.as_ref() {
+                        active_was_reloaded = true;
+                    }
+                }
+            }
+            if active_was_reloaded && self.search.is_open {
+                // Invalidate maybe_rebuild_search's "cached for" shadow
+                self.search.last_tab = None;
+            }
+        }
+
+        // Refresh the file explorer tree if any changes were within the explorer root
+        if refresh_tree {
+            log::info!("Refreshing file explorer tree");
+            self.file_explorer.refresh();
+        }
+    }
+
+    /// Reload tabs whose file went missing once it is back, retrying every
+    /// `MISSING_FILE_RETRY` during the grace period.
+    fn retry_missing_files(&mut self, ctx: &egui::Context) {
+        let waiting: Vec<&Tab> = self
+            .tabs
+            .iter()
+            .filter(|tab| tab.missing_since.is_some() && !tab.is_missing())
+            .collect();
+        if waiting.is_empty() {
+            return;
+        }
+        if self.last_missing_retry.elapsed() >= MISSING_FILE_RETRY {
+            self.last_missing_retry = Instant::now();
+            let back: Vec<PathBuf> = waiting
+                .iter()
+                .filter(|tab| fs::File::open(&tab.path).is_ok())
+                .map(|tab| tab.path.clone())
+                .collect();
+            if !back.is_empty() {
+                log::info!("Missing files are back: {:?}", back);
+                self.reload_changed_tabs(back);
+                self.rewatch_replaced_files();
+            }
+        }
+        // Keep retrying, and show the banner once the grace period is over
+        ctx.request_repaint_after(MISSING_FILE_RETRY);
+    }
+
+    /// Outcome of rendering the find bar (consumed after panel renders, before global input handler runs)
+    fn render_search_bar(&mut self, ctx: &egui::Context) -> SearchBarOutcome {
+        let mut outcome = SearchBarOutcome::default();
+        if !self.search.is_open {
+            return outcome;
+        }
+
+        let input_id = egui::Id::new("search_input");
+        let total_matches = self
+            .tabs
+            .get(self.active_tab)
+            .map(|t| t.search_matches.len())
+            .unwrap_or(0);
+        let active_idx = self.search.active_match_index;
+
+        egui::TopBottomPanel::top("search_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("🔍");
+
+                let text_edit = egui::TextEdit::singleline(&mut self.search.query)
+                    .id(input_id)
+                    .hint_text("Find in document")
+                    .desired_width(280.0);
+                let response = ui.add(text_edit);
+
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge
+                    .register_widget("Search: Input", "textbox", &response, None);
+
+                if self.search.focus_requested {
+                    response.request_focus();
+                    self.search.focus_requested = false;
+                }
+
+                ui.add_space(8.0);
+
+                let label_text = if self.search.query.is_empty() {
+                    String::new()
+                } else if total_matches == 0 {
+                    "0 matches".to_string()
+                } else {
+                    format!("{} / {}", active_idx + 1, total_matches)
+                };
+                let label_response = ui.label(
+                    egui::RichText::new(&label_text)
+                        .color(ui.style().visuals.weak_text_color())
+                        .small(),
+                );
+
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge.register_widget(
+                    "Search: Match Count",
+                    "label",
+                    &label_response,
+                    Some(&label_text),
+                );
+                #[cfg(not(feature = "mcp"))]
+                let _ = label_response;
+
+                ui.add_space(4.0);
+                let prev_btn = ui
+                    .add_enabled(total_matches > 0, egui::Button::new("↑").small())
+                    .on_hover_text("Previous match (Shift+Enter / ↑)");
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge
+                    .register_widget("Search: Previous", "button", &prev_btn, None);
+                if prev_btn.clicked() {
+                    outcome.prev_clicked = true;
+                }
+
+                let next_btn = ui
+                    .add_enabled(total_matches > 0, egui::Button::new("↓").small())
+                    .on_hover_text("Next match (Enter / ↓)");
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge
+                    .register_widget("Search: Next", "button", &next_btn, None);
+                if next_btn.clicked() {
+                    outcome.next_clicked = true;
+                }
+
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let close_btn = ui.small_button("✕").on_hover_text("Close (Esc)");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge
+                        .register_widget("Search: Close", "button", &close_btn, None);
+                    if close_btn.clicked() {
+                        outcome.close_requested = true;
+                    }
+
+                    let save_btn = ui
+                        .add_enabled(
+                            total_matches > 0,
+                            egui::Button::new("Save Results…").small(),
+                        )
+                        .on_hover_text("Save the matches as a markdown report");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Search: Save Results",
+                        "button",
+                        &save_btn,
+                        None,
+                    );
+                    if save_btn.clicked() {
+                        outcome.save_report = true;
+                    }
+
+                    let copy_btn = ui
+                        .add_enabled(total_matches > 0, egui::Button::new("Copy Results").small())
+                        .on_hover_text("Copy the matches as a markdown report");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Search: Copy Results",
+                        "button",
+                        &copy_btn,
+                        None,
+                    );
+                    if copy_btn.clicked() {
+                        outcome.copy_report = true;
                     }
                 });
             });
         });
 
-        // Handle navigation button clicks (must be after menu bar UI)
-        if go_back {
-            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                tab.navigate_back();
-            }
-        }
-        if go_forward {
-            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                tab.navigate_forward();
-            }
-        }
+        outcome
+    }
+
+    /// Warning that only the start of a large file is shown, with controls
+    /// to read more of it.
+    fn render_large_file_bar(&mut self, ctx: &egui::Context) {
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+        if !tab.truncated {
+            return;
+        }
+        let mut load = None;
+        egui::TopBottomPanel::top("large_file_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let color = ui.visuals().warn_fg_color;
+                ui.label(egui::RichText::new("⚠").color(color));
+                // Cut at a line break, so usually less than the limit
+                let shown = tab.source.len() as u64;
+                ui.label(
+                    egui::RichText::new(format!(
+                        "Large file: showing the first {} of {}",
+                        format_size(shown),
+                        format_size(tab.file_size)
+                    ))
+                    .color(color),
+                );
+                let next = format!("Load next {}", format_size(LARGE_FILE_CHUNK_BYTES as u64));
+                if ui.small_button(next).clicked() {
+                    load = Some(false);
+                }
+                if ui
+                    .small_button("Load all")
+                    .on_hover_text("Rendering the whole file may be slow")
+                    .clicked()
+                {
+                    load = Some(true);
+                }
+            });
+        });
+        if let Some(all) = load {
+            tab.load_more(all);
+        }
+    }
+
+    /// Banner for an active tab whose file is gone, with its last content
+    /// still shown below.
+    fn render_missing_file_bar(&mut self, ctx: &egui::Context) {
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+        if !tab.is_missing() {
+            return;
+        }
+        let mut close = false;
+        egui::TopBottomPanel::top("missing_file_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let color = ui.visuals().warn_fg_color;
+                ui.label(egui::RichText::new("⚠").color(color));
+                ui.label(
+                    egui::RichText::new(format!(
+                        "{} was removed or can't be read; showing its last content",
+                        tab.title()
+                    ))
+                    .color(color),
+                );
+                if ui.small_button("Retry").clicked() {
+                    tab.reload();
+                }
+                if ui.small_button("Close Tab").clicked() {
+                    close = true;
+                }
+            });
+        });
+        if close {
+            self.close_tab(self.active_tab);
+        } else if self.tabs[self.active_tab].missing_since.is_none() {
+            self.rewatch_replaced_files();
+        }
+    }
+
+    /// Copy the active tab's search matches as a markdown report, or save
+    /// them to a file picked in a dialog when `save` is set.
+    fn export_search_report(&mut self, ctx: &egui::Context, save: bool) {
+        let Some(tab) = self.tabs.get(self.active_tab) else {
+            return;
+        };
+        let report = search_report(
+            &self.search.query,
+            &tab.path,
+            &tab.content,
+            &tab.search_matches,
+        );
+        if !save {
+            ctx.copy_text(report);
+            return;
+        }
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name("search-results.md")
+            .add_filter("Markdown", &["md"])
+            .save_file()
+        else {
+            return;
+        };
+        if let Err(e) = fs::write(&path, report) {
+            self.error_message = Some(format!("Failed to save {}: {e}", path.display()));
+        }
+    }
+
+    /// Move the active match index forward (dir > 0) or backward (dir < 0), wrapping
+    /// around, and request a scroll-to-line via `pending_scroll_offset`.
+    fn jump_match(&mut self, dir: i32) {
+        let n = self
+            .tabs
+            .get(self.active_tab)
+            .map(|t| t.search_matches.len())
+            .unwrap_or(0);
+        if n == 0 {
+            return;
+        }
+        let new_idx = if dir >= 0 {
+            (self.search.active_match_index + 1) % n
+        } else if self.search.active_match_index == 0 {
+            n - 1
+        } else {
+            self.search.active_match_index - 1
+        };
+        self.search.active_match_index = new_idx;
+        self.scroll_to_active_match();
+    }
+
+    /// Request a scroll-to-line for the current `active_match_index`. No-op when
+    /// there's no active tab, no matches, or content height hasn't been measured yet.
+    /// Used both by `jump_match` (cycling) and by `maybe_rebuild_search` (so typing a
+    /// fresh query or switching tabs lands the view on the first match instead of
+    /// leaving the view at wherever the user previously scrolled).
+    fn scroll_to_active_match(&mut self) {
+        let idx = self.search.active_match_index;
+        let tab = match self.tabs.get_mut(self.active_tab) {
+            Some(t) => t,
+            None => return,
+        };
+        let Some(m) = tab.search_matches.get(idx) else {
+            return;
+        };
+        if tab.last_content_height <= 0.0 || tab.content_lines == 0 {
+            return;
+        }
+        // Line-ratio estimate gets the view roughly in the right area. The renderer
+        // records the actual y of the active match during paint; render_tab_content
+        // schedules a corrective scroll next frame if this estimate was off.
+        let estimated_y =
+            (m.line_number as f32 / tab.content_lines as f32) * tab.last_content_height;
+        let margin = if tab.last_viewport_height > 0.0 {
+            tab.last_viewport_height * 0.35
+        } else {
+            100.0
+        };
+        tab.pending_scroll_offset = Some((estimated_y - margin).max(0.0));
+        // Grant the post-render corrective block one frame of permission to
+        // snap to the renderer-recorded `active_search_y`. The block clears
+        // the flag after it runs, so subsequent frames (e.g. user wheeling
+        // away from the match) won't re-trigger snap-back. See Tab field doc.
+        tab.correct_active_search_pending = true;
+    }
+
+    /// Rebuild the active tab's search matches if the query or active tab has changed.
+    /// Cheap (no-op) when nothing changed.
+    fn maybe_rebuild_search(&mut self) {
+        if !self.search.is_open {
+            return;
+        }
+        let tab_idx = self.active_tab;
+        let needs_rebuild =
+            self.search.query != self.search.last_query || self.search.last_tab != Some(tab_idx);
+        if !needs_rebuild {
+            return;
+        }
+        let q = self.search.query.clone();
+        if let Some(tab) = self.tabs.get_mut(tab_idx) {
+            tab.rebuild_search(&q);
+        }
+        self.search.last_query = q;
+        self.search.last_tab = Some(tab_idx);
+        // Always start at the first match after any rebuild — query change, tab
+        // change, or watcher reload. Match indices are not semantically comparable
+        // across rebuilds; preserving a numeric index across docs is coincidental
+        // (matches Firefox / Chrome / VS Code behavior).
+        self.search.active_match_index = 0;
+        // Scroll to the new first match so the user sees the active highlight
+        // immediately rather than wherever they were previously scrolled. Without
+        // this, the visible match (somewhere else on screen) gets the regular
+        // highlight color, leaving the user with the impression that the active
+        // highlight is "stuck on the previous result".
+        self.scroll_to_active_match();
+    }
+
+    /// Toggle View Source in the active tab. Its matches are searched for
+    /// again, in the text shown now.
+    fn toggle_source_view(&mut self) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            tab.toggle_source_view();
+            tab.search_matches.clear();
+            self.search.last_tab = None;
+        }
+    }
+
+    /// Close the find bar and clear highlight state on every tab.
+    fn close_search(&mut self) {
+        self.search.is_open = false;
+        self.search.query.clear();
+        self.search.last_query.clear();
+        self.search.last_tab = None;
+        self.search.focus_requested = false;
+        self.search.active_match_index = 0;
+        for tab in self.tabs.iter_mut() {
+            tab.search_matches.clear();
+            tab.cache.clear_search_ranges();
+        }
+    }
+
+    /// Render the custom tab bar
+    fn render_tab_bar(&mut self, ui: &mut egui::Ui) -> Option<usize> {
+        let mut tab_to_close: Option<usize> = None;
+        let mut new_active: Option<usize> = None;
+        let mut close_others: Option<usize> = None;
+        let mut pin: Option<usize> = None;
+
+        // Collect tab info first to avoid borrow issues
+        let tab_info: Vec<(String, bool, PathBuf, bool)> = self
+            .tabs
+            .iter()
+            .enumerate()
+            .map(|(idx, tab)| {
+                let active = idx == self.active_tab;
+                // Unsaved tabs are marked until they are saved
+                let title = match tab.unsaved {
+                    Some(_) => format!("● {}", tab.title()),
+                    None => tab.title(),
+                };
+                (title, active, tab.path.clone(), tab.preview)
+            })
+            .collect();
+
+        let tab_count = tab_info.len();
+        let hovered_tab = self.hovered_tab;
+
+        // Collect widget data for MCP registration (name, widget_type, rect, value)
+        #[cfg(feature = "mcp")]
+        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
+
+        ui.horizontal(|ui| {
+            // Scrollable tab area
+            egui::ScrollArea::horizontal()
+                .max_width(ui.available_width() - 30.0)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        for (idx, (title, is_active, path, preview)) in tab_info.iter().enumerate()
+                        {
+                            let is_hovered = hovered_tab == Some(idx);
+
+                            // Tab frame
+                            let tab_response = ui.horizontal(|ui| {
+                                // Tab button
+                                let text = egui::RichText::new(title);
+                                let text = if *is_active { text.strong() } else { text };
+                                // Preview tabs are set in italics until kept
+                                let text = if *preview { text.italics() } else { text };
+
+                                let response = ui.selectable_label(*is_active, text);
+                                let response = if *preview {
+                                    response.on_hover_text("Preview; double-click to keep it open")
+                                } else {
+                                    response
+                                };
+
+                                // Collect tab widget data for MCP
+                                #[cfg(feature = "mcp")]
+                                widget_data.push((
+                                    format!("Tab: {}", title),
+                                    "tab",
+                                    response.rect,
+                                    Some(if *is_active {
+                                        "active".to_string()
+                                    } else {
+                                        "".to_string()
+                                    }),
+                                ));
+
+                                if response.clicked() {
+                                    new_active = Some(idx);
+                                }
+                                if response.double_clicked() {
+                                    pin = Some(idx);
+                                }
+
+                                // Middle-click to close
+                                if response.middle_clicked() {
+                                    tab_to_close = Some(idx);
+                                }
+
+                                // Close button (show on hover or active)
+                                if *is_active || is_hovered {
+                                    let close_btn = ui.small_button("×");
+
+                                    // Collect close button widget data for MCP
+                                    #[cfg(feature = "mcp")]
+                                    widget_data.push((
+                                        format!("Close Tab: {}", title),
+                                        "button",
+                                        close_btn.rect,
+                                        None,
+                                    ));
+
+                                    if close_btn.clicked() {
+                                        tab_to_close = Some(idx);
+                                    }
+                                }
+
+                                // Context menu
+                                response.context_menu(|ui| {
+                                    if ui.button("Close").clicked() {
+                                        tab_to_close = Some(idx);
+                                        ui.close();
+                                    }
+                                    if tab_count > 1 && ui.button("Close Others").clicked() {
+                                        close_others = Some(idx);
+                                        ui.close();
+                                    }
+                                    ui.separator();
+                                    if ui.button("Open Containing Folder").clicked() {
+                                        reveal_in_file_manager(path);
+                                        ui.close();
+                                    }
+                                    if ui.button("Copy Path").clicked() {
+                                        ui.ctx().copy_text(path.display().to_string());
+                                        ui.close();
+                                    }
+                                    if ui.button("Copy File URI").clicked() {
+                                        ui.ctx().copy_text(file_uri(path));
+                                        ui.close();
+                                    }
+                                });
+
+                                response
+                            });
+
+                            // Track hover state
+                            if tab_response.response.hovered() {
+                                self.hovered_tab = Some(idx);
+                            }
+
+                            ui.separator();
+                        }
+                    });
+                });
+
+            // Placeholder hint when no document is open (the welcome page is showing)
+            if tab_count == 0 {
+                ui.add_space(4.0);
+                ui.label(egui::RichText::new("No file open").weak());
+                ui.add_space(4.0);
+            }
+
+            // New tab button
+            let new_tab_btn = ui.button("+").on_hover_text("New Tab (Ctrl+T)");
+
+            // Collect new tab button widget data for MCP
+            #[cfg(feature = "mcp")]
+            widget_data.push(("New Tab".to_string(), "button", new_tab_btn.rect, None));
+
+            if new_tab_btn.clicked() {
+                self.open_file_dialog();
+            }
+        });
+
+        // Register all collected widgets with MCP bridge
+        #[cfg(feature = "mcp")]
+        for (name, widget_type, rect, value) in widget_data {
+            self.mcp_bridge
+                .register_widget_rect(&name, widget_type, rect, value.as_deref());
+        }
+
+        // Apply new active tab
+        if let Some(idx) = new_active {
+            self.active_tab = idx;
+            self.title_dirty = true;
+        }
+
+        // Keep a preview tab open
+        if let Some(tab) = pin.and_then(|idx| self.tabs.get_mut(idx)) {
+            tab.preview = false;
+        }
+
+        // Handle close others
+        if let Some(keep_idx) = close_others {
+            for idx in (0..self.tabs.len()).filter(|&idx| idx != keep_idx) {
+                self.remember_reading_position(idx);
+            }
+            let kept = self.tabs.remove(keep_idx);
+            self.tabs.clear();
+            self.tabs.push(kept);
+            self.active_tab = 0;
+            self.title_dirty = true;
+            self.refresh_open_tab_paths();
+            if self.watch_enabled {
+                self.update_watched_paths();
+            }
+            return None; // Don't close any tab, we already handled it
+        }
+
+        tab_to_close
+    }
+
+    /// Render the outline sidebar (right panel)
+    /// Rendered at top level for proper layout space allocation
+    fn render_outline(&mut self, ctx: &egui::Context) {
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+
+        if !self.show_outline || tab.outline_headers.is_empty() {
+            return;
+        }
+
+        let numbered = self.number_headings;
+        // Handle outline header click (store index to access both title and line_number)
+        let mut clicked_header_index: Option<usize> = None;
+        // A section dragged onto another heading: (from, to) header indices
+        let mut moved_section: Option<(usize, usize)> = None;
+        // Heading whose section to open in a tab of its own
+        let mut section_to_open: Option<usize> = None;
+        // Rewriting a partly loaded file would cut off the rest of it, a page
+        // in reader view with its markdown, and a section tab the rest of
+        // the document
+        let reorder = self.outline_reordering
+            && tab.unsaved.is_none()
+            && !tab.truncated
+            && !reader::is_html(&tab.path)
+            && tab.section.is_none();
+
+        // Collect widget data for MCP registration (name, widget_type, rect, value)
+        #[cfg(feature = "mcp")]
+        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
+
+        let is_dragging = ctx.input(|i| i.pointer.any_down());
+
+        egui::SidePanel::right("outline")
+            .resizable(true)
+            .default_width(200.0)
+            .min_width(120.0)
+            .max_width(400.0)
+            .frame(
+                egui::Frame::side_top_panel(&ctx.style()).inner_margin(egui::Margin {
+                    left: 8,
+                    right: 0,
+                    top: 8,
+                    bottom: 0,
+                }),
+            )
+            .show(ctx, |ui| {
+                // Expand/Collapse All buttons (only if there are nested headers)
+                let has_nested = any_header_has_children(&tab.outline_headers);
+                if has_nested {
+                    ui.horizontal(|ui| {
+                        ui.add_space(6.0);
+                        let expand_btn = ui.small_button("Expand All");
+
+                        // Collect Expand All button for MCP
+                        #[cfg(feature = "mcp")]
+                        widget_data.push((
+                            "Outline: Expand All".to_string(),
+                            "button",
+                            expand_btn.rect,
+                            None,
+                        ));
+
+                        if expand_btn.clicked() {
+                            tab.collapsed_headers.clear();
+                        }
+
+                        let collapse_btn = ui.small_button("Collapse All");
+
+                        // Collect Collapse All button for MCP
+                        #[cfg(feature = "mcp")]
+                        widget_data.push((
+                            "Outline: Collapse All".to_string(),
+                            "button",
+                            collapse_btn.rect,
+                            None,
+                        ));
+
+                        if collapse_btn.clicked() {
+                            for i in 0..tab.outline_headers.len() {
+                                if header_has_children(&tab.outline_headers, i) {
+                                    tab.collapsed_headers.insert(i);
+                                }
+                            }
+                        }
+                    });
+                    ui.separator();
+                }
+                // Pre-compute the visible header indices (skip those hidden
+                // by collapsed ancestors). This lets us virtualize via
+                // show_rows, paying O(visible-on-screen) instead of
+                // O(total-headers) per frame. On a 100k-line doc with ~15k
+                // headers this is the difference between visibly laggy and
+                // smooth outline interactions.
+                let visible_indices: Vec<usize> = (0..tab.outline_headers.len())
+                    .filter(|&i| !header_is_hidden(&tab.outline_headers, i, &tab.collapsed_headers))
+                    .collect();
+                let show_fold_indicators = any_header_has_children(&tab.outline_headers);
+                // Row height: fold indicator is 20px tall, fold-indicator-less
+                // rows fall back to the standard interact_size which is
+                // typically 18–20px anyway. A small fudge keeps neighboring
+                // rows from clipping into each other.
+                let row_height = ui.spacing().interact_size.y.max(20.0);
+
+                egui::ScrollArea::vertical()
+                    .scroll_bar_visibility(egui::scroll_area::ScrollBarVisibility::AlwaysHidden)
+                    .id_salt("outline")
+                    .show_rows(ui, row_height, visible_indices.len(), |ui, row_range| {
+                        let mut toggle_index: Option<usize> = None;
+                        for &idx in &visible_indices[row_range] {
+                            let header = &tab.outline_headers[idx];
+
+                            let has_children = header_has_children(&tab.outline_headers, idx);
+                            let is_collapsed = tab.collapsed_headers.contains(&idx);
+
+                            // Indent based on header level (h2 = 0, h3 = 1 indent, etc.)
+                            let indent = (header.level.saturating_sub(2) as usize) * 12;
+
+                            ui.horizontal(|ui| {
+                                // Add base indent
+                                if indent > 0 {
+                                    ui.add_space(indent as f32);
+                                }
+
+                                // Fold indicator (fixed width area for alignment)
+                                // Only allocate space if any header has children
+                                if show_fold_indicators {
+                                    let (rect, response) = ui.allocate_exact_size(
+                                        egui::vec2(20.0, 20.0),
+                                        egui::Sense::click(),
+                                    );
+                                    if has_children {
+                                        let indicator = if is_collapsed { "+" } else { "-" };
+                                        let text_color = if response.hovered() {
+                                            ui.visuals().strong_text_color()
+                                        } else {
+                                            ui.visuals().text_color()
+                                        };
+                                        ui.painter().text(
+                                            rect.center(),
+                                            egui::Align2::CENTER_CENTER,
+                                            indicator,
+                                            egui::FontId::monospace(16.0),
+                                            text_color,
+                                        );
+
+                                        // Collect fold indicator for MCP
+                                        #[cfg(feature = "mcp")]
+                                        widget_data.push((
+                                            format!("Toggle: {}", header.title),
+                                            "button",
+                                            rect,
+                                            Some(if is_collapsed {
+                                                "collapsed".to_string()
+                                            } else {
+                                                "expanded".to_string()
+                                            }),
+                                        ));
+
+                                        if !is_dragging && response.clicked() {
+                                            toggle_index = Some(idx);
+                                        }
+                                    }
+                                }
+
+                                // Header title (pre-computed truncation)
+                                let mut response =
+                                    ui.selectable_label(false, header.outline_title(numbered));
+                                if reorder {
+                                    response = response.interact(egui::Sense::drag());
+                                    response.dnd_set_drag_payload(OutlineDrag(idx));
+                                    // The upper half of a heading drops in front
+                                    // of it, the lower half behind the next
+                                    // visible heading
+                                    let pointer = ui.ctx().pointer_interact_pos();
+                                    let before =
+                                        !pointer.is_some_and(|p| p.y >= response.rect.center().y);
+                                    let to = if before {
+                                        idx
+                                    } else {
+                                        let next = visible_indices.partition_point(|&i| i <= idx);
+                                        visible_indices
+                                            .get(next)
+                                            .copied()
+                                            .unwrap_or(tab.outline_headers.len())
+                                    };
+                                    if response.dnd_hover_payload::<OutlineDrag>().is_some() {
+                                        let y = if before {
+                                            response.rect.top()
+                                        } else {
+                                            response.rect.bottom()
+                                        };
+                                        ui.painter().hline(
+                                            response.rect.x_range(),
+                                            y,
+                                            ui.visuals().selection.stroke,
+                                        );
+                                    }
+                                    if let Some(dragged) =
+                                        response.dnd_release_payload::<OutlineDrag>()
+                                    {
+                                        moved_section = Some((dragged.0, to));
+                                    }
+                                }
+
+                                // Reading time of top-level sections
+                                if header.level <= 2 && header.words > 0 {
+                                    ui.with_layout(
+                                        egui::Layout::right_to_left(egui::Align::Center),
+                                        |ui| {
+                                            ui.add_space(8.0);
+                                            ui.weak(reading_time(header.words))
+                                                .on_hover_text(format!("{} words", header.words));
+                                        },
+                                    );
+                                }
+
+                                // Collect header for MCP
+                                #[cfg(feature = "mcp")]
+                                widget_data.push((
+                                    format!("Header: {}", header.title),
+                                    "header",
+                                    response.rect,
+                                    Some(format!("h{}", header.level)),
+                                ));
+
+                                if !is_dragging && response.clicked() {
+                                    clicked_header_index = Some(idx);
+                                }
+                                response.context_menu(|ui| {
+                                    if ui.button("Open Section in New Tab").clicked() {
+                                        section_to_open = Some(idx);
+                                        ui.close();
+                                    }
+                                });
+                            });
+                        }
+                        // Apply toggle after iteration to avoid borrow issues
+                        if let Some(idx) = toggle_index {
+                            if tab.collapsed_headers.contains(&idx) {
+                                tab.collapsed_headers.remove(&idx);
+                            } else {
+                                tab.collapsed_headers.insert(idx);
+                            }
+                        }
+                    });
+            });
+
+        // Register all collected widgets with MCP bridge
+        #[cfg(feature = "mcp")]
+        for (name, widget_type, rect, value) in widget_data {
+            self.mcp_bridge
+                .register_widget_rect(&name, widget_type, rect, value.as_deref());
+        }
+
+        // Calculate scroll target if header was clicked
+        if let Some(idx) = clicked_header_index {
+            tab.scroll_to_outline_header(idx);
+        }
+
+        // Moved in the file as it is on disk, not in the shown content
+        if let Some((from, to)) = moved_section {
+            let result = fs::read(&tab.path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| {
+                    move_section_in_file(&bytes, tab.encoding, &tab.outline_headers, from, to)
+                })
+                .and_then(|moved| match moved {
+                    Some(bytes) => fs::write(&tab.path, bytes)
+                        .map(|()| true)
+                        .map_err(|e| e.to_string()),
+                    None => Ok(false),
+                });
+            match result {
+                Ok(true) => {
+                    tab.collapsed_headers.clear();
+                    tab.reload();
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    self.error_message = Some(format!("Failed to save {}: {e}", tab.path.display()))
+                }
+            }
+        }
+
+        if let Some(idx) = section_to_open {
+            let offset = tab.section.as_ref().map_or(0, |section| section.line);
+            let line = tab.outline_headers[idx].line_number + offset;
+            let path = tab.path.clone();
+            self.open_section_tab(path, line);
+        }
+    }
+
+    /// A contents button in the top right corner of the document that expands
+    /// into a list of its headings, for when the outline panel is hidden.
+    fn render_floating_toc(&mut self, ctx: &egui::Context, content_rect: egui::Rect) {
+        if !self.show_floating_toc {
+            return;
+        }
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+        if tab.outline_headers.is_empty() {
+            return;
+        }
+
+        let numbered = self.number_headings;
+        let mut clicked_header_index: Option<usize> = None;
+        // Clear of the scrollbar on the right edge
+        let corner = content_rect.right_top() + egui::vec2(-24.0, 8.0);
+        let area = egui::Area::new(egui::Id::new("floating_toc"))
+            .order(egui::Order::Foreground)
+            .pivot(egui::Align2::RIGHT_TOP)
+            .fixed_pos(corner)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                        let toggle = ui
+                            .selectable_label(self.floating_toc_open, "☰")
+                            .on_hover_text("Contents");
+                        #[cfg(feature = "mcp")]
+                        self.mcp_bridge.register_widget(
+                            "Floating Contents",
+                            "button",
+                            &toggle,
+                            Some(if self.floating_toc_open {
+                                "open"
+                            } else {
+                                "closed"
+                            }),
+                        );
+                        if toggle.clicked() {
+                            self.floating_toc_open = !self.floating_toc_open;
+                        }
+                        if !self.floating_toc_open {
+                            return;
+                        }
+                        ui.separator();
+                        let row_height = ui.spacing().interact_size.y;
+                        egui::ScrollArea::vertical()
+                            .id_salt("floating_toc_list")
+                            .max_height(content_rect.height() * 0.6)
+                            .max_width(280.0)
+                            .show_rows(ui, row_height, tab.outline_headers.len(), |ui, range| {
+                                ui.with_layout(egui::Layout::top_down(egui::Align::Min), |ui| {
+                                    for idx in range {
+                                        let header = &tab.outline_headers[idx];
+                                        let indent = header.level.saturating_sub(1) as f32 * 12.0;
+                                        ui.horizontal(|ui| {
+                                            ui.add_space(indent);
+                                            let response = ui.selectable_label(
+                                                false,
+                                                header.outline_title(numbered),
+                                            );
+                                            if response.clicked() {
+                                                clicked_header_index = Some(idx);
+                                            }
+                                        });
+                                    }
+                                });
+                            });
+                    });
+                });
+            });
+
+        // Clicking elsewhere folds the list away again
+        if self.floating_toc_open
+            && ctx.input(|i| i.pointer.any_pressed())
+            && !area.response.contains_pointer()
+        {
+            self.floating_toc_open = false;
+        }
+        if let Some(idx) = clicked_header_index {
+            tab.scroll_to_outline_header(idx);
+            self.floating_toc_open = false;
+        }
+    }
+
+    /// Scan the markdown files under `root` for tasks in the background.
+    fn scan_tasks(&mut self, root: PathBuf) {
+        self.tasks_root = Some(root.clone());
+        let job = self
+            .jobs
+            .spawn(&self.egui_ctx, "Scanning tasks", move |progress| {
+                scan_workspace_tasks(&root, progress)
+            });
+        self.task_scan = Some(job);
+    }
+
+    /// Re-read the tasks of one file under the scanned root after it changed.
+    fn refresh_file_tasks(&mut self, path: &Path) {
+        let in_root = self
+            .tasks_root
+            .as_ref()
+            .is_some_and(|root| path.starts_with(root));
+        if !in_root || !FileExplorer::is_markdown_file(path) {
+            return;
+        }
+        let tasks = fs::read(path)
+            .map(|bytes| parse_tasks(&decode_document(&bytes, None).0))
+            .unwrap_or_default();
+        match self
+            .workspace_tasks
+            .binary_search_by(|file| file.path.as_path().cmp(path))
+        {
+            Ok(i) if tasks.is_empty() => {
+                self.workspace_tasks.remove(i);
+            }
+            Ok(i) => self.workspace_tasks[i].tasks = tasks,
+            Err(i) if !tasks.is_empty() => {
+                let path = path.to_path_buf();
+                self.workspace_tasks.insert(i, FileTasks { path, tasks });
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Check or uncheck `task` of `path` in the file itself, in the encoding
+    /// it is read with.
+    fn toggle_task_in_file(&mut self, path: &Path, task: &TaskItem) {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let encoding = self
+            .tabs
+            .iter()
+            .find(|tab| tab.path == canonical)
+            .and_then(|tab| tab.encoding_override);
+        let result = fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| toggle_task_in_document(&bytes, encoding, task))
+            .and_then(|bytes| fs::write(path, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            self.error_message = Some(format!("Failed to update task in {}: {e}", path.display()));
+        }
+        self.refresh_file_tasks(path);
+        // With watching on, open tabs reload through the watcher
+        if !self.watch_enabled {
+            for tab in self.tabs.iter_mut().filter(|tab| tab.path == canonical) {
+                tab.reload();
+            }
+        }
+    }
+
+    /// Task list items of every markdown file under the explorer root, grouped
+    /// by file with completion counts. Clicking a task opens its file there;
+    /// its checkbox toggles the task in the file.
+    fn render_tasks(&mut self, ctx: &egui::Context) {
+        if !self.show_tasks {
+            return;
+        }
+        let Some(root) = self.file_explorer.root.clone() else {
+            return;
+        };
+        if self.tasks_root.as_ref() != Some(&root) {
+            self.workspace_tasks.clear();
+            self.scan_tasks(root.clone());
+        }
+        if let Some(tasks) = self.task_scan.as_ref().and_then(background::Job::try_take) {
+            self.workspace_tasks = tasks;
+            self.task_scan = None;
+        }
+        // Cancelled from the status bar: keep what was listed before
+        if self
+            .task_scan
+            .as_ref()
+            .is_some_and(background::Job::is_cancelled)
+        {
+            self.task_scan = None;
+        }
+
+        let mut open: Option<(PathBuf, usize)> = None;
+        let mut toggle: Option<(PathBuf, TaskItem)> = None;
+        let mut refresh = false;
+        let scanning = self.task_scan.is_some();
+
+        // Collect widget data for MCP registration (name, widget_type, rect, value)
+        #[cfg(feature = "mcp")]
+        let mut widget_data: Vec<(String, &'static str, egui::Rect, Option<String>)> = Vec::new();
+
+        egui::SidePanel::right("tasks")
+            .resizable(true)
+            .default_width(240.0)
+            .min_width(160.0)
+            .max_width(480.0)
+            .show(ctx, |ui| {
+                let all = || self.workspace_tasks.iter().flat_map(|file| &file.tasks);
+                let total = all().count();
+                let done = all().filter(|task| task.done).count();
+                ui.horizontal(|ui| {
+                    ui.strong("Tasks");
+                    #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                    let summary = ui.label(format!("{done}/{total} done"));
+                    #[cfg(feature = "mcp")]
+                    widget_data.push((
+                        "Tasks: Summary".to_string(),
+                        "label",
+                        summary.rect,
+                        Some(format!("{done}/{total}")),
+                    ));
+                    if ui.small_button("Refresh").clicked() {
+                        refresh = true;
+                    }
+                });
+                if total > 0 {
+                    ui.add(egui::ProgressBar::new(done as f32 / total as f32));
+                }
+                ui.separator();
+                if total == 0 {
+                    if scanning {
+                        ui.spinner();
+                    } else {
+                        ui.weak("No tasks in this folder");
+                    }
+                    return;
+                }
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for file in &self.workspace_tasks {
+                        let name = file.path.strip_prefix(&root).unwrap_or(&file.path);
+                        let file_done = file.tasks.iter().filter(|task| task.done).count();
+                        let title =
+                            format!("{} ({file_done}/{})", name.display(), file.tasks.len());
+                        egui::CollapsingHeader::new(title)
+                            .id_salt(&file.path)
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                for task in &file.tasks {
+                                    ui.horizontal(|ui| {
+                                        let mut checked = task.done;
+                                        if ui.checkbox(&mut checked, "").changed() {
+                                            toggle = Some((file.path.clone(), task.clone()));
+                                        }
+                                        let mut text = egui::RichText::new(&task.text);
+                                        if task.done {
+                                            text = text.strikethrough().weak();
+                                        }
+                                        let label = ui
+                                            .add(
+                                                egui::Label::new(text)
+                                                    .sense(egui::Sense::click())
+                                                    .truncate(),
+                                            )
+                                            .on_hover_text(format!("Line {}", task.line));
+                                        #[cfg(feature = "mcp")]
+                                        widget_data.push((
+                                            format!("Task: {}:{}", name.display(), task.line),
+                                            "button",
+                                            label.rect,
+                                            Some(if task.done { "done" } else { "open" }.into()),
+                                        ));
+                                        if label.clicked() {
+                                            open = Some((file.path.clone(), task.line));
+                                        }
+                                    });
+                                }
+                            });
+                    }
+                });
+            });
+
+        // Register all collected widgets with MCP bridge
+        #[cfg(feature = "mcp")]
+        for (name, widget_type, rect, value) in widget_data {
+            self.mcp_bridge
+                .register_widget_rect(&name, widget_type, rect, value.as_deref());
+        }
+
+        if refresh {
+            self.scan_tasks(root);
+        }
+        if let Some((path, task)) = toggle {
+            self.toggle_task_in_file(&path, &task);
+        }
+        if let Some((path, line)) = open {
+            self.open_in_new_tab(path);
+            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                tab.pending_line = Some(line);
+            }
+        }
+    }
+
+    /// Lay out the links between the markdown files under `root` in the
+    /// background.
+    fn scan_link_graph(&mut self, root: PathBuf) {
+        self.graph_root = Some(root.clone());
+        let job = self
+            .jobs
+            .spawn(&self.egui_ctx, "Scanning links", move |progress| {
+                graph::scan(&root, progress)
+            });
+        self.graph_scan = Some(job);
+    }
+
+    /// Re-read the links of one file on the link graph after it changed.
+    fn refresh_file_links(&mut self, path: &Path) {
+        let Some(graph) = &mut self.link_graph else {
+            return;
+        };
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let links = fs::read_to_string(&canonical)
+            .map(|content| graph::links(&content, &canonical))
+            .unwrap_or_default();
+        graph.set_links(&canonical, &links);
+    }
+
+    /// The markdown files under the explorer root as a graph of their links,
+    /// the active document and the ones it links to or from highlighted.
+    /// Clicking a document opens it.
+    fn render_link_graph(&mut self, ctx: &egui::Context) {
+        if !self.show_link_graph {
+            return;
+        }
+        let Some(root) = self.file_explorer.root.clone() else {
+            return;
+        };
+        if self.graph_root.as_ref() != Some(&root) {
+            self.link_graph = None;
+            self.scan_link_graph(root.clone());
+        }
+        if let Some(graph) = self.graph_scan.as_ref().and_then(background::Job::try_take) {
+            self.link_graph = Some(graph);
+            self.graph_scan = None;
+        }
+        // Cancelled from the status bar: keep the graph shown before
+        if self
+            .graph_scan
+            .as_ref()
+            .is_some_and(background::Job::is_cancelled)
+        {
+            self.graph_scan = None;
+        }
+
+        let active = self.tabs.get(self.active_tab).map(|tab| tab.path.clone());
+        let scanning = self.graph_scan.is_some();
+        let mut open = None;
+        let mut refresh = false;
+        let mut close = false;
+        egui::SidePanel::right("link_graph")
+            .resizable(true)
+            .default_width(360.0)
+            .min_width(200.0)
+            .max_width(720.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong("Link Graph");
+                    if let Some(graph) = &self.link_graph {
+                        ui.weak(format!(
+                            "{} documents, {} links",
+                            graph.nodes.len(),
+                            graph.edges.len()
+                        ));
+                    }
+                    if ui.small_button("Refresh").clicked() {
+                        refresh = true;
+                    }
+                    if ui.small_button("✕").on_hover_text("Close").clicked() {
+                        close = true;
+                    }
+                });
+                ui.separator();
+                let Some(graph) = &mut self.link_graph else {
+                    if scanning {
+                        ui.spinner();
+                    }
+                    return;
+                };
+                if graph.nodes.is_empty() {
+                    ui.weak("No documents in this folder");
+                    return;
+                }
+                if graph.step() {
+                    ui.ctx().request_repaint();
+                }
+                open = show_link_graph(ui, graph, &root, active.as_deref());
+            });
+
+        if close {
+            self.show_link_graph = false;
+        }
+        if refresh {
+            self.scan_link_graph(root);
+        }
+        if let Some(path) = open {
+            self.open_in_new_tab(path);
+        }
+    }
+
+    /// Render the active tab's content
+    /// Render the welcome / idle page shown when no document is open (issue #28).
+    fn render_welcome(&mut self, ui: &mut egui::Ui) {
+        let mut do_open_file = false;
+        let mut do_open_folder = false;
+        let mut open_path: Option<PathBuf> = None;
+        let mut toggle_show_all = false;
+        let now = now_epoch_secs();
+
+        // Snapshot the entries to display so we don't borrow self while acting on clicks.
+        let shown = if self.welcome_show_all {
+            RECENT_FILES_CAP
+        } else {
+            RECENT_SHOWN
+        };
+        let recents: Vec<(RecentEntry, bool)> = self
+            .recent_files
+            .iter()
+            .take(shown)
+            .map(|e| (e.clone(), e.path.exists()))
+            .collect();
+        let total_recent = self.recent_files.len();
+        let show_all = self.welcome_show_all;
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.add_space(64.0);
+                ui.vertical_centered(|ui| {
+                    ui.label(egui::RichText::new("📄").size(56.0).weak());
+                    ui.add_space(10.0);
+                    ui.label(
+                        egui::RichText::new("Open a file or folder")
+                            .size(22.0)
+                            .strong(),
+                    );
+                    ui.add_space(2.0);
+                    ui.label(egui::RichText::new("No document is open.").weak());
+                });
+                ui.add_space(18.0);
+                // Center the button row manually: a horizontal inside
+                // vertical_centered expands to full width, defeating centering.
+                ui.horizontal(|ui| {
+                    let avail = ui.available_width();
+                    ui.add_space(((avail - 230.0) / 2.0).max(0.0));
+                    if ui
+                        .button(egui::RichText::new("📂  Open File").size(15.0))
+                        .clicked()
+                    {
+                        do_open_file = true;
+                    }
+                    ui.add_space(10.0);
+                    if ui
+                        .button(egui::RichText::new("📁  Open Folder").size(15.0))
+                        .clicked()
+                    {
+                        do_open_folder = true;
+                    }
+                });
+
+                if !recents.is_empty() {
+                    ui.add_space(32.0);
+                    // Center a fixed-width, left-aligned column for the recent list.
+                    let avail = ui.available_width();
+                    let col_w = 560.0_f32.min((avail - 16.0).max(200.0));
+                    let margin = ((avail - col_w) / 2.0).max(0.0);
+                    ui.horizontal(|ui| {
+                        ui.add_space(margin);
+                        ui.vertical(|ui| {
+                            ui.set_max_width(col_w);
+                            ui.label(egui::RichText::new("Recent").strong());
+                            ui.add_space(2.0);
+                            ui.separator();
+                            for (entry, exists) in &recents {
+                                let name = entry
+                                    .path
+                                    .file_name()
+                                    .map(|n| n.to_string_lossy().to_string())
+                                    .unwrap_or_else(|| entry.path.to_string_lossy().to_string());
+                                let dir = entry
+                                    .path
+                                    .parent()
+                                    .map(|p| p.to_string_lossy().to_string())
+                                    .unwrap_or_default();
+                                let when = format_relative_time(entry.last_opened, now);
+                                ui.horizontal(|ui| {
+                                    let label =
+                                        egui::RichText::new(format!("📄 {name}")).size(14.0);
+                                    let label = if *exists { label } else { label.weak() };
+                                    let resp = ui
+                                        .add_enabled(*exists, egui::Button::new(label).frame(false))
+                                        .on_hover_text(entry.path.to_string_lossy());
+                                    if resp.clicked() {
+                                        open_path = Some(entry.path.clone());
+                                    }
+                                    ui.label(
+                                        egui::RichText::new(format!("{dir}   ·   {when}"))
+                                            .weak()
+                                            .small(),
+                                    );
+                                });
+                            }
+                            if total_recent > RECENT_SHOWN {
+                                ui.add_space(6.0);
+                                let more = if show_all {
+                                    "Show less"
+                                } else {
+                                    "Show more…"
+                                };
+                                if ui.link(more).clicked() {
+                                    toggle_show_all = true;
+                                }
+                            }
+                        });
+                    });
+                }
+            });
+
+        // Deferred actions (run after the borrow on self.recent_files is released).
+        if do_open_file {
+            self.open_file_dialog();
+        }
+        if do_open_folder {
+            self.open_folder_dialog();
+        }
+        if let Some(p) = open_path {
+            self.open_in_new_tab(p);
+        }
+        if toggle_show_all {
+            self.welcome_show_all = !self.welcome_show_all;
+        }
+    }
+
+    /// Returns a link target to open in a new tab, and whether that tab
+    /// opens in the background.
+    fn render_tab_content(
+        &mut self,
+        ui: &mut egui::Ui,
+        ctrl_held: bool,
+    ) -> Option<(PathBuf, bool)> {
+        let mut open_in_new_tab: Option<(PathBuf, bool)> = None;
+
+        // Snapshot search state before taking a mutable borrow on the active tab
+        let search_is_open = self.search.is_open;
+        let active_idx = self.search.active_match_index;
+        let sandboxed = self
+            .tabs
+            .get(self.active_tab)
+            .is_some_and(|tab| self.is_sandboxed(tab));
+        let sandbox = if sandboxed {
+            Sandbox::all()
+        } else {
+            Sandbox::default()
+        };
+
+        // No document open → render the welcome / idle page instead.
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            self.render_welcome(ui);
+            return None;
+        };
+        if let Some(comparison) = &mut tab.compare {
+            scale_text_styles(ui.style_mut(), self.zoom_level);
+            show_comparison(ui, comparison, &mut tab.cache, &tab.path);
+            return None;
+        }
+
+        // Push current search match ranges into the cache so the renderer can paint highlights.
+        // They are offsets into the whole document, so the task view goes without,
+        // and the source view has them after its opening fence.
+        if search_is_open && !tab.search_matches.is_empty() && tab.task_view.is_none() {
+            let fence = tab
+                .source_view
+                .as_deref()
+                .and_then(|view| view.find('\n'))
+                .map_or(0, |end| end + 1);
+            let ranges: Vec<_> = tab
+                .search_matches
+                .iter()
+                .map(|m| m.byte_start + fence..m.byte_end + fence)
+                .collect();
+            let active = tab
+                .search_matches
+                .get(active_idx)
+                .map(|m| m.byte_start + fence..m.byte_end + fence);
+            tab.cache.set_search_ranges(ranges);
+            tab.cache.set_active_search_range(active);
+        } else {
+            tab.cache.clear_search_ranges();
+        }
+
+        // Content area (no inner CentralPanel needed - we're already in one)
+        // Left margin for breathing room, right margin prevents scrollbar/resize-handle overlap jitter
+        #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+        let content_frame = egui::Frame::NONE
+            .inner_margin(egui::Margin {
+                left: 8,
+                right: 3,
+                ..Default::default()
+            })
+            .show(ui, |ui| {
+                // Content zoom applies to the document only; chrome uses ui_scale
+                scale_text_styles(ui.style_mut(), self.zoom_level);
+
+                // Capture scroll input for manual handling during selection
+                let raw_scroll = ui.ctx().input(|i| i.raw_scroll_delta.y);
+                let content_rect = ui.available_rect_before_wrap();
+
+                // The renderer owns the ScrollArea now (via show_scrollable),
+                // so we configure scroll_source / pending offset / content
+                // version through builder methods. The returned ScrollAreaOutput
+                // exposes state.offset and inner_rect for the post-render
+                // selection-preserving wheel hack below.
+                // A task panel jump into a new tab waits for its first paint
+                if let Some(line) = tab.pending_line {
+                    let line = line + usize::from(tab.source_view.is_some());
+                    match tab.cache.y_for_line(tab.id, line) {
+                        Some(y) => {
+                            tab.pending_scroll_offset = Some((y - 50.0).max(0.0));
+                            tab.pending_line = None;
+                        }
+                        None => ui.ctx().request_repaint(),
+                    }
+                }
+                let pending = tab.pending_scroll_offset.take();
+                // Scale the column with the text so zoom keeps the same line
+                // length; the page preview's is as wide as a printed page's
+                let preview = self.page_preview.then_some(self.page_setup);
+                let default_width = match preview {
+                    Some(setup) => Some((setup.printable().x * self.zoom_level).round() as usize),
+                    None => content_default_width(
+                        self.full_width_content,
+                        self.content_width * self.zoom_level,
+                    ),
+                };
+                // Line numbers are those of the whole document; the source
+                // view numbers its lines itself
+                let show_line_numbers =
+                    self.show_line_numbers && tab.task_view.is_none() && tab.source_view.is_none();
+                let gutter_width = if show_line_numbers {
+                    LINE_NUMBER_GUTTER_WIDTH
+                } else {
+                    0.0
+                };
+                let center_margin = content_center_margin(
+                    content_rect.width() - gutter_width,
+                    default_width,
+                    self.center_content || preview.is_some(),
+                );
+                // The renderer's ScrollArea still spans to the right edge so the
+                // scrollbar stays put; only the column's left edge moves.
+                let mut viewer_rect = content_rect;
+                viewer_rect.min.x += center_margin + gutter_width;
+                let run_dir = tab.path.parent().map(Path::to_path_buf);
+                // Heading links copied on hover name the file, to paste into
+                // the documents next to it
+                let file_name = tab
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let code_runs = self.code_runs.clone();
+                let rust_runner = self.rust_runner.clone();
+                let run_footer = move |ui: &mut egui::Ui, lang: &str, code: &str, id: egui::Id| {
+                    let dir = run_dir.as_deref();
+                    code_block_run_footer(ui, &code_runs, dir, &rust_runner, (lang, code, id));
+                };
+                let footer = code_run_footer(self.allow_running_code, sandboxed, &run_footer);
+                // Numbers are offsets into the content, not the other views
+                let heading_numbers: Vec<(usize, String)> =
+                    if self.number_headings && tab.source_view.is_none() && tab.task_view.is_none()
+                    {
+                        tab.outline_headers
+                            .iter()
+                            .filter_map(|h| Some((h.start, h.number.clone()?)))
+                            .collect()
+                    } else {
+                        Vec::new()
+                    };
+                // The page preview's sheet goes behind the document
+                let sheet = ui.painter().add(egui::Shape::Noop);
+                let mut scroll_output = ui
+                    .scope_builder(egui::UiBuilder::new().max_rect(viewer_rect), |ui| {
+                        CommonMarkViewer::new()
+                            .default_implicit_uri_scheme(&tab.base_uri)
+                            .max_image_width(Some((800.0 * self.zoom_level).round() as usize))
+                            .default_width(default_width)
+                            .indentation_spaces(2)
+                            .use_strong_font_family(true)
+                            .clickable_images(true)
+                            .show_alt_text_on_hover(true)
+                            .media_embeds(true)
+                            .image_captions(ImageCaptions::TitleOrEmphasis)
+                            .lazy_images(true)
+                            .image_prefetch(true)
+                            .image_context_menu(true)
+                            .table_context_menu(true)
+                            .color_swatches(self.show_color_swatches)
+                            .dark_mode_images(self.dark_images.renderer_mode())
+                            .blockquote_colors(if self.email_quotes {
+                                EMAIL_QUOTE_COLORS.to_vec()
+                            } else {
+                                Vec::new()
+                            })
+                            .collapse_quotes_deeper_than(
+                                self.email_quotes.then_some(EMAIL_QUOTE_DEPTH),
+                            )
+                            .math_macros(true)
+                            .equation_numbers(self.equation_numbers)
+                            .heading_numbers(&heading_numbers)
+                            .heading_link_button(Some(&file_name))
+                            .block_copy_buttons(self.copy_blocks)
+                            .code_line_numbers(tab.source_view.is_some())
+                            .sandbox(sandbox)
+                            .copy_without_prompts(true)
+                            .progressive_loading(true)
+                            .continuous_selection(true)
+                            .code_block_fn(&plugins::handles_fence, &plugins::render_fence)
+                            .rewrite_url_fn(Some(&|url: &str, _| plugins::rewrite_link(url)))
+                            .code_block_footer_fn(footer)
+                            .alerts(self.alerts.clone())
+                            .syntax_theme_dark(&self.syntax_theme_dark)
+                            .syntax_theme_light(&self.syntax_theme_light)
+                            .line_height(1.5)
+                            .code_line_height(1.3)
+                            .paragraph_spacing(2.0)
+                            .heading_spacing_above(2.0)
+                            .heading_spacing_below(0.75)
+                            .content_version(tab.content_version)
+                            .pending_scroll_offset(pending)
+                            .scroll_source(egui::scroll_area::ScrollSource {
+                                scroll_bar: true,
+                                drag: false,
+                                mouse_wheel: true,
+                            })
+                            .show_scrollable(
+                                tab.id,
+                                ui,
+                                &mut tab.cache,
+                                tab.source_view
+                                    .as_deref()
+                                    .or(tab.task_view.as_deref())
+                                    .unwrap_or(&tab.content),
+                            )
+                    })
+                    .inner;
+
+                if show_line_numbers {
+                    let gutter_rect = egui::Rect::from_x_y_ranges(
+                        viewer_rect.min.x - gutter_width..=viewer_rect.min.x,
+                        scroll_output.inner_rect.y_range(),
+                    );
+                    paint_line_number_gutter(
+                        ui,
+                        gutter_rect,
+                        tab.cache.source_blocks(tab.id),
+                        scroll_output.state.offset.y,
+                    );
+                }
+
+                if let (Some(setup), Some(width)) = (preview, default_width) {
+                    let shown = tab
+                        .source_view
+                        .as_deref()
+                        .or(tab.task_view.as_deref())
+                        .unwrap_or(&tab.content);
+                    let blocks: Vec<(egui::Rangef, bool)> = tab
+                        .cache
+                        .source_blocks(tab.id)
+                        .iter()
+                        .map(|block| {
+                            let source = shown.get(block.byte_range.clone()).unwrap_or_default();
+                            (block.y_range, is_page_break(source))
+                        })
+                        .collect();
+                    let top = scroll_output.inner_rect.top() - scroll_output.state.offset.y;
+                    let page_starts: Vec<f32> =
+                        pages::page_starts(&blocks, setup.printable().y * self.zoom_level)
+                            .into_iter()
+                            .map(|y| top + y)
+                            .collect();
+                    let margin = setup.margins.width() * self.zoom_level;
+                    let column = viewer_rect.min.x..=viewer_rect.min.x + width as f32;
+                    let page_rect = egui::Rect::from_x_y_ranges(
+                        column,
+                        top..=top + scroll_output.content_size.y,
+                    )
+                    .expand(margin);
+                    paint_pages(ui, sheet, page_rect, scroll_output.inner_rect, &page_starts);
+                }
+
+                tab.scroll_offset = scroll_output.state.offset.y;
+                tab.last_viewport_height = scroll_output.inner_rect.height();
+                tab.last_content_height = scroll_output.content_size.y;
+
+                // If the renderer recorded an exact y for the active match, check
+                // whether the current scroll position keeps it visible. If not,
+                // schedule a corrective scroll using the recorded y — this fixes
+                // line-ratio overshoot/undershoot in image-heavy documents.
+                // Corrective scroll for search-match jump. Gated by the
+                // one-shot `correct_active_search_pending` flag (set by
+                // `scroll_to_active_match`) so it fires at most once per
+                // jump — without this gate the block would re-trigger every
+                // frame the active match is off-screen, fighting the user's
+                // wheel input and locking the view (issue #19).
+                if tab.correct_active_search_pending {
+                    if let Some(actual_y) = tab.cache.active_search_y() {
+                        let current_scroll = scroll_output.state.offset.y;
+                        let viewport_top = current_scroll;
+                        let viewport_bottom = current_scroll + tab.last_viewport_height;
+                        // Consider "not visible" if outside the viewport with a small margin
+                        let margin_outside = 20.0_f32;
+                        let needs_correction = actual_y < viewport_top + margin_outside
+                            || actual_y > viewport_bottom - margin_outside;
+                        if needs_correction && tab.last_viewport_height > 0.0 {
+                            // Place the active match ~35% from the top of the viewport
+                            let inset = tab.last_viewport_height * 0.35;
+                            let want_scroll = (actual_y - inset).max(0.0);
+                            // Avoid stomping if we're already at the target (within a frame)
+                            if (want_scroll - current_scroll).abs() > 2.0 {
+                                tab.pending_scroll_offset = Some(want_scroll);
+                            }
+                        }
+                        // Clear the one-shot regardless of which branch ran;
+                        // the corrective block has now had its chance to snap.
+                        tab.correct_active_search_pending = false;
+                    }
+                }
+
+                // Corrective scroll for outline-click. The bootstrap full paint
+                // triggered above by `pending_scroll_offset` recorded every
+                // heading's precise y under its composite cache key. If the
+                // line-ratio first-frame estimate left the target outside the
+                // viewport (common in image-heavy docs and the failure mode for
+                // duplicate-titled headers before this), snap the next frame to
+                // the recorded y. Mirrors the search-match corrective above.
+                if let Some(key) = tab.pending_header_click_key.take() {
+                    if let Some(actual_y) = tab.cache.get_header_position(&key) {
+                        let current_scroll = scroll_output.state.offset.y;
+                        let want_scroll = (actual_y - 50.0).max(0.0);
+                        if (want_scroll - current_scroll).abs() > 2.0 {
+                            tab.pending_scroll_offset = Some(want_scroll);
+                        }
+                    }
+                }
+
+                // Manual scroll handling for mouse wheel during text selection
+                let pointer_over_content = ui.ctx().input(|i| {
+                    i.pointer
+                        .hover_pos()
+                        .is_some_and(|pos| content_rect.contains(pos))
+                });
+                if raw_scroll.abs() > 0.0 && pointer_over_content {
+                    let current_offset = scroll_output.state.offset.y;
+                    let max_scroll = (tab.last_content_height - content_rect.height()).max(0.0);
+                    let new_offset = (current_offset - raw_scroll).clamp(0.0, max_scroll);
+
+                    // Don't store at boundaries (can break selection)
+                    let would_hit_top = new_offset < 0.5;
+                    let would_hit_bottom = new_offset > max_scroll - 0.5;
+                    let offset_changed = (new_offset - current_offset).abs() > 0.5;
+
+                    if offset_changed && !would_hit_top && !would_hit_bottom {
+                        scroll_output.state.offset.y = new_offset;
+                        scroll_output.state.store(ui.ctx(), scroll_output.id);
+                        ui.ctx().request_repaint();
+                    }
+                }
+
+                // Request repaint during smooth scrolling
+                let scrolling = ui.ctx().input(|i| i.smooth_scroll_delta.length_sq() > 0.0);
+                if scrolling {
+                    ui.ctx().request_repaint();
+                }
+
+                // Once the wheel has rested, settle on a heading near the top
+                if self.snap_to_headings {
+                    let zooming = ui.ctx().input(|i| i.modifiers.ctrl);
+                    if raw_scroll != 0.0 && pointer_over_content && !zooming {
+                        tab.last_wheel_scroll = Some(Instant::now());
+                    }
+                    if let Some(since) = tab.last_wheel_scroll {
+                        if scrolling || since.elapsed() < HEADING_SNAP_DELAY {
+                            ui.ctx().request_repaint_after(HEADING_SNAP_DELAY);
+                        } else {
+                            tab.last_wheel_scroll = None;
+                            let headings: Vec<f32> = tab
+                                .outline_headers
+                                .iter()
+                                .filter_map(|h| {
+                                    let key = HeaderKey::new(tab.id, h.nth_with_same_slug, &h.slug);
+                                    tab.cache.get_header_position(&key)
+                                })
+                                .collect();
+                            if let Some(target) = heading_snap_offset(
+                                tab.scroll_offset,
+                                tab.last_viewport_height,
+                                &headings,
+                            ) {
+                                tab.pending_scroll_offset = Some(target);
+                            }
+                        }
+                    }
+                }
+            });
+
+        // Expose the document to E2E agents: where it lives, its source and
+        // outline, and the blocks currently scrolled into view
+        #[cfg(feature = "mcp")]
+        {
+            let rect = content_frame.response.rect;
+            let path = tab.path.display().to_string();
+            let outline = outline_text(tab.document_title.as_deref(), &tab.outline_headers);
+            let visible = visible_source(
+                &tab.content,
+                tab.cache.source_blocks(tab.id),
+                tab.scroll_offset,
+                tab.last_viewport_height,
+            );
+            for (name, value) in [
+                ("Document: Path", path.as_str()),
+                ("Document: Content", tab.content.as_str()),
+                ("Document: Outline", outline.as_str()),
+                ("Document: Visible", visible.as_str()),
+            ] {
+                self.mcp_bridge
+                    .register_widget_rect(name, "document", rect, Some(value));
+            }
+        }
+
+        // Check for clicked links, which scripts may redirect or stop
+        if let Some(link) = tab.check_link_hooks() {
+            let background = tab
+                .cache
+                .get_link_hook_click(&link)
+                .is_some_and(opens_in_background);
+            let clicked_link = scripting::on_link_click(link);
+            let root = self.file_explorer.link_root();
+            match clicked_link {
+                Some(clicked_link) if ctrl_held || background => {
+                    // Open in new tab
+                    if let Some(target_path) = tab.resolve_link(&clicked_link, root) {
+                        open_in_new_tab = Some((target_path, background));
+                    }
+                }
+                // Navigate in current tab
+                Some(clicked_link) => tab.navigate_to_link(&clicked_link, root),
+                None => {}
+            }
+        }
+
+        open_in_new_tab
+    }
+
+    /// Render the file explorer sidebar
+    /// Returns actions for files to open or close
+    fn render_file_explorer(&mut self, ctx: &egui::Context) -> ExplorerAction {
+        let mut action = ExplorerAction::default();
+
+        if !self.show_explorer {
+            return action;
+        }
+
+        egui::SidePanel::left("file_explorer")
+            .resizable(true)
+            .default_width(200.0)
+            .min_width(150.0)
+            .max_width(300.0)
+            .frame(
+                egui::Frame::side_top_panel(&ctx.style()).inner_margin(egui::Margin {
+                    left: 8,
+                    right: 8,
+                    top: 8,
+                    bottom: 8,
+                }),
+            )
+            .show(ctx, |ui| {
+                // Header with folder name, a menu of the recent roots - OUTSIDE ScrollArea
+                let folder_name = self.file_explorer.root.as_ref().map_or_else(
+                    || "No folder".to_string(),
+                    |root| {
+                        root.file_name()
+                            .map(|n| n.to_string_lossy().to_string())
+                            .unwrap_or_else(|| root.display().to_string())
+                    },
+                );
+                let mut switch_to = None;
+                let mut pick_folder = false;
+                #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                let roots_menu = ui.menu_button(
+                    egui::RichText::new(format!("{folder_name} ⏷")).strong(),
+                    |ui| {
+                        let current = self
+                            .file_explorer
+                            .root
+                            .as_ref()
+                            .and_then(|root| root.canonicalize().ok());
+                        for root in self.recent_roots.iter().filter(|root| root.is_dir()) {
+                            let selected = current.as_ref() == Some(root);
+                            let text = checked_text(selected, &root.display().to_string());
+                            if ui.button(text).clicked() {
+                                switch_to = Some(root.clone());
+                                ui.close();
+                            }
+                        }
+                        ui.separator();
+                        if ui.button("Open Folder…").clicked() {
+                            pick_folder = true;
+                            ui.close();
+                        }
+                    },
+                );
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge.register_widget(
+                    "Explorer: Recent Folders",
+                    "button",
+                    &roots_menu.response,
+                    Some(&folder_name),
+                );
+
+                // Up one level and a breadcrumb of the root's ancestors
+                if let Some(root) = &self.file_explorer.root {
+                    let crumbs = root_breadcrumb(root);
+                    ui.horizontal_wrapped(|ui| {
+                        ui.spacing_mut().item_spacing.x = 2.0;
+                        let parent = crumbs.len().checked_sub(2).map(|i| &crumbs[i].1);
+                        let up_btn = ui
+                            .add_enabled(parent.is_some(), egui::Button::new("⬆").small())
+                            .on_hover_text("Up one level");
+                        #[cfg(feature = "mcp")]
+                        self.mcp_bridge.register_widget(
+                            "Explorer: Up One Level",
+                            "button",
+                            &up_btn,
+                            None,
+                        );
+                        if up_btn.clicked() {
+                            switch_to = parent.cloned();
+                        }
+                        let last = crumbs.len() - 1;
+                        for (i, (name, dir)) in crumbs.iter().enumerate() {
+                            if i > 0 {
+                                ui.weak("›");
+                            }
+                            if i == last {
+                                ui.small(name);
+                            } else if ui
+                                .small_button(name)
+                                .on_hover_text(dir.display().to_string())
+                                .clicked()
+                            {
+                                switch_to = Some(dir.clone());
+                            }
+                        }
+                    });
+                }
+
+                if let Some(root) = switch_to {
+                    self.set_explorer_root(root);
+                }
+                if pick_folder {
+                    self.open_folder_dialog();
+                }
+
+                // Expand/collapse/refresh buttons - OUTSIDE ScrollArea
+                ui.horizontal(|ui| {
+                    let expand_btn = ui.small_button("⊞").on_hover_text("Expand all directories");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Explorer: Expand All",
+                        "button",
+                        &expand_btn,
+                        None,
+                    );
+                    if expand_btn.clicked() {
+                        self.file_explorer.expand_all();
+                        self.reconcile_explorer_watches();
+                    }
+
+                    let collapse_btn = ui
+                        .small_button("⊟")
+                        .on_hover_text("Collapse all directories");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Explorer: Collapse All",
+                        "button",
+                        &collapse_btn,
+                        None,
+                    );
+                    if collapse_btn.clicked() {
+                        self.file_explorer.collapse_all();
+                        self.reconcile_explorer_watches();
+                    }
+
+                    if ui.small_button("↻").on_hover_text("Refresh").clicked() {
+                        self.file_explorer.refresh();
+                    }
+                });
+
+                // Sort order dropdown
+                ui.horizontal(|ui| {
+                    ui.label("Sort:");
+                    #[allow(unused_variables)]
+                    let current_label = self.file_explorer.sort_order.label();
+                    let combo_response = egui::ComboBox::from_id_salt("explorer_sort")
+                        .selected_text(self.file_explorer.sort_order.label())
+                        .show_ui(ui, |ui| {
+                            for order in [
+                                SortOrder::NameAsc,
+                                SortOrder::NameDesc,
+                                SortOrder::DateAsc,
+                                SortOrder::DateDesc,
+                            ] {
+                                let is_selected = self.file_explorer.sort_order == order;
+                                if ui.selectable_label(is_selected, order.label()).clicked() {
+                                    self.file_explorer.set_sort_order(order);
+                                }
+                            }
+                        });
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Explorer: Sort Order",
+                        "combobox",
+                        &combo_response.response,
+                        Some(current_label),
+                    );
+                    #[cfg(not(feature = "mcp"))]
+                    let _ = combo_response;
+                });
+
+                let links = ui.checkbox(&mut self.file_explorer.skip_symlinks, "Hide symlinks");
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge.register_widget(
+                    "Explorer: Hide Symlinks",
+                    "checkbox",
+                    &links,
+                    Some(if self.file_explorer.skip_symlinks {
+                        "on"
+                    } else {
+                        "off"
+                    }),
+                );
+                if links.changed() {
+                    self.file_explorer.refresh();
+                }
+
+                let readmes = ui
+                    .checkbox(
+                        &mut self.file_explorer.preview_readmes,
+                        "Preview folder READMEs",
+                    )
+                    .on_hover_text("Clicking a folder's name shows its README.md or index.md");
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge.register_widget(
+                    "Explorer: Preview Folder READMEs",
+                    "checkbox",
+                    &readmes,
+                    Some(if self.file_explorer.preview_readmes {
+                        "on"
+                    } else {
+                        "off"
+                    }),
+                );
+                #[cfg(not(feature = "mcp"))]
+                let _ = readmes;
+
+                let rooted = ui
+                    .checkbox(
+                        &mut self.file_explorer.root_relative_links,
+                        "Links from / start here",
+                    )
+                    .on_hover_text(
+                        "Links such as /docs/guide.md open the file in this folder \
+                         instead of at the filesystem root",
+                    );
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge.register_widget(
+                    "Explorer: Root Relative Links",
+                    "checkbox",
+                    &rooted,
+                    Some(if self.file_explorer.root_relative_links {
+                        "on"
+                    } else {
+                        "off"
+                    }),
+                );
+                #[cfg(not(feature = "mcp"))]
+                let _ = rooted;
+
+                ui.separator();
+
+                // Pre-load children for all expanded dirs to avoid mutation during render
+                for dir in self
+                    .file_explorer
+                    .expanded_dirs
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                {
+                    if self.file_explorer.get_children(&dir).is_none() {
+                        self.file_explorer.load_children(&dir);
+                    }
+                }
+
+                // Take tree to iterate without cloning, then put it back
+                let tree = std::mem::take(&mut self.file_explorer.tree);
+                // Clone the small set of open tab paths (avoids borrow conflict with &mut self)
+                let open_paths = self.open_tab_paths.clone();
+
+                // File tree inside ScrollArea
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .id_salt("file_explorer")
+                    .show(ui, |ui| {
+                        for node in &tree {
+                            let node_action = self.render_tree_node(ui, node, 0, &open_paths);
+                            if node_action.file_to_open.is_some() {
+                                action.file_to_open = node_action.file_to_open;
+                            }
+                            if node_action.file_to_close.is_some() {
+                                action.file_to_close = node_action.file_to_close;
+                            }
+                            if node_action.dir_to_toggle.is_some() {
+                                action.dir_to_toggle = node_action.dir_to_toggle;
+                            }
+                        }
+                    });
+
+                // Put tree back and apply deferred toggle
+                self.file_explorer.tree = tree;
+                if let Some(ref dir_path) = action.dir_to_toggle {
+                    self.file_explorer.toggle_expanded(dir_path);
+                    // Keep the non-recursive explorer watches in sync with the
+                    // newly expanded/collapsed directory.
+                    self.reconcile_explorer_watches();
+                }
+            });
+
+        action
+    }
+
+    /// Calculate flash intensity for a path (0.0 = no flash, 1.0 = full flash)
+    fn get_flash_intensity(&self, path: &PathBuf) -> f32 {
+        if self.flashing_paths.is_empty() {
+            return 0.0;
+        }
+
+        self.flashing_paths.get(path).map_or(0.0, |start_time| {
+            self.motion.flash_intensity(start_time.elapsed())
+        })
+    }
+
+    /// Render a single node in the file tree (recursive)
+    fn render_tree_node(
+        &mut self,
+        ui: &mut egui::Ui,
+        node: &FileTreeNode,
+        depth: usize,
+        open_paths: &HashSet<PathBuf>,
+    ) -> ExplorerAction {
+        let mut action = ExplorerAction::default();
+        let indent = depth * 16;
+
+        match node {
+            FileTreeNode::File {
+                path,
+                canonical,
+                is_symlink,
+                name,
+                display_name,
+                ..
+            } => {
+                // Calculate flash intensity for this file
+                let flash_intensity = self.get_flash_intensity(canonical);
+                let dark_mode = self.dark_mode;
+
+                // Render file row and get its rect
+                let row_response = ui.horizontal(|ui| {
+                    ui.add_space(indent as f32);
+
+                    // File icon
+                    ui.label("📄");
+
+                    // Highlight if file is open in a tab
+                    let is_open = open_paths.contains(canonical);
+                    let text = if is_open {
+                        egui::RichText::new(display_name.as_str()).strong()
+                    } else {
+                        egui::RichText::new(display_name.as_str())
+                    };
+
+                    let response = ui.selectable_label(is_open, text);
+                    if *is_symlink {
+                        symlink_badge(ui, canonical);
+                    }
+                    #[cfg(feature = "mcp")]
+                    {
+                        let state_value = if is_open { "open" } else { "" };
+                        self.mcp_bridge.register_widget(
+                            &format!("File: {}", name),
+                            "button",
+                            &response,
+                            Some(state_value),
+                        );
+                    }
+
+                    // Show full name on hover if truncated
+                    if display_name.len() != name.len() {
+                        response.clone().on_hover_text(name);
+                    }
+                    if response.clicked() {
+                        action.file_to_open = Some(path.clone());
+                    }
+                    // Middle-click to close tab (only if file is open)
+                    if response.middle_clicked() && is_open {
+                        action.file_to_close = Some(canonical.clone());
+                    }
+
+                    // Context menu for file actions
+                    response.context_menu(|ui| {
+                        if ui.button("Copy Contents").clicked() {
+                            if let Ok(contents) = fs::read_to_string(path) {
+                                ui.ctx().copy_text(contents);
+                            }
+                            ui.close();
+                        }
+                        if ui.button("Copy Path").clicked() {
+                            ui.ctx().copy_text(path.display().to_string());
+                            ui.close();
+                        }
+                        if ui.button("Copy File URI").clicked() {
+                            ui.ctx().copy_text(file_uri(path));
+                            ui.close();
+                        }
+                        ui.separator();
+                        if ui.button("Open Containing Folder").clicked() {
+                            reveal_in_file_manager(path);
+                            ui.close();
+                        }
+                    });
+                });
+
+                // Paint flash overlay on top using the row rect
+                if flash_intensity > 0.0 {
+                    let alpha = ((flash_intensity * 180.0) as u8).max(60);
+                    let flash_color = if dark_mode {
+                        egui::Color32::from_rgba_unmultiplied(60, 200, 60, alpha)
+                    } else {
+                        egui::Color32::from_rgba_unmultiplied(80, 200, 80, alpha)
+                    };
+                    let rect = row_response.response.rect;
+                    // Use debug_painter which draws on top of everything
+                    ui.ctx().debug_painter().rect_filled(rect, 4.0, flash_color);
+                }
+            }
+            FileTreeNode::Directory {
+                path,
+                canonical,
+                is_symlink,
+                name,
+                display_name,
+                ..
+            } => {
+                // Calculate flash intensity for this directory
+                let flash_intensity = self.get_flash_intensity(canonical);
+                let dark_mode = self.dark_mode;
+
+                // Track if we should toggle this frame (detected in closure, applied after)
+                let mut should_toggle = false;
+
+                // Get current expansion state
+                let is_expanded = self.file_explorer.is_expanded(path);
+
+                // Render directory row
+                let row_response = ui.horizontal(|ui| {
+                    ui.add_space(indent as f32);
+
+                    // Expand/collapse indicator
+                    let indicator = if is_expanded { "v" } else { ">" };
+
+                    #[cfg(feature = "mcp")]
+                    let expand_btn = ui.mcp_small_button(format!("Toggle: {}", name), indicator);
+                    #[cfg(not(feature = "mcp"))]
+                    let expand_btn = ui.small_button(indicator);
+
+                    if expand_btn.clicked() {
+                        should_toggle = true;
+                    }
+
+                    // Folder icon
+                    let folder_icon = if is_expanded { "📂" } else { "📁" };
+                    ui.label(folder_icon);
+
+                    let response = ui.add(
+                        egui::Label::new(display_name.as_str())
+                            .selectable(false)
+                            .sense(egui::Sense::click()),
+                    );
+                    if *is_symlink {
+                        symlink_badge(ui, canonical);
+                    }
+                    #[cfg(feature = "mcp")]
+                    {
+                        let state_value = if is_expanded { "expanded" } else { "collapsed" };
+                        self.mcp_bridge.register_widget(
+                            &format!("Directory: {}", name),
+                            "button",
+                            &response,
+                            Some(state_value),
+                        );
+                    }
+
+                    // Show full name on hover if truncated
+                    if display_name.len() != name.len() {
+                        response.clone().on_hover_text(name);
+                    }
+
+                    // Click directory name to preview its README, or else to
+                    // toggle expansion
+                    if response.clicked() {
+                        if self.file_explorer.preview_readmes {
+                            action.dir_to_preview = Some(path.clone());
+                        } else {
+                            should_toggle = true;
+                        }
+                    }
+
+                    // Context menu for directory actions
+                    response.context_menu(|ui| {
+                        if ui.button("Copy Path").clicked() {
+                            ui.ctx().copy_text(path.display().to_string());
+                            ui.close();
+                        }
+                        if ui.button("Copy File URI").clicked() {
+                            ui.ctx().copy_text(file_uri(path));
+                            ui.close();
+                        }
+                        ui.separator();
+                        if ui.button("Show in File Manager").clicked() {
+                            reveal_in_file_manager(path);
+                            ui.close();
+                        }
+                    });
+                });
+
+                // Defer toggle to after tree is restored (avoids clone)
+                if should_toggle {
+                    action.dir_to_toggle = Some(path.clone());
+                }
+
+                // Paint flash overlay on top using the row rect
+                if flash_intensity > 0.0 {
+                    let alpha = ((flash_intensity * 180.0) as u8).max(60);
+                    let flash_color = if dark_mode {
+                        egui::Color32::from_rgba_unmultiplied(60, 200, 60, alpha)
+                    } else {
+                        egui::Color32::from_rgba_unmultiplied(80, 200, 80, alpha)
+                    };
+                    let rect = row_response.response.rect;
+                    // Use debug_painter which draws on top of everything
+                    ui.ctx().debug_painter().rect_filled(rect, 4.0, flash_color);
+                }
+
+                // Render children if expanded (read from node directly, no clone needed)
+                if self.file_explorer.is_expanded(path) {
+                    if let FileTreeNode::Directory {
+                        children: Some(ref child_nodes),
+                        ..
+                    } = node
+                    {
+                        for child in child_nodes {
+                            let child_action =
+                                self.render_tree_node(ui, child, depth + 1, open_paths);
+                            if child_action.file_to_open.is_some() {
+                                action.file_to_open = child_action.file_to_open;
+                            }
+                            if child_action.file_to_close.is_some() {
+                                action.file_to_close = child_action.file_to_close;
+                            }
+                            if child_action.dir_to_toggle.is_some() {
+                                action.dir_to_toggle = child_action.dir_to_toggle;
+                            }
+                            if child_action.dir_to_preview.is_some() {
+                                action.dir_to_preview = child_action.dir_to_preview;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        action
+    }
+
+    fn render_lightbox(&mut self, ctx: &egui::Context) {
+        let Some(lightbox) = &mut self.lightbox else {
+            return;
+        };
+
+        let screen_rect = ctx.available_rect();
+        let mut should_close = false;
+
+        // 1. Semi-transparent backdrop (visual only, drawn below Tooltip layers)
+        ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Foreground,
+            egui::Id::new("lightbox_backdrop"),
+        ))
+        .rect_filled(
+            screen_rect,
+            0.0,
+            egui::Color32::from_rgba_unmultiplied(0, 0, 0, 200),
+        );
+
+        // 2. Full-screen input sink at Tooltip order — blocks all input to lower layers.
+        //    Clicking on the sink (outside image/close) closes the lightbox.
+        let oid = lightbox.open_id;
+        egui::Area::new(egui::Id::new("lightbox_sink").with(oid))
+            .order(egui::Order::Tooltip)
+            .movable(false)
+            .fixed_pos(screen_rect.min)
+            .show(ctx, |ui| {
+                let r = ui.allocate_response(screen_rect.size(), egui::Sense::click());
+                if r.clicked() {
+                    should_close = true;
+                }
+            });
+
+        // 3. Zoom controls (bottom-center): step out/in, current zoom, reset
+        let mut button_factor = 1.0_f32;
+        let mut reset = std::mem::take(&mut lightbox.pending_reset);
+        let zoom_pct = (lightbox.zoom * 100.0).round() as i32;
+        let at_rest = zoom_pct == 100 && lightbox.scroll_offset == egui::Vec2::ZERO;
+        egui::Area::new(egui::Id::new("lightbox_controls").with(oid))
+            .order(egui::Order::Tooltip)
+            .movable(false)
+            .pivot(egui::Align2::CENTER_BOTTOM)
+            .fixed_pos(egui::pos2(
+                screen_rect.center().x,
+                screen_rect.bottom() - 8.0,
+            ))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button("\u{2212}").on_hover_text("Zoom out").clicked() {
+                            button_factor = 1.0 / LIGHTBOX_ZOOM_STEP;
+                        }
+                        ui.add_sized(
+                            [48.0, ui.spacing().interact_size.y],
+                            egui::Label::new(format!("{zoom_pct}%")),
+                        );
+                        if ui.button("+").on_hover_text("Zoom in").clicked() {
+                            button_factor = LIGHTBOX_ZOOM_STEP;
+                        }
+                        if ui
+                            .add_enabled(!at_rest, egui::Button::new("Reset"))
+                            .on_hover_text("Back to 100% and centered (or double-click)")
+                            .clicked()
+                        {
+                            reset = true;
+                        }
+                    });
+                });
+            });
+
+        // 4. Apply scroll-wheel zoom (proportional to scroll amount for smooth feel)
+        //    and button zoom
+        let old_zoom = lightbox.zoom;
+        let wheel_zoom = self.lightbox_scroll != 0.0;
+        if wheel_zoom {
+            // ~10% per scroll notch, smooth with proportional delta
+            let factor = (1.0_f32 + self.lightbox_scroll * 0.08).clamp(0.5, 2.0);
+            lightbox.zoom = (lightbox.zoom * factor).clamp(0.1, 10.0);
+        }
+        lightbox.zoom = (lightbox.zoom * button_factor).clamp(0.1, 10.0);
+        if reset {
+            lightbox.zoom = 1.0;
+        }
+        let zoom_changed = (lightbox.zoom - old_zoom).abs() > f32::EPSILON;
+
+        // 5. Image — GPU texture scaling, no re-rasterization
+        let padding = 40.0;
+        let area_rect = screen_rect.shrink(padding);
+        let area_size = area_rect.size();
+        let zoom = lightbox.zoom;
+        let base_size = lightbox.base_size;
+        let display_size = base_size * zoom;
+        let tex_id = lightbox.texture.id();
+        let tex_uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+
+        // Use fixed_pos instead of anchor to avoid position jitter when content
+        // size changes (anchor recalculates position based on laid-out size,
+        // causing a one-frame shift when scrollbars appear/disappear).
+        let area_pos = egui::pos2(
+            screen_rect.center().x - area_size.x / 2.0,
+            screen_rect.center().y - area_size.y / 2.0,
+        );
+
+        // Pre-compute zoom-to-cursor offset BEFORE ScrollArea renders,
+        // so it uses the correct offset on the same frame (no one-frame jitter).
+        // Button zoom keeps the middle of the viewport in place instead.
+        let pre_offset: Option<egui::Vec2> = if reset {
+            Some(egui::Vec2::ZERO)
+        } else if zoom_changed {
+            let anchor = if wheel_zoom {
+                ctx.input(|i| i.pointer.latest_pos())
+            } else {
+                Some(area_rect.center())
+            };
+            anchor.map(|mouse_pos| {
+                // area_pos ≈ viewport origin (no scrollbar/frame margins)
+                let mouse_in_vp = mouse_pos - area_pos;
+
+                let old_display = base_size * old_zoom;
+                let new_display = display_size;
+
+                let old_content = egui::vec2(
+                    old_display.x.max(area_size.x),
+                    old_display.y.max(area_size.y),
+                );
+                let new_content = egui::vec2(
+                    new_display.x.max(area_size.x),
+                    new_display.y.max(area_size.y),
+                );
+
+                let old_pad = (old_content - old_display) * 0.5;
+                let new_pad = (new_content - new_display) * 0.5;
+
+                let frac = (mouse_in_vp + lightbox.scroll_offset - old_pad) / old_display;
+
+                let new_offset = frac * new_display + new_pad - mouse_in_vp;
+
+                let max_offset = egui::vec2(
+                    (new_content.x - area_size.x).max(0.0),
+                    (new_content.y - area_size.y).max(0.0),
+                );
+
+                egui::vec2(
+                    new_offset.x.clamp(0.0, max_offset.x),
+                    new_offset.y.clamp(0.0, max_offset.y),
+                )
+            })
+        } else {
+            None
+        };
+
+        if let Some(offset) = pre_offset {
+            lightbox.scroll_offset = offset;
+        }
+
+        egui::Area::new(egui::Id::new("lightbox_image").with(oid))
+            .order(egui::Order::Tooltip)
+            .movable(false)
+            .fixed_pos(area_pos)
+            .show(ctx, |ui| {
+                ui.set_min_size(area_size);
+                ui.set_max_size(area_size);
+
+                // Only enable scroll/drag on axes where image overflows the viewport
+                let mut scroll_area = egui::ScrollArea::new([
+                    display_size.x > area_size.x,
+                    display_size.y > area_size.y,
+                ])
+                .id_salt(egui::Id::new("lightbox_scroll").with(oid))
+                .max_width(area_size.x)
+                .max_height(area_size.y)
+                .scroll_bar_visibility(egui::scroll_area::ScrollBarVisibility::AlwaysHidden)
+                .scroll_source(egui::scroll_area::ScrollSource {
+                    drag: true,
+                    ..Default::default()
+                });
+
+                // Set the pre-computed offset so ScrollArea renders at the
+                // correct position on this frame (avoids one-frame lag).
+                if let Some(offset) = pre_offset {
+                    scroll_area = scroll_area
+                        .horizontal_scroll_offset(offset.x)
+                        .vertical_scroll_offset(offset.y);
+                }
+
+                let scroll_output = scroll_area.show(ui, |ui| {
+                    // Allocate content: at least area_size for centering,
+                    // larger when image overflows (for scroll panning)
+                    let content_size = egui::vec2(
+                        display_size.x.max(area_size.x),
+                        display_size.y.max(area_size.y),
+                    );
+                    let (content_rect, response) =
+                        ui.allocate_exact_size(content_size, egui::Sense::click());
+                    // Draw image centered within the content rect
+                    let image_rect =
+                        egui::Rect::from_center_size(content_rect.center(), display_size);
+                    if ui.is_rect_visible(image_rect) {
+                        let mut mesh = egui::Mesh::with_texture(tex_id);
+                        mesh.add_rect_with_uv(image_rect, tex_uv, egui::Color32::WHITE);
+                        ui.painter().add(egui::Shape::mesh(mesh));
+                    }
+                    if response.double_clicked() {
+                        lightbox.pending_reset = true;
+                        ui.ctx().request_repaint();
+                    }
+                    // Click outside image area → close lightbox
+                    if response.clicked() {
+                        if let Some(pos) = response.interact_pointer_pos() {
+                            if !image_rect.contains(pos) {
+                                should_close = true;
+                            }
+                        }
+                    }
+                });
+
+                // Sync tracking: captures drag-to-scroll changes on non-zoom frames
+                if pre_offset.is_none() {
+                    lightbox.scroll_offset = scroll_output.state.offset;
+                }
+            });
+
+        // 6. Close button (top-right)
+        egui::Area::new(egui::Id::new("lightbox_close").with(oid))
+            .order(egui::Order::Tooltip)
+            .movable(false)
+            .fixed_pos(egui::pos2(
+                screen_rect.right() - 48.0,
+                screen_rect.top() + 8.0,
+            ))
+            .show(ctx, |ui| {
+                let btn = ui.add(
+                    egui::Button::new(
+                        egui::RichText::new("\u{2715}")
+                            .size(20.0)
+                            .color(egui::Color32::from_gray(200)),
+                    )
+                    .frame(false),
+                );
+                if btn.hovered() {
+                    ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                }
+                if btn.clicked() {
+                    should_close = true;
+                }
+            });
+
+        // 7. Escape to close
+        ctx.input(|i| {
+            if i.key_pressed(egui::Key::Escape) {
+                should_close = true;
+            }
+        });
+
+        if should_close {
+            self.lightbox = None;
+        }
+    }
+
+    /// Everything saved between sessions, with the reading positions of the
+    /// open tabs brought up to date.
+    fn persisted_state(&mut self) -> PersistedState {
+        // Open tabs last, so they are the last to fall off the list
+        for idx in (0..self.tabs.len()).rev() {
+            self.remember_reading_position(idx);
+        }
+        PersistedState {
+            dark_mode: Some(self.dark_mode),
+            theme_mode: Some(self.theme_mode),
+            zoom_level: Some(self.zoom_level),
+            ui_scale: Some(self.ui_scale),
+            reduce_motion: Some(self.reduce_motion),
+            snap_to_headings: Some(self.snap_to_headings),
+            show_outline: Some(self.show_outline),
+            show_tasks: Some(self.show_tasks),
+            show_problems: Some(self.show_problems),
+            show_footnotes: Some(self.show_footnotes),
+            show_figures: Some(self.show_figures),
+            show_link_graph: Some(self.show_link_graph),
+            show_floating_toc: Some(self.show_floating_toc),
+            outline_reordering: Some(self.outline_reordering),
+            watch_always_poll: Some(self.poll_policy.always),
+            watch_poll_interval: Some(self.poll_policy.interval_secs),
+            watch_debounce_ms: Some(self.reload_policy.debounce_ms),
+            watch_min_reload_ms: Some(self.reload_policy.min_reload_ms),
+            watch_focused_only: Some(self.reload_policy.focused_only),
+            language: self.language.clone(),
+            full_width_content: Some(self.full_width_content),
+            content_width: Some(self.content_width),
+            center_content: Some(self.center_content),
+            syntax_theme_dark: Some(self.syntax_theme_dark.clone()),
+            syntax_theme_light: Some(self.syntax_theme_light.clone()),
+            show_line_numbers: Some(self.show_line_numbers),
+            show_color_swatches: Some(self.show_color_swatches),
+            copy_blocks: Some(self.copy_blocks),
+            dark_images: Some(self.dark_images),
+            email_quotes: Some(self.email_quotes),
+            equation_numbers: Some(self.equation_numbers),
+            open_tabs: Some(self.get_open_tab_paths()),
+            tab_histories: Some(
+                self.tabs
+                    .iter()
+                    .filter(|t| t.path.exists())
+                    .map(Tab::history)
+                    .collect(),
+            ),
+            active_tab: Some(self.active_tab),
+            show_explorer: Some(self.show_explorer),
+            explorer_root: self.file_explorer.root.clone(),
+            recent_roots: Some(self.recent_roots.clone()),
+            expanded_dirs: Some(self.file_explorer.expanded_dirs.iter().cloned().collect()),
+            explorer_sort_order: Some(self.file_explorer.sort_order),
+            explorer_skip_symlinks: Some(self.file_explorer.skip_symlinks),
+            explorer_preview_readmes: Some(self.file_explorer.preview_readmes),
+            explorer_root_relative_links: Some(self.file_explorer.root_relative_links),
+            recent_files: Some(self.recent_files.clone()),
+            reading_positions: Some(self.reading_positions.clone()),
+            editor_command: Some(self.editor_command.clone()),
+            allow_running_code: Some(self.allow_running_code),
+            restrict_untrusted: Some(self.restrict_untrusted),
+            trusted_folders: Some(self.trusted_folders.clone()),
+            export_toc_depth: Some(self.export_toc_depth),
+            number_headings: Some(self.number_headings),
+            page_preview: Some(self.page_preview),
+            page_size: Some(self.page_setup.size),
+            page_margins: Some(self.page_setup.margins),
+            rust_runner: Some(self.rust_runner.clone()),
+        }
+    }
+
+    /// Write the session to its snapshot file every
+    /// `SESSION_SNAPSHOT_INTERVAL` when it changed, for recovering it after a
+    /// crash.
+    fn snapshot_session(&mut self) {
+        // Until the user answers, the snapshot on disk is the crashed session
+        if self.recovered_session.is_some()
+            || self.last_session_snapshot.elapsed() < SESSION_SNAPSHOT_INTERVAL
+        {
+            return;
+        }
+        self.last_session_snapshot = Instant::now();
+        let Some(dir) = session_snapshot_dir() else {
+            return;
+        };
+        let Ok(json) = serde_json::to_string(&self.persisted_state()) else {
+            return;
+        };
+        if json == self.session_snapshot {
+            return;
+        }
+        let path = session_snapshot_file(&dir, std::process::id());
+        match write_session_snapshot(&path, &json) {
+            Ok(()) => self.session_snapshot = json,
+            Err(e) => log::warn!("Failed to snapshot the session to {}: {e}", path.display()),
+        }
+    }
+
+    /// Replace the open tabs and panel layout with those of `session`.
+    fn restore_session(&mut self, session: PersistedState) {
+        let reading_positions = session.reading_positions.unwrap_or_default();
+        self.tabs = session_tabs(
+            session.open_tabs.unwrap_or_default(),
+            session.tab_histories.unwrap_or_default(),
+            &reading_positions,
+        );
+        self.active_tab = session
+            .active_tab
+            .unwrap_or(0)
+            .min(self.tabs.len().saturating_sub(1));
+        self.title_dirty = true;
+        self.refresh_open_tab_paths();
+
+        self.show_outline = session.show_outline.unwrap_or(self.show_outline);
+        self.show_tasks = session.show_tasks.unwrap_or(self.show_tasks);
+        self.show_problems = session.show_problems.unwrap_or(self.show_problems);
+        self.show_footnotes = session.show_footnotes.unwrap_or(self.show_footnotes);
+        self.show_figures = session.show_figures.unwrap_or(self.show_figures);
+        self.show_link_graph = session.show_link_graph.unwrap_or(self.show_link_graph);
+        self.show_floating_toc = session.show_floating_toc.unwrap_or(self.show_floating_toc);
+        if let Some(expanded) = session.expanded_dirs {
+            self.file_explorer.expanded_dirs = expanded.into_iter().collect();
+        }
+        match session.explorer_root.filter(|root| root.exists()) {
+            Some(root) => self.set_explorer_root(root),
+            None if self.watch_enabled => self.update_watched_paths(),
+            None => {}
+        }
+        self.show_explorer = session.show_explorer.unwrap_or(self.show_explorer);
+    }
+
+    /// After a crash, ask whether to reopen the tabs that were open then.
+    fn render_session_recovery(&mut self, ctx: &egui::Context) {
+        let Some(session) = &self.recovered_session else {
+            return;
+        };
+        let tabs = session.open_tabs.as_ref().map_or(0, Vec::len);
+        let mut restore = false;
+        let mut discard = false;
+        let modal = egui::Modal::new(egui::Id::new("session_recovery")).show(ctx, |ui| {
+            ui.set_max_width(420.0);
+            ui.heading("Restore previous session?");
+            ui.label(format!(
+                "md-viewer didn't close properly last time. Reopen the {tabs} {} that {} open then?",
+                if tabs == 1 { "tab" } else { "tabs" },
+                if tabs == 1 { "was" } else { "were" },
+            ));
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                restore = ui.button("Restore").clicked();
+                discard = ui.button("Discard").clicked();
+            });
+        });
+        if !(restore || discard || modal.should_close()) {
+            return;
+        }
+        if let Some(session) = self.recovered_session.take().filter(|_| restore) {
+            self.restore_session(session);
+        }
+    }
+}
+
+impl eframe::App for MarkdownApp {
+    fn raw_input_hook(&mut self, _ctx: &egui::Context, raw_input: &mut egui::RawInput) {
+        #[cfg(feature = "mcp")]
+        {
+            self.mcp_bridge.process_commands();
+            self.mcp_bridge.inject_raw_input(raw_input);
+        }
+
+        // When lightbox is open, intercept scroll events before they reach InputState.
+        // This prevents the document's ScrollArea from scrolling while letting us
+        // use the captured delta for lightbox zoom.
+        self.lightbox_scroll = 0.0;
+        if self.lightbox.is_some() {
+            for event in &raw_input.events {
+                if let egui::Event::MouseWheel { delta, .. } = event {
+                    self.lightbox_scroll += delta.y;
+                }
+            }
+            raw_input
+                .events
+                .retain(|e| !matches!(e, egui::Event::MouseWheel { .. }));
+        }
+    }
+
+    fn persist_egui_memory(&self) -> bool {
+        false // Don't persist egui's internal Memory (widget states, panel sizes, etc.)
+              // Our PersistedState handles everything we need; egui's blob grows unbounded (~170KB)
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, APP_KEY, &self.persisted_state());
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // A clean exit: nothing to recover at the next start. Other viewers
+        // keep their snapshots.
+        if let Some(dir) = session_snapshot_dir() {
+            let _ = fs::remove_file(session_snapshot_file(&dir, std::process::id()));
+        }
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Enable AccessKit for MCP bridge
+        #[cfg(feature = "mcp")]
+        {
+            ctx.enable_accesskit();
+            self.mcp_bridge.begin_frame();
+            self.mcp_bridge.store_in_context(ctx); // Enable McpUiExt methods
+                                                   // Poll for MCP commands at reasonable rate (prevents CPU spin on virtual displays)
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+
+        // Commands from scripts and editors over the remote control socket
+        while let Some(request) = self.remote.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            let result = AutomationCommand::parse(&request.line)
+                .and_then(|command| self.run_automation_command(command));
+            request.reply(result);
+        }
+
+        // Check for file changes and reload affected tabs
+        let changed_paths = self.check_file_changes();
+        let focused = ctx.input(|i| i.focused);
+        let due =
+            self.reload_batch
+                .take_due(changed_paths, &self.reload_policy, focused, Instant::now());
+        if !due.is_empty() {
+            self.reload_changed_tabs(due);
+        }
+        // Focusing the window repaints by itself
+        if focused {
+            if let Some(wait) = self
+                .reload_batch
+                .next_due(&self.reload_policy, Instant::now())
+            {
+                ctx.request_repaint_after(wait);
+            }
+        }
+        self.retry_missing_files(ctx);
+
+        // Poll for async GVFS directory scan completion
+        if self.file_explorer.pending_scan.is_some() {
+            if self.file_explorer.poll_pending_scan() {
+                log::info!("GVFS directory scan completed");
+            }
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        // Clean up expired flash effects and request repaints while animating
+        if !self.flashing_paths.is_empty() {
+            let flash_duration = self.motion.flash_duration;
+            self.flashing_paths
+                .retain(|_, start_time| start_time.elapsed() < flash_duration);
+
+            // Request repaints while there are active flashes
+            if !self.flashing_paths.is_empty() {
+                ctx.request_repaint_after(Duration::from_millis(16)); // ~60fps for smooth animation
+            }
+        }
+
+        // Limit frame rate on virtual displays (e.g., Xvfb) which lack vsync
+        // request_repaint_after alone doesn't limit actual frame rate, so we sleep
+        // This prevents 500%+ CPU usage during E2E testing
+        if self.is_virtual_display {
+            std::thread::sleep(Duration::from_millis(16)); // ~60 FPS cap
+        }
+
+        // In Auto mode, follow the OS theme (egui-winit updates it on change)
+        self.dark_mode = self
+            .theme_mode
+            .resolve_dark(ctx.system_theme(), self.dark_mode);
+
+        // Apply theme settings only when dark_mode changes
+        if self.last_applied_dark_mode != Some(self.dark_mode) {
+            self.last_applied_dark_mode = Some(self.dark_mode);
+            // Pin egui's own theme so `set_visuals` below lands in the style
+            // that is actually active; with egui's default System preference
+            // an OS switch would otherwise flip to the unstyled other theme.
+            ctx.set_theme(if self.dark_mode {
+                egui::Theme::Dark
+            } else {
+                egui::Theme::Light
+            });
+            let visuals = if self.dark_mode {
+                let mut v = egui::Visuals::dark();
+                v.panel_fill = egui::Color32::from_rgb(0x12, 0x12, 0x12);
+                v.window_fill = egui::Color32::from_rgb(0x12, 0x12, 0x12);
+                v.extreme_bg_color = egui::Color32::from_rgb(0x1E, 0x1E, 0x1E);
+                v.override_text_color = Some(egui::Color32::from_rgb(0xE0, 0xE0, 0xE0));
+                v
+            } else {
+                let mut v = egui::Visuals::light();
+                v.panel_fill = egui::Color32::from_rgb(0xF8, 0xF8, 0xF8);
+                v.window_fill = egui::Color32::from_rgb(0xF8, 0xF8, 0xF8);
+                v.extreme_bg_color = egui::Color32::from_rgb(0xF0, 0xF0, 0xF0);
+                v.override_text_color = Some(egui::Color32::from_rgb(0x33, 0x33, 0x33));
+                v
+            };
+            ctx.set_visuals(visuals);
+        }
+
+        ctx.set_zoom_factor(self.ui_scale);
+
+        // The title shows the progress of background jobs, and loses it
+        // once the last one is done
+        if !self.jobs.is_empty() {
+            self.title_dirty = true;
+        }
+        self.jobs.prune();
+        self.poll_article_downloads();
+        self.poll_site_export();
+        for tab in &mut self.tabs {
+            if let Some(comparison) = &mut tab.compare {
+                comparison.poll(&mut self.jobs, ctx);
+            }
+        }
+
+        // Update window title only when dirty
+        if self.title_dirty {
+            self.title_dirty = false;
+            let title = self.window_title();
+            if title != self.last_window_title {
+                self.last_window_title = title;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Title(self.last_window_title.clone()));
+            }
+        }
+
+        // Handle keyboard shortcuts from the keymap (most are suppressed
+        // while the lightbox is open; zoom applies to it instead)
+        let state = keymap::State {
+            lightbox: self.lightbox.is_some(),
+            searching: self.search.is_open,
+            typing: ctx.wants_keyboard_input(),
+        };
+        let pressed = ctx.input(|i| keymap::pressed(i, &state));
+        for (action, chord) in pressed {
+            self.run_key_action(ctx, action, chord);
+        }
+
+        if self.lightbox.is_none() {
+            // Ctrl + scroll wheel for zoom
+            ctx.input(|i| {
+                if i.modifiers.ctrl && i.raw_scroll_delta.y != 0.0 {
+                    self.zoom_level = (self.zoom_level
+                        + if i.raw_scroll_delta.y > 0.0 {
+                            0.1
+                        } else {
+                            -0.1
+                        })
+                    .clamp(0.5, 3.0);
+                }
+            });
+        }
+        self.poll_clipboard_tab(ctx);
+
+        // Handle drag and drop
+        self.is_dragging = false;
+        ctx.input(|i| {
+            if !i.raw.hovered_files.is_empty() {
+                self.is_dragging = true;
+            }
+
+            for file in &i.raw.dropped_files {
+                if let Some(path) = &file.path {
+                    if Self::is_markdown_file(path) || reader::is_html(path) {
+                        self.open_in_new_tab(path.clone());
+                    } else {
+                        self.error_message = Some(
+                            "Unsupported file type. Please drop a markdown file (.md, .markdown, .txt) or an HTML page".to_string(),
+                        );
+                    }
+                }
+            }
+        });
+
+        // Get ctrl state for link handling
+        let ctrl_held = ctx.input(|i| i.modifiers.ctrl || i.modifiers.command);
+
+        // Menu bar
+        let lang = self.ui_language.clone();
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::MenuBar::new().ui(ui, |ui| {
+                ui.menu_button(tr(&lang, "File"), |ui| {
+                    if ui
+                        .add(egui::Button::new(tr(&lang, "New Tab...")).shortcut_text("Ctrl+T"))
+                        .clicked()
+                    {
+                        self.open_file_dialog();
+                        ui.close();
+                    }
+
+                    if ui
+                        .add(
+                            egui::Button::new(tr(&lang, "New Tab from Clipboard"))
+                                .shortcut_text("Ctrl+Shift+V"),
+                        )
+                        .clicked()
+                    {
+                        self.request_clipboard_tab(ctx);
+                        ui.close();
+                    }
+
+                    if ui
+                        .button(tr(&lang, "Compare Files..."))
+                        .on_hover_text("Show the changes between two documents, block by block")
+                        .clicked()
+                    {
+                        self.compare_files_dialog();
+                        ui.close();
+                    }
+
+                    if ui.button(tr(&lang, "Open Folder...")).clicked() {
+                        self.open_folder_dialog();
+                        ui.close();
+                    }
+
+                    let unsaved = self
+                        .tabs
+                        .get(self.active_tab)
+                        .is_some_and(|tab| tab.unsaved.is_some());
+                    if ui
+                        .add_enabled(unsaved, egui::Button::new(tr(&lang, "Save As...")))
+                        .clicked()
+                    {
+                        self.save_unsaved_tab();
+                        ui.close();
+                    }
+
+                    if ui
+                        .add(egui::Button::new(tr(&lang, "Close Tab")).shortcut_text("Ctrl+W"))
+                        .clicked()
+                    {
+                        self.close_active_tab();
+                        ui.close();
+                    }
+
+                    ui.separator();
+
+                    let has_tab = !self.tabs.is_empty();
+                    if ui
+                        .add_enabled(
+                            has_tab,
+                            egui::Button::new(tr(&lang, "Open in Editor")).shortcut_text("Ctrl+E"),
+                        )
+                        .clicked()
+                    {
+                        self.open_in_editor();
+                        ui.close();
+                    }
+
+                    ui.menu_button(tr(&lang, "Editor Command"), |ui| {
+                        ui.label(
+                            egui::RichText::new(
+                                "{file} and {line} are filled in. The editor runs without a \
+                                 terminal, so a terminal editor such as vim needs one: \
+                                 kitty -e vim +{line} {file}",
+                            )
+                            .weak()
+                            .small(),
+                        );
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.editor_command)
+                                .hint_text("code --goto {file}:{line}")
+                                .desired_width(240.0),
+                        );
+                    });
+
+                    let can_export = has_tab || self.file_explorer.root.is_some();
+                    if ui
+                        .add_enabled(can_export, egui::Button::new(tr(&lang, "Export Site...")))
+                        .on_hover_text(
+                            "Write every markdown file in the explorer folder, or the open \
+                             documents, as linked HTML pages with an index",
+                        )
+                        .clicked()
+                    {
+                        self.export_site(ctx);
+                        ui.close();
+                    }
+                    ui.menu_button(tr(&lang, "Export Contents"), |ui| {
+                        for depth in toc::DEPTHS {
+                            let text = match depth {
+                                0 => "Off".to_string(),
+                                1 => "1 Level".to_string(),
+                                6 => "All Levels".to_string(),
+                                _ => format!("{depth} Levels"),
+                            };
+                            let selected = self.export_toc_depth == depth;
+                            if ui.button(checked_text(selected, &text)).clicked() {
+                                self.export_toc_depth = depth;
+                                ui.close();
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("The table of contents HTML exports start with");
+
+                    ui.separator();
+
+                    if ui
+                        .add(egui::Button::new(tr(&lang, "Find...")).shortcut_text("Ctrl+F"))
+                        .clicked()
+                    {
+                        self.search.is_open = true;
+                        self.search.focus_requested = true;
+                        ui.close();
+                    }
+
+                    if ui
+                        .add(
+                            egui::Button::new(tr(&lang, "Command Palette..."))
+                                .shortcut_text("Ctrl+Shift+P"),
+                        )
+                        .on_hover_text("Run the commands of your scripts")
+                        .clicked()
+                    {
+                        self.command_palette = Some(String::new());
+                        ui.close();
+                    }
+
+                    ui.separator();
+
+                    let run_text = checked_text(
+                        self.allow_running_code,
+                        tr(&lang, "Allow Running Code Blocks"),
+                    );
+                    if ui
+                        .button(run_text)
+                        .on_hover_text("Show a Run button below bash, sh and rust code blocks")
+                        .clicked()
+                    {
+                        self.allow_running_code = !self.allow_running_code;
+                        ui.close();
+                    }
+
+                    let restrict_text = checked_text(
+                        self.restrict_untrusted,
+                        tr(&lang, "Restrict Untrusted Documents"),
+                    );
+                    if ui
+                        .button(restrict_text)
+                        .on_hover_text(
+                            "Outside the trusted folders, don't load remote or data: images, \
+                             show raw HTML as text and ask before opening links",
+                        )
+                        .clicked()
+                    {
+                        self.restrict_untrusted = !self.restrict_untrusted;
+                        ui.close();
+                    }
+
+                    ui.menu_button(tr(&lang, "Trusted Folders"), |ui| {
+                        if self.trusted_folders.is_empty() {
+                            ui.label(egui::RichText::new("No trusted folders").weak().small());
+                        }
+                        let mut untrusted = None;
+                        for (i, folder) in self.trusted_folders.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                if ui.small_button("✕").on_hover_text("Stop trusting").clicked() {
+                                    untrusted = Some(i);
+                                }
+                                ui.label(folder.display().to_string());
+                            });
+                        }
+                        if let Some(i) = untrusted {
+                            self.trusted_folders.remove(i);
+                        }
+                        ui.separator();
+                        let folder = self
+                            .tabs
+                            .get(self.active_tab)
+                            .filter(|tab| tab.unsaved.is_none())
+                            .and_then(|tab| tab.path.parent())
+                            .map(Path::to_path_buf);
+                        let trusted = folder
+                            .as_deref()
+                            .is_some_and(|folder| is_trusted(folder, &self.trusted_folders));
+                        if ui
+                            .add_enabled(
+                                folder.is_some() && !trusted,
+                                egui::Button::new("Trust This Document's Folder"),
+                            )
+                            .clicked()
+                        {
+                            self.trust_folder(folder.unwrap_or_default());
+                            ui.close();
+                        }
+                        if ui.button("Add Folder...").clicked() {
+                            if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                                self.trust_folder(folder);
+                            }
+                            ui.close();
+                        }
+                    });
+
+                    ui.menu_button(tr(&lang, "Rust Runner"), |ui| {
+                        ui.label(
+                            egui::RichText::new(
+                                "{file} is a .rs file with the block's code; empty disables it",
+                            )
+                            .weak()
+                            .small(),
+                        );
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.rust_runner)
+                                .hint_text(DEFAULT_RUST_RUNNER)
+                                .desired_width(240.0),
+                        );
+                    });
+
+                    let is_watching = self.watcher.is_some();
+                    let watch_text = checked_text(is_watching, tr(&lang, "Watch Files"));
+                    if ui
+                        .add(egui::Button::new(watch_text).shortcut_text("F5"))
+                        .clicked()
+                    {
+                        if is_watching {
+                            self.disable_watching();
+                        } else {
+                            self.watch_enabled = true;
+                            self.start_watching();
+                        }
+                        ui.close();
+                    }
+
+                    ui.menu_button(tr(&lang, "Watch Mode"), |ui| {
+                        let mut changed = false;
+                        let policy = &mut self.poll_policy;
+                        if ui
+                            .selectable_label(!policy.always, "Automatic")
+                            .on_hover_text("Poll only files on network filesystems, or when file notifications fail")
+                            .clicked()
+                        {
+                            policy.always = false;
+                            changed = true;
+                        }
+                        if ui
+                            .selectable_label(policy.always, "Always Poll")
+                            .on_hover_text("Check every file's modification time instead of using file notifications")
+                            .clicked()
+                        {
+                            policy.always = true;
+                            changed = true;
+                        }
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("Poll every");
+                            let interval = ui.add(
+                                egui::DragValue::new(&mut policy.interval_secs)
+                                    .range(1..=60)
+                                    .suffix(" s"),
+                            );
+                            // Restarting on every drag step would rebuild the watchers
+                            changed |= interval.lost_focus() || interval.drag_stopped();
+                        });
+                        ui.separator();
+                        let reload = &mut self.reload_policy;
+                        ui.horizontal(|ui| {
+                            ui.label("Wait for writes to settle");
+                            let debounce = ui.add(
+                                egui::DragValue::new(&mut reload.debounce_ms)
+                                    .range(50..=5000)
+                                    .speed(10)
+                                    .suffix(" ms"),
+                            );
+                            changed |= debounce.lost_focus() || debounce.drag_stopped();
+                        })
+                        .response
+                        .on_hover_text("How long a file must stop changing before it's reloaded");
+                        ui.horizontal(|ui| {
+                            ui.label("Reload a tab at most every");
+                            ui.add(
+                                egui::DragValue::new(&mut reload.min_reload_ms)
+                                    .range(0..=60_000)
+                                    .speed(50)
+                                    .suffix(" ms"),
+                            );
+                        })
+                        .response
+                        .on_hover_text("Changes in between are batched into one reload; 0 reloads on every change");
+                        ui.checkbox(&mut reload.focused_only, "Reload Only When Focused")
+                            .on_hover_text("Hold changes back while the window is in the background, and reload once it's focused");
+                        if changed && self.watcher.is_some() {
+                            self.start_watching();
+                        }
+                    });
+
+                    ui.separator();
+
+                    if ui
+                        .add(egui::Button::new(tr(&lang, "Quit")).shortcut_text("Ctrl+Q"))
+                        .clicked()
+                    {
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                        ui.close();
+                    }
+                });
+
+                ui.menu_button(tr(&lang, "Navigate"), |ui| {
+                    let can_back = self
+                        .tabs
+                        .get(self.active_tab)
+                        .map(|t| t.can_go_back())
+                        .unwrap_or(false);
+                    if ui
+                        .add_enabled(
+                            can_back,
+                            egui::Button::new(tr(&lang, "← Back")).shortcut_text("Alt+←"),
+                        )
+                        .clicked()
+                    {
+                        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                            tab.navigate_back();
+                        }
+                        ui.close();
+                    }
+
+                    let can_forward = self
+                        .tabs
+                        .get(self.active_tab)
+                        .map(|t| t.can_go_forward())
+                        .unwrap_or(false);
+                    if ui
+                        .add_enabled(
+                            can_forward,
+                            egui::Button::new(tr(&lang, "→ Forward")).shortcut_text("Alt+→"),
+                        )
+                        .clicked()
+                    {
+                        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                            tab.navigate_forward();
+                        }
+                        ui.close();
+                    }
+                });
+
+                #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                let view_menu = ui.menu_button(tr(&lang, "View"), |ui| {
+                    let theme_text = if self.dark_mode {
+                        tr(&lang, "☀ Light Mode")
+                    } else {
+                        tr(&lang, "🌙 Dark Mode")
+                    };
+                    let theme_btn = ui.add(egui::Button::new(theme_text).shortcut_text("Ctrl+D"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Dark Mode",
+                        "button",
+                        &theme_btn,
+                        Some(if self.dark_mode { "dark" } else { "light" }),
+                    );
+                    if theme_btn.clicked() {
+                        self.theme_mode = ThemeMode::toggled(self.dark_mode);
+                        self.dark_mode = !self.dark_mode;
+                        ui.close();
+                    }
+
+                    let follow_system = self.theme_mode == ThemeMode::Auto;
+                    let system_text = checked_text(follow_system, tr(&lang, "Follow System Theme"));
+                    let system_btn = ui.add(egui::Button::new(system_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Follow System Theme",
+                        "button",
+                        &system_btn,
+                        Some(if follow_system { "on" } else { "off" }),
+                    );
+                    if system_btn.clicked() {
+                        self.theme_mode = if follow_system {
+                            ThemeMode::toggled(!self.dark_mode)
+                        } else {
+                            ThemeMode::Auto
+                        };
+                        ui.close();
+                    }
+
+                    let explorer_text =
+                        checked_text(self.show_explorer, tr(&lang, "Show Explorer"));
+                    let explorer_btn =
+                        ui.add(egui::Button::new(explorer_text).shortcut_text("Ctrl+Shift+E"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Show Explorer",
+                        "button",
+                        &explorer_btn,
+                        Some(if self.show_explorer { "on" } else { "off" }),
+                    );
+                    if explorer_btn.clicked() {
+                        self.show_explorer = !self.show_explorer;
+                        ui.close();
+                    }
+
+                    let outline_text = checked_text(self.show_outline, tr(&lang, "Show Outline"));
+                    let outline_btn =
+                        ui.add(egui::Button::new(outline_text).shortcut_text("Ctrl+Shift+O"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Show Outline",
+                        "button",
+                        &outline_btn,
+                        Some(if self.show_outline { "on" } else { "off" }),
+                    );
+                    if outline_btn.clicked() {
+                        self.show_outline = !self.show_outline;
+                        ui.close();
+                    }
+
+                    let tasks_text = checked_text(self.show_tasks, tr(&lang, "Show Tasks"));
+                    let tasks_btn =
+                        ui.add(egui::Button::new(tasks_text).shortcut_text("Ctrl+Shift+K"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Show Tasks",
+                        "button",
+                        &tasks_btn,
+                        Some(if self.show_tasks { "on" } else { "off" }),
+                    );
+                    if tasks_btn.clicked() {
+                        self.show_tasks = !self.show_tasks;
+                        ui.close();
+                    }
+
+                    let problems_text =
+                        checked_text(self.show_problems, tr(&lang, "Show Problems"));
+                    let problems_btn =
+                        ui.add(egui::Button::new(problems_text).shortcut_text("Ctrl+Shift+M"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Show Problems",
+                        "button",
+                        &problems_btn,
+                        Some(if self.show_problems { "on" } else { "off" }),
+                    );
+                    if problems_btn.clicked() {
+                        self.show_problems = !self.show_problems;
+                        ui.close();
+                    }
+
+                    let footnotes_text =
+                        checked_text(self.show_footnotes, tr(&lang, "Show Footnotes"));
+                    let footnotes_btn = ui.add(egui::Button::new(footnotes_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Show Footnotes",
+                        "button",
+                        &footnotes_btn,
+                        Some(if self.show_footnotes { "on" } else { "off" }),
+                    );
+                    if footnotes_btn.clicked() {
+                        self.show_footnotes = !self.show_footnotes;
+                        ui.close();
+                    }
+
+                    let figures_text =
+                        checked_text(self.show_figures, tr(&lang, "Show Figures and Tables"));
+                    let figures_btn = ui.add(egui::Button::new(figures_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Show Figures and Tables",
+                        "button",
+                        &figures_btn,
+                        Some(if self.show_figures { "on" } else { "off" }),
+                    );
+                    if figures_btn.clicked() {
+                        self.show_figures = !self.show_figures;
+                        ui.close();
+                    }
+
+                    let graph_text =
+                        checked_text(self.show_link_graph, tr(&lang, "Show Link Graph"));
+                    let graph_btn = ui.add_enabled(
+                        self.file_explorer.root.is_some(),
+                        egui::Button::new(graph_text),
+                    );
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Show Link Graph",
+                        "button",
+                        &graph_btn,
+                        Some(if self.show_link_graph { "on" } else { "off" }),
+                    );
+                    if graph_btn.clicked() {
+                        self.show_link_graph = !self.show_link_graph;
+                        ui.close();
+                    }
+
+                    let floating_toc_text =
+                        checked_text(self.show_floating_toc, tr(&lang, "Floating Contents"));
+                    let floating_toc_btn = ui.add(egui::Button::new(floating_toc_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Floating Contents",
+                        "button",
+                        &floating_toc_btn,
+                        Some(if self.show_floating_toc { "on" } else { "off" }),
+                    );
+                    if floating_toc_btn.clicked() {
+                        self.show_floating_toc = !self.show_floating_toc;
+                        self.floating_toc_open = false;
+                        ui.close();
+                    }
+
+                    let reorder_text = checked_text(
+                        self.outline_reordering,
+                        tr(&lang, "Reorder Sections in Outline"),
+                    );
+                    let reorder_btn = ui
+                        .add(egui::Button::new(reorder_text))
+                        .on_hover_text("Drag outline headings to move their sections in the file");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Reorder Sections in Outline",
+                        "button",
+                        &reorder_btn,
+                        Some(if self.outline_reordering { "on" } else { "off" }),
+                    );
+                    if reorder_btn.clicked() {
+                        self.outline_reordering = !self.outline_reordering;
+                        ui.close();
+                    }
+
+                    let full_width_text =
+                        checked_text(self.full_width_content, tr(&lang, "Full Width"));
+                    let full_width_btn = ui.add(egui::Button::new(full_width_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Full Width",
+                        "button",
+                        &full_width_btn,
+                        Some(if self.full_width_content { "on" } else { "off" }),
+                    );
+                    if full_width_btn.clicked() {
+                        self.full_width_content = !self.full_width_content;
+                        ui.close();
+                    }
+
+                    #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                    let width_menu = ui.menu_button(tr(&lang, "Content Width"), |ui| {
+                        for preset in CONTENT_WIDTH_PRESETS {
+                            let selected = !self.full_width_content
+                                && (self.content_width - preset).abs() < 0.5;
+                            let text = checked_text(selected, &format!("{preset:.0} px"));
+                            if ui.button(text).clicked() {
+                                self.content_width = preset;
+                                self.full_width_content = false;
+                                ui.close();
+                            }
+                        }
+                        ui.separator();
+                        #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                        let slider = ui.add_enabled(
+                            !self.full_width_content,
+                            egui::Slider::new(&mut self.content_width, CONTENT_WIDTH_RANGE)
+                                .step_by(10.0)
+                                .suffix(" px"),
+                        );
+                        #[cfg(feature = "mcp")]
+                        self.mcp_bridge.register_widget(
+                            "Menu: View → Content Width",
+                            "slider",
+                            &slider,
+                            Some(format!("{:.0}", self.content_width).as_str()),
+                        );
+                    });
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Content Width Menu",
+                        "button",
+                        &width_menu.response,
+                        None,
+                    );
+
+                    let center_text =
+                        checked_text(self.center_content, tr(&lang, "Center Content"));
+                    let center_btn = ui.add(egui::Button::new(center_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Center Content",
+                        "button",
+                        &center_btn,
+                        Some(if self.center_content { "on" } else { "off" }),
+                    );
+                    if center_btn.clicked() {
+                        self.center_content = !self.center_content;
+                        ui.close();
+                    }
+
+                    let line_numbers_text =
+                        checked_text(self.show_line_numbers, tr(&lang, "Source Line Numbers"));
+                    let line_numbers_btn = ui.add(egui::Button::new(line_numbers_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Source Line Numbers",
+                        "button",
+                        &line_numbers_btn,
+                        Some(if self.show_line_numbers { "on" } else { "off" }),
+                    );
+                    if line_numbers_btn.clicked() {
+                        self.show_line_numbers = !self.show_line_numbers;
+                        ui.close();
+                    }
+
+                    let swatches_text =
+                        checked_text(self.show_color_swatches, tr(&lang, "Color Swatches"));
+                    let swatches_btn = ui.add(egui::Button::new(swatches_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Color Swatches",
+                        "button",
+                        &swatches_btn,
+                        Some(if self.show_color_swatches {
+                            "on"
+                        } else {
+                            "off"
+                        }),
+                    );
+                    if swatches_btn.clicked() {
+                        self.show_color_swatches = !self.show_color_swatches;
+                        ui.close();
+                    }
+
+                    let copy_blocks_text =
+                        checked_text(self.copy_blocks, tr(&lang, "Copy Buttons on Paragraphs"));
+                    let copy_blocks_btn = ui.add(egui::Button::new(copy_blocks_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Copy Buttons on Paragraphs",
+                        "button",
+                        &copy_blocks_btn,
+                        Some(if self.copy_blocks { "on" } else { "off" }),
+                    );
+                    if copy_blocks_btn.clicked() {
+                        self.copy_blocks = !self.copy_blocks;
+                        ui.close();
+                    }
+
+                    let email_quotes_text =
+                        checked_text(self.email_quotes, tr(&lang, "Email Quote Style"));
+                    let email_quotes_btn = ui.add(egui::Button::new(email_quotes_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Email Quote Style",
+                        "button",
+                        &email_quotes_btn,
+                        Some(if self.email_quotes { "on" } else { "off" }),
+                    );
+                    if email_quotes_btn.clicked() {
+                        self.email_quotes = !self.email_quotes;
+                        ui.close();
+                    }
+
+                    let equation_numbers_text =
+                        checked_text(self.equation_numbers, tr(&lang, "Equation Numbers"));
+                    let equation_numbers_btn = ui.add(egui::Button::new(equation_numbers_text));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Equation Numbers",
+                        "button",
+                        &equation_numbers_btn,
+                        Some(if self.equation_numbers { "on" } else { "off" }),
+                    );
+                    if equation_numbers_btn.clicked() {
+                        self.equation_numbers = !self.equation_numbers;
+                        ui.close();
+                    }
+
+                    let number_headings_text =
+                        checked_text(self.number_headings, tr(&lang, "Number Headings"));
+                    let number_headings_btn = ui
+                        .add(egui::Button::new(number_headings_text))
+                        .on_hover_text("Number the headings 1, 1.1, 1.2, … here and in HTML exports");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Number Headings",
+                        "button",
+                        &number_headings_btn,
+                        Some(if self.number_headings { "on" } else { "off" }),
+                    );
+                    if number_headings_btn.clicked() {
+                        self.number_headings = !self.number_headings;
+                        ui.close();
+                    }
+
+                    let tasks_only = self
+                        .tabs
+                        .get(self.active_tab)
+                        .is_some_and(|tab| tab.task_view.is_some());
+                    let tasks_only_btn = ui.add_enabled(
+                        !self.tabs.is_empty(),
+                        egui::Button::new(checked_text(tasks_only, tr(&lang, "Only Tasks"))),
+                    );
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Only Tasks",
+                        "button",
+                        &tasks_only_btn,
+                        Some(if tasks_only { "on" } else { "off" }),
+                    );
+                    if tasks_only_btn.clicked() {
+                        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                            tab.toggle_task_view();
+                        }
+                        ui.close();
+                    }
+
+                    let source = self
+                        .tabs
+                        .get(self.active_tab)
+                        .is_some_and(|tab| tab.source_view.is_some());
+                    let source_btn = ui.add_enabled(
+                        !self.tabs.is_empty(),
+                        egui::Button::new(checked_text(source, tr(&lang, "View Source")))
+                            .shortcut_text("Ctrl+U"),
+                    );
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → View Source",
+                        "button",
+                        &source_btn,
+                        Some(if source { "on" } else { "off" }),
+                    );
+                    if source_btn.clicked() {
+                        self.toggle_source_view();
+                        ui.close();
+                    }
+
+                    let preview_text = checked_text(self.page_preview, tr(&lang, "Page Preview"));
+                    let preview_btn = ui
+                        .button(preview_text)
+                        .on_hover_text("Lay the document out on pages, to see where they break when printed");
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Page Preview",
+                        "button",
+                        &preview_btn,
+                        Some(if self.page_preview { "on" } else { "off" }),
+                    );
+                    if preview_btn.clicked() {
+                        self.page_preview = !self.page_preview;
+                        ui.close();
+                    }
+                    #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                    let page_setup_menu = ui.menu_button(tr(&lang, "Page Setup"), |ui| {
+                        for size in pages::PageSize::ALL {
+                            let selected = self.page_setup.size == size;
+                            if ui.button(checked_text(selected, size.label())).clicked() {
+                                self.page_setup.size = size;
+                                ui.close();
+                            }
+                        }
+                        ui.separator();
+                        for margins in pages::Margins::ALL {
+                            let selected = self.page_setup.margins == margins;
+                            let text = checked_text(selected, tr(&lang, margins.label()));
+                            if ui.button(text).clicked() {
+                                self.page_setup.margins = margins;
+                                ui.close();
+                            }
+                        }
+                    });
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Page Setup",
+                        "button",
+                        &page_setup_menu.response,
+                        Some(self.page_setup.size.label()),
+                    );
+
+                    #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                    let dark_images_menu = ui.menu_button(tr(&lang, "Images in Dark Mode"), |ui| {
+                        for choice in DarkImages::ALL {
+                            let text =
+                                checked_text(self.dark_images == choice, tr(&lang, choice.label()));
+                            if ui.button(text).clicked() {
+                                self.dark_images = choice;
+                                ui.close();
+                            }
+                        }
+                    });
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Images in Dark Mode",
+                        "button",
+                        &dark_images_menu.response,
+                        Some(self.dark_images.label()),
+                    );
+
+                    #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                    let syntax_menu = ui.menu_button(tr(&lang, "Syntax Theme"), |ui| {
+                        // Every tab cache loads the same set (bundled + user folder)
+                        let names: Vec<String> = match self.tabs.first() {
+                            Some(tab) => {
+                                tab.cache.syntax_theme_names().map(str::to_owned).collect()
+                            }
+                            None => new_document_cache()
+                                .syntax_theme_names()
+                                .map(str::to_owned)
+                                .collect(),
+                        };
+                        ui.menu_button(tr(&lang, "Dark Mode"), |ui| {
+                            if syntax_theme_list(ui, &names, &mut self.syntax_theme_dark) {
+                                ui.close();
+                            }
+                        });
+                        ui.menu_button(tr(&lang, "Light Mode"), |ui| {
+                            if syntax_theme_list(ui, &names, &mut self.syntax_theme_light) {
+                                ui.close();
+                            }
+                        });
+                        if let Some(dir) = user_syntax_theme_dir() {
+                            ui.separator();
+                            ui.weak(format!("Add .tmTheme files to {}", dir.display()));
+                        }
+                    });
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Syntax Theme",
+                        "button",
+                        &syntax_menu.response,
+                        Some(if self.dark_mode {
+                            self.syntax_theme_dark.as_str()
+                        } else {
+                            self.syntax_theme_light.as_str()
+                        }),
+                    );
+
+                    #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                    let scale_menu = ui.menu_button(tr(&lang, "Interface Scale"), |ui| {
+                        for preset in UI_SCALE_PRESETS {
+                            let percent = (preset * 100.0).round() as i32;
+                            let selected = (self.ui_scale - preset).abs() < 0.01;
+                            let text = checked_text(selected, &format!("{percent}%"));
+                            if ui.button(text).clicked() {
+                                self.ui_scale = preset;
+                                ui.close();
+                            }
+                        }
+                    });
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Interface Scale",
+                        "button",
+                        &scale_menu.response,
+                        Some(format!("{:.0}", self.ui_scale * 100.0).as_str()),
+                    );
+
+                    #[cfg_attr(not(feature = "mcp"), allow(unused_variables))]
+                    let language_menu = ui.menu_button(tr(&lang, "Language"), |ui| {
+                        let automatic = format!(
+                            "{} ({})",
+                            tr(&lang, "Automatic"),
+                            i18n::language_name(&i18n::system_language())
+                        );
+                        let mut picked = None;
+                        if ui
+                            .button(checked_text(self.language.is_none(), &automatic))
+                            .clicked()
+                        {
+                            picked = Some(None);
+                        }
+                        ui.separator();
+                        let codes = std::iter::once("en")
+                            .chain(i18n::LANGUAGES.iter().map(|(code, _)| *code));
+                        for code in codes {
+                            let selected = self.language.as_deref() == Some(code);
+                            let text = checked_text(selected, i18n::language_name(code));
+                            if ui.button(text).clicked() {
+                                picked = Some(Some(code.to_string()));
+                            }
+                        }
+                        if let Some(language) = picked {
+                            self.set_language(language);
+                            ui.close();
+                        }
+                    });
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Language",
+                        "button",
+                        &language_menu.response,
+                        Some(self.ui_language.as_str()),
+                    );
+
+                    let motion_text = checked_text(self.reduce_motion, tr(&lang, "Reduce Motion"));
+                    let motion_btn = ui.add(egui::Button::new(motion_text)).on_hover_text(
+                        "Turns off smooth scrolling, the file-change flash in the explorer, \
+                         and expand/collapse animations",
+                    );
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Reduce Motion",
+                        "button",
+                        &motion_btn,
+                        Some(if self.reduce_motion { "on" } else { "off" }),
+                    );
+                    if motion_btn.clicked() {
+                        self.reduce_motion = !self.reduce_motion;
+                        self.motion = MotionConfig::new(self.reduce_motion);
+                        let motion = self.motion;
+                        ui.ctx().all_styles_mut(|style| motion.apply(style));
+                        ui.close();
+                    }
+
+                    let snap_text = checked_text(
+                        self.snap_to_headings,
+                        tr(&lang, "Snap Scrolling to Headings"),
+                    );
+                    let snap_btn = ui.add(egui::Button::new(snap_text)).on_hover_text(
+                        "When the mouse wheel stops, a heading close to the top of the \
+                         window is scrolled to it",
+                    );
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Snap Scrolling to Headings",
+                        "button",
+                        &snap_btn,
+                        Some(if self.snap_to_headings { "on" } else { "off" }),
+                    );
+                    if snap_btn.clicked() {
+                        self.snap_to_headings = !self.snap_to_headings;
+                        ui.close();
+                    }
+
+                    ui.separator();
+
+                    let zoom_in_btn =
+                        ui.add(egui::Button::new(tr(&lang, "Zoom In")).shortcut_text("Ctrl++"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Zoom In",
+                        "button",
+                        &zoom_in_btn,
+                        None,
+                    );
+                    if zoom_in_btn.clicked() {
+                        self.zoom_level = (self.zoom_level + 0.1).min(3.0);
+                        ui.close();
+                    }
+                    let zoom_out_btn =
+                        ui.add(egui::Button::new(tr(&lang, "Zoom Out")).shortcut_text("Ctrl+-"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Zoom Out",
+                        "button",
+                        &zoom_out_btn,
+                        None,
+                    );
+                    if zoom_out_btn.clicked() {
+                        self.zoom_level = (self.zoom_level - 0.1).max(0.5);
+                        ui.close();
+                    }
+                    let reset_zoom_btn =
+                        ui.add(egui::Button::new(tr(&lang, "Reset Zoom")).shortcut_text("Ctrl+0"));
+                    #[cfg(feature = "mcp")]
+                    self.mcp_bridge.register_widget(
+                        "Menu: View → Reset Zoom",
+                        "button",
+                        &reset_zoom_btn,
+                        None,
+                    );
+                    if reset_zoom_btn.clicked() {
+                        self.zoom_level = 1.0;
+                        ui.close();
+                    }
+                });
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge
+                    .register_widget("Menu: View", "button", &view_menu.response, None);
+
+                // Navigation buttons (visible arrows for back/forward)
+                ui.separator();
+                let can_back = self
+                    .tabs
+                    .get(self.active_tab)
+                    .map(|t| t.can_go_back())
+                    .unwrap_or(false);
+                let back_btn = ui.add_enabled(can_back, egui::Button::new("◀").small());
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge
+                    .register_widget("Navigate Back", "button", &back_btn, None);
+                if back_btn.on_hover_text("Back (Alt+←)").clicked() {
+                    go_back = true;
+                }
+
+                let can_forward = self
+                    .tabs
+                    .get(self.active_tab)
+                    .map(|t| t.can_go_forward())
+                    .unwrap_or(false);
+                let forward_btn = ui.add_enabled(can_forward, egui::Button::new("▶").small());
+                #[cfg(feature = "mcp")]
+                self.mcp_bridge
+                    .register_widget("Navigate Forward", "button", &forward_btn, None);
+                if forward_btn.on_hover_text("Forward (Alt+→)").clicked() {
+                    go_forward = true;
+                }
+
+                // Show status on the right
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    // Show zoom level if not at 100%
+                    if (self.zoom_level - 1.0).abs() > 0.01 {
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "{}%",
+                                (self.zoom_level * 100.0).round() as i32
+                            ))
+                            .small()
+                            .color(ui.visuals().weak_text_color()),
+                        );
+                        ui.separator();
+                    }
+
+                    let sandboxed = self
+                        .tabs
+                        .get(self.active_tab)
+                        .is_some_and(|tab| self.is_sandboxed(tab));
+                    if sandboxed {
+                        ui.label(
+                            egui::RichText::new("🔒 Restricted")
+                                .small()
+                                .color(ui.visuals().weak_text_color()),
+                        )
+                        .on_hover_text(
+                            "This document isn't in a trusted folder: remote and data: images \
+                             aren't loaded, raw HTML shows as text and links ask before opening \
+                             (File → Trusted Folders)",
+                        );
+                        ui.separator();
+                    }
+
+                    if let Some(fw) = &self.watcher {
+                        let (text, hover) = if fw.is_polling() {
+                            (
+                                format!("● LIVE (polling {}s)", self.poll_policy.interval_secs),
+                                self.poll_policy.reason(),
+                            )
+                        } else {
+                            ("● LIVE".to_string(), "Watching for file changes")
+                        };
+                        let (text, hover) = match self.reload_batch.held.len() {
+                            0 => (text, hover),
+                            held => (
+                                format!("{text} · {held} waiting"),
+                                "Changed files wait to reload until the window is focused or \
+                                 their tab's reload limit has passed (File → Watch Mode)",
+                            ),
+                        };
+                        ui.label(
+                            egui::RichText::new(text).color(egui::Color32::from_rgb(100, 200, 100)),
+                        )
+                        .on_hover_text(hover);
+                        ui.separator();
+                    }
+
+                    // Images in the active document that failed to load
+                    if let Some(tab) = self.tabs.get(self.active_tab) {
+                        let missing = tab.cache.failed_images().count();
+                        if missing > 0 {
+                            let details = tab
+                                .cache
+                                .failed_images()
+                                .map(|(uri, error)| format!("{uri}: {error}"))
+                                .collect::<Vec<_>>()
+                                .join("\n");
+                            let noun = if missing == 1 { "image" } else { "images" };
+                            ui.label(
+                                egui::RichText::new(format!("⚠ {missing} missing {noun}"))
+                                    .small()
+                                    .color(ui.visuals().warn_fg_color),
+                            )
+                            .on_hover_text(details);
+                            ui.separator();
+                        }
+                    }
+
+                    // Show current file path from active tab
+                    if let Some(tab) = self.tabs.get(self.active_tab) {
+                        if tab.path.exists() {
+                            ui.add(
+                                egui::Label::new(
+                                    egui::RichText::new(tab.path.display().to_string())
+                                        .small()
+                                        .color(ui.visuals().weak_text_color()),
+                                )
+                                .truncate(),
+                            );
+                        }
+                    }
+                });
+            });
+        });
+
+        // Handle navigation button clicks (must be after menu bar UI)
+        if go_back {
+            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                tab.navigate_back();
+            }
+        }
+        if go_forward {
+            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+                tab.navigate_forward();
+            }
+        }
+
+        // Show error message if any
+        let mut clear_error = false;
+        if let Some(error) = &self.error_message {
+            egui::TopBottomPanel::top("error_bar").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        egui::RichText::new("⚠").color(egui::Color32::from_rgb(255, 200, 100)),
+                    );
+                    ui.label(
+                        egui::RichText::new(error).color(egui::Color32::from_rgb(255, 200, 100)),
+                    );
+                    if ui.small_button("✕").clicked() {
+                        clear_error = true;
+                    }
+                });
+            });
+        }
+        if clear_error {
+            self.error_message = None;
+        }
+
+        // Large file banner (conditional, while the active file is cut short)
+        self.render_large_file_bar(ctx);
+        // Missing file banner (conditional, once the grace period is over)
+        self.render_missing_file_bar(ctx);
+
+        // Find bar (conditional, between error bar and tab bar)
+        let search_outcome = self.render_search_bar(ctx);
+        // Rebuild matches if query or tab changed (TextEdit may have mutated the query)
+        self.maybe_rebuild_search();
+        if search_outcome.close_requested {
+            self.close_search();
+        }
+        if search_outcome.next_clicked {
+            self.jump_match(1);
+        }
+        if search_outcome.prev_clicked {
+            self.jump_match(-1);
+        }
+        if search_outcome.copy_report || search_outcome.save_report {
+            self.export_search_report(ctx, search_outcome.save_report);
+        }
+
+        // Tab bar
+        let mut tab_to_close: Option<usize> = None;
+        egui::TopBottomPanel::top("tab_bar").show(ctx, |ui| {
+            tab_to_close = self.render_tab_bar(ui);
+        });
+
+        // Close tab if requested
+        if let Some(idx) = tab_to_close {
+            self.close_tab(idx);
+        }
+
+        // Status bar with the encoding and git status, and the git log
+        // (bottom, spanning both sidebars)
+        self.render_status_bar(ctx);
+
+        // Problems of the active document (bottom, above the status bar)
+        self.render_problems(ctx);
+
+        // File explorer (left sidebar)
+        let explorer_action = self.render_file_explorer(ctx);
+
+        // Open file from explorer (left-click)
+        if let Some(path) = explorer_action.file_to_open {
+            self.open_in_new_tab(path);
+        }
+
+        // Preview the README of a directory selected in the explorer
+        if let Some(readme) = explorer_action
+            .dir_to_preview
+            .as_deref()
+            .and_then(directory_readme)
+        {
+            self.open_preview_tab(readme);
+        }
+
+        // Close tab from explorer (middle-click on open file)
+        if let Some(path) = explorer_action.file_to_close {
+            if let Some(idx) = self.tabs.iter().position(|t| t.path == path) {
+                self.close_tab(idx);
+            }
+        }
+
+        // Outline sidebar (right) - at top level for proper layout
+        self.render_outline(ctx);
+
+        // Workspace tasks (right, beside the outline)
+        self.render_tasks(ctx);
+
+        // Footnotes of the active document (right)
+        self.render_footnotes(ctx);
+        self.render_figures(ctx);
+        self.render_link_graph(ctx);
+
+        // Main content area
+        let mut open_in_new_tab: Option<(PathBuf, bool)> = None;
+        let content_rect = egui::CentralPanel::default()
+            .frame(egui::Frame::central_panel(&ctx.style()).inner_margin(egui::Margin::ZERO))
+            .show(ctx, |ui| {
+                open_in_new_tab = self.render_tab_content(ui, ctrl_held);
+            })
+            .response
+            .rect;
+
+        // Contents button floating over the document
+        self.render_floating_toc(ctx, content_rect);
+
+        // Open link in new tab if requested
+        if let Some((path, background)) = open_in_new_tab {
+            self.open_tab(path, !background);
+        }
+
+        // Check if a mermaid diagram was clicked → open lightbox
+        // Texture is pre-rasterized at 2x the display density by a background thread — no work on click
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            if let Some((texture, base_size)) = tab.cache.take_clicked_mermaid() {
+                self.lightbox_open_count += 1;
+                self.lightbox = Some(LightboxState {
+                    texture: LightboxTexture::Owned(texture),
+                    base_size,
+                    zoom: 1.0,
+                    open_id: self.lightbox_open_count,
+                    scroll_offset: egui::Vec2::ZERO,
+                    pending_reset: false,
+                });
+            }
+            if let Some((tex_id, base_size)) = tab.cache.take_clicked_image() {
+                self.lightbox_open_count += 1;
+                self.lightbox = Some(LightboxState {
+                    texture: LightboxTexture::Loaded(tex_id),
+                    base_size,
+                    zoom: 1.0,
+                    open_id: self.lightbox_open_count,
+                    scroll_offset: egui::Vec2::ZERO,
+                    pending_reset: false,
+                });
+            }
+            if let Some((name, bytes)) = tab.cache.take_image_to_save() {
+                save_image_as(&name, &bytes);
+            }
+        }
+
+        // Lightbox overlay for enlarged diagrams or images
+        self.render_lightbox(ctx);
+
+        // Confirmation before a shell code block runs
+        self.render_run_confirmation(ctx);
+
+        // Offer the session of a crashed run, and snapshot this one
+        self.render_session_recovery(ctx);
+        self.snapshot_session();
+
+        // Keyboard shortcut overlay
+        self.render_shortcuts(ctx);
+
+        // Command palette of script commands
+        self.render_command_palette(ctx);
+
+        // Drag and drop overlay
+        if self.is_dragging {
+            let screen_rect = ctx.available_rect();
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("drop_overlay"),
+            ));
+
+            painter.rect_filled(
+                screen_rect,
+                0.0,
+                egui::Color32::from_rgba_unmultiplied(0, 0, 0, 180),
+            );
+
+            painter.text(
+                screen_rect.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop markdown file here",
+                egui::FontId::proportional(24.0),
+                egui::Color32::WHITE,
+            );
+        }
+
+        self.publish_jump_lists(ctx);
+
+        #[cfg(feature = "mcp")]
+        self.render_automation_input(ctx);
+
+        // Capture AccessKit output for MCP bridge
+        #[cfg(feature = "mcp")]
+        self.mcp_bridge.capture_output(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_recent_dedupes_and_moves_to_front() {
+        let mut v = Vec::new();
+        push_recent(&mut v, Path::new("/a.md"), 100);
+        push_recent(&mut v, Path::new("/b.md"), 200);
+        push_recent(&mut v, Path::new("/a.md"), 300); // re-open a → moves to front, no dup
+        assert_eq!(v.len(), 2);
+        assert_eq!(v[0].path, PathBuf::from("/a.md"));
+        assert_eq!(v[0].last_opened, 300);
+        assert_eq!(v[1].path, PathBuf::from("/b.md"));
+    }
+
+    #[test]
+    fn push_recent_caps_length_keeping_newest() {
+        let mut v = Vec::new();
+        for i in 0..(RECENT_FILES_CAP + 5) {
+            push_recent(&mut v, Path::new(&format!("/f{i}.md")), i as u64);
+        }
+        assert_eq!(v.len(), RECENT_FILES_CAP);
+        // The most recently pushed entry is at the front.
+        assert_eq!(
+            v[0].path,
+            PathBuf::from(format!("/f{}.md", RECENT_FILES_CAP + 4))
+        );
+    }
+
+    #[test]
+    fn reading_positions_are_capped_and_skip_the_top() {
+        let mut v = Vec::new();
+        push_reading_position(&mut v, Path::new("/a.md"), 40);
+        push_reading_position(&mut v, Path::new("/b.md"), 1);
+        push_reading_position(&mut v, Path::new("/a.md"), 75);
+        assert_eq!(v.len(), 2);
+        assert_eq!(saved_reading_line(&v, Path::new("/a.md")), Some(75));
+        assert_eq!(saved_reading_line(&v, Path::new("/b.md")), None);
+        assert_eq!(saved_reading_line(&v, Path::new("/c.md")), None);
+
+        for i in 0..(READING_POSITIONS_CAP + 5) {
+            push_reading_position(&mut v, Path::new(&format!("/f{i}.md")), i + 2);
+        }
+        assert_eq!(v.len(), READING_POSITIONS_CAP);
+        assert_eq!(saved_reading_line(&v, Path::new("/a.md")), None);
+    }
+
+    #[test]
+    fn recent_roots_dedupe_and_cap() {
+        let mut roots = Vec::new();
+        push_recent_root(&mut roots, Path::new("/no/such/a"));
+        push_recent_root(&mut roots, Path::new("/no/such/b"));
+        push_recent_root(&mut roots, Path::new("/no/such/a"));
+        assert_eq!(
+            roots,
+            vec![PathBuf::from("/no/such/a"), PathBuf::from("/no/such/b")]
+        );
+        for i in 0..(RECENT_ROOTS_CAP + 3) {
+            push_recent_root(&mut roots, Path::new(&format!("/no/such/{i}")));
+        }
+        assert_eq!(roots.len(), RECENT_ROOTS_CAP);
+        assert_eq!(
+            roots[0],
+            PathBuf::from(format!("/no/such/{}", RECENT_ROOTS_CAP + 2))
+        );
+    }
+
+    #[test]
+    fn breadcrumb_lists_the_ancestors_of_the_root() {
+        let crumbs = root_breadcrumb(Path::new("/no/such/dir"));
+        assert_eq!(
+            crumbs,
+            vec![
+                ("/".to_string(), PathBuf::from("/")),
+                ("no".to_string(), PathBuf::from("/no")),
+                ("such".to_string(), PathBuf::from("/no/such")),
+                ("dir".to_string(), PathBuf::from("/no/such/dir")),
+            ]
+        );
+    }
+
+    #[test]
+    fn middle_and_ctrl_shift_clicks_open_links_in_the_background() {
+        let click = |button, modifiers| LinkClick { button, modifiers };
+        let ctrl_shift = egui::Modifiers::COMMAND | egui::Modifiers::SHIFT;
+        assert!(opens_in_background(click(
+            egui::PointerButton::Middle,
+            egui::Modifiers::NONE
+        )));
+        assert!(opens_in_background(click(
+            egui::PointerButton::Primary,
+            ctrl_shift
+        )));
+        assert!(!opens_in_background(click(
+            egui::PointerButton::Primary,
+            egui::Modifiers::COMMAND
+        )));
+    }
+
+    #[test]
+    fn relative_time_buckets() {
+        let now = 1_000_000u64;
+        assert_eq!(format_relative_time(now, now), "just now");
+        assert_eq!(format_relative_time(now - 120, now), "2m ago");
+        assert_eq!(format_relative_time(now - 7200, now), "2h ago");
+        assert_eq!(format_relative_time(now - 90_000, now), "yesterday");
+        assert_eq!(format_relative_time(now - 3 * 86_400, now), "3d ago");
+        // Clock skew (future timestamp) must not underflow.
+        assert_eq!(format_relative_time(now + 500, now), "just now");
+    }
+
+    #[test]
+    fn default_terminal_launch_detaches() {
+        let args = Args::try_parse_from(["md-viewer", "README.md"]).unwrap();
+        assert!(should_detach(&args, true));
+    }
+
+    #[test]
+    fn non_terminal_launch_does_not_detach() {
+        let args = Args::try_parse_from(["md-viewer", "README.md"]).unwrap();
+        assert!(!should_detach(&args, false));
+    }
+
+    #[test]
+    fn foreground_flag_disables_detach() {
+        let args = Args::try_parse_from(["md-viewer", "--foreground", "README.md"]).unwrap();
+        assert!(!should_detach(&args, true));
+    }
+
+    #[test]
+    fn hidden_no_detach_marker_disables_detach() {
+        let args = Args::try_parse_from(["md-viewer", "--no-detach", "README.md"]).unwrap();
+        assert!(!should_detach(&args, true));
+    }
+
+    #[test]
+    fn child_args_preserve_user_args_and_append_marker() {
+        let child_args = child_args_with_no_detach([
+            OsString::from("md-viewer"),
+            OsString::from("README.md"),
+            OsString::from("--no-watch"),
+        ]);
+
+        assert_eq!(
+            child_args,
+            vec![
+                OsString::from("README.md"),
+                OsString::from("--no-watch"),
+                OsString::from("--no-detach"),
+            ]
+        );
+    }
+
+    #[test]
+    fn child_args_insert_marker_before_double_dash() {
+        let child_args = child_args_with_no_detach([
+            OsString::from("md-viewer"),
+            OsString::from("--"),
+            OsString::from("README.md"),
+        ]);
+
+        assert_eq!(
+            child_args,
+            vec![
+                OsString::from("--no-detach"),
+                OsString::from("--"),
+                OsString::from("README.md"),
+            ]
+        );
+    }
+
+    #[test]
+    fn hidden_no_detach_marker_is_not_in_help() {
+        use clap::CommandFactory;
+
+        let help = Args::command().render_long_help().to_string();
+        assert!(help.contains("--foreground"));
+        assert!(!help.contains("--no-detach"));
+    }
+
+    #[test]
+    fn capped_content_width_uses_optimal_width() {
+        assert_eq!(
+            content_default_width(false, CONTENT_OPTIMAL_WIDTH),
+            Some(600)
+        );
+    }
+
+    #[test]
+    fn capped_content_width_uses_configured_width() {
+        assert_eq!(content_default_width(false, 920.0), Some(920));
+    }
+
+    #[test]
+    fn full_width_content_uses_available_width() {
+        assert_eq!(content_default_width(true, 920.0), None);
+    }
+
+    #[test]
+    fn center_margin_splits_leftover_width() {
+        assert_eq!(content_center_margin(1000.0, Some(600), true), 200.0);
+        assert_eq!(content_center_margin(1000.0, Some(600), false), 0.0);
+    }
+
+    #[test]
+    fn center_margin_is_zero_for_full_width_or_narrow_windows() {
+        assert_eq!(content_center_margin(1000.0, None, true), 0.0);
+        assert_eq!(content_center_margin(500.0, Some(600), true), 0.0);
+    }
+
+    #[test]
+    fn content_zoom_scales_every_text_style() {
+        let mut style = egui::Style::default();
+        let before = style.text_styles.clone();
+        scale_text_styles(&mut style, 1.5);
+        for (text_style, font_id) in &style.text_styles {
+            assert_eq!(font_id.size, before[text_style].size * 1.5);
+        }
+    }
+
+    #[test]
+    fn legacy_dark_mode_migrates_to_theme_mode() {
+        assert_eq!(
+            ThemeMode::from_legacy_dark_mode(Some(true)),
+            ThemeMode::Dark
+        );
+        assert_eq!(
+            ThemeMode::from_legacy_dark_mode(Some(false)),
+            ThemeMode::Light
+        );
+        assert_eq!(ThemeMode::from_legacy_dark_mode(None), ThemeMode::Auto);
+    }
+
+    #[test]
+    fn auto_theme_follows_system_and_keeps_current_when_unknown() {
+        let auto = ThemeMode::Auto;
+        assert!(auto.resolve_dark(Some(egui::Theme::Dark), false));
+        assert!(!auto.resolve_dark(Some(egui::Theme::Light), true));
+        assert!(auto.resolve_dark(None, true));
+        assert!(!auto.resolve_dark(None, false));
+    }
+
+    #[test]
+    fn manual_theme_ignores_system_and_toggle_pins_opposite() {
+        assert!(ThemeMode::Dark.resolve_dark(Some(egui::Theme::Light), false));
+        assert!(!ThemeMode::Light.resolve_dark(Some(egui::Theme::Dark), true));
+        assert_eq!(ThemeMode::toggled(true), ThemeMode::Light);
+        assert_eq!(ThemeMode::toggled(false), ThemeMode::Dark);
+    }
+
+    #[test]
+    fn search_report_lists_file_line_and_snippet() {
+        let content = "# Notes\n\nUse a | pipe to todo\n\n  - todo: x\n";
+        let matches = find_matches(content, "todo");
+        let report = search_report("todo", Path::new("/docs/notes.md"), content, &matches);
+        assert_eq!(
+            report,
+            "# Search results for \"todo\"\n\n2 matches in `/docs/notes.md`\n\n\
+             | File | Line | Snippet |\n| --- | --- | --- |\n\
+             | notes.md | 3 | Use a \\| pipe to todo |\n\
+             | notes.md | 5 | - todo: x |\n"
+        );
+
+        let long = "x".repeat(200);
+        let matches = find_matches(&long, "x");
+        let report = search_report("x", Path::new("a.md"), &long, &matches[..1]);
+        assert!(report.ends_with(&format!("| a.md | 1 | {}… |\n", "x".repeat(160))));
+    }
+
+    #[test]
+    fn find_matches_empty_query_returns_none() {
+        assert_eq!(find_matches("hello world", ""), vec![]);
+    }
+
+    #[test]
+    fn find_matches_empty_content_returns_none() {
+        assert_eq!(find_matches("", "hello"), vec![]);
+    }
+
+    #[test]
+    fn find_matches_single_ascii() {
+        let m = find_matches("hello world", "world");
+        assert_eq!(m.len(), 1);
+        assert_eq!(m[0].byte_start, 6);
+        assert_eq!(m[0].byte_end, 11);
+        assert_eq!(m[0].line_number, 1);
+    }
+
+    #[test]
+    fn find_matches_case_insensitive_ascii() {
+        let m = find_matches("Hello WORLD", "world");
+        assert_eq!(m.len(), 1);
+        assert_eq!(&"Hello WORLD"[m[0].byte_start..m[0].byte_end], "WORLD");
+    }
+
+    #[test]
+    fn find_matches_multiple_per_line() {
+        // "foo bar foo baz foo" → 3 matches, all on line 1
+        let m = find_matches("foo bar foo baz foo", "foo");
+        assert_eq!(m.len(), 3);
+        assert!(m.iter().all(|sm| sm.line_number == 1));
+        let starts: Vec<_> = m.iter().map(|sm| sm.byte_start).collect();
+        assert_eq!(starts, vec![0, 8, 16]);
+    }
+
+    #[test]
+    fn find_matches_line_numbers_one_based() {
+        let content = "first line\nsecond match here\nthird\nmatch on four";
+        let m = find_matches(content, "match");
+        assert_eq!(m.len(), 2);
+        assert_eq!(m[0].line_number, 2);
+        assert_eq!(m[1].line_number, 4);
+    }
+
+    #[test]
+    fn find_matches_preserves_byte_offsets_with_utf8() {
+        // "café résumé naïve" — each accented char is 2 bytes in UTF-8
+        let content = "café résumé naïve";
+        let m = find_matches(content, "café");
+        assert_eq!(m.len(), 1);
+        // Confirm byte range slices back to the original substring
+        assert_eq!(&content[m[0].byte_start..m[0].byte_end], "café");
+    }
+
+    #[test]
+    fn find_matches_skips_cross_newline_matches() {
+        // Query "oo\nb" theoretically spans two lines — should not be reported
+        let content = "foo\nbar";
+        let m = find_matches(content, "oo\nb");
+        assert_eq!(m, vec![]);
+    }
+
+    #[test]
+    fn find_matches_overlapping_uses_match_indices_semantics() {
+        // str::match_indices does NOT overlap matches: "aaaa" / "aa" → [0, 2]
+        let m = find_matches("aaaa", "aa");
+        let starts: Vec<_> = m.iter().map(|sm| sm.byte_start).collect();
+        assert_eq!(starts, vec![0, 2]);
+    }
+
+    #[test]
+    fn find_matches_keeps_image_alt_text() {
+        // Alt text describes the image, so searching for it finds the image
+        let content = "See ![Architecture diagram](pic.png) below.";
+        let m = find_matches(content, "architecture diagram");
+        assert_eq!(m.len(), 1, "got {:?}", m);
+        assert_eq!(m[0].byte_start, content.find("Architecture").unwrap());
+    }
+
+    #[test]
+    fn find_matches_keeps_link_titles_and_fence_languages() {
+        let content = "[docs](guide.html \"User guide\")\n\n```python\nprint()\n```\n";
+        let m = find_matches(content, "guide");
+        assert_eq!(m.len(), 1, "the url is skipped, the title kept: {:?}", m);
+        assert_eq!(m[0].byte_start, content.find("guide\"").unwrap());
+        let m = find_matches(content, "python");
+        assert_eq!(m.len(), 1);
+        assert_eq!(m[0].line_number, 3);
+    }
+
+    #[test]
+    fn find_matches_skips_image_url() {
+        // Image URL isn't visibly rendered — exclude matches inside.
+        let content = "![ok](path/syntax.png) more text";
+        let m = find_matches(content, "syntax");
+        assert_eq!(m.len(), 0, "URL match should be filtered: {:?}", m);
+    }
+
+    #[test]
+    fn find_matches_skips_link_url_keeps_link_text() {
+        // Link text IS rendered (visible clickable text). Link URL is not.
+        let content = "Click [syntax docs](https://example.com/syntax.html) here";
+        let m = find_matches(content, "syntax");
+        // Two raw matches: "syntax" in link text (visible) + "syntax" in URL (invisible)
+        assert_eq!(m.len(), 1, "should keep link text, drop URL: {:?}", m);
+        let s = m[0].byte_start;
+        // The kept match is "syntax" in "[syntax docs]" (visible link text)
+        assert_eq!(&content[s..s + 6], "syntax");
+        // And it's the FIRST occurrence (inside the brackets), not the URL one
+        assert!(s < content.find("https").unwrap());
+    }
+
+    #[test]
+    fn find_matches_keeps_link_syntax_in_code() {
+        let content = "```\nitems[i](syntax)\n```\n\n`[a](syntax)` [b](syntax)\n";
+        let m = find_matches(content, "syntax");
+        assert_eq!(m.len(), 2, "code shows its link syntax: {:?}", m);
+        assert!(m[1].byte_start < content.find("[b]").unwrap());
+    }
+
+    #[test]
+    fn find_matches_multiple_alt_text_images() {
+        let content = "![a one](one.png) one ![two two](w.png) two";
+        // The alt text matches, the url doesn't
+        assert_eq!(find_matches(content, "one").len(), 2);
+        assert_eq!(find_matches(content, "two").len(), 3);
+    }
+
+    #[test]
+    fn shortcode_search_range_uses_raw_source_bytes() {
+        let content = "before :pushpin: after";
+        let matches = find_matches(content, ":pushpin:");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].byte_start, 7);
+        assert_eq!(matches[0].byte_end, 16);
+        assert_eq!(
+            &content[matches[0].byte_start..matches[0].byte_end],
+            ":pushpin:"
+        );
+    }
 
-        // Show error message if any
-        let mut clear_error = false;
-        if let Some(error) = &self.error_message {
-            egui::TopBottomPanel::top("error_bar").show(ctx, |ui| {
-                ui.horizontal(|ui| {
-                    ui.label(
-                        egui::RichText::new("⚠").color(egui::Color32::from_rgb(255, 200, 100)),
-                    );
-                    ui.label(
-                        egui::RichText::new(error).color(egui::Color32::from_rgb(255, 200, 100)),
-                    );
-                    if ui.small_button("✕").clicked() {
-                        clear_error = true;
-                    }
-                });
-            });
-        }
-        if clear_error {
-            self.error_message = None;
-        }
+    #[test]
+    fn rendered_emoji_does_not_match_shortcode_only_source() {
+        assert!(find_matches("before :pushpin: after", "📌").is_empty());
+    }
 
-        // Find bar (conditional, between error bar and tab bar)
-        let search_outcome = self.render_search_bar(ctx);
-        // Rebuild matches if query or tab changed (TextEdit may have mutated the query)
-        self.maybe_rebuild_search();
-        if search_outcome.close_requested {
-            self.close_search();
-        }
-        if search_outcome.next_clicked {
-            self.jump_match(1);
-        }
-        if search_outcome.prev_clicked {
-            self.jump_match(-1);
-        }
+    #[test]
+    fn literal_unicode_emoji_still_matches_raw_source() {
+        let matches = find_matches("before 📌 after", "📌");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].byte_start, 7);
+        assert_eq!(matches[0].byte_end, 11);
+    }
 
-        // Tab bar
-        let mut tab_to_close: Option<usize> = None;
-        egui::TopBottomPanel::top("tab_bar").show(ctx, |ui| {
-            tab_to_close = self.render_tab_bar(ui);
-        });
+    #[test]
+    fn shortcode_heading_parser_keeps_raw_identity_and_duplicate_index() {
+        let parsed = parse_headers("# Doc\n\n## Pin :pushpin:\n\n## Pin :pushpin:\n");
+        assert_eq!(parsed.outline_headers.len(), 3);
+        assert_eq!(parsed.outline_headers[1].title, "Pin :pushpin:");
+        assert_eq!(parsed.outline_headers[1].slug, "pin-pushpin");
+        assert_eq!(parsed.outline_headers[1].nth_with_same_slug, 0);
+        assert_eq!(parsed.outline_headers[2].slug, "pin-pushpin");
+        assert_eq!(parsed.outline_headers[2].nth_with_same_slug, 1);
+    }
 
-        // Close tab if requested
-        if let Some(idx) = tab_to_close {
-            self.close_tab(idx);
-        }
+    #[test]
+    fn heading_parser_follows_markdown_not_lines() {
+        let parsed = parse_headers(
+            "# Doc\n\n~~~\n# not a heading\n~~~\n\nSetext *Title*\n---\n\n    # indented code\n",
+        );
+        let titles: Vec<_> = parsed
+            .outline_headers
+            .iter()
+            .map(|h| (h.level, h.title.as_str(), h.line_number))
+            .collect();
+        assert_eq!(titles, vec![(1, "Doc", 0), (2, "Setext Title", 6)]);
+    }
 
-        // File explorer (left sidebar)
-        let explorer_action = self.render_file_explorer(ctx);
+    #[test]
+    fn unknown_shortcode_heading_stays_raw() {
+        let parsed = parse_headers("# Doc\n\n## Pin :not_a_gemoji:\n");
+        assert_eq!(parsed.outline_headers[1].title, "Pin :not_a_gemoji:");
+        assert_eq!(parsed.outline_headers[1].slug, "pin-not_a_gemoji");
+    }
 
-        // Open file from explorer (left-click)
-        if let Some(path) = explorer_action.file_to_open {
-            self.open_in_new_tab(path);
-        }
+    #[test]
+    fn automation_commands_parse_their_argument() {
+        assert_eq!(
+            AutomationCommand::parse("open docs/My Notes.md"),
+            Ok(AutomationCommand::Open(PathBuf::from("docs/My Notes.md")))
+        );
+        assert_eq!(
+            AutomationCommand::parse(" tab 2 "),
+            Ok(AutomationCommand::Tab(2))
+        );
+        assert_eq!(
+            AutomationCommand::parse("heading #install-1"),
+            Ok(AutomationCommand::Heading("#install-1".to_string()))
+        );
+        assert_eq!(
+            AutomationCommand::parse("tabs"),
+            Ok(AutomationCommand::Tabs)
+        );
+        assert_eq!(
+            AutomationCommand::parse("outline"),
+            Ok(AutomationCommand::Outline)
+        );
+        assert!(AutomationCommand::parse("tab 0").is_err());
+        assert!(AutomationCommand::parse("heading").is_err());
+        assert!(AutomationCommand::parse("close 1").is_err());
+    }
 
-        // Close tab from explorer (middle-click on open file)
-        if let Some(path) = explorer_action.file_to_close {
-            if let Some(idx) = self.tabs.iter().position(|t| t.path == path) {
-                self.close_tab(idx);
-            }
-        }
+    #[test]
+    fn outline_anchors_number_repeated_headings() {
+        let parsed = parse_headers("# Doc\n\n## Install\n\n## Usage\n\n### Install\n");
+        let anchors: Vec<String> = parsed.outline_headers.iter().map(Header::anchor).collect();
+        assert_eq!(anchors, ["doc", "install", "usage", "install-1"]);
+    }
 
-        // Outline sidebar (right) - at top level for proper layout
-        self.render_outline(ctx);
+    #[test]
+    fn outline_numbers_headings_like_exports() {
+        let content = "# Doc\n\n## Install\n\n### Linux\n\n## Usage\n";
+        let headers = parse_headers(content).outline_headers;
+        let titles: Vec<String> = headers.iter().map(|h| h.outline_title(true)).collect();
+        assert_eq!(titles, ["Doc", "1 Install", "1.1 Linux", "2 Usage"]);
+        assert_eq!(headers[2].outline_title(false), "Linux");
+        assert_eq!(&content[headers[2].start..], "### Linux\n\n## Usage\n");
+    }
 
-        // Main content area
-        let mut open_in_new_tab: Option<PathBuf> = None;
-        egui::CentralPanel::default()
-            .frame(egui::Frame::central_panel(&ctx.style()).inner_margin(egui::Margin::ZERO))
-            .show(ctx, |ui| {
-                open_in_new_tab = self.render_tab_content(ui, ctrl_held);
-            });
+    #[test]
+    fn remote_commands_parse_and_export_documents() {
+        assert_eq!(
+            AutomationCommand::parse("goto install"),
+            Ok(AutomationCommand::Heading("#install".to_string()))
+        );
+        assert_eq!(
+            AutomationCommand::parse("reload"),
+            Ok(AutomationCommand::Reload)
+        );
+        assert_eq!(
+            AutomationCommand::parse("export html"),
+            Ok(AutomationCommand::Export("html".to_string(), None))
+        );
+        assert_eq!(
+            AutomationCommand::parse("export markdown /tmp/out file.md"),
+            Ok(AutomationCommand::Export(
+                "markdown".to_string(),
+                Some(PathBuf::from("/tmp/out file.md"))
+            ))
+        );
+        assert!(AutomationCommand::parse("export").is_err());
+
+        let html = export_document(
+            "html",
+            "Notes & <Ideas>",
+            "# Hi\n\n- [x] done\n",
+            toc::Options::default(),
+        )
+        .unwrap();
+        assert!(html.contains("<title>Notes &amp; &lt;Ideas&gt;</title>"));
+        assert!(html.contains("<h1 id=\"hi\">Hi</h1>"));
+        assert!(html.contains("checked=\"\""));
+        assert_eq!(
+            export_document("md", "t", "# Hi\n", toc::Options::default()),
+            Ok("# Hi\n".to_string())
+        );
+        assert!(export_document("pdf", "t", "", toc::Options::default()).is_err());
+    }
 
-        // Open link in new tab if requested
-        if let Some(path) = open_in_new_tab {
-            self.open_in_new_tab(path);
-        }
+    #[test]
+    fn exported_headings_get_github_anchors() {
+        let html = export_document(
+            "html",
+            "t",
+            "# Setup\n\n## Setup\n\n## `cargo` Über *uns*?\n\n## Setup-1\n\n## !!\n",
+            toc::Options::default(),
+        )
+        .unwrap();
+        assert!(html.contains("<h1 id=\"setup\">Setup</h1>"));
+        assert!(html.contains("<h2 id=\"setup-1\">Setup</h2>"));
+        assert!(
+            html.contains("<h2 id=\"cargo-über-uns\"><code>cargo</code> Über <em>uns</em>?</h2>")
+        );
+        assert!(html.contains("<h2 id=\"setup-1-1\">Setup-1</h2>"));
+        assert!(html.contains("<h2>!!</h2>"));
+    }
 
-        // Check if a mermaid diagram was clicked → open lightbox
-        // Texture is pre-rasterized at 2x by background thread — no work on click
-        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-            if let Some((texture, base_size)) = tab.cache.take_clicked_mermaid() {
-                self.lightbox_open_count += 1;
-                self.lightbox = Some(LightboxState {
-                    texture: LightboxTexture::Owned(texture),
-                    base_size,
-                    zoom: 1.0,
-                    open_id: self.lightbox_open_count,
-                    scroll_offset: egui::Vec2::ZERO,
-                });
-            }
-            if let Some((tex_id, base_size)) = tab.cache.take_clicked_image() {
-                self.lightbox_open_count += 1;
-                self.lightbox = Some(LightboxState {
-                    texture: LightboxTexture::Loaded(tex_id),
-                    base_size,
-                    zoom: 1.0,
-                    open_id: self.lightbox_open_count,
-                    scroll_offset: egui::Vec2::ZERO,
-                });
-            }
-        }
+    #[test]
+    fn sections_count_the_words_of_their_subsections() {
+        let content = "# Doc\n\nOne two.\n\n## Usage\n\nThree four five.\n\n\
+                       ### Flags\n\n| a b | c |\n|---|---|\n| d | e |\n\n\
+                       ## License\n\n```\nsix seven\n```\n";
+        let parsed = parse_headers(content);
+        let words: Vec<usize> = parsed.outline_headers.iter().map(|h| h.words).collect();
+        assert_eq!(words, vec![12, 8, 5, 2]);
+        assert_eq!(reading_time(1), "1 min");
+        assert_eq!(reading_time(200), "1 min");
+        assert_eq!(reading_time(201), "2 min");
+    }
 
-        // Lightbox overlay for enlarged diagrams or images
-        self.render_lightbox(ctx);
+    #[test]
+    fn moving_a_section_takes_its_subsections_along() {
+        let content = "# Doc\n\n## A\n\na\n\n### A1\n\n## B\n\nb";
+        let headers = parse_headers(content).outline_headers;
+        assert_eq!(
+            move_section(content, &headers, 1, 4).as_deref(),
+            Some("# Doc\n\n## B\n\nb\n## A\n\na\n\n### A1\n\n")
+        );
+        assert_eq!(
+            move_section(content, &headers, 3, 1).as_deref(),
+            Some("# Doc\n\n## B\n\nb\n## A\n\na\n\n### A1\n\n")
+        );
+        // Into itself, or to where it already is
+        assert_eq!(move_section(content, &headers, 1, 2), None);
+        assert_eq!(move_section(content, &headers, 1, 3), None);
+    }
 
-        // Drag and drop overlay
-        if self.is_dragging {
-            let screen_rect = ctx.available_rect();
-            let painter = ctx.layer_painter(egui::LayerId::new(
-                egui::Order::Foreground,
-                egui::Id::new("drop_overlay"),
-            ));
+    #[test]
+    fn moved_sections_keep_the_text_of_the_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(
+            tmp.path().join("references.bib"),
+            "@book{sicp, author = {Abelson, Harold}, title = {SICP}, year = {1996}}\n",
+        )
+        .unwrap();
+        let path = tmp.path().join("a.md");
+        let source = "# Doc\n\n## A\n\nSee [@sicp]. Café\n\n\\newpage\n\n## B\n\nb\n";
+        let (bytes, _, _) = encoding_rs::WINDOWS_1252.encode(source);
+        fs::write(&path, &bytes).unwrap();
+        let tab = Tab::new(path);
+        // Cited, paged and with references as shown
+        assert!(!tab.content.contains("[@sicp]") && !tab.content.contains("\\newpage"));
+        assert_eq!(tab.outline_headers.len(), 4);
+
+        let moved = move_section_in_file(&bytes, tab.encoding, &tab.outline_headers, 2, 1);
+        let expected = "# Doc\n\n## B\n\nb\n## A\n\nSee [@sicp]. Café\n\n\\newpage\n\n";
+        let (expected, _, _) = encoding_rs::WINDOWS_1252.encode(expected);
+        assert_eq!(moved, Ok(Some(expected.into_owned())));
+        // Dropped on the references, the section goes to the end of the file
+        let to_end = move_section_in_file(&bytes, tab.encoding, &tab.outline_headers, 1, 3);
+        assert!(to_end
+            .unwrap()
+            .unwrap()
+            .ends_with(b"## A\n\nSee [@sicp]. Caf\xe9\n\n\\newpage\n\n"));
+        assert_eq!(
+            move_section_in_file(&bytes, tab.encoding, &tab.outline_headers, 1, 2),
+            Ok(None)
+        );
 
-            painter.rect_filled(
-                screen_rect,
-                0.0,
-                egui::Color32::from_rgba_unmultiplied(0, 0, 0, 180),
-            );
+        // Headings added in front by a script don't match the file
+        let shown = parse_headers("# Added\n\n# Doc\n\n## A\n\n## B\n").outline_headers;
+        assert!(move_section_in_file(&bytes, tab.encoding, &shown, 3, 2).is_err());
 
-            painter.text(
-                screen_rect.center(),
-                egui::Align2::CENTER_CENTER,
-                "Drop markdown file here",
-                egui::FontId::proportional(24.0),
-                egui::Color32::WHITE,
-            );
-        }
+        let bom = b"\xff\xfe#\0 \0A\0\n\0";
+        assert_eq!(
+            encode_document("# B\n", encoding_rs::UTF_16LE, bom).as_deref(),
+            Some(&b"\xff\xfe#\0 \0B\0\n\0"[..])
+        );
+        assert_eq!(
+            encode_document("日本", encoding_rs::WINDOWS_1252, b""),
+            None
+        );
+    }
 
-        // Capture AccessKit output for MCP bridge
-        #[cfg(feature = "mcp")]
-        self.mcp_bridge.capture_output(ctx);
+    #[test]
+    fn unsaved_tabs_show_their_text_without_a_file() {
+        let path = std::env::temp_dir().join("md-viewer-no-such-dir/Clipboard 1.md");
+        let mut tab = Tab::new_unsaved(path.clone(), "# Pasted\n\n## Part\n".to_string());
+        tab.reload();
+        assert_eq!(tab.content, "# Pasted\n\n## Part\n");
+        assert_eq!(tab.document_title.as_deref(), Some("Pasted"));
+        assert_eq!(tab.outline_headers.len(), 2);
+        assert_eq!(tab.path, path);
+        assert!(tab.missing_since.is_none());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn compare_tabs_follow_the_revised_document() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (old, new) = (dir.join("v1.md"), dir.join("v2.md"));
+        fs::write(&old, "# Doc\n\nOld.\n").unwrap();
+        fs::write(&new, "# Doc\n\nNew.\n").unwrap();
+        let ctx = egui::Context::default();
+        let mut jobs = background::Jobs::default();
+        let mut compared = |tab: &mut Tab| {
+            let comparison = tab.compare.as_mut().unwrap();
+            comparison.poll(&mut jobs, &ctx);
+            let start = Instant::now();
+            while comparison.diff.is_some() && start.elapsed() < Duration::from_secs(5) {
+                std::thread::sleep(Duration::from_millis(5));
+                comparison.poll(&mut jobs, &ctx);
+            }
+            comparison.rows.clone()
+        };
+        let mut tab = Tab::new_comparison(old, new.clone());
+        let title = tab.title();
+        let before = compared(&mut tab).len();
+        fs::write(&new, "# Doc\n\nOld.\n").unwrap();
+        tab.reload();
+        let rows = compared(&mut tab);
+
+        assert_eq!(title, "v1.md ↔ v2.md");
+        assert_eq!(before, 2);
+        assert!(rows.iter().all(|row| matches!(row, compare::Row::Same(..))));
+    }
 
     #[test]
-    fn push_recent_dedupes_and_moves_to_front() {
-        let mut v = Vec::new();
-        push_recent(&mut v, Path::new("/a.md"), 100);
-        push_recent(&mut v, Path::new("/b.md"), 200);
-        push_recent(&mut v, Path::new("/a.md"), 300); // re-open a → moves to front, no dup
-        assert_eq!(v.len(), 2);
-        assert_eq!(v[0].path, PathBuf::from("/a.md"));
-        assert_eq!(v[0].last_opened, 300);
-        assert_eq!(v[1].path, PathBuf::from("/b.md"));
+    fn section_tabs_follow_their_heading() {
+        let content = "# Doc\n\n## A\n\na\n\n### A1\n\n## B\n\nb\n";
+        let mut section = TabSection {
+            title: String::new(),
+            slug: String::new(),
+            nth_with_same_slug: 0,
+            line: 2,
+        };
+        assert_eq!(
+            cut_section(content, &mut section).as_deref(),
+            Some("## A\n\na\n\n### A1\n\n")
+        );
+        assert_eq!((section.title.as_str(), section.slug.as_str()), ("A", "a"));
+
+        // Found by its heading after text is added above it
+        let edited = "# Doc\n\nIntro.\n\n## A\n\na\n\n### A1\n\n## B\n\nb\n";
+        assert!(cut_section(edited, &mut section).is_some());
+        assert_eq!(section.line, 4);
+        // Renamed, it is found where it was
+        let renamed = "# Doc\n\nIntro.\n\n## Z\n\nz\n";
+        assert_eq!(
+            cut_section(renamed, &mut section).as_deref(),
+            Some("## Z\n\nz\n")
+        );
+        assert_eq!(cut_section("# Doc\n", &mut section), None);
     }
 
     #[test]
-    fn push_recent_caps_length_keeping_newest() {
-        let mut v = Vec::new();
-        for i in 0..(RECENT_FILES_CAP + 5) {
-            push_recent(&mut v, Path::new(&format!("/f{i}.md")), i as u64);
-        }
-        assert_eq!(v.len(), RECENT_FILES_CAP);
-        // The most recently pushed entry is at the front.
+    fn outline_and_visible_source_for_automation() {
+        let content = "# Doc\n\nFirst.\n\n## Usage\n\nLast.\n";
+        let parsed = parse_headers(content);
         assert_eq!(
-            v[0].path,
-            PathBuf::from(format!("/f{}.md", RECENT_FILES_CAP + 4))
+            outline_text(parsed.document_title.as_deref(), &parsed.outline_headers),
+            "title: Doc\n# Doc\n## Usage\n"
+        );
+
+        let block = |start: usize, end: usize, top: f32| SourceBlock {
+            byte_range: start..end,
+            line: 1,
+            end_line: 1,
+            y_range: egui::Rangef::new(top, top + 20.0),
+        };
+        let blocks = [block(0, 5, 0.0), block(7, 13, 20.0), block(15, 23, 40.0)];
+        assert_eq!(
+            visible_source(content, &blocks, 25.0, 20.0),
+            "First.\n\n## Usage"
+        );
+    }
+
+    #[test]
+    fn documents_are_decoded_with_the_detected_encoding() {
+        let utf16le: Vec<u8> = "# Café\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let utf16be: Vec<u8> = "# Café\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let with_bom = [&[0xFF, 0xFE][..], &utf16le].concat();
+        let cases: [(&[u8], &str, &str); 5] = [
+            ("# Café\n".as_bytes(), "UTF-8", "# Café\n"),
+            (b"\xEF\xBB\xBF# Hi\n", "UTF-8", "# Hi\n"),
+            (&with_bom, "UTF-16LE", "# Café\n"),
+            (&utf16be, "UTF-16BE", "# Café\n"),
+            (b"# Caf\xE9\n", "windows-1252", "# Café\n"),
+        ];
+        for (bytes, encoding, text) in cases {
+            let (decoded, used) = decode_document(bytes, None);
+            assert_eq!((decoded.as_str(), used.name()), (text, encoding));
+        }
+
+        let (decoded, used) =
+            decode_document("# Café\n".as_bytes(), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(
+            (decoded.as_str(), used.name()),
+            ("# CafÃ©\n", "windows-1252")
         );
     }
 
     #[test]
-    fn relative_time_buckets() {
-        let now = 1_000_000u64;
-        assert_eq!(format_relative_time(now, now), "just now");
-        assert_eq!(format_relative_time(now - 120, now), "2m ago");
-        assert_eq!(format_relative_time(now - 7200, now), "2h ago");
-        assert_eq!(format_relative_time(now - 90_000, now), "yesterday");
-        assert_eq!(format_relative_time(now - 3 * 86_400, now), "3d ago");
-        // Clock skew (future timestamp) must not underflow.
-        assert_eq!(format_relative_time(now + 500, now), "just now");
+    fn large_files_are_read_a_chunk_at_a_time() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("chunk.md");
+        fs::write(&path, "line one\nline two\nline three\n").unwrap();
+        let first = read_document_bytes(&path, Some(12)).unwrap();
+        let second = read_document_bytes(&path, Some(24)).unwrap();
+        let exact = read_document_bytes(&path, Some(29)).unwrap();
+        let all = read_document_bytes(&path, None).unwrap();
+
+        assert_eq!(first, (b"line one\n".to_vec(), true));
+        assert_eq!(second, (b"line one\nline two\n".to_vec(), true));
+        assert_eq!(exact, (b"line one\nline two\nline three\n".to_vec(), false));
+        assert_eq!(all, exact);
+
+        assert_eq!(format_size(300), "300 bytes");
+        assert_eq!(format_size(12 * 1024), "12 KB");
+        assert_eq!(format_size(1_572_864), "1.5 MB");
     }
 
     #[test]
-    fn default_terminal_launch_detaches() {
-        let args = Args::try_parse_from(["md-viewer", "README.md"]).unwrap();
-        assert!(should_detach(&args, true));
+    fn font_fallbacks_need_the_family_weight_and_glyph() {
+        let bundled = egui::FontDefinitions::default();
+        let mut db = fontdb::Database::new();
+        for name in ["NotoEmoji-Regular", "Ubuntu-Light"] {
+            db.load_font_data(bundled.font_data[name].font.to_vec());
+        }
+        let normal = fontdb::Weight::NORMAL;
+
+        let emoji = find_face(&db, &["Missing", "Noto Emoji"], normal, '😀');
+        assert!(emoji.is_some());
+        assert!(!face_has_glyph(&db, emoji.unwrap(), 'Ж'));
+        assert_eq!(find_face(&db, &["Noto Emoji"], normal, 'Ж'), None);
+        // Ubuntu Light is the family but not the weight
+        assert_eq!(find_face(&db, &["Ubuntu"], normal, 'A'), None);
+        let light = find_face(&db, &["Ubuntu"], fontdb::Weight::LIGHT, 'A');
+        assert_eq!(
+            load_face(&db, light.unwrap()).map(|data| data.index),
+            Some(0)
+        );
     }
 
+    #[cfg(feature = "bundled-fonts")]
     #[test]
-    fn non_terminal_launch_does_not_detach() {
-        let args = Args::try_parse_from(["md-viewer", "README.md"]).unwrap();
-        assert!(!should_detach(&args, false));
+    fn bundled_fonts_cover_their_fallback_slots() {
+        let mut db = fontdb::Database::new();
+        for data in typst_assets::fonts() {
+            db.load_font_source(fontdb::Source::Binary(Arc::new(data)));
+        }
+        let bundled = [
+            "DejaVu Sans Mono",
+            "Libertinus Serif",
+            "NewComputerModernMath",
+        ];
+        let covered: Vec<&str> = FONT_FALLBACKS
+            .iter()
+            .filter(|fallback| {
+                let families: Vec<&str> = fallback
+                    .families
+                    .iter()
+                    .copied()
+                    .filter(|family| bundled.contains(family))
+                    .collect();
+                find_face(&db, &families, fontdb::Weight::NORMAL, fallback.sample).is_some()
+            })
+            .map(|fallback| fallback.name)
+            .collect();
+        assert_eq!(
+            covered,
+            ["Arabic", "Hebrew", "Symbols", "Symbols2", "MiscSymbols"]
+        );
     }
 
     #[test]
-    fn foreground_flag_disables_detach() {
-        let args = Args::try_parse_from(["md-viewer", "--foreground", "README.md"]).unwrap();
-        assert!(!should_detach(&args, true));
+    fn restored_history_returns_to_the_line_left() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n\n[b](b.md)\n").unwrap();
+        fs::write(dir.join("b.md"), "# B\n").unwrap();
+        let mut tab = Tab::new(dir.join("a.md"));
+        tab.pending_line = Some(3);
+        tab.navigate_to_link("b.md", None);
+        let json = serde_json::to_string(&tab.history()).unwrap();
+
+        let mut restored = Tab::new(dir.join("b.md"));
+        restored.restore_history(serde_json::from_str(&json).unwrap());
+        restored.navigate_back();
+        let back = (restored.path.clone(), restored.pending_line);
+        restored.navigate_forward();
+        let forward = (restored.path.clone(), restored.pending_line);
+
+        assert_eq!(back, (tab.history_back[0].path.clone(), Some(3)));
+        assert_eq!(forward, (tab.path.clone(), None));
     }
 
     #[test]
-    fn hidden_no_detach_marker_disables_detach() {
-        let args = Args::try_parse_from(["md-viewer", "--no-detach", "README.md"]).unwrap();
-        assert!(!should_detach(&args, true));
+    fn files_on_network_mounts_are_polled() {
+        let info = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+41 22 0:40 / /mnt/share rw,relatime shared:20 - nfs4 server:/export rw,vers=4.2
+42 41 8:3 / /mnt/share/local rw,relatime shared:21 - ext4 /dev/sda3 rw
+43 22 0:41 / /home/me/My\\040Drive rw,nosuid - fuse.rclone drive: rw
+";
+        let mounts = parse_mounts(info);
+        assert_eq!(mounts.len(), 4);
+        assert!(!on_polled_mount(&mounts, Path::new("/home/me/notes.md")));
+        assert!(on_polled_mount(&mounts, Path::new("/mnt/share/docs/a.md")));
+        assert!(!on_polled_mount(
+            &mounts,
+            Path::new("/mnt/share/local/b.md")
+        ));
+        assert!(on_polled_mount(
+            &mounts,
+            Path::new("/home/me/My Drive/c.md")
+        ));
+        // Prefix of a mount point's name without being inside it
+        assert!(!on_polled_mount(&mounts, Path::new("/mnt/shared/d.md")));
     }
 
     #[test]
-    fn child_args_preserve_user_args_and_append_marker() {
-        let child_args = child_args_with_no_detach([
-            OsString::from("md-viewer"),
-            OsString::from("README.md"),
-            OsString::from("--no-watch"),
-        ]);
-
+    fn reloads_are_batched_by_the_reload_policy() {
+        let mut policy = ReloadPolicy {
+            debounce_ms: 200,
+            min_reload_ms: 1000,
+            focused_only: true,
+        };
+        let mut batch = ReloadBatch::default();
+        let (a, b) = (PathBuf::from("/docs/a.md"), PathBuf::from("/docs/b.md"));
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        // Held back in the background, all at once when focused again
+        assert!(batch
+            .take_due(vec![a.clone(), a.clone()], &policy, false, at(0))
+            .is_empty());
         assert_eq!(
-            child_args,
-            vec![
-                OsString::from("README.md"),
-                OsString::from("--no-watch"),
-                OsString::from("--no-detach"),
-            ]
+            batch.take_due(vec![], &policy, true, at(10)),
+            vec![a.clone()]
         );
-    }
 
-    #[test]
-    fn child_args_insert_marker_before_double_dash() {
-        let child_args = child_args_with_no_detach([
-            OsString::from("md-viewer"),
-            OsString::from("--"),
-            OsString::from("README.md"),
-        ]);
+        // Changes within the limit wait for it to pass
+        let due = batch.take_due(vec![a.clone(), b.clone()], &policy, true, at(300));
+        assert_eq!(due, vec![b.clone()]);
+        assert_eq!(
+            batch.next_due(&policy, at(300)),
+            Some(Duration::from_millis(710))
+        );
+        assert!(batch
+            .take_due(vec![a.clone()], &policy, true, at(900))
+            .is_empty());
+        assert_eq!(
+            batch.take_due(vec![], &policy, true, at(1010)),
+            vec![a.clone()]
+        );
+        assert_eq!(batch.next_due(&policy, at(1010)), None);
 
+        policy.min_reload_ms = 0;
+        policy.focused_only = false;
         assert_eq!(
-            child_args,
-            vec![
-                OsString::from("--no-detach"),
-                OsString::from("--"),
-                OsString::from("README.md"),
-            ]
+            batch.take_due(vec![a.clone()], &policy, false, at(1011)),
+            vec![a]
         );
     }
 
     #[test]
-    fn hidden_no_detach_marker_is_not_in_help() {
-        use clap::CommandFactory;
-
-        let help = Args::command().render_long_help().to_string();
-        assert!(help.contains("--foreground"));
-        assert!(!help.contains("--no-detach"));
+    fn trusted_folders_cover_the_folders_below_them() {
+        let trusted = [PathBuf::from("/home/me/notes"), PathBuf::from("/srv/docs")];
+        assert!(is_trusted(Path::new("/home/me/notes/a.md"), &trusted));
+        assert!(is_trusted(Path::new("/srv/docs/guide/b.md"), &trusted));
+        assert!(!is_trusted(Path::new("/home/me/notes-old/c.md"), &trusted));
+        assert!(!is_trusted(Path::new("/home/me/Downloads/d.md"), &[]));
     }
 
     #[test]
-    fn capped_content_width_uses_optimal_width() {
-        assert_eq!(content_default_width(false), Some(600));
+    fn root_relative_links_resolve_inside_the_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("docs/guide.md"), "# Guide\n").unwrap();
+        fs::write(dir.join("docs/a.md"), "[guide](/docs/guide.md)\n").unwrap();
+        let tab = Tab::new(dir.join("docs/a.md"));
+        let rooted = tab.resolve_link("/docs/guide.md#setup", Some(dir));
+        let unrooted = tab.resolve_link("/docs/guide.md", None);
+        let relative = tab.resolve_link("guide.md", Some(dir));
+        let guide = dir.join("docs/guide.md").canonicalize().unwrap();
+
+        assert_eq!(rooted, Some(guide.clone()));
+        assert_eq!(unrooted, None);
+        assert_eq!(relative, Some(guide));
     }
 
     #[test]
-    fn full_width_content_uses_available_width() {
-        assert_eq!(content_default_width(true), None);
+    fn directory_readme_prefers_the_readme_over_an_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("index.md"), "# Index\n").unwrap();
+        let index = directory_readme(dir);
+        fs::write(dir.join("Readme.md"), "# Readme\n").unwrap();
+        let readme = directory_readme(dir);
+        let none = directory_readme(&dir.join("docs"));
+
+        assert_eq!(index, Some(dir.join("index.md")));
+        assert_eq!(readme, Some(dir.join("Readme.md")));
+        assert_eq!(none, None);
     }
 
     #[test]
-    fn find_matches_empty_query_returns_none() {
-        assert_eq!(find_matches("hello world", ""), vec![]);
+    fn session_snapshot_restores_the_tabs_that_still_exist() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let a = dir.join("a.md");
+        fs::write(&a, "# A\n").unwrap();
+        let a = a.canonicalize().unwrap();
+        let state = PersistedState {
+            open_tabs: Some(vec![dir.join("gone.md"), a.clone()]),
+            reading_positions: Some(vec![ReadingPosition {
+                path: a.clone(),
+                line: 7,
+            }]),
+            show_outline: Some(false),
+            ..Default::default()
+        };
+        let path = dir.join("snapshots").join("session.json");
+        write_session_snapshot(&path, &serde_json::to_string(&state).unwrap()).unwrap();
+        let recovered = read_session_snapshot(&path).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+        let garbled = read_session_snapshot(&path);
+        let tabs = session_tabs(
+            recovered.open_tabs.unwrap(),
+            Vec::new(),
+            &recovered.reading_positions.unwrap(),
+        );
+
+        assert_eq!(recovered.show_outline, Some(false));
+        assert!(garbled.is_none());
+        assert_eq!(tabs.len(), 1);
+        assert_eq!((tabs[0].path.clone(), tabs[0].pending_line), (a, Some(7)));
     }
 
     #[test]
-    fn find_matches_empty_content_returns_none() {
-        assert_eq!(find_matches("", "hello"), vec![]);
+    fn only_sessions_of_viewers_no_longer_running_are_recovered() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let session = |tab: &str| PersistedState {
+            open_tabs: Some(vec![PathBuf::from(tab)]),
+            ..Default::default()
+        };
+        let write = |pid: u32, state: &PersistedState| {
+            let json = serde_json::to_string(state).unwrap();
+            write_session_snapshot(&session_snapshot_file(dir, pid), &json).unwrap();
+        };
+        let open_tabs = |state: Option<PersistedState>| state.and_then(|s| s.open_tabs);
+        fs::write(dir.join("session-snapshot-notes.json"), "{}").unwrap();
+
+        // A second viewer starting while the first runs
+        write(100, &session("first.md"));
+        assert!(claim_crashed_session(dir, 200, |pid| pid == 100).is_none());
+        write(200, &session("second.md"));
+
+        // The first crashes: the next viewer to start takes its session over
+        let first = claim_crashed_session(dir, 300, |pid| pid == 200);
+        assert_eq!(open_tabs(first), Some(vec![PathBuf::from("first.md")]));
+        assert!(!session_snapshot_file(dir, 100).exists());
+        assert!(claim_crashed_session(dir, 400, |pid| pid == 200 || pid == 300).is_none());
+
+        // The second exits cleanly without touching the taken over one
+        fs::remove_file(session_snapshot_file(dir, 200)).unwrap();
+        let taken = read_session_snapshot(&session_snapshot_file(dir, 300));
+        assert_eq!(open_tabs(taken), Some(vec![PathBuf::from("first.md")]));
+
+        assert_eq!(
+            session_snapshot_owner(Path::new("/x/session-snapshot-42.json")),
+            Some(42)
+        );
+        assert!(process_is_running(std::process::id()));
     }
 
     #[test]
-    fn find_matches_single_ascii() {
-        let m = find_matches("hello world", "world");
-        assert_eq!(m.len(), 1);
-        assert_eq!(m[0].byte_start, 6);
-        assert_eq!(m[0].byte_end, 11);
-        assert_eq!(m[0].line_number, 1);
+    fn older_crashed_sessions_are_dropped_once_one_is_recovered() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let start = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        for (pid, tab) in [(100, "old.md"), (200, "new.md")] {
+            let state = PersistedState {
+                open_tabs: Some(vec![PathBuf::from(tab)]),
+                ..Default::default()
+            };
+            let path = session_snapshot_file(dir, pid);
+            write_session_snapshot(&path, &serde_json::to_string(&state).unwrap()).unwrap();
+            let modified = start + std::time::Duration::from_secs(pid.into());
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let recovered = claim_crashed_session(dir, 300, |_| false).and_then(|s| s.open_tabs);
+
+        assert_eq!(recovered, Some(vec![PathBuf::from("new.md")]));
+        assert!(!session_snapshot_file(dir, 100).exists());
+        assert!(claim_crashed_session(dir, 400, |pid| pid == 300).is_none());
     }
 
     #[test]
-    fn find_matches_case_insensitive_ascii() {
-        let m = find_matches("Hello WORLD", "world");
-        assert_eq!(m.len(), 1);
-        assert_eq!(&"Hello WORLD"[m[0].byte_start..m[0].byte_end], "WORLD");
+    fn reloading_a_missing_file_keeps_its_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("a.md");
+        fs::write(&path, "# One\n").unwrap();
+        let mut tab = Tab::new(path.clone());
+        fs::remove_file(&path).unwrap();
+        tab.reload();
+        let missing = (
+            tab.content.clone(),
+            tab.missing_since.is_some(),
+            tab.is_missing(),
+        );
+        fs::write(&path, "# Two\n").unwrap();
+        tab.reload();
+
+        assert_eq!(missing, ("# One\n".to_string(), true, false));
+        assert_eq!(tab.content, "# Two\n");
+        assert_eq!(tab.missing_since, None);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn find_matches_multiple_per_line() {
-        // "foo bar foo baz foo" → 3 matches, all on line 1
-        let m = find_matches("foo bar foo baz foo", "foo");
-        assert_eq!(m.len(), 3);
-        assert!(m.iter().all(|sm| sm.line_number == 1));
-        let starts: Vec<_> = m.iter().map(|sm| sm.byte_start).collect();
-        assert_eq!(starts, vec![0, 8, 16]);
+    fn atomic_saves_change_file_identity() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let path = dir.join("a.md");
+        fs::write(&path, "# One\n").unwrap();
+        let before = file_identity(&path);
+        fs::write(&path, "# Two\n").unwrap();
+        let in_place = file_identity(&path);
+        fs::write(dir.join("a.md.tmp"), "# Three\n").unwrap();
+        fs::rename(dir.join("a.md.tmp"), &path).unwrap();
+        let replaced = file_identity(&path);
+        let dirs = tab_dirs(&[&path, &dir.join("b.md")]);
+        drop(tmp);
+
+        assert!(before.is_some());
+        assert_eq!(in_place, before);
+        assert_ne!(replaced, before);
+        assert_eq!(file_identity(&path), None);
+        assert_eq!(dirs, HashSet::from([dir]));
     }
 
+    #[cfg(unix)]
     #[test]
-    fn find_matches_line_numbers_one_based() {
-        let content = "first line\nsecond match here\nthird\nmatch on four";
-        let m = find_matches(content, "match");
-        assert_eq!(m.len(), 2);
-        assert_eq!(m[0].line_number, 2);
-        assert_eq!(m[1].line_number, 4);
+    fn expand_all_stops_at_cyclic_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("docs/inner")).unwrap();
+        fs::write(root.join("docs/inner/a.md"), "# A\n").unwrap();
+        std::os::unix::fs::symlink(root.join("docs"), root.join("docs/inner/loop")).unwrap();
+        std::os::unix::fs::symlink(root.join("docs/inner/a.md"), root.join("b.md")).unwrap();
+
+        let mut explorer = FileExplorer::default();
+        explorer.set_root(root.to_path_buf());
+        explorer.expand_all();
+        let expanded = explorer.expanded_dirs.clone();
+        let links: Vec<(String, bool)> = explorer
+            .tree
+            .iter()
+            .map(|node| match node {
+                FileTreeNode::File {
+                    name, is_symlink, ..
+                }
+                | FileTreeNode::Directory {
+                    name, is_symlink, ..
+                } => (name.clone(), *is_symlink),
+            })
+            .collect();
+
+        explorer.skip_symlinks = true;
+        explorer.refresh();
+        let names: Vec<&str> = explorer.tree.iter().map(FileTreeNode::name).collect();
+
+        assert_eq!(
+            expanded,
+            HashSet::from([root.join("docs"), root.join("docs/inner")])
+        );
+        assert_eq!(
+            links,
+            [("docs".to_string(), false), ("b.md".to_string(), true)]
+        );
+        assert_eq!(names, ["docs"]);
     }
 
     #[test]
-    fn find_matches_preserves_byte_offsets_with_utf8() {
-        // "café résumé naïve" — each accented char is 2 bytes in UTF-8
-        let content = "café résumé naïve";
-        let m = find_matches(content, "café");
-        assert_eq!(m.len(), 1);
-        // Confirm byte range slices back to the original substring
-        assert_eq!(&content[m[0].byte_start..m[0].byte_end], "café");
+    fn file_uris_are_percent_encoded() {
+        assert_eq!(
+            file_uri(Path::new("/home/me/My Notes/ü#1.md")),
+            "file:///home/me/My%20Notes/%C3%BC%231.md"
+        );
+        assert_eq!(
+            file_uri(Path::new(r"C:\Users\me\notes.md")),
+            "file:///C:/Users/me/notes.md"
+        );
     }
 
     #[test]
-    fn find_matches_skips_cross_newline_matches() {
-        // Query "oo\nb" theoretically spans two lines — should not be reported
-        let content = "foo\nbar";
-        let m = find_matches(content, "oo\nb");
-        assert_eq!(m, vec![]);
+    fn editor_command_fills_in_file_and_line() {
+        assert_eq!(
+            default_editor_command(Some(" "), Some("/usr/bin/code --wait"), None),
+            "/usr/bin/code --wait --goto {file}:{line}"
+        );
+        // Terminal editors need a terminal to run in
+        assert_eq!(
+            default_editor_command(None, Some("nvim"), Some("kitty")),
+            "kitty -e nvim +{line} {file}"
+        );
+        assert_eq!(
+            default_editor_command(Some("vim"), None, None),
+            default_editor_command(None, None, None)
+        );
+        assert_eq!(
+            editor_command_args(
+                "code --goto {file}:{line}",
+                Path::new("/tmp/My Notes.md"),
+                12
+            ),
+            vec!["code", "--goto", "/tmp/My Notes.md:12"]
+        );
+
+        let block = |line: usize, top: f32| SourceBlock {
+            byte_range: 0..0,
+            line,
+            end_line: line,
+            y_range: egui::Rangef::new(top, top + 50.0),
+        };
+        let blocks = [block(1, 0.0), block(4, 50.0), block(9, 100.0)];
+        assert_eq!(line_at_scroll(&blocks, 0.0), 1);
+        assert_eq!(line_at_scroll(&blocks, 50.0), 4);
+        assert_eq!(line_at_scroll(&blocks, 500.0), 9);
+        assert_eq!(line_at_scroll(&[], 10.0), 1);
     }
 
     #[test]
-    fn find_matches_overlapping_uses_match_indices_semantics() {
-        // str::match_indices does NOT overlap matches: "aaaa" / "aa" → [0, 2]
-        let m = find_matches("aaaa", "aa");
-        let starts: Vec<_> = m.iter().map(|sm| sm.byte_start).collect();
-        assert_eq!(starts, vec![0, 2]);
+    fn shell_code_blocks_run_in_the_document_folder() {
+        assert_eq!(
+            code_block_runner("bash", ""),
+            Some(CodeRunner::Shell("bash"))
+        );
+        assert_eq!(
+            code_block_runner("shell", ""),
+            Some(CodeRunner::Shell("sh"))
+        );
+        assert_eq!(code_block_runner("python", DEFAULT_RUST_RUNNER), None);
+
+        let go = AtomicBool::new(false);
+        let dir = std::env::temp_dir();
+        let sh = CodeRunner::Shell("sh");
+        let output = sh.run("pwd; echo oops >&2; exit 3", Some(&dir), &go);
+        assert!(output.finished);
+        assert_eq!(
+            Path::new(output.stdout.trim()).canonicalize().unwrap(),
+            dir.canonicalize().unwrap()
+        );
+        assert_eq!(output.stderr, "oops\n");
+        assert_eq!(output.status, Some(3));
+
+        let failed = CodeRunner::Shell("no-such-shell-for-md-viewer").run("true", None, &go);
+        assert!(failed.finished && failed.status.is_none());
+        assert!(failed.stderr.starts_with("Failed to start"));
+
+        let long = limit_output("é".repeat(CODE_RUN_OUTPUT_LIMIT));
+        assert!(long.ends_with("[output truncated]"));
+        assert!(long.len() <= CODE_RUN_OUTPUT_LIMIT + 20);
     }
 
     #[test]
-    fn find_matches_skips_image_alt_text() {
-        // Image alt text isn't visibly rendered (used only as hover/screen-reader text).
-        // A match inside the alt portion of `![alt](url)` would have no visible target
-        // for highlighting/scrolling — exclude it.
-        let content = "See ![Syntax docs](pic.png) and the syntax guide.";
-        let m = find_matches(content, "syntax");
-        let starts: Vec<_> = m.iter().map(|sm| sm.byte_start).collect();
-        assert_eq!(m.len(), 1, "got {:?}", m);
-        // The surviving match is "syntax" in "the syntax guide", not "Syntax" in alt
-        assert_eq!(&content[starts[0]..starts[0] + 6], "syntax");
+    fn endless_code_block_runs_are_stopped() {
+        let sh = CodeRunner::Shell("sh");
+        let endless = sh.run("while :; do echo y; done", None, &AtomicBool::new(false));
+        assert!(endless.stdout.ends_with("[output truncated]"));
+        assert_eq!(endless.stderr, "\n[stopped after printing too much]");
+        assert_eq!(endless.status, None);
+
+        // What the shell started goes along with it
+        let started = Instant::now();
+        let stopped = sh.run("sleep 30; echo done", None, &AtomicBool::new(true));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(stopped.stdout, "");
+        assert!(stopped.stderr.ends_with("[stopped]"));
     }
 
     #[test]
-    fn find_matches_skips_image_url() {
-        // Image URL isn't visibly rendered — exclude matches inside.
-        let content = "![ok](path/syntax.png) more text";
-        let m = find_matches(content, "syntax");
-        assert_eq!(m.len(), 0, "URL match should be filtered: {:?}", m);
+    fn sandboxed_tabs_get_no_code_runner() {
+        let footer = |_: &mut egui::Ui, _: &str, _: &str, _: egui::Id| {};
+        assert!(code_run_footer(true, false, &footer).is_some());
+        assert!(code_run_footer(true, true, &footer).is_none());
+        assert!(code_run_footer(false, false, &footer).is_none());
     }
 
     #[test]
-    fn find_matches_skips_link_url_keeps_link_text() {
-        // Link text IS rendered (visible clickable text). Link URL is not.
-        let content = "Click [syntax docs](https://example.com/syntax.html) here";
-        let m = find_matches(content, "syntax");
-        // Two raw matches: "syntax" in link text (visible) + "syntax" in URL (invisible)
-        assert_eq!(m.len(), 1, "should keep link text, drop URL: {:?}", m);
-        let s = m[0].byte_start;
-        // The kept match is "syntax" in "[syntax docs]" (visible link text)
-        assert_eq!(&content[s..s + 6], "syntax");
-        // And it's the FIRST occurrence (inside the brackets), not the URL one
-        assert!(s < content.find("https").unwrap());
+    fn rust_code_blocks_run_through_the_configured_runner() {
+        let runner = code_block_runner("rust", "cat {file}").unwrap();
+        assert_eq!(runner.program(), "cat");
+        assert_eq!(
+            code_block_runner("rust,edition2021", "cat {file}"),
+            Some(runner.clone())
+        );
+        assert_eq!(code_block_runner("rust,no_run", "cat {file}"), None);
+        assert_eq!(code_block_runner("rust", " "), None);
+
+        // The code reaches the runner through a file that is gone afterwards
+        let go = AtomicBool::new(false);
+        let code = "fn main() { println!(\"hi\"); }\n";
+        let output = runner.run(code, None, &go);
+        assert_eq!(output.stdout, code);
+        assert_eq!(output.status, Some(0));
+        // Each run gets its own file, so the same code can run twice at once
+        let echo = code_block_runner("rust", "echo {file}").unwrap();
+        let (first, second) = (
+            echo.run(code, None, &go).stdout,
+            echo.run(code, None, &go).stdout,
+        );
+        assert_ne!(first, second);
+        assert!(first.contains("md-viewer-") && first.trim().ends_with(".rs"));
+        assert!(!Path::new(first.trim()).exists());
+        assert!(!Path::new(second.trim()).exists());
+        // Also when the runner fails
+        let failing = code_block_runner("rust", "ls {file} /no/such/md-viewer/file").unwrap();
+        let output = failing.run(code, None, &go);
+        assert_ne!(output.status, Some(0));
+        assert!(output.stdout.trim().ends_with(".rs"));
+        assert!(!Path::new(output.stdout.trim()).exists());
     }
 
     #[test]
-    fn find_matches_multiple_alt_text_images() {
-        let content = "![a one](u.png) one ![two two](w.png) two";
-        let m = find_matches(content, "one");
-        let starts: Vec<_> = m.iter().map(|sm| sm.byte_start).collect();
-        // Two raw "one" matches in alt + 1 in visible text → only 1 survives
-        assert_eq!(m.len(), 1);
-        assert_eq!(&content[starts[0]..starts[0] + 3], "one");
+    fn tasks_are_found_and_toggled_in_the_source() {
+        let content = "# Plan\n\
+            - [ ] Write docs\n\
+            \x20 * [x] Nested done\n\
+            > 1. [X] Quoted\n\
+            - [link](x) not a task\n\
+            -[ ] no space\n\
+            ```\n- [ ] in code\n```\n\
+            + [ ]\n";
+        let tasks = parse_tasks(content);
+        let summary: Vec<_> = tasks
+            .iter()
+            .map(|t| (t.line, t.done, t.text.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, false, "Write docs"),
+                (3, true, "Nested done"),
+                (4, true, "Quoted"),
+                (10, false, ""),
+            ]
+        );
 
-        let m2 = find_matches(content, "two");
-        // Two raw "two" matches in alt + 1 in visible text → only 1 survives
-        assert_eq!(m2.len(), 1);
+        let toggled = toggle_task(content, &tasks[0]).unwrap();
+        assert!(toggled.contains("- [x] Write docs\n"));
+        let toggled = toggle_task(&toggled, &tasks[1]).unwrap();
+        assert!(toggled.contains("  * [ ] Nested done\n"));
+        // Not after the file changed under the listed task
+        assert_eq!(toggle_task(&toggled, &tasks[0]), None);
+        let inserted = content.replacen("# Plan\n", "# Plan\n- [ ] New\n", 1);
+        assert_eq!(toggle_task(&inserted, &tasks[0]), None);
+        let moved = TaskItem {
+            line: 1,
+            ..tasks[0].clone()
+        };
+        assert_eq!(toggle_task(content, &moved), None);
     }
 
     #[test]
-    fn shortcode_search_range_uses_raw_source_bytes() {
-        let content = "before :pushpin: after";
-        let matches = find_matches(content, ":pushpin:");
-        assert_eq!(matches.len(), 1);
-        assert_eq!(matches[0].byte_start, 7);
-        assert_eq!(matches[0].byte_end, 16);
-        assert_eq!(
-            &content[matches[0].byte_start..matches[0].byte_end],
-            ":pushpin:"
-        );
+    fn tasks_are_toggled_in_the_encoding_of_the_file() {
+        let source = "# Café\n\n- [ ] Crème brûlée\n";
+        let (bytes, _, _) = encoding_rs::WINDOWS_1252.encode(source);
+        let task = parse_tasks(source).remove(0);
+
+        let toggled = toggle_task_in_document(&bytes, None, &task).unwrap();
+
+        let (text, encoding) = decode_document(&toggled, None);
+        assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+        assert_eq!(text, "# Café\n\n- [x] Crème brûlée\n");
     }
 
     #[test]
-    fn rendered_emoji_does_not_match_shortcode_only_source() {
-        assert!(find_matches("before :pushpin: after", "📌").is_empty());
+    fn source_view_is_one_code_block_of_the_document() {
+        let content = "# Doc\n\n```rust\nlet x = 1;\n```\n\n````\nnested\n````\n";
+        let view = source_view(content, "markdown");
+        assert!(view.starts_with("`````markdown\n# Doc\n"));
+        assert_eq!(view.lines().nth(3), content.lines().nth(2));
+        let events: Vec<_> = pulldown_cmark::Parser::new(&view).collect();
+        let code: String = events
+            .iter()
+            .filter_map(|event| match event {
+                pulldown_cmark::Event::Text(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(code, content);
+        assert_eq!(events.len(), 3, "a single block: {events:?}");
     }
 
     #[test]
-    fn literal_unicode_emoji_still_matches_raw_source() {
-        let matches = find_matches("before 📌 after", "📌");
-        assert_eq!(matches.len(), 1);
-        assert_eq!(matches[0].byte_start, 7);
-        assert_eq!(matches[0].byte_end, 11);
+    fn source_view_shows_the_text_of_the_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(
+            tmp.path().join("references.bib"),
+            "@book{sicp, author = {Abelson, Harold}, title = {SICP}, year = {1996}}\n",
+        )
+        .unwrap();
+        let path = tmp.path().join("a.md");
+        let source = "# Doc\n\n## A\n\nSee [@sicp].\n\n\\newpage\n\n## B\n\nb\n";
+        fs::write(&path, source).unwrap();
+        let mut tab = Tab::new(path.clone());
+        assert!(tab.content.contains(pages::PAGE_BREAK));
+        tab.toggle_source_view();
+        assert_eq!(tab.source_view, Some(source_view(source, "markdown")));
+        // Matches are offsets into the text shown
+        tab.rebuild_search("newpage");
+        assert_eq!(tab.search_matches.len(), 1);
+        assert_eq!(
+            tab.search_matches[0].byte_start,
+            source.find("newpage").unwrap()
+        );
+        tab.toggle_source_view();
+        tab.rebuild_search("newpage");
+        assert!(tab.search_matches.is_empty());
+
+        // Leaving the source view partway down keeps the line read there
+        tab.toggle_source_view();
+        let lines = source.lines().count();
+        let id = egui::Id::new(tab.id);
+        tab.cache.record_source_block(
+            id,
+            SourceBlock {
+                byte_range: 0..tab.source_view.as_ref().unwrap().len(),
+                line: 1,
+                end_line: lines + 2,
+                y_range: egui::Rangef::point(0.0),
+            },
+        );
+        tab.cache
+            .finish_source_blocks(id, (lines + 2) as f32 * 20.0);
+        tab.scroll_offset = 6.0 * 20.0 + 5.0;
+        assert_eq!(tab.reading_line(), 6);
+        tab.toggle_source_view();
+        assert_eq!(tab.pending_line, Some(6));
+
+        // A section tab shows the source of its section
+        let section = Tab::new_section(path, 2).unwrap();
+        assert_eq!(section.source, "## A\n\nSee [@sicp].\n\n\\newpage\n\n");
     }
 
     #[test]
-    fn shortcode_heading_parser_keeps_raw_identity_and_duplicate_index() {
-        let parsed = parse_headers("# Doc\n\n## Pin :pushpin:\n\n## Pin :pushpin:\n");
-        assert_eq!(parsed.outline_headers.len(), 3);
-        assert_eq!(parsed.outline_headers[1].title, "Pin :pushpin:");
-        assert_eq!(parsed.outline_headers[1].normalized_title, "pin :pushpin:");
-        assert_eq!(parsed.outline_headers[1].nth_with_same_text, 0);
-        assert_eq!(parsed.outline_headers[2].normalized_title, "pin :pushpin:");
-        assert_eq!(parsed.outline_headers[2].nth_with_same_text, 1);
+    fn task_view_keeps_tasks_with_their_headings_and_parents() {
+        let content = "# Plan\n\
+            Intro text.\n\
+            ## Done\n\
+            - Nothing to do\n\
+            ## Next\n\
+            - Release\n\
+            \x20 - [ ] Tag it\n\
+            \x20 - Announce\n\
+            1. [x] Write docs\n\
+            ```\n- [ ] in code\n```\n\
+            # Notes\n\
+            Just text.\n";
         assert_eq!(
-            header_position_key(
-                &parsed.outline_headers[2].normalized_title,
-                parsed.outline_headers[2].nth_with_same_text,
-            ),
-            "pin :pushpin:#1"
+            task_list_view(content),
+            "# Plan\n\n## Next\n\n- Release\n  - [ ] Tag it\n1. [x] Write docs\n"
+        );
+        assert_eq!(
+            task_list_view("# Title\n\nNo tasks.\n"),
+            "*No tasks in this document.*\n"
         );
     }
 
     #[test]
-    fn unknown_shortcode_heading_stays_raw() {
-        let parsed = parse_headers("# Doc\n\n## Pin :not_a_gemoji:\n");
-        assert_eq!(parsed.outline_headers[1].title, "Pin :not_a_gemoji:");
+    fn git_log_and_status_are_parsed_for_the_status_bar() {
+        let log = parse_git_log(
+            "a1b2c3d\x00Ada Lovelace\x001700000000\x00Fix: a\x00b\nbroken line\n9f8e7d6\x00Bob\x001600000000\x00\n",
+        );
         assert_eq!(
-            parsed.outline_headers[1].normalized_title,
-            "pin :not_a_gemoji:"
+            log,
+            vec![
+                GitLogEntry {
+                    hash: "a1b2c3d".to_string(),
+                    author: "Ada Lovelace".to_string(),
+                    time: 1_700_000_000,
+                    subject: "Fix: a\0b".to_string(),
+                },
+                GitLogEntry {
+                    hash: "9f8e7d6".to_string(),
+                    author: "Bob".to_string(),
+                    time: 1_600_000_000,
+                    subject: String::new(),
+                },
+            ]
         );
+
+        assert_eq!(git_status_badge(""), None);
+        assert_eq!(git_status_badge(" M notes.md\n"), Some("Modified"));
+        assert_eq!(git_status_badge("?? notes.md\n"), Some("Untracked"));
+    }
+
+    #[test]
+    fn wheel_scrolling_snaps_to_the_nearest_heading_in_range() {
+        let headings = [0.0, 650.0, 1250.0];
+        assert_eq!(heading_snap_offset(560.0, 800.0, &headings), Some(600.0));
+        assert_eq!(heading_snap_offset(1100.0, 800.0, &headings), Some(1200.0));
+        // Nothing within a quarter viewport, or already there
+        assert_eq!(heading_snap_offset(900.0, 800.0, &headings), None);
+        assert_eq!(heading_snap_offset(600.0, 800.0, &headings), None);
     }
 
     #[test]
@@ -4860,4 +12686,27 @@ mod tests {
             0.0
         );
     }
+
+    #[test]
+    fn reduced_motion_disables_every_animation() {
+        let motion = MotionConfig::new(true);
+        assert_eq!(motion.animation_time, 0.0);
+        assert_eq!(
+            motion.scroll_animation,
+            egui::style::ScrollAnimation::none()
+        );
+        assert_eq!(motion.flash_intensity(Duration::ZERO), 0.0);
+
+        let mut style = egui::Style::default();
+        motion.apply(&mut style);
+        assert_eq!(style.animation_time, 0.0);
+    }
+
+    #[test]
+    fn flash_fades_out_over_its_duration() {
+        let motion = MotionConfig::new(false);
+        assert_eq!(motion.flash_intensity(Duration::ZERO), 1.0);
+        assert_eq!(motion.flash_intensity(motion.flash_duration / 2), 0.5);
+        assert_eq!(motion.flash_intensity(motion.flash_duration), 0.0);
+    }
 }
@@ -0,0 +1,164 @@
+//! Page layout for the page preview.
+//!
+//! Pages are measured in CSS pixels, 96 to the inch, so the preview column is
+//! as wide as the printable area of a page printed from an HTML export. A
+//! line of just `\newpage` or `\pagebreak`, as in LaTeX, breaks the page by
+//! hand: it becomes a `<div class="page-break">` that browsers print as a
+//! page break and the viewer marks as one.
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+/// The HTML a manual page break becomes.
+pub const PAGE_BREAK: &str = "<div class=\"page-break\" style=\"break-after: page\"></div>";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageSize {
+    A4,
+    Letter,
+}
+
+impl PageSize {
+    pub const ALL: [Self; 2] = [Self::A4, Self::Letter];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::A4 => "A4",
+            Self::Letter => "Letter",
+        }
+    }
+
+    fn size(self) -> egui::Vec2 {
+        match self {
+            Self::A4 => egui::vec2(794.0, 1123.0),
+            Self::Letter => egui::vec2(816.0, 1056.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Margins {
+    Narrow,
+    Normal,
+    Wide,
+}
+
+impl Margins {
+    pub const ALL: [Self; 3] = [Self::Narrow, Self::Normal, Self::Wide];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Narrow => "Narrow Margins",
+            Self::Normal => "Normal Margins",
+            Self::Wide => "Wide Margins",
+        }
+    }
+
+    /// Half an inch, an inch and an inch and a half
+    pub fn width(self) -> f32 {
+        match self {
+            Self::Narrow => 48.0,
+            Self::Normal => 96.0,
+            Self::Wide => 144.0,
+        }
+    }
+}
+
+/// The pages the preview lays a document out on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageSetup {
+    pub size: PageSize,
+    pub margins: Margins,
+}
+
+impl PageSetup {
+    /// The part of a page inside the margins
+    pub fn printable(self) -> egui::Vec2 {
+        self.size.size() - egui::Vec2::splat(2.0 * self.margins.width())
+    }
+}
+
+/// `content` with its `\newpage` and `\pagebreak` lines, outside code blocks
+/// and followed by a blank line or the end, turned into [`PAGE_BREAK`]s. The
+/// lines stay where they are, so line numbers hold.
+pub fn process_document(content: String) -> String {
+    if !content.contains("\\newpage") && !content.contains("\\pagebreak") {
+        return content;
+    }
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut fence: Option<&str> = None;
+    let mut processed = String::with_capacity(content.len());
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let marker = ["```", "~~~"]
+            .into_iter()
+            .find(|marker| trimmed.starts_with(marker));
+        match (fence, marker) {
+            (None, Some(marker)) => fence = Some(marker),
+            (Some(open), Some(marker)) if open == marker => fence = None,
+            _ => {}
+        }
+        let next_blank = lines.get(i + 1).map_or(true, |next| next.trim().is_empty());
+        if fence.is_none() && matches!(trimmed, "\\newpage" | "\\pagebreak") && next_blank {
+            processed.push_str(PAGE_BREAK);
+            processed.push_str(&line[line.trim_end().len()..]);
+        } else {
+            processed.push_str(line);
+        }
+    }
+    processed
+}
+
+/// Where each page after the first starts, in content coordinates, given the
+/// extents of the document's blocks and whether each is a page break. A block
+/// crossing the bottom of a page starts the next one, unless it's taller than
+/// a page and is cut where the page ends.
+pub fn page_starts(blocks: &[(egui::Rangef, bool)], page_height: f32) -> Vec<f32> {
+    let mut starts = Vec::new();
+    let mut top = 0.0;
+    for &(extent, page_break) in blocks {
+        if page_break {
+            if extent.max > top {
+                starts.push(extent.max);
+                top = extent.max;
+            }
+            continue;
+        }
+        while extent.max > top + page_height {
+            top = if extent.min > top {
+                extent.min
+            } else {
+                top + page_height
+            };
+            starts.push(top);
+        }
+    }
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_break_before_overflowing_blocks_and_at_page_breaks() {
+        let content = "Intro\n\n\\newpage\n\n```\n\\newpage\n\n```\n\\pagebreak\ntext\n";
+        assert_eq!(
+            process_document(content.to_string()),
+            format!("Intro\n\n{PAGE_BREAK}\n\n```\n\\newpage\n\n```\n\\pagebreak\ntext\n")
+        );
+
+        let block = |min: f32, max: f32| (egui::Rangef::new(min, max), false);
+        let blocks = [
+            block(0.0, 400.0),
+            block(400.0, 900.0),
+            block(900.0, 1100.0),
+            (egui::Rangef::new(1100.0, 1120.0), true),
+            block(1120.0, 3400.0),
+        ];
+        assert_eq!(
+            page_starts(&blocks, 1000.0),
+            vec![900.0, 1120.0, 2120.0, 3120.0]
+        );
+    }
+}
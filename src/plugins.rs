@@ -0,0 +1,507 @@
+//! WASM plugins that add renderers without touching the core app.
+//!
+//! Plugins are the `.wasm` files in `$XDG_CONFIG_HOME/md-viewer/plugins`
+//! (`~/.config/md-viewer/plugins` by default) and those passed with
+//! `--plugin`. A plugin exports its `memory`, an `alloc(len: i32) -> i32` the
+//! viewer copies its arguments into, optionally a `dealloc(ptr: i32, len: i32)`
+//! the viewer hands them back to once a call returned, and any of:
+//!
+//! - `fences() -> i64`, the code fence languages it renders, one per line,
+//!   and `render_fence(lang, lang_len, code, code_len) -> i64`, the SVG or
+//!   text a fenced block is shown as
+//! - `schemes() -> i64`, link schemes such as `jira`, one per line, and
+//!   `rewrite_link(url, url_len) -> i64`, where a link with one of them goes
+//! - `process_document(text, text_len) -> i64`, the markdown shown in place
+//!   of a document's source
+//!
+//! Strings are UTF-8. Results pack their pointer into the high and their
+//! length into the low 32 bits, and an empty result leaves things as they
+//! were. Native (dylib) plugins are not loaded, since they can't be
+//! sandboxed.
+
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use eframe::egui;
+use wasmi::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Val,
+};
+
+/// Instructions a plugin may execute per call. Calls run while a frame is
+/// drawn, so a stuck one holds it up for a few tens of milliseconds at most.
+const FUEL_PER_CALL: u64 = 10_000_000;
+
+/// Bytes a plugin's memory may grow to, so a runaway one can't exhaust the
+/// viewer's.
+const MAX_MEMORY_BYTES: usize = 256 << 20;
+
+/// Rendered fences kept before they are all dropped.
+const MAX_RENDERED: usize = 256;
+
+/// Rewritten links kept before they are all dropped.
+const MAX_REWRITTEN: usize = 4096;
+
+static PLUGINS: OnceLock<Mutex<Plugins>> = OnceLock::new();
+
+/// Load the plugins from the plugin directory and `extra`, once at startup.
+/// Plugins that fail to load are logged and skipped.
+pub fn init(extra: &[PathBuf]) {
+    let mut plugins = Plugins::default();
+    let files = plugin_dir()
+        .map(|dir| plugin_files(&dir))
+        .unwrap_or_default();
+    for path in files.iter().chain(extra) {
+        match Plugin::load(path) {
+            Ok(plugin) => {
+                log::info!("Loaded plugin {}", path.display());
+                plugins.loaded.push(plugin);
+            }
+            Err(err) => log::warn!("Failed to load plugin {}: {err}", path.display()),
+        }
+    }
+    let _ = PLUGINS.set(Mutex::new(plugins));
+}
+
+/// Run `f` on the loaded plugins, unless there are none.
+fn with_plugins<R>(f: impl FnOnce(&mut Plugins) -> Option<R>) -> Option<R> {
+    let mut plugins = PLUGINS.get()?.lock().ok()?;
+    if plugins.loaded.is_empty() {
+        return None;
+    }
+    f(&mut plugins)
+}
+
+/// The markdown to show for a document's source `content`.
+pub fn process_document(content: String) -> String {
+    with_plugins(|plugins| Some(plugins.process_document(&content))).unwrap_or(content)
+}
+
+/// Whether a plugin renders code fences tagged `lang`.
+pub fn handles_fence(lang: &str) -> bool {
+    with_plugins(|plugins| Some(plugins.fence_plugin(lang).is_some())).unwrap_or(false)
+}
+
+/// Show the fenced block `code` as its plugin renders it. Returns `false`,
+/// falling back to a code block, when the plugin fails.
+pub fn render_fence(ui: &mut egui::Ui, lang: &str, code: &str) -> bool {
+    match with_plugins(|plugins| plugins.render_fence(lang, code)) {
+        Some(Rendered::Svg { uri, bytes }) => {
+            ui.add(
+                egui::Image::from_bytes(uri, egui::load::Bytes::Shared(bytes))
+                    .max_width(ui.available_width()),
+            );
+            true
+        }
+        Some(Rendered::Text(text)) => {
+            ui.label(egui::RichText::new(text).monospace());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Where a link to `url` goes when a plugin handles its scheme.
+pub fn rewrite_link(url: &str) -> Option<String> {
+    with_plugins(|plugins| plugins.rewrite_link(url))
+}
+
+/// `$XDG_CONFIG_HOME/md-viewer/plugins`, or `~/.config/md-viewer/plugins`.
+fn plugin_dir() -> Option<PathBuf> {
+    crate::config_dir().map(|dir| dir.join("plugins"))
+}
+
+/// The `.wasm` files in `dir`, sorted so plugins load in a stable order.
+fn plugin_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wasm") && path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Split a packed result into its pointer and length.
+fn unpack(packed: i64) -> (usize, usize) {
+    let packed = packed as u64;
+    ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize)
+}
+
+/// What a fenced block renders to.
+#[derive(Clone, Debug, PartialEq)]
+enum Rendered {
+    Svg { uri: String, bytes: Arc<[u8]> },
+    Text(String),
+}
+
+impl Rendered {
+    /// SVG documents are drawn as images, anything else as text.
+    fn classify(output: String, lang: &str, code: &str) -> Self {
+        let start = output.trim_start();
+        if start.starts_with("<svg") || start.starts_with("<?xml") {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            (lang, code).hash(&mut hasher);
+            Self::Svg {
+                uri: format!("bytes://plugin-{lang}-{:016x}.svg", hasher.finish()),
+                bytes: output.into_bytes().into(),
+            }
+        } else {
+            Self::Text(output)
+        }
+    }
+}
+
+struct Plugin {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    fences: Vec<String>,
+    schemes: Vec<String>,
+}
+
+impl Plugin {
+    fn load(path: &Path) -> Result<Self, String> {
+        let wasm = std::fs::read(path).map_err(|err| err.to_string())?;
+        Self::from_wasm(&wasm)
+    }
+
+    fn from_wasm(wasm: &[u8]) -> Result<Self, String> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|err| err.to_string())?;
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(MAX_MEMORY_BYTES)
+            .build();
+        let mut store = Store::new(&engine, limits);
+        store.limiter(|limits| limits);
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|err| err.to_string())?;
+        let instance = Linker::<StoreLimits>::new(&engine)
+            .instantiate_and_start(&mut store, &module)
+            .map_err(|err| err.to_string())?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("the plugin exports no memory")?;
+        let mut plugin = Self {
+            store,
+            instance,
+            memory,
+            fences: Vec::new(),
+            schemes: Vec::new(),
+        };
+        plugin.fences = plugin.list("fences");
+        plugin.schemes = plugin.list("schemes");
+        Ok(plugin)
+    }
+
+    /// The non-empty lines the export `name` returns.
+    fn list(&mut self, name: &str) -> Vec<String> {
+        self.call(name, &[])
+            .map(|text| {
+                text.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Call the export `name` with each of `args` as a pointer and a length,
+    /// returning its non-empty result.
+    fn call(&mut self, name: &str, args: &[&str]) -> Option<String> {
+        let func = self.instance.get_func(&self.store, name)?;
+        self.store.set_fuel(FUEL_PER_CALL).ok()?;
+        let mut params = Vec::with_capacity(args.len() * 2);
+        if !args.is_empty() {
+            let alloc = self
+                .instance
+                .get_typed_func::<i32, i32>(&self.store, "alloc")
+                .ok()?;
+            for arg in args {
+                let len = i32::try_from(arg.len()).ok()?;
+                let ptr = alloc.call(&mut self.store, len).ok()?;
+                params.extend([Val::I32(ptr), Val::I32(len)]);
+                self.memory
+                    .write(&mut self.store, ptr as u32 as usize, arg.as_bytes())
+                    .ok()?;
+            }
+        }
+        let result = self.call_with(name, func, &params);
+        // Without a `dealloc` the arguments stay in the plugin's memory until
+        // it is full
+        if let Ok(dealloc) = self
+            .instance
+            .get_typed_func::<(i32, i32), ()>(&self.store, "dealloc")
+        {
+            for arg in params.chunks(2) {
+                if let [Val::I32(ptr), Val::I32(len)] = *arg {
+                    let _ = dealloc.call(&mut self.store, (ptr, len));
+                }
+            }
+        }
+        result
+    }
+
+    /// Call `func`, the export `name`, and read its result.
+    fn call_with(&mut self, name: &str, func: wasmi::Func, params: &[Val]) -> Option<String> {
+        let mut results = [Val::I64(0)];
+        if let Err(err) = func.call(&mut self.store, params, &mut results) {
+            log::warn!("Plugin call {name} failed: {err}");
+            return None;
+        }
+        let Val::I64(packed) = results[0] else {
+            return None;
+        };
+        let (ptr, len) = unpack(packed);
+        if len == 0 {
+            return None;
+        }
+        // The length is the plugin's word; check it against its memory before
+        // allocating for it
+        if ptr.saturating_add(len) > self.memory.data_size(&self.store) {
+            log::warn!("Plugin call {name} returned a result outside its memory");
+            return None;
+        }
+        let mut bytes = vec![0; len];
+        self.memory.read(&self.store, ptr, &mut bytes).ok()?;
+        String::from_utf8(bytes).ok()
+    }
+}
+
+#[derive(Default)]
+struct Plugins {
+    loaded: Vec<Plugin>,
+    /// What each fenced block rendered to, keyed by language and code, so
+    /// plugins run once per edit rather than once per frame
+    rendered: HashMap<(String, String), Option<Rendered>>,
+    /// Where each link a plugin handles goes, as links are rewritten every
+    /// frame they are drawn
+    rewritten: HashMap<String, Option<String>>,
+}
+
+impl Plugins {
+    /// Each plugin in turn gets the document the previous one returned.
+    fn process_document(&mut self, content: &str) -> String {
+        let mut content = content.to_string();
+        for plugin in &mut self.loaded {
+            if let Some(processed) = plugin.call("process_document", &[&content]) {
+                content = processed;
+            }
+        }
+        content
+    }
+
+    /// The first plugin that renders fences tagged `lang`.
+    fn fence_plugin(&self, lang: &str) -> Option<usize> {
+        self.loaded.iter().position(|plugin| {
+            plugin
+                .fences
+                .iter()
+                .any(|fence| fence.eq_ignore_ascii_case(lang))
+        })
+    }
+
+    fn render_fence(&mut self, lang: &str, code: &str) -> Option<Rendered> {
+        let key = (lang.to_string(), code.to_string());
+        if let Some(rendered) = self.rendered.get(&key) {
+            return rendered.clone();
+        }
+        let index = self.fence_plugin(lang)?;
+        let rendered = self.loaded[index]
+            .call("render_fence", &[lang, code])
+            .map(|output| Rendered::classify(output, lang, code));
+        if self.rendered.len() >= MAX_RENDERED {
+            self.rendered.clear();
+        }
+        self.rendered.insert(key, rendered.clone());
+        rendered
+    }
+
+    fn rewrite_link(&mut self, url: &str) -> Option<String> {
+        let (scheme, _) = url.split_once(':')?;
+        let plugin = self.loaded.iter_mut().find(|plugin| {
+            plugin
+                .schemes
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(scheme))
+        })?;
+        if let Some(rewritten) = self.rewritten.get(url) {
+            return rewritten.clone();
+        }
+        let rewritten = plugin.call("rewrite_link", &[url]);
+        if self.rewritten.len() >= MAX_REWRITTEN {
+            self.rewritten.clear();
+        }
+        self.rewritten.insert(url.to_string(), rewritten.clone());
+        rewritten
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module with one page of memory holding `data` at `offset`, and
+    /// `funcs` of type `(params) -> i64` that each return a constant.
+    fn module(data: &[(u32, &str)], funcs: &[(&str, u8, i64)]) -> Vec<u8> {
+        fn leb(mut value: i64, out: &mut Vec<u8>) {
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+                out.push(if done { byte } else { byte | 0x80 });
+                if done {
+                    break;
+                }
+            }
+        }
+        fn section(id: u8, body: Vec<u8>, out: &mut Vec<u8>) {
+            out.push(id);
+            leb(body.len() as i64, out);
+            out.extend(body);
+        }
+        fn name(name: &str, out: &mut Vec<u8>) {
+            out.push(name.len() as u8);
+            out.extend(name.as_bytes());
+        }
+
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        // One type per function, plus `alloc: (i32) -> i32`
+        let mut types = vec![funcs.len() as u8 + 1];
+        for &(_, params, _) in funcs {
+            types.extend([0x60, params]);
+            types.extend(std::iter::repeat(0x7f).take(params as usize));
+            types.extend([1, 0x7e]);
+        }
+        types.extend([0x60, 1, 0x7f, 1, 0x7f]);
+        section(1, types, &mut wasm);
+        let mut indices = vec![funcs.len() as u8 + 1];
+        indices.extend(0..=funcs.len() as u8);
+        section(3, indices, &mut wasm);
+        section(5, vec![1, 0, 1], &mut wasm);
+        let mut exports = vec![funcs.len() as u8 + 2];
+        name("memory", &mut exports);
+        exports.extend([2, 0]);
+        for (index, &(func, _, _)) in funcs.iter().enumerate() {
+            name(func, &mut exports);
+            exports.extend([0, index as u8]);
+        }
+        name("alloc", &mut exports);
+        exports.extend([0, funcs.len() as u8]);
+        section(7, exports, &mut wasm);
+        let mut code = vec![funcs.len() as u8 + 1];
+        for &(_, _, result) in funcs {
+            let mut body = vec![0, 0x42];
+            leb(result, &mut body);
+            body.push(0x0b);
+            code.push(body.len() as u8);
+            code.extend(body);
+        }
+        // `alloc` hands out the same scratch space every time
+        code.extend([5, 0, 0x41, 0x80, 0x08, 0x0b]);
+        section(10, code, &mut wasm);
+        let mut segments = vec![data.len() as u8];
+        for &(offset, text) in data {
+            segments.extend([0, 0x41]);
+            leb(offset as i64, &mut segments);
+            segments.push(0x0b);
+            name(text, &mut segments);
+        }
+        section(11, segments, &mut wasm);
+        wasm
+    }
+
+    fn packed(ptr: i64, len: usize) -> i64 {
+        (ptr << 32) | len as i64
+    }
+
+    #[test]
+    fn link_schemes_go_to_the_plugin_that_lists_them() {
+        let wasm = module(
+            &[(0, "jira\nwiki\n"), (64, "https://jira.example/ABC-1")],
+            &[
+                ("schemes", 0, packed(0, 10)),
+                ("rewrite_link", 2, packed(64, 26)),
+            ],
+        );
+        let mut plugins = Plugins {
+            loaded: vec![Plugin::from_wasm(&wasm).unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(plugins.loaded[0].schemes, ["jira", "wiki"]);
+        assert_eq!(
+            plugins.rewrite_link("JIRA:ABC-1").as_deref(),
+            Some("https://jira.example/ABC-1")
+        );
+        assert_eq!(plugins.rewrite_link("https://example.com"), None);
+        // Only links with a plugin's scheme are kept, once each
+        assert_eq!(
+            plugins.rewrite_link("JIRA:ABC-1").as_deref(),
+            Some("https://jira.example/ABC-1")
+        );
+        assert_eq!(plugins.rewritten.len(), 1);
+    }
+
+    #[test]
+    fn fences_render_once_as_svg_or_text() {
+        let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+        let wasm = module(
+            &[(0, "abc"), (64, svg)],
+            &[
+                ("fences", 0, packed(0, 3)),
+                ("render_fence", 4, packed(64, svg.len())),
+                ("process_document", 2, 0),
+            ],
+        );
+        let mut plugins = Plugins {
+            loaded: vec![Plugin::from_wasm(&wasm).unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(plugins.fence_plugin("ABC"), Some(0));
+        assert_eq!(plugins.fence_plugin("rust"), None);
+        let Some(Rendered::Svg { uri, bytes }) = plugins.render_fence("abc", "X:1") else {
+            panic!("expected an SVG");
+        };
+        assert!(uri.starts_with("bytes://plugin-abc-") && uri.ends_with(".svg"));
+        assert_eq!(&*bytes, svg.as_bytes());
+        assert_eq!(plugins.rendered.len(), 1);
+
+        // An empty result leaves the document alone
+        assert_eq!(plugins.process_document("# Doc\n"), "# Doc\n");
+
+        assert_eq!(
+            Rendered::classify("C D E".to_string(), "abc", "X:1"),
+            Rendered::Text("C D E".to_string())
+        );
+    }
+
+    #[test]
+    fn broken_plugins_are_rejected() {
+        assert!(Plugin::from_wasm(b"not wasm").is_err());
+        // Results reaching past the plugin's memory are dropped unread
+        let wasm = module(
+            &[],
+            &[
+                ("fences", 0, packed(0, 0xffff_ffff)),
+                ("schemes", 0, packed(65_000, 1000)),
+            ],
+        );
+        let plugin = Plugin::from_wasm(&wasm).unwrap();
+        assert!(plugin.fences.is_empty() && plugin.schemes.is_empty());
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for file in ["b.wasm", "a.wasm", "notes.txt"] {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        let files = plugin_files(dir);
+        assert_eq!(files, [dir.join("a.wasm"), dir.join("b.wasm")]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Structural problems in a markdown document, for the Problems panel.
+//!
+//! The checks look at the source text for mistakes that render without an
+//! error but not as the author meant: code fences that are never closed,
+//! tables whose rows don't match their header, and footnotes defined twice.
+//! Images that failed to load are reported by the renderer and mapped back to
+//! their source line with [`image_problem`], and so are the parts it couldn't
+//! render at all, with [`render_problem`].
+
+use std::collections::HashMap;
+
+/// A problem found in a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    /// 1-based source line, `None` when it couldn't be found
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    fn at(line: usize, message: String) -> Self {
+        Self {
+            line: Some(line),
+            message,
+        }
+    }
+}
+
+/// The opening of a code fence: its character and length, e.g. ('`', 3).
+fn fence_marker(line: &str) -> Option<(char, usize)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let trimmed = &line[indent..];
+    let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.len() - trimmed.trim_start_matches(marker).len();
+    // Backtick fences can't have backticks in their info string
+    let backtick_info = marker == '`' && trimmed[len..].contains('`');
+    (indent <= 3 && len >= 3 && !backtick_info).then_some((marker, len))
+}
+
+/// Cells of a table row, split on unescaped pipes, without the outer pipes.
+fn table_cells(line: &str) -> Vec<&str> {
+    let row = line.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = match row.strip_suffix('|') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => row,
+    };
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in row.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '|' if !escaped => {
+                cells.push(row[start..i].trim());
+                start = i + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+    cells.push(row[start..].trim());
+    cells
+}
+
+/// Whether `line` is a table delimiter row such as `| --- | :-: |`.
+fn is_delimiter_row(line: &str) -> bool {
+    line.contains('-')
+        && line.contains(['|', ':'])
+        && table_cells(line).iter().all(|cell| {
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            !dashes.is_empty() && dashes.chars().all(|c| c == '-')
+        })
+}
+
+/// The label of a footnote definition (`[^label]: text`), lowercased as
+/// labels match case-insensitively.
+fn footnote_definition(line: &str) -> Option<String> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let rest = line[indent..].strip_prefix("[^")?;
+    let (label, after) = rest.split_once(']')?;
+    (indent <= 3 && !label.is_empty() && after.starts_with(':')).then(|| label.to_lowercase())
+}
+
+/// Problems in the structure of `content`.
+pub fn find_problems(content: &str) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let lines: Vec<&str> = content.lines().collect();
+    // Line, character and length of the open fence
+    let mut fence: Option<(usize, char, usize)> = None;
+    let mut footnotes: HashMap<String, usize> = HashMap::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let number = i + 1;
+        i += 1;
+
+        if let Some((_, marker, len)) = fence {
+            let closes = fence_marker(line).is_some_and(|(m, l)| {
+                m == marker && l >= len && line.trim().trim_start_matches(marker).is_empty()
+            });
+            if closes {
+                fence = None;
+            }
+            continue;
+        }
+        if let Some((marker, len)) = fence_marker(line) {
+            fence = Some((number, marker, len));
+            continue;
+        }
+
+        if let Some(label) = footnote_definition(line) {
+            match footnotes.get(&label) {
+                Some(first) => problems.push(Problem::at(
+                    number,
+                    format!("Footnote [^{label}] is already defined on line {first}"),
+                )),
+                None => {
+                    footnotes.insert(label, number);
+                }
+            }
+            continue;
+        }
+
+        // A table starts with a header row followed by a delimiter row
+        let Some(delimiter) = lines.get(i) else {
+            continue;
+        };
+        if !line.contains('|') || !is_delimiter_row(delimiter) {
+            continue;
+        }
+        let columns = table_cells(line).len();
+        let delimiter_columns = table_cells(delimiter).len();
+        if columns != delimiter_columns {
+            problems.push(Problem::at(
+                number,
+                format!(
+                    "Table header has {columns} columns but its delimiter row has \
+                     {delimiter_columns}, so it isn't rendered as a table"
+                ),
+            ));
+            continue;
+        }
+        i += 1;
+        while let Some(row) = lines.get(i).filter(|row| !row.trim().is_empty()) {
+            if !row.contains('|') {
+                break;
+            }
+            let cells = table_cells(row).len();
+            if cells != columns {
+                let effect = if cells > columns {
+                    "extra cells are dropped"
+                } else {
+                    "missing cells are left empty"
+                };
+                problems.push(Problem::at(
+                    i + 1,
+                    format!("Table row has {cells} cells but the header has {columns}; {effect}"),
+                ));
+            }
+            i += 1;
+        }
+    }
+
+    if let Some((line, marker, len)) = fence {
+        let fence: String = std::iter::repeat(marker).take(len).collect();
+        problems.push(Problem::at(
+            line,
+            format!("Code fence {fence} is never closed; the rest of the document is code"),
+        ));
+    }
+    problems.sort_by_key(|problem| problem.line);
+    problems
+}
+
+/// A problem for an image at `uri` that failed to load, on the first line
+/// linking to it.
+pub fn image_problem(content: &str, uri: &str, error: &str) -> Problem {
+    let line = content.lines().position(|line| {
+        line.match_indices("](").any(|(at, _)| {
+            let destination = line[at + 2..].split([')', ' ']).next().unwrap_or_default();
+            let destination = destination.trim_start_matches('<').trim_end_matches('>');
+            let destination = destination.trim_start_matches("./");
+            !destination.is_empty() && line[..at].contains("![") && uri.ends_with(destination)
+        })
+    });
+    Problem {
+        line: line.map(|line| line + 1),
+        message: format!("Image {uri} failed to load: {error}"),
+    }
+}
+
+/// A problem for the part of `content` starting at byte `start` that the
+/// renderer failed on.
+pub fn render_problem(content: &str, start: usize, message: &str) -> Problem {
+    let line = content
+        .get(..start)
+        .map(|before| before.matches('\n').count() + 1);
+    Problem {
+        line,
+        message: format!("Couldn't render this part: {message}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structural_problems_are_found_with_their_line() {
+        let content = "\
+# Doc
+
+| a | b |
+| - | - |
+| 1 | 2 | 3 |
+| 1 \\| x | 2 |
+| 4 |
+
+| a | b | c |
+| --- | --- |
+
+Text[^one] and[^One].
+
+[^one]: First.
+[^ONE]: Again.
+
+```rust
+let s = \"| a | b |\\n| - |\";
+```
+
+~~~~
+unclosed ```
+~~~
+";
+        let found = find_problems(content);
+        let problems: Vec<(Option<usize>, &str)> =
+            found.iter().map(|p| (p.line, p.message.as_str())).collect();
+        assert_eq!(
+            problems,
+            [
+                (
+                    Some(5),
+                    "Table row has 3 cells but the header has 2; extra cells are dropped"
+                ),
+                (
+                    Some(7),
+                    "Table row has 1 cells but the header has 2; missing cells are left empty"
+                ),
+                (
+                    Some(9),
+                    "Table header has 3 columns but its delimiter row has 2, \
+                     so it isn't rendered as a table"
+                ),
+                (Some(15), "Footnote [^one] is already defined on line 14"),
+                (
+                    Some(21),
+                    "Code fence ~~~~ is never closed; the rest of the document is code"
+                ),
+            ]
+        );
+        assert!(find_problems("```\ncode\n```\n\n| a |\n|---|\n| b |\n").is_empty());
+    }
+
+    #[test]
+    fn failed_images_point_at_their_source_line() {
+        let content = "# Doc\n\nSee [text](pic.png).\n\n![Pic](./img/pic.png \"Title\")\n";
+        let problem = image_problem(content, "file:///docs/img/pic.png", "not found");
+        assert_eq!(problem.line, Some(5));
+        assert_eq!(
+            problem.message,
+            "Image file:///docs/img/pic.png failed to load: not found"
+        );
+        assert_eq!(image_problem(content, "https://x/y.png", "404").line, None);
+
+        let problem = render_problem(content, content.find("![").unwrap(), "boom");
+        assert_eq!(problem.line, Some(5));
+        assert_eq!(problem.message, "Couldn't render this part: boom");
+    }
+}
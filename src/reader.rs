@@ -0,0 +1,873 @@
+//! Reader view of HTML pages.
+//!
+//! An opened `.html` file is reduced to its main content, the first
+//! `<article>` or `<main>` or else the element holding the most paragraph
+//! text, and converted to markdown, so saved articles read like documents.
+//! Navigation, headers, footers, forms and scripts are dropped; tables are
+//! kept as HTML for the renderer. `http://` and `https://` URLs are
+//! downloaded into a folder first and opened from there.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Elements whose text is never shown.
+const RAW_TEXT: [&str; 5] = ["script", "style", "noscript", "template", "textarea"];
+
+/// Elements of page chrome rather than content, dropped with what's in them.
+const CHROME: [&str; 9] = [
+    "nav", "header", "footer", "aside", "form", "button", "iframe", "svg", "select",
+];
+
+/// Elements the paragraph text in them counts towards when the main
+/// content is looked for.
+const CONTAINERS: [&str; 4] = ["div", "section", "td", "body"];
+
+/// Elements that start and end a paragraph of their own.
+const BLOCKS: [&str; 12] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "figure",
+    "figcaption",
+    "dl",
+    "dt",
+    "dd",
+    "details",
+    "summary",
+];
+
+/// Pages larger than this aren't downloaded.
+const MAX_DOWNLOAD_BYTES: usize = 16 * 1024 * 1024;
+
+/// Whether `path` is an HTML page shown in reader view.
+pub fn is_html(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy().to_lowercase();
+        ext == "html" || ext == "htm" || ext == "xhtml"
+    })
+}
+
+/// Whether `text` is an `http://` or `https://` URL rather than a path.
+pub fn is_url(text: &str) -> bool {
+    text.starts_with("http://") || text.starts_with("https://")
+}
+
+/// The markdown of `content` when `path` is an HTML page, else `content`.
+pub fn process_document(path: &Path, content: String) -> String {
+    if is_html(path) {
+        article_markdown(&content)
+    } else {
+        content
+    }
+}
+
+/// Download the page at `url` into `dir`, named after the URL, and return
+/// the file. The page gets a `<base>` so its relative links and images
+/// still point at the site.
+pub fn download(url: &str, dir: &Path) -> Result<PathBuf, String> {
+    let response = ureq::get(url).call().map_err(|e| e.to_string())?;
+    let content_type = response.header("content-type").map(str::to_owned);
+    let bytes = read_page(response.into_reader())?;
+    let page = decode_page(&bytes, content_type.as_deref());
+    let page = if page.to_ascii_lowercase().contains("<base") {
+        page
+    } else {
+        format!("<base href=\"{}\">\n{page}", url.replace('"', "%22"))
+    };
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let path = dir.join(download_name(url));
+    fs::write(&path, page).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// All of `reader`, or an error once it holds more than `MAX_DOWNLOAD_BYTES`.
+fn read_page(reader: impl Read) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    reader
+        .take(MAX_DOWNLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    if bytes.len() > MAX_DOWNLOAD_BYTES {
+        return Err(format!(
+            "the page is larger than {}",
+            crate::format_size(MAX_DOWNLOAD_BYTES as u64)
+        ));
+    }
+    Ok(bytes)
+}
+
+/// A downloaded page decoded as the charset of its `Content-Type`, or as
+/// local files are when it names none.
+fn decode_page(bytes: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(|content_type| {
+            content_type.split(';').find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("charset")
+                    .then(|| value.trim().trim_matches('"'))
+            })
+        })
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()));
+    crate::decode_document(bytes, encoding).0
+}
+
+/// File name of the page downloaded from `url`: its host and path with
+/// anything but letters, digits, `-` and `.` turned into `_`.
+fn download_name(url: &str) -> String {
+    let address = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let address = address.split(['?', '#']).next().unwrap_or(address);
+    let mut name: String = address
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(120)
+        .collect();
+    if !name.ends_with(".html") && !name.ends_with(".htm") {
+        name.push_str(".html");
+    }
+    name
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'h> {
+    /// An opening tag with its name lowercased and all of it between `<>`
+    Open(String, &'h str),
+    Close(String),
+    Text(&'h str),
+}
+
+/// The tags and text of `html` with the byte range each was read from.
+/// Comments, doctypes and the contents of [`RAW_TEXT`] elements are left out.
+fn tokenize(html: &str) -> Vec<(Token<'_>, Range<usize>)> {
+    let mut tokens = Vec::new();
+    let mut at = 0;
+    while at < html.len() {
+        let Some(lt) = html[at..].find('<').map(|lt| at + lt) else {
+            tokens.push((Token::Text(&html[at..]), at..html.len()));
+            break;
+        };
+        if lt > at {
+            tokens.push((Token::Text(&html[at..lt]), at..lt));
+        }
+        if html[lt..].starts_with("<!--") {
+            at = html[lt..]
+                .find("-->")
+                .map_or(html.len(), |end| lt + end + 3);
+            continue;
+        }
+        let Some(end) = html[lt..].find('>').map(|gt| lt + gt + 1) else {
+            tokens.push((Token::Text(&html[lt..]), lt..html.len()));
+            break;
+        };
+        let tag = &html[lt + 1..end - 1];
+        at = end;
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // Doctypes and processing instructions are dropped, a lone `<`
+            // is text
+            if !tag.starts_with(['!', '?']) {
+                tokens.push((Token::Text(&html[lt..end]), lt..end));
+            }
+            continue;
+        }
+        if closing {
+            tokens.push((Token::Close(name), lt..end));
+        } else if RAW_TEXT.contains(&name.as_str()) {
+            let close = format!("</{name}");
+            at = find_ignore_ascii_case(&html[end..], &close)
+                .and_then(|close| html[end + close..].find('>').map(|gt| end + close + gt + 1))
+                .unwrap_or(html.len());
+        } else {
+            tokens.push((Token::Open(name, tag), lt..end));
+        }
+    }
+    tokens
+}
+
+/// Where the ASCII `needle` first occurs in `haystack`, ignoring ASCII case,
+/// without copying the haystack.
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Index of the token closing the element opened at `open`, or the last
+/// token when it's never closed.
+fn matching_close(tokens: &[(Token, Range<usize>)], open: usize) -> usize {
+    let Token::Open(name, _) = &tokens[open].0 else {
+        return open;
+    };
+    let mut depth = 0;
+    for (i, (token, _)) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Open(other, _) if other == name => depth += 1,
+            Token::Close(other) if other == name => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.len().saturating_sub(1)
+}
+
+/// Tokens of the page's main content: the inside of its first `<article>`
+/// or `<main>`, or else of the container with the most paragraph text, half
+/// of which also counts for the container around it.
+fn main_content(tokens: &[(Token, Range<usize>)]) -> Range<usize> {
+    for landmark in ["article", "main"] {
+        let open = tokens
+            .iter()
+            .position(|(token, _)| matches!(token, Token::Open(name, _) if name == landmark));
+        if let Some(open) = open {
+            return open + 1..matching_close(tokens, open);
+        }
+    }
+
+    let mut scores: HashMap<usize, usize> = HashMap::new();
+    let mut containers: Vec<(&str, usize)> = Vec::new();
+    let mut paragraphs = 0;
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::Open(name, _) if name == "p" => paragraphs += 1,
+            Token::Close(name) if name == "p" => paragraphs = 0,
+            Token::Open(name, _) if CONTAINERS.contains(&name.as_str()) => {
+                containers.push((name, i));
+            }
+            Token::Close(name) => {
+                if let Some(open) = containers.iter().rposition(|(open, _)| open == name) {
+                    containers.truncate(open);
+                    paragraphs = 0;
+                }
+            }
+            Token::Text(text) if paragraphs > 0 => {
+                let mut around = containers.iter().rev();
+                if let Some((_, parent)) = around.next() {
+                    *scores.entry(*parent).or_default() += text.len() * 2;
+                }
+                if let Some((_, grandparent)) = around.next() {
+                    *scores.entry(*grandparent).or_default() += text.len();
+                }
+            }
+            _ => {}
+        }
+    }
+    match scores
+        .into_iter()
+        .max_by_key(|&(open, score)| (score, usize::MAX - open))
+    {
+        Some((open, _)) => open + 1..matching_close(tokens, open),
+        None => 0..tokens.len(),
+    }
+}
+
+/// What the markdown written into a [`Frame`] becomes once it's closed.
+enum FrameKind {
+    Root,
+    /// A list item of the list at this depth
+    Item(usize),
+    Quote,
+    Heading(usize),
+    /// Preformatted text, with the language of its code
+    Pre(String),
+}
+
+struct Frame {
+    kind: FrameKind,
+    out: String,
+}
+
+/// Markdown written while the tokens of an HTML page are walked.
+struct Writer {
+    frames: Vec<Frame>,
+    /// The open lists, each with the number of its next item when ordered
+    lists: Vec<Option<usize>>,
+    /// The targets of the open links, `None` for anchors without one
+    links: Vec<Option<String>>,
+    /// A space is due before the next words
+    space: bool,
+    base: Option<String>,
+}
+
+impl Writer {
+    fn out(&mut self) -> &mut String {
+        &mut self
+            .frames
+            .last_mut()
+            .expect("the root frame is never closed")
+            .out
+    }
+
+    fn in_pre(&self) -> bool {
+        self.frames
+            .iter()
+            .any(|frame| matches!(frame.kind, FrameKind::Pre(_)))
+    }
+
+    /// Write `text` after the space due, if any.
+    fn words(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if std::mem::take(&mut self.space) {
+            let out = self.out();
+            if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+                out.push(' ');
+            }
+        }
+        self.out().push_str(text);
+    }
+
+    fn text(&mut self, text: &str) {
+        let text = decode_entities(text);
+        if self.in_pre() {
+            self.out().push_str(&text);
+            return;
+        }
+        let words: Vec<String> = text.split_whitespace().map(escape_markdown).collect();
+        if text.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+        self.words(&words.join(" "));
+        if !words.is_empty() && text.ends_with(char::is_whitespace) {
+            self.space = true;
+        }
+    }
+
+    /// End the paragraph written so far.
+    fn block(&mut self) {
+        self.space = false;
+        let out = self.out();
+        if !out.is_empty() && !out.ends_with("\n\n") {
+            out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+        }
+    }
+
+    /// End the line written so far.
+    fn line(&mut self) {
+        self.space = false;
+        let out = self.out();
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+
+    fn open_frame(&mut self, kind: FrameKind) {
+        self.space = false;
+        self.frames.push(Frame {
+            kind,
+            out: String::new(),
+        });
+    }
+
+    /// Close the innermost frame, writing what it holds into the one around
+    /// it.
+    fn close_frame(&mut self) {
+        if self.frames.len() == 1 {
+            return;
+        }
+        let frame = self.frames.pop().expect("checked above");
+        self.space = false;
+        match frame.kind {
+            FrameKind::Root => {}
+            FrameKind::Item(_) => {
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                let indent = " ".repeat(marker.len());
+                let mut item = String::new();
+                for (i, line) in frame.out.trim().lines().enumerate() {
+                    match (i, line.is_empty()) {
+                        (0, _) => item.push_str(&marker),
+                        (_, false) => item.push_str(&indent),
+                        _ => {}
+                    }
+                    item.push_str(line);
+                    item.push('\n');
+                }
+                self.line();
+                self.out().push_str(&item);
+            }
+            FrameKind::Quote => {
+                let quote: String = frame
+                    .out
+                    .trim()
+                    .lines()
+                    .map(|line| match line {
+                        "" => ">\n".to_string(),
+                        _ => format!("> {line}\n"),
+                    })
+                    .collect();
+                self.block();
+                self.out().push_str(&quote);
+                self.block();
+            }
+            FrameKind::Heading(level) => {
+                let title = frame.out.split_whitespace().collect::<Vec<_>>().join(" ");
+                let title = title.replace("\\ ", " ");
+                self.block();
+                if !title.is_empty() {
+                    let heading = format!("{} {title}", "#".repeat(level));
+                    self.out().push_str(&heading);
+                    self.block();
+                }
+            }
+            FrameKind::Pre(language) => {
+                let code = frame.out.trim_matches('\n');
+                let fence = if code.contains("```") { "````" } else { "```" };
+                let block = format!("{fence}{language}\n{code}\n{fence}");
+                self.block();
+                self.out().push_str(&block);
+                self.block();
+            }
+        }
+    }
+
+    /// Close frames up to and including the innermost one `is` matches, if
+    /// any is open.
+    fn close_frames_to(&mut self, is: impl Fn(&FrameKind) -> bool) {
+        if let Some(at) = self.frames.iter().rposition(|frame| is(&frame.kind)) {
+            while self.frames.len() > at.max(1) {
+                self.close_frame();
+            }
+        }
+    }
+
+    /// `url` resolved against the page's `<base>`.
+    fn url(&self, url: &str) -> String {
+        let url = decode_entities(url.trim());
+        let Some(base) = &self.base else {
+            return url;
+        };
+        if url.contains("://") || url.starts_with('#') || (url.contains(':') && !url.contains('/'))
+        {
+            return url;
+        }
+        let scheme_end = base.find("://").map_or(0, |at| at + 3);
+        if let Some(rest) = url.strip_prefix("//") {
+            return format!("{}{rest}", &base[..scheme_end]);
+        }
+        if url.starts_with('/') {
+            let origin_end = base[scheme_end..]
+                .find('/')
+                .map_or(base.len(), |at| scheme_end + at);
+            return format!("{}{url}", &base[..origin_end]);
+        }
+        let dir_end = base
+            .rfind('/')
+            .filter(|&at| at >= scheme_end)
+            .map_or(base.len(), |at| at + 1);
+        let separator = if dir_end == base.len() && !base.ends_with('/') {
+            "/"
+        } else {
+            ""
+        };
+        format!("{}{separator}{url}", &base[..dir_end])
+    }
+}
+
+/// The main content of the HTML page `html` as markdown, headed by the
+/// page's `<title>` when the content has no top-level heading of its own.
+pub fn article_markdown(html: &str) -> String {
+    let tokens = tokenize(html);
+    let mut title = String::new();
+    let mut base = None;
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::Open(name, tag) if name == "base" => {
+                base = attribute(tag, "href").map(decode_entities);
+            }
+            Token::Open(name, _) if name == "title" && title.is_empty() => {
+                if let Some((Token::Text(text), _)) = tokens.get(i + 1) {
+                    title = decode_entities(text)
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut writer = Writer {
+        frames: vec![Frame {
+            kind: FrameKind::Root,
+            out: String::new(),
+        }],
+        lists: Vec::new(),
+        links: Vec::new(),
+        space: false,
+        base,
+    };
+    let content = main_content(&tokens);
+    let mut i = content.start;
+    while i < content.end.min(tokens.len()) {
+        match &tokens[i].0 {
+            Token::Text(text) => writer.text(text),
+            Token::Open(name, tag) => match name.as_str() {
+                _ if CHROME.contains(&name.as_str()) || attribute(tag, "hidden").is_some() => {
+                    i = matching_close(&tokens, i);
+                }
+                "table" => {
+                    let close = matching_close(&tokens, i);
+                    let table = &html[tokens[i].1.start..tokens[close].1.end];
+                    writer.block();
+                    writer.out().push_str(table.trim());
+                    writer.block();
+                    i = close;
+                }
+                _ if writer.in_pre() => {
+                    if name == "br" {
+                        writer.out().push('\n');
+                    }
+                    if let FrameKind::Pre(language) = &mut writer.frames.last_mut().unwrap().kind {
+                        if language.is_empty() {
+                            *language = code_language(tag);
+                        }
+                    }
+                }
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    writer.block();
+                    writer.open_frame(FrameKind::Heading(usize::from(name.as_bytes()[1] - b'0')));
+                }
+                "blockquote" => {
+                    writer.block();
+                    writer.open_frame(FrameKind::Quote);
+                }
+                "pre" => {
+                    writer.block();
+                    writer.open_frame(FrameKind::Pre(code_language(tag)));
+                }
+                "ul" | "ol" => {
+                    writer.line();
+                    let start = attribute(tag, "start").and_then(|start| start.parse().ok());
+                    writer
+                        .lists
+                        .push((name == "ol").then_some(start.unwrap_or(1)));
+                }
+                "li" => {
+                    let depth = writer.lists.len();
+                    writer
+                        .close_frames_to(|kind| matches!(kind, FrameKind::Item(d) if *d == depth));
+                    writer.open_frame(FrameKind::Item(depth));
+                }
+                "br" => {
+                    writer.out().push_str("\\\n");
+                    writer.space = false;
+                }
+                "hr" => {
+                    writer.block();
+                    writer.out().push_str("---");
+                    writer.block();
+                }
+                "img" => {
+                    if let Some(src) = attribute(tag, "src") {
+                        let alt = attribute(tag, "alt")
+                            .map(decode_entities)
+                            .unwrap_or_default();
+                        let image = format!("![{}](<{}>)", escape_markdown(&alt), writer.url(src));
+                        writer.words(&image);
+                    }
+                }
+                "a" => {
+                    let href = attribute(tag, "href").map(|href| writer.url(href));
+                    if href.is_some() {
+                        writer.words("[");
+                    }
+                    writer.links.push(href);
+                }
+                "strong" | "b" => writer.words("**"),
+                "em" | "i" => writer.words("*"),
+                "del" | "s" | "strike" => writer.words("~~"),
+                "code" | "kbd" | "samp" => writer.words("`"),
+                _ if BLOCKS.contains(&name.as_str()) => writer.block(),
+                _ => {}
+            },
+            Token::Close(name) => match name.as_str() {
+                "pre" => writer.close_frames_to(|kind| matches!(kind, FrameKind::Pre(_))),
+                _ if writer.in_pre() => {}
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    writer.close_frames_to(|kind| matches!(kind, FrameKind::Heading(_)));
+                }
+                "blockquote" => writer.close_frames_to(|kind| matches!(kind, FrameKind::Quote)),
+                "li" => {
+                    let depth = writer.lists.len();
+                    writer
+                        .close_frames_to(|kind| matches!(kind, FrameKind::Item(d) if *d == depth));
+                }
+                "ul" | "ol" => {
+                    let depth = writer.lists.len();
+                    writer
+                        .close_frames_to(|kind| matches!(kind, FrameKind::Item(d) if *d == depth));
+                    writer.lists.pop();
+                    if writer.lists.is_empty() {
+                        writer.block();
+                    }
+                }
+                "a" => {
+                    if let Some(Some(href)) = writer.links.pop() {
+                        writer.out().push_str(&format!("](<{href}>)"));
+                    }
+                }
+                "strong" | "b" => writer.out().push_str("**"),
+                "em" | "i" => writer.out().push('*'),
+                "del" | "s" | "strike" => writer.out().push_str("~~"),
+                "code" | "kbd" | "samp" => writer.out().push('`'),
+                _ if BLOCKS.contains(&name.as_str()) => writer.block(),
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+    while writer.frames.len() > 1 {
+        writer.close_frame();
+    }
+
+    let markdown = writer
+        .frames
+        .pop()
+        .map(|frame| frame.out)
+        .unwrap_or_default();
+    let mut lines: Vec<&str> = Vec::new();
+    for line in markdown.lines().map(str::trim_end) {
+        if !(line.is_empty() && lines.last().map_or(true, |last| last.is_empty())) {
+            lines.push(line);
+        }
+    }
+    let body = lines.join("\n").trim().to_string();
+    if body.starts_with("# ") || title.is_empty() {
+        format!("{body}\n")
+    } else {
+        format!("# {}\n\n{body}\n", escape_markdown(&title))
+    }
+}
+
+/// Language of a `<pre>` or `<code>` from its `language-*` or `lang-*` class.
+fn code_language(tag: &str) -> String {
+    attribute(tag, "class")
+        .into_iter()
+        .flat_map(str::split_whitespace)
+        .find_map(|class| {
+            class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+        })
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Value of the attribute `name` in the opening tag `tag`, quoted or not;
+/// an empty one for an attribute without a value.
+fn attribute<'t>(tag: &'t str, name: &str) -> Option<&'t str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    let start = loop {
+        let at = from + lower[from..].find(name)?;
+        from = at + name.len();
+        let before = lower[..at].chars().next_back();
+        let after = lower[from..].chars().next();
+        if before.is_some_and(char::is_whitespace)
+            && after.map_or(true, |c| c.is_whitespace() || c == '=' || c == '/')
+        {
+            break from;
+        }
+    };
+    let Some(value) = tag[start..].trim_start().strip_prefix('=') else {
+        return Some("");
+    };
+    let value = value.trim_start();
+    Some(match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split(char::is_whitespace).next()?,
+    })
+}
+
+/// `text` with its named and numeric character references decoded.
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{00A0}'),
+            "ndash" => Some('–'),
+            "mdash" => Some('—'),
+            "hellip" => Some('…'),
+            "lsquo" => Some('‘'),
+            "rsquo" => Some('’'),
+            "ldquo" => Some('“'),
+            "rdquo" => Some('”'),
+            "copy" => Some('©'),
+            _ => {
+                let number = entity.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (character, entity) {
+            (Some(character), Some(entity)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// `text` with the characters markdown would read as markup escaped.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#' | '|' | '~'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_article_of_a_page_becomes_markdown() {
+        let page = r#"<!DOCTYPE html>
+<html><head><title>Saved &amp; read</title><base href="https://example.com/blog/post.html">
+<script>var x = "<p>not text</p>";</script></head>
+<body><nav><a href="/">Home</a></nav>
+<article>
+  <h2>Why   <em>it</em> matters</h2>
+  <p>Some <strong>bold</strong> text with a <a href="about.html">link</a> &mdash; and 2*3.</p>
+  <ul><li>One<li>Two <ol start="3"><li>Nested</li></ol></li></ul>
+  <blockquote><p>Quoted</p><p>twice</p></blockquote>
+  <pre><code class="language-rust">fn main() {
+    println!("&lt;hi&gt;");
+}</code></pre>
+  <img src="/img/a.png" alt="A chart">
+  <table><tr><td>kept</td></tr></table>
+  <footer>Share this</footer>
+</article>
+<aside>Related posts</aside>
+</body></html>"#;
+        assert_eq!(
+            article_markdown(page),
+            "# Saved & read\n\n\
+             ## Why *it* matters\n\n\
+             Some **bold** text with a [link](<https://example.com/blog/about.html>) — and 2\\*3.\n\n\
+             - One\n\
+             - Two\n  3. Nested\n\n\
+             > Quoted\n>\n> twice\n\n\
+             ```rust\nfn main() {\n    println!(\"<hi>\");\n}\n```\n\n\
+             ![A chart](<https://example.com/img/a.png>)\n\n\
+             <table><tr><td>kept</td></tr></table>\n"
+        );
+    }
+
+    #[test]
+    fn raw_text_ends_at_its_closing_tag_in_any_case() {
+        let html = "<p>a</p><SCRIPT>x = '</p>';</Script ><p>b</p>";
+        let texts: Vec<&str> = tokenize(html)
+            .into_iter()
+            .filter_map(|(token, _)| match token {
+                Token::Text(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, ["a", "b"]);
+        assert_eq!(find_ignore_ascii_case("aÄ</STYLE>", "</style"), Some(3));
+        assert_eq!(find_ignore_ascii_case("</sty", "</style"), None);
+    }
+
+    #[test]
+    fn without_an_article_the_wordiest_container_is_read() {
+        let page = "<body><div class=menu><p>Menu</p></div>\
+                    <div><h1>Title</h1><p>A long paragraph of the story.</p><p>Another one.</p></div>\
+                    </body>";
+        assert_eq!(
+            article_markdown(page),
+            "# Title\n\nA long paragraph of the story.\n\nAnother one.\n"
+        );
+    }
+
+    #[test]
+    fn downloads_are_named_after_their_url() {
+        assert_eq!(
+            download_name("https://example.com/blog/a-post/?utm=1#top"),
+            "example.com_blog_a-post.html"
+        );
+        assert_eq!(
+            download_name("http://example.com/page.htm"),
+            "example.com_page.htm"
+        );
+        assert!(is_url("https://example.com") && !is_url("docs/http.md"));
+    }
+
+    #[test]
+    fn downloads_are_decoded_by_their_charset_and_limited() {
+        let (latin1, _, _) = encoding_rs::WINDOWS_1252.encode("<p>Café</p>");
+        assert_eq!(
+            decode_page(&latin1, Some("text/html; Charset=\"ISO-8859-1\"")),
+            "<p>Café</p>"
+        );
+        assert_eq!(
+            decode_page("<p>Café</p>".as_bytes(), Some("text/html")),
+            "<p>Café</p>"
+        );
+
+        let page = vec![b'a'; MAX_DOWNLOAD_BYTES];
+        assert_eq!(read_page(&page[..]).unwrap().len(), MAX_DOWNLOAD_BYTES);
+        let page = vec![b'a'; MAX_DOWNLOAD_BYTES + 1];
+        assert!(read_page(&page[..]).is_err());
+    }
+}
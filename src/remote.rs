@@ -0,0 +1,280 @@
+//! Remote control of a running viewer over a Unix socket.
+//!
+//! Scripts and editors connect to `$XDG_RUNTIME_DIR/md-viewer.sock` (or a
+//! private `md-viewer-<uid>` folder in the temporary directory) and send one
+//! command per line (`open <path or URL>`, `reload`, `goto <anchor>`,
+//! `export <format> [path]`, ...). Each line is answered with one line,
+//! `ok <what happened>` or `error <why>`. `md-viewer --remote '<command>'`
+//! sends a single command and prints the answer.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// How long a connection waits for the UI thread to run a command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command line received from a client, answered through [`Request::reply`].
+pub struct Request {
+    pub line: String,
+    reply: Sender<String>,
+}
+
+impl Request {
+    /// Answer the client with `ok <message>` or `error <message>`.
+    pub fn reply(self, result: Result<String, String>) {
+        let line = match result {
+            Ok(message) => format!("ok {message}"),
+            Err(message) => format!("error {message}"),
+        };
+        let _ = self.reply.send(line);
+    }
+}
+
+/// Where the running viewer listens: in the user's runtime directory, or a
+/// per-user folder in the temporary directory when there is none.
+pub fn socket_path() -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir().join(format!("md-viewer-{}", user_id())),
+    };
+    dir.join("md-viewer.sock")
+}
+
+#[cfg(unix)]
+fn user_id() -> u32 {
+    extern "C" {
+        fn getuid() -> u32;
+    }
+    // SAFETY: getuid can't fail and touches no memory of ours
+    unsafe { getuid() }
+}
+
+#[cfg(not(unix))]
+fn user_id() -> u32 {
+    0
+}
+
+/// Create `dir` for the socket, only accessible to the user. An existing one
+/// has to be theirs and closed to others, as whoever can write to it could
+/// put their own socket in its place.
+#[cfg(unix)]
+fn create_private_dir(dir: &Path, uid: u32) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is open to other users", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Start listening for remote commands, waking `ctx` for each one. `None`
+/// when another viewer already listens or the socket can't be created.
+#[cfg(unix)]
+pub fn listen(ctx: eframe::egui::Context) -> Option<Receiver<Request>> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = socket_path();
+    let uid = user_id();
+    // The folder keeps other users away from the socket, whatever the umask
+    // gives the socket itself
+    if let Err(e) = path
+        .parent()
+        .map_or(Ok(()), |dir| create_private_dir(dir, uid))
+    {
+        log::warn!("Failed to listen on {}: {e}", path.display());
+        return None;
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(&path).is_ok() {
+                log::info!("Another viewer listens on {}", path.display());
+                return None;
+            }
+            // Left behind by a viewer that didn't exit cleanly
+            let ours = std::fs::symlink_metadata(&path).is_ok_and(|m| m.uid() == uid);
+            if !ours {
+                log::warn!("{} belongs to another user", path.display());
+                return None;
+            }
+            let _ = std::fs::remove_file(&path);
+            UnixListener::bind(&path)
+                .map_err(|e| log::warn!("Failed to listen on {}: {e}", path.display()))
+                .ok()?
+        }
+        Err(e) => {
+            log::warn!("Failed to listen on {}: {e}", path.display());
+            return None;
+        }
+    };
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("remote-control".into())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let tx = tx.clone();
+                let ctx = ctx.clone();
+                std::thread::spawn(move || serve(stream, &tx, &ctx));
+            }
+        })
+        .ok()?;
+    Some(rx)
+}
+
+#[cfg(not(unix))]
+pub fn listen(_ctx: eframe::egui::Context) -> Option<Receiver<Request>> {
+    None
+}
+
+/// Answer the commands of one connection until the client hangs up.
+#[cfg(unix)]
+fn serve(
+    stream: std::os::unix::net::UnixStream,
+    requests: &Sender<Request>,
+    ctx: &eframe::egui::Context,
+) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (reply, answer) = mpsc::channel();
+        if requests.send(Request { line, reply }).is_err() {
+            return;
+        }
+        ctx.request_repaint();
+        let answer = answer
+            .recv_timeout(REPLY_TIMEOUT)
+            .unwrap_or_else(|_| "error the viewer didn't answer".to_string());
+        if writeln!(writer, "{answer}").is_err() {
+            return;
+        }
+    }
+}
+
+/// Send one command to the running viewer and return its answer.
+#[cfg(unix)]
+pub fn send(command: &str) -> io::Result<String> {
+    let mut stream = std::os::unix::net::UnixStream::connect(socket_path())?;
+    writeln!(stream, "{}", with_absolute_path(command))?;
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    Ok(answer.trim_end().to_string())
+}
+
+#[cfg(not(unix))]
+pub fn send(_command: &str) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "remote control needs Unix sockets",
+    ))
+}
+
+/// `command` with the path of `open` and `export` made absolute, since the
+/// viewer runs in another directory than the client. URLs are left as they are.
+fn with_absolute_path(command: &str) -> String {
+    let command = command.trim();
+    let absolute = |path: &str| {
+        if crate::reader::is_url(path) {
+            return path.to_string();
+        }
+        std::path::absolute(Path::new(path))
+            .map_or_else(|_| path.to_string(), |p| p.display().to_string())
+    };
+    let words: Vec<&str> = command.splitn(3, ' ').collect();
+    match words.as_slice() {
+        ["open", path] => format!("open {}", absolute(path.trim())),
+        ["open", first, rest] => format!("open {}", absolute(&format!("{first} {rest}"))),
+        ["export", format, path] => format!("export {format} {}", absolute(path.trim())),
+        _ => command.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_paths_are_made_absolute() {
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            with_absolute_path("open docs/a b.md"),
+            format!("open {}", cwd.join("docs/a b.md").display())
+        );
+        assert_eq!(
+            with_absolute_path("export html out.html"),
+            format!("export html {}", cwd.join("out.html").display())
+        );
+        assert_eq!(with_absolute_path(" export html "), "export html");
+        assert_eq!(with_absolute_path("open /tmp/x.md"), "open /tmp/x.md");
+        assert_eq!(
+            with_absolute_path("open https://example.com/a"),
+            "open https://example.com/a"
+        );
+        assert_eq!(with_absolute_path("goto install"), "goto install");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_socket_folder_is_private_to_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("md-viewer");
+        create_private_dir(&dir, user_id()).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        // Reused while it stays private
+        create_private_dir(&dir, user_id()).unwrap();
+
+        assert!(create_private_dir(&dir, user_id() + 1).is_err());
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(create_private_dir(&dir, user_id()).is_err());
+        let file = tmp.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(create_private_dir(&file, user_id()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn requests_are_answered_one_line_each() {
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let ctx = eframe::egui::Context::default();
+        let handle = std::thread::spawn(move || serve(server, &tx, &ctx));
+        let answer = std::thread::spawn(move || {
+            let request: Request = rx.recv().unwrap();
+            assert_eq!(request.line, "reload");
+            request.reply(Ok("reloaded a.md".to_string()));
+            let request = rx.recv().unwrap();
+            request.reply(Err("unknown command: x".to_string()));
+        });
+
+        let mut writer = client.try_clone().unwrap();
+        writeln!(writer, "reload\n\nx").unwrap();
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "ok reloaded a.md");
+        assert_eq!(lines.next().unwrap().unwrap(), "error unknown command: x");
+        answer.join().unwrap();
+        drop(writer);
+        drop(lines);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+//! Rhai scripts that automate the viewer, in builds with the `scripting`
+//! feature.
+//!
+//! Scripts are the `.rhai` files in `$XDG_CONFIG_HOME/md-viewer/scripts`
+//! (`~/.config/md-viewer/scripts` by default) and those passed with
+//! `--script`. A script defines any of:
+//!
+//! - `on_document_load(path, text)`, the markdown shown in place of a
+//!   document's source
+//! - `on_link_click(link)`, the link followed instead when a link to another
+//!   document is clicked, or `false` to stay on the page
+//! - `command_<name>(path, text)`, a command palette (Ctrl+Shift+P) entry run
+//!   on the active document, whose result opens in a new tab;
+//!   `command_insert_header` is listed as "Insert header"
+//!
+//! Returning `()` leaves things as they were. Scripts run in Rhai's sandbox,
+//! without file or process access, and a call stops after
+//! `MAX_OPERATIONS` steps so a stuck script can't hang the UI.
+
+use std::path::{Path, PathBuf};
+#[cfg(feature = "scripting")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "scripting")]
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Scope, AST};
+
+/// Steps a script may take per call.
+#[cfg(feature = "scripting")]
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+#[cfg(feature = "scripting")]
+static SCRIPTS: OnceLock<Scripts> = OnceLock::new();
+
+/// A command a script adds to the command palette.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+pub struct Command {
+    pub title: String,
+    /// The script defining it, by its place in load order
+    script: usize,
+    function: String,
+}
+
+/// Load the scripts from the script directory and `extra`, once at startup.
+/// Scripts that fail to load are logged and skipped.
+#[cfg(feature = "scripting")]
+pub fn init(extra: &[PathBuf]) {
+    let files = script_dir()
+        .map(|dir| script_files(&dir))
+        .unwrap_or_default();
+    let sources =
+        files
+            .iter()
+            .chain(extra)
+            .filter_map(|path| match std::fs::read_to_string(path) {
+                Ok(source) => Some((path.clone(), source)),
+                Err(err) => {
+                    log::warn!("Failed to load script {}: {err}", path.display());
+                    None
+                }
+            });
+    let _ = SCRIPTS.set(Scripts::new(sources));
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn init(extra: &[PathBuf]) {
+    if !extra.is_empty() {
+        log::warn!("Scripts need a build with the scripting feature");
+    }
+}
+
+/// The markdown to show for the source `content` of the document at `path`.
+#[cfg(feature = "scripting")]
+pub fn on_document_load(path: &Path, content: String) -> String {
+    match SCRIPTS.get() {
+        Some(scripts) => scripts.on_document_load(path, content),
+        None => content,
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn on_document_load(_path: &Path, content: String) -> String {
+    content
+}
+
+/// The link to follow for a click on `link`, or `None` to stay on the page.
+#[cfg(feature = "scripting")]
+pub fn on_link_click(link: String) -> Option<String> {
+    match SCRIPTS.get() {
+        Some(scripts) => scripts.on_link_click(link),
+        None => Some(link),
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn on_link_click(link: String) -> Option<String> {
+    Some(link)
+}
+
+/// The commands of the loaded scripts, sorted by title.
+#[cfg(feature = "scripting")]
+pub fn commands() -> Vec<Command> {
+    SCRIPTS.get().map(Scripts::commands).unwrap_or_default()
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn commands() -> Vec<Command> {
+    Vec::new()
+}
+
+/// Run `command` on the document at `path` with `content`, returning the
+/// markdown it made, if any.
+#[cfg(feature = "scripting")]
+pub fn run_command(
+    command: &Command,
+    path: &Path,
+    content: &str,
+) -> Result<Option<String>, String> {
+    SCRIPTS
+        .get()
+        .ok_or("No scripts are loaded")?
+        .run_command(command, path, content)
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn run_command(
+    _command: &Command,
+    _path: &Path,
+    _content: &str,
+) -> Result<Option<String>, String> {
+    Err("Scripts need a build with the scripting feature".to_string())
+}
+
+/// `$XDG_CONFIG_HOME/md-viewer/scripts`, or `~/.config/md-viewer/scripts`.
+#[cfg(feature = "scripting")]
+fn script_dir() -> Option<PathBuf> {
+    crate::config_dir().map(|dir| dir.join("scripts"))
+}
+
+/// The `.rhai` files in `dir`, sorted so scripts load and hook in a stable
+/// order.
+#[cfg(feature = "scripting")]
+fn script_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rhai") && path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+/// The palette title of the command function `command_<name>`.
+#[cfg(feature = "scripting")]
+fn command_title(name: &str) -> String {
+    let name = name.replace('_', " ");
+    let mut chars = name.trim().chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(feature = "scripting")]
+struct Script {
+    path: PathBuf,
+    ast: AST,
+}
+
+#[cfg(feature = "scripting")]
+impl Script {
+    /// Whether the script defines the function `name` taking `params`
+    /// arguments.
+    fn defines(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == params)
+    }
+}
+
+#[cfg(feature = "scripting")]
+struct Scripts {
+    engine: Engine,
+    loaded: Vec<Script>,
+}
+
+#[cfg(feature = "scripting")]
+impl Scripts {
+    /// Compile the scripts `sources`, each with its path.
+    fn new(sources: impl IntoIterator<Item = (PathBuf, String)>) -> Self {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| log::info!("Script: {text}"));
+        engine.on_debug(|text, _, pos| log::debug!("Script at {pos}: {text}"));
+        let loaded = sources
+            .into_iter()
+            .filter_map(|(path, source)| match engine.compile(&source) {
+                Ok(ast) => {
+                    log::info!("Loaded script {}", path.display());
+                    Some(Script { path, ast })
+                }
+                Err(err) => {
+                    log::warn!("Failed to load script {}: {err}", path.display());
+                    None
+                }
+            })
+            .collect();
+        Self { engine, loaded }
+    }
+
+    /// Call the function `name` of `script` with `args`.
+    fn call(&self, script: &Script, name: &str, args: impl FuncArgs) -> Result<Dynamic, String> {
+        // Only the function runs, not the statements at the script's top
+        // level
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options(options, &mut Scope::new(), &script.ast, name, args)
+            .map_err(|err| format!("{name} in {} failed: {err}", script.path.display()))
+    }
+
+    /// The source `content` passed through the `on_document_load` hook of
+    /// each script in turn.
+    fn on_document_load(&self, path: &Path, mut content: String) -> String {
+        let path = path.display().to_string();
+        for script in self
+            .loaded
+            .iter()
+            .filter(|s| s.defines("on_document_load", 2))
+        {
+            match self.call(script, "on_document_load", (path.clone(), content.clone())) {
+                Ok(result) if result.is_string() => {
+                    content = result.into_string().unwrap_or(content);
+                }
+                Ok(result) if result.is_unit() => {}
+                Ok(result) => log::warn!(
+                    "on_document_load in {} returned a {}, not text",
+                    script.path.display(),
+                    result.type_name()
+                ),
+                Err(err) => log::warn!("{err}"),
+            }
+        }
+        content
+    }
+
+    /// `link` passed through the `on_link_click` hook of each script in
+    /// turn, until one keeps the click from being followed.
+    fn on_link_click(&self, mut link: String) -> Option<String> {
+        for script in self.loaded.iter().filter(|s| s.defines("on_link_click", 1)) {
+            match self.call(script, "on_link_click", (link.clone(),)) {
+                Ok(result) if result.is_string() => link = result.into_string().unwrap_or(link),
+                Ok(result) if result.as_bool() == Ok(false) => return None,
+                Ok(_) => {}
+                Err(err) => log::warn!("{err}"),
+            }
+        }
+        Some(link)
+    }
+
+    fn commands(&self) -> Vec<Command> {
+        let mut commands: Vec<Command> = self
+            .loaded
+            .iter()
+            .enumerate()
+            .flat_map(|(index, script)| {
+                script
+                    .ast
+                    .iter_functions()
+                    .filter(|f| f.params.len() == 2)
+                    .filter_map(move |f| {
+                        let name = f.name.strip_prefix("command_")?;
+                        Some(Command {
+                            title: command_title(name),
+                            script: index,
+                            function: f.name.to_string(),
+                        })
+                    })
+            })
+            .collect();
+        commands.sort_by(|a, b| a.title.cmp(&b.title));
+        commands
+    }
+
+    fn run_command(
+        &self,
+        command: &Command,
+        path: &Path,
+        content: &str,
+    ) -> Result<Option<String>, String> {
+        let script = self
+            .loaded
+            .get(command.script)
+            .ok_or_else(|| format!("The script of {} is gone", command.title))?;
+        let args = (path.display().to_string(), content.to_string());
+        let result = self.call(script, &command.function, args)?;
+        if result.is_unit() {
+            return Ok(None);
+        }
+        let kind = result.type_name();
+        result
+            .into_string()
+            .map(Some)
+            .map_err(|_| format!("{} returned a {kind}, not text", command.title))
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use super::*;
+
+    fn scripts(sources: &[(&str, &str)]) -> Scripts {
+        Scripts::new(
+            sources
+                .iter()
+                .map(|(path, source)| (PathBuf::from(path), source.to_string())),
+        )
+    }
+
+    #[test]
+    fn hooks_rewrite_documents_and_links_in_turn() {
+        let scripts = scripts(&[
+            (
+                "a.rhai",
+                r##"
+                fn on_document_load(path, text) {
+                    if path.ends_with(".md") { "# Header\n\n" + text }
+                }
+                fn on_link_click(link) {
+                    if link.starts_with("old/") {
+                        link.replace("old/", "new/");
+                        link
+                    } else if link == "private.md" {
+                        false
+                    }
+                }
+                "##,
+            ),
+            (
+                "b.rhai",
+                r##"fn on_document_load(path, text) { text + "Footer\n" }"##,
+            ),
+            ("broken.rhai", "fn on_link_click(link) {"),
+            ("stuck.rhai", "fn on_link_click(link) { loop {} }"),
+        ]);
+        assert_eq!(scripts.loaded.len(), 3);
+
+        assert_eq!(
+            scripts.on_document_load(Path::new("/docs/a.md"), "Text\n".to_string()),
+            "# Header\n\nText\nFooter\n"
+        );
+        assert_eq!(
+            scripts.on_document_load(Path::new("/docs/a.txt"), "Text\n".to_string()),
+            "Text\nFooter\n"
+        );
+
+        // The stuck script is stopped and leaves the link alone
+        assert_eq!(
+            scripts.on_link_click("old/a.md".to_string()).as_deref(),
+            Some("new/a.md")
+        );
+        assert_eq!(scripts.on_link_click("private.md".to_string()), None);
+        assert_eq!(
+            scripts.on_link_click("b.md".to_string()).as_deref(),
+            Some("b.md")
+        );
+    }
+
+    #[test]
+    fn commands_are_listed_by_title_and_run_on_the_document() {
+        let scripts = scripts(&[(
+            "commands.rhai",
+            r##"
+            fn command_insert_header(path, text) { "# " + path + "\n\n" + text }
+            fn command_count(path, text) { text.len }
+            fn command_nothing(path, text) { }
+            fn helper(text) { text }
+            "##,
+        )]);
+        let commands = scripts.commands();
+        let titles: Vec<&str> = commands.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["Count", "Insert header", "Nothing"]);
+
+        let run = |title: &str| {
+            let command = commands.iter().find(|c| c.title == title).unwrap();
+            scripts.run_command(command, Path::new("a.md"), "Text\n")
+        };
+        assert_eq!(
+            run("Insert header"),
+            Ok(Some("# a.md\n\nText\n".to_string()))
+        );
+        assert_eq!(run("Nothing"), Ok(None));
+        assert_eq!(
+            run("Count"),
+            Err("Count returned a i64, not text".to_string())
+        );
+    }
+}
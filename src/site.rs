@@ -0,0 +1,311 @@
+//! Static HTML site of a folder of documents.
+//!
+//! Every markdown file under a folder, or the documents open in tabs, is
+//! rendered to a page at the same place in an output folder. Links between
+//! the documents point at their pages, the local images and files they link
+//! to are copied along, and an index page lists all pages by folder. The
+//! pages share one stylesheet in the colors of the theme the export ran with.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eframe::egui;
+use pulldown_cmark::{Event, Tag};
+
+use crate::background::Progress;
+
+/// The stylesheet every page links, at the top of the output folder.
+const STYLESHEET: &str = "style.css";
+
+/// The folder holding all of `paths`.
+pub fn common_folder(paths: &[PathBuf]) -> Option<PathBuf> {
+    let mut folder = paths.first()?.parent()?.to_path_buf();
+    for path in &paths[1..] {
+        while !path.starts_with(&folder) {
+            if !folder.pop() {
+                return None;
+            }
+        }
+    }
+    Some(folder)
+}
+
+/// A stylesheet in the colors of `visuals`.
+pub fn stylesheet(visuals: &egui::Visuals) -> String {
+    let hex = |c: egui::Color32| format!("#{:02x}{:02x}{:02x}", c.r(), c.g(), c.b());
+    let text = hex(visuals.text_color());
+    let weak = hex(visuals.weak_text_color());
+    let background = hex(visuals.panel_fill);
+    let link = hex(visuals.hyperlink_color);
+    let code = hex(visuals.code_bg_color);
+    let block = hex(visuals.extreme_bg_color);
+    let border = hex(visuals.widgets.noninteractive.bg_stroke.color);
+    format!(
+        "body {{ margin: 0; background: {background}; color: {text}; \
+         font-family: sans-serif; line-height: 1.6; }}
+nav, main {{ max-width: 50em; margin: 0 auto; padding: 0 1.5em; }}
+nav {{ padding-top: 1em; font-size: 0.9em; }}
+nav.toc {{ padding: 0; }}
+a {{ color: {link}; }}
+h1, h2 {{ border-bottom: 1px solid {border}; padding-bottom: 0.2em; }}
+code {{ background: {code}; padding: 0.1em 0.3em; border-radius: 3px; }}
+pre {{ background: {block}; padding: 0.8em; border-radius: 4px; overflow-x: auto; }}
+pre code {{ background: none; padding: 0; }}
+blockquote {{ margin-left: 0; padding-left: 1em; border-left: 3px solid {border}; color: {weak}; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid {border}; padding: 0.3em 0.6em; }}
+img {{ max-width: 100%; }}
+"
+    )
+}
+
+/// Render `files` under `root` as pages in `out`, with `stylesheet`, contents
+/// as `toc` asks and an index. Returns the index page.
+pub fn export(
+    root: &Path,
+    files: &[PathBuf],
+    out: &Path,
+    stylesheet: &str,
+    toc: crate::toc::Options,
+    progress: &Progress,
+) -> Result<PathBuf, String> {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let files: Vec<PathBuf> = files
+        .iter()
+        .map(|file| file.canonicalize().unwrap_or_else(|_| file.clone()))
+        .filter(|file| file.starts_with(&root))
+        .collect();
+    let pages: HashSet<&Path> = files.iter().map(PathBuf::as_path).collect();
+    // A document named index keeps its page; the listing moves aside
+    let index = if files
+        .iter()
+        .any(|file| page_path(&root, file) == Path::new("index.html"))
+    {
+        "contents.html"
+    } else {
+        "index.html"
+    };
+    let write = |path: &Path, contents: &str| {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create {}: {e}", parent.display()))?;
+        }
+        fs::write(path, contents).map_err(|e| format!("failed to write {}: {e}", path.display()))
+    };
+
+    progress.set_total(files.len());
+    write(&out.join(STYLESHEET), stylesheet)?;
+    let mut assets = BTreeSet::new();
+    let mut listing: BTreeMap<PathBuf, Vec<(PathBuf, String)>> = BTreeMap::new();
+    for file in &files {
+        if progress.is_cancelled() {
+            return Err("export cancelled".to_string());
+        }
+        progress.advance();
+        let Ok(bytes) = fs::read(file) else {
+            continue;
+        };
+        let (content, _) = crate::decode_document(&bytes, None);
+        let content = crate::plugins::process_document(content);
+        let content = crate::pages::process_document(content);
+        let content = crate::citations::process_document(file, content);
+        let page = page_path(&root, file);
+        let title = crate::parse_headers(&content)
+            .document_title
+            .unwrap_or_else(|| file_name(file));
+        let mut events = crate::export_events(&content);
+        let folder = file.parent().unwrap_or(&root);
+        for event in &mut events {
+            let (Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. })) = event
+            else {
+                continue;
+            };
+            let Some((target, rewritten)) = local_target(dest_url, folder) else {
+                continue;
+            };
+            if pages.contains(target.as_path()) {
+                *dest_url = rewritten.into();
+            } else if target.is_file() && target.starts_with(&root) {
+                assets.insert(target);
+            }
+        }
+        let events = crate::toc::apply(crate::with_heading_ids(events).collect(), toc);
+        let mut body = String::new();
+        pulldown_cmark::html::push_html(&mut body, events.into_iter());
+        let up = "../".repeat(page.components().count() - 1);
+        let html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+             <title>{}</title>\n<link rel=\"stylesheet\" href=\"{up}{STYLESHEET}\">\n\
+             </head>\n<body>\n<nav><a href=\"{up}{index}\">Index</a></nav>\n\
+             <main>\n{body}</main>\n</body>\n</html>\n",
+            crate::escape_html(&title)
+        );
+        write(&out.join(&page), &html)?;
+        let folder = page.parent().map(Path::to_path_buf).unwrap_or_default();
+        listing.entry(folder).or_default().push((page, title));
+    }
+
+    for asset in assets {
+        let Ok(relative) = asset.strip_prefix(&root) else {
+            continue;
+        };
+        let copy = out.join(relative);
+        if let Some(parent) = copy.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Err(e) = fs::copy(&asset, &copy) {
+            log::warn!("Failed to copy {}: {e}", asset.display());
+        }
+    }
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{0}</title>\n<link rel=\"stylesheet\" href=\"{STYLESHEET}\">\n\
+         </head>\n<body>\n<main>\n<h1>{0}</h1>\n",
+        crate::escape_html(&file_name(&root))
+    );
+    for (folder, pages) in listing {
+        if !folder.as_os_str().is_empty() {
+            html.push_str(&format!(
+                "<h2>{}</h2>\n",
+                crate::escape_html(&url_path(&folder))
+            ));
+        }
+        html.push_str("<ul>\n");
+        for (page, title) in pages {
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                crate::escape_html(&url_path(&page).replace(' ', "%20")),
+                crate::escape_html(&title)
+            ));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</main>\n</body>\n</html>\n");
+    let index = out.join(index);
+    write(&index, &html)?;
+    Ok(index)
+}
+
+/// Where the page of `file` goes, relative to the output folder.
+fn page_path(root: &Path, file: &Path) -> PathBuf {
+    file.strip_prefix(root)
+        .unwrap_or(file)
+        .with_extension("html")
+}
+
+/// The file a relative link in a document in `folder` points at, and the
+/// link pointing at its page instead. External links, anchors and links
+/// from `/` are left alone.
+fn local_target(url: &str, folder: &Path) -> Option<(PathBuf, String)> {
+    if url.contains("://") || url.starts_with(['#', '/']) || url.starts_with("mailto:") {
+        return None;
+    }
+    let (path, fragment) = match url.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (url, None),
+    };
+    let target = folder.join(path.replace("%20", " ")).canonicalize().ok()?;
+    let mut rewritten = Path::new(path)
+        .with_extension("html")
+        .to_string_lossy()
+        .into_owned();
+    if let Some(fragment) = fragment {
+        rewritten.push('#');
+        rewritten.push_str(fragment);
+    }
+    Some((target, rewritten))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// `path` with `/` between its parts, as in a URL.
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_folder_becomes_linked_pages_with_an_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (src, out) = (dir.join("src"), dir.join("out"));
+        fs::create_dir_all(src.join("guide")).unwrap();
+        fs::write(
+            src.join("README.md"),
+            "# Home\n\n[Setup](guide/setup.md#install) ![logo](logo.png) [web](https://example.com)\n",
+        )
+        .unwrap();
+        fs::write(
+            src.join("guide/setup.md"),
+            "# Setup\n\n## Install\n\n[Back](../README.md)\n",
+        )
+        .unwrap();
+        fs::write(src.join("logo.png"), b"png").unwrap();
+        fs::write(src.join("unlinked.png"), b"png").unwrap();
+        let files = vec![src.join("README.md"), src.join("guide/setup.md")];
+        let progress = Progress::new("Exporting", egui::Context::default());
+
+        let toc = crate::toc::Options {
+            depth: 1,
+            numbered: false,
+        };
+        let index = export(&src, &files, &out, "body {}", toc, &progress).unwrap();
+        fs::write(src.join("index.md"), "# Start\n").unwrap();
+        let mut with_index = files.clone();
+        with_index.push(src.join("index.md"));
+        let contents = export(&src, &with_index, &dir.join("out2"), "", toc, &progress).unwrap();
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        let home = read(out.join("README.html"));
+        let setup = read(out.join("guide/setup.html"));
+        let listing = read(index.clone());
+        let copied = (
+            out.join("logo.png").exists(),
+            out.join("unlinked.png").exists(),
+        );
+
+        assert_eq!(index, out.join("index.html"));
+        assert!(home.contains("<title>Home</title>"));
+        assert!(home.contains("href=\"guide/setup.html#install\""));
+        assert!(home.contains("href=\"https://example.com\""));
+        assert!(home.contains("href=\"style.css\""));
+        assert!(setup.contains("href=\"../README.html\""));
+        assert!(setup.contains("href=\"../style.css\""));
+        assert!(setup.contains("<h2 id=\"install\">"));
+        assert!(setup.contains("<li><a href=\"#install\">Install</a></li>"));
+        assert_eq!(copied, (true, false));
+        assert!(listing.contains("<a href=\"README.html\">Home</a>"));
+        assert!(listing.contains("<h2>guide</h2>"));
+        assert!(listing.contains("<a href=\"guide/setup.html\">Setup</a>"));
+        assert_eq!(contents, dir.join("out2/contents.html"));
+    }
+
+    #[test]
+    fn common_folder_of_open_documents() {
+        let paths = [
+            PathBuf::from("/notes/a.md"),
+            PathBuf::from("/notes/work/b.md"),
+            PathBuf::from("/notes/home/c.md"),
+        ];
+        assert_eq!(common_folder(&paths), Some(PathBuf::from("/notes")));
+        assert_eq!(
+            common_folder(&paths[1..2]),
+            Some(PathBuf::from("/notes/work"))
+        );
+        assert_eq!(common_folder(&[]), None);
+    }
+}
@@ -0,0 +1,230 @@
+//! Tables of contents and section numbers.
+//!
+//! The sections of a document, up to a depth, become a nested list of links
+//! to their headings, put after the document's title. Sections can also be
+//! numbered `1`, `1.1`, `1.2`, … in both the contents and the headings, the
+//! same numbers View → Number Headings shows in the app. The title, the first
+//! heading when no other is of level 1, stays out of both, and the levels
+//! count from the highest section heading.
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// How exports get contents and section numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Options {
+    /// Heading levels listed in the contents, none for no contents
+    pub depth: u8,
+    pub numbered: bool,
+}
+
+/// The contents depths offered in the File menu, the last one for all levels.
+pub const DEPTHS: [u8; 5] = [0, 1, 2, 3, 6];
+
+/// A section heading: where it starts in the events, its level below the
+/// highest section heading, its anchor and its title.
+struct Section {
+    start: usize,
+    level: usize,
+    id: Option<String>,
+    title: String,
+}
+
+/// `events`, their headings given ids already, with section numbers and
+/// contents as `options` ask.
+pub fn apply(mut events: Vec<Event<'_>>, options: Options) -> Vec<Event<'_>> {
+    if options.depth == 0 && !options.numbered {
+        return events;
+    }
+    let mut headings: Vec<(usize, u8, Option<String>, String)> = Vec::new();
+    let mut title_end = None;
+    let mut in_heading = false;
+    for (i, event) in events.iter().enumerate() {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                in_heading = true;
+                let id = id.as_ref().map(|id| id.to_string());
+                headings.push((i, *level as u8, id, String::new()));
+            }
+            Event::Text(text) | Event::Code(text) if in_heading => {
+                if let Some((.., title)) = headings.last_mut() {
+                    title.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                in_heading = false;
+                if headings.len() == 1 {
+                    title_end = Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    let levels: Vec<u8> = headings.iter().map(|h| h.1).collect();
+    let (first, top) = sections_of(&levels);
+    let title_end = if first > 0 { title_end } else { None };
+    let sections: Vec<Section> = headings[first..]
+        .iter()
+        .map(|(start, level, id, title)| Section {
+            start: *start,
+            level: (level - top) as usize,
+            id: id.clone(),
+            title: title.trim().to_string(),
+        })
+        .collect();
+    let numbers: Vec<String> = if options.numbered {
+        section_numbers(&levels).into_iter().flatten().collect()
+    } else {
+        Vec::new()
+    };
+
+    // Numbers go in front of the headings, last one first so the positions
+    // of the others hold
+    for (section, number) in sections.iter().zip(&numbers).rev() {
+        let html = format!("<span class=\"section-number\">{number}</span> ");
+        events.insert(section.start + 1, Event::InlineHtml(html.into()));
+    }
+    let listed: Vec<(&Section, Option<&String>)> = sections
+        .iter()
+        .enumerate()
+        .filter(|(_, section)| section.level < options.depth as usize && section.id.is_some())
+        .map(|(i, section)| (section, numbers.get(i)))
+        .collect();
+    if !listed.is_empty() {
+        events.insert(
+            title_end.unwrap_or(0),
+            Event::Html(contents(&listed).into()),
+        );
+    }
+    events
+}
+
+/// Where the sections start among headings of `levels`, past the title, and
+/// the level of the highest of them.
+fn sections_of(levels: &[u8]) -> (usize, u8) {
+    // The first heading is the title when it's the only one of level 1
+    let has_title =
+        levels.first() == Some(&1) && levels.iter().filter(|&&level| level == 1).count() == 1;
+    let first = usize::from(has_title);
+    let top = levels[first..].iter().copied().min().unwrap_or(1);
+    (first, top)
+}
+
+/// `1`, `1.1`, `1.2`, `2`, … for the headings of `levels` in document order,
+/// a level skipped counting as 0. The title gets no number.
+pub fn section_numbers(levels: &[u8]) -> Vec<Option<String>> {
+    let (first, top) = sections_of(levels);
+    let mut counters: Vec<usize> = Vec::new();
+    levels
+        .iter()
+        .enumerate()
+        .map(|(i, level)| {
+            if i < first {
+                return None;
+            }
+            let level = (level - top) as usize;
+            counters.resize(level + 1, 0);
+            counters[level] += 1;
+            let parts: Vec<String> = counters.iter().map(usize::to_string).collect();
+            Some(parts.join("."))
+        })
+        .collect()
+}
+
+/// The contents listing `sections` with their numbers, nested by level.
+fn contents(sections: &[(&Section, Option<&String>)]) -> String {
+    let mut html = String::from("<nav class=\"toc\">");
+    let mut open = 0;
+    for (section, number) in sections {
+        // A list nests at most one level deeper than the one it's in
+        let depth = section.level.min(open) + 1;
+        if depth > open {
+            html.push_str("\n<ul>\n");
+            open += 1;
+        } else {
+            html.push_str("</li>\n");
+            while open > depth {
+                html.push_str("</ul>\n</li>\n");
+                open -= 1;
+            }
+        }
+        let title = match number {
+            Some(number) => format!("{number} {}", section.title),
+            None => section.title.clone(),
+        };
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            crate::escape_html(section.id.as_deref().unwrap_or_default()),
+            crate::escape_html(&title)
+        ));
+    }
+    for _ in 0..open {
+        html.push_str("</li>\n</ul>\n");
+    }
+    html.push_str("</nav>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(content: &str, options: Options) -> String {
+        let events = crate::with_heading_ids(crate::export_events(content)).collect();
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, apply(events, options).into_iter());
+        html
+    }
+
+    #[test]
+    fn contents_follow_the_title_and_share_the_section_numbers() {
+        let content = "# Guide\n\nIntro.\n\n## Setup\n\n### Linux\n\n#### Arch\n\n\
+                       ### macOS\n\n## Usage & tips\n";
+        let html = export(
+            content,
+            Options {
+                depth: 2,
+                numbered: true,
+            },
+        );
+        assert!(html.starts_with(
+            "<h1 id=\"guide\">Guide</h1>\n<nav class=\"toc\">\n<ul>\n\
+             <li><a href=\"#setup\">1 Setup</a>\n<ul>\n\
+             <li><a href=\"#linux\">1.1 Linux</a></li>\n\
+             <li><a href=\"#macos\">1.2 macOS</a></li>\n</ul>\n</li>\n\
+             <li><a href=\"#usage--tips\">2 Usage &amp; tips</a></li>\n</ul>\n</nav>\n<p>Intro.</p>"
+        ));
+        assert!(
+            html.contains("<h4 id=\"arch\"><span class=\"section-number\">1.1.1</span> Arch</h4>")
+        );
+        assert!(
+            html.contains("<h2 id=\"setup\"><span class=\"section-number\">1</span> Setup</h2>")
+        );
+
+        // Without a single title every heading is a section
+        let html = export(
+            "# One\n\n# Two\n",
+            Options {
+                depth: 0,
+                numbered: true,
+            },
+        );
+        assert!(!html.contains("<nav"));
+        assert!(html.contains("<span class=\"section-number\">2</span> Two"));
+        let plain = export(content, Options::default());
+        assert!(!plain.contains("<nav") && !plain.contains("section-number"));
+    }
+
+    #[test]
+    fn section_numbers_leave_out_the_title() {
+        let numbers = |levels: &[u8]| -> Vec<String> {
+            section_numbers(levels)
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect()
+        };
+        assert_eq!(numbers(&[1, 2, 3, 3, 2]), ["", "1", "1.1", "1.2", "2"]);
+        // A level skipped counts as 0
+        assert_eq!(numbers(&[2, 4]), ["1", "1.0.1"]);
+        assert!(numbers(&[]).is_empty());
+    }
+}